@@ -12,6 +12,8 @@
 
 use anyhow::{anyhow, Context, Result};
 use arc_swap::ArcSwap;
+use log::warn;
+use std::cmp::min;
 use std::fmt;
 use std::fmt::Debug;
 use std::io::Write;
@@ -50,6 +52,15 @@ pub struct RegionCache {
 
 type ListenerObj = Arc<Mutex<dyn Listener>>;
 
+/// Guest memory range pinned by a device which does DMA on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinnedRange {
+    /// Name of the device (or device class) which pins the range.
+    pub owner: String,
+    /// The pinned guest physical range.
+    pub range: AddressRange,
+}
+
 /// Address Space of memory.
 #[derive(Clone)]
 pub struct AddressSpace {
@@ -62,6 +73,9 @@ pub struct AddressSpace {
     listeners: Arc<Mutex<Vec<ListenerObj>>>,
     /// The current layout of ioeventfds, which is compared with new ones in topology-update stage.
     ioeventfds: Arc<Mutex<Vec<RegionIoEventFd>>>,
+    /// Guest memory ranges which must stay resident at a stable host address,
+    /// e.g. ranges mapped into an IOMMU or shared with a vhost-user backend.
+    pinned_ranges: Arc<Mutex<Vec<PinnedRange>>>,
 }
 
 impl fmt::Debug for AddressSpace {
@@ -70,6 +84,7 @@ impl fmt::Debug for AddressSpace {
             .field("root", &self.root)
             .field("flat_view", &self.flat_view)
             .field("ioeventfds", &self.ioeventfds)
+            .field("pinned_ranges", &self.pinned_ranges)
             .finish()
     }
 }
@@ -86,6 +101,7 @@ impl AddressSpace {
             flat_view: Arc::new(ArcSwap::new(Arc::new(FlatView::default()))),
             listeners: Arc::new(Mutex::new(Vec::new())),
             ioeventfds: Arc::new(Mutex::new(Vec::new())),
+            pinned_ranges: Arc::new(Mutex::new(Vec::new())),
         });
 
         root.set_belonged_address_space(&space);
@@ -103,6 +119,13 @@ impl AddressSpace {
         &self.root
     }
 
+    /// Render the region tree of this address space, nested regions are indented.
+    pub fn mtree(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.root.render_mtree(GuestAddress(0), 0, &mut lines);
+        lines
+    }
+
     /// Register the listener to the `AddressSpace`.
     ///
     /// # Arguments
@@ -114,8 +137,10 @@ impl AddressSpace {
     /// Return Error if fail to call `listener`.
     pub fn register_listener(&self, listener: ListenerObj) -> Result<()> {
         let mut locked_listener = listener.lock().unwrap();
+        let pin_owner = locked_listener.pin_owner();
         for fr in self.flat_view.load().0.iter() {
             locked_listener.handle_request(Some(fr), None, ListenerReqType::AddRegion)?;
+            self.update_pinned_range(pin_owner.as_deref(), fr, ListenerReqType::AddRegion);
         }
         locked_listener.enable();
 
@@ -143,8 +168,10 @@ impl AddressSpace {
     /// Return Error if fail to call `listener`.
     pub fn unregister_listener(&self, listener: ListenerObj) -> Result<()> {
         let mut locked_listener = listener.lock().unwrap();
+        let pin_owner = locked_listener.pin_owner();
         for fr in self.flat_view.load().0.iter() {
             locked_listener.handle_request(Some(fr), None, ListenerReqType::DeleteRegion)?;
+            self.update_pinned_range(pin_owner.as_deref(), fr, ListenerReqType::DeleteRegion);
         }
         locked_listener.disable();
         drop(locked_listener);
@@ -177,22 +204,98 @@ impl AddressSpace {
         req_type: ListenerReqType,
     ) -> Result<()> {
         let listeners = self.listeners.lock().unwrap();
+        let handle = |ml: &ListenerObj| -> Result<()> {
+            let locked_ml = ml.lock().unwrap();
+            locked_ml.handle_request(flat_range, evtfd, req_type)?;
+            if let Some(fr) = flat_range {
+                self.update_pinned_range(locked_ml.pin_owner().as_deref(), fr, req_type);
+            }
+            Ok(())
+        };
         match req_type {
             ListenerReqType::DeleteRegion | ListenerReqType::AddIoeventfd => {
-                listeners.iter().rev().try_for_each(|ml| {
-                    ml.lock()
-                        .unwrap()
-                        .handle_request(flat_range, evtfd, req_type)
-                })
+                listeners.iter().rev().try_for_each(handle)
             }
-            _ => listeners.iter().try_for_each(|ml| {
-                ml.lock()
-                    .unwrap()
-                    .handle_request(flat_range, evtfd, req_type)
-            }),
+            _ => listeners.iter().try_for_each(handle),
         }
     }
 
+    /// Pin or unpin the RAM `FlatRange` on behalf of a listener which does DMA on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `owner` - Pin owner of the listener, `None` if the listener does not pin memory.
+    /// * `fr` - FlatRange added to or deleted from the listener.
+    /// * `req_type` - Request type sent to the listener.
+    fn update_pinned_range(&self, owner: Option<&str>, fr: &FlatRange, req_type: ListenerReqType) {
+        let owner = match owner {
+            Some(o) if fr.owner.region_type() == RegionType::Ram => o,
+            _ => return,
+        };
+        match req_type {
+            ListenerReqType::AddRegion => self.pin_range(owner, fr.addr_range),
+            ListenerReqType::DeleteRegion => self.unpin_range(owner, fr.addr_range),
+            _ => {}
+        }
+    }
+
+    /// Pin a range of guest memory, so that it will not be reclaimed (by balloon, etc.)
+    /// while the device is doing DMA on it. The same range can be pinned more than once,
+    /// and it stays pinned until each pin is released.
+    ///
+    /// # Arguments
+    ///
+    /// * `owner` - Name of the device which pins the range.
+    /// * `range` - Guest physical range to pin.
+    pub fn pin_range(&self, owner: &str, range: AddressRange) {
+        self.pinned_ranges.lock().unwrap().push(PinnedRange {
+            owner: owner.to_string(),
+            range,
+        });
+    }
+
+    /// Release a range of guest memory pinned by `pin_range`.
+    ///
+    /// # Arguments
+    ///
+    /// * `owner` - Name of the device which pinned the range.
+    /// * `range` - Guest physical range to unpin.
+    pub fn unpin_range(&self, owner: &str, range: AddressRange) {
+        let mut pinned = self.pinned_ranges.lock().unwrap();
+        match pinned
+            .iter()
+            .position(|p| p.owner == owner && p.range == range)
+        {
+            Some(idx) => {
+                pinned.remove(idx);
+            }
+            None => warn!(
+                "Unpin memory range (0x{:X}, 0x{:X}) which is not pinned by {}",
+                range.base.raw_value(),
+                range.size,
+                owner
+            ),
+        }
+    }
+
+    /// Return true if any part of the given guest range is pinned.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - Guest physical range to check.
+    pub fn is_range_pinned(&self, range: AddressRange) -> bool {
+        self.pinned_ranges
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|p| p.range.find_intersection(range).is_some())
+    }
+
+    /// Get all the pinned ranges of this address space.
+    pub fn pinned_ranges(&self) -> Vec<PinnedRange> {
+        self.pinned_ranges.lock().unwrap().clone()
+    }
+
     /// Update the topology pass.
     ///
     /// # Arguments
@@ -421,6 +524,18 @@ impl AddressSpace {
             .map_or(GuestAddress(0), |fr| fr.addr_range.end_addr())
     }
 
+    /// Return the address ranges of all Ram regions in AddressSpace, sorted by base address.
+    /// Ranges of a Ram region which are covered by other regions are excluded.
+    pub fn ram_ranges(&self) -> Vec<AddressRange> {
+        self.flat_view
+            .load()
+            .0
+            .iter()
+            .filter(|fr| fr.owner.region_type() == RegionType::Ram)
+            .map(|fr| fr.addr_range)
+            .collect()
+    }
+
     /// Read memory segment to `dst`.
     ///
     /// # Arguments
@@ -519,6 +634,80 @@ impl AddressSpace {
                 ))
     }
 
+    /// Read guest physical memory to `buf`, the range may cross several regions.
+    ///
+    /// # Arguments
+    ///
+    /// * `gpa` - Start guest physical address.
+    /// * `buf` - Destination buffer, its length is the size of data.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if any part of the range is not mapped or not backed by host memory.
+    pub fn read_bytes(&self, gpa: u64, buf: &mut [u8]) -> Result<()> {
+        gpa.checked_add(buf.len() as u64)
+            .with_context(|| anyhow!(AddressSpaceError::Overflow(gpa)))?;
+
+        let mut offset = 0;
+        while offset < buf.len() {
+            let addr = GuestAddress(gpa + offset as u64);
+            let (fr, count) = self.find_host_backed_range(addr, (buf.len() - offset) as u64)?;
+            let region_base = fr.addr_range.base.unchecked_sub(fr.offset_in_region);
+            let offset_in_region = fr.offset_in_region + addr.offset_from(fr.addr_range.base);
+            let mut dst = &mut buf[offset..offset + count as usize];
+            fr.owner
+                .read(&mut dst, region_base, offset_in_region, count)
+                .with_context(|| format!("Failed to read memory at 0x{:X}", addr.raw_value()))?;
+            offset += count as usize;
+        }
+        Ok(())
+    }
+
+    /// Write `buf` to guest physical memory, the range may cross several regions.
+    ///
+    /// # Arguments
+    ///
+    /// * `gpa` - Start guest physical address.
+    /// * `buf` - Source buffer, its length is the size of data.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if any part of the range is not mapped or not backed by host memory.
+    pub fn write_bytes(&self, gpa: u64, buf: &[u8]) -> Result<()> {
+        gpa.checked_add(buf.len() as u64)
+            .with_context(|| anyhow!(AddressSpaceError::Overflow(gpa)))?;
+
+        let mut offset = 0;
+        while offset < buf.len() {
+            let addr = GuestAddress(gpa + offset as u64);
+            let (fr, count) = self.find_host_backed_range(addr, (buf.len() - offset) as u64)?;
+            let region_base = fr.addr_range.base.unchecked_sub(fr.offset_in_region);
+            let offset_in_region = fr.offset_in_region + addr.offset_from(fr.addr_range.base);
+            let mut src = &buf[offset..offset + count as usize];
+            fr.owner
+                .write(&mut src, region_base, offset_in_region, count)
+                .with_context(|| format!("Failed to write memory at 0x{:X}", addr.raw_value()))?;
+            offset += count as usize;
+        }
+        Ok(())
+    }
+
+    /// Find the `FlatRange` backed by host memory which contains `addr`, and return
+    /// it with the size of data to access in it, which is at most `count`.
+    fn find_host_backed_range(&self, addr: GuestAddress, count: u64) -> Result<(FlatRange, u64)> {
+        let view = self.flat_view.load();
+        let fr = view
+            .find_flatrange(addr)
+            .with_context(|| anyhow!(AddressSpaceError::RegionNotFound(addr.raw_value())))?;
+        if fr.owner.get_host_address().is_none() {
+            return Err(anyhow!(AddressSpaceError::RegionType(
+                fr.owner.region_type()
+            )));
+        }
+        let count = min(count, fr.addr_range.end_addr().offset_from(addr));
+        Ok((fr.clone(), count))
+    }
+
     /// Write an object to memory.
     ///
     /// # Arguments
@@ -1004,6 +1193,89 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_pinned_ranges() {
+        #[derive(Default)]
+        struct PinListener {
+            enabled: bool,
+        }
+        impl Listener for PinListener {
+            fn priority(&self) -> i32 {
+                0
+            }
+
+            fn enabled(&self) -> bool {
+                self.enabled
+            }
+
+            fn enable(&mut self) {
+                self.enabled = true;
+            }
+
+            fn disable(&mut self) {
+                self.enabled = false;
+            }
+
+            fn pin_owner(&self) -> Option<String> {
+                Some("test".to_string())
+            }
+        }
+
+        let root = Region::init_container_region(8000);
+        let space = AddressSpace::new(root.clone()).unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+        );
+        let region_a = Region::init_ram_region(ram1.clone());
+        root.add_subregion(region_a, ram1.start_address().raw_value())
+            .unwrap();
+
+        // RAM regions existing before registering are pinned.
+        let listener = Arc::new(Mutex::new(PinListener::default()));
+        space.register_listener(listener.clone()).unwrap();
+        assert_eq!(
+            space.pinned_ranges(),
+            vec![PinnedRange {
+                owner: "test".to_string(),
+                range: AddressRange::new(GuestAddress(0), 1000),
+            }]
+        );
+        assert!(space.is_range_pinned(AddressRange::new(GuestAddress(500), 1000)));
+        assert!(!space.is_range_pinned(AddressRange::new(GuestAddress(1000), 1000)));
+
+        // RAM regions added later are pinned, IO regions are not.
+        let ram2 = Arc::new(
+            HostMemMapping::new(GuestAddress(2000), None, 1000, None, false, false, false).unwrap(),
+        );
+        let region_b = Region::init_ram_region(ram2.clone());
+        root.add_subregion(region_b.clone(), ram2.start_address().raw_value())
+            .unwrap();
+        let default_ops = RegionOps {
+            read: Arc::new(|_: &mut [u8], _: GuestAddress, _: u64| -> bool { true }),
+            write: Arc::new(|_: &[u8], _: GuestAddress, _: u64| -> bool { true }),
+        };
+        root.add_subregion(Region::init_io_region(1000, default_ops), 4000)
+            .unwrap();
+        assert_eq!(space.pinned_ranges().len(), 2);
+        assert!(space.is_range_pinned(AddressRange::new(GuestAddress(2500), 10)));
+        assert!(!space.is_range_pinned(AddressRange::new(GuestAddress(4000), 1000)));
+
+        // Manual pins are reference counted.
+        let range = AddressRange::new(GuestAddress(6000), 100);
+        space.pin_range("manual", range);
+        space.pin_range("manual", range);
+        space.unpin_range("manual", range);
+        assert!(space.is_range_pinned(range));
+        space.unpin_range("manual", range);
+        assert!(!space.is_range_pinned(range));
+
+        // Deleted RAM regions and unregistered listeners release their pins.
+        root.delete_subregion(&region_b).unwrap();
+        assert!(!space.is_range_pinned(AddressRange::new(GuestAddress(2000), 1000)));
+        space.unregister_listener(listener).unwrap();
+        assert!(space.pinned_ranges().is_empty());
+    }
+
     #[test]
     fn test_get_ram_info() {
         let root = Region::init_container_region(8000);
@@ -1075,6 +1347,13 @@ mod test {
             space.get_host_address(GuestAddress(2500)),
             Some(ram2.host_address() + 500)
         );
+        assert_eq!(
+            space.ram_ranges(),
+            vec![
+                AddressRange::new(GuestAddress(0), 1000),
+                AddressRange::new(GuestAddress(2500), 500)
+            ]
+        );
     }
 
     #[test]
@@ -1094,4 +1373,70 @@ mod test {
         assert_eq!(data1, 10000);
         assert!(space.write_object(&data, GuestAddress(993)).is_err());
     }
+
+    #[test]
+    fn test_mtree() {
+        let root = Region::init_container_region(0x10000);
+        root.set_name("root");
+        let space = AddressSpace::new(root.clone()).unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x1000, None, false, false, false).unwrap(),
+        );
+        let ram_region = Region::init_ram_region(ram);
+        ram_region.set_name("ram");
+        let container = Region::init_container_region(0x2000);
+        root.add_subregion(container.clone(), 0x8000).unwrap();
+        root.add_subregion(ram_region, 0).unwrap();
+        let default_ops = RegionOps {
+            read: Arc::new(|_: &mut [u8], _: GuestAddress, _: u64| -> bool { true }),
+            write: Arc::new(|_: &[u8], _: GuestAddress, _: u64| -> bool { true }),
+        };
+        let io_region = Region::init_io_region(0x100, default_ops);
+        io_region.set_priority(1);
+        container.add_subregion(io_region, 0x1000).unwrap();
+
+        assert_eq!(
+            space.mtree(),
+            vec![
+                "0000000000000000-000000000000ffff (prio 0, container): root",
+                "  0000000000000000-0000000000000fff (prio 0, ram): ram",
+                "  0000000000008000-0000000000009fff (prio 0, container): unnamed",
+                "    0000000000009000-00000000000090ff (prio 1, i/o): unnamed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_and_write_bytes() {
+        let root = Region::init_container_region(8000);
+        let space = AddressSpace::new(root.clone()).unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+        );
+        let ram2 = Arc::new(
+            HostMemMapping::new(GuestAddress(1000), None, 1000, None, false, false, false).unwrap(),
+        );
+        let ram3 = Arc::new(
+            HostMemMapping::new(GuestAddress(3000), None, 1000, None, false, false, false).unwrap(),
+        );
+        for ram in [ram1, ram2, ram3] {
+            root.add_subregion(Region::init_ram_region(ram.clone()), ram.start_address().0)
+                .unwrap();
+        }
+
+        // Across the boundary of regions.
+        let data: Vec<u8> = (0..100).collect();
+        space.write_bytes(950, &data).unwrap();
+        let mut buf = vec![0_u8; 100];
+        space.read_bytes(950, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        let value: u64 = space.read_object(GuestAddress(1000)).unwrap();
+        assert_eq!(value, u64::from_le_bytes([50, 51, 52, 53, 54, 55, 56, 57]));
+
+        // Unmapped address.
+        assert!(space.read_bytes(1950, &mut buf).is_err());
+        assert!(space.write_bytes(2500, &data).is_err());
+        assert!(space.read_bytes(u64::MAX - 10, &mut buf).is_err());
+        space.read_bytes(3900, &mut buf).unwrap();
+    }
 }
@@ -31,6 +31,12 @@ const MAX_PREALLOC_THREAD: u8 = 16;
 const MPOL_MF_STRICT: u32 = 1;
 /// Move pages owned by this process to conform to mapping.
 const MPOL_MF_MOVE: u32 = 2;
+/// Magic number of hugetlbfs file system.
+const HUGETLBFS_MAGIC: u64 = 0x9584_58f6;
+/// Create memfd in the hugetlbfs.
+const MFD_HUGETLB: u32 = 0x0004;
+/// Shift of the log2 of huge page size in the flags of `memfd_create`.
+const MFD_HUGE_SHIFT: u32 = 26;
 
 /// FileBackend represents backend-file of `HostMemMapping`.
 #[derive(Clone, Debug)]
@@ -122,10 +128,13 @@ impl FileBackend {
         // and set to all-zero will not cause any undefined behavior.
         let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
         unsafe { libc::fstatfs(file.as_raw_fd(), &mut fstat) };
-        info!(
-            "Using memory backing file, the page size is {}",
-            fstat.f_bsize
-        );
+        // Only hugetlbfs maps file with its block size, others use normal page.
+        let page_size = if fstat.f_type as u64 == HUGETLBFS_MAGIC {
+            fstat.f_bsize as u64
+        } else {
+            host_page_size()
+        };
+        info!("Using memory backing file, the page size is {}", page_size);
 
         let old_file_len = file.metadata().unwrap().len();
         if old_file_len == 0 {
@@ -142,7 +151,7 @@ impl FileBackend {
         Ok(FileBackend {
             file: Arc::new(file),
             offset: 0_u64,
-            page_size: fstat.f_bsize as u64,
+            page_size,
         })
     }
 }
@@ -190,9 +199,9 @@ fn touch_pages(start: u64, page_size: u64, nr_pages: u64) {
 ///
 /// * `host_addr` - The start host address to pre allocate.
 /// * `size` - Size of memory.
+/// * `page_size` - Size of the pages backing memory.
 /// * `nr_vcpus` - Number of vcpus.
-fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
-    let page_size = host_page_size();
+fn mem_prealloc(host_addr: u64, size: u64, page_size: u64, nr_vcpus: u8) {
     let threads = max_nr_threads(nr_vcpus);
     let nr_pages = (size + page_size - 1) / page_size;
     let pages_per_thread = nr_pages / (threads as u64);
@@ -219,6 +228,36 @@ fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
     }
 }
 
+/// Lock memory of virtual machine in host RAM, so that it will never be swapped.
+///
+/// # Arguments
+///
+/// * `host_addr` - The start host address to lock.
+/// * `size` - Size of memory.
+fn mem_lock(host_addr: u64, size: u64) -> Result<()> {
+    // Safe because the memory has been mapped and the return value is checked.
+    let ret = unsafe { libc::mlock(host_addr as *const libc::c_void, size as libc::size_t) };
+    if ret != 0 {
+        let err = std::io::Error::last_os_error();
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // Safe because `limit` is a valid rlimit struct, only used for error message.
+        unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) };
+        if limit.rlim_cur != libc::RLIM_INFINITY && limit.rlim_cur < size {
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to lock 0x{:X} bytes guest ram, RLIMIT_MEMLOCK is only 0x{:X} bytes",
+                    size, limit.rlim_cur
+                )
+            });
+        }
+        return Err(err).with_context(|| format!("Failed to lock 0x{:X} bytes guest ram", size));
+    }
+    Ok(())
+}
+
 /// Create HostMemMappings according to address ranges.
 ///
 /// # Arguments
@@ -238,14 +277,26 @@ pub fn create_host_mmaps(
             FileBackend::new_mem(path, file_len)
                 .with_context(|| "Failed to create file that backs memory")?,
         );
-    } else if mem_config.mem_share {
+    } else if mem_config.mem_share || mem_config.hugepage_size.is_some() {
         let file_len = ranges.iter().fold(0, |acc, x| acc + x.1);
         let anon_mem_name = String::from("stratovirt_anon_mem");
+        // Anonymous huge pages are allocated from hugetlbfs by memfd, so that
+        // the page size can be specified.
+        let memfd_flags = mem_config.hugepage_size.map_or(0, |size| {
+            MFD_HUGETLB | size.trailing_zeros() << MFD_HUGE_SHIFT
+        });
 
         let anon_fd =
-            unsafe { libc::syscall(libc::SYS_memfd_create, anon_mem_name.as_ptr(), 0) } as RawFd;
+            unsafe { libc::syscall(libc::SYS_memfd_create, anon_mem_name.as_ptr(), memfd_flags) }
+                as RawFd;
         if anon_fd < 0 {
-            return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create memfd");
+            return Err(std::io::Error::last_os_error()).with_context(|| {
+                if let Some(size) = mem_config.hugepage_size {
+                    format!("Failed to create memfd with huge page size 0x{:X}", size)
+                } else {
+                    "Failed to create memfd".to_string()
+                }
+            });
         }
 
         let anon_file = unsafe { File::from_raw_fd(anon_fd) };
@@ -256,11 +307,26 @@ pub fn create_host_mmaps(
         f_back = Some(FileBackend {
             file: Arc::new(anon_file),
             offset: 0,
-            page_size: host_page_size(),
+            page_size: mem_config.hugepage_size.unwrap_or_else(host_page_size),
         });
     }
 
     let backend = f_back.as_ref();
+    let page_size = backend.map_or_else(host_page_size, |fb| fb.page_size);
+    if page_size > host_page_size() {
+        // Guest physical and host virtual address of the memory must be
+        // congruent modulo the huge page size, for kvm to map huge pages.
+        for range in ranges.iter() {
+            if range.0 % page_size != 0 || range.1 % page_size != 0 {
+                bail!(
+                    "Guest ram range (0x{:X}, 0x{:X}) is unaligned with huge page size 0x{:X}",
+                    range.0,
+                    range.1,
+                    page_size
+                );
+            }
+        }
+    }
     let mut host_addr = do_mmap(
         &backend.map(|fb| fb.file.as_ref()),
         mem_config.mem_size,
@@ -268,9 +334,22 @@ pub fn create_host_mmaps(
         false,
         mem_config.mem_share,
         mem_config.dump_guest_core,
-    )?;
+    )
+    .with_context(|| {
+        if page_size > host_page_size() {
+            format!(
+                "Failed to reserve huge pages of size 0x{:X} for 0x{:X} bytes guest ram, check the free huge pages of host",
+                page_size, mem_config.mem_size
+            )
+        } else {
+            "Failed to mmap guest ram".to_string()
+        }
+    })?;
     if mem_config.mem_prealloc {
-        mem_prealloc(host_addr, mem_config.mem_size, nr_vcpus);
+        mem_prealloc(host_addr, mem_config.mem_size, page_size, nr_vcpus);
+    }
+    if mem_config.mem_lock {
+        mem_lock(host_addr, mem_config.mem_size)?;
     }
     let mut mappings = Vec::new();
     for range in ranges.iter() {
@@ -559,6 +638,8 @@ mod test {
             dump_guest_core: false,
             mem_share: false,
             mem_prealloc: false,
+            mem_lock: false,
+            hugepage_size: None,
             mem_zones: None,
         };
 
@@ -594,7 +675,7 @@ mod test {
         assert_eq!(max_nr_threads(1), 1);
         // The max threads limit is 16, or the number of host CPUs, it will never be 20.
         assert_ne!(max_nr_threads(20), 20);
-        mem_prealloc(host_addr, 0x20_0000, 20);
+        mem_prealloc(host_addr, 0x20_0000, host_page_size(), 20);
 
         // Mmap and prealloc with file backend.
         let file_path = String::from("back_mem_test");
@@ -609,6 +690,6 @@ mod test {
             false,
         )
         .unwrap();
-        mem_prealloc(host_addr, 0x10_0000, 2);
+        mem_prealloc(host_addr, 0x10_0000, host_page_size(), 2);
     }
 }
@@ -85,7 +85,7 @@ mod listener;
 mod region;
 mod state;
 
-pub use crate::address_space::{AddressSpace, RegionCache};
+pub use crate::address_space::{AddressSpace, PinnedRange, RegionCache};
 pub use address::{AddressRange, GuestAddress};
 pub use anyhow::Result;
 pub use error::AddressSpaceError;
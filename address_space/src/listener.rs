@@ -48,6 +48,12 @@ pub trait Listener: Send + Sync {
     /// Disable listener for address space.
     fn disable(&mut self);
 
+    /// Name of the pin owner if the listener does DMA on the RAM regions it receives.
+    /// The RAM regions are pinned in the address space while they are held by the listener.
+    fn pin_owner(&self) -> Option<String> {
+        None
+    }
+
     /// Function that handle request according to request-type.
     ///
     /// # Arguments
@@ -67,6 +67,8 @@ pub struct Region {
     rom_dev_romd: Arc<AtomicBool>,
     /// Max access size supported by the device.
     max_access_size: Option<u64>,
+    /// Name of Region, used to show the memory tree.
+    name: Arc<RwLock<String>>,
 }
 
 impl fmt::Debug for Region {
@@ -82,6 +84,7 @@ impl fmt::Debug for Region {
             .field("subregions", &self.subregions)
             .field("rom_dev_romd", &self.rom_dev_romd)
             .field("max_access_size", &self.max_access_size)
+            .field("name", &self.name)
             .finish()
     }
 }
@@ -249,6 +252,7 @@ impl Region {
             subregions: Arc::new(RwLock::new(Vec::new())),
             rom_dev_romd: Arc::new(AtomicBool::new(false)),
             max_access_size: None,
+            name: Arc::new(RwLock::new(String::new())),
         }
     }
 
@@ -340,6 +344,20 @@ impl Region {
         self.priority.store(prior, Ordering::SeqCst);
     }
 
+    /// Get name of this region.
+    pub fn name(&self) -> String {
+        self.name.read().unwrap().clone()
+    }
+
+    /// Set name of this region, which is shown in the memory tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of region.
+    pub fn set_name(&self, name: &str) {
+        *self.name.write().unwrap() = name.to_string();
+    }
+
     /// Get size of this region.
     pub fn size(&self) -> u64 {
         self.size.load(Ordering::SeqCst)
@@ -716,6 +734,43 @@ impl Region {
         Ok(())
     }
 
+    /// Render this region and its sub-regions as lines of memory tree, sub-regions are
+    /// indented and sorted by address.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - Base address of parent region.
+    /// * `depth` - Depth of this region in the tree.
+    /// * `lines` - Rendered lines.
+    pub(crate) fn render_mtree(&self, base: GuestAddress, depth: usize, lines: &mut Vec<String>) {
+        let start = base.unchecked_add(self.offset().raw_value());
+        let end = start.unchecked_add(self.size().saturating_sub(1));
+        let region_type = match self.region_type {
+            RegionType::Ram => "ram",
+            RegionType::IO => "i/o",
+            RegionType::Container => "container",
+            RegionType::RomDevice => "romd",
+            RegionType::RamDevice => "ram device",
+        };
+        let name = self.name();
+        lines.push(format!(
+            "{:indent$}{:016x}-{:016x} (prio {}, {}): {}",
+            "",
+            start.raw_value(),
+            end.raw_value(),
+            self.priority(),
+            region_type,
+            if name.is_empty() { "unnamed" } else { &name },
+            indent = depth * 2
+        ));
+
+        let mut sub_regions = self.subregions.read().unwrap().clone();
+        sub_regions.sort_by_key(|r| r.offset());
+        for sub_r in sub_regions.iter() {
+            sub_r.render_mtree(start, depth + 1, lines);
+        }
+    }
+
     /// Delete sub-region of this region.
     ///
     /// # Arguments
@@ -15,14 +15,18 @@ use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+use crate::check_initrd_range;
+use crate::elf::{is_elf_kernel, load_elf_kernel};
 use crate::error::BootLoaderError;
+use crate::kernel_image::KernelImage;
 use address_space::{AddressSpace, GuestAddress};
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use devices::legacy::{error::LegacyError as FwcfgErrorKind, FwCfgEntryType, FwCfgOps};
 use log::info;
 use util::byte_code::ByteCode;
 
 const AARCH64_KERNEL_OFFSET: u64 = 0x8_0000;
+const AARCH64_INITRD_ALIGN: u64 = 0x1000;
 
 /// Boot loader config used for aarch64.
 #[derive(Default, Debug)]
@@ -47,15 +51,29 @@ pub struct AArch64BootLoader {
     pub dtb_start: u64,
 }
 
+/// Load kernel image, return the start address and size of the kernel in guest
+/// memory, and the entry of the kernel.
+///
+/// ELF kernel is loaded according to its program headers, and boots from the
+/// entry in ELF header. It's not supported to boot ELF kernel by firmware.
 fn load_kernel(
     fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
+    config: &AArch64BootLoaderConfig,
     kernel_start: u64,
-    kernel_path: &Path,
     sys_mem: &Arc<AddressSpace>,
-) -> Result<u64> {
-    let mut kernel_image =
-        File::open(kernel_path).with_context(|| anyhow!(BootLoaderError::BootLoaderOpenKernel))?;
-    let kernel_size = kernel_image.metadata().unwrap().len();
+) -> Result<((u64, u64), u64)> {
+    let mem_end = sys_mem.memory_end_address().raw_value();
+    let mut kernel_image = KernelImage::open(config.kernel.as_ref().unwrap(), mem_end)?;
+    if is_elf_kernel(&mut kernel_image)? {
+        if fwcfg.is_some() {
+            bail!("ELF kernel image can't be booted by firmware");
+        }
+        let ram_ranges = [(config.mem_start, mem_end - config.mem_start)];
+        let elf_info = load_elf_kernel(&mut kernel_image, sys_mem, &ram_ranges)?;
+        return Ok((elf_info.load_range, elf_info.entry));
+    }
+
+    let kernel_size = kernel_image.size()?;
     let kernel_end = kernel_start + kernel_size;
 
     if let Some(fw_cfg) = fwcfg {
@@ -87,32 +105,39 @@ fn load_kernel(
             .write(&mut kernel_image, GuestAddress(kernel_start), kernel_size)
             .with_context(|| "Fail to write kernel to guest memory")?;
     }
-    Ok(kernel_end)
+    Ok(((kernel_start, kernel_size), kernel_start))
 }
 
 fn load_initrd(
     fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
     initrd_path: &Path,
     sys_mem: &Arc<AddressSpace>,
-    kernel_end: u64,
+    mem_start: u64,
+    kernel: (u64, u64),
 ) -> Result<(u64, u64)> {
     let mut initrd_image =
         File::open(initrd_path).with_context(|| anyhow!(BootLoaderError::BootLoaderOpenInitrd))?;
     let initrd_size = initrd_image.metadata().unwrap().len();
 
-    let initrd_start = if let Some(addr) = sys_mem
-        .memory_end_address()
-        .raw_value()
-        .checked_sub(initrd_size)
-        .filter(|addr| addr >= &kernel_end)
-    {
-        addr
+    let mem_end = sys_mem.memory_end_address().raw_value();
+    // The initrd address is passed to firmware by fw_cfg in 32 bits, keep it below 4G
+    // so that it's the same as the one written into the device tree.
+    let initrd_addr_max = if fwcfg.is_some() {
+        std::cmp::min(mem_end, u64::from(u32::MAX) + 1)
     } else {
-        return Err(anyhow!(BootLoaderError::InitrdOverflow(
-            kernel_end,
-            initrd_size
-        )));
+        mem_end
     };
+    let initrd_start = initrd_addr_max.checked_sub(initrd_size).ok_or_else(|| {
+        anyhow!(BootLoaderError::InitrdOverflow(
+            initrd_addr_max,
+            initrd_size
+        ))
+    })? & !(AARCH64_INITRD_ALIGN - 1);
+    check_initrd_range(
+        (initrd_start, initrd_size),
+        Some(kernel),
+        &[(mem_start, mem_end - mem_start)],
+    )?;
 
     if let Some(fw_cfg) = fwcfg {
         let mut initrd_data = Vec::new();
@@ -142,7 +167,7 @@ fn load_initrd(
     Ok((initrd_start, initrd_size))
 }
 
-/// Load PE(vmlinux.bin) linux kernel and other boot source to Guest Memory.
+/// Load PE(vmlinux.bin) / ELF linux kernel and other boot source to Guest Memory.
 ///
 /// # Steps
 ///
@@ -182,30 +207,31 @@ pub fn load_linux(
     }
 
     let kernel_start = config.mem_start + AARCH64_KERNEL_OFFSET;
-    let boot_pc = if fwcfg.is_some() { 0 } else { kernel_start };
 
     if config.kernel.is_none() {
         return Ok(AArch64BootLoader {
-            boot_pc,
+            boot_pc: if fwcfg.is_some() { 0 } else { kernel_start },
             initrd_start: 0,
             initrd_size: 0,
             dtb_start: dtb_addr,
         });
     }
 
-    let kernel_end = load_kernel(
-        fwcfg,
-        kernel_start,
-        config.kernel.as_ref().unwrap(),
-        sys_mem,
-    )
-    .with_context(|| "Fail to load kernel")?;
+    let (kernel, kernel_entry) =
+        load_kernel(fwcfg, config, kernel_start, sys_mem).with_context(|| "Fail to load kernel")?;
+    let boot_pc = if fwcfg.is_some() { 0 } else { kernel_entry };
 
     let mut initrd_start = 0_u64;
     let mut initrd_size = 0_u64;
     if config.initrd.is_some() {
-        let initrd_tuple = load_initrd(fwcfg, config.initrd.as_ref().unwrap(), sys_mem, kernel_end)
-            .with_context(|| "Fail to load initrd")?;
+        let initrd_tuple = load_initrd(
+            fwcfg,
+            config.initrd.as_ref().unwrap(),
+            sys_mem,
+            config.mem_start,
+            kernel,
+        )
+        .with_context(|| "Fail to load initrd")?;
         initrd_start = initrd_tuple.0;
         initrd_size = initrd_tuple.1;
     } else {
@@ -0,0 +1,423 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! ELF-format kernel image, which is loaded according to its program headers.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use address_space::{AddressSpace, GuestAddress};
+use util::byte_code::ByteCode;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::error::BootLoaderError;
+use crate::kernel_image::KernelImage;
+
+const EI_MAG0: usize = 0;
+const EI_MAG3: usize = 3;
+const EI_CLASS: usize = 4;
+const EI_DATA: usize = 5;
+
+const ELFMAG: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+
+const ELFCLASS64: u8 = 2;
+
+const ELFDATA2LSB: u8 = 1;
+
+const ET_EXEC: u16 = 2;
+
+#[cfg(target_arch = "x86_64")]
+const EM_ARCH: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const EM_ARCH: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+
+/// Alignment of name and desc of ELF notes.
+const NOTE_ALIGN: u64 = 4;
+
+type Elf64_Addr = u64;
+type Elf64_Half = u16;
+type Elf64_Off = u64;
+type Elf64_Word = u32;
+type Elf64_Xword = u64;
+
+/// Note of the 32-bit entry of PVH boot, which is generated by linux kernel
+/// with `CONFIG_PVH` enabled.
+const XEN_ELFNOTE_PHYS32_ENTRY: u32 = 0x12;
+const XEN_ELFNOTE_NAME: &[u8] = b"Xen\0";
+
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64Header {
+    e_ident: [u8; 16usize],
+    e_type: Elf64_Half,
+    e_machine: Elf64_Half,
+    e_version: Elf64_Word,
+    e_entry: Elf64_Addr,
+    e_phoff: Elf64_Off,
+    e_shoff: Elf64_Off,
+    e_flags: Elf64_Word,
+    e_ehsize: Elf64_Half,
+    e_phentsize: Elf64_Half,
+    e_phnum: Elf64_Half,
+    e_shentsize: Elf64_Half,
+    e_shnum: Elf64_Half,
+    e_shstrndx: Elf64_Half,
+}
+
+impl ByteCode for Elf64Header {}
+
+impl Elf64Header {
+    fn is_valid(&self) -> Result<()> {
+        if self.e_ident[EI_MAG0..=EI_MAG3] != ELFMAG {
+            return Err(invalid_elf("invalid magic"));
+        }
+        if self.e_ident[EI_DATA] != ELFDATA2LSB {
+            return Err(invalid_elf("big endian is not supported"));
+        }
+        if self.e_ident[EI_CLASS] != ELFCLASS64 {
+            return Err(invalid_elf("only 64-bit ELF is supported"));
+        }
+        let e_type = self.e_type;
+        if e_type != ET_EXEC {
+            return Err(invalid_elf(&format!(
+                "unsupported type {}, only executable file is supported",
+                e_type
+            )));
+        }
+        let e_machine = self.e_machine;
+        if e_machine != EM_ARCH {
+            return Err(invalid_elf(&format!("unsupported machine {}", e_machine)));
+        }
+        Ok(())
+    }
+
+    fn parse_prog_hdrs(&self, kernel_image: &mut KernelImage) -> Result<Vec<Elf64ProgHeader>> {
+        kernel_image.seek(SeekFrom::Start(self.e_phoff))?;
+
+        let mut elf_phs = Vec::with_capacity(self.e_phnum as usize);
+        for _ in 0..self.e_phnum {
+            let mut ph = Elf64ProgHeader::default();
+            kernel_image.read_exact(ph.as_mut_bytes())?;
+            elf_phs.push(ph);
+        }
+        Ok(elf_phs)
+    }
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64ProgHeader {
+    p_type: Elf64_Word,
+    p_flags: Elf64_Word,
+    p_offset: Elf64_Off,
+    p_vaddr: Elf64_Addr,
+    p_paddr: Elf64_Addr,
+    p_filesz: Elf64_Xword,
+    p_memsz: Elf64_Xword,
+    p_align: Elf64_Xword,
+}
+
+impl ByteCode for Elf64ProgHeader {}
+
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64NoteHeader {
+    namesz: Elf64_Word,
+    descsz: Elf64_Word,
+    type_: Elf64_Word,
+}
+
+impl ByteCode for Elf64NoteHeader {}
+
+fn invalid_elf(reason: &str) -> anyhow::Error {
+    anyhow!(BootLoaderError::InvalidElfKernel(reason.to_string()))
+}
+
+fn align_note(len: u32) -> u64 {
+    // Never overflows, as `len` is u32.
+    (u64::from(len) + NOTE_ALIGN - 1) & !(NOTE_ALIGN - 1)
+}
+
+/// Information of the loaded ELF kernel.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ElfKernelInfo {
+    /// Entry of the kernel in ELF header.
+    pub entry: u64,
+    /// 32-bit entry of PVH boot, if the kernel has `XEN_ELFNOTE_PHYS32_ENTRY` note.
+    #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
+    pub pvh_entry: Option<u64>,
+    /// Start address and size of the guest memory the kernel is loaded to.
+    pub load_range: (u64, u64),
+}
+
+/// Check whether the kernel image is in ELF format.
+pub fn is_elf_kernel(kernel_image: &mut KernelImage) -> Result<bool> {
+    let mut magic = [0_u8; 4];
+    kernel_image.seek(SeekFrom::Start(0))?;
+    let magic_len = kernel_image.read(&mut magic)?;
+    kernel_image.seek(SeekFrom::Start(0))?;
+    Ok(magic_len == magic.len() && magic == ELFMAG)
+}
+
+/// Search the PVH entry in a `PT_NOTE` segment.
+fn parse_pvh_entry(kernel_image: &mut KernelImage, ph: &Elf64ProgHeader) -> Result<Option<u64>> {
+    kernel_image.seek(SeekFrom::Start(ph.p_offset))?;
+    let note_size = std::mem::size_of::<Elf64NoteHeader>() as u64;
+
+    let mut offset = 0;
+    while offset + note_size <= ph.p_filesz {
+        let mut note_hdr = Elf64NoteHeader::default();
+        kernel_image.read_exact(note_hdr.as_mut_bytes())?;
+        let aligned_namesz = align_note(note_hdr.namesz);
+        let aligned_descsz = align_note(note_hdr.descsz);
+        offset += note_size + aligned_namesz + aligned_descsz;
+        if offset > ph.p_filesz {
+            return Err(invalid_elf("note overflows the segment"));
+        }
+
+        let mut name = vec![0_u8; aligned_namesz as usize];
+        kernel_image.read_exact(&mut name)?;
+        let mut desc = vec![0_u8; aligned_descsz as usize];
+        kernel_image.read_exact(&mut desc)?;
+        if note_hdr.type_ != XEN_ELFNOTE_PHYS32_ENTRY
+            || !name.starts_with(XEN_ELFNOTE_NAME)
+            || note_hdr.namesz as usize != XEN_ELFNOTE_NAME.len()
+        {
+            continue;
+        }
+        // The entry is 32-bit, but it's put in a 64-bit field by 64-bit kernel.
+        let entry = match note_hdr.descsz {
+            4 => u64::from(u32::from_le_bytes(desc[..4].try_into().unwrap())),
+            8 => u64::from_le_bytes(desc[..8].try_into().unwrap()),
+            size => return Err(invalid_elf(&format!("invalid PVH entry size {}", size))),
+        };
+        return Ok(Some(entry));
+    }
+    Ok(None)
+}
+
+/// Load ELF-format kernel to guest memory according to its program headers.
+/// Each `PT_LOAD` segment is loaded at its physical address, which must lie
+/// in guest RAM.
+///
+/// # Arguments
+///
+/// * `kernel_image` - ELF-format kernel file.
+/// * `sys_mem` - Guest memory.
+/// * `ram_ranges` - Start address and size of each guest RAM range.
+///
+/// # Errors
+///
+/// * The ELF file is invalid, or is not an executable of this architecture.
+/// * A segment overflows the ELF file, or doesn't lie in guest RAM.
+pub fn load_elf_kernel(
+    kernel_image: &mut KernelImage,
+    sys_mem: &Arc<AddressSpace>,
+    ram_ranges: &[(u64, u64)],
+) -> Result<ElfKernelInfo> {
+    kernel_image.seek(SeekFrom::Start(0))?;
+    let kernel_length = kernel_image.size()?;
+
+    let mut elf_header = Elf64Header::default();
+    kernel_image
+        .read_exact(elf_header.as_mut_bytes())
+        .map_err(|_| invalid_elf("truncated ELF header"))?;
+    elf_header
+        .is_valid()
+        .with_context(|| "ELF header is invalid")?;
+
+    let ep_hdrs = elf_header
+        .parse_prog_hdrs(kernel_image)
+        .with_context(|| "Failed to parse ELF program header")?;
+
+    let mut pvh_entry = None;
+    let mut addr_low = u64::MAX;
+    let mut addr_high = 0_u64;
+    for ph in &ep_hdrs {
+        let ph_offset = ph.p_offset;
+        let ph_size = ph.p_filesz;
+        if ph_offset
+            .checked_add(ph_size)
+            .filter(|end| *end <= kernel_length)
+            .is_none()
+        {
+            bail!(
+                "ELF program header overflows: offset 0x{:x}, size 0x{:x}, ELF file size 0x{:x}",
+                ph_offset,
+                ph_size,
+                kernel_length,
+            );
+        }
+
+        if ph.p_type == PT_LOAD && ph.p_memsz != 0 {
+            let (start, mem_size) = (ph.p_paddr, ph.p_memsz);
+            if ph_size > mem_size {
+                return Err(invalid_elf(&format!(
+                    "segment at 0x{:x} has file size 0x{:x} larger than memory size 0x{:x}",
+                    start, ph_size, mem_size
+                )));
+            }
+            let end = start
+                .checked_add(mem_size)
+                .ok_or_else(|| anyhow!(BootLoaderError::KernelOverflow(start, mem_size)))?;
+            if !ram_ranges
+                .iter()
+                .any(|(ram_start, ram_size)| start >= *ram_start && end <= ram_start + ram_size)
+            {
+                return Err(anyhow!(BootLoaderError::KernelOverflow(start, mem_size)));
+            }
+
+            kernel_image.seek(SeekFrom::Start(ph_offset))?;
+            sys_mem
+                .write(kernel_image, GuestAddress(start), ph_size)
+                .with_context(|| format!("Failed to load ELF segment to 0x{:x}", start))?;
+            // Zero the rest of the segment, e.g. bss.
+            if mem_size > ph_size {
+                sys_mem
+                    .write(
+                        &mut std::io::repeat(0),
+                        GuestAddress(start + ph_size),
+                        mem_size - ph_size,
+                    )
+                    .with_context(|| format!("Failed to zero ELF segment at 0x{:x}", start))?;
+            }
+
+            addr_low = std::cmp::min(addr_low, start);
+            addr_high = std::cmp::max(addr_high, end);
+        }
+        if ph.p_type == PT_NOTE && pvh_entry.is_none() {
+            pvh_entry = parse_pvh_entry(kernel_image, ph)?;
+        }
+    }
+    if addr_low >= addr_high {
+        return Err(invalid_elf("no loadable segment"));
+    }
+
+    Ok(ElfKernelInfo {
+        entry: elf_header.e_entry,
+        pvh_entry,
+        load_range: (addr_low, addr_high - addr_low),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use address_space::{HostMemMapping, Region};
+
+    use super::*;
+
+    /// Build an ELF kernel with a PVH note and a loadable segment at `paddr`,
+    /// which has 8 bytes of data and 8 bytes of bss.
+    fn build_elf_kernel(e_machine: u16, paddr: u64) -> Vec<u8> {
+        let ehdr_size = std::mem::size_of::<Elf64Header>();
+        let phdr_size = std::mem::size_of::<Elf64ProgHeader>();
+        let note_offset = (ehdr_size + phdr_size * 2) as u64;
+        let mut note = Elf64NoteHeader {
+            namesz: XEN_ELFNOTE_NAME.len() as u32,
+            descsz: 8,
+            type_: XEN_ELFNOTE_PHYS32_ENTRY,
+        }
+        .as_bytes()
+        .to_vec();
+        note.extend_from_slice(XEN_ELFNOTE_NAME);
+        note.extend_from_slice(&0x10_0040_u64.to_le_bytes());
+        let load_offset = note_offset + note.len() as u64;
+
+        let mut e_ident = [0_u8; 16];
+        e_ident[..4].copy_from_slice(&ELFMAG);
+        e_ident[EI_CLASS] = ELFCLASS64;
+        e_ident[EI_DATA] = ELFDATA2LSB;
+        let ehdr = Elf64Header {
+            e_ident,
+            e_type: ET_EXEC,
+            e_machine,
+            e_entry: paddr + 0x4,
+            e_phoff: ehdr_size as u64,
+            e_phnum: 2,
+            ..Default::default()
+        };
+        let note_phdr = Elf64ProgHeader {
+            p_type: PT_NOTE,
+            p_offset: note_offset,
+            p_filesz: note.len() as u64,
+            p_align: NOTE_ALIGN,
+            ..Default::default()
+        };
+        let load_phdr = Elf64ProgHeader {
+            p_type: PT_LOAD,
+            p_offset: load_offset,
+            p_paddr: paddr,
+            p_filesz: 8,
+            p_memsz: 16,
+            ..Default::default()
+        };
+
+        let mut elf = ehdr.as_bytes().to_vec();
+        elf.extend_from_slice(note_phdr.as_bytes());
+        elf.extend_from_slice(load_phdr.as_bytes());
+        elf.extend_from_slice(&note);
+        elf.extend_from_slice(&0x1122_3344_5566_7788_u64.to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn test_load_elf_kernel() {
+        let root = Region::init_container_region(0x20_0000);
+        let sys_mem = AddressSpace::new(root.clone()).unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x20_0000, None, false, false, false)
+                .unwrap(),
+        );
+        root.add_subregion(Region::init_ram_region(ram), 0).unwrap();
+        sys_mem
+            .write_object(&u64::MAX, GuestAddress(0x10_0008))
+            .unwrap();
+        let ram_ranges = [(0, 0x20_0000)];
+
+        let mut kernel =
+            KernelImage::Decompressed(Cursor::new(build_elf_kernel(EM_ARCH, 0x10_0000)));
+        assert!(is_elf_kernel(&mut kernel).unwrap());
+        let info = load_elf_kernel(&mut kernel, &sys_mem, &ram_ranges).unwrap();
+        assert_eq!(info.entry, 0x10_0004);
+        assert_eq!(info.pvh_entry, Some(0x10_0040));
+        assert_eq!(info.load_range, (0x10_0000, 16));
+        assert_eq!(
+            sys_mem.read_object::<u64>(GuestAddress(0x10_0000)).unwrap(),
+            0x1122_3344_5566_7788
+        );
+        // The bss is zeroed.
+        assert_eq!(
+            sys_mem.read_object::<u64>(GuestAddress(0x10_0008)).unwrap(),
+            0
+        );
+
+        // The segment is out of guest RAM.
+        let mut kernel =
+            KernelImage::Decompressed(Cursor::new(build_elf_kernel(EM_ARCH, 0x1F_FFF8)));
+        assert!(load_elf_kernel(&mut kernel, &sys_mem, &ram_ranges).is_err());
+
+        // ELF of another architecture.
+        let mut kernel =
+            KernelImage::Decompressed(Cursor::new(build_elf_kernel(EM_ARCH + 1, 0x10_0000)));
+        let err = load_elf_kernel(&mut kernel, &sys_mem, &ram_ranges).unwrap_err();
+        assert!(format!("{:?}", err).contains("unsupported machine"));
+
+        let mut kernel = KernelImage::Decompressed(Cursor::new(vec![0_u8; 0x100]));
+        assert!(!is_elf_kernel(&mut kernel).unwrap());
+    }
+}
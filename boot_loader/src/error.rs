@@ -40,8 +40,12 @@ pub enum BootLoaderError {
     KernelOverflow(u64, u64),
     #[error("Failed to load initrd image {0} to memory {1}.")]
     InitrdOverflow(u64, u64),
+    #[error("Initrd image [0x{0:x}, 0x{1:x}) overlaps {2}.")]
+    InitrdOverlap(u64, u64, String),
     #[error("Failed to open kernel image")]
     BootLoaderOpenKernel,
+    #[error("Failed to decompress {0} kernel image: {1}")]
+    DecompressKernel(&'static str, String),
     #[error("Failed to open initrd image")]
     BootLoaderOpenInitrd,
     #[error("Configure cpu number({0}) above supported max cpu numbers(254)")]
@@ -55,4 +59,6 @@ pub enum BootLoaderError {
     #[error("ELF-format kernel is not supported")]
     #[cfg(target_arch = "x86_64")]
     ElfKernel,
+    #[error("Invalid ELF kernel image: {0}")]
+    InvalidElfKernel(String),
 }
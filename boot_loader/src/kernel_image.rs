@@ -0,0 +1,387 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Kernel image which may be compressed by gzip or lz4.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+
+use crate::error::BootLoaderError;
+
+const GZIP_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+const GZIP_FLAG_HCRC: u8 = 0x02;
+const GZIP_FLAG_EXTRA: u8 = 0x04;
+const GZIP_FLAG_NAME: u8 = 0x08;
+const GZIP_FLAG_COMMENT: u8 = 0x10;
+const GZIP_HEADER_LEN: usize = 10;
+
+/// Magic of lz4 legacy frame, which is produced by `lz4 -l` as linux kernel does.
+const LZ4_LEGACY_MAGIC: u32 = 0x184C_2102;
+/// Magic of lz4 frame.
+const LZ4_FRAME_MAGIC: u32 = 0x184D_2204;
+const LZ4_FLAG_VERSION_MASK: u8 = 0xc0;
+const LZ4_FLAG_VERSION: u8 = 0x40;
+const LZ4_FLAG_BLOCK_CHECKSUM: u8 = 0x10;
+const LZ4_FLAG_CONTENT_SIZE: u8 = 0x08;
+const LZ4_FLAG_CONTENT_CHECKSUM: u8 = 0x04;
+const LZ4_FLAG_DICT_ID: u8 = 0x01;
+const LZ4_BLOCK_UNCOMPRESSED: u32 = 0x8000_0000;
+const LZ4_MIN_MATCH: usize = 4;
+
+/// Kernel image to be loaded, which is decompressed into memory if it's compressed.
+pub enum KernelImage {
+    /// Uncompressed kernel image file.
+    Raw(File),
+    /// Kernel image decompressed from gzip or lz4 compressed file.
+    Decompressed(Cursor<Vec<u8>>),
+}
+
+impl KernelImage {
+    /// Open the kernel image. If it's compressed by gzip or lz4, decompress it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the kernel image.
+    /// * `max_size` - Max size of the decompressed kernel image.
+    pub fn open(path: &Path, max_size: u64) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| anyhow!(BootLoaderError::BootLoaderOpenKernel))?;
+        let mut magic = [0_u8; 4];
+        let magic_len = file.read(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let max_size = usize::try_from(max_size).unwrap_or(usize::MAX);
+        let magic_u32 = u32::from_le_bytes(magic);
+        let data = if magic_len >= GZIP_MAGIC.len() && magic[..GZIP_MAGIC.len()] == GZIP_MAGIC {
+            info!("Decompress gzip kernel image {:?}", path);
+            decompress_gzip(&read_all(&mut file)?, max_size)?
+        } else if magic_len == magic.len() && magic_u32 == LZ4_LEGACY_MAGIC {
+            info!("Decompress lz4 kernel image {:?}", path);
+            decompress_lz4_legacy(&read_all(&mut file)?, max_size)?
+        } else if magic_len == magic.len() && magic_u32 == LZ4_FRAME_MAGIC {
+            info!("Decompress lz4 kernel image {:?}", path);
+            decompress_lz4_frame(&read_all(&mut file)?, max_size)?
+        } else {
+            return Ok(KernelImage::Raw(file));
+        };
+
+        Ok(KernelImage::Decompressed(Cursor::new(data)))
+    }
+
+    /// Get the size of the (decompressed) kernel image.
+    pub fn size(&self) -> Result<u64> {
+        match self {
+            KernelImage::Raw(file) => Ok(file.metadata()?.len()),
+            KernelImage::Decompressed(data) => Ok(data.get_ref().len() as u64),
+        }
+    }
+}
+
+impl Read for KernelImage {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            KernelImage::Raw(file) => file.read(buf),
+            KernelImage::Decompressed(data) => data.read(buf),
+        }
+    }
+}
+
+impl Seek for KernelImage {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            KernelImage::Raw(file) => file.seek(pos),
+            KernelImage::Decompressed(data) => data.seek(pos),
+        }
+    }
+}
+
+fn read_all(file: &mut File) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn decompress_err(format: &'static str, reason: String) -> anyhow::Error {
+    anyhow!(BootLoaderError::DecompressKernel(format, reason))
+}
+
+fn decompress_gzip(input: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    if input.len() < GZIP_HEADER_LEN {
+        return Err(decompress_err("gzip", "truncated header".to_string()));
+    }
+    let flags = input[3];
+    let mut pos = GZIP_HEADER_LEN;
+    if flags & GZIP_FLAG_EXTRA != 0 {
+        let extra_len = input
+            .get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| decompress_err("gzip", "truncated extra field".to_string()))?;
+        pos += 2 + extra_len;
+    }
+    for flag in [GZIP_FLAG_NAME, GZIP_FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let str_len = input
+                .get(pos..)
+                .and_then(|s| s.iter().position(|b| *b == 0))
+                .ok_or_else(|| decompress_err("gzip", "truncated header".to_string()))?;
+            pos += str_len + 1;
+        }
+    }
+    if flags & GZIP_FLAG_HCRC != 0 {
+        pos += 2;
+    }
+    let deflate_data = input
+        .get(pos..)
+        .ok_or_else(|| decompress_err("gzip", "truncated header".to_string()))?;
+
+    miniz_oxide::inflate::decompress_to_vec_with_limit(deflate_data, max_size).map_err(|e| {
+        if e == miniz_oxide::inflate::TINFLStatus::HasMoreOutput {
+            decompress_err("gzip", format!("size exceeds 0x{:x}", max_size))
+        } else {
+            decompress_err("gzip", format!("{:?}", e))
+        }
+    })
+}
+
+fn read_u32_le(input: &[u8], pos: usize) -> Option<u32> {
+    input
+        .get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Decompress one lz4 block and append the data to `output`. The back references
+/// may point to data of the previous blocks, as `output` is contiguous.
+fn decompress_lz4_block(input: &[u8], output: &mut Vec<u8>, max_size: usize) -> Result<()> {
+    let truncated = || decompress_err("lz4", "truncated block".to_string());
+    let read_len = |pos: &mut usize, mut len: usize| -> Result<usize> {
+        if len == 0xf {
+            loop {
+                let byte = *input.get(*pos).ok_or_else(truncated)?;
+                *pos += 1;
+                len += byte as usize;
+                if byte != 0xff {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    };
+
+    let mut pos = 0;
+    while pos < input.len() {
+        let token = input[pos];
+        pos += 1;
+
+        let literal_len = read_len(&mut pos, (token >> 4) as usize)?;
+        let literal = input.get(pos..pos + literal_len).ok_or_else(truncated)?;
+        if output.len() + literal_len > max_size {
+            return Err(decompress_err(
+                "lz4",
+                format!("size exceeds 0x{:x}", max_size),
+            ));
+        }
+        output.extend_from_slice(literal);
+        pos += literal_len;
+        // The last sequence contains literals only.
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = input
+            .get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(truncated)?;
+        pos += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(decompress_err("lz4", format!("invalid offset {}", offset)));
+        }
+        let match_len = read_len(&mut pos, (token & 0xf) as usize)? + LZ4_MIN_MATCH;
+        if output.len() + match_len > max_size {
+            return Err(decompress_err(
+                "lz4",
+                format!("size exceeds 0x{:x}", max_size),
+            ));
+        }
+        // The matched data may overlap the data being copied, so copy it byte by byte.
+        let match_start = output.len() - offset;
+        for i in match_start..match_start + match_len {
+            output.push(output[i]);
+        }
+    }
+    Ok(())
+}
+
+fn decompress_lz4_legacy(input: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut pos = 4;
+    while let Some(block_size) = read_u32_le(input, pos) {
+        pos += 4;
+        // Legacy frames may be concatenated.
+        if block_size == LZ4_LEGACY_MAGIC {
+            continue;
+        }
+        // Linux kernel appends the uncompressed size to the end of compressed image.
+        if pos == input.len() {
+            break;
+        }
+        let block = input
+            .get(pos..pos + block_size as usize)
+            .ok_or_else(|| decompress_err("lz4", "truncated block".to_string()))?;
+        decompress_lz4_block(block, &mut output, max_size)?;
+        pos += block_size as usize;
+    }
+    Ok(output)
+}
+
+fn decompress_lz4_frame(input: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let truncated = || decompress_err("lz4", "truncated frame".to_string());
+    let flags = *input.get(4).ok_or_else(truncated)?;
+    if flags & LZ4_FLAG_VERSION_MASK != LZ4_FLAG_VERSION {
+        return Err(decompress_err(
+            "lz4",
+            format!("unsupported frame version flags 0x{:x}", flags),
+        ));
+    }
+    // Skip magic, FLG, BD, optional content size and dictionary id, and header checksum.
+    let mut pos = 6;
+    if flags & LZ4_FLAG_CONTENT_SIZE != 0 {
+        pos += 8;
+    }
+    if flags & LZ4_FLAG_DICT_ID != 0 {
+        pos += 4;
+    }
+    pos += 1;
+
+    let mut output = Vec::new();
+    loop {
+        let block_size = read_u32_le(input, pos).ok_or_else(truncated)?;
+        pos += 4;
+        // End mark of the frame.
+        if block_size == 0 {
+            break;
+        }
+        let size = (block_size & !LZ4_BLOCK_UNCOMPRESSED) as usize;
+        let block = input.get(pos..pos + size).ok_or_else(truncated)?;
+        if block_size & LZ4_BLOCK_UNCOMPRESSED != 0 {
+            if output.len() + size > max_size {
+                return Err(decompress_err(
+                    "lz4",
+                    format!("size exceeds 0x{:x}", max_size),
+                ));
+            }
+            output.extend_from_slice(block);
+        } else {
+            decompress_lz4_block(block, &mut output, max_size)?;
+        }
+        pos += size;
+        if flags & LZ4_FLAG_BLOCK_CHECKSUM != 0 {
+            pos += 4;
+        }
+    }
+    if flags & LZ4_FLAG_CONTENT_CHECKSUM != 0 && pos + 4 > input.len() {
+        return Err(truncated());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn create_image(path: &str, data: &[u8]) {
+        let mut file = File::create(path).unwrap();
+        file.write_all(data).unwrap();
+    }
+
+    #[test]
+    fn test_kernel_image() {
+        let kernel: Vec<u8> = (0..0x3000_u32).map(|i| (i % 251) as u8).collect();
+
+        // Raw kernel image.
+        let path = "/tmp/test_kernel_image_raw";
+        create_image(path, &kernel);
+        let mut image = KernelImage::open(Path::new(path), 0x1_0000).unwrap();
+        assert!(matches!(image, KernelImage::Raw(_)));
+        assert_eq!(image.size().unwrap(), kernel.len() as u64);
+        let mut data = Vec::new();
+        image.read_to_end(&mut data).unwrap();
+        assert_eq!(data, kernel);
+        std::fs::remove_file(path).unwrap();
+
+        // Gzip kernel image, with file name in header.
+        let path = "/tmp/test_kernel_image_gzip";
+        let mut gzip = vec![0x1f, 0x8b, 0x08, GZIP_FLAG_NAME, 0, 0, 0, 0, 0, 3];
+        gzip.extend_from_slice(b"Image\0");
+        gzip.extend(miniz_oxide::deflate::compress_to_vec(&kernel, 6));
+        gzip.extend_from_slice(&[0; 8]);
+        create_image(path, &gzip);
+        let mut image = KernelImage::open(Path::new(path), 0x1_0000).unwrap();
+        assert!(matches!(image, KernelImage::Decompressed(_)));
+        assert_eq!(image.size().unwrap(), kernel.len() as u64);
+        image.seek(SeekFrom::Start(0x1000)).unwrap();
+        let mut data = Vec::new();
+        image.read_to_end(&mut data).unwrap();
+        assert_eq!(data, kernel[0x1000..]);
+        // Decompressed kernel image is larger than guest memory.
+        assert!(KernelImage::open(Path::new(path), 0x2fff).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        // Lz4 legacy kernel image: 4 literals "abcd", then a 12-bytes match with offset 4,
+        // followed by the size appended by linux kernel.
+        let path = "/tmp/test_kernel_image_lz4";
+        let mut lz4 = LZ4_LEGACY_MAGIC.to_le_bytes().to_vec();
+        let block = [0x48, b'a', b'b', b'c', b'd', 0x04, 0x00, 0x10, b'e'];
+        lz4.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        lz4.extend_from_slice(&block);
+        lz4.extend_from_slice(&17_u32.to_le_bytes());
+        create_image(path, &lz4);
+        let mut image = KernelImage::open(Path::new(path), 0x1_0000).unwrap();
+        let mut data = Vec::new();
+        image.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"abcdabcdabcdabcde".to_vec());
+        assert!(KernelImage::open(Path::new(path), 16).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        // Lz4 frame kernel image, with an uncompressed block and a compressed block.
+        let path = "/tmp/test_kernel_image_lz4_frame";
+        let mut lz4 = LZ4_FRAME_MAGIC.to_le_bytes().to_vec();
+        lz4.extend_from_slice(&[LZ4_FLAG_VERSION, 0x40, 0x00]);
+        lz4.extend_from_slice(&(2 | LZ4_BLOCK_UNCOMPRESSED).to_le_bytes());
+        lz4.extend_from_slice(b"xy");
+        let block = [0x00, 0x02, 0x00];
+        lz4.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        lz4.extend_from_slice(&block);
+        lz4.extend_from_slice(&0_u32.to_le_bytes());
+        create_image(path, &lz4);
+        let mut image = KernelImage::open(Path::new(path), 0x1_0000).unwrap();
+        let mut data = Vec::new();
+        image.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"xyxyxy".to_vec());
+        std::fs::remove_file(path).unwrap();
+
+        // Corrupted lz4 image is rejected.
+        let path = "/tmp/test_kernel_image_lz4_bad";
+        let mut lz4 = LZ4_LEGACY_MAGIC.to_le_bytes().to_vec();
+        let block = [0x10, b'a', 0x08, 0x00];
+        lz4.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        lz4.extend_from_slice(&block);
+        create_image(path, &lz4);
+        assert!(KernelImage::open(Path::new(path), 0x1_0000).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
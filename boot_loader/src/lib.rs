@@ -17,7 +17,8 @@
 //! ## Design
 //!
 //! This crate offers support for:
-//! 1. Loading PE (vmlinux.bin) kernel images and bzImage kernel images (only in x86_64).
+//! 1. Loading PE (vmlinux.bin) kernel images, ELF kernel images and bzImage kernel
+//!    images (only in x86_64).
 //! 2. Loading initrd image.
 //! 3. Initialization for architecture related information.
 //!
@@ -76,7 +77,10 @@
 #[allow(clippy::upper_case_acronyms)]
 #[cfg(target_arch = "aarch64")]
 mod aarch64;
+#[allow(non_camel_case_types)]
+mod elf;
 pub mod error;
+mod kernel_image;
 #[cfg(target_arch = "x86_64")]
 mod x86_64;
 
@@ -94,3 +98,87 @@ pub use x86_64::load_linux;
 pub use x86_64::X86BootLoader as BootLoader;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86BootLoaderConfig as BootLoaderConfig;
+
+use anyhow::{anyhow, Result};
+
+/// Check that the initrd image lies in guest RAM and doesn't overlap the kernel image.
+///
+/// # Arguments
+///
+/// * `initrd` - Start address and size of the initrd image.
+/// * `kernel` - Start address and size of the kernel image.
+/// * `ram_ranges` - Start address and size of each guest RAM range, sorted by address.
+fn check_initrd_range(
+    initrd: (u64, u64),
+    kernel: Option<(u64, u64)>,
+    ram_ranges: &[(u64, u64)],
+) -> Result<()> {
+    let (start, size) = initrd;
+    let end = start
+        .checked_add(size)
+        .ok_or_else(|| anyhow!(BootLoaderError::InitrdOverflow(start, size)))?;
+
+    if let Some((kernel_start, kernel_size)) = kernel {
+        let kernel_end = kernel_start + kernel_size;
+        if start < kernel_end && kernel_start < end {
+            return Err(anyhow!(BootLoaderError::InitrdOverlap(
+                start,
+                end,
+                format!("kernel image [0x{:x}, 0x{:x})", kernel_start, kernel_end)
+            )));
+        }
+    }
+
+    if ram_ranges
+        .iter()
+        .any(|(ram_start, ram_size)| start >= *ram_start && end <= ram_start + ram_size)
+    {
+        return Ok(());
+    }
+
+    // Find the first hole between RAM ranges that the initrd image overlaps.
+    let mut hole_start = 0;
+    for (ram_start, ram_size) in ram_ranges {
+        if hole_start < *ram_start && start < *ram_start && hole_start < end {
+            return Err(anyhow!(BootLoaderError::InitrdOverlap(
+                start,
+                end,
+                format!("non-RAM region [0x{:x}, 0x{:x})", hole_start, ram_start)
+            )));
+        }
+        hole_start = ram_start + ram_size;
+    }
+    Err(anyhow!(BootLoaderError::InitrdOverlap(
+        start,
+        end,
+        format!("the end of guest RAM 0x{:x}", hole_start)
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_initrd_range() {
+        // RAM ranges split by a memory gap [0xc000_0000, 0x1_0000_0000).
+        let ram_ranges = [(0, 0xc000_0000), (0x1_0000_0000, 0x4000_0000)];
+        let kernel = Some((0x10_0000, 0x80_0000));
+
+        assert!(check_initrd_range((0x1000_0000, 0x1000), kernel, &ram_ranges).is_ok());
+        assert!(check_initrd_range((0x1_0000_0000, 0x4000_0000), kernel, &ram_ranges).is_ok());
+        assert!(check_initrd_range((0x1000_0000, 0x1000), None, &ram_ranges).is_ok());
+
+        let err = check_initrd_range((0x80_0000, 0x1000_0000), kernel, &ram_ranges).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("kernel image [0x100000, 0x900000)"));
+        let err = check_initrd_range((0xbfff_f000, 0x2000), kernel, &ram_ranges).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("non-RAM region [0xc0000000, 0x100000000)"));
+        let err = check_initrd_range((0x1_3fff_f000, 0x1001), kernel, &ram_ranges).unwrap_err();
+        assert!(err.to_string().contains("the end of guest RAM 0x140000000"));
+        assert!(check_initrd_range((u64::MAX - 0xfff, 0x1000), kernel, &ram_ranges).is_err());
+    }
+}
@@ -103,6 +103,15 @@ impl RealModeKernelHeader {
         Ok(())
     }
 
+    /// Size of the real-mode setup code, which is followed by the protected-mode kernel.
+    pub fn setup_size(&self) -> u64 {
+        let mut setup_sects = self.setup_sects as u64;
+        if setup_sects == 0 {
+            setup_sects = 4;
+        }
+        (setup_sects + 1) << 9
+    }
+
     pub fn set_cmdline(&mut self, cmdline_addr: u32, cmdline_size: u32) {
         self.cmdline_ptr = cmdline_addr;
         self.cmdline_size = cmdline_size;
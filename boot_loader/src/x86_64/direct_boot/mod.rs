@@ -24,12 +24,14 @@ use util::byte_code::ByteCode;
 use self::gdt::setup_gdt;
 use self::mptable::setup_isa_mptable;
 use super::bootparam::{BootParams, RealModeKernelHeader, UNDEFINED_ID};
-use super::{X86BootLoader, X86BootLoaderConfig};
+use super::{initrd_load_addr, ram_ranges, X86BootLoader, X86BootLoaderConfig};
 use super::{
-    BOOT_HDR_START, BOOT_LOADER_SP, BZIMAGE_BOOT_OFFSET, CMDLINE_START, EBDA_START,
-    INITRD_ADDR_MAX, PDE_START, PDPTE_START, PML4_START, VMLINUX_STARTUP, ZERO_PAGE_START,
+    BOOT_HDR_START, BOOT_LOADER_SP, BZIMAGE_BOOT_OFFSET, CMDLINE_START, EBDA_START, PDE_START,
+    PDPTE_START, PML4_START, VMLINUX_STARTUP, ZERO_PAGE_START,
 };
+use crate::elf::{is_elf_kernel, load_elf_kernel};
 use crate::error::BootLoaderError;
+use crate::kernel_image::KernelImage;
 use anyhow::{anyhow, bail, Context, Result};
 /// Load bzImage linux kernel to Guest Memory.
 ///
@@ -51,7 +53,7 @@ use anyhow::{anyhow, bail, Context, Result};
 ///
 /// * Invalid BzImage header or version.
 /// * Failed to write bzImage linux kernel to guest memory.
-fn load_bzimage(kernel_image: &mut File) -> Result<RealModeKernelHeader> {
+fn load_bzimage(kernel_image: &mut KernelImage) -> Result<RealModeKernelHeader> {
     let mut boot_hdr = RealModeKernelHeader::new();
 
     kernel_image.seek(SeekFrom::Start(BOOT_HDR_START))?;
@@ -65,12 +67,7 @@ fn load_bzimage(kernel_image: &mut File) -> Result<RealModeKernelHeader> {
         return Err(e);
     }
 
-    let mut setup_size = boot_hdr.setup_sects as u64;
-    if setup_size == 0 {
-        setup_size = 4;
-    }
-    setup_size = (setup_size + 1) << 9;
-    kernel_image.seek(SeekFrom::Start(setup_size))?;
+    kernel_image.seek(SeekFrom::Start(boot_hdr.setup_size()))?;
 
     Ok(boot_hdr)
 }
@@ -85,23 +82,41 @@ fn load_bzimage(kernel_image: &mut File) -> Result<RealModeKernelHeader> {
 /// # Errors
 ///
 /// * Write image to guest memory failed.
-fn load_image(image: &mut File, start_addr: u64, sys_mem: &Arc<AddressSpace>) -> Result<()> {
+fn load_image<T: Read + Seek>(
+    image: &mut T,
+    start_addr: u64,
+    sys_mem: &Arc<AddressSpace>,
+) -> Result<u64> {
     let curr_loc = image.seek(SeekFrom::Current(0))?;
     let len = image.seek(SeekFrom::End(0))?;
     image.seek(SeekFrom::Start(curr_loc))?;
 
     sys_mem.write(image, GuestAddress(start_addr), len - curr_loc)?;
 
-    Ok(())
+    Ok(len - curr_loc)
 }
 
+/// Load linux kernel image to Guest Memory, return the boot header, and the start
+/// address and size of the kernel in guest memory.
+///
+/// ELF kernel is loaded according to its program headers, and boots from the
+/// 64-bit entry in ELF header.
 fn load_kernel_image(
-    kernel_path: &std::path::Path,
+    config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
     boot_layout: &mut X86BootLoader,
-) -> Result<RealModeKernelHeader> {
-    let mut kernel_image =
-        File::open(kernel_path).with_context(|| anyhow!(BootLoaderError::BootLoaderOpenKernel))?;
+) -> Result<(RealModeKernelHeader, (u64, u64))> {
+    let mut kernel_image = KernelImage::open(
+        config.kernel.as_ref().unwrap(),
+        sys_mem.memory_end_address().raw_value(),
+    )?;
+
+    if is_elf_kernel(&mut kernel_image)? {
+        let elf_info = load_elf_kernel(&mut kernel_image, sys_mem, &ram_ranges(config, sys_mem))
+            .with_context(|| "Failed to load ELF kernel")?;
+        boot_layout.boot_ip = elf_info.entry;
+        return Ok((RealModeKernelHeader::new(), elf_info.load_range));
+    }
 
     let (boot_hdr, kernel_start, vmlinux_start) = if let Ok(hdr) = load_bzimage(&mut kernel_image) {
         (
@@ -117,33 +132,36 @@ fn load_kernel_image(
         )
     };
 
-    load_image(&mut kernel_image, vmlinux_start, sys_mem)
+    let image_size = kernel_image.size()? - kernel_image.seek(SeekFrom::Current(0))?;
+    if vmlinux_start + image_size > sys_mem.memory_end_address().raw_value() {
+        return Err(anyhow!(BootLoaderError::KernelOverflow(
+            vmlinux_start,
+            image_size
+        )));
+    }
+    let kernel_size = load_image(&mut kernel_image, vmlinux_start, sys_mem)
         .with_context(|| "Failed to load image")?;
 
     boot_layout.boot_ip = kernel_start;
 
-    Ok(boot_hdr)
+    Ok((boot_hdr, (vmlinux_start, kernel_size)))
 }
 
 fn load_initrd(
     config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
     header: &mut RealModeKernelHeader,
+    kernel: (u64, u64),
 ) -> Result<()> {
     if config.initrd.is_none() {
         info!("No initrd image file.");
         return Ok(());
     };
 
-    let mut initrd_addr_max = INITRD_ADDR_MAX;
-    if initrd_addr_max > sys_mem.memory_end_address().raw_value() {
-        initrd_addr_max = sys_mem.memory_end_address().raw_value();
-    };
-
     let mut initrd_image = File::open(config.initrd.as_ref().unwrap())
         .with_context(|| anyhow!(BootLoaderError::BootLoaderOpenInitrd))?;
     let initrd_size = initrd_image.metadata().unwrap().len();
-    let initrd_addr = (initrd_addr_max - initrd_size) & !0xfff_u64;
+    let initrd_addr = initrd_load_addr(config, sys_mem, initrd_size, Some(kernel))?;
 
     load_image(&mut initrd_image, initrd_addr, sys_mem).with_context(|| "Failed to load image")?;
 
@@ -214,7 +232,7 @@ fn setup_kernel_cmdline(
     Ok(())
 }
 
-/// Load PE(vmlinux.bin) linux kernel / bzImage linux kernel and
+/// Load PE(vmlinux.bin) / ELF / bzImage linux kernel and
 /// other boot source to Guest Memory.
 ///
 /// # Steps
@@ -246,13 +264,9 @@ pub fn load_linux(
         zero_page_addr: ZERO_PAGE_START,
         ..Default::default()
     };
-    let mut boot_header = load_kernel_image(
-        config.kernel.as_ref().unwrap(),
-        sys_mem,
-        &mut boot_loader_layout,
-    )?;
+    let (mut boot_header, kernel) = load_kernel_image(config, sys_mem, &mut boot_loader_layout)?;
 
-    load_initrd(config, sys_mem, &mut boot_header)
+    load_initrd(config, sys_mem, &mut boot_header, kernel)
         .with_context(|| "Failed to load initrd to vm memory")?;
 
     setup_kernel_cmdline(config, sys_mem, &mut boot_header)
@@ -402,4 +416,54 @@ mod test {
         let s = String::from_utf8(read_buffer.to_vec()).unwrap();
         assert_eq!(s, "this_is_a_piece_of_test_string".to_string());
     }
+
+    #[test]
+    fn test_x86_load_initrd() {
+        let root = Region::init_container_region(0x4000_0000);
+        let space = AddressSpace::new(root.clone()).unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x400_0000, None, false, false, false)
+                .unwrap(),
+        );
+        root.add_subregion(Region::init_ram_region(ram), 0).unwrap();
+
+        // Kernel image which isn't bzImage is loaded at `VMLINUX_STARTUP`.
+        let kernel_path = "/tmp/test_x86_load_initrd_kernel";
+        let kernel_file = File::create(kernel_path).unwrap();
+        kernel_file.set_len(0x1000).unwrap();
+        let initrd_path = "/tmp/test_x86_load_initrd_initrd";
+        let initrd_file = File::create(initrd_path).unwrap();
+
+        let config = X86BootLoaderConfig {
+            kernel: Some(PathBuf::from(kernel_path)),
+            initrd: Some(PathBuf::from(initrd_path)),
+            kernel_cmdline: String::new(),
+            cpu_count: 1,
+            gap_range: (0xC000_0000, 0x4000_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            prot64_mode: true,
+            ident_tss_range: None,
+        };
+        let mut boot_layout = X86BootLoader::default();
+        let (mut boot_hdr, kernel) = load_kernel_image(&config, &space, &mut boot_layout).unwrap();
+        assert_eq!(kernel, (VMLINUX_STARTUP, 0x1000));
+
+        // Initrd fills all the RAM above kernel.
+        let available = 0x400_0000 - VMLINUX_STARTUP - 0x1000;
+        initrd_file.set_len(available).unwrap();
+        assert!(load_initrd(&config, &space, &mut boot_hdr, kernel).is_ok());
+
+        // Initrd just overflows the available RAM, overlapping kernel.
+        initrd_file.set_len(available + 1).unwrap();
+        let err = load_initrd(&config, &space, &mut boot_hdr, kernel).unwrap_err();
+        assert!(err.to_string().contains("kernel image"));
+
+        // Initrd is larger than the whole RAM.
+        initrd_file.set_len(0x400_0001).unwrap();
+        assert!(load_initrd(&config, &space, &mut boot_hdr, kernel).is_err());
+
+        std::fs::remove_file(kernel_path).unwrap();
+        std::fs::remove_file(initrd_path).unwrap();
+    }
 }
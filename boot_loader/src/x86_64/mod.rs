@@ -10,7 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-//! Boot Loader load PE and bzImage linux kernel image to guest memory according
+//! Boot Loader load PE, ELF and bzImage linux kernel image to guest memory according
 //! [`x86 boot protocol`](https://www.kernel.org/doc/Documentation/x86/boot.txt).
 //!
 //! Below is x86_64 bootloader memory layout:
@@ -63,7 +63,10 @@ use address_space::AddressSpace;
 use devices::legacy::FwCfgOps;
 use kvm_bindings::kvm_segment;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
+
+use crate::check_initrd_range;
+use crate::error::BootLoaderError;
 
 const ZERO_PAGE_START: u64 = 0x0000_7000;
 const PML4_START: u64 = 0x0000_9000;
@@ -135,6 +138,54 @@ pub struct BootGdtSegment {
     pub idt_limit: u16,
 }
 
+/// Get the start address and size of each guest RAM range, which is split by
+/// the memory gap below 4G.
+fn ram_ranges(config: &X86BootLoaderConfig, sys_mem: &Arc<AddressSpace>) -> Vec<(u64, u64)> {
+    let mem_end = sys_mem.memory_end_address().raw_value();
+    let (gap_start, gap_size) = config.gap_range;
+    let mut ram_ranges = vec![(0, std::cmp::min(mem_end, gap_start))];
+    if mem_end > gap_start + gap_size {
+        ram_ranges.push((gap_start + gap_size, mem_end - gap_start - gap_size));
+    }
+    ram_ranges
+}
+
+/// Get the guest address to load initrd, which is the highest page-aligned address
+/// below `INITRD_ADDR_MAX` that can hold the whole initrd image.
+///
+/// # Arguments
+///
+/// * `config` - Boot source config, contains the memory gap below 4G.
+/// * `sys_mem` - Guest memory.
+/// * `initrd_size` - Size of the initrd image.
+/// * `kernel` - Start address and size of the kernel image.
+///
+/// # Errors
+///
+/// The initrd image doesn't fit in guest RAM, or overlaps the kernel image.
+fn initrd_load_addr(
+    config: &X86BootLoaderConfig,
+    sys_mem: &Arc<AddressSpace>,
+    initrd_size: u64,
+    kernel: Option<(u64, u64)>,
+) -> Result<u64> {
+    let initrd_addr_max = std::cmp::min(INITRD_ADDR_MAX, sys_mem.memory_end_address().raw_value());
+    let initrd_addr = initrd_addr_max.checked_sub(initrd_size).ok_or_else(|| {
+        anyhow!(BootLoaderError::InitrdOverflow(
+            initrd_addr_max,
+            initrd_size
+        ))
+    })? & !0xfff_u64;
+
+    check_initrd_range(
+        (initrd_addr, initrd_size),
+        kernel,
+        &ram_ranges(config, sys_mem),
+    )?;
+
+    Ok(initrd_addr)
+}
+
 pub fn load_linux(
     config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
@@ -10,9 +10,6 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-#[allow(non_camel_case_types)]
-mod elf;
-
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::sync::Arc;
@@ -22,22 +19,23 @@ use devices::legacy::{FwCfgEntryType, FwCfgOps};
 use log::{error, info};
 use util::byte_code::ByteCode;
 
-use self::elf::load_elf_kernel;
 use super::bootparam::RealModeKernelHeader;
-use super::X86BootLoaderConfig;
+use super::{ram_ranges, X86BootLoaderConfig};
 use super::{BOOT_HDR_START, CMDLINE_START};
+use crate::elf::load_elf_kernel;
 use crate::error::BootLoaderError;
+use crate::kernel_image::KernelImage;
 use crate::x86_64::bootparam::{E820Entry, E820_RAM, E820_RESERVED, UEFI_OVMF_ID};
-use crate::x86_64::{INITRD_ADDR_MAX, SETUP_START};
+use crate::x86_64::{initrd_load_addr, SETUP_START};
 use anyhow::{anyhow, bail, Context, Result};
 
-fn load_image(
-    image: &mut File,
+fn load_image<T: Read + Seek>(
+    image: &mut T,
     file_offset: u64,
     key: FwCfgEntryType,
     fwcfg: &mut dyn FwCfgOps,
 ) -> Result<()> {
-    let file_len = image.metadata().unwrap().len();
+    let file_len = image.seek(SeekFrom::End(0))?;
     if file_offset >= file_len {
         bail!(
             "File offset 0x{:x} overflows file length 0x{:x}",
@@ -55,21 +53,17 @@ fn load_image(
 }
 
 fn load_kernel_image(
-    kernel_image: &mut File,
+    kernel_image: &mut KernelImage,
     header: &RealModeKernelHeader,
     fwcfg: &mut dyn FwCfgOps,
 ) -> Result<Vec<u8>> {
-    let mut setup_size = header.setup_sects as u64;
-    if setup_size == 0 {
-        setup_size = 4;
-    }
-    setup_size = (setup_size + 1) << 9;
+    let setup_size = header.setup_size();
 
     let mut setup_data = vec![0_u8; setup_size as usize];
     kernel_image.seek(SeekFrom::Start(0))?;
     kernel_image.read_exact(setup_data.as_mut_slice())?;
 
-    let kernel_size = kernel_image.metadata().unwrap().len() - setup_size;
+    let kernel_size = kernel_image.size()? - setup_size;
     load_image(kernel_image, setup_size, FwCfgEntryType::KernelData, fwcfg)
         .with_context(|| "Failed to load kernel image")?;
 
@@ -92,20 +86,17 @@ fn load_initrd(
     sys_mem: &Arc<AddressSpace>,
     header: &mut RealModeKernelHeader,
     fwcfg: &mut dyn FwCfgOps,
+    kernel: Option<(u64, u64)>,
 ) -> Result<()> {
     if config.initrd.is_none() {
         info!("No initrd image file.");
         return Ok(());
     };
-    let mut initrd_addr_max = INITRD_ADDR_MAX;
-    if initrd_addr_max > sys_mem.memory_end_address().raw_value() {
-        initrd_addr_max = sys_mem.memory_end_address().raw_value();
-    };
 
     let mut initrd_image = File::open(config.initrd.as_ref().unwrap())
         .with_context(|| anyhow!(BootLoaderError::BootLoaderOpenInitrd))?;
     let initrd_size = initrd_image.metadata().unwrap().len();
-    let initrd_addr = (initrd_addr_max - initrd_size) & !0xfff_u64;
+    let initrd_addr = initrd_load_addr(config, sys_mem, initrd_size, kernel)?;
 
     load_image(&mut initrd_image, 0, FwCfgEntryType::InitrdData, fwcfg)
         .with_context(|| "Failed to load initrd")?;
@@ -190,6 +181,41 @@ fn load_kernel_cmdline(
     Ok(())
 }
 
+/// Load ELF-format kernel to guest memory, and pass its PVH entry to firmware.
+///
+/// # Arguments
+///
+/// * `kernel_image` - ELF-format kernel file.
+/// * `config` - Boot source config, contains the memory gap below 4G.
+/// * `sys_mem` - Guest memory.
+/// * `fwcfg` - FwCfg device.
+fn load_pvh_kernel(
+    kernel_image: &mut KernelImage,
+    config: &X86BootLoaderConfig,
+    sys_mem: &Arc<AddressSpace>,
+    fwcfg: &mut dyn FwCfgOps,
+) -> Result<()> {
+    let elf_info = load_elf_kernel(kernel_image, sys_mem, &ram_ranges(config, sys_mem))?;
+    let pvh_entry = elf_info
+        .pvh_entry
+        .with_context(|| "No Note header contains PVH entry info in ELF kernel image.")?;
+    let (kernel_addr, kernel_size) = elf_info.load_range;
+
+    fwcfg.add_data_entry(
+        FwCfgEntryType::KernelEntry,
+        (pvh_entry as u32).as_bytes().to_vec(),
+    )?;
+    fwcfg.add_data_entry(
+        FwCfgEntryType::KernelAddr,
+        (kernel_addr as u32).as_bytes().to_vec(),
+    )?;
+    fwcfg.add_data_entry(
+        FwCfgEntryType::KernelSize,
+        (kernel_size as u32).as_bytes().to_vec(),
+    )?;
+    Ok(())
+}
+
 /// Load ELF-format / bzImage linux kernel and other boot source.
 ///
 /// # Arguments
@@ -207,8 +233,10 @@ pub fn load_linux(
         return Ok(());
     }
 
-    let mut kernel_image = File::open(config.kernel.as_ref().unwrap().clone())
-        .with_context(|| anyhow!(BootLoaderError::BootLoaderOpenKernel))?;
+    let mut kernel_image = KernelImage::open(
+        config.kernel.as_ref().unwrap(),
+        sys_mem.memory_end_address().raw_value(),
+    )?;
 
     let mut boot_header = RealModeKernelHeader::default();
     kernel_image.seek(SeekFrom::Start(BOOT_HDR_START))?;
@@ -217,12 +245,22 @@ pub fn load_linux(
 
     load_kernel_cmdline(config, &mut boot_header, fwcfg)?;
     setup_e820_table(config, sys_mem, fwcfg)?;
-    load_initrd(config, sys_mem, &mut boot_header, fwcfg)?;
+    // The protected-mode part of bzImage kernel is loaded to `code32_start` by firmware,
+    // while ELF kernel is loaded according to its program headers.
+    let kernel = if boot_header.check_valid_kernel().is_ok() {
+        let kernel_size = kernel_image
+            .size()?
+            .saturating_sub(boot_header.setup_size());
+        Some((boot_header.code32_start as u64, kernel_size))
+    } else {
+        None
+    };
+    load_initrd(config, sys_mem, &mut boot_header, fwcfg, kernel)?;
     if let Err(e) = boot_header.check_valid_kernel() {
         if let Some(err) = e.downcast_ref::<BootLoaderError>() {
             match err {
                 BootLoaderError::ElfKernel => {
-                    load_elf_kernel(&mut kernel_image, sys_mem, fwcfg)?;
+                    load_pvh_kernel(&mut kernel_image, config, sys_mem, fwcfg)?;
                     return Ok(());
                 }
                 _ => return Err(e),
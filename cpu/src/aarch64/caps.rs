@@ -28,6 +28,7 @@ pub struct ArmCPUCaps {
     pub user_mem: bool,
     pub psci02: bool,
     pub mp_state: bool,
+    pub pmu_v3: bool,
 }
 
 impl ArmCPUCaps {
@@ -42,6 +43,7 @@ impl ArmCPUCaps {
             user_mem: kvm.check_extension(Cap::UserMemory),
             psci02: kvm.check_extension(Cap::ArmPsci02),
             mp_state: kvm.check_extension(Cap::MpState),
+            pmu_v3: kvm.check_extension(Cap::ArmPmuV3),
         }
     }
 }
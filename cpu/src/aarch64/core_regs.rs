@@ -125,6 +125,15 @@ impl From<Arm64CoreRegs> for u64 {
     }
 }
 
+/// Returns the program counter of the vcpu.
+///
+/// # Arguments
+///
+/// * `vcpu_fd` - the VcpuFd in KVM mod.
+pub fn get_pc(vcpu_fd: &VcpuFd) -> Result<u64> {
+    Ok(vcpu_fd.get_one_reg(Arm64CoreRegs::UserPTRegPc.into())? as u64)
+}
+
 /// Returns the vcpu's current `core_register`.
 ///
 /// The register state is gotten from `KVM_GET_ONE_REG` api in KVM.
@@ -29,7 +29,7 @@ use kvm_ioctls::{DeviceFd, VcpuFd};
 
 use self::caps::CpregListEntry;
 pub use self::caps::{ArmCPUCaps, ArmCPUFeatures};
-use self::core_regs::{get_core_regs, set_core_regs};
+pub use self::core_regs::{get_core_regs, get_pc, set_core_regs};
 use crate::CPU;
 use anyhow::{anyhow, Context, Result};
 
@@ -330,6 +330,13 @@ impl StateTransfer for CPU {
     fn set_state(&self, state: &[u8]) -> migration::Result<()> {
         let cpu_state = *ArmCPUState::from_bytes(state)
             .ok_or_else(|| anyhow!(MigrationError::FromBytesError("CPU")))?;
+        if cpu_state.features.pmu && !self.caps.pmu_v3 {
+            return Err(anyhow!(MigrationError::MigrationConfigErr(
+                "pmu".to_string(),
+                "on".to_string(),
+                "not supported".to_string(),
+            )));
+        }
 
         let mut cpu_state_locked = self.arch_cpu.lock().unwrap();
         *cpu_state_locked = cpu_state;
@@ -32,6 +32,8 @@ pub enum CpuError {
     KickVcpu(String),
     #[error("Failed to destroy kvm vcpu: {0}!")]
     DestroyVcpu(String),
+    #[error("Failed to single step kvm vcpu: {0}!")]
+    StepVcpu(String),
     #[error("CPU {0}/KVM halted!")]
     VcpuHltEvent(u8),
     #[error("CPU {0}/KVM received an unexpected exit reason: {1}!")]
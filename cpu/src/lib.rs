@@ -35,8 +35,12 @@ mod aarch64;
 mod x86_64;
 
 pub mod error;
+mod liveness;
+mod stats;
 use anyhow::{anyhow, Context, Result};
 pub use error::CpuError;
+pub use liveness::start_liveness_monitor;
+pub use stats::{thread_cpu_time, VcpuExitReason, VcpuExitStats, VCPU_EXIT_REASONS};
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::ArmCPUBootConfig as CPUBootConfig;
@@ -52,10 +56,14 @@ pub use aarch64::ArmCPUTopology as CPUTopology;
 pub use aarch64::PMU_INTR;
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::PPI_BASE;
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::{get_core_regs, set_core_regs};
 use machine_manager::qmp::qmp_schema;
 #[cfg(target_arch = "x86_64")]
 use x86_64::caps::X86CPUCaps as CPUCaps;
 #[cfg(target_arch = "x86_64")]
+pub use x86_64::caps::X86CPUFeatures as CPUFeatures;
+#[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUBootConfig as CPUBootConfig;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUState as ArchCPU;
@@ -63,21 +71,26 @@ pub use x86_64::X86CPUState as ArchCPU;
 pub use x86_64::X86CPUTopology as CPUTopology;
 
 use std::cell::RefCell;
-use std::sync::atomic::{fence, AtomicBool, Ordering};
+use std::cmp::min;
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use kvm_bindings::{kvm_guest_debug, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP};
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use libc::{c_int, c_void, siginfo_t};
 use log::{error, info, warn};
 use machine_manager::config::ShutdownAction::{ShutdownActionPause, ShutdownActionPoweroff};
+#[cfg(target_arch = "x86_64")]
+use machine_manager::config::TripleFaultAction;
 use machine_manager::event;
 use machine_manager::machine::MachineInterface;
 use machine_manager::{qmp::qmp_schema as schema, qmp::QmpChannel};
 
 #[cfg(not(test))]
 use util::test_helper::is_test_enabled;
+use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::{register_signal_handler, Killable};
 
 // SIGRTMIN = 34 (GNU, in MUSL is 35) and SIGRTMAX = 64  in linux, VCPU signal
@@ -90,6 +103,10 @@ const VCPU_TASK_SIGNAL: i32 = 35;
 const VCPU_RESET_SIGNAL: i32 = 35;
 #[cfg(target_env = "musl")]
 const VCPU_RESET_SIGNAL: i32 = 36;
+/// Interval in milliseconds of kicking vcpu when waiting it to stop.
+const VCPU_KICK_INTERVAL: u64 = 32;
+/// Timeout in milliseconds of waiting vcpu to execute one instruction.
+const VCPU_STEP_TIMEOUT: u64 = 1000;
 
 /// Watch `0x3ff` IO port to record the magic value trapped from guest kernel.
 #[cfg(all(target_arch = "x86_64", feature = "boot_time"))]
@@ -155,6 +172,13 @@ pub trait CPUInterface {
     /// Make `CPU` lifecycle to `Stopping`, then `Stopped`.
     fn destroy(&self) -> Result<()>;
 
+    /// Make `CPU` lifecycle to `Stopping`, then `Stopped`, waiting at most `timeout`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Max time to wait for vcpu thread to stop.
+    fn destroy_timeout(&self, timeout: Duration) -> Result<()>;
+
     /// Reset registers value for `CPU`.
     fn reset(&self) -> Result<()>;
 
@@ -164,6 +188,9 @@ pub trait CPUInterface {
     /// Make `CPU` destroy because of guest inner reset.
     fn guest_reset(&self) -> Result<()>;
 
+    /// Pause the VM and notify the management layer because of an unrecoverable kvm exit.
+    fn guest_panicked(&self, reason: String) -> Result<()>;
+
     /// Handle vcpu event from `kvm`.
     fn kvm_vcpu_exec(&self) -> Result<bool>;
 }
@@ -187,10 +214,27 @@ pub struct CPU {
     vm: Weak<Mutex<dyn MachineInterface + Send + Sync>>,
     /// The capability of VCPU.
     caps: CPUCaps,
+    /// The features of VCPU set by user.
+    #[cfg(target_arch = "x86_64")]
+    features: CPUFeatures,
     /// The state backup of architecture CPU right before boot.
     boot_state: Arc<Mutex<ArchCPU>>,
     /// Sync the pause state of vCPU in kvm and userspace.
     pause_signal: Arc<AtomicBool>,
+    /// The vCPU is single stepping, it pauses itself on the next debug exit.
+    single_step: Arc<AtomicBool>,
+    /// Flags of `KVM_SET_GUEST_DEBUG` set by debugger, e.g. software breakpoint.
+    debug_flags: Arc<AtomicU32>,
+    /// Notify debugger when the vCPU pauses itself on debug exit.
+    debug_notifier: Arc<Mutex<Option<Arc<EventFd>>>>,
+    /// The vCPU paused on debug exit and the debugger is not aware of it.
+    debug_stopped: Arc<AtomicBool>,
+    /// Count of exits from kvm, which shows the progress of vCPU in VMM.
+    heartbeat: Arc<AtomicU64>,
+    /// The vCPU is running in kvm, including being halted by guest.
+    in_kvm: Arc<AtomicBool>,
+    /// Count of exits from kvm by reason.
+    exit_stats: Arc<VcpuExitStats>,
 }
 
 impl CPU {
@@ -202,11 +246,13 @@ impl CPU {
     /// * `id` - ID of this `CPU`.
     /// * `arch_cpu` - Architecture special `CPU` property.
     /// * `vm` - The virtual machine this `CPU` gets attached to.
+    /// * `features` - The `CPU` features set by user, applied on every reset.
     pub fn new(
         vcpu_fd: Arc<VcpuFd>,
         id: u8,
         arch_cpu: Arc<Mutex<ArchCPU>>,
         vm: Arc<Mutex<dyn MachineInterface + Send + Sync>>,
+        #[cfg(target_arch = "x86_64")] features: CPUFeatures,
     ) -> Self {
         CPU {
             id,
@@ -217,8 +263,17 @@ impl CPU {
             tid: Arc::new(Mutex::new(None)),
             vm: Arc::downgrade(&vm),
             caps: CPUCaps::init_capabilities(),
+            #[cfg(target_arch = "x86_64")]
+            features,
             boot_state: Arc::new(Mutex::new(ArchCPU::default())),
             pause_signal: Arc::new(AtomicBool::new(false)),
+            single_step: Arc::new(AtomicBool::new(false)),
+            debug_flags: Arc::new(AtomicU32::new(0)),
+            debug_notifier: Arc::new(Mutex::new(None)),
+            debug_stopped: Arc::new(AtomicBool::new(false)),
+            heartbeat: Arc::new(AtomicU64::new(0)),
+            in_kvm: Arc::new(AtomicBool::new(false)),
+            exit_stats: Arc::new(VcpuExitStats::default()),
         }
     }
 
@@ -253,11 +308,151 @@ impl CPU {
         *data = task;
     }
 
+    /// Whether this `CPU`'s thread has exited kvm and sleeps for pause.
+    pub fn is_paused(&self) -> bool {
+        self.pause_signal.load(Ordering::SeqCst)
+    }
+
+    /// Enable or disable single step of this `CPU` in kvm. When enabled, kvm
+    /// exits with `KVM_EXIT_DEBUG` after each guest instruction.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - Whether to enable single step.
+    pub fn set_single_step(&self, enable: bool) -> Result<()> {
+        let mut debug = kvm_guest_debug {
+            control: self.debug_flags.load(Ordering::SeqCst),
+            ..Default::default()
+        };
+        if enable {
+            debug.control |= KVM_GUESTDBG_SINGLESTEP;
+        }
+        if debug.control != 0 {
+            debug.control |= KVM_GUESTDBG_ENABLE;
+        }
+        self.fd.set_guest_debug(&debug).with_context(|| {
+            anyhow!(CpuError::StepVcpu(format!(
+                "Failed to set guest debug for vcpu{}",
+                self.id
+            )))
+        })
+    }
+
+    /// Set flags of `KVM_SET_GUEST_DEBUG` for debugger, the vCPU pauses
+    /// itself and notifies debugger on debug exit. `0` disables guest debug.
+    ///
+    /// # Arguments
+    ///
+    /// * `flags` - `KVM_GUESTDBG_*` flags, e.g. `KVM_GUESTDBG_USE_SW_BP`.
+    pub fn set_guest_debug(&self, flags: u32) -> Result<()> {
+        self.debug_flags.store(flags, Ordering::SeqCst);
+        self.set_single_step(false)
+    }
+
+    /// Set the eventfd to notify debugger when the vCPU pauses itself on
+    /// debug exit.
+    pub fn set_debug_notifier(&self, notifier: Option<Arc<EventFd>>) {
+        *self.debug_notifier.lock().unwrap() = notifier;
+    }
+
+    /// Check whether the vCPU paused on debug exit since last check.
+    pub fn take_debug_stop(&self) -> bool {
+        self.debug_stopped.swap(false, Ordering::SeqCst)
+    }
+
+    /// Pause the vCPU itself on vcpu exit, e.g. debug exit. It's called in vCPU thread.
+    fn pause_on_exit(&self) {
+        let (cpu_state, cvar) = &*self.state;
+        *cpu_state.lock().unwrap() = CpuLifecycleState::Paused;
+        self.pause_signal.store(true, Ordering::SeqCst);
+        cvar.notify_all();
+    }
+
+    /// Translate guest virtual address to guest physical address with the
+    /// current page table of this `CPU`.
+    ///
+    /// # Notes
+    ///
+    /// Kvm doesn't support translation on aarch64, so the address is taken
+    /// as guest physical address.
+    pub fn translate_gva(&self, gva: u64) -> Result<u64> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            let translation = self.fd.translate_gva(gva)?;
+            if translation.valid == 0 {
+                return Err(anyhow!("Guest virtual address 0x{:x} is not mapped", gva));
+            }
+            Ok(translation.physical_address)
+        }
+        #[cfg(target_arch = "aarch64")]
+        Ok(gva)
+    }
+
+    /// Get program counter of this `CPU` from kvm.
+    pub fn pc(&self) -> Result<u64> {
+        #[cfg(target_arch = "x86_64")]
+        let pc = self.fd.get_regs()?.rip;
+        #[cfg(target_arch = "aarch64")]
+        let pc = aarch64::get_pc(&self.fd)?;
+        Ok(pc)
+    }
+
+    /// Execute one guest instruction on this paused `CPU`, the `CPU` keeps
+    /// paused afterwards. Returns the new program counter.
+    pub fn step(&self) -> Result<u64> {
+        let (cpu_state_locked, cvar) = &*self.state;
+        let mut cpu_state = cpu_state_locked.lock().unwrap();
+        if *cpu_state != CpuLifecycleState::Paused {
+            return Err(anyhow!(CpuError::StepVcpu(format!(
+                "vcpu{} is not paused",
+                self.id
+            ))));
+        }
+
+        self.set_single_step(true)?;
+        self.single_step.store(true, Ordering::SeqCst);
+        *cpu_state = CpuLifecycleState::Running;
+        self.pause_signal.store(false, Ordering::SeqCst);
+        cvar.notify_all();
+
+        // The vcpu thread pauses itself on the debug exit of single step.
+        let (cpu_state, wait) = cvar
+            .wait_timeout_while(
+                cpu_state,
+                Duration::from_millis(VCPU_STEP_TIMEOUT),
+                |state| *state == CpuLifecycleState::Running,
+            )
+            .unwrap();
+        let stepped = *cpu_state == CpuLifecycleState::Paused;
+        drop(cpu_state);
+
+        if !stepped {
+            self.single_step.store(false, Ordering::SeqCst);
+            if wait.timed_out() {
+                // The vcpu may be halted in kvm, pause it as before stepping.
+                self.pause()?;
+            }
+            self.set_single_step(false)?;
+            return Err(anyhow!(CpuError::StepVcpu(format!(
+                "vcpu{} doesn't finish the instruction",
+                self.id
+            ))));
+        }
+        self.set_single_step(false)?;
+
+        self.pc()
+    }
+
     /// Get this `CPU`'s thread id.
     pub fn tid(&self) -> u64 {
         (*self.tid.lock().unwrap()).unwrap_or(0)
     }
 
+    /// Get the count of exits from kvm by reason of this `CPU`.
+    pub fn exit_stats(&self) -> &Arc<VcpuExitStats> {
+        &self.exit_stats
+    }
+
     /// Set thread id for `CPU`.
     fn set_tid(&self) {
         *self.tid.lock().unwrap() = Some(util::unix::gettid());
@@ -279,6 +474,13 @@ impl CPUInterface for CPU {
                 self.id()
             ))));
         }
+        #[cfg(target_arch = "aarch64")]
+        if config.pmu && !self.caps.pmu_v3 {
+            return Err(anyhow!(CpuError::RealizeVcpu(format!(
+                "PMU is enabled for VCPU{}, but it is not supported by host",
+                self.id()
+            ))));
+        }
 
         self.arch_cpu
             .lock()
@@ -297,6 +499,13 @@ impl CPUInterface for CPU {
             .set_cpu_topology(topology)
             .with_context(|| "Failed to realize arch cpu")?;
 
+        #[cfg(target_arch = "x86_64")]
+        self.arch_cpu
+            .lock()
+            .unwrap()
+            .set_tsc_khz(&self.fd, &self.caps)
+            .with_context(|| "Failed to realize arch cpu")?;
+
         self.boot_state.lock().unwrap().set(&self.arch_cpu);
         Ok(())
     }
@@ -334,6 +543,7 @@ impl CPUInterface for CPU {
         let handle = thread::Builder::new()
             .name(format!("CPU {}/KVM", local_cpu.id))
             .spawn(move || {
+                let _log_ctx = util::logger::log_context("vcpu", cpu_thread_worker.thread_cpu.id);
                 if let Err(e) = cpu_thread_worker.handle(thread_barrier) {
                     error!(
                         "{}",
@@ -407,6 +617,10 @@ impl CPUInterface for CPU {
     }
 
     fn destroy(&self) -> Result<()> {
+        self.destroy_timeout(Duration::from_millis(VCPU_KICK_INTERVAL))
+    }
+
+    fn destroy_timeout(&self, timeout: Duration) -> Result<()> {
         let (cpu_state, cvar) = &*self.state;
         let mut cpu_state = cpu_state.lock().unwrap();
         if *cpu_state == CpuLifecycleState::Running {
@@ -418,11 +632,17 @@ impl CPUInterface for CPU {
             return Ok(());
         }
 
-        self.kick()?;
-        cpu_state = cvar
-            .wait_timeout(cpu_state, Duration::from_millis(32))
-            .unwrap()
-            .0;
+        // Kick vcpu repeatedly, the signal may arrive before vcpu enters kvm.
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.kick()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let interval = min(remaining, Duration::from_millis(VCPU_KICK_INTERVAL));
+            cpu_state = cvar.wait_timeout(cpu_state, interval).unwrap().0;
+            if *cpu_state == CpuLifecycleState::Stopped || Instant::now() >= deadline {
+                break;
+            }
+        }
 
         if *cpu_state == CpuLifecycleState::Stopped {
             *cpu_state = CpuLifecycleState::Nothing;
@@ -473,6 +693,27 @@ impl CPUInterface for CPU {
         Ok(())
     }
 
+    fn guest_panicked(&self, reason: String) -> Result<()> {
+        error!("Vcpu{} hits unrecoverable exit: {}", self.id(), reason);
+        if let Some(vm) = self.vm.upgrade() {
+            // Keep the vcpu alive in paused state, so that its registers can be inspected.
+            vm.lock().unwrap().pause();
+        } else {
+            return Err(anyhow!(CpuError::NoMachineInterface));
+        }
+
+        if QmpChannel::is_connected() {
+            let panicked_msg = schema::GuestPanicked {
+                action: "pause".to_string(),
+                cpu_index: Some(self.id()),
+                reason,
+            };
+            event!(GuestPanicked; panicked_msg);
+        }
+
+        Ok(())
+    }
+
     fn kvm_vcpu_exec(&self) -> Result<bool> {
         let vm = if let Some(vm) = self.vm.upgrade() {
             vm
@@ -480,7 +721,13 @@ impl CPUInterface for CPU {
             return Err(anyhow!(CpuError::NoMachineInterface));
         };
 
-        match self.fd.run() {
+        self.in_kvm.store(true, Ordering::SeqCst);
+        let ret = self.fd.run();
+        self.in_kvm.store(false, Ordering::SeqCst);
+        self.heartbeat.fetch_add(1, Ordering::SeqCst);
+        self.exit_stats.account(VcpuExitReason::from_exit(&ret));
+
+        match ret {
             Ok(run) => match run {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoIn(addr, data) => {
@@ -510,9 +757,27 @@ impl CPUInterface for CPU {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::Shutdown => {
                     info!("Vcpu{} received an KVM_EXIT_SHUTDOWN signal", self.id());
-                    self.guest_shutdown()?;
-
-                    return Ok(false);
+                    let action = vm.lock().unwrap().get_triple_fault_action();
+                    match action {
+                        TripleFaultAction::Poweroff => {
+                            self.guest_shutdown()?;
+
+                            // Keep the vcpu thread alive if the VM is paused instead of shut down.
+                            let action = vm.lock().unwrap().get_shutdown_action();
+                            return Ok(action == ShutdownActionPause);
+                        }
+                        TripleFaultAction::Reboot => {
+                            // Don't run the faulted vcpu again, it's resumed after the reset
+                            // request is handled by main loop.
+                            self.pause_on_exit();
+                            self.guest_reset()
+                                .with_context(|| "Some error occurred in guest reset")?;
+                        }
+                        TripleFaultAction::Pause => {
+                            self.guest_panicked("triple-fault".to_string())
+                                .with_context(|| "Some error occurred in guest panicked")?;
+                        }
+                    }
                 }
                 #[cfg(target_arch = "aarch64")]
                 VcpuExit::SystemEvent(event, flags) => {
@@ -542,22 +807,42 @@ impl CPUInterface for CPU {
                     }
                     return Ok(false);
                 }
+                VcpuExit::Debug(_) => {
+                    if self.single_step.swap(false, Ordering::SeqCst) {
+                        self.pause_on_exit();
+                    } else if self.debug_flags.load(Ordering::SeqCst) != 0 {
+                        self.pause_on_exit();
+                        self.debug_stopped.store(true, Ordering::SeqCst);
+                        if let Some(notifier) = self.debug_notifier.lock().unwrap().as_ref() {
+                            notifier
+                                .write(1)
+                                .with_context(|| "Failed to notify debugger")?;
+                        }
+                    } else {
+                        warn!(
+                            "Vcpu{} received unexpected KVM_EXIT_DEBUG signal",
+                            self.id()
+                        );
+                    }
+                }
                 VcpuExit::FailEntry(reason, cpuid) => {
                     info!(
                         "Vcpu{} received KVM_EXIT_FAIL_ENTRY signal. the vcpu could not be run due to unknown reasons({})",
                         cpuid, reason
                     );
-                    return Ok(false);
+                    self.guest_panicked(format!("fail-entry(0x{:x})", reason))
+                        .with_context(|| "Some error occurred in guest panicked")?;
                 }
                 VcpuExit::InternalError => {
                     info!("Vcpu{} received KVM_EXIT_INTERNAL_ERROR signal", self.id());
-                    return Ok(false);
+                    self.guest_panicked("internal-error".to_string())
+                        .with_context(|| "Some error occurred in guest panicked")?;
                 }
                 r => {
-                    return Err(anyhow!(CpuError::VcpuExitReason(
-                        self.id(),
-                        format!("{:?}", r)
-                    )));
+                    let reason = format!("{:?}", r);
+                    error!("{}", CpuError::VcpuExitReason(self.id(), reason.clone()));
+                    self.guest_panicked(reason)
+                        .with_context(|| "Some error occurred in guest panicked")?;
                 }
             },
             Err(ref e) => {
@@ -631,6 +916,8 @@ impl CPUThreadWorker {
                             &vcpu.fd,
                             #[cfg(target_arch = "x86_64")]
                             &vcpu.caps,
+                            #[cfg(target_arch = "x86_64")]
+                            &vcpu.features,
                         ) {
                             error!("Failed to reset vcpu state: {}", e.to_string())
                         }
@@ -701,6 +988,8 @@ impl CPUThreadWorker {
                 &self.thread_cpu.fd,
                 #[cfg(target_arch = "x86_64")]
                 &self.thread_cpu.caps,
+                #[cfg(target_arch = "x86_64")]
+                &self.thread_cpu.features,
             )
             .with_context(|| "Failed to reset for cpu register state")?;
 
@@ -954,6 +1243,8 @@ mod tests {
             0,
             Arc::new(Mutex::new(ArchCPU::default())),
             vm.clone(),
+            #[cfg(target_arch = "x86_64")]
+            CPUFeatures::default(),
         );
         let (cpu_state, _) = &*cpu.state;
         assert_eq!(*cpu_state.lock().unwrap(), CpuLifecycleState::Created);
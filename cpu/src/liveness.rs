@@ -0,0 +1,183 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{error, info};
+use machine_manager::event;
+use machine_manager::qmp::{qmp_schema as schema, QmpChannel};
+
+use crate::{CpuLifecycleState, CPU};
+
+/// Interval of checking the progress of vcpus.
+const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Progress of a vcpu observed by liveness monitor.
+struct VcpuProgress {
+    /// Heartbeat of vcpu in last check.
+    heartbeat: u64,
+    /// The time since when vcpu doesn't make progress.
+    since: Instant,
+    /// The stall has been reported.
+    reported: bool,
+}
+
+impl VcpuProgress {
+    fn new(now: Instant) -> Self {
+        VcpuProgress {
+            heartbeat: 0,
+            since: now,
+            reported: false,
+        }
+    }
+
+    /// Update the progress of vcpu. Return the stalled time when it exceeds `window`
+    /// for the first time.
+    ///
+    /// # Arguments
+    ///
+    /// * `busy` - The vcpu is running and is handling an exit from kvm in VMM.
+    /// * `heartbeat` - The heartbeat of vcpu.
+    /// * `now` - Time of this check.
+    /// * `window` - Max time in which vcpu must make progress.
+    fn update(
+        &mut self,
+        busy: bool,
+        heartbeat: u64,
+        now: Instant,
+        window: Duration,
+    ) -> Option<Duration> {
+        // A vcpu in kvm is running guest code or halted by guest, a paused vcpu is
+        // waiting for resume, neither of them is stalled in VMM.
+        if !busy || heartbeat != self.heartbeat {
+            self.heartbeat = heartbeat;
+            self.since = now;
+            self.reported = false;
+            return None;
+        }
+
+        let stalled = now.saturating_duration_since(self.since);
+        if stalled < window || self.reported {
+            return None;
+        }
+        self.reported = true;
+        Some(stalled)
+    }
+}
+
+/// Start a thread to monitor the liveness of vcpus. A running vcpu which stays out
+/// of kvm without handling any new exit within `window` is considered stalled in VMM,
+/// and is reported by QMP event `VCPU_STALLED` once until it makes progress again.
+///
+/// # Arguments
+///
+/// * `cpus` - Cpus vector restore cpu structure.
+/// * `window` - Max time in which vcpu must make progress.
+pub fn start_liveness_monitor(cpus: Vec<Arc<CPU>>, window: Duration) -> Result<()> {
+    thread::Builder::new()
+        .name("vcpu liveness".to_string())
+        .spawn(move || {
+            let mut progress: Vec<VcpuProgress> = cpus
+                .iter()
+                .map(|_| VcpuProgress::new(Instant::now()))
+                .collect();
+            loop {
+                thread::sleep(LIVENESS_CHECK_INTERVAL);
+                let mut alive = false;
+                for (cpu, progress) in cpus.iter().zip(progress.iter_mut()) {
+                    let state = *cpu.state.0.lock().unwrap();
+                    if state == CpuLifecycleState::Stopping || state == CpuLifecycleState::Stopped {
+                        continue;
+                    }
+                    alive = true;
+
+                    let busy =
+                        state == CpuLifecycleState::Running && !cpu.in_kvm.load(Ordering::SeqCst);
+                    let heartbeat = cpu.heartbeat.load(Ordering::SeqCst);
+                    if let Some(stalled) = progress.update(busy, heartbeat, Instant::now(), window)
+                    {
+                        report_stalled_vcpu(cpu.id(), stalled);
+                    }
+                }
+                if !alive {
+                    info!("All vcpus are stopped, vcpu liveness monitor exits");
+                    break;
+                }
+            }
+        })
+        .with_context(|| "Failed to create thread for vcpu liveness monitor")?;
+    Ok(())
+}
+
+fn report_stalled_vcpu(cpu_index: u8, stalled: Duration) {
+    error!(
+        "Vcpu{} doesn't make progress in VMM for {} seconds",
+        cpu_index,
+        stalled.as_secs()
+    );
+    if QmpChannel::is_connected() {
+        let stalled_msg = schema::VcpuStalled {
+            cpu_index,
+            seconds: stalled.as_secs(),
+        };
+        event!(VcpuStalled; stalled_msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vcpu_progress() {
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut progress = VcpuProgress::new(start);
+
+        // Vcpu running in kvm or paused is never stalled.
+        assert!(progress
+            .update(false, 0, start + Duration::from_secs(20), window)
+            .is_none());
+
+        // Vcpu handles new exits in VMM.
+        let start = start + Duration::from_secs(20);
+        assert!(progress.update(true, 1, start, window).is_none());
+        assert!(progress
+            .update(true, 2, start + Duration::from_secs(9), window)
+            .is_none());
+
+        // Vcpu is stuck in VMM, and it is reported once.
+        let start = start + Duration::from_secs(9);
+        assert!(progress
+            .update(true, 2, start + Duration::from_secs(9), window)
+            .is_none());
+        assert_eq!(
+            progress.update(true, 2, start + Duration::from_secs(10), window),
+            Some(Duration::from_secs(10))
+        );
+        assert!(progress
+            .update(true, 2, start + Duration::from_secs(30), window)
+            .is_none());
+
+        // Vcpu makes progress again, and a new stall is reported.
+        let start = start + Duration::from_secs(31);
+        assert!(progress.update(true, 3, start, window).is_none());
+        assert_eq!(
+            progress.update(true, 3, start + Duration::from_secs(11), window),
+            Some(Duration::from_secs(11))
+        );
+    }
+}
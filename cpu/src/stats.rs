@@ -0,0 +1,187 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use kvm_ioctls::VcpuExit;
+
+/// Reason of vcpu exit from kvm, which is accounted in `VcpuExitStats`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcpuExitReason {
+    IoIn,
+    IoOut,
+    MmioRead,
+    MmioWrite,
+    Hlt,
+    Shutdown,
+    SystemEvent,
+    Debug,
+    FailEntry,
+    InternalError,
+    /// `KVM_RUN` is interrupted by signal or returns `EAGAIN`.
+    Interrupted,
+    /// Other exits and errors of `KVM_RUN`.
+    Other,
+}
+
+/// All the reasons of vcpu exit, the index is the same as the discriminant.
+pub const VCPU_EXIT_REASONS: [VcpuExitReason; 12] = [
+    VcpuExitReason::IoIn,
+    VcpuExitReason::IoOut,
+    VcpuExitReason::MmioRead,
+    VcpuExitReason::MmioWrite,
+    VcpuExitReason::Hlt,
+    VcpuExitReason::Shutdown,
+    VcpuExitReason::SystemEvent,
+    VcpuExitReason::Debug,
+    VcpuExitReason::FailEntry,
+    VcpuExitReason::InternalError,
+    VcpuExitReason::Interrupted,
+    VcpuExitReason::Other,
+];
+
+impl VcpuExitReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VcpuExitReason::IoIn => "io_in",
+            VcpuExitReason::IoOut => "io_out",
+            VcpuExitReason::MmioRead => "mmio_read",
+            VcpuExitReason::MmioWrite => "mmio_write",
+            VcpuExitReason::Hlt => "hlt",
+            VcpuExitReason::Shutdown => "shutdown",
+            VcpuExitReason::SystemEvent => "system_event",
+            VcpuExitReason::Debug => "debug",
+            VcpuExitReason::FailEntry => "fail_entry",
+            VcpuExitReason::InternalError => "internal_error",
+            VcpuExitReason::Interrupted => "interrupted",
+            VcpuExitReason::Other => "other",
+        }
+    }
+
+    /// Get the reason of the result of `KVM_RUN`.
+    pub(crate) fn from_exit(ret: &std::result::Result<VcpuExit, kvm_ioctls::Error>) -> Self {
+        match ret {
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuExit::IoIn(..)) => VcpuExitReason::IoIn,
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuExit::IoOut(..)) => VcpuExitReason::IoOut,
+            Ok(VcpuExit::MmioRead(..)) => VcpuExitReason::MmioRead,
+            Ok(VcpuExit::MmioWrite(..)) => VcpuExitReason::MmioWrite,
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuExit::Hlt) => VcpuExitReason::Hlt,
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuExit::Shutdown) => VcpuExitReason::Shutdown,
+            #[cfg(target_arch = "aarch64")]
+            Ok(VcpuExit::SystemEvent(..)) => VcpuExitReason::SystemEvent,
+            Ok(VcpuExit::Debug(_)) => VcpuExitReason::Debug,
+            Ok(VcpuExit::FailEntry(..)) => VcpuExitReason::FailEntry,
+            Ok(VcpuExit::InternalError) => VcpuExitReason::InternalError,
+            Err(e) if e.errno() == libc::EAGAIN || e.errno() == libc::EINTR => {
+                VcpuExitReason::Interrupted
+            }
+            _ => VcpuExitReason::Other,
+        }
+    }
+}
+
+/// Count of vcpu exits from kvm by reason.
+#[derive(Default)]
+pub struct VcpuExitStats {
+    counts: [AtomicU64; VCPU_EXIT_REASONS.len()],
+}
+
+impl VcpuExitStats {
+    pub fn account(&self, reason: VcpuExitReason) {
+        self.counts[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the count of exits for all the reasons.
+    pub fn get_info(&self) -> Vec<(VcpuExitReason, u64)> {
+        VCPU_EXIT_REASONS
+            .iter()
+            .map(|reason| {
+                (
+                    *reason,
+                    self.counts[*reason as usize].load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Get the cpu time consumed by the thread, including user and system time.
+///
+/// # Arguments
+///
+/// * `tid` - Thread id in this process.
+pub fn thread_cpu_time(tid: u64) -> Result<Duration> {
+    let path = format!("/proc/self/task/{}/stat", tid);
+    let stat =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+    let ticks = match parse_cpu_ticks(&stat) {
+        Some(ticks) => ticks,
+        None => bail!("Invalid thread stat {}", stat),
+    };
+    // SAFETY: sysconf has no side effect.
+    let clk_tck = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if clk_tck <= 0 {
+        bail!("Failed to get clock ticks per second");
+    }
+    Ok(Duration::from_nanos(ticks * 1_000_000_000 / clk_tck as u64))
+}
+
+/// Parse the sum of `utime` and `stime` in clock ticks from `/proc/<pid>/stat`.
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    // The thread name in parentheses may contain spaces, fields are counted from
+    // the last ')', which is followed by the 3rd field `state`.
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let utime = fields.get(11)?.parse::<u64>().ok()?;
+    let stime = fields.get(12)?.parse::<u64>().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vcpu_exit_stats() {
+        for (index, reason) in VCPU_EXIT_REASONS.iter().enumerate() {
+            assert_eq!(*reason as usize, index);
+        }
+
+        let stats = VcpuExitStats::default();
+        stats.account(VcpuExitReason::MmioWrite);
+        stats.account(VcpuExitReason::MmioWrite);
+        stats.account(VcpuExitReason::Interrupted);
+        let info = stats.get_info();
+        assert_eq!(info.len(), VCPU_EXIT_REASONS.len());
+        assert_eq!(info[VcpuExitReason::MmioWrite as usize].1, 2);
+        assert_eq!(info[VcpuExitReason::Interrupted as usize].1, 1);
+        assert_eq!(info[VcpuExitReason::MmioRead as usize].1, 0);
+    }
+
+    #[test]
+    fn test_thread_cpu_time() {
+        let stat = "1234 (CPU 0/KVM) S 1 1234 1234 0 -1 4194560 300 0 0 0 \
+                    150 25 0 0 20 0 1 0 100 0 0 18446744073709551615";
+        assert_eq!(parse_cpu_ticks(stat), Some(175));
+        assert_eq!(parse_cpu_ticks("1234 (a) S 1"), None);
+        assert_eq!(parse_cpu_ticks("1234 vcpu"), None);
+
+        let tid = util::unix::gettid();
+        assert!(thread_cpu_time(tid).is_ok());
+    }
+}
@@ -10,8 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use kvm_bindings::{kvm_msr_entry, Msrs};
+use anyhow::{bail, Result};
+use kvm_bindings::{kvm_cpuid_entry2, kvm_msr_entry, Msrs};
 use kvm_ioctls::{Cap, Kvm};
+use log::warn;
+use machine_manager::config::{CpuConfig, CpuFeatureFlag, CpuidReg};
 use vmm_sys_util::fam::Error;
 
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/arch/x86/include/asm/msr-index.h#L558
@@ -30,6 +33,8 @@ const MSR_IA32_VMX_VMFUNC: ::std::os::raw::c_uint = 0x491;
 pub struct X86CPUCaps {
     pub has_xsave: bool,
     pub has_xcrs: bool,
+    pub has_get_tsc_khz: bool,
+    pub has_tsc_control: bool,
     supported_msrs: Vec<u32>,
 }
 
@@ -41,6 +46,8 @@ impl X86CPUCaps {
         X86CPUCaps {
             has_xsave: kvm.check_extension(Cap::Xsave),
             has_xcrs: kvm.check_extension(Cap::Xcrs),
+            has_get_tsc_khz: kvm.check_extension(Cap::GetTscKhz),
+            has_tsc_control: kvm.check_extension(Cap::TscControl),
             supported_msrs: kvm.get_msr_index_list().unwrap().as_slice().to_vec(),
         }
     }
@@ -69,3 +76,127 @@ impl X86CPUCaps {
         Msrs::from_entries(&entry_vec)
     }
 }
+
+/// Cpu features of x86_64 vcpu which are set by user.
+#[derive(Clone, Debug, Default)]
+pub struct X86CPUFeatures {
+    /// CPUID feature flags to be forced on or masked off.
+    pub cpuid_flags: Vec<CpuFeatureFlag>,
+}
+
+impl From<&CpuConfig> for X86CPUFeatures {
+    fn from(conf: &CpuConfig) -> Self {
+        Self {
+            cpuid_flags: conf.features.clone(),
+        }
+    }
+}
+
+impl X86CPUFeatures {
+    /// Force on or mask off the feature flags in CPUID entries.
+    ///
+    /// The entries are based on the CPUID supported by kvm, a feature flag which is
+    /// forced on must be offered by them, otherwise the host can't provide it to VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - CPUID entries to be set to kvm.
+    pub fn apply_cpuid(&self, entries: &mut [kvm_cpuid_entry2]) -> Result<()> {
+        for flag in self.cpuid_flags.iter() {
+            let entry = match entries.iter_mut().find(|entry| {
+                entry.function == flag.cpuid.function && entry.index == flag.cpuid.index
+            }) {
+                Some(entry) => entry,
+                None if flag.enabled => {
+                    bail!(
+                        "Cpu feature {} is not supported by kvm: CPUID leaf 0x{:x} is missing",
+                        flag.name,
+                        flag.cpuid.function
+                    );
+                }
+                None => {
+                    warn!(
+                        "CPUID leaf 0x{:x} for cpu feature {} is not supported",
+                        flag.cpuid.function, flag.name
+                    );
+                    continue;
+                }
+            };
+
+            let reg = match flag.cpuid.reg {
+                CpuidReg::Eax => &mut entry.eax,
+                CpuidReg::Ebx => &mut entry.ebx,
+                CpuidReg::Ecx => &mut entry.ecx,
+                CpuidReg::Edx => &mut entry.edx,
+            };
+            if flag.enabled {
+                if *reg & (1u32 << flag.cpuid.bit) == 0 {
+                    bail!(
+                        "Cpu feature {} is not supported by kvm on this host",
+                        flag.name
+                    );
+                }
+            } else {
+                *reg &= !(1u32 << flag.cpuid.bit);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use machine_manager::config::x86_cpuid_feature;
+
+    use super::*;
+
+    #[test]
+    fn test_apply_cpuid() {
+        let mut entries = vec![
+            kvm_cpuid_entry2 {
+                function: 1,
+                index: 0,
+                ecx: 0,
+                edx: u32::MAX,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 7,
+                index: 0,
+                ebx: u32::MAX,
+                ..Default::default()
+            },
+        ];
+        let flag = |name: &str, enabled: bool| CpuFeatureFlag {
+            name: name.to_string(),
+            cpuid: x86_cpuid_feature(name).unwrap(),
+            enabled,
+        };
+        let features = X86CPUFeatures {
+            cpuid_flags: vec![
+                flag("sse", true),
+                flag("sse2", false),
+                flag("avx512f", false),
+                // CPUID[0xd, 1] is missing, which will be skipped.
+                flag("xsaves", false),
+            ],
+        };
+        assert!(features.apply_cpuid(&mut entries).is_ok());
+
+        assert_eq!(entries[0].ecx, 0);
+        assert_eq!(entries[0].edx, !(1 << 26));
+        assert_eq!(entries[1].ebx, !(1 << 16));
+        assert_eq!(entries.len(), 2);
+
+        // Feature flags not supported by kvm can't be forced on.
+        let features = X86CPUFeatures {
+            cpuid_flags: vec![flag("ssse3", true)],
+        };
+        assert!(features.apply_cpuid(&mut entries).is_err());
+        assert_eq!(entries[0].ecx, 0);
+        let features = X86CPUFeatures {
+            cpuid_flags: vec![flag("xsaves", true)],
+        };
+        assert!(features.apply_cpuid(&mut entries).is_err());
+    }
+}
@@ -23,6 +23,7 @@ use kvm_bindings::{
     KVM_MP_STATE_UNINITIALIZED,
 };
 use kvm_ioctls::{Kvm, VcpuFd};
+use log::warn;
 use migration::{
     DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
 };
@@ -35,6 +36,7 @@ use crate::CPU;
 const ECX_EPB_SHIFT: u32 = 3;
 const X86_FEATURE_HYPERVISOR: u32 = 31;
 const X86_FEATURE_TSC_DEADLINE_TIMER: u32 = 24;
+const X86_FEATURE_HTT: u32 = 28;
 
 const MSR_LIST: &[u32] = &[
     0x0174,      // MSR_IA32_SYSENTER_CS
@@ -125,6 +127,8 @@ pub struct X86CPUState {
     xsave: kvm_xsave,
     xcrs: kvm_xcrs,
     debugregs: kvm_debugregs,
+    /// TSC frequency in kHz, 0 means it is unknown.
+    tsc_khz: u32,
 }
 
 impl X86CPUState {
@@ -169,6 +173,7 @@ impl X86CPUState {
         self.xsave = locked_cpu_state.xsave;
         self.xcrs = locked_cpu_state.xcrs;
         self.debugregs = locked_cpu_state.debugregs;
+        self.tsc_khz = locked_cpu_state.tsc_khz;
     }
 
     /// Set register value in `X86CPUState` according to `boot_config`.
@@ -203,14 +208,37 @@ impl X86CPUState {
         Ok(())
     }
 
+    /// Record the TSC frequency of vcpu, which is restored on the destination of migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    /// * `caps` - Vcpu capabilities in kvm.
+    pub fn set_tsc_khz(&mut self, vcpu_fd: &Arc<VcpuFd>, caps: &caps::X86CPUCaps) -> Result<()> {
+        if caps.has_get_tsc_khz {
+            self.tsc_khz = vcpu_fd
+                .get_tsc_khz()
+                .with_context(|| format!("Failed to get tsc khz for CPU {}/KVM", self.apic_id))?;
+        }
+        Ok(())
+    }
+
     /// Reset register value with `X86CPUState`.
     ///
     /// # Arguments
     ///
     /// * `vcpu_fd` - Vcpu file descriptor in kvm.
     /// * `caps` - Vcpu capabilities in kvm.
-    pub fn reset_vcpu(&self, vcpu_fd: &Arc<VcpuFd>, caps: &caps::X86CPUCaps) -> Result<()> {
-        self.setup_cpuid(vcpu_fd)
+    /// * `features` - Vcpu features set by user.
+    pub fn reset_vcpu(
+        &self,
+        vcpu_fd: &Arc<VcpuFd>,
+        caps: &caps::X86CPUCaps,
+        features: &caps::X86CPUFeatures,
+    ) -> Result<()> {
+        self.setup_tsc_khz(vcpu_fd, caps)
+            .with_context(|| format!("Failed to set tsc khz for CPU {}", self.apic_id))?;
+        self.setup_cpuid(vcpu_fd, features)
             .with_context(|| format!("Failed to set cpuid for CPU {}", self.apic_id))?;
 
         vcpu_fd
@@ -252,6 +280,34 @@ impl X86CPUState {
         Ok(())
     }
 
+    fn setup_tsc_khz(&self, vcpu_fd: &Arc<VcpuFd>, caps: &caps::X86CPUCaps) -> Result<()> {
+        // The TSC frequency is unknown in the state from an older version.
+        if self.tsc_khz == 0 || !caps.has_get_tsc_khz {
+            return Ok(());
+        }
+        let host_tsc_khz = vcpu_fd.get_tsc_khz()?;
+        if host_tsc_khz == self.tsc_khz {
+            return Ok(());
+        }
+
+        if !caps.has_tsc_control {
+            warn!(
+                "TSC frequency of CPU {} changes from {} kHz to {} kHz without TSC scaling \
+                supported by host, guest clock may skew",
+                self.apic_id, self.tsc_khz, host_tsc_khz
+            );
+            return Ok(());
+        }
+        if let Err(e) = vcpu_fd.set_tsc_khz(self.tsc_khz) {
+            warn!(
+                "Failed to scale TSC frequency of CPU {} from {} kHz to {} kHz, \
+                guest clock may skew: {:?}",
+                self.apic_id, host_tsc_khz, self.tsc_khz, e
+            );
+        }
+        Ok(())
+    }
+
     fn setup_lapic(&mut self, vcpu_fd: &Arc<VcpuFd>) -> Result<()> {
         // Disable nmi and external interrupt before enter protected mode
         // See: https://elixir.bootlin.com/linux/v4.19.123/source/arch/x86/include/asm/apicdef.h
@@ -420,10 +476,12 @@ impl X86CPUState {
         Ok(())
     }
 
-    fn setup_cpuid(&self, vcpu_fd: &Arc<VcpuFd>) -> Result<()> {
+    fn setup_cpuid(&self, vcpu_fd: &Arc<VcpuFd>, features: &caps::X86CPUFeatures) -> Result<()> {
         let core_offset = 32u32 - (self.nr_threads - 1).leading_zeros();
         let die_offset = (32u32 - (self.nr_cores - 1).leading_zeros()) + core_offset;
         let pkg_offset = (32u32 - (self.nr_dies - 1).leading_zeros()) + die_offset;
+        let cores_per_pkg = self.nr_dies * self.nr_cores;
+        let threads_per_pkg = cores_per_pkg * self.nr_threads;
         let sys_fd = match Kvm::new() {
             Ok(fd) => fd,
             _ => bail!("setup_cpuid: Open /dev/kvm failed"),
@@ -443,6 +501,13 @@ impl X86CPUState {
                         entry.ecx |= 1u32 << X86_FEATURE_HYPERVISOR;
                         entry.ecx |= 1u32 << X86_FEATURE_TSC_DEADLINE_TIMER;
                         entry.ebx = self.apic_id << 24 | 8 << 8;
+                        // Logical processors per package, valid when HTT is set.
+                        if threads_per_pkg > 1 {
+                            entry.ebx |= (threads_per_pkg & 0xff) << 16;
+                            entry.edx |= 1u32 << X86_FEATURE_HTT;
+                        } else {
+                            entry.edx &= !(1u32 << X86_FEATURE_HTT);
+                        }
                     }
                 }
                 2 => {
@@ -467,8 +532,8 @@ impl X86CPUState {
                         &mut entry.edx,
                     );
                     entry.eax &= !0xfc00_0000;
-                    if entry.eax & 0x0001_ffff != 0 && self.nr_vcpus > 1 {
-                        entry.eax |= (self.nr_vcpus - 1) << 26;
+                    if entry.eax & 0x0001_ffff != 0 && cores_per_pkg > 1 {
+                        entry.eax |= (cores_per_pkg - 1) << 26;
                     }
                 }
                 6 => {
@@ -495,7 +560,7 @@ impl X86CPUState {
                         }
                         1 => {
                             entry.eax = pkg_offset;
-                            entry.ebx = self.nr_threads * self.nr_cores;
+                            entry.ebx = threads_per_pkg;
                             entry.ecx |= ECX_CORE;
                         }
                         _ => {
@@ -531,7 +596,7 @@ impl X86CPUState {
                         }
                         2 => {
                             entry.eax = pkg_offset;
-                            entry.ebx = self.nr_dies * self.nr_cores * self.nr_threads;
+                            entry.ebx = threads_per_pkg;
                             entry.ecx |= ECX_DIE;
                         }
                         _ => {
@@ -556,6 +621,8 @@ impl X86CPUState {
             }
         }
 
+        features.apply_cpuid(entries)?;
+
         vcpu_fd
             .set_cpuid2(&cpuid)
             .with_context(|| format!("Failed to set cpuid for CPU {}/KVM", self.apic_id))?;
@@ -685,7 +752,8 @@ mod test {
 
         // test setup special registers
         let cpu_caps = caps::X86CPUCaps::init_capabilities();
-        assert!(x86_cpu.reset_vcpu(&vcpu, &cpu_caps).is_ok());
+        let cpu_features = caps::X86CPUFeatures::default();
+        assert!(x86_cpu.reset_vcpu(&vcpu, &cpu_caps, &cpu_features).is_ok());
         let x86_sregs = vcpu.get_sregs().unwrap();
         assert_eq!(x86_sregs.cs, code_seg);
         assert_eq!(x86_sregs.ds, data_seg);
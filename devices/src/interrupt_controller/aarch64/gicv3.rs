@@ -21,7 +21,7 @@ use anyhow::{anyhow, Context, Result};
 
 use hypervisor::kvm::KVM_FDS;
 use kvm_ioctls::DeviceFd;
-use log::error;
+use log::{error, warn};
 use machine_manager::machine::{KvmVmState, MachineLifecycle};
 use migration::{
     snapshot::{GICV3_ITS_SNAPSHOT_ID, GICV3_SNAPSHOT_ID},
@@ -35,7 +35,8 @@ const KVM_VGIC_V3_REDIST_SIZE: u64 = 2 * SZ_64K;
 
 /// Configure a v3 Interrupt controller.
 pub struct GICv3Config {
-    /// Config msi support
+    /// Config msi support, ITS is created only if it's set. If the host GIC doesn't
+    /// support ITS, the guest falls back to SPIs.
     pub msi: bool,
     /// GIC distributor address range.
     pub dist_range: (u64, u64),
@@ -162,10 +163,14 @@ impl GICv3 {
             state: Arc::new(Mutex::new(KvmVmState::Created)),
         };
 
-        if let Some(its_range) = v3config.its_range {
-            gicv3.its_dev = Some(Arc::new(
-                GICv3Its::new(&its_range).with_context(|| "Failed to create ITS")?,
-            ));
+        if let (true, Some(its_range)) = (v3config.msi, v3config.its_range) {
+            match GICv3Its::new(&its_range) {
+                Ok(its) => gicv3.its_dev = Some(Arc::new(its)),
+                Err(e) => warn!(
+                    "Failed to create ITS, MSI is unavailable and falls back to SPIs: {:?}",
+                    e
+                ),
+            }
         }
 
         Ok(gicv3)
@@ -448,6 +453,10 @@ impl GICDevice for GICv3 {
     fn get_redist_count(&self) -> u8 {
         self.redist_regions.len() as u8
     }
+
+    fn has_its(&self) -> bool {
+        self.its_dev.is_some()
+    }
 }
 
 pub struct GICv3Its {
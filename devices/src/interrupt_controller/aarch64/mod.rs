@@ -136,6 +136,11 @@ pub trait GICDevice: MachineLifecycle {
     fn get_redist_count(&self) -> u8 {
         0
     }
+
+    /// Whether GIC ITS is created, which translates MSIs into LPIs.
+    fn has_its(&self) -> bool {
+        false
+    }
 }
 
 /// A wrapper around creating and using a kvm-based interrupt controller.
@@ -179,6 +184,11 @@ impl InterruptController {
     pub fn get_redist_count(&self) -> u8 {
         self.gic.get_redist_count()
     }
+
+    /// Whether MSIs can be delivered through GIC ITS.
+    pub fn has_its(&self) -> bool {
+        self.gic.has_its()
+    }
 }
 
 impl device_tree::CompileFDT for InterruptController {
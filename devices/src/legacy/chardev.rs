@@ -10,26 +10,40 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::VecDeque;
 use std::fs::{read_link, File, OpenOptions};
-use std::io::{Stdin, Stdout};
-use std::os::unix::io::{AsRawFd, FromRawFd};
+#[cfg(not(target_env = "musl"))]
+use std::io::Read;
+use std::io::{ErrorKind, Stdin, Stdout, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
+#[cfg(not(target_env = "musl"))]
+use anyhow::anyhow;
 use anyhow::{bail, Context, Result};
 use libc::{cfmakeraw, tcgetattr, tcsetattr, termios};
 use log::{error, info};
-use machine_manager::machine::{PathInfo, PTY_PATH};
+#[cfg(not(target_env = "musl"))]
+use machine_manager::event_loop::EventLoop;
+use machine_manager::machine::{register_ringbuf_chardev, ChardevRingBuf, PathInfo, PTY_PATH};
 use machine_manager::{
-    config::{ChardevConfig, ChardevType},
+    config::{ChardevConfig, ChardevType, TlsCredObjConfig},
     temp_cleaner::TempCleaner,
 };
+#[cfg(not(target_env = "musl"))]
+use rustls::{ServerConfig, ServerConnection};
+#[cfg(not(target_env = "musl"))]
+use ui::vnc::auth_vencrypt::{make_vencrypt_config, TlsCreds};
 use util::loop_context::{
     gen_delete_notifiers, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
 use util::set_termi_raw_mode;
+#[cfg(not(target_env = "musl"))]
+use util::time::NANOSECONDS_PER_SECOND;
 use util::unix::limit_permission;
 use vmm_sys_util::epoll::EventSet;
 
@@ -42,6 +56,24 @@ pub trait InputReceiver: Send {
 
 type ReceFn = Option<Arc<dyn Fn(&[u8]) + Send + Sync>>;
 
+/// Timeout in seconds for the client of tls chardev to finish handshake.
+#[cfg(not(target_env = "musl"))]
+const TLS_HANDSHAKE_TIMEOUT: u64 = 5;
+
+/// Max size of the output held by tcp chardev while no client is connected.
+const TCP_BACKLOG_SIZE: usize = 64 * 1024;
+
+/// Telnet commands, see RFC 854.
+const TELNET_SE: u8 = 240;
+const TELNET_SB: u8 = 250;
+const TELNET_WILL: u8 = 251;
+const TELNET_DONT: u8 = 254;
+const TELNET_IAC: u8 = 255;
+/// Telnet options.
+const TELNET_OPT_ECHO: u8 = 1;
+const TELNET_OPT_SGA: u8 = 3;
+const TELNET_OPT_LINEMODE: u8 = 34;
+
 /// Character device structure.
 pub struct Chardev {
     /// Id of chardev.
@@ -50,6 +82,10 @@ pub struct Chardev {
     pub backend: ChardevType,
     /// UnixListener for socket-type chardev.
     pub listener: Option<UnixListener>,
+    /// TcpListener for tcp-type chardev.
+    tcp_listener: Option<TcpListener>,
+    /// Output of tcp-type chardev, which is kept across the connections of clients.
+    tcp_output: Option<Arc<Mutex<TcpOutput>>>,
     /// Chardev input.
     pub input: Option<Arc<Mutex<dyn CommunicatInInterface>>>,
     /// Chardev output.
@@ -58,6 +94,11 @@ pub struct Chardev {
     pub stream_fd: Option<i32>,
     /// Device is deactivated or not.
     pub deactivated: bool,
+    /// Tls credentials for socket-type chardev.
+    tls_creds: Option<TlsCredObjConfig>,
+    /// Tls configuration to encrypt the accepted connections.
+    #[cfg(not(target_env = "musl"))]
+    tls_config: Option<Arc<ServerConfig>>,
     /// Handle the input data and trigger interrupt if necessary.
     receive: ReceFn,
     /// Return the remain space size of receiver buffer.
@@ -70,16 +111,22 @@ impl Chardev {
             id: chardev_cfg.id,
             backend: chardev_cfg.backend,
             listener: None,
+            tcp_listener: None,
+            tcp_output: None,
             input: None,
             output: None,
             stream_fd: None,
             deactivated: false,
+            tls_creds: chardev_cfg.tls_creds,
+            #[cfg(not(target_env = "musl"))]
+            tls_config: None,
             receive: None,
             get_remain_space_size: None,
         }
     }
 
     pub fn realize(&mut self) -> Result<()> {
+        self.init_tls_config()?;
         match &self.backend {
             ChardevType::Stdio => {
                 set_termi_raw_mode().with_context(|| "Failed to set terminal to raw mode")?;
@@ -123,6 +170,30 @@ impl Chardev {
                     )
                 })?;
             }
+            ChardevType::Tcp {
+                host,
+                port,
+                server,
+                nowait,
+                telnet,
+            } => {
+                if !*server || !*nowait {
+                    bail!(
+                        "Argument \'server\' and \'nowait\' are both required for chardev \'{}\'",
+                        self.id
+                    );
+                }
+                let listener = TcpListener::bind((host.as_str(), *port)).with_context(|| {
+                    format!(
+                        "Failed to bind tcp socket for chardev {}, address {}:{}",
+                        self.id, host, port
+                    )
+                })?;
+                self.tcp_listener = Some(listener);
+                let output = Arc::new(Mutex::new(TcpOutput::new(*telnet)));
+                self.output = Some(output.clone());
+                self.tcp_output = Some(output);
+            }
             ChardevType::File(path) => {
                 let file = Arc::new(Mutex::new(
                     OpenOptions::new()
@@ -133,10 +204,54 @@ impl Chardev {
                 ));
                 self.output = Some(file);
             }
+            ChardevType::Ringbuf { size } => {
+                let ringbuf = Arc::new(Mutex::new(ChardevRingBuf::new(*size as usize)));
+                register_ringbuf_chardev(&self.id, ringbuf.clone());
+                self.output = Some(ringbuf);
+            }
         };
         Ok(())
     }
 
+    /// Make the tls configuration from tls credentials of socket-type chardev.
+    fn init_tls_config(&mut self) -> Result<()> {
+        let tls_creds = match self.tls_creds.as_ref() {
+            Some(tls_creds) => tls_creds,
+            None => return Ok(()),
+        };
+        #[cfg(not(target_env = "musl"))]
+        {
+            let tls_creds = TlsCreds {
+                cred_type: tls_creds.cred_type.clone(),
+                dir: tls_creds.dir.clone(),
+                endpoint: tls_creds.endpoint.clone(),
+                verifypeer: tls_creds.verifypeer,
+            };
+            self.tls_config =
+                Some(make_vencrypt_config(&tls_creds).with_context(|| {
+                    format!("Failed to make tls config for chardev {}", self.id)
+                })?);
+            Ok(())
+        }
+        #[cfg(target_env = "musl")]
+        bail!(
+            "Tls-creds {} is not supported for chardev {}",
+            tls_creds.id,
+            self.id
+        );
+    }
+
+    /// Set the connected stream as input and output of socket-type chardev.
+    fn set_socket_stream<T>(&mut self, stream: T)
+    where
+        T: CommunicatInInterface + CommunicatOutInterface + 'static,
+    {
+        self.stream_fd = Some(stream.as_raw_fd());
+        let stream_arc = Arc::new(Mutex::new(stream));
+        self.input = Some(stream_arc.clone());
+        self.output = Some(stream_arc);
+    }
+
     pub fn set_input_callback<T: 'static + InputReceiver>(&mut self, dev: &Arc<Mutex<T>>) {
         let cloned_dev = dev.clone();
         self.receive = Some(Arc::new(move |data: &[u8]| {
@@ -198,6 +313,108 @@ fn set_pty_raw_mode() -> Result<(i32, PathBuf)> {
     Ok((master, path))
 }
 
+/// Create the notifier of the connection of socket-type chardev.
+fn socket_stream_notifier(
+    chardev: Arc<Mutex<Chardev>>,
+    stream_fd: RawFd,
+    listener_fd: RawFd,
+) -> EventNotifier {
+    let inner_handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
+        let mut locked_chardev = chardev.lock().unwrap();
+        if event == EventSet::IN {
+            if locked_chardev.deactivated {
+                return None;
+            }
+            let buff_size = locked_chardev.get_remain_space_size.as_ref().unwrap()();
+            let mut buffer = vec![0_u8; buff_size];
+            if let Some(input) = locked_chardev.input.clone() {
+                if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
+                    // Tls stream may get no data until the whole record is received.
+                    if index == 0 {
+                        return None;
+                    }
+                    locked_chardev.receive.as_ref().unwrap()(&mut buffer[..index]);
+                } else {
+                    error!("Failed to read input data");
+                }
+            } else {
+                error!("Failed to get chardev input fd");
+            }
+            None
+        } else if event & EventSet::HANG_UP == EventSet::HANG_UP {
+            // Always allow disconnect even if has deactivated.
+            locked_chardev.input = None;
+            locked_chardev.output = None;
+            locked_chardev.stream_fd = None;
+            Some(gen_delete_notifiers(&[stream_fd]))
+        } else {
+            None
+        }
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        stream_fd,
+        Some(listener_fd),
+        EventSet::IN | EventSet::HANG_UP,
+        vec![inner_handler],
+    )
+}
+
+/// Attach the connected client to tcp-type chardev, and create the notifier of it.
+fn attach_tcp_client(
+    chardev: Arc<Mutex<Chardev>>,
+    client: TcpClient,
+    listener_fd: RawFd,
+) -> Vec<EventNotifier> {
+    let mut locked_chardev = chardev.lock().unwrap();
+    let stream_fd = client.as_raw_fd();
+    let output = locked_chardev.tcp_output.clone().unwrap();
+    let input = match output.lock().unwrap().attach(client) {
+        Ok(input) => Arc::new(Mutex::new(input)),
+        Err(e) => {
+            error!(
+                "Reject connection of chardev {}: {:?}",
+                locked_chardev.id, e
+            );
+            return Vec::new();
+        }
+    };
+    locked_chardev.stream_fd = Some(stream_fd);
+    drop(locked_chardev);
+
+    let inner_handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
+        let mut locked_chardev = chardev.lock().unwrap();
+        if event & (EventSet::HANG_UP | EventSet::READ_HANG_UP) != EventSet::empty() {
+            // Always allow disconnect even if has deactivated. The output of guest
+            // is held until the next client connects.
+            locked_chardev.stream_fd = None;
+            output.lock().unwrap().detach();
+            return Some(gen_delete_notifiers(&[stream_fd]));
+        }
+        if event & EventSet::IN != EventSet::IN || locked_chardev.deactivated {
+            return None;
+        }
+        let buff_size = locked_chardev.get_remain_space_size.as_ref().unwrap()();
+        let receive = locked_chardev.receive.clone();
+        drop(locked_chardev);
+        let mut buffer = vec![0_u8; buff_size];
+        match input.lock().unwrap().chr_read_raw(&mut buffer) {
+            // There may be no data left after telnet commands are filtered out.
+            Ok(0) => (),
+            Ok(index) => receive.as_ref().unwrap()(&buffer[..index]),
+            Err(e) => error!("Failed to read input data: {:?}", e),
+        }
+        None
+    });
+    vec![EventNotifier::new(
+        NotifierOperation::AddShared,
+        stream_fd,
+        Some(listener_fd),
+        EventSet::IN | EventSet::HANG_UP | EventSet::READ_HANG_UP,
+        vec![inner_handler],
+    )]
+}
+
 fn get_notifier_handler(
     chardev: Arc<Mutex<Chardev>>,
     backend: ChardevType,
@@ -231,50 +448,101 @@ fn get_notifier_handler(
             }
             let (stream, _) = locked_chardev.listener.as_ref().unwrap().accept().unwrap();
             let listener_fd = locked_chardev.listener.as_ref().unwrap().as_raw_fd();
-            let stream_fd = stream.as_raw_fd();
-            locked_chardev.stream_fd = Some(stream_fd);
-            let stream_arc = Arc::new(Mutex::new(stream));
-            locked_chardev.input = Some(stream_arc.clone());
-            locked_chardev.output = Some(stream_arc);
-
-            let cloned_chardev = chardev.clone();
-            let inner_handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
-                let mut locked_chardev = cloned_chardev.lock().unwrap();
-                if event == EventSet::IN {
-                    if locked_chardev.deactivated {
-                        return None;
-                    }
-                    let buff_size = locked_chardev.get_remain_space_size.as_ref().unwrap()();
-                    let mut buffer = vec![0_u8; buff_size];
-                    if let Some(input) = locked_chardev.input.clone() {
-                        if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
-                            locked_chardev.receive.as_ref().unwrap()(&mut buffer[..index]);
-                        } else {
-                            error!("Failed to read input data");
-                        }
-                    } else {
-                        error!("Failed to get chardev input fd");
-                    }
-                    None
-                } else if event & EventSet::HANG_UP == EventSet::HANG_UP {
-                    // Always allow disconnect even if has deactivated.
-                    locked_chardev.input = None;
-                    locked_chardev.output = None;
-                    locked_chardev.stream_fd = None;
-                    Some(gen_delete_notifiers(&[stream_fd]))
-                } else {
-                    None
+
+            #[cfg(not(target_env = "musl"))]
+            if let Some(tls_config) = locked_chardev.tls_config.clone() {
+                if let Err(e) = stream.set_nonblocking(true) {
+                    error!(
+                        "Reject connection of chardev {}: {:?}",
+                        locked_chardev.id, e
+                    );
+                    return None;
                 }
-            });
-            Some(vec![EventNotifier::new(
-                NotifierOperation::AddShared,
+                let cloned_chardev = chardev.clone();
+                let done: TlsHandshakeDone<UnixStream> = Rc::new(move |stream: TlsStream<_>| {
+                    let stream_fd = stream.as_raw_fd();
+                    cloned_chardev.lock().unwrap().set_socket_stream(stream);
+                    vec![socket_stream_notifier(
+                        cloned_chardev.clone(),
+                        stream_fd,
+                        listener_fd,
+                    )]
+                });
+                return start_tls_handshake(
+                    &locked_chardev.id,
+                    stream,
+                    tls_config,
+                    listener_fd,
+                    done,
+                );
+            }
+
+            let stream_fd = stream.as_raw_fd();
+            locked_chardev.set_socket_stream(stream);
+            Some(vec![socket_stream_notifier(
+                chardev.clone(),
                 stream_fd,
-                Some(listener_fd),
-                EventSet::IN | EventSet::HANG_UP,
-                vec![inner_handler],
+                listener_fd,
             )])
         }),
-        ChardevType::File(_) => Rc::new(move |_, _| None),
+        ChardevType::Tcp { .. } => Rc::new(move |_, _| {
+            let locked_chardev = chardev.lock().unwrap();
+            if locked_chardev.deactivated {
+                return None;
+            }
+            let listener = locked_chardev.tcp_listener.as_ref().unwrap();
+            let listener_fd = listener.as_raw_fd();
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!(
+                        "Failed to accept connection of chardev {}: {:?}",
+                        locked_chardev.id, e
+                    );
+                    return None;
+                }
+            };
+            // Only one client is served at a time, the others are closed at once.
+            if locked_chardev.stream_fd.is_some() {
+                info!(
+                    "Reject connection of chardev {}, which already has a client",
+                    locked_chardev.id
+                );
+                return None;
+            }
+            // Slow client can't block the vcpu writing output.
+            if let Err(e) = stream.set_nonblocking(true) {
+                error!(
+                    "Reject connection of chardev {}: {:?}",
+                    locked_chardev.id, e
+                );
+                return None;
+            }
+
+            #[cfg(not(target_env = "musl"))]
+            if let Some(tls_config) = locked_chardev.tls_config.clone() {
+                let cloned_chardev = chardev.clone();
+                let done: TlsHandshakeDone<TcpStream> = Rc::new(move |stream: TlsStream<_>| {
+                    let client = TcpClient::Tls(Arc::new(Mutex::new(stream)));
+                    attach_tcp_client(cloned_chardev.clone(), client, listener_fd)
+                });
+                return start_tls_handshake(
+                    &locked_chardev.id,
+                    stream,
+                    tls_config,
+                    listener_fd,
+                    done,
+                );
+            }
+
+            drop(locked_chardev);
+            Some(attach_tcp_client(
+                chardev.clone(),
+                TcpClient::Plain(stream),
+                listener_fd,
+            ))
+        }),
+        ChardevType::File(_) | ChardevType::Ringbuf { .. } => Rc::new(move |_, _| None),
     }
 }
 
@@ -314,7 +582,27 @@ impl EventNotifierHelper for Chardev {
                     ));
                 }
             }
-            ChardevType::File(_) => (),
+            ChardevType::Tcp { .. } => {
+                let locked_chardev = chardev.lock().unwrap();
+                if let Some(stream_fd) = locked_chardev.stream_fd {
+                    notifiers.push(EventNotifier::new(
+                        NotifierOperation::Resume,
+                        stream_fd,
+                        None,
+                        EventSet::IN | EventSet::HANG_UP | EventSet::READ_HANG_UP,
+                        Vec::new(),
+                    ));
+                } else if let Some(listener) = locked_chardev.tcp_listener.as_ref() {
+                    notifiers.push(EventNotifier::new(
+                        NotifierOperation::AddShared,
+                        listener.as_raw_fd(),
+                        None,
+                        EventSet::IN,
+                        vec![get_notifier_handler(cloned_chardev, backend)],
+                    ));
+                }
+            }
+            ChardevType::File(_) | ChardevType::Ringbuf { .. } => (),
         }
         notifiers
     }
@@ -343,3 +631,569 @@ impl CommunicatInInterface for Stdin {}
 impl CommunicatOutInterface for UnixStream {}
 impl CommunicatOutInterface for File {}
 impl CommunicatOutInterface for Stdout {}
+impl CommunicatOutInterface for ChardevRingBuf {}
+
+/// Output of tcp-type chardev. The output of guest is held while no client is connected,
+/// and is sent to the client once it connects.
+struct TcpOutput {
+    /// The connected client.
+    stream: Option<TcpClient>,
+    /// The output not sent to the client yet, the oldest is dropped if it's full.
+    backlog: VecDeque<u8>,
+    /// Speak telnet protocol with the client.
+    telnet: bool,
+}
+
+impl TcpOutput {
+    fn new(telnet: bool) -> Self {
+        TcpOutput {
+            stream: None,
+            backlog: VecDeque::new(),
+            telnet,
+        }
+    }
+
+    /// Attach the connected non-blocking client, and return the input of it.
+    fn attach(&mut self, stream: TcpClient) -> Result<TcpInput> {
+        let mut output = stream.try_clone()?;
+        if self.telnet {
+            // Character at a time mode, which is echoed by the guest.
+            output.write_all(&[
+                TELNET_IAC,
+                TELNET_WILL,
+                TELNET_OPT_ECHO,
+                TELNET_IAC,
+                TELNET_WILL,
+                TELNET_OPT_SGA,
+                TELNET_IAC,
+                TELNET_DONT,
+                TELNET_OPT_LINEMODE,
+            ])?;
+        }
+        self.stream = Some(output);
+        self.send_backlog();
+        Ok(TcpInput {
+            stream,
+            telnet: if self.telnet {
+                Some(TelnetFilter::new())
+            } else {
+                None
+            },
+        })
+    }
+
+    fn detach(&mut self) {
+        self.stream = None;
+    }
+
+    /// Send the held output to the client as much as possible.
+    fn send_backlog(&mut self) {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return,
+        };
+        while !self.backlog.is_empty() {
+            match stream.write(self.backlog.as_slices().0) {
+                Ok(0) => break,
+                Ok(len) => {
+                    self.backlog.drain(..len);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // The rest is sent when more output comes, or dropped if the client is gone.
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Write for TcpOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            // Data byte 255 is doubled in telnet protocol.
+            if self.telnet && *byte == TELNET_IAC {
+                self.backlog.push_back(TELNET_IAC);
+            }
+            self.backlog.push_back(*byte);
+        }
+        if self.backlog.len() > TCP_BACKLOG_SIZE {
+            let dropped = self.backlog.len() - TCP_BACKLOG_SIZE;
+            self.backlog.drain(..dropped);
+        }
+        self.send_backlog();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_backlog();
+        Ok(())
+    }
+}
+
+impl CommunicatOutInterface for TcpOutput {}
+
+/// State of parsing the telnet commands from the client.
+enum TelnetState {
+    Data,
+    /// Carriage return is received, the following NUL is dropped.
+    Cr,
+    Iac,
+    /// Option of WILL/WONT/DO/DONT command.
+    Option,
+    Subneg,
+    SubnegIac,
+}
+
+/// Filter out the telnet commands from the input of client.
+struct TelnetFilter {
+    state: TelnetState,
+}
+
+impl TelnetFilter {
+    fn new() -> Self {
+        TelnetFilter {
+            state: TelnetState::Data,
+        }
+    }
+
+    /// Parse one byte from the client, and return it if it's data.
+    fn filter_byte(&mut self, byte: u8) -> Option<u8> {
+        match self.state {
+            TelnetState::Data | TelnetState::Cr => {
+                let cr = matches!(self.state, TelnetState::Cr);
+                self.state = TelnetState::Data;
+                match byte {
+                    TELNET_IAC => {
+                        self.state = TelnetState::Iac;
+                        None
+                    }
+                    0 if cr => None,
+                    b'\r' => {
+                        self.state = TelnetState::Cr;
+                        Some(byte)
+                    }
+                    _ => Some(byte),
+                }
+            }
+            TelnetState::Iac => {
+                self.state = match byte {
+                    TELNET_WILL..=TELNET_DONT => TelnetState::Option,
+                    TELNET_SB => TelnetState::Subneg,
+                    _ => TelnetState::Data,
+                };
+                // Data byte 255 is sent as IAC IAC.
+                if byte == TELNET_IAC {
+                    Some(byte)
+                } else {
+                    None
+                }
+            }
+            TelnetState::Option => {
+                self.state = TelnetState::Data;
+                None
+            }
+            TelnetState::Subneg => {
+                if byte == TELNET_IAC {
+                    self.state = TelnetState::SubnegIac;
+                }
+                None
+            }
+            TelnetState::SubnegIac => {
+                self.state = if byte == TELNET_SE {
+                    TelnetState::Data
+                } else {
+                    TelnetState::Subneg
+                };
+                None
+            }
+        }
+    }
+}
+
+/// Connection with the client of tcp-type chardev.
+enum TcpClient {
+    Plain(TcpStream),
+    /// Connection encrypted by tls, which is shared by input and output.
+    #[cfg(not(target_env = "musl"))]
+    Tls(Arc<Mutex<TlsStream<TcpStream>>>),
+}
+
+impl TcpClient {
+    fn try_clone(&self) -> Result<Self> {
+        match self {
+            TcpClient::Plain(stream) => Ok(TcpClient::Plain(stream.try_clone()?)),
+            #[cfg(not(target_env = "musl"))]
+            TcpClient::Tls(stream) => Ok(TcpClient::Tls(stream.clone())),
+        }
+    }
+
+    /// Read the data from the client, return 0 if no data is available now.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            TcpClient::Plain(stream) => match std::io::Read::read(stream, buf) {
+                Ok(len) => Ok(len),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+                Err(e) => Err(e.into()),
+            },
+            #[cfg(not(target_env = "musl"))]
+            TcpClient::Tls(stream) => stream.lock().unwrap().chr_read_raw(buf),
+        }
+    }
+}
+
+impl AsRawFd for TcpClient {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            TcpClient::Plain(stream) => stream.as_raw_fd(),
+            #[cfg(not(target_env = "musl"))]
+            TcpClient::Tls(stream) => stream.lock().unwrap().as_raw_fd(),
+        }
+    }
+}
+
+impl Write for TcpClient {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            TcpClient::Plain(stream) => stream.write(buf),
+            #[cfg(not(target_env = "musl"))]
+            TcpClient::Tls(stream) => stream.lock().unwrap().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            TcpClient::Plain(stream) => stream.flush(),
+            #[cfg(not(target_env = "musl"))]
+            TcpClient::Tls(stream) => stream.lock().unwrap().flush(),
+        }
+    }
+}
+
+/// Input of the connection of tcp-type chardev.
+struct TcpInput {
+    stream: TcpClient,
+    /// Telnet command filter if telnet is enabled.
+    telnet: Option<TelnetFilter>,
+}
+
+impl AsRawFd for TcpInput {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl CommunicatInInterface for TcpInput {
+    fn chr_read_raw(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.stream.read(buf)?;
+        if let Some(telnet) = self.telnet.as_mut() {
+            let data: Vec<u8> = buf[..len]
+                .iter()
+                .filter_map(|byte| telnet.filter_byte(*byte))
+                .collect();
+            buf[..data.len()].copy_from_slice(&data);
+            return Ok(data.len());
+        }
+        Ok(len)
+    }
+}
+
+/// Socket stream of chardev encrypted by tls.
+#[cfg(not(target_env = "musl"))]
+struct TlsStream<S> {
+    conn: ServerConnection,
+    sock: S,
+}
+
+/// Progress of tls handshake on the non-blocking socket.
+#[cfg(not(target_env = "musl"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TlsHandshakeState {
+    /// Wait for more data from the client.
+    WantRead,
+    /// Wait for the socket to be writable to send the rest of data.
+    WantWrite,
+    Done,
+}
+
+#[cfg(not(target_env = "musl"))]
+impl<S: Read + Write> TlsStream<S> {
+    /// Go on with tls handshake as far as the non-blocking socket allows.
+    fn handshake(&mut self) -> Result<TlsHandshakeState> {
+        loop {
+            while self.conn.wants_write() {
+                match self.conn.write_tls(&mut self.sock) {
+                    Ok(_) => (),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        return Ok(TlsHandshakeState::WantWrite)
+                    }
+                    Err(e) => return Err(e).with_context(|| "Failed to write tls data"),
+                }
+            }
+            if !self.conn.is_handshaking() {
+                return Ok(TlsHandshakeState::Done);
+            }
+            match self.conn.read_tls(&mut self.sock) {
+                Ok(0) => bail!("Connection is closed during tls handshake"),
+                Ok(_) => {
+                    self.conn
+                        .process_new_packets()
+                        .with_context(|| "Failed to process tls data")?;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    return Ok(TlsHandshakeState::WantRead)
+                }
+                Err(e) => return Err(e).with_context(|| "Failed to read tls data"),
+            }
+        }
+    }
+}
+
+/// Callback to set up the connection once tls handshake is finished, which returns
+/// the notifiers of it.
+#[cfg(not(target_env = "musl"))]
+type TlsHandshakeDone<S> = Rc<dyn Fn(TlsStream<S>) -> Vec<EventNotifier>>;
+
+/// Start tls handshake with the accepted non-blocking client, which is driven by the events of
+/// main loop. Client which fails to finish the handshake in time is rejected.
+#[cfg(not(target_env = "musl"))]
+fn start_tls_handshake<S>(
+    id: &str,
+    sock: S,
+    tls_config: Arc<ServerConfig>,
+    parked_fd: RawFd,
+    done: TlsHandshakeDone<S>,
+) -> Option<Vec<EventNotifier>>
+where
+    S: Read + Write + AsRawFd + 'static,
+{
+    let conn = match ServerConnection::new(tls_config) {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Reject connection of chardev {}: {:?}", id, e);
+            return None;
+        }
+    };
+    let ctx = match EventLoop::get_ctx(None) {
+        Some(ctx) => ctx,
+        None => {
+            error!(
+                "Reject connection of chardev {}: main loop is not found",
+                id
+            );
+            return None;
+        }
+    };
+    let handshake = Arc::new(Mutex::new(Some(TlsStream { conn, sock })));
+
+    let cloned_handshake = handshake.clone();
+    let chardev_id = id.to_string();
+    let func = Box::new(move || {
+        // The stream is taken if the handshake has finished or failed.
+        let stream = match cloned_handshake.lock().unwrap().take() {
+            Some(stream) => stream,
+            None => return,
+        };
+        error!(
+            "Reject connection of chardev {}: tls handshake is not finished in {} seconds",
+            chardev_id, TLS_HANDSHAKE_TIMEOUT
+        );
+        if let Err(e) = EventLoop::update_event(gen_delete_notifiers(&[stream.as_raw_fd()]), None) {
+            error!("Failed to remove tls connection of chardev: {:?}", e);
+        }
+    });
+    ctx.delay_call(func, TLS_HANDSHAKE_TIMEOUT * NANOSECONDS_PER_SECOND);
+
+    // Client starts the handshake by sending ClientHello.
+    Some(vec![tls_handshake_notifier(
+        id.to_string(),
+        handshake,
+        parked_fd,
+        TlsHandshakeState::WantRead,
+        done,
+    )])
+}
+
+/// Create the notifier of the client in tls handshake, which waits for the events of `state`.
+#[cfg(not(target_env = "musl"))]
+fn tls_handshake_notifier<S>(
+    id: String,
+    handshake: Arc<Mutex<Option<TlsStream<S>>>>,
+    parked_fd: RawFd,
+    state: TlsHandshakeState,
+    done: TlsHandshakeDone<S>,
+) -> EventNotifier
+where
+    S: Read + Write + AsRawFd + 'static,
+{
+    let stream_fd = handshake.lock().unwrap().as_ref().unwrap().as_raw_fd();
+    let event = match state {
+        TlsHandshakeState::WantWrite => EventSet::OUT | EventSet::HANG_UP,
+        _ => EventSet::IN | EventSet::HANG_UP,
+    };
+
+    let handler: Rc<NotifierCallback> = Rc::new(move |event, fd| {
+        let mut locked_handshake = handshake.lock().unwrap();
+        // The handshake is timeout and the connection has been removed.
+        let stream = locked_handshake.as_mut()?;
+        let new_state = if event.contains(EventSet::HANG_UP) {
+            Err(anyhow!("Connection is closed during tls handshake"))
+        } else {
+            stream.handshake()
+        };
+
+        match new_state {
+            Ok(TlsHandshakeState::Done) => {
+                let stream = locked_handshake.take().unwrap();
+                drop(locked_handshake);
+                let mut notifiers = gen_delete_notifiers(&[fd]);
+                notifiers.extend(done(stream));
+                Some(notifiers)
+            }
+            Ok(new_state) if new_state == state => None,
+            Ok(new_state) => {
+                drop(locked_handshake);
+                let mut notifiers = gen_delete_notifiers(&[fd]);
+                notifiers.push(tls_handshake_notifier(
+                    id.clone(),
+                    handshake.clone(),
+                    parked_fd,
+                    new_state,
+                    done.clone(),
+                ));
+                Some(notifiers)
+            }
+            Err(e) => {
+                error!("Reject connection of chardev {}: {:?}", id, e);
+                locked_handshake.take();
+                Some(gen_delete_notifiers(&[fd]))
+            }
+        }
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        stream_fd,
+        Some(parked_fd),
+        event,
+        vec![handler],
+    )
+}
+
+#[cfg(not(target_env = "musl"))]
+impl<S: AsRawFd> AsRawFd for TlsStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+#[cfg(not(target_env = "musl"))]
+impl<S: Read + Write + AsRawFd + Send> CommunicatInInterface for TlsStream<S> {
+    fn chr_read_raw(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.conn.read_tls(&mut self.sock) {
+            Ok(_) => (),
+            // Non-blocking socket has no data now.
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(0),
+            Err(e) => return Err(e).with_context(|| "Failed to read tls data"),
+        }
+        self.conn
+            .process_new_packets()
+            .with_context(|| "Failed to process tls data")?;
+
+        let mut len = 0;
+        while len < buf.len() {
+            match self.conn.reader().read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                // No complete tls record is received yet.
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(not(target_env = "musl"))]
+impl<S: Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.conn.writer().write(buf)?;
+        self.flush()?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.conn.writer().flush()?;
+        while self.conn.wants_write() {
+            match self.conn.write_tls(&mut self.sock) {
+                Ok(_) => (),
+                // The rest is sent by the next write or flush of non-blocking socket.
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_env = "musl"))]
+impl<S: Write + Send> CommunicatOutInterface for TlsStream<S> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_telnet_filter() {
+        let mut telnet = TelnetFilter::new();
+        let input = [
+            b'a',
+            TELNET_IAC,
+            TELNET_WILL,
+            TELNET_OPT_ECHO,
+            b'b',
+            TELNET_IAC,
+            TELNET_IAC,
+            TELNET_IAC,
+            TELNET_SB,
+            24,
+            0,
+            TELNET_IAC,
+            TELNET_SE,
+            b'\r',
+            0,
+            b'c',
+        ];
+        let data: Vec<u8> = input
+            .iter()
+            .filter_map(|byte| telnet.filter_byte(*byte))
+            .collect();
+        assert_eq!(data, vec![b'a', b'b', TELNET_IAC, b'\r', b'c']);
+    }
+
+    #[test]
+    fn test_tcp_output_backlog() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Output is held while no client is connected.
+        let mut output = TcpOutput::new(true);
+        output.write_all(&[b'a', TELNET_IAC]).unwrap();
+        output.write_all(&vec![b'b'; TCP_BACKLOG_SIZE - 2]).unwrap();
+        assert_eq!(output.backlog.len(), TCP_BACKLOG_SIZE);
+        assert_eq!(output.backlog[0], TELNET_IAC);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        output.attach(TcpClient::Plain(stream)).unwrap();
+        assert!(output.backlog.is_empty());
+
+        let mut buf = vec![0_u8; 9 + TCP_BACKLOG_SIZE];
+        client.read_exact(&mut buf).unwrap();
+        // Telnet negotiation is sent first.
+        assert_eq!(&buf[..3], &[TELNET_IAC, TELNET_WILL, TELNET_OPT_ECHO]);
+        // The oldest byte 'a' is dropped, and data byte 255 is doubled.
+        assert_eq!(&buf[9..11], &[TELNET_IAC, TELNET_IAC]);
+        assert!(buf[11..].iter().all(|byte| *byte == b'b'));
+    }
+}
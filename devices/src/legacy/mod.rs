@@ -33,6 +33,7 @@ mod pflash;
 mod pl011;
 #[cfg(target_arch = "aarch64")]
 mod pl031;
+mod pvpanic;
 #[cfg(all(not(target_env = "musl"), target_arch = "aarch64"))]
 mod ramfb;
 #[cfg(target_arch = "x86_64")]
@@ -53,6 +54,9 @@ pub use pflash::PFlash;
 pub use pl011::PL011;
 #[cfg(target_arch = "aarch64")]
 pub use pl031::{PL031, RTC_CR, RTC_DR, RTC_IMSC, RTC_LR};
+#[cfg(target_arch = "x86_64")]
+pub use pvpanic::PVPANIC_PORT;
+pub use pvpanic::{PvPanic, PVPANIC_REGION_SIZE};
 #[cfg(target_arch = "aarch64")]
 #[cfg(not(target_env = "musl"))]
 pub use ramfb::Ramfb;
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp::{max, min};
 use std::fs::File;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
@@ -21,9 +22,21 @@ use anyhow::{anyhow, bail, Context, Result};
 use log::{debug, error, warn};
 use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
 use util::num_ops::{deposit_u32, extract_u32, read_data_u32, write_data_u32};
+use util::unix::host_page_size;
+
+/// Intel/Sharp compatible parallel flash, e.g. UEFI code and variable store.
+///
+/// Programmed data is written to the shared mapping of backend file at once, but
+/// synced to disk in batch: when a buffered program is confirmed, when a block is
+/// erased, when firmware switches back to read array mode, and when VM shuts down
+/// or resets. So every operation which firmware waits for is durable before the
+/// next one starts, and a power loss leaves the variable store in a state which
+/// fault tolerant write of firmware can recover from.
 pub struct PFlash {
-    /// Has backend file or not.
-    has_backend: bool,
+    /// Backend file, used to sync programmed data to disk.
+    backend: Option<File>,
+    /// Range of programmed data which is not synced to disk, `(start, end)`.
+    dirty: Option<(u64, u64)>,
     /// Length of block.
     block_len: u32,
     /// The width of PFlash array for vm.
@@ -73,7 +86,7 @@ impl PFlash {
     /// Return Error when
     /// * block-length is zero.
     /// * PFlash size is zero.
-    /// * file size is smaller than PFlash size.
+    /// * file size doesn't match PFlash size.
     pub fn new(
         size: u64,
         backend: &Option<File>,
@@ -89,16 +102,23 @@ impl PFlash {
         if blocks_per_device == 0 {
             bail!("PFlash: num-blocks is zero which is invalid.");
         }
-        if let Some(fd) = backend.as_ref() {
-            let len = fd.metadata().unwrap().len();
-            if len < size {
-                bail!(
-                    "Mmap requires 0x{:X} bytes, given file provides 0x{:X} bytes",
-                    size,
-                    len
-                );
+        let backend = match backend.as_ref() {
+            Some(fd) => {
+                let len = fd.metadata().unwrap().len();
+                if len != size {
+                    bail!(
+                        "PFlash bank requires 0x{:X} bytes, given file provides 0x{:X} bytes",
+                        size,
+                        len
+                    );
+                }
+                Some(
+                    fd.try_clone()
+                        .with_context(|| "Failed to clone PFlash backend file")?,
+                )
             }
-        }
+            None => None,
+        };
 
         let num_devices: u32 = if device_width == 0 {
             1
@@ -173,7 +193,8 @@ impl PFlash {
         cfi_table[0x3f] = 0x01;
 
         Ok(PFlash {
-            has_backend: backend.is_some(),
+            backend,
+            dirty: None,
             block_len,
             bank_width,
             // device id for Intel PFlash.
@@ -215,6 +236,7 @@ impl PFlash {
         let dev = Arc::new(Mutex::new(self));
         let region_ops = sysbus.build_region_ops(&dev);
         let rom_region = Region::init_rom_device_region(host_mmap, region_ops);
+        rom_region.set_name("pflash");
         dev.lock().unwrap().rom = Some(rom_region.clone());
         sysbus
             .sys_mem
@@ -227,6 +249,8 @@ impl PFlash {
     }
 
     fn set_read_array_mode(&mut self, is_illegal_cmd: bool) -> Result<()> {
+        // Programs are finished when firmware reads array again.
+        self.flush_content()?;
         self.rom
             .as_ref()
             .unwrap()
@@ -312,8 +336,9 @@ impl PFlash {
         Ok(resp)
     }
 
+    /// Record the programmed range, which is synced to disk by `flush_content`.
     fn update_content(&mut self, offset: u64, size: u32) -> Result<()> {
-        if !self.has_backend {
+        if self.backend.is_none() {
             return Ok(());
         }
         // Unwrap is safe, because after realize function, rom isn't none.
@@ -326,20 +351,42 @@ impl PFlash {
             )));
         }
 
+        let end = offset + size as u64;
+        self.dirty = Some(match self.dirty {
+            Some((start, old_end)) => (min(start, offset), max(old_end, end)),
+            None => (offset, end),
+        });
+        Ok(())
+    }
+
+    /// Sync the programmed data to disk.
+    fn flush_content(&mut self) -> Result<()> {
+        let (start, end) = match self.dirty.take() {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        // Unwrap is safe, because only device with backend has dirty range.
+        let backend = self.backend.as_ref().unwrap();
+        let mr = self.rom.as_ref().unwrap();
         let addr: u64 = mr
             .get_host_address()
             .ok_or_else(|| anyhow!("Failed to get host address."))?;
+        // Msync requires address aligned with page size.
+        let aligned_start = start & !(host_page_size() - 1);
         let ret = unsafe {
             // Safe as addr and size are valid.
             libc::msync(
-                addr as *mut libc::c_void,
-                size as libc::size_t,
+                (addr + aligned_start) as *mut libc::c_void,
+                (end - aligned_start) as libc::size_t,
                 libc::MS_SYNC,
             )
         };
         if ret != 0 {
             bail!("{}", std::io::Error::last_os_error());
         }
+        backend
+            .sync_data()
+            .with_context(|| "Failed to sync PFlash backend file")?;
 
         Ok(())
     }
@@ -411,7 +458,10 @@ impl PFlash {
                         error!("{}", format!("Failed to write PFlash device: {:?}.", e));
                     }
 
-                    if let Err(e) = self.update_content(offset_mask, self.block_len) {
+                    if let Err(e) = self
+                        .update_content(offset_mask, self.block_len)
+                        .and_then(|_| self.flush_content())
+                    {
                         error!(
                             "{}",
                             format!("Failed to update content for PFlash device: {:?}.", e)
@@ -646,6 +696,10 @@ impl PFlash {
         match self.cmd {
             0xe8 => {
                 if cmd == 0xd0 {
+                    // Write buffer to flash is confirmed.
+                    if let Err(e) = self.flush_content() {
+                        error!("Failed to flush PFlash device: {:?}.", e);
+                    }
                     self.write_cycle = 0;
                     self.status |= 0x80;
                 } else {
@@ -875,7 +929,12 @@ impl SysBusDevOps for PFlash {
         SysBusDevType::Flash
     }
 
+    fn flush(&mut self) -> sysbus::Result<()> {
+        self.flush_content()
+    }
+
     fn reset(&mut self) -> sysbus::Result<()> {
+        self.flush_content()?;
         sysbus::Result::with_context(self.rom.as_ref().unwrap().set_rom_device_romd(true), || {
             "Fail to set PFlash rom region read only"
         })?;
@@ -916,9 +975,12 @@ mod test {
     }
 
     fn pflash_dev_init(file_name: &str) -> Arc<Mutex<PFlash>> {
+        pflash_dev_init_with_mode(file_name, false)
+    }
+
+    fn pflash_dev_init_with_mode(file_name: &str, read_only: bool) -> Arc<Mutex<PFlash>> {
         let sector_len: u32 = 0x40_000;
         let flash_size: u64 = 0x400_0000;
-        let read_only: bool = false;
         let flash_base: u64 = 0;
 
         let fd = File::create(file_name).unwrap();
@@ -1048,6 +1110,13 @@ mod test {
         assert!(dev.lock().unwrap().write(data.as_ref(), base, offset));
         let data = vec![0x70, 0, 0x70, 0];
         assert!(dev.lock().unwrap().write(data.as_ref(), base, offset));
+        // Programmed data is synced when firmware reads array again.
+        assert_eq!(dev.lock().unwrap().dirty, Some((0, 4)));
+        assert!(dev
+            .lock()
+            .unwrap()
+            .write([0xff, 0, 0xff, 0].as_ref(), base, offset));
+        assert_eq!(dev.lock().unwrap().dirty, None);
 
         let mut read_data = vec![0, 0, 0, 0];
         dev.lock().unwrap().cmd = 0x00;
@@ -1087,4 +1156,34 @@ mod test {
 
         fs::remove_file(file_name).unwrap();
     }
+
+    #[test]
+    fn test_write_read_only() {
+        let file_name = "flash_vars_for_write_4.fd";
+        let dev = pflash_dev_init_with_mode(file_name, true);
+        let base = GuestAddress(0x0000);
+        let offset = 0_u64;
+
+        // Single byte program is rejected with program error in status.
+        let data = vec![0x10, 0, 0, 0];
+        dev.lock().unwrap().write_cycle = 0;
+        assert!(dev.lock().unwrap().write(data.as_ref(), base, offset));
+        let data = vec![0x70, 0, 0x70, 0];
+        assert!(dev.lock().unwrap().write(data.as_ref(), base, offset));
+        assert_eq!(dev.lock().unwrap().status, 0x90);
+
+        let mut read_data = vec![0xFF_u8, 0xFF, 0xFF, 0xFF];
+        dev.lock().unwrap().cmd = 0x00;
+        assert!(dev.lock().unwrap().read(&mut read_data, base, offset));
+        assert_eq!(read_data, vec![0, 0, 0, 0]);
+
+        // Block erase is rejected with erase error in status.
+        dev.lock().unwrap().status = 0x80;
+        let data = vec![0x20, 0, 0, 0];
+        dev.lock().unwrap().write_cycle = 0;
+        assert!(dev.lock().unwrap().write(data.as_ref(), base, offset));
+        assert_eq!(dev.lock().unwrap().status, 0xa0);
+
+        fs::remove_file(file_name).unwrap();
+    }
 }
@@ -456,9 +456,12 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            tls_creds: None,
         };
         let mut pl011_dev = PL011::new(SerialConfig {
             chardev: chardev_cfg,
+            flow_control: false,
+            baud: None,
         })
         .unwrap();
         assert_eq!(pl011_dev.state.rfifo, [0; PL011_FIFO_SIZE]);
@@ -119,6 +119,12 @@ impl PL031 {
         Ok(())
     }
 
+    /// Set the time which the RTC starts from, in seconds since 1970-01-01 00:00:00.
+    pub fn set_start_time(&mut self, time: u64) {
+        self.tick_offset = time as u32;
+        self.base_time = Instant::now();
+    }
+
     /// Get current clock value.
     fn get_current_value(&self) -> u32 {
         (self.base_time.elapsed().as_secs() as u128 + self.tick_offset as u128) as u32
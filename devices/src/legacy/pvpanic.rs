@@ -0,0 +1,153 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use acpi::{
+    AmlBuilder, AmlDevice, AmlInteger, AmlNameDecl, AmlResTemplate, AmlScopeBuilder, AmlString,
+};
+#[cfg(target_arch = "x86_64")]
+use acpi::{AmlIoDecode, AmlIoResource};
+#[cfg(target_arch = "aarch64")]
+use acpi::{AmlMemory32Fixed, AmlReadAndWrite};
+use address_space::GuestAddress;
+use anyhow::{anyhow, Context, Result};
+use log::{error, info};
+use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
+use vmm_sys_util::eventfd::EventFd;
+
+use super::error::LegacyError;
+
+/// IO port of pvpanic device.
+#[cfg(target_arch = "x86_64")]
+pub const PVPANIC_PORT: u64 = 0x505;
+/// Size of the IO port or MMIO region of pvpanic device.
+pub const PVPANIC_REGION_SIZE: u64 = 2;
+
+/// Guest kernel panicked.
+const PVPANIC_PANICKED: u8 = 1 << 0;
+/// Guest kernel is going to boot the crash kernel.
+const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+/// Events supported by the device.
+const PVPANIC_EVENTS: u8 = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+
+/// Pvpanic device, which is notified by guest kernel when it panics.
+pub struct PvPanic {
+    /// Eventfd notified when guest panics, the action is taken by VM.
+    panic_req: Arc<EventFd>,
+    /// System resource.
+    res: SysRes,
+}
+
+impl PvPanic {
+    pub fn new(panic_req: Arc<EventFd>) -> Self {
+        PvPanic {
+            panic_req,
+            res: SysRes::default(),
+        }
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<()> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| anyhow!(LegacyError::SetSysResErr))?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size)?;
+        Ok(())
+    }
+}
+
+impl SysBusDevOps for PvPanic {
+    /// Read the events supported by the device.
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, _offset: u64) -> bool {
+        data.fill(0);
+        data[0] = PVPANIC_EVENTS;
+        true
+    }
+
+    /// Write the event of guest kernel.
+    fn write(&mut self, data: &[u8], _base: GuestAddress, _offset: u64) -> bool {
+        let event = data[0];
+        if event & PVPANIC_PANICKED != 0 {
+            error!("Guest kernel panicked, notified by pvpanic");
+            if let Err(e) = self.panic_req.write(1) {
+                error!("Failed to write panic request of pvpanic: {:?}", e);
+                return false;
+            }
+        } else if event & PVPANIC_CRASH_LOADED != 0 {
+            info!("Guest kernel is going to boot the crash kernel");
+        }
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.res)
+    }
+
+    fn get_type(&self) -> SysBusDevType {
+        SysBusDevType::PvPanic
+    }
+}
+
+impl AmlBuilder for PvPanic {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new("PEVT");
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlString("QEMU0001".to_string())));
+        acpi_dev.append_child(AmlNameDecl::new("_STA", AmlInteger(0xF)));
+
+        let mut res = AmlResTemplate::new();
+        #[cfg(target_arch = "x86_64")]
+        res.append_child(AmlIoResource::new(
+            AmlIoDecode::Decode16,
+            self.res.region_base as u16,
+            self.res.region_base as u16,
+            0x01,
+            self.res.region_size as u8,
+        ));
+        #[cfg(target_arch = "aarch64")]
+        res.append_child(AmlMemory32Fixed::new(
+            AmlReadAndWrite::ReadWrite,
+            self.res.region_base as u32,
+            self.res.region_size as u32,
+        ));
+        acpi_dev.append_child(AmlNameDecl::new("_CRS", res));
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pvpanic_read_write() {
+        let panic_req = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut pvpanic = PvPanic::new(panic_req.clone());
+
+        let mut data = [0_u8; 1];
+        assert!(pvpanic.read(&mut data, GuestAddress(0), 0));
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        // Loading crash kernel doesn't notify the VM.
+        assert!(pvpanic.write(&[PVPANIC_CRASH_LOADED], GuestAddress(0), 0));
+        assert!(panic_req.read().is_err());
+
+        assert!(pvpanic.write(&[PVPANIC_PANICKED], GuestAddress(0), 0));
+        assert_eq!(panic_req.read().unwrap(), 1);
+    }
+}
@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use acpi::{
     AmlBuilder, AmlDevice, AmlEisaId, AmlIoDecode, AmlIoResource, AmlIrqNoFlags, AmlNameDecl,
@@ -20,6 +20,8 @@ use acpi::{
 use address_space::GuestAddress;
 use anyhow::Result;
 use log::{debug, error, warn};
+use machine_manager::config::RtcDriftFix;
+use machine_manager::machine::{register_vm_state_notifier, KvmVmState};
 use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
 use vmm_sys_util::eventfd::EventFd;
 
@@ -59,6 +61,12 @@ const CMOS_MEM_BELOW_4GB: (u8, u8) = (0x34, 0x35);
 // 0x5B/0x5C/0x5D stores low/middle/high byte of memory above 4GB, unit is 64KB.
 const CMOS_MEM_ABOVE_4GB: (u8, u8, u8) = (0x5B, 0x5C, 0x5D);
 
+/// With `driftfix=slew`, RTC runs faster by 1/RTC_SLEW_DIVISOR after VM is resumed,
+/// until the time while VM is paused is caught up.
+const RTC_SLEW_DIVISOR: u32 = 10;
+/// Id of the VM state notifier of RTC.
+const RTC_VM_STATE_NOTIFIER: &str = "cmos-rtc";
+
 fn rtc_time_to_tm(time_val: i64) -> libc::tm {
     let mut dest_tm = libc::tm {
         tm_sec: 0,
@@ -115,6 +123,14 @@ pub struct RTC {
     tick_offset: u64,
     /// Record the real time.
     base_time: Instant,
+    /// Policy to correct the drift after VM is resumed.
+    driftfix: RtcDriftFix,
+    /// The time when VM is paused, RTC is stopped while VM is paused with `driftfix=slew`.
+    paused_time: Option<Instant>,
+    /// Time lagged behind the real time at `slew_time`, which is being caught up.
+    lag: Duration,
+    /// The time when RTC starts to catch up the lag.
+    slew_time: Instant,
 }
 
 impl RTC {
@@ -137,6 +153,10 @@ impl RTC {
                 .expect("time wrong")
                 .as_secs(),
             base_time: Instant::now(),
+            driftfix: RtcDriftFix::None,
+            paused_time: None,
+            lag: Duration::ZERO,
+            slew_time: Instant::now(),
         };
 
         let tm = rtc_time_to_tm(rtc.get_current_value());
@@ -186,6 +206,20 @@ impl RTC {
         }
     }
 
+    /// Set the time which RTC starts from, in seconds since 1970-01-01 00:00:00.
+    pub fn set_start_time(&mut self, time: u64) {
+        self.tick_offset = time;
+        self.base_time = Instant::now();
+        self.lag = Duration::ZERO;
+        let tm = rtc_time_to_tm(self.get_current_value());
+        self.set_rtc_cmos(tm);
+    }
+
+    /// Set the policy to correct the drift after VM is resumed.
+    pub fn set_driftfix(&mut self, driftfix: RtcDriftFix) {
+        self.driftfix = driftfix;
+    }
+
     fn init_rtc_reg(&mut self) {
         // Set Time frequency divider and Rate selection frequency in Register-A.
         // Bits 6-4 = Time frequency divider (010 = 32.768KHz).
@@ -266,11 +300,48 @@ impl RTC {
         let region_size = self.res.region_size;
         self.set_sys_resource(sysbus, region_base, region_size)?;
 
+        let driftfix = self.driftfix;
         let dev = Arc::new(Mutex::new(self));
         sysbus.attach_device(&dev, region_base, region_size)?;
+        if driftfix == RtcDriftFix::Slew {
+            register_vm_state_notifier(
+                RTC_VM_STATE_NOTIFIER,
+                Arc::new(move |state: KvmVmState| dev.lock().unwrap().vm_state_changed(state)),
+            );
+        }
         Ok(())
     }
 
+    /// Stop RTC when VM is paused, and start to catch up the paused time when VM is resumed.
+    fn vm_state_changed(&mut self, state: KvmVmState) {
+        match state {
+            KvmVmState::Paused if self.paused_time.is_none() => {
+                self.paused_time = Some(Instant::now());
+            }
+            KvmVmState::Running => {
+                if let Some(paused_time) = self.paused_time.take() {
+                    let now = Instant::now();
+                    self.lag = self.remaining_lag(paused_time) + now.duration_since(paused_time);
+                    self.slew_time = now;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Get the lag which is not caught up yet at the time `now`.
+    fn remaining_lag(&self, now: Instant) -> Duration {
+        let caught_up = now.saturating_duration_since(self.slew_time) / RTC_SLEW_DIVISOR;
+        self.lag.saturating_sub(caught_up)
+    }
+
+    /// Get the time elapsed on RTC since `base_time`.
+    fn elapsed(&self) -> Duration {
+        let now = self.paused_time.unwrap_or_else(Instant::now);
+        now.saturating_duration_since(self.base_time)
+            .saturating_sub(self.remaining_lag(now))
+    }
+
     fn inject_interrupt(&self) {
         if let Some(evt_fd) = self.interrupt_evt() {
             if let Err(e) = evt_fd.write(1) {
@@ -283,7 +354,7 @@ impl RTC {
 
     /// Get current clock value.
     fn get_current_value(&self) -> i64 {
-        (self.base_time.elapsed().as_secs() as i128 + self.tick_offset as i128) as i64
+        (self.elapsed().as_secs() as i128 + self.tick_offset as i128) as i64
     }
 
     fn set_rtc_cmos(&mut self, tm: libc::tm) {
@@ -347,11 +418,13 @@ impl RTC {
 
         self.tick_offset = mktime64(year, mon, day, hour, min, sec);
 
-        self.base_time = Instant::now();
+        // The time set by guest is not caught up.
+        self.base_time = self.paused_time.unwrap_or_else(Instant::now);
+        self.lag = Duration::ZERO;
     }
 
     fn update_in_progress(&self) -> bool {
-        self.base_time.elapsed().subsec_nanos() >= (NANOSECONDS_PER_SECOND - UIP_HOLD_LENGTH) as u32
+        self.elapsed().subsec_nanos() >= (NANOSECONDS_PER_SECOND - UIP_HOLD_LENGTH) as u32
     }
 }
 
@@ -527,4 +600,40 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_rtc_driftfix_slew() -> Result<()> {
+        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        rtc.set_driftfix(RtcDriftFix::Slew);
+        // Start from 2013-11-13 02:04:56.
+        let start_time = 1384308296;
+        rtc.set_start_time(start_time);
+        assert_eq!(cmos_read(&mut rtc, RTC_YEAR), 0x13);
+        assert_eq!(cmos_read(&mut rtc, RTC_HOURS), 0x02);
+        assert_eq!(cmos_read(&mut rtc, RTC_MINUTES), 0x04);
+
+        // RTC is stopped while VM is paused for 2 seconds.
+        let shift = |time: Instant, secs: u64| time - Duration::from_secs(secs);
+        rtc.vm_state_changed(KvmVmState::Paused);
+        rtc.base_time = shift(rtc.base_time, 2);
+        rtc.paused_time = rtc.paused_time.map(|time| shift(time, 2));
+        let paused_value = rtc.get_current_value();
+        assert!(paused_value - (start_time as i64) <= WIGGLE as i64);
+        rtc.vm_state_changed(KvmVmState::Running);
+        assert!(rtc.get_current_value() - paused_value <= WIGGLE as i64);
+        assert_eq!(rtc.lag.as_secs(), 2);
+
+        // The paused time is caught up after running for 10 times of it.
+        rtc.base_time = shift(rtc.base_time, 10);
+        rtc.slew_time = shift(rtc.slew_time, 10);
+        let remaining_lag = rtc.remaining_lag(Instant::now()).as_millis();
+        assert!((900..=1100).contains(&remaining_lag));
+        rtc.base_time = shift(rtc.base_time, 11);
+        rtc.slew_time = shift(rtc.slew_time, 11);
+        assert_eq!(rtc.remaining_lag(Instant::now()), Duration::ZERO);
+        let value = rtc.get_current_value() - start_time as i64;
+        assert!((23..=23 + WIGGLE as i64).contains(&value));
+
+        Ok(())
+    }
 }
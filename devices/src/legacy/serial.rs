@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
 use acpi::{
     AmlActiveLevel, AmlBuilder, AmlDevice, AmlEdgeLevel, AmlEisaId, AmlExtendedInterrupt,
@@ -32,6 +32,7 @@ use migration_derive::{ByteCode, Desc};
 use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
+use util::time::NANOSECONDS_PER_SECOND;
 use vmm_sys_util::eventfd::EventFd;
 
 use super::chardev::{Chardev, InputReceiver};
@@ -53,6 +54,7 @@ const _UART_LSR_BI: u8 = 0x10;
 const UART_LSR_THRE: u8 = 0x20;
 const UART_LSR_TEMT: u8 = 0x40;
 
+const UART_MCR_RTS: u8 = 0x02;
 const UART_MCR_OUT2: u8 = 0x08;
 const UART_MCR_LOOP: u8 = 0x10;
 const UART_MSR_CTS: u8 = 0x10;
@@ -64,6 +66,9 @@ const UART_IRQ: i32 = 4;
 
 const RECEIVER_BUFF_SIZE: usize = 1024;
 
+/// Bits on the line for one character: 1 start bit, 8 data bits and 1 stop bit.
+const UART_CHAR_BITS: u64 = 10;
+
 /// Contain register status of serial device.
 #[repr(C)]
 #[derive(Copy, Clone, Desc, ByteCode)]
@@ -123,6 +128,12 @@ pub struct Serial {
     res: SysRes,
     /// Character device for redirection.
     chardev: Arc<Mutex<Chardev>>,
+    /// Hold received data until the guest asserts RTS.
+    flow_control: bool,
+    /// Time in nanoseconds to transmit one character at the emulated baud rate.
+    tx_char_ns: Option<u64>,
+    /// Weak reference to self, used to finish transmitting in the event loop.
+    self_ref: Option<Weak<Mutex<Serial>>>,
 }
 
 impl Serial {
@@ -133,6 +144,11 @@ impl Serial {
             interrupt_evt: None,
             res: SysRes::default(),
             chardev: Arc::new(Mutex::new(Chardev::new(cfg.chardev))),
+            flow_control: cfg.flow_control,
+            tx_char_ns: cfg
+                .baud
+                .map(|baud| UART_CHAR_BITS * NANOSECONDS_PER_SECOND / u64::from(baud)),
+            self_ref: None,
         }
     }
     pub fn realize(
@@ -164,7 +180,8 @@ impl Serial {
             param_type: "earlycon".to_string(),
             value: format!("uart,mmio,0x{:08x}", region_base),
         });
-        let locked_dev = dev.lock().unwrap();
+        let mut locked_dev = dev.lock().unwrap();
+        locked_dev.self_ref = Some(Arc::downgrade(&dev));
         locked_dev.chardev.lock().unwrap().set_input_callback(&dev);
         EventLoop::update_event(
             EventNotifierHelper::internal_notifiers(locked_dev.chardev.clone()),
@@ -199,6 +216,36 @@ impl Serial {
         }
     }
 
+    /// Whether received data can be passed to the guest now.
+    fn rx_ready(&self) -> bool {
+        !self.flow_control || self.state.mcr & UART_MCR_RTS != 0
+    }
+
+    /// Emulate the time to transmit one character at the emulated baud rate.
+    /// The transmitter holding register becomes empty again after it.
+    fn delay_thr_empty(&mut self, tx_char_ns: u64) {
+        let serial = self.self_ref.clone();
+        let ctx = EventLoop::get_ctx(None);
+        if serial.is_none() || ctx.is_none() {
+            return;
+        }
+
+        self.state.thr_pending = 0;
+        self.state.lsr &= !(UART_LSR_THRE | UART_LSR_TEMT);
+        let func = Box::new(move || {
+            if let Some(serial) = serial.as_ref().unwrap().upgrade() {
+                serial.lock().unwrap().thr_empty();
+            }
+        });
+        ctx.unwrap().delay_call(func, tx_char_ns);
+    }
+
+    fn thr_empty(&mut self) {
+        self.state.lsr |= UART_LSR_THRE | UART_LSR_TEMT;
+        self.state.thr_pending = 1;
+        self.update_iir();
+    }
+
     // Read one byte data from a certain register selected by `offset`.
     //
     // # Arguments
@@ -310,6 +357,10 @@ impl Serial {
                         locked_output
                             .flush()
                             .with_context(|| "serial: failed to flush.")?;
+                        drop(locked_output);
+                        if let Some(tx_char_ns) = self.tx_char_ns {
+                            self.delay_thr_empty(tx_char_ns);
+                        }
                     }
 
                     self.update_iir();
@@ -331,7 +382,12 @@ impl Serial {
                 self.state.lcr = data;
             }
             4 => {
+                let rts_raised = data & !self.state.mcr & UART_MCR_RTS != 0;
                 self.state.mcr = data;
+                if self.flow_control && rts_raised && !self.rbr.is_empty() {
+                    self.state.lsr |= UART_LSR_DR;
+                    self.update_iir();
+                }
             }
             7 => {
                 self.state.scr = data;
@@ -356,8 +412,10 @@ impl InputReceiver for Serial {
             }
 
             self.rbr.extend(data);
-            self.state.lsr |= UART_LSR_DR;
-            self.update_iir();
+            if self.rx_ready() {
+                self.state.lsr |= UART_LSR_DR;
+                self.update_iir();
+            }
         }
     }
 
@@ -471,9 +529,12 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            tls_creds: None,
         };
         let mut usart = Serial::new(SerialConfig {
             chardev: chardev_cfg.clone(),
+            flow_control: false,
+            baud: None,
         });
         assert_eq!(usart.state.ier, 0);
         assert_eq!(usart.state.iir, 1);
@@ -521,14 +582,51 @@ mod test {
         assert_eq!(usart.read_internal(6), 0xf0);
     }
 
+    #[test]
+    fn test_serial_flow_control() {
+        let chardev_cfg = ChardevConfig {
+            id: "chardev".to_string(),
+            backend: ChardevType::Stdio,
+            tls_creds: None,
+        };
+        let mut usart = Serial::new(SerialConfig {
+            chardev: chardev_cfg,
+            flow_control: true,
+            baud: Some(115200),
+        });
+        assert_eq!(usart.tx_char_ns, Some(86805));
+
+        // Received data is held while RTS is not asserted.
+        usart.input_handle(&[0x01, 0x02]);
+        assert_eq!(usart.rbr.len(), 2);
+        assert_eq!(usart.read_internal(5) & UART_LSR_DR, 0);
+
+        // Data becomes ready once the guest asserts RTS.
+        usart
+            .write_internal(4, UART_MCR_OUT2 | UART_MCR_RTS)
+            .unwrap();
+        assert_eq!(usart.read_internal(5) & UART_LSR_DR, UART_LSR_DR);
+        assert_eq!(usart.read_internal(0), 0x01);
+        assert_eq!(usart.read_internal(0), 0x02);
+        assert_eq!(usart.read_internal(5) & UART_LSR_DR, 0);
+
+        // Deassert RTS again.
+        usart.write_internal(4, UART_MCR_OUT2).unwrap();
+        usart.input_handle(&[0x03]);
+        assert_eq!(usart.read_internal(5) & UART_LSR_DR, 0);
+    }
+
     #[test]
     fn test_serial_migration_interface() {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            tls_creds: None,
         };
         let mut usart = Serial::new(SerialConfig {
             chardev: chardev_cfg,
+            flow_control: false,
+            baud: None,
         });
         // Get state vector for usart
         let serial_state_result = usart.get_state_vec();
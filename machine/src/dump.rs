@@ -0,0 +1,372 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # Guest memory dump
+//!
+//! Dump guest RAM and vcpu registers to an ELF core file, which can be
+//! analyzed offline by tools like `crash`. Paging is not supported, so the
+//! `PT_LOAD` segments only carry guest physical addresses.
+
+use std::io::Write;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use address_space::{AddressRange, AddressSpace};
+#[cfg(target_arch = "aarch64")]
+use cpu::get_core_regs;
+use cpu::CPU;
+use util::byte_code::ByteCode;
+
+#[cfg(target_arch = "aarch64")]
+use crate::gdbstub::{PSR_MODE_EL1H, PSR_MODE_MASK};
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_CORE: u16 = 4;
+#[cfg(target_arch = "x86_64")]
+const EM_ARCH: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const EM_ARCH: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+/// Note type of the process status, which contains the registers of vcpu.
+const NT_PRSTATUS: u32 = 1;
+/// Name of the notes generated by kernel.
+const NOTE_NAME_CORE: &[u8] = b"CORE\0";
+
+/// Offset of `pr_pid` in `struct elf_prstatus`.
+const PRSTATUS_PID_OFFSET: usize = 32;
+/// Offset of `pr_reg` in `struct elf_prstatus`.
+const PRSTATUS_REGS_OFFSET: usize = 112;
+/// Size of the fields following `pr_reg` in `struct elf_prstatus`.
+const PRSTATUS_TAIL_SIZE: usize = 8;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64Header {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u64,
+    e_phoff: u64,
+    e_shoff: u64,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+impl ByteCode for Elf64Header {}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+impl ByteCode for Elf64ProgramHeader {}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64NoteHeader {
+    n_namesz: u32,
+    n_descsz: u32,
+    n_type: u32,
+}
+
+impl ByteCode for Elf64NoteHeader {}
+
+fn align_up_4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Build an ELF note, name and desc are padded to 4 bytes.
+fn elf_note(n_type: u32, name: &[u8], desc: &[u8]) -> Vec<u8> {
+    let header = Elf64NoteHeader {
+        n_namesz: name.len() as u32,
+        n_descsz: desc.len() as u32,
+        n_type,
+    };
+    let mut note = header.as_bytes().to_vec();
+    note.extend_from_slice(name);
+    note.resize(note.len() + align_up_4(name.len()) - name.len(), 0);
+    note.extend_from_slice(desc);
+    note.resize(note.len() + align_up_4(desc.len()) - desc.len(), 0);
+    note
+}
+
+/// Build `struct elf_prstatus` of a vcpu, only `pr_pid` and `pr_reg` are filled.
+fn elf_prstatus(pid: u32, regs: &[u64]) -> Vec<u8> {
+    let mut desc = vec![0_u8; PRSTATUS_REGS_OFFSET];
+    desc[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + size_of::<u32>()]
+        .copy_from_slice(&pid.to_le_bytes());
+    for reg in regs {
+        desc.extend_from_slice(&reg.to_le_bytes());
+    }
+    desc.resize(desc.len() + PRSTATUS_TAIL_SIZE, 0);
+    desc
+}
+
+/// Get the registers of vcpu in the layout of `struct user_regs_struct`.
+#[cfg(target_arch = "x86_64")]
+fn vcpu_user_regs(cpu: &CPU) -> Result<Vec<u64>> {
+    let regs = cpu.fd().get_regs()?;
+    let sregs = cpu.fd().get_sregs()?;
+
+    Ok(vec![
+        regs.r15,
+        regs.r14,
+        regs.r13,
+        regs.r12,
+        regs.rbp,
+        regs.rbx,
+        regs.r11,
+        regs.r10,
+        regs.r9,
+        regs.r8,
+        regs.rax,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        // orig_rax
+        u64::MAX,
+        regs.rip,
+        u64::from(sregs.cs.selector),
+        regs.rflags,
+        regs.rsp,
+        u64::from(sregs.ss.selector),
+        sregs.fs.base,
+        sregs.gs.base,
+        u64::from(sregs.ds.selector),
+        u64::from(sregs.es.selector),
+        u64::from(sregs.fs.selector),
+        u64::from(sregs.gs.selector),
+    ])
+}
+
+/// Get the registers of vcpu in the layout of `struct user_pt_regs`.
+#[cfg(target_arch = "aarch64")]
+fn vcpu_user_regs(cpu: &CPU) -> Result<Vec<u64>> {
+    let core_regs = get_core_regs(cpu.fd())?;
+
+    let mut regs = core_regs.regs.regs.to_vec();
+    let sp = if core_regs.regs.pstate & PSR_MODE_MASK == PSR_MODE_EL1H {
+        core_regs.sp_el1
+    } else {
+        core_regs.regs.sp
+    };
+    regs.push(sp);
+    regs.push(core_regs.regs.pc);
+    regs.push(core_regs.regs.pstate);
+    Ok(regs)
+}
+
+/// Write ELF core file with the given notes, and one `PT_LOAD` segment for
+/// each Ram range of `sys_mem`. Guest memory is written range by range, it's
+/// never buffered.
+fn write_elf_core(sys_mem: &Arc<AddressSpace>, notes: &[u8], dst: &mut dyn Write) -> Result<()> {
+    let ram_ranges: Vec<AddressRange> = sys_mem.ram_ranges();
+    let phnum = ram_ranges.len() + 1;
+    let ehdr_size = size_of::<Elf64Header>();
+    let phdr_size = size_of::<Elf64ProgramHeader>();
+
+    let mut e_ident = [0_u8; 16];
+    e_ident[..4].copy_from_slice(b"\x7fELF");
+    e_ident[4] = ELFCLASS64;
+    e_ident[5] = ELFDATA2LSB;
+    e_ident[6] = EV_CURRENT;
+    let ehdr = Elf64Header {
+        e_ident,
+        e_type: ET_CORE,
+        e_machine: EM_ARCH,
+        e_version: u32::from(EV_CURRENT),
+        e_phoff: ehdr_size as u64,
+        e_ehsize: ehdr_size as u16,
+        e_phentsize: phdr_size as u16,
+        e_phnum: phnum as u16,
+        ..Default::default()
+    };
+    let mut headers = ehdr.as_bytes().to_vec();
+
+    let mut offset = (ehdr_size + phdr_size * phnum) as u64;
+    let note_phdr = Elf64ProgramHeader {
+        p_type: PT_NOTE,
+        p_offset: offset,
+        p_filesz: notes.len() as u64,
+        p_memsz: notes.len() as u64,
+        ..Default::default()
+    };
+    headers.extend_from_slice(note_phdr.as_bytes());
+    offset += notes.len() as u64;
+    for range in ram_ranges.iter() {
+        let load_phdr = Elf64ProgramHeader {
+            p_type: PT_LOAD,
+            p_offset: offset,
+            p_paddr: range.base.raw_value(),
+            p_filesz: range.size,
+            p_memsz: range.size,
+            ..Default::default()
+        };
+        headers.extend_from_slice(load_phdr.as_bytes());
+        offset += range.size;
+    }
+
+    dst.write_all(&headers)
+        .with_context(|| "Failed to write ELF headers")?;
+    dst.write_all(notes)
+        .with_context(|| "Failed to write ELF notes")?;
+    for range in ram_ranges.iter() {
+        sys_mem
+            .read(dst, range.base, range.size)
+            .with_context(|| format!("Failed to dump guest memory at 0x{:X}", range.base.0))?;
+    }
+    dst.flush()
+        .with_context(|| "Failed to flush guest memory dump")?;
+
+    Ok(())
+}
+
+/// Get the path of the core file from the arguments of `dump-guest-memory`.
+///
+/// # Arguments
+///
+/// * `paging` - Whether to dump with guest paging, it's not supported.
+/// * `protocol` - Destination of the dump, only `file:<path>` is supported.
+pub fn parse_dump_protocol(paging: bool, protocol: &str) -> Result<&str> {
+    if paging {
+        bail!("Dump guest memory with paging is not supported");
+    }
+    match protocol.strip_prefix("file:") {
+        Some(path) if !path.is_empty() => Ok(path),
+        _ => bail!("Unsupported dump protocol {}", protocol),
+    }
+}
+
+/// Dump guest memory and the registers of vcpus to `dst` in ELF core format.
+/// VM should be paused, so that the memory and registers are consistent.
+///
+/// # Arguments
+///
+/// * `sys_mem` - Memory address space.
+/// * `cpus` - Cpus vector restore cpu structure.
+/// * `dst` - Destination the core file is written to.
+pub fn dump_guest_memory(
+    sys_mem: &Arc<AddressSpace>,
+    cpus: &[Arc<CPU>],
+    dst: &mut dyn Write,
+) -> Result<()> {
+    let mut notes = Vec::new();
+    for cpu in cpus.iter() {
+        let regs = vcpu_user_regs(cpu)
+            .with_context(|| format!("Failed to get registers of vcpu{}", cpu.id()))?;
+        // Vcpu is presented as a thread of the core, and pid 0 is reserved.
+        let prstatus = elf_prstatus(u32::from(cpu.id()) + 1, &regs);
+        notes.extend(elf_note(NT_PRSTATUS, NOTE_NAME_CORE, &prstatus));
+    }
+
+    write_elf_core(sys_mem, &notes, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use address_space::{GuestAddress, HostMemMapping, Region};
+
+    use super::*;
+
+    #[test]
+    fn test_elf_note() {
+        let note = elf_note(NT_PRSTATUS, NOTE_NAME_CORE, &[1, 2, 3]);
+        // Header, name "CORE\0" padded to 8 bytes, desc padded to 4 bytes.
+        assert_eq!(note.len(), 12 + 8 + 4);
+        assert_eq!(&note[..4], &5_u32.to_le_bytes());
+        assert_eq!(&note[4..8], &3_u32.to_le_bytes());
+        assert_eq!(&note[8..12], &NT_PRSTATUS.to_le_bytes());
+        assert_eq!(&note[12..20], b"CORE\0\0\0\0");
+        assert_eq!(&note[20..], &[1, 2, 3, 0]);
+
+        // Size of `struct elf_prstatus` on x86_64 is 336, and 392 on aarch64.
+        assert_eq!(elf_prstatus(1, &[0; 27]).len(), 336);
+        assert_eq!(elf_prstatus(1, &[0; 34]).len(), 392);
+        assert_eq!(&elf_prstatus(2, &[0; 27])[32..36], &2_u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse_dump_protocol() {
+        assert_eq!(
+            parse_dump_protocol(false, "file:/tmp/vmcore").unwrap(),
+            "/tmp/vmcore"
+        );
+        assert!(parse_dump_protocol(true, "file:/tmp/vmcore").is_err());
+        assert!(parse_dump_protocol(false, "file:").is_err());
+        assert!(parse_dump_protocol(false, "fd:dumpfd").is_err());
+    }
+
+    #[test]
+    fn test_write_elf_core() {
+        let root = Region::init_container_region(0x4000);
+        let sys_mem = AddressSpace::new(root.clone()).unwrap();
+        for base in [0_u64, 0x2000] {
+            let ram = Arc::new(
+                HostMemMapping::new(GuestAddress(base), None, 0x1000, None, false, false, false)
+                    .unwrap(),
+            );
+            root.add_subregion(Region::init_ram_region(ram), base)
+                .unwrap();
+        }
+        sys_mem
+            .write_object(&0xAA_u8, GuestAddress(0x2010))
+            .unwrap();
+
+        let notes = elf_note(NT_PRSTATUS, NOTE_NAME_CORE, &elf_prstatus(1, &[0; 27]));
+        let mut core = Vec::new();
+        write_elf_core(&sys_mem, &notes, &mut core).unwrap();
+
+        let ehdr_size = size_of::<Elf64Header>();
+        let phdr_size = size_of::<Elf64ProgramHeader>();
+        let ehdr = Elf64Header::from_bytes(&core[..ehdr_size]).unwrap();
+        assert_eq!(&ehdr.e_ident[..4], b"\x7fELF");
+        assert_eq!(ehdr.e_type, ET_CORE);
+        assert_eq!(ehdr.e_phnum, 3);
+
+        let data_offset = (ehdr_size + phdr_size * 3 + notes.len()) as u64;
+        assert_eq!(core.len() as u64, data_offset + 0x2000);
+        let phdr = |idx: usize| {
+            let start = ehdr_size + phdr_size * idx;
+            *Elf64ProgramHeader::from_bytes(&core[start..start + phdr_size]).unwrap()
+        };
+        assert_eq!(phdr(0).p_type, PT_NOTE);
+        assert_eq!(phdr(0).p_filesz, notes.len() as u64);
+        assert_eq!(phdr(1).p_type, PT_LOAD);
+        assert_eq!(phdr(1).p_offset, data_offset);
+        assert_eq!(phdr(1).p_paddr, 0);
+        assert_eq!(phdr(2).p_offset, data_offset + 0x1000);
+        assert_eq!(phdr(2).p_paddr, 0x2000);
+        assert_eq!(phdr(2).p_filesz, 0x1000);
+        assert_eq!(core[(data_offset + 0x1010) as usize], 0xAA);
+    }
+}
@@ -0,0 +1,897 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # Gdb stub
+//!
+//! Implement a subset of gdb remote serial protocol, so that gdb can debug the
+//! guest through tcp or unix socket, e.g. `target remote :1234`.
+//!
+//! The stub works in all-stop mode: VM is paused when gdb connects or a vcpu
+//! stops on breakpoint, and the whole VM is resumed on continue. Supported
+//! packets are `?`, `g`, `G`, `m`, `M`, `c`, `s`, `Z0`, `z0`, `H`, `T`, `D`,
+//! `k`, interrupt (Ctrl-C) and some `q` queries.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use kvm_bindings::KVM_GUESTDBG_USE_SW_BP;
+use log::{error, info, warn};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use address_space::{AddressSpace, GuestAddress};
+#[cfg(target_arch = "aarch64")]
+use cpu::{get_core_regs, set_core_regs};
+use cpu::{CPUInterface, CPU};
+use machine_manager::config::GdbConfig;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::machine::MachineLifecycle;
+use machine_manager::temp_cleaner::TempCleaner;
+use util::loop_context::{
+    gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
+    NotifierOperation,
+};
+
+/// Max size of packet, reported to gdb in `qSupported`.
+const MAX_PACKET_SIZE: usize = 4096;
+/// Size of page to translate guest virtual address.
+const GDB_PAGE_SIZE: u64 = 4096;
+/// Signal reported to gdb when vcpu stops on breakpoint or single step.
+const GDB_SIGTRAP: u8 = 5;
+/// Signal reported to gdb when VM is interrupted by gdb.
+const GDB_SIGINT: u8 = 2;
+/// Byte sent by gdb to interrupt the running VM.
+const GDB_INTERRUPT: u8 = 0x03;
+/// Error reply for invalid arguments.
+const GDB_EINVAL: &str = "E22";
+/// Error reply for inaccessible memory.
+const GDB_EFAULT: &str = "E14";
+
+/// Instruction of software breakpoint: `int3`.
+#[cfg(target_arch = "x86_64")]
+const SW_BREAKPOINT: [u8; 1] = [0xcc];
+/// Instruction of software breakpoint: `brk #0`.
+#[cfg(target_arch = "aarch64")]
+const SW_BREAKPOINT: [u8; 4] = [0x00, 0x00, 0x20, 0xd4];
+
+/// Mask of exception level and stack pointer selection in pstate.
+#[cfg(target_arch = "aarch64")]
+pub(crate) const PSR_MODE_MASK: u64 = 0xf;
+/// EL1 with SP_EL1.
+#[cfg(target_arch = "aarch64")]
+pub(crate) const PSR_MODE_EL1H: u64 = 0x5;
+
+/// Packet received from gdb.
+#[derive(Debug, PartialEq, Eq)]
+enum GdbPacket {
+    /// Command packet with valid checksum.
+    Command(String),
+    /// Packet with bad checksum, it should be retransmitted by gdb.
+    BadChecksum,
+    /// Request to interrupt the running VM.
+    Interrupt,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn encode_packet(data: &str) -> Vec<u8> {
+    format!("${}#{:02x}", data, checksum(data.as_bytes())).into_bytes()
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(data: &str) -> Result<Vec<u8>> {
+    if !data.is_ascii() || data.len() % 2 != 0 {
+        bail!("Invalid hex string {}", data);
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&data[i..i + 2], 16)
+                .with_context(|| format!("Invalid hex string {}", data))
+        })
+        .collect()
+}
+
+/// Parse `addr,length` in hex.
+fn parse_addr_len(args: &str) -> Result<(u64, usize)> {
+    let (addr, len) = args
+        .split_once(',')
+        .with_context(|| format!("Invalid address and length {}", args))?;
+    let addr =
+        u64::from_str_radix(addr, 16).with_context(|| format!("Invalid address {}", addr))?;
+    let len = usize::from_str_radix(len, 16).with_context(|| format!("Invalid length {}", len))?;
+    if len > MAX_PACKET_SIZE {
+        bail!("Length {} exceeds max packet size", len);
+    }
+    Ok((addr, len))
+}
+
+/// Parse packets from received data, the incomplete packet is left in `buf`.
+fn parse_packets(buf: &mut Vec<u8>) -> Vec<GdbPacket> {
+    let mut packets = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        match buf[pos] {
+            GDB_INTERRUPT => {
+                packets.push(GdbPacket::Interrupt);
+                pos += 1;
+            }
+            b'$' => {
+                // Packet is `$<data>#<checksum>`, checksum is two hex digits.
+                let end = match buf[pos..].iter().position(|b| *b == b'#') {
+                    Some(off) if pos + off + 2 < buf.len() => pos + off,
+                    _ => break,
+                };
+                let data = &buf[pos + 1..end];
+                let sum = std::str::from_utf8(&buf[end + 1..end + 3])
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok());
+                if sum == Some(checksum(data)) {
+                    packets.push(GdbPacket::Command(
+                        String::from_utf8_lossy(data).to_string(),
+                    ));
+                } else {
+                    packets.push(GdbPacket::BadChecksum);
+                }
+                pos = end + 3;
+            }
+            // Acknowledgement from gdb and garbage are ignored.
+            _ => pos += 1,
+        }
+    }
+    buf.drain(..pos);
+    // Drop the broken packet which never ends.
+    if buf.len() > 2 * MAX_PACKET_SIZE {
+        buf.clear();
+    }
+    packets
+}
+
+#[cfg(target_arch = "x86_64")]
+fn read_registers(cpu: &CPU) -> Result<Vec<u8>> {
+    let regs = cpu.fd().get_regs()?;
+    let sregs = cpu.fd().get_sregs()?;
+
+    // Layout of `i386:x86-64` in gdb: general registers, rip, eflags and
+    // segment selectors.
+    let mut data = Vec::new();
+    for reg in [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+    ] {
+        data.extend_from_slice(&reg.to_le_bytes());
+    }
+    data.extend_from_slice(&(regs.rflags as u32).to_le_bytes());
+    for seg in [sregs.cs, sregs.ss, sregs.ds, sregs.es, sregs.fs, sregs.gs] {
+        data.extend_from_slice(&(seg.selector as u32).to_le_bytes());
+    }
+    Ok(data)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn write_registers(cpu: &CPU, data: &[u8]) -> Result<()> {
+    // Segment selectors can't be changed without descriptors, they are ignored.
+    if data.len() < 17 * 8 + 4 {
+        bail!("Registers data is too short");
+    }
+    let mut regs = cpu.fd().get_regs()?;
+    let mut values = data
+        .chunks_exact(8)
+        .map(|v| u64::from_le_bytes(v.try_into().unwrap()));
+    for reg in [
+        &mut regs.rax,
+        &mut regs.rbx,
+        &mut regs.rcx,
+        &mut regs.rdx,
+        &mut regs.rsi,
+        &mut regs.rdi,
+        &mut regs.rbp,
+        &mut regs.rsp,
+        &mut regs.r8,
+        &mut regs.r9,
+        &mut regs.r10,
+        &mut regs.r11,
+        &mut regs.r12,
+        &mut regs.r13,
+        &mut regs.r14,
+        &mut regs.r15,
+        &mut regs.rip,
+    ] {
+        *reg = values.next().unwrap();
+    }
+    let eflags = u32::from_le_bytes(data[136..140].try_into().unwrap());
+    regs.rflags = (regs.rflags & !0xffff_ffff) | u64::from(eflags);
+    cpu.fd().set_regs(&regs)?;
+    Ok(())
+}
+
+#[cfg(target_arch = "aarch64")]
+fn read_registers(cpu: &CPU) -> Result<Vec<u8>> {
+    let core_regs = get_core_regs(cpu.fd())?;
+
+    // Layout of `aarch64` in gdb: x0-x30, sp, pc and cpsr.
+    let mut data = Vec::new();
+    for reg in core_regs.regs.regs.iter() {
+        data.extend_from_slice(&reg.to_le_bytes());
+    }
+    let sp = if core_regs.regs.pstate & PSR_MODE_MASK == PSR_MODE_EL1H {
+        core_regs.sp_el1
+    } else {
+        core_regs.regs.sp
+    };
+    data.extend_from_slice(&sp.to_le_bytes());
+    data.extend_from_slice(&core_regs.regs.pc.to_le_bytes());
+    data.extend_from_slice(&(core_regs.regs.pstate as u32).to_le_bytes());
+    Ok(data)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn write_registers(cpu: &CPU, data: &[u8]) -> Result<()> {
+    if data.len() < 33 * 8 + 4 {
+        bail!("Registers data is too short");
+    }
+    let mut core_regs = get_core_regs(cpu.fd())?;
+    let mut values = data
+        .chunks_exact(8)
+        .map(|v| u64::from_le_bytes(v.try_into().unwrap()));
+    for reg in core_regs.regs.regs.iter_mut() {
+        *reg = values.next().unwrap();
+    }
+    let sp = values.next().unwrap();
+    if core_regs.regs.pstate & PSR_MODE_MASK == PSR_MODE_EL1H {
+        core_regs.sp_el1 = sp;
+    } else {
+        core_regs.regs.sp = sp;
+    }
+    core_regs.regs.pc = values.next().unwrap();
+    let cpsr = u32::from_le_bytes(data[264..268].try_into().unwrap());
+    core_regs.regs.pstate = (core_regs.regs.pstate & !0xffff_ffff) | u64::from(cpsr);
+    set_core_regs(cpu.fd(), core_regs)?;
+    Ok(())
+}
+
+/// Listener of gdb stub server.
+enum GdbListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl GdbListener {
+    fn accept(&self) -> Result<GdbStream> {
+        match self {
+            GdbListener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                // Packets are small and interactive.
+                stream.set_nodelay(true)?;
+                Ok(GdbStream::Tcp(stream))
+            }
+            GdbListener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                Ok(GdbStream::Unix(stream))
+            }
+        }
+    }
+}
+
+impl AsRawFd for GdbListener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            GdbListener::Tcp(listener) => listener.as_raw_fd(),
+            GdbListener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// Connection with gdb.
+enum GdbStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl GdbStream {
+    fn shutdown(&self) {
+        let ret = match self {
+            GdbStream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            GdbStream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+        if let Err(e) = ret {
+            warn!("Failed to shutdown gdb connection: {:?}", e);
+        }
+    }
+}
+
+impl Read for GdbStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            GdbStream::Tcp(stream) => stream.read(buf),
+            GdbStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for GdbStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            GdbStream::Tcp(stream) => stream.write(buf),
+            GdbStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            GdbStream::Tcp(stream) => stream.flush(),
+            GdbStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for GdbStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            GdbStream::Tcp(stream) => stream.as_raw_fd(),
+            GdbStream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+/// The VM debugged by gdb.
+struct GdbTarget {
+    /// The VM to pause and resume.
+    vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>,
+    /// Vcpus of the VM, thread id in gdb is index of vcpu plus one.
+    cpus: Vec<Arc<CPU>>,
+    /// System address space to access guest memory.
+    sys_mem: Arc<AddressSpace>,
+    /// Connection with gdb. It's kept after disconnection, so that the fd
+    /// is valid until it's removed from event loop.
+    stream: Option<GdbStream>,
+    /// Gdb is connected.
+    connected: bool,
+    /// Data received but not parsed.
+    buf: Vec<u8>,
+    /// Index of vcpu selected by gdb.
+    current: usize,
+    /// Inserted software breakpoints, address -> original instruction.
+    breakpoints: HashMap<u64, Vec<u8>>,
+    /// VM is resumed by gdb and gdb is waiting for stop reply.
+    running: bool,
+    /// VM is paused by gdb, resume it when gdb disconnects.
+    paused_by_gdb: bool,
+}
+
+impl GdbTarget {
+    fn new(
+        vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>,
+        cpus: Vec<Arc<CPU>>,
+        sys_mem: Arc<AddressSpace>,
+    ) -> Self {
+        GdbTarget {
+            vm,
+            cpus,
+            sys_mem,
+            stream: None,
+            connected: false,
+            buf: Vec::new(),
+            current: 0,
+            breakpoints: HashMap::new(),
+            running: false,
+            paused_by_gdb: false,
+        }
+    }
+
+    /// Gdb connects, the VM is paused for gdb.
+    fn attach(&mut self, stream: GdbStream) {
+        self.stream = Some(stream);
+        self.connected = true;
+        self.buf.clear();
+        self.current = 0;
+        self.running = false;
+        self.paused_by_gdb = self.vm.lock().unwrap().pause();
+    }
+
+    /// Gdb disconnects, remove all breakpoints and resume the VM.
+    fn detach(&mut self) {
+        if !self.connected {
+            return;
+        }
+        self.connected = false;
+        if let Some(stream) = self.stream.as_ref() {
+            stream.shutdown();
+        }
+
+        // Guest debug can only be changed when vcpus are out of kvm.
+        if self.running {
+            self.paused_by_gdb = self.vm.lock().unwrap().pause();
+            self.running = false;
+        }
+        let breakpoints: Vec<(u64, Vec<u8>)> = self.breakpoints.drain().collect();
+        for (addr, mut orig) in breakpoints {
+            if let Err(e) = self.access_memory(addr, &mut orig, true) {
+                error!("Failed to remove breakpoint 0x{:x}: {:?}", addr, e);
+            }
+        }
+        for cpu in self.cpus.iter() {
+            if let Err(e) = cpu.set_guest_debug(0) {
+                error!("{:?}", e);
+            }
+            cpu.take_debug_stop();
+        }
+        if self.paused_by_gdb && !self.vm.lock().unwrap().resume() {
+            warn!("VM is not resumed after gdb disconnects");
+        }
+        self.paused_by_gdb = false;
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        if let Some(stream) = self.stream.as_mut() {
+            if let Err(e) = stream.write_all(data) {
+                error!("Failed to send data to gdb: {:?}", e);
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        self.send(&encode_packet(data));
+    }
+
+    fn stop_reply(&self, signal: u8) -> String {
+        format!("T{:02x}thread:{:x};", signal, self.current + 1)
+    }
+
+    /// Handle data from gdb, returns false if gdb disconnects.
+    fn handle_input(&mut self) -> bool {
+        let mut data = [0_u8; MAX_PACKET_SIZE];
+        let len = match self.stream.as_mut().map(|stream| stream.read(&mut data)) {
+            Some(Ok(len)) if len > 0 => len,
+            Some(Err(e)) => {
+                error!("Failed to read data from gdb: {:?}", e);
+                return false;
+            }
+            _ => return false,
+        };
+        self.buf.extend_from_slice(&data[..len]);
+
+        for packet in parse_packets(&mut self.buf) {
+            match packet {
+                GdbPacket::Interrupt => {
+                    if self.running {
+                        self.running = false;
+                        self.paused_by_gdb = self.vm.lock().unwrap().pause();
+                        let reply = self.stop_reply(GDB_SIGINT);
+                        self.send_packet(&reply);
+                    }
+                }
+                GdbPacket::BadChecksum => self.send(b"-"),
+                GdbPacket::Command(cmd) => {
+                    self.send(b"+");
+                    if let Some(reply) = self.handle_command(&cmd) {
+                        self.send_packet(&reply);
+                    }
+                    if !self.connected {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Handle command from gdb, returns the reply. There is no reply for
+    /// `c` until VM stops, or for `k`.
+    fn handle_command(&mut self, cmd: &str) -> Option<String> {
+        if cmd.is_empty() || !cmd.is_ascii() {
+            return Some(String::new());
+        }
+        let (head, args) = cmd.split_at(1);
+        let ret = match head {
+            "?" => Ok(self.stop_reply(GDB_SIGTRAP)),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "m" => self
+                .read_memory(args)
+                .or_else(|_| Ok(GDB_EFAULT.to_string())),
+            "M" => self
+                .write_memory(args)
+                .or_else(|_| Ok(GDB_EFAULT.to_string())),
+            "c" => match self.resume() {
+                Ok(()) => return None,
+                Err(e) => Err(e),
+            },
+            "s" => self.step(),
+            "Z" => self.update_breakpoint(true, args),
+            "z" => self.update_breakpoint(false, args),
+            "H" => self.set_thread(args),
+            "T" => self.parse_thread(args).map(|_| "OK".to_string()),
+            "D" => {
+                self.detach();
+                Ok("OK".to_string())
+            }
+            "k" => {
+                self.detach();
+                return None;
+            }
+            "q" => Ok(self.query(args)),
+            // Empty reply for unsupported command.
+            _ => Ok(String::new()),
+        };
+        Some(ret.unwrap_or_else(|e| {
+            warn!("Failed to handle gdb command {}: {:?}", cmd, e);
+            GDB_EINVAL.to_string()
+        }))
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            format!("PacketSize={:x}", MAX_PACKET_SIZE)
+        } else if args == "Attached" {
+            "1".to_string()
+        } else if args == "C" {
+            format!("QC{:x}", self.current + 1)
+        } else if args == "fThreadInfo" {
+            let threads: Vec<String> = (1..=self.cpus.len()).map(|t| format!("{:x}", t)).collect();
+            format!("m{}", threads.join(","))
+        } else if args == "sThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// Parse thread id of gdb to index of vcpu.
+    fn parse_thread(&self, thread: &str) -> Result<usize> {
+        let id = usize::from_str_radix(thread, 16)
+            .with_context(|| format!("Invalid thread id {}", thread))?;
+        if id == 0 || id > self.cpus.len() {
+            bail!("Thread {} doesn't exist", id);
+        }
+        Ok(id - 1)
+    }
+
+    fn set_thread(&mut self, args: &str) -> Result<String> {
+        if args.len() < 2 {
+            bail!("Invalid thread {}", args);
+        }
+        // `-1` means all threads and `0` means any thread.
+        let thread = &args[1..];
+        if thread != "-1" && thread != "0" {
+            self.current = self.parse_thread(thread)?;
+        }
+        Ok("OK".to_string())
+    }
+
+    fn read_registers(&self) -> Result<String> {
+        Ok(hex_encode(&read_registers(&self.cpus[self.current])?))
+    }
+
+    fn write_registers(&self, args: &str) -> Result<String> {
+        write_registers(&self.cpus[self.current], &hex_decode(args)?)?;
+        Ok("OK".to_string())
+    }
+
+    /// Access guest memory with virtual address of the current vcpu.
+    fn access_memory(&self, addr: u64, data: &mut [u8], write: bool) -> Result<()> {
+        let cpu = &self.cpus[self.current];
+        let mut offset = 0;
+        while offset < data.len() {
+            let gva = addr
+                .checked_add(offset as u64)
+                .with_context(|| format!("Address 0x{:x} overflows", addr))?;
+            let len = min(
+                data.len() - offset,
+                (GDB_PAGE_SIZE - gva % GDB_PAGE_SIZE) as usize,
+            );
+            let gpa = GuestAddress(cpu.translate_gva(gva)?);
+            let chunk = &mut data[offset..offset + len];
+            if write {
+                let mut src: &[u8] = chunk;
+                self.sys_mem.write(&mut src, gpa, len as u64)?;
+            } else {
+                let mut dst: &mut [u8] = chunk;
+                self.sys_mem.read(&mut dst, gpa, len as u64)?;
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+
+    fn read_memory(&self, args: &str) -> Result<String> {
+        let (addr, len) = parse_addr_len(args)?;
+        let mut data = vec![0_u8; len];
+        self.access_memory(addr, &mut data, false)?;
+        Ok(hex_encode(&data))
+    }
+
+    fn write_memory(&self, args: &str) -> Result<String> {
+        let (addr_len, data) = args
+            .split_once(':')
+            .with_context(|| format!("Invalid memory data {}", args))?;
+        let (addr, len) = parse_addr_len(addr_len)?;
+        let mut data = hex_decode(data)?;
+        if data.len() != len {
+            bail!("Length of memory data mismatches {}", len);
+        }
+        self.access_memory(addr, &mut data, true)?;
+        Ok("OK".to_string())
+    }
+
+    /// Insert or remove software breakpoint, `Z0,addr,kind`.
+    fn update_breakpoint(&mut self, insert: bool, args: &str) -> Result<String> {
+        let params: Vec<&str> = args.split(',').collect();
+        if params.len() != 3 {
+            bail!("Invalid breakpoint {}", args);
+        }
+        // Only software breakpoint is supported.
+        if params[0] != "0" {
+            return Ok(String::new());
+        }
+        let addr = u64::from_str_radix(params[1], 16)
+            .with_context(|| format!("Invalid breakpoint address {}", params[1]))?;
+
+        if insert {
+            if self.breakpoints.contains_key(&addr) {
+                return Ok("OK".to_string());
+            }
+            let mut orig = vec![0_u8; SW_BREAKPOINT.len()];
+            self.access_memory(addr, &mut orig, false)?;
+            self.access_memory(addr, &mut SW_BREAKPOINT.to_vec(), true)?;
+            self.breakpoints.insert(addr, orig);
+        } else if let Some(mut orig) = self.breakpoints.remove(&addr) {
+            self.access_memory(addr, &mut orig, true)?;
+        }
+        Ok("OK".to_string())
+    }
+
+    /// Resume the VM, the stop reply is sent when a vcpu stops on breakpoint.
+    fn resume(&mut self) -> Result<()> {
+        let flags = if self.breakpoints.is_empty() {
+            0
+        } else {
+            KVM_GUESTDBG_USE_SW_BP
+        };
+        for cpu in self.cpus.iter() {
+            cpu.set_guest_debug(flags)?;
+            cpu.take_debug_stop();
+        }
+        self.running = true;
+        if !self.vm.lock().unwrap().resume() {
+            warn!("VM is not paused when gdb continues");
+        }
+        self.paused_by_gdb = false;
+        Ok(())
+    }
+
+    /// Execute one instruction on the current vcpu while VM is paused.
+    fn step(&mut self) -> Result<String> {
+        let pc = self.cpus[self.current].step()?;
+        info!("Vcpu{} stepped to 0x{:x}", self.current, pc);
+        Ok(self.stop_reply(GDB_SIGTRAP))
+    }
+
+    /// Vcpus pause themselves on debug exit, pause the VM and report to gdb.
+    fn handle_debug_stop(&mut self) {
+        let mut stopped = Vec::new();
+        for (index, cpu) in self.cpus.iter().enumerate() {
+            if cpu.take_debug_stop() {
+                stopped.push(index);
+            }
+        }
+        if stopped.is_empty() {
+            return;
+        }
+
+        if !self.connected || !self.running {
+            // The stop is out of date, let the vcpus go on.
+            for index in stopped {
+                if let Err(e) = self.cpus[index].resume() {
+                    error!("{:?}", e);
+                }
+            }
+            return;
+        }
+
+        self.running = false;
+        self.paused_by_gdb = self.vm.lock().unwrap().pause();
+        self.current = stopped[0];
+        let reply = self.stop_reply(GDB_SIGTRAP);
+        self.send_packet(&reply);
+    }
+}
+
+/// Gdb stub server, only one gdb connection is allowed at a time.
+pub struct GdbServer {
+    listener: GdbListener,
+    target: Arc<Mutex<GdbTarget>>,
+    /// Notified by vcpus paused on debug exit.
+    debug_evt: Arc<EventFd>,
+}
+
+impl GdbServer {
+    fn accept(&self) -> Option<Vec<EventNotifier>> {
+        let stream = match self.listener.accept() {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Failed to accept gdb connection: {:?}", e);
+                return None;
+            }
+        };
+        let stream_fd = stream.as_raw_fd();
+        info!("Gdb is connected");
+        self.target.lock().unwrap().attach(stream);
+
+        let target = self.target.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |event, fd| {
+            let mut locked_target = target.lock().unwrap();
+            let mut connected = !event.contains(EventSet::HANG_UP);
+            if connected && event.contains(EventSet::IN) {
+                connected = locked_target.handle_input();
+            }
+            if connected {
+                return None;
+            }
+            info!("Gdb is disconnected");
+            locked_target.detach();
+            Some(gen_delete_notifiers(&[fd]))
+        });
+        // The listener is parked until gdb disconnects.
+        Some(vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            stream_fd,
+            Some(self.listener.as_raw_fd()),
+            EventSet::IN | EventSet::HANG_UP,
+            vec![handler],
+        )])
+    }
+}
+
+impl EventNotifierHelper for GdbServer {
+    fn internal_notifiers(server: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+        let locked_server = server.lock().unwrap();
+
+        let cloned_server = server.clone();
+        let handler: Rc<NotifierCallback> =
+            Rc::new(move |_, _| cloned_server.lock().unwrap().accept());
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            locked_server.listener.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        ));
+
+        let target = locked_server.target.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd| {
+            read_fd(fd);
+            target.lock().unwrap().handle_debug_stop();
+            None
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            locked_server.debug_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        ));
+
+        notifiers
+    }
+}
+
+/// Start gdb stub server if it's configured.
+///
+/// # Arguments
+///
+/// * `config` - Listening address of gdb stub server.
+/// * `vm` - The VM to be debugged.
+/// * `cpus` - Vcpus of the VM.
+/// * `sys_mem` - System address space of the VM.
+pub fn gdb_init(
+    config: &Option<GdbConfig>,
+    vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>,
+    cpus: &[Arc<CPU>],
+    sys_mem: &Arc<AddressSpace>,
+) -> Result<()> {
+    let config = match config {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+
+    let listener = match config {
+        GdbConfig::Tcp(addr) => GdbListener::Tcp(
+            TcpListener::bind(addr)
+                .with_context(|| format!("Failed to bind gdb server to {}", addr))?,
+        ),
+        GdbConfig::Unix(path) => {
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to bind gdb server to {}", path))?;
+            TempCleaner::add_path(path.clone());
+            GdbListener::Unix(listener)
+        }
+    };
+    let debug_evt = Arc::new(
+        EventFd::new(libc::EFD_NONBLOCK)
+            .map_err(|e| anyhow!("Failed to create eventfd for gdb server: {:?}", e))?,
+    );
+    for cpu in cpus.iter() {
+        cpu.set_debug_notifier(Some(debug_evt.clone()));
+    }
+
+    let server = GdbServer {
+        listener,
+        target: Arc::new(Mutex::new(GdbTarget::new(
+            vm,
+            cpus.to_vec(),
+            sys_mem.clone(),
+        ))),
+        debug_evt,
+    };
+    EventLoop::update_event(
+        EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(server))),
+        None,
+    )
+    .with_context(|| "Failed to add gdb server to MainLoop")?;
+    info!("Gdb server is listening on {:?}", config);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gdb_packet_codec() {
+        assert_eq!(encode_packet("OK"), b"$OK#9a".to_vec());
+        assert_eq!(encode_packet(""), b"$#00".to_vec());
+        assert_eq!(hex_encode(&[0x12, 0xab, 0x00]), "12ab00");
+        assert_eq!(hex_decode("12ab00").unwrap(), vec![0x12, 0xab, 0x00]);
+        assert!(hex_decode("12a").is_err());
+        assert!(hex_decode("zz").is_err());
+
+        assert_eq!(parse_addr_len("ffff0000,10").unwrap(), (0xffff0000, 16));
+        assert!(parse_addr_len("ffff0000").is_err());
+        assert!(parse_addr_len("ffff0000,100000").is_err());
+    }
+
+    #[test]
+    fn test_parse_gdb_packets() {
+        let mut buf = b"+$g#67\x03$m10,4#".to_vec();
+        assert_eq!(
+            parse_packets(&mut buf),
+            vec![GdbPacket::Command("g".to_string()), GdbPacket::Interrupt]
+        );
+        // Incomplete packet is kept.
+        assert_eq!(buf, b"$m10,4#".to_vec());
+
+        buf.extend_from_slice(b"2e$c#00");
+        assert_eq!(
+            parse_packets(&mut buf),
+            vec![
+                GdbPacket::Command("m10,4".to_string()),
+                GdbPacket::BadChecksum
+            ]
+        );
+        assert!(buf.is_empty());
+    }
+}
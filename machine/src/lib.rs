@@ -10,22 +10,32 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub mod dump;
 pub mod error;
+pub mod gdbstub;
+pub mod metrics;
 mod micro_vm;
 pub mod standard_vm;
+mod vm_clock;
 #[cfg(target_arch = "x86_64")]
 mod vm_state;
 
 pub use crate::error::MachineError;
+use std::cmp::min;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::{remove_file, File};
+use std::io::Write;
 use std::net::TcpListener;
 use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixListener;
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
 
-use log::warn;
+use log::{error, info, warn};
 use util::file::{lock_file, unlock_file};
 
 pub use micro_vm::LightMachine;
@@ -37,26 +47,37 @@ use address_space::{
 };
 pub use anyhow::Result;
 use anyhow::{anyhow, bail, Context};
-#[cfg(target_arch = "aarch64")]
-use cpu::CPUFeatures;
-use cpu::{ArchCPU, CPUBootConfig, CPUInterface, CPUTopology, CPU};
+use cpu::{
+    start_liveness_monitor, ArchCPU, CPUBootConfig, CPUFeatures, CPUInterface, CPUTopology,
+    CpuLifecycleState, CPU,
+};
 use devices::legacy::FwCfgOps;
 #[cfg(target_arch = "aarch64")]
 use devices::InterruptController;
 
 use hypervisor::kvm::KVM_FDS;
+use machine_manager::cmdline::bind_socket;
 use machine_manager::config::{
-    complete_numa_node, get_multi_function, get_pci_bdf, parse_balloon, parse_blk, parse_demo_dev,
-    parse_device_id, parse_fs, parse_net, parse_numa_distance, parse_numa_mem, parse_rng_dev,
-    parse_root_port, parse_scsi_controller, parse_scsi_device, parse_vfio,
-    parse_vhost_user_blk_pci, parse_virtconsole, parse_virtio_serial, parse_vsock, BootIndexInfo,
-    DriveFile, Incoming, MachineMemConfig, MigrateMode, NumaConfig, NumaDistance, NumaNode,
-    NumaNodes, PFlashConfig, PciBdf, SerialConfig, VfioConfig, VmConfig, FAST_UNPLUG_ON,
-    MAX_VIRTIO_QUEUE,
+    complete_numa_node, device_parsers, get_multi_function, get_pci_bdf, parse_balloon, parse_blk,
+    parse_demo_dev, parse_device_id, parse_fs, parse_net, parse_numa_distance, parse_numa_mem,
+    parse_rng_dev, parse_root_port, parse_scsi_controller, parse_scsi_device, parse_vfio,
+    parse_vhost_user_blk_pci, parse_virtconsole, parse_virtio_serial, parse_vsock,
+    scsi_cntlr_auto_queues_num, BootIndexInfo, ChardevConfig, ChardevType, ConfigCheck,
+    ConfigError, DriveFile, Incoming, MachineMemConfig, MachineType, MigrateMode, NumaConfig,
+    NumaDistance, NumaNode, NumaNodes, PFlashConfig, PciBdf, RtcConfig, SerialConfig, VfioConfig,
+    VirtioConsole, VmConfig, FAST_UNPLUG_ON, MAX_VIRTIO_QUEUE, PACKED_RING_ON,
 };
 #[cfg(not(target_env = "musl"))]
-use machine_manager::config::{parse_gpu, parse_usb_keyboard, parse_usb_tablet, parse_xhci};
-use machine_manager::machine::{KvmVmState, MachineInterface};
+use machine_manager::config::{
+    parse_gpu, parse_input, parse_usb_keyboard, parse_usb_tablet, parse_xhci,
+};
+use machine_manager::event_loop::EventLoop;
+use machine_manager::machine::{
+    notify_vm_state, register_vm_state_notifier, set_vm_pause_req, unregister_vm_state_notifier,
+    KvmVmState, MachineExternalInterface, MachineInterface, MachineLifecycle,
+};
+use machine_manager::qmp::qmp_schema;
+use machine_manager::socket::Socket;
 use migration::MigrationManager;
 use pci::{demo_dev::DemoDev, PciBus, PciDevOps, PciHost, RootPort};
 use standard_vm::Result as StdResult;
@@ -67,20 +88,122 @@ use usb::{
     keyboard::UsbKeyboard, tablet::UsbTablet, usb::UsbDeviceOps, xhci::xhci_pci::XhciPciDevice,
 };
 use util::{
+    aio::{io_uring_allow_list, AioEngine},
     arg_parser,
+    loop_context::{
+        read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+    },
     seccomp::{BpfRule, SeccompOpt, SyscallFilter},
+    unix::set_thread_affinity,
 };
 use vfio::{VfioDevice, VfioPciDevice};
-#[cfg(not(target_env = "musl"))]
-use virtio::Gpu;
 use virtio::{
-    balloon_allow_list, vhost, Balloon, Block, BlockState, Console, Rng, RngState, ScsiBus,
-    ScsiCntlr, ScsiDisk, VhostKern, VhostUser, VirtioConsoleState, VirtioDevice, VirtioMmioDevice,
-    VirtioMmioState, VirtioNetState, VirtioPciDevice,
+    balloon_allow_list, set_packed_ring_feature, vhost, Balloon, Block, BlockState, Console, Rng,
+    RngState, ScsiBus, ScsiCntlr, ScsiDisk, VhostKern, VhostUser, VirtioConsoleState, VirtioDevice,
+    VirtioMmioDevice, VirtioMmioState, VirtioNetState, VirtioPciDevice,
 };
+#[cfg(not(target_env = "musl"))]
+use virtio::{Gpu, Input, InputState};
+use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 use ScsiCntlr::ScsiCntlrMap;
 use ScsiDisk::{SCSI_TYPE_DISK, SCSI_TYPE_ROM};
 
+/// Constructor of the device given by `-device`, which takes the config args of the device.
+pub type DeviceConstructor<T> = fn(&mut T, &mut VmConfig, &str) -> Result<()>;
+
+/// Id of the virtio-serial device created for guest agent.
+const GUEST_AGENT_CHANNEL_ID: &str = "guest-agent0";
+/// Port name which guest agents look for, e.g. qemu-guest-agent.
+const GUEST_AGENT_PORT_NAME: &str = "org.qemu.guest_agent.0";
+/// Maximum number of extra QMP control sockets registered at runtime.
+const MAX_CONTROL_SOCKETS: usize = 8;
+/// Size of memory read at a time when saving guest memory to file.
+const MEMSAVE_CHUNK_SIZE: u64 = 1 << 20;
+/// Number of extra QMP control sockets which have been registered.
+static CONTROL_SOCKETS_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Device classes which need extra syscalls out of the basic seccomp whitelist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SeccompDevices {
+    /// Virtio balloon device.
+    pub balloon: bool,
+    /// Block or scsi drive using `io_uring` aio engine.
+    pub io_uring: bool,
+    /// Vhost-user devices.
+    pub vhost_user: bool,
+    /// Live migration through unix or tcp socket, which is only supported by standard VM.
+    pub migration: bool,
+}
+
+impl SeccompDevices {
+    /// Collect the enabled device classes from the configuration of VM.
+    pub fn from_vm_config(vm_config: &VmConfig) -> Self {
+        SeccompDevices {
+            balloon: vm_config.dev_name.get("balloon").is_some(),
+            io_uring: vm_config
+                .drives
+                .values()
+                .any(|drive| drive.aio == AioEngine::IoUring),
+            vhost_user: vm_config
+                .devices
+                .iter()
+                .any(|(dev_type, _)| dev_type.starts_with("vhost-user"))
+                || vm_config
+                    .netdevs
+                    .values()
+                    .any(|netdev| netdev.vhost_type.as_deref() == Some("vhost-user")),
+            migration: vm_config.machine_config.mach_type == MachineType::StandardVm,
+        }
+    }
+}
+
+/// Lifecycle state of a vcpu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VcpuStatus {
+    /// Id of the vcpu.
+    pub id: u8,
+    /// The vcpu is running.
+    pub running: bool,
+    /// The vcpu is paused and its thread has exited kvm.
+    pub paused: bool,
+}
+
+/// Lifecycle state of VM and its vcpus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmStatus {
+    /// Overall state of VM.
+    pub state: KvmVmState,
+    /// State of each vcpu.
+    pub vcpus: Vec<VcpuStatus>,
+}
+
+impl From<VmStatus> for qmp_schema::StatusInfo {
+    fn from(vm_status: VmStatus) -> Self {
+        let status = match vm_status.state {
+            KvmVmState::Created => qmp_schema::RunState::prelaunch,
+            KvmVmState::Running => qmp_schema::RunState::running,
+            KvmVmState::InMigrating => qmp_schema::RunState::inmigrate,
+            KvmVmState::Migrated => qmp_schema::RunState::postmigrate,
+            KvmVmState::Paused => qmp_schema::RunState::paused,
+            KvmVmState::Shutdown => qmp_schema::RunState::shutdown,
+        };
+        qmp_schema::StatusInfo {
+            singlestep: false,
+            running: vm_status.state == KvmVmState::Running,
+            status,
+            vcpus: vm_status
+                .vcpus
+                .iter()
+                .map(|vcpu| qmp_schema::VcpuStatusInfo {
+                    cpu_index: vcpu.id,
+                    running: vcpu.running,
+                    paused: vcpu.paused,
+                })
+                .collect(),
+        }
+    }
+}
+
 pub trait MachineOps {
     /// Calculate the ranges of memory according to architecture.
     ///
@@ -96,7 +219,6 @@ pub trait MachineOps {
 
     fn load_boot_source(&self, fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>) -> Result<CPUBootConfig>;
 
-    #[cfg(target_arch = "aarch64")]
     fn load_cpu_features(&self, vmcfg: &VmConfig) -> Result<CPUFeatures> {
         Ok((&vmcfg.machine_config.cpu_config).into())
     }
@@ -124,7 +246,7 @@ pub trait MachineOps {
         if migrate_info.0 != MigrateMode::File {
             let ram_ranges = self.arch_ram_ranges(mem_config.mem_size);
             mem_mappings = create_host_mmaps(&ram_ranges, mem_config, nr_cpus)
-                .with_context(|| "Failed to mmap guest ram.")?;
+                .with_context(|| anyhow!(MachineError::CrtMemSpaceErr))?;
             set_host_memory_policy(&mem_mappings, &mem_config.mem_zones)
                 .with_context(|| "Failed to set host memory NUMA policy.")?;
         }
@@ -143,9 +265,11 @@ pub trait MachineOps {
             for mmap in mem_mappings.iter() {
                 let base = mmap.start_address().raw_value();
                 let size = mmap.size();
+                let ram_region = Region::init_ram_region(mmap.clone());
+                ram_region.set_name("ram");
                 sys_mem
                     .root()
-                    .add_subregion(Region::init_ram_region(mmap.clone()), base)
+                    .add_subregion(ram_region, base)
                     .with_context(|| anyhow!(MachineError::RegMemRegionErr(base, size)))?;
             }
         }
@@ -162,12 +286,13 @@ pub trait MachineOps {
     /// * `vm` - `MachineInterface` to obtain functions cpu can use.
     /// * `nr_cpus` - The number of vcpus.
     /// * `boot_cfg` - Boot message generated by reading boot source to guest memory.
+    /// * `vcpu_cfg` - Vcpu features set by user.
     fn init_vcpu(
         vm: Arc<Mutex<dyn MachineInterface + Send + Sync>>,
         nr_cpus: u8,
         topology: &CPUTopology,
         boot_cfg: &Option<CPUBootConfig>,
-        #[cfg(target_arch = "aarch64")] vcpu_cfg: &Option<CPUFeatures>,
+        vcpu_cfg: &Option<CPUFeatures>,
     ) -> Result<Vec<Arc<CPU>>>
     where
         Self: Sized,
@@ -192,6 +317,8 @@ pub trait MachineOps {
                 vcpu_id,
                 Arc::new(Mutex::new(arch_cpu)),
                 vm.clone(),
+                #[cfg(target_arch = "x86_64")]
+                vcpu_cfg.clone().unwrap_or_default(),
             ));
             cpus.push(cpu.clone());
 
@@ -226,7 +353,15 @@ pub trait MachineOps {
     fn init_interrupt_controller(&mut self, vcpu_count: u64) -> Result<()>;
 
     /// Add RTC device.
-    fn add_rtc_device(&mut self, #[cfg(target_arch = "x86_64")] mem_size: u64) -> Result<()>;
+    ///
+    /// # Arguments
+    ///
+    /// * `rtc` - RTC configuration.
+    fn add_rtc_device(
+        &mut self,
+        rtc: &RtcConfig,
+        #[cfg(target_arch = "x86_64")] mem_size: u64,
+    ) -> Result<()>;
 
     /// Add Generic event device.
     #[cfg(target_arch = "aarch64")]
@@ -253,9 +388,10 @@ pub trait MachineOps {
     ///
     /// # Arguments
     ///
+    /// * `vm_config` - VM configuration.
     /// * `cfg_args` - Device configuration.
-    fn add_virtio_vsock(&mut self, cfg_args: &str) -> Result<()> {
-        let device_cfg = parse_vsock(cfg_args)?;
+    fn add_virtio_vsock(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_vsock(vm_config, cfg_args)?;
         let sys_mem = self.get_sys_mem().clone();
         let vsock = Arc::new(Mutex::new(VhostKern::Vsock::new(&device_cfg, &sys_mem)));
         if cfg_args.contains("vhost-vsock-device") {
@@ -410,6 +546,67 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Add a virtio-serial device with a single port named `org.qemu.guest_agent.0`, which is
+    /// backed by a unix socket server. The device is plugged to a free slot of `pcie.0` if pci
+    /// is supported, otherwise to virtio-mmio bus. It's called when realizing machine if
+    /// `-guest-agent` is given.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket_path` - Path of the unix socket which host side guest agent tool connects to.
+    fn add_guest_agent_channel(&mut self, socket_path: &str) -> Result<()> {
+        let id = GUEST_AGENT_CHANNEL_ID.to_string();
+        self.check_device_id_existed(&id)?;
+        let device_cfg = VirtioConsole {
+            id: id.clone(),
+            chardev: ChardevConfig {
+                id: format!("{}-chardev", id),
+                backend: ChardevType::Socket {
+                    path: socket_path.to_string(),
+                    server: true,
+                    nowait: true,
+                },
+                tls_creds: None,
+            },
+            name: Some(GUEST_AGENT_PORT_NAME.to_string()),
+        };
+        device_cfg.chardev.check()?;
+        let console = Arc::new(Mutex::new(Console::new(device_cfg)));
+
+        let root_bus = self
+            .get_pci_host()
+            .ok()
+            .map(|pci_host| pci_host.lock().unwrap().root_bus.clone());
+        if let Some(root_bus) = root_bus {
+            let locked_root_bus = root_bus.lock().unwrap();
+            let slot = (1..32_u8)
+                .find(|slot| {
+                    !locked_root_bus
+                        .devices
+                        .keys()
+                        .any(|devfn| devfn >> 3 == *slot)
+                })
+                .with_context(|| {
+                    format!("No free slot on {} for guest agent", locked_root_bus.name)
+                })?;
+            let bdf = PciBdf::new(locked_root_bus.name.clone(), (slot, 0));
+            drop(locked_root_bus);
+            self.add_virtio_pci_device(&id, &bdf, console.clone(), false, false)
+                .with_context(|| "Failed to add guest agent channel")?;
+        } else {
+            let device = VirtioMmioDevice::new(self.get_sys_mem(), console.clone());
+            MigrationManager::register_device_instance(
+                VirtioMmioState::descriptor(),
+                self.realize_virtio_mmio_device(device)
+                    .with_context(|| anyhow!(MachineError::RlzVirtioMmioErr))?,
+                &id,
+            );
+        }
+        MigrationManager::register_device_instance(VirtioConsoleState::descriptor(), console, &id);
+
+        Ok(())
+    }
+
     /// Add virtio-rng device.
     ///
     /// # Arguments
@@ -638,6 +835,7 @@ pub trait MachineOps {
             device_cfg.clone(),
             self.get_drive_files(),
         )));
+        Block::object_init(device.clone());
         let pci_dev = self
             .add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func, false)
             .with_context(|| "Failed to add virtio pci device")?;
@@ -665,11 +863,7 @@ pub trait MachineOps {
     fn add_virtio_pci_scsi(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         let bdf = get_pci_bdf(cfg_args)?;
         let multi_func = get_multi_function(cfg_args)?;
-        let queues_auto = Some(VirtioPciDevice::virtio_pci_auto_queues_num(
-            0,
-            vm_config.machine_config.nr_cpus,
-            MAX_VIRTIO_QUEUE,
-        ));
+        let queues_auto = Some(scsi_cntlr_auto_queues_num(vm_config.machine_config.nr_cpus));
         let device_cfg = parse_scsi_controller(cfg_args, queues_auto)?;
         let device = Arc::new(Mutex::new(ScsiCntlr::ScsiCntlr::new(device_cfg.clone())));
 
@@ -758,13 +952,24 @@ pub trait MachineOps {
         let bdf = get_pci_bdf(cfg_args)?;
         let multi_func = get_multi_function(cfg_args)?;
         let device_cfg = parse_net(vm_config, cfg_args)?;
+        if let Some(bootindex) = device_cfg.boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Fail to add virtio pci net device for invalid bootindex")?;
+        }
         let mut need_irqfd = false;
         let device: Arc<Mutex<dyn VirtioDevice>> = if device_cfg.vhost_type.is_some() {
             if device_cfg.vhost_type == Some(String::from("vhost-kernel")) {
-                Arc::new(Mutex::new(VhostKern::Net::new(
+                let device = Arc::new(Mutex::new(VhostKern::Net::new(
                     &device_cfg,
                     self.get_sys_mem(),
-                )))
+                )));
+                // Registered to make sure both ends of migration agree on vhost.
+                MigrationManager::register_device_instance(
+                    VirtioNetState::descriptor(),
+                    device.clone(),
+                    &device_cfg.id,
+                );
+                device
             } else {
                 need_irqfd = true;
                 Arc::new(Mutex::new(VhostUser::Net::new(
@@ -781,7 +986,14 @@ pub trait MachineOps {
             );
             device
         };
-        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, need_irqfd)?;
+        let pci_dev =
+            self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, need_irqfd)?;
+        if let Some(bootindex) = device_cfg.boot_index {
+            // Eg: OpenFirmware device path(virtio-net): /pci@i0cf8/ethernet@3[,1]
+            if let Some(dev_path) = pci_dev.lock().unwrap().get_dev_path() {
+                self.add_bootindex_devices(bootindex, &dev_path, &device_cfg.id);
+            }
+        }
         self.reset_bus(&device_cfg.id)?;
         Ok(())
     }
@@ -869,6 +1081,34 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Add virtio input device, which is a keyboard, mouse or tablet receiving the
+    /// input events of ui.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Device configuration args.
+    #[cfg(not(target_env = "musl"))]
+    fn add_input_device(&mut self, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_input(cfg_args)?;
+        let input_dev = Arc::new(Mutex::new(Input::new(device_cfg.clone())));
+        if cfg_args.contains("virtio-input-device") {
+            let device = VirtioMmioDevice::new(self.get_sys_mem(), input_dev.clone());
+            self.realize_virtio_mmio_device(device)
+                .with_context(|| "Failed to add virtio mmio input device")?;
+        } else {
+            let bdf = get_pci_bdf(cfg_args)?;
+            let multi_func = get_multi_function(cfg_args)?;
+            self.add_virtio_pci_device(&device_cfg.id, &bdf, input_dev.clone(), multi_func, false)
+                .with_context(|| "Failed to add virtio pci input device")?;
+        }
+        MigrationManager::register_device_instance(
+            InputState::descriptor(),
+            input_dev,
+            &device_cfg.id,
+        );
+        Ok(())
+    }
+
     fn get_devfn_and_parent_bus(&mut self, bdf: &PciBdf) -> StdResult<(u8, Weak<Mutex<PciBus>>)> {
         let pci_host = self.get_pci_host()?;
         let bus = pci_host.lock().unwrap().root_bus.clone();
@@ -987,6 +1227,12 @@ pub trait MachineOps {
             .map_or(false, |val| val == FAST_UNPLUG_ON);
 
         RootPort::set_fast_unplug_feature(fast_unplug);
+
+        let packed = vm_config
+            .global_config
+            .get("virtio.packed")
+            .map_or(false, |val| val == PACKED_RING_ON);
+        set_packed_ring_feature(packed);
         Ok(())
     }
 
@@ -1163,13 +1409,121 @@ pub trait MachineOps {
         Ok(())
     }
 
-    /// Add peripheral devices.
+    /// Get the device types supported by `-device` and the constructor of each of them.
+    /// Machines can override it, every device type must be registered in `device_parsers`
+    /// too, so that dry run validates it.
+    fn device_constructors() -> Vec<(&'static str, DeviceConstructor<Self>)>
+    where
+        Self: Sized,
+    {
+        let constructors: &[(&'static str, DeviceConstructor<Self>)] = &[
+            ("virtio-blk-device", |vm, vm_config, cfg_args| {
+                vm.add_virtio_mmio_block(vm_config, cfg_args)
+            }),
+            ("virtio-blk-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_pci_blk(vm_config, cfg_args)
+            }),
+            ("virtio-scsi-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_pci_scsi(vm_config, cfg_args)
+            }),
+            ("scsi-hd", |vm, vm_config, cfg_args| {
+                vm.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_DISK)
+            }),
+            ("scsi-cd", |vm, vm_config, cfg_args| {
+                vm.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_ROM)
+            }),
+            ("virtio-net-device", |vm, vm_config, cfg_args| {
+                vm.add_virtio_mmio_net(vm_config, cfg_args)
+            }),
+            ("virtio-net-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_pci_net(vm_config, cfg_args)
+            }),
+            ("pcie-root-port", |vm, _, cfg_args| {
+                vm.add_pci_root_port(cfg_args)
+            }),
+            ("vhost-vsock-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_vsock(vm_config, cfg_args)
+            }),
+            ("vhost-vsock-device", |vm, vm_config, cfg_args| {
+                vm.add_virtio_vsock(vm_config, cfg_args)
+            }),
+            ("virtio-balloon-device", |vm, vm_config, cfg_args| {
+                vm.add_virtio_balloon(vm_config, cfg_args)
+            }),
+            ("virtio-balloon-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_balloon(vm_config, cfg_args)
+            }),
+            ("virtio-serial-device", |vm, vm_config, cfg_args| {
+                vm.add_virtio_serial(vm_config, cfg_args)
+            }),
+            ("virtio-serial-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_serial(vm_config, cfg_args)
+            }),
+            ("virtconsole", |vm, vm_config, cfg_args| {
+                vm.add_virtio_console(vm_config, cfg_args)
+            }),
+            ("virtio-rng-device", |vm, vm_config, cfg_args| {
+                vm.add_virtio_rng(vm_config, cfg_args)
+            }),
+            ("virtio-rng-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_rng(vm_config, cfg_args)
+            }),
+            ("vfio-pci", |vm, _, cfg_args| vm.add_vfio_device(cfg_args)),
+            ("vhost-user-blk-pci", |vm, vm_config, cfg_args| {
+                vm.add_vhost_user_blk_pci(vm_config, cfg_args)
+            }),
+            ("vhost-user-fs-pci", |vm, vm_config, cfg_args| {
+                vm.add_virtio_fs(vm_config, cfg_args)
+            }),
+            ("vhost-user-fs-device", |vm, vm_config, cfg_args| {
+                vm.add_virtio_fs(vm_config, cfg_args)
+            }),
+            #[cfg(not(target_env = "musl"))]
+            ("nec-usb-xhci", |vm, _, cfg_args| vm.add_usb_xhci(cfg_args)),
+            #[cfg(not(target_env = "musl"))]
+            ("usb-kbd", |vm, vm_config, cfg_args| {
+                vm.add_usb_keyboard(vm_config, cfg_args)
+            }),
+            #[cfg(not(target_env = "musl"))]
+            ("usb-tablet", |vm, vm_config, cfg_args| {
+                vm.add_usb_tablet(vm_config, cfg_args)
+            }),
+            #[cfg(not(target_env = "musl"))]
+            ("virtio-gpu-pci", |vm, _, cfg_args| {
+                vm.add_virtio_pci_gpu(cfg_args)
+            }),
+            #[cfg(not(target_env = "musl"))]
+            ("virtio-input-device", |vm, _, cfg_args| {
+                vm.add_input_device(cfg_args)
+            }),
+            #[cfg(not(target_env = "musl"))]
+            ("virtio-input-pci", |vm, _, cfg_args| {
+                vm.add_input_device(cfg_args)
+            }),
+            #[cfg(not(target_env = "musl"))]
+            ("ramfb", |vm, _, _| vm.add_ramfb()),
+            ("pvpanic", |vm, vm_config, cfg_args| {
+                vm.add_pvpanic_device(vm_config, cfg_args)
+            }),
+            ("pcie-demo-dev", |vm, vm_config, cfg_args| {
+                vm.add_demo_dev(vm_config, cfg_args)
+            }),
+        ];
+        constructors.to_vec()
+    }
+
+    /// Add peripheral devices, the devices given by `-device` are added in the order of
+    /// command line.
     ///
     /// # Arguments
     ///
     /// * `vm_config` - VM Configuration.
-    fn add_devices(&mut self, vm_config: &mut VmConfig) -> Result<()> {
+    fn add_devices(&mut self, vm_config: &mut VmConfig) -> Result<()>
+    where
+        Self: Sized,
+    {
         self.add_rtc_device(
+            &vm_config.rtc,
             #[cfg(target_arch = "x86_64")]
             vm_config.machine_config.mem_config.mem_size,
         )
@@ -1190,88 +1544,38 @@ pub trait MachineOps {
                 .with_context(|| anyhow!(MachineError::AddDevErr("pflash".to_string())))?;
         }
 
-        for dev in &cloned_vm_config.devices {
-            let cfg_args = dev.1.as_str();
+        let parsers = device_parsers();
+        let constructors = Self::device_constructors();
+        for (dev_type, cfg_args) in &cloned_vm_config.devices {
+            if !parsers.iter().any(|(name, _)| *name == dev_type.as_str()) {
+                return Err(anyhow!(ConfigError::UnknownDeviceType(dev_type.clone())));
+            }
             // Check whether the device id exists to ensure device uniqueness.
             let id = parse_device_id(cfg_args)?;
             self.check_device_id_existed(&id)
                 .with_context(|| format!("Failed to check device id: config {}", cfg_args))?;
-            match dev.0.as_str() {
-                "virtio-blk-device" => {
-                    self.add_virtio_mmio_block(vm_config, cfg_args)?;
-                }
-                "virtio-blk-pci" => {
-                    self.add_virtio_pci_blk(vm_config, cfg_args)?;
-                }
-                "virtio-scsi-pci" => {
-                    self.add_virtio_pci_scsi(vm_config, cfg_args)?;
-                }
-                "scsi-hd" => {
-                    self.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_DISK)?;
-                }
-                "scsi-cd" => {
-                    self.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_ROM)?;
-                }
-                "virtio-net-device" => {
-                    self.add_virtio_mmio_net(vm_config, cfg_args)?;
-                }
-                "virtio-net-pci" => {
-                    self.add_virtio_pci_net(vm_config, cfg_args)?;
-                }
-                "pcie-root-port" => {
-                    self.add_pci_root_port(cfg_args)?;
-                }
-                "vhost-vsock-pci" | "vhost-vsock-device" => {
-                    self.add_virtio_vsock(cfg_args)?;
-                }
-                "virtio-balloon-device" | "virtio-balloon-pci" => {
-                    self.add_virtio_balloon(vm_config, cfg_args)?;
-                }
-                "virtio-serial-device" | "virtio-serial-pci" => {
-                    self.add_virtio_serial(vm_config, cfg_args)?;
-                }
-                "virtconsole" => {
-                    self.add_virtio_console(vm_config, cfg_args)?;
-                }
-                "virtio-rng-device" | "virtio-rng-pci" => {
-                    self.add_virtio_rng(vm_config, cfg_args)?;
-                }
-                "vfio-pci" => {
-                    self.add_vfio_device(cfg_args)?;
-                }
-                "vhost-user-blk-pci" => {
-                    self.add_vhost_user_blk_pci(vm_config, cfg_args)?;
-                }
-                "vhost-user-fs-pci" | "vhost-user-fs-device" => {
-                    self.add_virtio_fs(vm_config, cfg_args)?;
-                }
-                #[cfg(not(target_env = "musl"))]
-                "nec-usb-xhci" => {
-                    self.add_usb_xhci(cfg_args)?;
-                }
-                #[cfg(not(target_env = "musl"))]
-                "usb-kbd" => {
-                    self.add_usb_keyboard(vm_config, cfg_args)?;
-                }
-                #[cfg(not(target_env = "musl"))]
-                "usb-tablet" => {
-                    self.add_usb_tablet(vm_config, cfg_args)?;
-                }
-                #[cfg(not(target_env = "musl"))]
-                "virtio-gpu-pci" => {
-                    self.add_virtio_pci_gpu(cfg_args)?;
-                }
-                #[cfg(not(target_env = "musl"))]
-                "ramfb" => {
-                    self.add_ramfb()?;
-                }
-                "pcie-demo-dev" => {
-                    self.add_demo_dev(vm_config, cfg_args)?;
-                }
-                _ => {
-                    bail!("Unsupported device: {:?}", dev.0.as_str());
-                }
-            }
+            let constructor = constructors
+                .iter()
+                .find(|(name, _)| *name == dev_type.as_str())
+                .map(|(_, constructor)| *constructor)
+                .with_context(|| {
+                    format!(
+                        "Unsupported device: {:?}, supported devices are: {}",
+                        dev_type,
+                        constructors
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    )
+                })?;
+            constructor(self, vm_config, cfg_args)?;
+        }
+
+        // Guest agent channel takes a free slot after the explicit devices are placed.
+        if let Some(socket_path) = cloned_vm_config.guest_agent.as_ref() {
+            self.add_guest_agent_channel(socket_path)
+                .with_context(|| anyhow!(MachineError::AddDevErr("guest agent".to_string())))?;
         }
 
         Ok(())
@@ -1285,6 +1589,16 @@ pub trait MachineOps {
         bail!("ramfb device is not supported!");
     }
 
+    /// Add pvpanic device, which notifies the guest panic to VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
+    fn add_pvpanic_device(&mut self, _vm_config: &mut VmConfig, _cfg_args: &str) -> Result<()> {
+        bail!("pvpanic device is not supported!");
+    }
+
     fn add_demo_dev(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         let bdf = get_pci_bdf(cfg_args)?;
         let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
@@ -1302,12 +1616,30 @@ pub trait MachineOps {
     fn syscall_whitelist(&self) -> Vec<BpfRule>;
 
     /// Register seccomp rules in syscall whitelist to seccomp.
-    fn register_seccomp(&self, balloon_enable: bool) -> Result<()> {
-        let mut seccomp_filter = SyscallFilter::new(SeccompOpt::Trap);
+    ///
+    /// # Arguments
+    ///
+    /// * `devices` - Enabled device classes, whose extra syscalls are merged into the whitelist.
+    /// * `opt` - Action for the syscalls out of the whitelist. With `SeccompOpt::Log`, the
+    ///   syscall is allowed and logged to the kernel audit log with its number and calling thread.
+    fn register_seccomp(&self, devices: SeccompDevices, opt: SeccompOpt) -> Result<()> {
+        if opt == SeccompOpt::Log {
+            warn!("Seccomp works in log mode, syscalls out of the whitelist are not trapped.");
+        }
+        let mut seccomp_filter = SyscallFilter::new(opt);
         let mut bpf_rules = self.syscall_whitelist();
-        if balloon_enable {
+        if devices.balloon {
             balloon_allow_list(&mut bpf_rules);
         }
+        if devices.io_uring {
+            io_uring_allow_list(&mut bpf_rules);
+        }
+        if devices.vhost_user {
+            VhostUser::vhost_user_allow_list(&mut bpf_rules);
+        }
+        if devices.migration {
+            migration_allow_list(&mut bpf_rules);
+        }
 
         if let Ok(cov_enable) = std::env::var("STRATOVIRT_COV") {
             if cov_enable.eq("on") {
@@ -1338,7 +1670,7 @@ pub trait MachineOps {
     fn register_drive_file(&self, path: &str, read_only: bool, direct: bool) -> Result<()> {
         let files = self.get_drive_files();
         let mut drive_files = files.lock().unwrap();
-        VmConfig::add_drive_file(&mut drive_files, path, read_only, direct)?;
+        VmConfig::add_drive_file(&mut drive_files, path, read_only, direct, false)?;
 
         // Lock the added file if VM is running.
         let drive_file = drive_files.get_mut(path).unwrap();
@@ -1363,10 +1695,19 @@ pub trait MachineOps {
     /// Active drive backend files. i.e., Apply lock.
     fn active_drive_files(&self) -> Result<()> {
         for drive_file in self.get_drive_files().lock().unwrap().values_mut() {
-            if drive_file.locked {
+            // The file shared with share-rw is not locked, so that it can be used by other VMs.
+            if drive_file.locked || drive_file.share_rw {
                 continue;
             }
-            lock_file(&drive_file.file, &drive_file.path, drive_file.read_only)?;
+            lock_file(&drive_file.file, &drive_file.path, drive_file.read_only).with_context(
+                || {
+                    format!(
+                        "Failed to lock drive file {}, set share-rw=on to share it with other VMs, \
+                        then the data integrity must be ensured by clustered filesystem in guest",
+                        drive_file.path
+                    )
+                },
+            )?;
             drive_file.locked = true;
         }
         Ok(())
@@ -1401,6 +1742,58 @@ pub trait MachineOps {
     /// * `paused` - Flag for `paused` when `LightMachine` starts to run.
     fn run(&self, paused: bool) -> Result<()>;
 
+    /// Register an extra QMP control socket besides the ones given by `-qmp` and `-mon`.
+    /// Commands received from the socket are handled by the same QMP handler, but async
+    /// events are only sent to the client of `-qmp` or `-mon`. Each socket serves one
+    /// client at a time, and at most `MAX_CONTROL_SOCKETS` sockets can be registered.
+    /// It's called for each `-control-socket` before the seccomp filter is applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm` - The machine structure.
+    /// * `path` - Path of the unix socket file.
+    /// * `read_only` - Only accept `query-*` commands from the socket.
+    fn register_control_socket(vm: &Arc<Mutex<Self>>, path: &Path, read_only: bool) -> Result<()>
+    where
+        Self: MachineExternalInterface + Sized + Send + Sync + 'static,
+    {
+        CONTROL_SOCKETS_NUM
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |num| {
+                if num < MAX_CONTROL_SOCKETS {
+                    Some(num + 1)
+                } else {
+                    None
+                }
+            })
+            .map_err(|_| {
+                anyhow!(
+                    "A maximum of {} control sockets are supported",
+                    MAX_CONTROL_SOCKETS
+                )
+            })?;
+
+        let add_socket = || -> Result<()> {
+            let path = path
+                .to_str()
+                .with_context(|| format!("Invalid control socket path {:?}", path))?;
+            let listener = bind_socket(path.to_string())?;
+            // Accept must never block the main loop.
+            listener
+                .set_nonblocking(true)
+                .with_context(|| "Failed to set control socket nonblocking")?;
+            let socket = Socket::from_control_listener(listener, Some(vm.clone()), read_only);
+            EventLoop::update_event(
+                EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(socket))),
+                None,
+            )
+            .with_context(|| format!("Failed to add control socket {} to MainLoop", path))
+        };
+        add_socket().map_err(|e| {
+            CONTROL_SOCKETS_NUM.fetch_sub(1, Ordering::SeqCst);
+            e
+        })
+    }
+
     /// Start machine as `Running` or `Paused` state.
     ///
     /// # Arguments
@@ -1430,6 +1823,26 @@ pub trait MachineOps {
         }
         cpus_thread_barrier.wait();
 
+        // Vcpu threads have recorded their tid before the barrier.
+        let vcpu_affinity = self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .machine_config
+            .vcpu_affinity
+            .clone();
+        for (vcpu, host_cpus) in vcpu_affinity.iter() {
+            if let Some(cpu) = cpus.get(*vcpu as usize) {
+                set_thread_affinity(cpu.tid(), host_cpus)
+                    .with_context(|| format!("Failed to set affinity for vcpu{}", vcpu))?;
+            }
+        }
+
+        let vcpu_liveness = self.get_vm_config().lock().unwrap().vcpu_liveness;
+        if let Some(liveness) = vcpu_liveness {
+            start_liveness_monitor(cpus.to_vec(), Duration::from_secs(liveness.window))?;
+        }
+
         Ok(())
     }
 
@@ -1458,6 +1871,10 @@ pub trait MachineOps {
         // SAFETY: ARM architecture must have interrupt controllers in user mode.
         irq_chip.as_ref().unwrap().stop();
 
+        if let Err(e) = vm_clock::save_guest_clock(cpus) {
+            warn!("Guest time may leap forward on resume: {:?}", e);
+        }
+
         *vm_state = KvmVmState::Paused;
 
         Ok(())
@@ -1470,6 +1887,10 @@ pub trait MachineOps {
     /// * `cpus` - Cpus vector restore cpu structure.
     /// * `vm_state` - Vm kvm vm state.
     fn vm_resume(&self, cpus: &[Arc<CPU>], vm_state: &mut KvmVmState) -> Result<()> {
+        if let Err(e) = vm_clock::restore_guest_clock(cpus) {
+            warn!("Guest time may leap forward by the paused time: {:?}", e);
+        }
+
         self.active_drive_files()?;
 
         for (cpu_index, cpu) in cpus.iter().enumerate() {
@@ -1484,16 +1905,147 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Query lifecycle state of VM and its vcpus. It only reads the states,
+    /// so it's safe to be called in any state.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpus` - Cpus vector restore cpu structure.
+    fn query_vm_status(&self, cpus: &[Arc<CPU>]) -> VmStatus {
+        let state = *self.get_vm_state().deref().0.lock().unwrap();
+        let vcpus = cpus
+            .iter()
+            .map(|cpu| {
+                let cpu_state = *cpu.state().0.lock().unwrap();
+                VcpuStatus {
+                    id: cpu.id(),
+                    running: cpu_state == CpuLifecycleState::Running,
+                    paused: cpu_state == CpuLifecycleState::Paused && cpu.is_paused(),
+                }
+            })
+            .collect();
+
+        VmStatus { state, vcpus }
+    }
+
+    /// Execute one guest instruction on a vcpu while VM is paused. Returns
+    /// the new program counter of the vcpu.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpus` - Cpus vector restore cpu structure.
+    /// * `cpu_index` - Index of the vcpu to step.
+    fn step_vcpu(&self, cpus: &[Arc<CPU>], cpu_index: u8) -> Result<u64> {
+        if *self.get_vm_state().deref().0.lock().unwrap() != KvmVmState::Paused {
+            bail!("Vcpu can only be stepped when VM is paused");
+        }
+        let cpu = cpus
+            .iter()
+            .find(|cpu| cpu.id() == cpu_index)
+            .with_context(|| format!("Invalid cpu index {}", cpu_index))?;
+        cpu.step()
+    }
+
+    /// Save guest physical memory to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Memory address space.
+    /// * `addr` - Start guest physical address.
+    /// * `size` - Size of memory to save.
+    /// * `filename` - Path of the file, it is truncated if it exists.
+    fn save_memory(
+        &self,
+        sys_mem: &Arc<AddressSpace>,
+        addr: u64,
+        size: u64,
+        filename: &str,
+    ) -> Result<()> {
+        let mut file =
+            File::create(filename).with_context(|| format!("Failed to create {}", filename))?;
+        let mut buf = vec![0_u8; min(size, MEMSAVE_CHUNK_SIZE) as usize];
+        let mut offset = 0;
+        while offset < size {
+            let len = min(size - offset, MEMSAVE_CHUNK_SIZE) as usize;
+            let ret = sys_mem
+                .read_bytes(addr.wrapping_add(offset), &mut buf[..len])
+                .and_then(|_| {
+                    file.write_all(&buf[..len])
+                        .with_context(|| format!("Failed to write {}", filename))
+                });
+            if let Err(e) = ret {
+                drop(file);
+                if let Err(err) = remove_file(filename) {
+                    warn!("Failed to remove {}: {:?}", filename, err);
+                }
+                return Err(e);
+            }
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Dump guest memory and vcpu registers to an ELF core file.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Memory address space.
+    /// * `cpus` - Cpus vector restore cpu structure.
+    /// * `filename` - Path of the file, it is truncated if it exists.
+    fn dump_guest_core(
+        &self,
+        sys_mem: &Arc<AddressSpace>,
+        cpus: &[Arc<CPU>],
+        filename: &str,
+    ) -> Result<()> {
+        if *self.get_vm_state().deref().0.lock().unwrap() != KvmVmState::Paused {
+            bail!("Guest memory can only be dumped when VM is paused");
+        }
+        let mut file =
+            File::create(filename).with_context(|| format!("Failed to create {}", filename))?;
+        if let Err(e) = dump::dump_guest_memory(sys_mem, cpus, &mut file) {
+            drop(file);
+            if let Err(err) = remove_file(filename) {
+                warn!("Failed to remove {}: {:?}", filename, err);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
     /// Destroy VM as `Shutdown` state, destroy vcpu thread.
     ///
+    /// # Notes
+    ///
+    /// All vcpus share the `destroy-timeout` of machine config. A vcpu which
+    /// doesn't stop in time is reported and left to be reaped at process exit,
+    /// so that a vcpu stuck in kvm can't block shutdown.
+    ///
+    /// Every vcpu is tried even if destroying a former one fails, and the failures
+    /// are only logged. `Ok` is always returned with the state set to `Shutdown`,
+    /// because the callers skip flushing devices and waking up the main loop on
+    /// error, which would leave the VM half shut down.
+    ///
     /// # Arguments
     ///
     /// * `cpus` - Cpus vector restore cpu structure.
     /// * `vm_state` - Vm kvm vm state.
     fn vm_destroy(&self, cpus: &[Arc<CPU>], vm_state: &mut KvmVmState) -> Result<()> {
+        let timeout = self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .machine_config
+            .destroy_timeout;
+        let deadline = Instant::now() + Duration::from_millis(timeout);
         for (cpu_index, cpu) in cpus.iter().enumerate() {
-            cpu.destroy()
-                .with_context(|| format!("Failed to destroy vcpu{}", cpu_index))?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Err(e) = cpu.destroy_timeout(remaining) {
+                error!(
+                    "{:?}",
+                    e.context(MachineError::DestroyVcpuErr(cpu_index as u8))
+                );
+            }
         }
 
         *vm_state = KvmVmState::Shutdown;
@@ -1553,6 +2105,7 @@ pub trait MachineOps {
                 new_state
             );
         }
+        notify_vm_state(new_state);
 
         Ok(())
     }
@@ -1581,6 +2134,108 @@ pub fn vm_run(
     Ok(())
 }
 
+/// Register the eventfd for devices to request pausing the VM, e.g. block device
+/// stops the VM on write error with `werror=stop`. The request is handled in main loop.
+///
+/// # Arguments
+///
+/// * `vm` - virtual machine to be paused.
+pub fn register_vm_pause_event(vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>) -> Result<()> {
+    let pause_req = Arc::new(
+        EventFd::new(libc::EFD_NONBLOCK)
+            .with_context(|| anyhow!(MachineError::InitEventFdErr("pause_req".to_string())))?,
+    );
+    let pause_req_fd = pause_req.as_raw_fd();
+    let pause_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+        read_fd(pause_req_fd);
+        // The VM may have been paused by another request.
+        if !vm.lock().unwrap().pause() {
+            warn!("VM is not paused by device request");
+        }
+        None
+    });
+    let notifier = EventNotifier::new(
+        NotifierOperation::AddShared,
+        pause_req_fd,
+        None,
+        EventSet::IN,
+        vec![pause_req_handler],
+    );
+    EventLoop::update_event(vec![notifier], None)
+        .with_context(|| "Failed to register event notifier for pause request.")?;
+    set_vm_pause_req(pause_req);
+    Ok(())
+}
+
+/// Id of the VM state notifier which starts hot plugging the devices in manifest.
+const HOTPLUG_MANIFEST_NOTIFIER: &str = "hotplug-manifest";
+
+/// Hot plug the devices in hotplug manifest once the VM is running for the first time.
+/// The devices are plugged one by one in main loop through QMP command `device_add`,
+/// and the failure of a device is reported without aborting the others.
+///
+/// # Arguments
+///
+/// * `vm` - virtual machine which the devices are plugged into.
+/// * `devices` - The arguments of `device_add` for each device.
+pub fn register_hotplug_manifest(
+    vm: Arc<Mutex<dyn MachineExternalInterface + Send + Sync>>,
+    devices: Vec<qmp_schema::DeviceAddArgument>,
+) -> Result<()> {
+    if devices.is_empty() {
+        return Ok(());
+    }
+
+    let hotplug_evt = Arc::new(
+        EventFd::new(libc::EFD_NONBLOCK)
+            .with_context(|| anyhow!(MachineError::InitEventFdErr("hotplug_evt".to_string())))?,
+    );
+    let hotplug_fd = hotplug_evt.as_raw_fd();
+    let devices = Mutex::new(devices);
+    let cloned_evt = hotplug_evt.clone();
+    let hotplug_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+        read_fd(cloned_evt.as_raw_fd());
+        unregister_vm_state_notifier(HOTPLUG_MANIFEST_NOTIFIER);
+        for device in std::mem::take(&mut *devices.lock().unwrap()) {
+            let id = device.id.clone();
+            let resp = vm.lock().unwrap().device_add(Box::new(device));
+            match resp.error_desc() {
+                Some(desc) => error!("Failed to hot plug device {} in manifest: {}", id, desc),
+                None => info!("Device {} in manifest is hot plugged", id),
+            }
+        }
+        Some(vec![EventNotifier::new(
+            NotifierOperation::Delete,
+            cloned_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            Vec::new(),
+        )])
+    });
+    let notifier = EventNotifier::new(
+        NotifierOperation::AddShared,
+        hotplug_fd,
+        None,
+        EventSet::IN,
+        vec![hotplug_handler],
+    );
+    EventLoop::update_event(vec![notifier], None)
+        .with_context(|| "Failed to register event notifier for hotplug manifest.")?;
+
+    register_vm_state_notifier(
+        HOTPLUG_MANIFEST_NOTIFIER,
+        Arc::new(move |state: KvmVmState| {
+            if state != KvmVmState::Running {
+                return;
+            }
+            if let Err(e) = hotplug_evt.write(1) {
+                error!("Failed to write event for hotplug manifest: {:?}", e);
+            }
+        }),
+    );
+    Ok(())
+}
+
 /// Start incoming migration from destination.
 fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Result<()> {
     let (mode, path) = vm.lock().unwrap().get_migrate_info();
@@ -1635,9 +2290,54 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
     Ok(())
 }
 
+fn migration_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
+    syscall_allow_list.extend(vec![
+        BpfRule::new(libc::SYS_socket),
+        BpfRule::new(libc::SYS_connect),
+    ])
+}
+
 fn coverage_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
     syscall_allow_list.extend(vec![
         BpfRule::new(libc::SYS_fcntl),
         BpfRule::new(libc::SYS_ftruncate),
     ])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_constructors_registered() {
+        let parsers = device_parsers();
+        let constructors = StdMachine::device_constructors();
+        let mut parser_names = parsers.iter().map(|(name, _)| *name).collect::<Vec<&str>>();
+        let mut constructor_names = constructors
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<&str>>();
+        parser_names.sort_unstable();
+        constructor_names.sort_unstable();
+        assert_eq!(parser_names, constructor_names);
+
+        // Dry run knows every device type the machine can create.
+        for name in constructor_names {
+            let mut vm_config = VmConfig::default();
+            vm_config.machine_config.mach_type = MachineType::StandardVm;
+            vm_config
+                .add_chardev("socket,id=test_console,path=/path/to/socket,server,nowait")
+                .unwrap();
+            vm_config.add_device(&format!("{},id=dev0", name)).unwrap();
+            if let Err(e) = vm_config.validate() {
+                let unknown = e.chain().any(|cause| {
+                    matches!(
+                        cause.downcast_ref::<ConfigError>(),
+                        Some(ConfigError::UnknownDeviceType(_))
+                    )
+                });
+                assert!(!unknown, "device type {} is not known to dry run", name);
+            }
+        }
+    }
+}
@@ -0,0 +1,815 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # Metrics
+//!
+//! Serve the statistics of VM in prometheus text exposition format over a
+//! minimal read-only http server, e.g. `curl http://127.0.0.1:9100/metrics`.
+//! Exported metrics are cpu time and exits of vcpus, IO of block and network
+//! devices, and the actual memory size of balloon.
+
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use vmm_sys_util::epoll::EventSet;
+
+use cpu::{thread_cpu_time, VcpuExitReason, CPU};
+use machine_manager::config::MetricsConfig;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::machine::{get_block_stats, get_net_stats, NetDeviceStats};
+use machine_manager::qmp::qmp_schema::BlockStatsInfo;
+use machine_manager::temp_cleaner::TempCleaner;
+use util::loop_context::{
+    gen_delete_notifiers, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use util::time::NANOSECONDS_PER_SECOND;
+
+/// Max size of http request header, larger requests are rejected.
+const MAX_REQUEST_SIZE: usize = 8192;
+/// Max number of connections served at the same time, more connections are closed at once.
+const MAX_CONNECTIONS: usize = 16;
+/// Deadline of a connection in seconds, it's closed if the response is not sent in time.
+const CONNECTION_TIMEOUT: u64 = 10;
+/// Content type of prometheus text exposition format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Statistics of a vcpu.
+struct VcpuMetrics {
+    id: u8,
+    /// Cpu time of vcpu thread, None if the thread is not started.
+    time: Option<Duration>,
+    exits: Vec<(VcpuExitReason, u64)>,
+}
+
+/// Statistics of VM collected for one scrape.
+#[derive(Default)]
+struct MetricsSnapshot {
+    vcpus: Vec<VcpuMetrics>,
+    blocks: Vec<BlockStatsInfo>,
+    nets: Vec<(String, NetDeviceStats)>,
+    /// Actual memory size of balloon in bytes, None if balloon is not configured.
+    balloon: Option<u64>,
+}
+
+impl MetricsSnapshot {
+    fn collect(cpus: &[Arc<CPU>]) -> Self {
+        let vcpus = cpus
+            .iter()
+            .map(|cpu| {
+                let tid = cpu.tid();
+                VcpuMetrics {
+                    id: cpu.id(),
+                    time: if tid == 0 {
+                        None
+                    } else {
+                        thread_cpu_time(tid).ok()
+                    },
+                    exits: cpu.exit_stats().get_info(),
+                }
+            })
+            .collect();
+        MetricsSnapshot {
+            vcpus,
+            blocks: get_block_stats(),
+            nets: get_net_stats(),
+            balloon: virtio::qmp_query_balloon(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        let samples = self
+            .vcpus
+            .iter()
+            .filter_map(|vcpu| {
+                let time = vcpu.time?;
+                let id = vcpu.id.to_string();
+                Some((
+                    labels(&[("vcpu", id.as_str())]),
+                    format!("{}", time.as_secs_f64()),
+                ))
+            })
+            .collect::<Vec<_>>();
+        write_family(
+            &mut out,
+            "stratovirt_vcpu_seconds_total",
+            "Cpu time consumed by vcpu thread in seconds.",
+            "counter",
+            &samples,
+        );
+
+        let mut samples = Vec::new();
+        for vcpu in self.vcpus.iter() {
+            let id = vcpu.id.to_string();
+            for (reason, count) in vcpu.exits.iter() {
+                samples.push((
+                    labels(&[("vcpu", id.as_str()), ("reason", reason.as_str())]),
+                    count.to_string(),
+                ));
+            }
+        }
+        write_family(
+            &mut out,
+            "stratovirt_vcpu_exits_total",
+            "Number of vcpu exits from kvm by reason.",
+            "counter",
+            &samples,
+        );
+
+        let mut bytes = Vec::new();
+        let mut ops = Vec::new();
+        let mut failed_ops = Vec::new();
+        for block in self.blocks.iter() {
+            let stats = &block.stats;
+            for (op, nbytes, count, failed) in [
+                (
+                    "read",
+                    Some(stats.rd_bytes),
+                    stats.rd_operations,
+                    stats.failed_rd_operations,
+                ),
+                (
+                    "write",
+                    Some(stats.wr_bytes),
+                    stats.wr_operations,
+                    stats.failed_wr_operations,
+                ),
+                (
+                    "flush",
+                    None,
+                    stats.flush_operations,
+                    stats.failed_flush_operations,
+                ),
+            ] {
+                let label = labels(&[("device", block.device.as_str()), ("operation", op)]);
+                if let Some(nbytes) = nbytes {
+                    bytes.push((label.clone(), nbytes.to_string()));
+                }
+                ops.push((label.clone(), count.to_string()));
+                failed_ops.push((label, failed.to_string()));
+            }
+        }
+        write_family(
+            &mut out,
+            "stratovirt_block_bytes_total",
+            "Bytes transferred by block device.",
+            "counter",
+            &bytes,
+        );
+        write_family(
+            &mut out,
+            "stratovirt_block_operations_total",
+            "Number of succeeded requests of block device.",
+            "counter",
+            &ops,
+        );
+        write_family(
+            &mut out,
+            "stratovirt_block_failed_operations_total",
+            "Number of failed requests of block device.",
+            "counter",
+            &failed_ops,
+        );
+
+        let mut bytes = Vec::new();
+        let mut packets = Vec::new();
+        for (id, stats) in self.nets.iter() {
+            for (direction, nbytes, count) in [
+                ("rx", stats.rx_bytes, stats.rx_packets),
+                ("tx", stats.tx_bytes, stats.tx_packets),
+            ] {
+                let label = labels(&[("device", id.as_str()), ("direction", direction)]);
+                bytes.push((label.clone(), nbytes.to_string()));
+                packets.push((label, count.to_string()));
+            }
+        }
+        write_family(
+            &mut out,
+            "stratovirt_net_bytes_total",
+            "Bytes of packets transferred by network device, excluding virtio header.",
+            "counter",
+            &bytes,
+        );
+        write_family(
+            &mut out,
+            "stratovirt_net_packets_total",
+            "Number of packets transferred by network device.",
+            "counter",
+            &packets,
+        );
+
+        if let Some(actual) = self.balloon {
+            write_family(
+                &mut out,
+                "stratovirt_balloon_actual_bytes",
+                "Actual memory size of guest with balloon in bytes.",
+                "gauge",
+                &[(String::new(), actual.to_string())],
+            );
+        }
+
+        out
+    }
+}
+
+/// Render labels as `{name="value",...}`, with the value escaped.
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+/// Write a metric family. Families without samples are skipped.
+fn write_family(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    kind: &str,
+    samples: &[(String, String)],
+) {
+    if samples.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples.iter() {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// Build http response for the request header, return None if the header is incomplete.
+fn handle_request(request: &[u8], cpus: &[Arc<CPU>]) -> Option<String> {
+    let end = request.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let header = String::from_utf8_lossy(&request[..end]);
+    let request_line = header.lines().next().unwrap_or_default();
+    let mut fields = request_line.split_whitespace();
+    let method = fields.next().unwrap_or_default();
+    let path = fields.next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", MetricsSnapshot::collect(cpus).render()),
+        ("GET", _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    Some(http_response(status, &body))
+}
+
+fn http_response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        METRICS_CONTENT_TYPE,
+        body.len(),
+        body
+    )
+}
+
+/// Listener of metrics server.
+enum MetricsListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl MetricsListener {
+    fn accept(&self) -> Result<MetricsStream> {
+        let stream = match self {
+            MetricsListener::Tcp(listener) => {
+                let stream = listener.accept()?.0;
+                stream.set_nonblocking(true)?;
+                MetricsStream::Tcp(stream)
+            }
+            MetricsListener::Unix(listener) => {
+                let stream = listener.accept()?.0;
+                stream.set_nonblocking(true)?;
+                MetricsStream::Unix(stream)
+            }
+        };
+        Ok(stream)
+    }
+}
+
+impl AsRawFd for MetricsListener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            MetricsListener::Tcp(listener) => listener.as_raw_fd(),
+            MetricsListener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// Connection with metrics scraper.
+enum MetricsStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl MetricsStream {
+    fn shutdown(&self) {
+        let ret = match self {
+            MetricsStream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            MetricsStream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+        if let Err(e) = ret {
+            warn!("Failed to shutdown metrics connection: {:?}", e);
+        }
+    }
+}
+
+impl Read for MetricsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            MetricsStream::Tcp(stream) => stream.read(buf),
+            MetricsStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for MetricsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            MetricsStream::Tcp(stream) => stream.write(buf),
+            MetricsStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            MetricsStream::Tcp(stream) => stream.flush(),
+            MetricsStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for MetricsStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            MetricsStream::Tcp(stream) => stream.as_raw_fd(),
+            MetricsStream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+/// State of a metrics connection after handling events.
+#[derive(Debug, PartialEq, Eq)]
+enum ConnectionState {
+    /// The request is not received completely.
+    Reading,
+    /// The response is not sent completely.
+    Writing,
+    Closed,
+}
+
+/// A connection which serves one request.
+struct MetricsConnection {
+    stream: MetricsStream,
+    /// Received data of the request.
+    buf: Vec<u8>,
+    /// Response to be sent.
+    response: Vec<u8>,
+    /// Length of the response which has been sent.
+    written: usize,
+    /// The connection has been closed by the client, the server or the deadline.
+    closed: bool,
+}
+
+impl MetricsConnection {
+    fn new(stream: MetricsStream) -> Self {
+        MetricsConnection {
+            stream,
+            buf: Vec::new(),
+            response: Vec::new(),
+            written: 0,
+            closed: false,
+        }
+    }
+
+    /// Shutdown the connection, return false if it has been closed.
+    fn close(&mut self) -> bool {
+        if self.closed {
+            return false;
+        }
+        self.closed = true;
+        self.stream.shutdown();
+        true
+    }
+
+    /// Handle the received data, and start to send the response once the request is complete.
+    fn handle_input(&mut self, cpus: &[Arc<CPU>]) -> ConnectionState {
+        let mut data = [0_u8; 1024];
+        let len = match self.stream.read(&mut data) {
+            Ok(0) => return ConnectionState::Closed,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted || e.kind() == ErrorKind::WouldBlock => {
+                return ConnectionState::Reading
+            }
+            Err(e) => {
+                warn!("Failed to read from metrics connection: {:?}", e);
+                return ConnectionState::Closed;
+            }
+        };
+        self.buf.extend_from_slice(&data[..len]);
+
+        let response = match handle_request(&self.buf, cpus) {
+            Some(response) => response,
+            None if self.buf.len() > MAX_REQUEST_SIZE => {
+                http_response("431 Request Header Fields Too Large", "")
+            }
+            None => return ConnectionState::Reading,
+        };
+        self.response = response.into_bytes();
+        self.handle_output()
+    }
+
+    /// Send the pending response as much as the socket accepts.
+    fn handle_output(&mut self) -> ConnectionState {
+        while self.written < self.response.len() {
+            match self.stream.write(&self.response[self.written..]) {
+                Ok(0) => return ConnectionState::Closed,
+                Ok(len) => self.written += len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return ConnectionState::Writing,
+                Err(e) => {
+                    warn!("Failed to write to metrics connection: {:?}", e);
+                    return ConnectionState::Closed;
+                }
+            }
+        }
+        ConnectionState::Closed
+    }
+}
+
+/// Create the notifier of a metrics connection, which waits for the events of `state`.
+fn connection_notifier(
+    conn: Arc<Mutex<MetricsConnection>>,
+    cpus: Vec<Arc<CPU>>,
+    conn_num: Arc<AtomicUsize>,
+    state: ConnectionState,
+) -> EventNotifier {
+    let stream_fd = conn.lock().unwrap().stream.as_raw_fd();
+    let event = match state {
+        ConnectionState::Writing => EventSet::OUT | EventSet::HANG_UP,
+        _ => EventSet::IN | EventSet::HANG_UP,
+    };
+
+    let handler: Rc<NotifierCallback> = Rc::new(move |event, fd| {
+        let mut locked_conn = conn.lock().unwrap();
+        // The connection missed the deadline and has been removed.
+        if locked_conn.closed {
+            return None;
+        }
+        let new_state = if event.contains(EventSet::HANG_UP) {
+            ConnectionState::Closed
+        } else if event.contains(EventSet::IN) {
+            locked_conn.handle_input(&cpus)
+        } else if event.contains(EventSet::OUT) {
+            locked_conn.handle_output()
+        } else {
+            return None;
+        };
+
+        match new_state {
+            ConnectionState::Reading => None,
+            ConnectionState::Writing if event.contains(EventSet::OUT) => None,
+            ConnectionState::Writing => {
+                // Wait for the socket to be writable to send the rest of response.
+                drop(locked_conn);
+                let mut notifiers = gen_delete_notifiers(&[fd]);
+                notifiers.push(connection_notifier(
+                    conn.clone(),
+                    cpus.clone(),
+                    conn_num.clone(),
+                    ConnectionState::Writing,
+                ));
+                Some(notifiers)
+            }
+            ConnectionState::Closed => {
+                locked_conn.close();
+                conn_num.fetch_sub(1, Ordering::SeqCst);
+                Some(gen_delete_notifiers(&[fd]))
+            }
+        }
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        stream_fd,
+        None,
+        event,
+        vec![handler],
+    )
+}
+
+/// Close the connection which misses the deadline, and remove it from main loop.
+fn connection_timeout(conn: &Weak<Mutex<MetricsConnection>>, conn_num: &AtomicUsize) {
+    // The connection is dropped if it has been closed and removed.
+    let conn = match conn.upgrade() {
+        Some(conn) => conn,
+        None => return,
+    };
+    let mut locked_conn = conn.lock().unwrap();
+    if !locked_conn.close() {
+        return;
+    }
+    warn!(
+        "Close metrics connection: response is not sent in {} seconds",
+        CONNECTION_TIMEOUT
+    );
+    conn_num.fetch_sub(1, Ordering::SeqCst);
+    let stream_fd = locked_conn.stream.as_raw_fd();
+    drop(locked_conn);
+    if let Err(e) = EventLoop::update_event(gen_delete_notifiers(&[stream_fd]), None) {
+        error!("Failed to remove metrics connection: {:?}", e);
+    }
+}
+
+/// Metrics server, which serves each connection with one response.
+pub struct MetricsServer {
+    listener: MetricsListener,
+    cpus: Vec<Arc<CPU>>,
+    /// Number of connections being served.
+    conn_num: Arc<AtomicUsize>,
+}
+
+impl MetricsServer {
+    fn accept(&self) -> Option<Vec<EventNotifier>> {
+        let stream = match self.listener.accept() {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Failed to accept metrics connection: {:?}", e);
+                return None;
+            }
+        };
+        if self.conn_num.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+            warn!(
+                "Too many metrics connections, the max number is {}",
+                MAX_CONNECTIONS
+            );
+            stream.shutdown();
+            return None;
+        }
+        let ctx = match EventLoop::get_ctx(None) {
+            Some(ctx) => ctx,
+            None => {
+                error!("Reject metrics connection: main loop is not found");
+                stream.shutdown();
+                return None;
+            }
+        };
+        self.conn_num.fetch_add(1, Ordering::SeqCst);
+
+        let conn = Arc::new(Mutex::new(MetricsConnection::new(stream)));
+        let weak_conn = Arc::downgrade(&conn);
+        let conn_num = self.conn_num.clone();
+        let func = Box::new(move || connection_timeout(&weak_conn, &conn_num));
+        ctx.delay_call(func, CONNECTION_TIMEOUT * NANOSECONDS_PER_SECOND);
+
+        Some(vec![connection_notifier(
+            conn,
+            self.cpus.clone(),
+            self.conn_num.clone(),
+            ConnectionState::Reading,
+        )])
+    }
+}
+
+impl EventNotifierHelper for MetricsServer {
+    fn internal_notifiers(server: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let listener_fd = server.lock().unwrap().listener.as_raw_fd();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, _| server.lock().unwrap().accept());
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            listener_fd,
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+/// Start metrics server if it's configured.
+///
+/// # Arguments
+///
+/// * `config` - Listening address of metrics server.
+/// * `cpus` - Vcpus of the VM.
+pub fn metrics_init(config: &Option<MetricsConfig>, cpus: &[Arc<CPU>]) -> Result<()> {
+    let config = match config {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+
+    let listener = match config {
+        MetricsConfig::Tcp(addr) => MetricsListener::Tcp(
+            TcpListener::bind(addr)
+                .with_context(|| format!("Failed to bind metrics server to {}", addr))?,
+        ),
+        MetricsConfig::Unix(path) => {
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to bind metrics server to {}", path))?;
+            TempCleaner::add_path(path.clone());
+            MetricsListener::Unix(listener)
+        }
+    };
+
+    let server = MetricsServer {
+        listener,
+        cpus: cpus.to_vec(),
+        conn_num: Arc::new(AtomicUsize::new(0)),
+    };
+    EventLoop::update_event(
+        EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(server))),
+        None,
+    )
+    .with_context(|| "Failed to add metrics server to MainLoop")?;
+    info!("Metrics server is listening on {:?}", config);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_manager::qmp::qmp_schema::BlockDeviceStats;
+
+    #[test]
+    fn test_render_metrics() {
+        let snapshot = MetricsSnapshot {
+            vcpus: vec![
+                VcpuMetrics {
+                    id: 0,
+                    time: Some(Duration::from_millis(1500)),
+                    exits: vec![(VcpuExitReason::MmioWrite, 3), (VcpuExitReason::Hlt, 1)],
+                },
+                VcpuMetrics {
+                    id: 1,
+                    time: None,
+                    exits: vec![(VcpuExitReason::MmioWrite, 0)],
+                },
+            ],
+            blocks: vec![BlockStatsInfo {
+                device: "drive-0".to_string(),
+                stats: BlockDeviceStats {
+                    rd_bytes: 4096,
+                    rd_operations: 1,
+                    failed_flush_operations: 2,
+                    ..Default::default()
+                },
+            }],
+            nets: vec![(
+                "net\"0".to_string(),
+                NetDeviceStats {
+                    rx_bytes: 60,
+                    rx_packets: 1,
+                    ..Default::default()
+                },
+            )],
+            balloon: None,
+        };
+        let text = snapshot.render();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert!(lines.contains(&"# TYPE stratovirt_vcpu_seconds_total counter"));
+        assert!(lines.contains(&"stratovirt_vcpu_seconds_total{vcpu=\"0\"} 1.5"));
+        assert!(!text.contains("stratovirt_vcpu_seconds_total{vcpu=\"1\"}"));
+        assert!(lines.contains(&"stratovirt_vcpu_exits_total{vcpu=\"0\",reason=\"mmio_write\"} 3"));
+        assert!(lines.contains(&"stratovirt_vcpu_exits_total{vcpu=\"1\",reason=\"mmio_write\"} 0"));
+        assert!(lines
+            .contains(&"stratovirt_block_bytes_total{device=\"drive-0\",operation=\"read\"} 4096"));
+        assert!(
+            !text.contains("stratovirt_block_bytes_total{device=\"drive-0\",operation=\"flush\"}")
+        );
+        assert!(lines.contains(
+            &"stratovirt_block_failed_operations_total{device=\"drive-0\",operation=\"flush\"} 2"
+        ));
+        assert!(
+            lines.contains(&"stratovirt_net_bytes_total{device=\"net\\\"0\",direction=\"rx\"} 60")
+        );
+        assert!(
+            lines.contains(&"stratovirt_net_packets_total{device=\"net\\\"0\",direction=\"tx\"} 0")
+        );
+        assert!(!text.contains("stratovirt_balloon_actual_bytes"));
+
+        let snapshot = MetricsSnapshot {
+            balloon: Some(1 << 30),
+            ..Default::default()
+        };
+        assert_eq!(
+            snapshot.render(),
+            "# HELP stratovirt_balloon_actual_bytes Actual memory size of guest with balloon \
+             in bytes.\n# TYPE stratovirt_balloon_actual_bytes gauge\n\
+             stratovirt_balloon_actual_bytes 1073741824\n"
+        );
+    }
+
+    #[test]
+    fn test_handle_metrics_request() {
+        assert!(handle_request(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n", &[]).is_none());
+
+        let response =
+            handle_request(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n", &[]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+
+        let response = handle_request(b"GET / HTTP/1.1\r\n\r\n", &[]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("Content-Length: 10\r\n"));
+
+        let response = handle_request(b"POST /metrics HTTP/1.1\r\n\r\n", &[]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn test_metrics_connection() {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut conn = MetricsConnection::new(MetricsStream::Unix(server));
+
+        assert_eq!(conn.handle_input(&[]), ConnectionState::Reading);
+        client.write_all(b"GET /metrics HTTP/1.1\r\n").unwrap();
+        assert_eq!(conn.handle_input(&[]), ConnectionState::Reading);
+        client.write_all(b"\r\n").unwrap();
+        assert_eq!(conn.handle_input(&[]), ConnectionState::Closed);
+        assert_eq!(conn.written, conn.response.len());
+
+        let mut response = vec![0_u8; conn.response.len()];
+        client.read_exact(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        // The pending response is sent when the socket is writable again.
+        conn.response = vec![0_u8; 1 << 24];
+        conn.written = 0;
+        assert_eq!(conn.handle_output(), ConnectionState::Writing);
+        let sent = conn.written;
+        assert!(sent > 0 && sent < conn.response.len());
+        let mut data = vec![0_u8; sent];
+        client.read_exact(&mut data).unwrap();
+        assert_ne!(conn.handle_output(), ConnectionState::Closed);
+        assert!(conn.written > sent);
+    }
+
+    #[test]
+    fn test_metrics_connection_timeout() {
+        EventLoop::object_init(&None).unwrap();
+        let (server, mut client) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let conn = Arc::new(Mutex::new(MetricsConnection::new(MetricsStream::Unix(
+            server,
+        ))));
+        let conn_num = Arc::new(AtomicUsize::new(1));
+        let notifier = connection_notifier(
+            conn.clone(),
+            Vec::new(),
+            conn_num.clone(),
+            ConnectionState::Reading,
+        );
+        EventLoop::update_event(vec![notifier], None).unwrap();
+
+        // The request is not complete when the deadline is reached.
+        client.write_all(b"GET /metrics HTTP/1.1\r\n").unwrap();
+        connection_timeout(&Arc::downgrade(&conn), &conn_num);
+        assert!(conn.lock().unwrap().closed);
+        assert_eq!(conn_num.load(Ordering::SeqCst), 0);
+        let mut data = Vec::new();
+        assert_eq!(client.read_to_end(&mut data).unwrap(), 0);
+
+        // The closed connection is not counted again.
+        connection_timeout(&Arc::downgrade(&conn), &conn_num);
+        assert_eq!(conn_num.load(Ordering::SeqCst), 0);
+
+        // The connection which has been dropped is ignored.
+        let (server, _client) = UnixStream::pair().unwrap();
+        let conn = Arc::new(Mutex::new(MetricsConnection::new(MetricsStream::Unix(
+            server,
+        ))));
+        let weak_conn = Arc::downgrade(&conn);
+        drop(conn);
+        connection_timeout(&weak_conn, &conn_num);
+        assert_eq!(conn_num.load(Ordering::SeqCst), 0);
+    }
+}
@@ -42,4 +42,8 @@ pub enum MicroVmError {
     UpdCfgErr(String),
     #[error("Failed to realize virtio mmio.")]
     RlzVirtioMmioErr,
+    #[error("Failed to init pflash device.")]
+    InitPflashErr,
+    #[error("Failed to realize pflash device.")]
+    RlzPflashErr,
 }
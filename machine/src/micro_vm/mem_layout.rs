@@ -14,6 +14,7 @@
 #[cfg(target_arch = "aarch64")]
 #[repr(usize)]
 pub enum LayoutEntryType {
+    Flash = 0,
     GicDist,
     GicCpu,
     GicIts,
@@ -28,6 +29,7 @@ pub enum LayoutEntryType {
 /// Layout of aarch64
 #[cfg(target_arch = "aarch64")]
 pub const MEM_LAYOUT: &[(u64, u64)] = &[
+    (0, 0x0800_0000),              // Flash
     (0x0800_0000, 0x0001_0000),    // GicDist
     (0x0801_0000, 0x0001_0000),    // GicCpu
     (0x0808_0000, 0x0002_0000),    // GicIts
@@ -42,11 +42,15 @@ use log::{error, info};
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Debug;
+#[cfg(target_arch = "x86_64")]
+use std::io::{Seek, SeekFrom};
 use std::ops::Deref;
 use std::os::unix::io::RawFd;
 use std::sync::{Arc, Condvar, Mutex};
 use std::vec::Vec;
 
+#[cfg(target_arch = "x86_64")]
+use address_space::HostMemMapping;
 use address_space::{AddressSpace, GuestAddress, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
 #[cfg(target_arch = "aarch64")]
@@ -58,17 +62,20 @@ use cpu::{CPUBootConfig, CPUTopology, CpuLifecycleState, CpuTopology, CPU};
 use devices::legacy::PL031;
 #[cfg(target_arch = "x86_64")]
 use devices::legacy::SERIAL_ADDR;
-use devices::legacy::{FwCfgOps, Serial};
+use devices::legacy::{FwCfgOps, PFlash, Serial};
 #[cfg(target_arch = "aarch64")]
 use devices::{ICGICConfig, ICGICv2Config, ICGICv3Config, InterruptController, GIC_IRQ_MAX};
 #[cfg(target_arch = "x86_64")]
 use hypervisor::kvm::KVM_FDS;
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
+#[cfg(target_arch = "aarch64")]
+use machine_manager::config::RtcDriftFix;
 use machine_manager::{
     config::{
-        parse_blk, parse_incoming_uri, parse_net, BlkDevConfig, BootSource, ConfigCheck, DriveFile,
-        Incoming, MigrateMode, NetworkInterfaceConfig, SerialConfig, VmConfig,
+        check_io_limits, parse_blk, parse_incoming_uri, parse_net, BlkDevConfig, BlockErrorPolicy,
+        BootSource, ConfigCheck, DriveFile, Incoming, MigrateMode, NetOffloadConfig,
+        NetworkInterfaceConfig, PFlashConfig, RtcConfig, SerialConfig, VmConfig,
         DEFAULT_VIRTQUEUE_SIZE,
     },
     event,
@@ -87,14 +94,15 @@ use syscall::syscall_whitelist;
 #[cfg(target_arch = "aarch64")]
 use util::device_tree::{self, CompileFDT, FdtBuilder};
 use util::{
-    loop_context::EventLoopManager, num_ops::str_to_usize, seccomp::BpfRule, set_termi_canon_mode,
+    leak_bucket::IoLimits, loop_context::EventLoopManager, num_ops::str_to_usize, seccomp::BpfRule,
+    set_termi_canon_mode,
 };
 use virtio::{
-    create_tap, qmp_balloon, qmp_query_balloon, Block, BlockState, Net, VhostKern, VirtioDevice,
-    VirtioMmioDevice, VirtioMmioState, VirtioNetState,
+    create_tap, qmp_balloon, qmp_block_resize, qmp_block_set_io_throttle, qmp_query_balloon, Block,
+    BlockState, Net, VhostKern, VirtioDevice, VirtioMmioDevice, VirtioMmioState, VirtioNetState,
 };
 
-use super::{error::MachineError, MachineOps};
+use super::{dump::parse_dump_protocol, error::MachineError, gdbstub, MachineOps};
 #[cfg(target_arch = "x86_64")]
 use crate::vm_state;
 use anyhow::{anyhow, bail, Context, Result};
@@ -269,6 +277,7 @@ impl LightMachine {
                 BlkDevConfig::default(),
                 self.get_drive_files(),
             )));
+            Block::object_init(block.clone());
             let virtio_mmio = VirtioMmioDevice::new(&self.sys_mem, block.clone());
             rpl_devs.push(virtio_mmio);
 
@@ -290,9 +299,12 @@ impl LightMachine {
             );
         }
 
-        let mut region_base = self.sysbus.min_free_base;
         let region_size = MEM_LAYOUT[LayoutEntryType::Mmio as usize].1;
         for (id, dev) in rpl_devs.into_iter().enumerate() {
+            let region_base = self
+                .sysbus
+                .alloc_mmio_slot(region_size)
+                .with_context(|| anyhow!(MicroVmError::RlzVirtioMmioErr))?;
             self.replaceable_info
                 .devices
                 .lock()
@@ -309,16 +321,14 @@ impl LightMachine {
                     dev,
                     &mut self.sysbus,
                     region_base,
-                    MEM_LAYOUT[LayoutEntryType::Mmio as usize].1,
+                    region_size,
                     #[cfg(target_arch = "x86_64")]
                     &self.boot_source,
                 )
                 .with_context(|| anyhow!(MicroVmError::RlzVirtioMmioErr))?,
                 &id.to_string(),
             );
-            region_base += region_size;
         }
-        self.sysbus.min_free_base = region_base;
         Ok(())
     }
 
@@ -553,6 +563,16 @@ impl MachineOps for LightMachine {
         fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
     ) -> MachineResult<CPUBootConfig> {
         let boot_source = self.boot_source.lock().unwrap();
+        // Boot from the reset vector of the firmware in pflash if no kernel is given.
+        if boot_source.kernel_file.is_none() {
+            return Ok(CPUBootConfig {
+                prot64_mode: false,
+                boot_ip: 0xFFF0,
+                boot_sp: 0x8000,
+                boot_selector: 0xF000,
+                ..Default::default()
+            });
+        }
         let initrd = boot_source.initrd.as_ref().map(|b| b.initrd_file.clone());
 
         let gap_start = MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].0
@@ -608,9 +628,16 @@ impl MachineOps for LightMachine {
             rd.initrd_size = layout.initrd_size;
         }
 
+        // Boot from the firmware in pflash if no kernel is given.
+        let boot_pc = if boot_source.kernel_file.is_none() {
+            MEM_LAYOUT[LayoutEntryType::Flash as usize].0
+        } else {
+            layout.boot_pc
+        };
+
         Ok(CPUBootConfig {
             fdt_addr: layout.dtb_start,
-            boot_pc: layout.boot_pc,
+            boot_pc,
         })
     }
 
@@ -618,8 +645,11 @@ impl MachineOps for LightMachine {
         &mut self,
         dev: VirtioMmioDevice,
     ) -> MachineResult<Arc<Mutex<VirtioMmioDevice>>> {
-        let region_base = self.sysbus.min_free_base;
         let region_size = MEM_LAYOUT[LayoutEntryType::Mmio as usize].1;
+        let region_base = self
+            .sysbus
+            .alloc_mmio_slot(region_size)
+            .with_context(|| anyhow!(MicroVmError::RlzVirtioMmioErr))?;
         let realized_virtio_mmio_device = VirtioMmioDevice::realize(
             dev,
             &mut self.sysbus,
@@ -629,7 +659,6 @@ impl MachineOps for LightMachine {
             &self.boot_source,
         )
         .with_context(|| anyhow!(MicroVmError::RlzVirtioMmioErr))?;
-        self.sysbus.min_free_base += region_size;
         Ok(realized_virtio_mmio_device)
     }
 
@@ -658,9 +687,14 @@ impl MachineOps for LightMachine {
     }
 
     #[cfg(target_arch = "aarch64")]
-    fn add_rtc_device(&mut self) -> MachineResult<()> {
+    fn add_rtc_device(&mut self, rtc_config: &RtcConfig) -> MachineResult<()> {
+        if rtc_config.driftfix != RtcDriftFix::None {
+            bail!("RTC driftfix is not supported by PL031");
+        }
+        let mut rtc = PL031::default();
+        rtc.set_start_time(rtc_config.base.start_time());
         PL031::realize(
-            PL031::default(),
+            rtc,
             &mut self.sysbus,
             MEM_LAYOUT[LayoutEntryType::Rtc as usize].0,
             MEM_LAYOUT[LayoutEntryType::Rtc as usize].1,
@@ -670,7 +704,7 @@ impl MachineOps for LightMachine {
     }
 
     #[cfg(target_arch = "x86_64")]
-    fn add_rtc_device(&mut self, _mem_size: u64) -> MachineResult<()> {
+    fn add_rtc_device(&mut self, _rtc_config: &RtcConfig, _mem_size: u64) -> MachineResult<()> {
         Ok(())
     }
 
@@ -702,6 +736,110 @@ impl MachineOps for LightMachine {
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn add_pflash_device(&mut self, configs: &[PFlashConfig]) -> MachineResult<()> {
+        let mut configs_vec = configs.to_vec();
+        configs_vec.sort_by_key(|c| c.unit);
+        // The PFlash devices locate below 4GB, this variable represents the end address
+        // of current PFlash device.
+        let mut flash_end: u64 = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
+        // PFlash devices can't overlap with the local apic.
+        let flash_window_base = MEM_LAYOUT[LayoutEntryType::LocalApic as usize].0
+            + MEM_LAYOUT[LayoutEntryType::LocalApic as usize].1;
+        let sector_len: u32 = 1024 * 4;
+        for config in configs_vec {
+            let mut fd = self.fetch_drive_file(&config.path_on_host)?;
+            let pfl_size = fd.metadata()?.len();
+            if pfl_size == 0 || pfl_size % sector_len as u64 != 0 {
+                bail!(
+                    "Size 0x{:X} of PFlash {} is not a multiple of sector size 0x{:X}",
+                    pfl_size,
+                    config.path_on_host,
+                    sector_len
+                );
+            }
+            if flash_end - flash_window_base < pfl_size {
+                bail!(
+                    "PFlash {} exceeds the flash window, only 0x{:X} bytes are left",
+                    config.path_on_host,
+                    flash_end - flash_window_base
+                );
+            }
+
+            if config.unit == 0 {
+                // The top 128 KiB of the first PFlash is the reset vector area of firmware,
+                // which is also shadowed to 0xe0000 - 0x100000 below 1 MiB.
+                let rom_base = 0xe0000;
+                let rom_size = 0x20000;
+                if pfl_size < rom_size {
+                    bail!(
+                        "PFlash {} is smaller than the firmware rom size {}",
+                        config.path_on_host,
+                        rom_size
+                    );
+                }
+                fd.seek(SeekFrom::Start(pfl_size - rom_size))?;
+
+                let ram = Arc::new(HostMemMapping::new(
+                    GuestAddress(rom_base),
+                    None,
+                    rom_size,
+                    None,
+                    false,
+                    false,
+                    false,
+                )?);
+                let rom_region = Region::init_ram_region(ram);
+                rom_region.write(&mut fd, GuestAddress(rom_base), 0, rom_size)?;
+                rom_region.set_priority(10);
+                rom_region.set_name("bios-rom");
+                self.sys_mem.root().add_subregion(rom_region, rom_base)?;
+
+                fd.seek(SeekFrom::Start(0))?;
+            }
+
+            let backend = Some(fd);
+            let pflash = PFlash::new(pfl_size, &backend, sector_len, 4, 1, config.read_only)
+                .with_context(|| anyhow!(MicroVmError::InitPflashErr))?;
+            PFlash::realize(
+                pflash,
+                &mut self.sysbus,
+                flash_end - pfl_size,
+                pfl_size,
+                backend,
+            )
+            .with_context(|| anyhow!(MicroVmError::RlzPflashErr))?;
+            flash_end -= pfl_size;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn add_pflash_device(&mut self, configs: &[PFlashConfig]) -> MachineResult<()> {
+        let sector_len: u32 = 1024 * 256;
+        let mut flash_base: u64 = MEM_LAYOUT[LayoutEntryType::Flash as usize].0;
+        let flash_size: u64 = MEM_LAYOUT[LayoutEntryType::Flash as usize].1 / 2;
+        // Both flash banks are always created, so that the layout in device tree is fixed.
+        for unit in 0..=1 {
+            let (fd, read_only) = match configs.iter().find(|c| c.unit == unit) {
+                Some(config) => (
+                    Some(self.fetch_drive_file(&config.path_on_host)?),
+                    config.read_only,
+                ),
+                None => (None, false),
+            };
+
+            let pflash = PFlash::new(flash_size, &fd, sector_len, 4, 2, read_only)
+                .with_context(|| anyhow!(MicroVmError::InitPflashErr))?;
+            PFlash::realize(pflash, &mut self.sysbus, flash_base, flash_size, fd)
+                .with_context(|| anyhow!(MicroVmError::RlzPflashErr))?;
+            flash_base += flash_size;
+        }
+
+        Ok(())
+    }
+
     fn add_virtio_mmio_net(
         &mut self,
         vm_config: &mut VmConfig,
@@ -754,6 +892,7 @@ impl MachineOps for LightMachine {
 
     fn realize(vm: &Arc<Mutex<Self>>, vm_config: &mut VmConfig) -> MachineResult<()> {
         let mut locked_vm = vm.lock().unwrap();
+        locked_vm.init_global_config(vm_config)?;
 
         //trace for lightmachine
         trace_sysbus(&locked_vm.sysbus);
@@ -794,12 +933,17 @@ impl MachineOps for LightMachine {
                 None
             };
 
+            // CPUID features are applied on every vcpu reset, including the
+            // incoming vcpus of migration.
+            let cpu_config = Some(locked_vm.load_cpu_features(vm_config)?);
+
             // vCPUs init
             locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
                 vm.clone(),
                 vm_config.machine_config.nr_cpus,
                 &topology,
                 &boot_config,
+                &cpu_config,
             )?);
         }
 
@@ -853,6 +997,16 @@ impl MachineOps for LightMachine {
             }
         }
 
+        gdbstub::gdb_init(
+            &vm_config.gdb,
+            vm.clone(),
+            &locked_vm.cpus,
+            &locked_vm.sys_mem,
+        )
+        .with_context(|| "Failed to init gdb server")?;
+        metrics::metrics_init(&vm_config.metrics, &locked_vm.cpus)
+            .with_context(|| "Failed to init metrics server")?;
+
         MigrationManager::register_vm_instance(vm.clone());
         #[cfg(target_arch = "x86_64")]
         MigrationManager::register_kvm_instance(
@@ -900,6 +1054,12 @@ impl MachineLifecycle for LightMachine {
             return false;
         }
 
+        // Data cached by devices, e.g. UEFI variables in pflash, must be durable
+        // after VM shuts down.
+        if let Err(e) = self.sysbus.flush() {
+            error!("Failed to flush sysbus devices: {:?}", e);
+        }
+
         if let Some(ctx) = EventLoop::get_ctx(None) {
             info!("vm destroy");
             ctx.kick();
@@ -971,24 +1131,55 @@ impl MachineAddressInterface for LightMachine {
 
 impl DeviceInterface for LightMachine {
     fn query_status(&self) -> Response {
-        let vmstate = self.get_vm_state().deref().0.lock().unwrap();
-        let qmp_state = match *vmstate {
-            KvmVmState::Running => qmp_schema::StatusInfo {
-                singlestep: false,
-                running: true,
-                status: qmp_schema::RunState::running,
-            },
-            KvmVmState::Paused => qmp_schema::StatusInfo {
-                singlestep: false,
-                running: false,
-                status: qmp_schema::RunState::paused,
-            },
-            _ => Default::default(),
-        };
-
+        let qmp_state: qmp_schema::StatusInfo = self.query_vm_status(&self.cpus).into();
         Response::create_response(serde_json::to_value(&qmp_state).unwrap(), None)
     }
 
+    fn vcpu_step(&self, cpu_index: u8) -> Response {
+        match self.step_vcpu(&self.cpus, cpu_index) {
+            Ok(pc) => {
+                let ret = qmp_schema::VcpuStepInfo { pc };
+                Response::create_response(serde_json::to_value(&ret).unwrap(), None)
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn memsave(&self, val: u64, size: u64, filename: String) -> Response {
+        match self.save_memory(&self.sys_mem, val, size, &filename) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn dump_guest_memory(&self, paging: bool, protocol: String) -> Response {
+        let ret = parse_dump_protocol(paging, &protocol).and_then(|filename| {
+            // Pause the running VM during the dump, and resume it afterwards.
+            let running = *self.get_vm_state().deref().0.lock().unwrap() == KvmVmState::Running;
+            if running && !self.pause() {
+                bail!("Failed to pause VM");
+            }
+            let ret = self.dump_guest_core(&self.sys_mem, &self.cpus, filename);
+            if running && !self.resume() {
+                error!("Failed to resume VM after dumping guest memory");
+            }
+            ret
+        });
+        match ret {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
     fn query_cpus(&self) -> Response {
         let mut cpu_vec: Vec<serde_json::Value> = Vec::new();
         for cpu_index in 0..self.cpu_topo.max_cpus {
@@ -1084,6 +1275,41 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn query_pinned_memory(&self) -> Response {
+        let pinned: Vec<qmp_schema::PinnedMemoryInfo> = self
+            .sys_mem
+            .pinned_ranges()
+            .iter()
+            .map(|p| qmp_schema::PinnedMemoryInfo {
+                owner: p.owner.clone(),
+                base: p.range.base.raw_value(),
+                size: p.range.size,
+            })
+            .collect();
+        Response::create_response(serde_json::to_value(&pinned).unwrap(), None)
+    }
+
+    fn query_mtree(&self) -> Response {
+        let mtree = vec![
+            qmp_schema::MtreeInfo {
+                address_space: "memory".to_string(),
+                regions: self.sys_mem.mtree(),
+            },
+            #[cfg(target_arch = "x86_64")]
+            qmp_schema::MtreeInfo {
+                address_space: "I/O".to_string(),
+                regions: self.sys_io.mtree(),
+            },
+        ];
+        Response::create_response(serde_json::to_value(&mtree).unwrap(), None)
+    }
+
+    fn query_pci(&self) -> Response {
+        // Micro vm has no pci bus.
+        let pci: Vec<qmp_schema::PciInfo> = Vec::new();
+        Response::create_response(serde_json::to_value(&pci).unwrap(), None)
+    }
+
     /// VNC is not supported by light machine currently.
     fn query_vnc(&self) -> Response {
         Response::create_error_response(
@@ -1149,6 +1375,11 @@ impl DeviceInterface for LightMachine {
 
     fn blockdev_add(&self, args: Box<qmp_schema::BlockDevAddArgument>) -> Response {
         let read_only = args.read_only.unwrap_or(false);
+        let no_flush = args
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.no_flush)
+            .unwrap_or(false);
         let direct = if let Some(cache) = args.cache {
             match cache.direct {
                 Some(direct) => direct,
@@ -1157,15 +1388,30 @@ impl DeviceInterface for LightMachine {
         } else {
             true
         };
+        let werror = match args.werror.as_ref() {
+            Some(werror) => match werror.parse::<BlockErrorPolicy>() {
+                Ok(policy) => policy,
+                Err(_) => {
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(format!(
+                            "Invalid werror {}",
+                            werror
+                        )),
+                        None,
+                    );
+                }
+            },
+            None => BlockErrorPolicy::Report,
+        };
 
         let config = BlkDevConfig {
             id: args.node_name.clone(),
             path_on_host: args.file.filename.clone(),
             read_only,
             direct,
+            no_flush,
             serial_num: None,
             iothread: None,
-            iops: None,
             queues: 1,
             boot_index: None,
             chardev: None,
@@ -1177,6 +1423,9 @@ impl DeviceInterface for LightMachine {
                 AioEngine::Off
             },
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            werror,
+            discard: args.discard.as_deref() == Some("unmap"),
+            ..BlkDevConfig::default()
         };
         if let Err(e) = config.check() {
             error!("{:?}", e);
@@ -1214,6 +1463,47 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn block_set_io_throttle(&self, args: qmp_schema::BlockSetIoThrottleArgument) -> Response {
+        let limits = IoLimits {
+            iops_total: args.iops,
+            iops_read: args.iops_rd,
+            iops_write: args.iops_wr,
+            bps_total: args.bps,
+            bps_read: args.bps_rd,
+            bps_write: args.bps_wr,
+        };
+        if let Err(e) = check_io_limits(&limits) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+        match qmp_block_set_io_throttle(&args.device, limits) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn block_resize(&mut self, args: qmp_schema::BlockResizeArgument) -> Response {
+        match qmp_block_resize(&args.device, args.size) {
+            Some(Ok(())) => Response::create_empty_response(),
+            Some(Err(e)) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+            None => Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                    "Block device {} is not found",
+                    args.device
+                )),
+                None,
+            ),
+        }
+    }
+
     fn netdev_add(&mut self, args: Box<qmp_schema::NetDevAddArgument>) -> Response {
         let mut config = NetworkInterfaceConfig {
             id: args.id.clone(),
@@ -1227,6 +1517,10 @@ impl DeviceInterface for LightMachine {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            rx_max_packets: 0,
+            rx_usecs: 0,
+            offload: NetOffloadConfig::default(),
+            boot_index: None,
         };
 
         if let Some(fds) = args.fds {
@@ -1348,6 +1642,10 @@ impl MigrateInterface for LightMachine {
     fn query_migrate(&self) -> Response {
         migration::query_migrate()
     }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::migrate_set_parameters(args)
+    }
 }
 
 impl MachineInterface for LightMachine {}
@@ -1419,6 +1717,28 @@ fn generate_rtc_device_node(fdt: &mut FdtBuilder, res: &SysRes) -> util::Result<
     Ok(())
 }
 
+// Function that helps to generate flash node in device-tree.
+//
+// # Arguments
+//
+// * `fdt` - Flatted device-tree blob where flash node will be filled into.
+#[cfg(target_arch = "aarch64")]
+fn generate_flash_device_node(fdt: &mut FdtBuilder) -> util::Result<()> {
+    let flash_base = MEM_LAYOUT[LayoutEntryType::Flash as usize].0;
+    let flash_size = MEM_LAYOUT[LayoutEntryType::Flash as usize].1 / 2;
+    let node = format!("flash@{:x}", flash_base);
+    let flash_node_dep = fdt.begin_node(&node)?;
+    fdt.set_property_string("compatible", "cfi-flash")?;
+    fdt.set_property_array_u64(
+        "reg",
+        &[flash_base, flash_size, flash_base + flash_size, flash_size],
+    )?;
+    fdt.set_property_u32("bank-width", 4)?;
+    fdt.end_node(flash_node_dep)?;
+
+    Ok(())
+}
+
 // Function that helps to generate Virtio-Mmio device's node in device-tree.
 //
 // # Arguments
@@ -1496,19 +1816,24 @@ impl CompileFDTHelper for LightMachine {
                 let cluster_node_dep = fdt.begin_node(&clster)?;
 
                 for core in 0..self.cpu_topo.cores {
+                    let core_id = self.cpu_topo.cores * self.cpu_topo.clusters * socket
+                        + self.cpu_topo.cores * cluster
+                        + core;
+                    // Only the present cpus have nodes to refer to, and an
+                    // empty core node is invalid.
+                    if self.cpu_topo.threads * core_id >= self.cpu_topo.nrcpus {
+                        continue;
+                    }
                     let core_name = format!("core{}", core);
                     let core_node_dep = fdt.begin_node(&core_name)?;
 
                     for thread in 0..self.cpu_topo.threads {
+                        let vcpuid = self.cpu_topo.threads * core_id + thread;
+                        if vcpuid >= self.cpu_topo.nrcpus {
+                            break;
+                        }
                         let thread_name = format!("thread{}", thread);
                         let thread_node_dep = fdt.begin_node(&thread_name)?;
-                        let vcpuid = self.cpu_topo.threads
-                            * self.cpu_topo.cores
-                            * self.cpu_topo.clusters
-                            * socket
-                            + self.cpu_topo.threads * self.cpu_topo.cores * cluster
-                            + self.cpu_topo.threads * core
-                            + thread;
                         fdt.set_property_u32(
                             "cpu",
                             u32::from(vcpuid) + device_tree::CPU_PHANDLE_START,
@@ -1596,6 +1921,8 @@ impl CompileFDTHelper for LightMachine {
         fdt.set_property_string("method", "hvc")?;
         fdt.end_node(psci_node_dep)?;
 
+        // The two flash banks are described by one node.
+        let mut flash_node_added = false;
         for dev in self.sysbus.devices.iter() {
             let mut locked_dev = dev.lock().unwrap();
             let dev_type = locked_dev.get_type();
@@ -1604,6 +1931,10 @@ impl CompileFDTHelper for LightMachine {
                 SysBusDevType::Serial => generate_serial_device_node(fdt, sys_res)?,
                 SysBusDevType::Rtc => generate_rtc_device_node(fdt, sys_res)?,
                 SysBusDevType::VirtioMmio => generate_virtio_devices_node(fdt, sys_res)?,
+                SysBusDevType::Flash if !flash_node_added => {
+                    generate_flash_device_node(fdt)?;
+                    flash_node_added = true;
+                }
                 _ => (),
             }
         }
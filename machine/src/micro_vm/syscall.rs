@@ -48,10 +48,10 @@ const KVM_RUN: u32 = 0xae80;
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * x86_64-unknown-gnu: 48 syscalls
-/// * x86_64-unknown-musl: 47 syscalls
-/// * aarch64-unknown-gnu: 46 syscalls
-/// * aarch64-unknown-musl: 46 syscalls
+/// * x86_64-unknown-gnu: 46 syscalls
+/// * x86_64-unknown-musl: 45 syscalls
+/// * aarch64-unknown-gnu: 44 syscalls
+/// * aarch64-unknown-musl: 44 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -67,9 +67,6 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_io_getevents),
         BpfRule::new(libc::SYS_io_submit),
         BpfRule::new(libc::SYS_io_destroy),
-        BpfRule::new(libc::SYS_io_uring_enter),
-        BpfRule::new(libc::SYS_io_uring_setup),
-        BpfRule::new(libc::SYS_io_uring_register),
         BpfRule::new(libc::SYS_dup),
         BpfRule::new(libc::SYS_close),
         BpfRule::new(libc::SYS_eventfd2),
@@ -110,6 +107,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_preadv),
         BpfRule::new(libc::SYS_pwrite64),
         BpfRule::new(libc::SYS_pwritev),
+        BpfRule::new(libc::SYS_msync),
         BpfRule::new(libc::SYS_statx),
         #[cfg(all(target_env = "musl", target_arch = "x86_64"))]
         BpfRule::new(libc::SYS_stat),
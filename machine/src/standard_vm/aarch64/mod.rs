@@ -44,14 +44,15 @@ use cpu::{
 #[cfg(not(target_env = "musl"))]
 use devices::legacy::Ramfb;
 use devices::legacy::{
-    FwCfgEntryType, FwCfgMem, FwCfgOps, LegacyError as DevErrorKind, PFlash, PL011, PL031,
+    FwCfgEntryType, FwCfgMem, FwCfgOps, LegacyError as DevErrorKind, PFlash, PvPanic, PL011, PL031,
 };
 
 use devices::{ICGICConfig, ICGICv3Config, InterruptController, GIC_IRQ_MAX};
 use hypervisor::kvm::KVM_FDS;
 use machine_manager::config::{
-    parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    parse_incoming_uri, parse_pvpanic, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode,
+    NumaNode, NumaNodes, PFlashConfig, PvPanicAction, RtcConfig, RtcDriftFix, SerialConfig,
+    VmConfig,
 };
 use machine_manager::event;
 use machine_manager::machine::{
@@ -73,7 +74,7 @@ use util::seccomp::BpfRule;
 use util::set_termi_canon_mode;
 
 use super::{AcpiBuilder, Result as StdResult, StdMachineOps};
-use crate::MachineOps;
+use crate::{gdbstub, MachineOps};
 use anyhow::{anyhow, bail, Context, Result};
 use virtio::ScsiCntlr::ScsiCntlrMap;
 
@@ -87,6 +88,7 @@ pub enum LayoutEntryType {
     Rtc,
     FwCfg,
     Ged,
+    PvPanic,
     Mmio,
     PcieMmio,
     PciePio,
@@ -106,6 +108,7 @@ pub const MEM_LAYOUT: &[(u64, u64)] = &[
     (0x0901_0000, 0x0000_1000),    // Rtc
     (0x0902_0000, 0x0000_0018),    // FwCfg
     (0x0908_0000, 0x0000_0004),    // Ged
+    (0x0909_0000, 0x0000_0002),    // PvPanic
     (0x0A00_0000, 0x0000_0200),    // Mmio
     (0x1000_0000, 0x2EFF_0000),    // PcieMmio
     (0x3EFF_0000, 0x0001_0000),    // PciePio
@@ -140,6 +143,8 @@ pub struct StdMachine {
     vm_config: Arc<Mutex<VmConfig>>,
     /// Reset request, handle VM `Reset` event.
     reset_req: Arc<EventFd>,
+    /// Panic request of pvpanic device and the action taken on it.
+    pvpanic: Option<(Arc<EventFd>, PvPanicAction)>,
     /// Device Tree Blob.
     dtb_vec: Vec<u8>,
     /// List of guest NUMA nodes information.
@@ -201,6 +206,7 @@ impl StdMachine {
                     anyhow!(MachineError::InitEventFdErr("reset_req".to_string()))
                 })?,
             ),
+            pvpanic: None,
             dtb_vec: Vec::new(),
             numa_nodes: None,
             boot_order_list: Arc::new(Mutex::new(Vec::new())),
@@ -248,6 +254,17 @@ impl StdMachine {
             event!(Reset; reset_msg);
         }
 
+        let vm_state = *locked_vm.vm_state.0.lock().unwrap();
+        if vm_state == KvmVmState::Paused
+            && locked_vm.get_shutdown_action() == ShutdownAction::ShutdownActionPause
+        {
+            // The VM was paused by guest poweroff with "-no-shutdown", boot it again.
+            if !locked_vm.resume() {
+                bail!("Failed to resume VM after reset");
+            }
+            return Ok(());
+        }
+
         for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
             cpu.resume()
                 .with_context(|| format!("Failed to resume vcpu{}", cpu_index))?;
@@ -316,6 +333,7 @@ impl StdMachineOps for StdMachine {
             MEM_LAYOUT[LayoutEntryType::HighPcieEcam as usize].1,
             mmconfig_region_ops,
         );
+        mmconfig_region.set_name("pcie-ecam");
         self.sys_mem
             .root()
             .add_subregion(
@@ -445,8 +463,12 @@ impl MachineOps for StdMachine {
         })
     }
 
-    fn add_rtc_device(&mut self) -> Result<()> {
-        let rtc = PL031::default();
+    fn add_rtc_device(&mut self, rtc_config: &RtcConfig) -> Result<()> {
+        if rtc_config.driftfix != RtcDriftFix::None {
+            bail!("RTC driftfix is not supported by PL031");
+        }
+        let mut rtc = PL031::default();
+        rtc.set_start_time(rtc_config.base.start_time());
         PL031::realize(
             rtc,
             &mut self.sysbus,
@@ -457,6 +479,23 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn add_pvpanic_device(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_pvpanic(vm_config, cfg_args)?;
+        let panic_req = Arc::new(
+            EventFd::new(libc::EFD_NONBLOCK)
+                .with_context(|| anyhow!(MachineError::InitEventFdErr("panic_req".to_string())))?,
+        );
+        PvPanic::new(panic_req.clone())
+            .realize(
+                &mut self.sysbus,
+                MEM_LAYOUT[LayoutEntryType::PvPanic as usize].0,
+                MEM_LAYOUT[LayoutEntryType::PvPanic as usize].1,
+            )
+            .with_context(|| "Failed to realize pvpanic device")?;
+        self.pvpanic = Some((panic_req, device_cfg.action));
+        Ok(())
+    }
+
     fn add_ged_device(&mut self) -> Result<()> {
         let ged = Ged::default();
         ged.realize(
@@ -546,6 +585,11 @@ impl MachineOps for StdMachine {
         locked_vm
             .add_devices(vm_config)
             .with_context(|| "Failed to add devices")?;
+        if let Some((panic_req, action)) = locked_vm.pvpanic.clone() {
+            locked_vm
+                .register_pvpanic_event(panic_req, action, vm.clone())
+                .with_context(|| "Fail to register pvpanic event")?;
+        }
 
         if let Some(boot_cfg) = boot_config {
             let mut fdt_helper = FdtBuilder::new();
@@ -569,14 +613,29 @@ impl MachineOps for StdMachine {
         // If it is direct kernel boot mode, the ACPI can not be enabled.
         if migrate.0 == MigrateMode::Unknown && fwcfg.is_some() {
             locked_vm
-                .build_acpi_tables(&fwcfg.unwrap())
+                .build_acpi_tables(fwcfg.as_ref().unwrap(), &vm_config.acpi_tables)
                 .with_context(|| "Failed to create ACPI tables")?;
         }
+        if let Some(fw_cfg) = fwcfg.as_ref() {
+            locked_vm
+                .add_fw_cfg_file(fw_cfg, &vm_config.fw_cfgs)
+                .with_context(|| "Failed to add user-specified fw_cfg files")?;
+        }
 
         locked_vm
             .reset_fwcfg_boot_order()
             .with_context(|| "Fail to update boot order imformation to FwCfg device")?;
 
+        gdbstub::gdb_init(
+            &vm_config.gdb,
+            vm.clone(),
+            &locked_vm.cpus,
+            &locked_vm.sys_mem,
+        )
+        .with_context(|| "Failed to init gdb server")?;
+        metrics::metrics_init(&vm_config.metrics, &locked_vm.cpus)
+            .with_context(|| "Failed to init metrics server")?;
+
         MigrationManager::register_vm_config(locked_vm.get_vm_config());
         MigrationManager::register_vm_instance(vm.clone());
         if let Err(e) = MigrationManager::set_status(MigrationStatus::Setup) {
@@ -712,7 +771,12 @@ impl AcpiBuilder for StdMachine {
         &self,
         acpi_data: &Arc<Mutex<Vec<u8>>>,
         loader: &mut TableLoader,
-    ) -> super::Result<u64> {
+    ) -> super::Result<Option<u64>> {
+        // IORT maps PCI requester IDs to ITS, it's useless without ITS.
+        if !self.irq_chip.as_ref().unwrap().has_its() {
+            return Ok(None);
+        }
+
         let mut iort = AcpiTable::new(*b"IORT", 2, *b"STRATO", *b"VIRTIORT", 1);
         iort.set_table_len(128);
 
@@ -748,7 +812,7 @@ impl AcpiBuilder for StdMachine {
 
         let iort_begin = StdMachine::add_table_to_loader(acpi_data, loader, &iort)
             .with_context(|| "Fail to add IORT table to loader")?;
-        Ok(iort_begin as u64)
+        Ok(Some(iort_begin as u64))
     }
 
     fn build_spcr_table(
@@ -875,11 +939,13 @@ impl AcpiBuilder for StdMachine {
         }
 
         // 4. GIC Its.
-        let mut gic_its = AcpiGicIts::default();
-        gic_its.type_id = ACPI_MADT_GENERIC_TRANSLATOR;
-        gic_its.length = 20;
-        gic_its.base_addr = MEM_LAYOUT[LayoutEntryType::GicIts as usize].0;
-        madt.append_child(&gic_its.aml_bytes());
+        if self.irq_chip.as_ref().unwrap().has_its() {
+            let mut gic_its = AcpiGicIts::default();
+            gic_its.type_id = ACPI_MADT_GENERIC_TRANSLATOR;
+            gic_its.length = 20;
+            gic_its.base_addr = MEM_LAYOUT[LayoutEntryType::GicIts as usize].0;
+            madt.append_child(&gic_its.aml_bytes());
+        }
 
         let madt_begin = StdMachine::add_table_to_loader(acpi_data, loader, &madt)
             .with_context(|| "Fail to add MADT table to loader")?;
@@ -989,6 +1055,12 @@ impl MachineLifecycle for StdMachine {
             return false;
         }
 
+        // Data cached by devices, e.g. UEFI variables in pflash, must be durable
+        // after VM shuts down.
+        if let Err(e) = self.sysbus.flush() {
+            error!("Failed to flush sysbus devices: {:?}", e);
+        }
+
         if let Some(ctx) = EventLoop::get_ctx(None) {
             info!("vm destroy");
             ctx.kick();
@@ -1068,6 +1140,10 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::migrate_set_parameters(args)
+    }
 }
 
 impl MachineInterface for StdMachine {}
@@ -1091,7 +1167,8 @@ impl EventLoopManager for StdMachine {
 // # Arguments
 //
 // * `fdt` - Flatted device-tree blob where node will be filled into.
-fn generate_pci_host_node(fdt: &mut FdtBuilder) -> util::Result<()> {
+// * `has_its` - Whether MSIs of pci devices are delivered through GIC ITS.
+fn generate_pci_host_node(fdt: &mut FdtBuilder, has_its: bool) -> util::Result<()> {
     let pcie_ecam_base = MEM_LAYOUT[LayoutEntryType::HighPcieEcam as usize].0;
     let pcie_ecam_size = MEM_LAYOUT[LayoutEntryType::HighPcieEcam as usize].1;
     let pcie_buses_num = MEM_LAYOUT[LayoutEntryType::HighPcieEcam as usize].1 >> 20;
@@ -1156,7 +1233,9 @@ fn generate_pci_host_node(fdt: &mut FdtBuilder) -> util::Result<()> {
         ],
     )?;
 
-    fdt.set_property_u32("msi-parent", device_tree::GIC_ITS_PHANDLE)?;
+    if has_its {
+        fdt.set_property_u32("msi-parent", device_tree::GIC_ITS_PHANDLE)?;
+    }
     fdt.end_node(pci_node_dep)?;
     Ok(())
 }
@@ -1277,6 +1356,16 @@ fn generate_rtc_device_node(fdt: &mut FdtBuilder, res: &SysRes) -> util::Result<
     Ok(())
 }
 
+fn generate_pvpanic_device_node(fdt: &mut FdtBuilder, res: &SysRes) -> util::Result<()> {
+    let node = format!("pvpanic@{:x}", res.region_base);
+    let pvpanic_node_dep = fdt.begin_node(&node)?;
+    fdt.set_property_string("compatible", "qemu,pvpanic-mmio")?;
+    fdt.set_property_array_u64("reg", &[res.region_base, res.region_size])?;
+    fdt.end_node(pvpanic_node_dep)?;
+
+    Ok(())
+}
+
 fn generate_pmu_node(fdt: &mut FdtBuilder) -> util::Result<()> {
     let node = "pmu";
     let pmu_node_dep = fdt.begin_node(node)?;
@@ -1328,15 +1417,24 @@ impl CompileFDTHelper for StdMachine {
                 let cluster_node_dep = fdt.begin_node(&clster)?;
 
                 for core in 0..self.cpu_topo.cores {
+                    let core_id = self.cpu_topo.cores * self.cpu_topo.clusters * socket
+                        + self.cpu_topo.cores * cluster
+                        + core;
+                    // Only the present cpus have nodes to refer to, and an
+                    // empty core node is invalid.
+                    if self.cpu_topo.threads * core_id >= self.cpu_topo.nrcpus {
+                        continue;
+                    }
                     let core_name = format!("core{}", core);
                     let core_node_dep = fdt.begin_node(&core_name)?;
 
                     for thread in 0..self.cpu_topo.threads {
+                        let vcpuid = self.cpu_topo.threads * core_id + thread;
+                        if vcpuid >= self.cpu_topo.nrcpus {
+                            break;
+                        }
                         let thread_name = format!("thread{}", thread);
                         let thread_node_dep = fdt.begin_node(&thread_name)?;
-                        let vcpuid = self.cpu_topo.threads * self.cpu_topo.cores * cluster
-                            + self.cpu_topo.threads * core
-                            + thread;
                         fdt.set_property_u32(
                             "cpu",
                             u32::from(vcpuid) + device_tree::CPU_PHANDLE_START,
@@ -1470,12 +1568,16 @@ impl CompileFDTHelper for StdMachine {
                     // SAFETY: Legacy devices guarantee is not empty.
                     generate_fwcfg_device_node(fdt, locked_dev.get_sys_resource().unwrap())?;
                 }
+                SysBusDevType::PvPanic => {
+                    // SAFETY: Legacy devices guarantee is not empty.
+                    generate_pvpanic_device_node(fdt, locked_dev.get_sys_resource().unwrap())?;
+                }
                 _ => (),
             }
         }
         generate_flash_device_node(fdt)?;
 
-        generate_pci_host_node(fdt)?;
+        generate_pci_host_node(fdt, self.irq_chip.as_ref().unwrap().has_its())?;
 
         Ok(())
     }
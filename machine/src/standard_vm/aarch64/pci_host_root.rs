@@ -89,4 +89,8 @@ impl PciDevOps for PciHostRoot {
     fn name(&self) -> String {
         "PCI Host Root".to_string()
     }
+
+    fn pci_config(&self) -> Option<&PciConfig> {
+        Some(&self.config)
+    }
 }
@@ -56,8 +56,8 @@ const KVM_RUN: u32 = 0xae80;
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * aarch64-unknown-gnu: 81 syscalls
-/// * aarch64-unknown-musl: 59 syscalls
+/// * aarch64-unknown-gnu: 76 syscalls
+/// * aarch64-unknown-musl: 54 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -70,9 +70,6 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_io_getevents),
         BpfRule::new(libc::SYS_io_submit),
         BpfRule::new(libc::SYS_io_destroy),
-        BpfRule::new(libc::SYS_io_uring_setup),
-        BpfRule::new(libc::SYS_io_uring_register),
-        BpfRule::new(libc::SYS_io_uring_enter),
         BpfRule::new(libc::SYS_dup),
         BpfRule::new(libc::SYS_close),
         BpfRule::new(libc::SYS_eventfd2),
@@ -124,10 +121,8 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         madvise_rule(),
         BpfRule::new(libc::SYS_msync),
         BpfRule::new(libc::SYS_readlinkat),
-        BpfRule::new(libc::SYS_socket),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_bind),
-        BpfRule::new(libc::SYS_connect),
         BpfRule::new(libc::SYS_getcwd),
         BpfRule::new(libc::SYS_clone),
         BpfRule::new(libc::SYS_prctl),
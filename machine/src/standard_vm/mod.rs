@@ -20,11 +20,13 @@ pub use error::StandardVmError;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::StdMachine;
-use log::error;
+use log::{error, warn};
+use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
 use machine_manager::qmp::qmp_schema::UpdateRegionArgument;
 #[cfg(not(target_env = "musl"))]
 use ui::{
+    console::console_screendump,
     input::{key_event, point_event},
     vnc::qmp_query_vnc,
 };
@@ -43,6 +45,7 @@ use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 use super::Result as MachineResult;
+use crate::dump::parse_dump_protocol;
 use crate::MachineOps;
 #[cfg(target_arch = "x86_64")]
 use acpi::AcpiGenericAddress;
@@ -58,19 +61,22 @@ use anyhow::{bail, Context};
 use cpu::{CpuTopology, CPU};
 use devices::legacy::FwCfgOps;
 use machine_manager::config::{
-    get_chardev_config, get_netdev_config, get_pci_df, BlkDevConfig, ChardevType, ConfigCheck,
-    DriveConfig, NetworkInterfaceConfig, NumaNode, NumaNodes, PciBdf, ScsiCntlrConfig, VmConfig,
-    DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
+    check_io_limits, get_chardev_config, get_netdev_config, get_pci_df, scsi_cntlr_auto_queues_num,
+    AcpiTableConfig, BlkDevConfig, BlockErrorPolicy, ChardevType, ConfigCheck, DriveConfig,
+    FwCfgConfig, NetOffloadConfig, NetworkInterfaceConfig, NumaNode, NumaNodes, PciBdf,
+    PvPanicAction, ScsiCntlrConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
 };
-use machine_manager::machine::{DeviceInterface, KvmVmState};
+use machine_manager::machine::{DeviceInterface, KvmVmState, MachineLifecycle};
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
 use migration::MigrationManager;
 use pci::hotplug::{handle_plug, handle_unplug_request};
 use pci::PciBus;
 use util::byte_code::ByteCode;
+use util::leak_bucket::IoLimits;
 use virtio::{
-    qmp_balloon, qmp_query_balloon, Block, BlockState, ScsiBus, ScsiCntlr, VhostKern, VhostUser,
-    VirtioDevice, VirtioNetState, VirtioPciDevice,
+    qmp_balloon, qmp_block_resize, qmp_block_set_io_throttle, qmp_query_balloon, Block, BlockState,
+    ScsiBus, ScsiCntlr, ScsiDisk, VhostKern, VhostUser, VirtioDevice, VirtioNetState,
+    VirtioPciDevice,
 };
 
 #[cfg(target_arch = "aarch64")]
@@ -89,7 +95,12 @@ trait StdMachineOps: AcpiBuilder {
     /// # Arguments
     ///
     /// `fw_cfg` - FwCfgOps trait object.
-    fn build_acpi_tables(&self, fw_cfg: &Arc<Mutex<dyn FwCfgOps>>) -> Result<()>
+    /// `user_tables` - ACPI tables specified by user, which are appended to XSDT.
+    fn build_acpi_tables(
+        &self,
+        fw_cfg: &Arc<Mutex<dyn FwCfgOps>>,
+        user_tables: &[AcpiTableConfig],
+    ) -> Result<()>
     where
         Self: Sized,
     {
@@ -125,10 +136,12 @@ trait StdMachineOps: AcpiBuilder {
                 .with_context(|| "Failed to build ACPI GTDT table")?;
             xsdt_entries.push(gtdt_addr);
 
-            let iort_addr = self
+            if let Some(iort_addr) = self
                 .build_iort_table(&acpi_tables, &mut loader)
-                .with_context(|| "Failed to build ACPI IORT table")?;
-            xsdt_entries.push(iort_addr);
+                .with_context(|| "Failed to build ACPI IORT table")?
+            {
+                xsdt_entries.push(iort_addr);
+            }
 
             let spcr_addr = self
                 .build_spcr_table(&acpi_tables, &mut loader)
@@ -159,6 +172,12 @@ trait StdMachineOps: AcpiBuilder {
             xsdt_entries.push(pptt_addr);
         }
 
+        for table in user_tables {
+            let table_addr = Self::add_user_table_to_loader(&acpi_tables, &mut loader, table)
+                .with_context(|| format!("Failed to add ACPI table {}", table.file))?;
+            xsdt_entries.push(table_addr);
+        }
+
         let xsdt_addr = Self::build_xsdt_table(&acpi_tables, &mut loader, xsdt_entries)?;
 
         let mut locked_fw_cfg = fw_cfg.lock().unwrap();
@@ -183,6 +202,27 @@ trait StdMachineOps: AcpiBuilder {
         bail!("Not implemented");
     }
 
+    /// Add guest-visible files specified by `-fw_cfg` to FwCfg as file entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `fw_cfg` - FwCfgOps trait object.
+    /// * `fw_cfgs` - Configs of the fw_cfg files.
+    fn add_fw_cfg_file(
+        &self,
+        fw_cfg: &Arc<Mutex<dyn FwCfgOps>>,
+        fw_cfgs: &[FwCfgConfig],
+    ) -> Result<()> {
+        let mut locked_fw_cfg = fw_cfg.lock().unwrap();
+        for config in fw_cfgs {
+            let data = config.get_data()?;
+            locked_fw_cfg
+                .add_file_entry(&config.name, data)
+                .with_context(|| format!("Failed to add fw_cfg file {}", config.name))?;
+        }
+        Ok(())
+    }
+
     fn get_cpu_topo(&self) -> &CpuTopology;
 
     fn get_cpus(&self) -> &Vec<Arc<CPU>>;
@@ -221,6 +261,53 @@ trait StdMachineOps: AcpiBuilder {
         Ok(())
     }
 
+    /// Register event notifier for the guest panic notified by pvpanic device.
+    ///
+    /// # Arguments
+    ///
+    /// * `panic_req` - Eventfd of the panic request.
+    /// * `action` - Action taken by VM on guest panic.
+    /// * `clone_vm` - Reference of the StdMachine.
+    fn register_pvpanic_event(
+        &self,
+        panic_req: Arc<EventFd>,
+        action: PvPanicAction,
+        clone_vm: Arc<Mutex<StdMachine>>,
+    ) -> MachineResult<()> {
+        let panic_req_fd = panic_req.as_raw_fd();
+        let panic_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            read_fd(panic_req_fd);
+            // The VM can only be paused when it's running, e.g. not during migration.
+            let action = match action {
+                PvPanicAction::Pause if !clone_vm.lock().unwrap().pause() => {
+                    warn!("Failed to pause VM on guest panic");
+                    PvPanicAction::None
+                }
+                _ => action,
+            };
+            if QmpChannel::is_connected() {
+                let panicked_msg = qmp_schema::GuestPanicked {
+                    action: action.as_str().to_string(),
+                    cpu_index: None,
+                    reason: "pvpanic".to_string(),
+                };
+                event!(GuestPanicked; panicked_msg);
+            }
+
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            panic_req_fd,
+            None,
+            EventSet::IN,
+            vec![panic_req_handler],
+        );
+        EventLoop::update_event(vec![notifier], None)
+            .with_context(|| "Failed to register event notifier.")?;
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn register_acpi_shutdown_event(
         &self,
@@ -232,8 +319,11 @@ trait StdMachineOps: AcpiBuilder {
         let shutdown_req_fd = shutdown_req.as_raw_fd();
         let shutdown_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
             let _ret = shutdown_req.read().unwrap();
-            StdMachine::handle_shutdown_request(&clone_vm);
-            Some(gen_delete_notifiers(&[shutdown_req_fd]))
+            if StdMachine::handle_shutdown_request(&clone_vm) {
+                Some(gen_delete_notifiers(&[shutdown_req_fd]))
+            } else {
+                None
+            }
         });
         let notifier = EventNotifier::new(
             NotifierOperation::AddShared,
@@ -282,6 +372,39 @@ trait AcpiBuilder {
         Ok(table_begin as u64)
     }
 
+    /// Add ACPI table specified by user, returns the offset of the table in `acpi_data`.
+    /// The checksum of the table is fixed up by firmware.
+    ///
+    /// # Arguments
+    ///
+    /// `acpi_data` - Bytes streams that ACPI tables converts to.
+    /// `loader` - ACPI table loader.
+    /// `table` - Config of the ACPI table.
+    fn add_user_table_to_loader(
+        acpi_data: &Arc<Mutex<Vec<u8>>>,
+        loader: &mut TableLoader,
+        table: &AcpiTableConfig,
+    ) -> Result<u64>
+    where
+        Self: Sized,
+    {
+        let data = table.get_data()?;
+        let mut locked_acpi_data = acpi_data.lock().unwrap();
+        let table_begin = locked_acpi_data.len() as u32;
+        locked_acpi_data.extend(data);
+        let table_end = locked_acpi_data.len() as u32;
+        drop(locked_acpi_data);
+
+        loader.add_cksum_entry(
+            ACPI_TABLE_FILE,
+            table_begin + TABLE_CHECKSUM_OFFSET,
+            table_begin,
+            table_end - table_begin,
+        )?;
+
+        Ok(table_begin as u64)
+    }
+
     /// Build ACPI DSDT table, returns the offset of ACPI DSDT table in `acpi_data`.
     ///
     /// # Arguments
@@ -328,7 +451,8 @@ trait AcpiBuilder {
         Ok(0)
     }
 
-    /// Build ACPI IORT table, returns the offset of ACPI IORT table in `acpi_data`.
+    /// Build ACPI IORT table, returns the offset of ACPI IORT table in `acpi_data`,
+    /// or `None` if the table is not needed.
     ///
     /// # Arguments
     ///
@@ -339,11 +463,11 @@ trait AcpiBuilder {
         &self,
         _acpi_data: &Arc<Mutex<Vec<u8>>>,
         _loader: &mut TableLoader,
-    ) -> Result<u64>
+    ) -> Result<Option<u64>>
     where
         Self: Sized,
     {
-        Ok(0)
+        Ok(None)
     }
 
     /// Build ACPI SPCR table, returns the offset of ACPI SPCR table in `acpi_data`.
@@ -736,6 +860,25 @@ fn get_device_bdf(bus: Option<String>, addr: Option<String>) -> Result<PciBdf> {
 }
 
 impl StdMachine {
+    /// Find the scsi device by id on the buses of all the scsi controllers.
+    fn get_scsi_device(&mut self, id: &str) -> Option<Arc<Mutex<ScsiDisk::ScsiDevice>>> {
+        let cntlrs = self.get_scsi_cntlr_list()?.lock().unwrap();
+        let bus_list = cntlrs
+            .values()
+            .filter_map(|cntlr| cntlr.lock().unwrap().bus.clone());
+        for bus in bus_list {
+            let locked_bus = bus.lock().unwrap();
+            if let Some(dev) = locked_bus
+                .devices
+                .values()
+                .find(|dev| dev.lock().unwrap().config.id == id)
+            {
+                return Some(dev.clone());
+            }
+        }
+        None
+    }
+
     fn plug_virtio_pci_blk(
         &mut self,
         pci_bdf: &PciBdf,
@@ -757,9 +900,12 @@ impl StdMachine {
                 path_on_host: conf.path_on_host.clone(),
                 read_only: conf.read_only,
                 direct: conf.direct,
+                write_cache: conf.write_cache,
+                no_flush: conf.no_flush,
                 serial_num: args.serial_num.clone(),
                 iothread: args.iothread.clone(),
-                iops: conf.iops,
+                iothreads: Vec::new(),
+                throttle: conf.throttle,
                 queues: args.queues.unwrap_or_else(|| {
                     VirtioPciDevice::virtio_pci_auto_queues_num(0, nr_cpus, MAX_VIRTIO_QUEUE)
                 }),
@@ -768,6 +914,15 @@ impl StdMachine {
                 socket_path: None,
                 aio: conf.aio,
                 queue_size,
+                werror: conf.werror,
+                rerror: conf.rerror,
+                retry_interval: conf.retry_interval,
+                retry_timeout: conf.retry_timeout,
+                discard: conf.discard,
+                write_zeroes: conf.write_zeroes,
+                overlay: conf.overlay.clone(),
+                snapshot: conf.snapshot,
+                format: conf.format,
             };
             dev.check()?;
             dev
@@ -784,6 +939,7 @@ impl StdMachine {
 
         let blk_id = blk.id.clone();
         let blk = Arc::new(Mutex::new(Block::new(blk, self.get_drive_files())));
+        Block::object_init(blk.clone());
         let pci_dev = self
             .add_virtio_pci_device(&args.id, pci_bdf, blk.clone(), multifunction, false)
             .with_context(|| "Failed to add virtio pci block device")?;
@@ -809,9 +965,9 @@ impl StdMachine {
         let dev_cfg = ScsiCntlrConfig {
             id: args.id.clone(),
             iothread: args.iothread.clone(),
-            queues: args.queues.unwrap_or_else(|| {
-                VirtioPciDevice::virtio_pci_auto_queues_num(0, nr_cpus, MAX_VIRTIO_QUEUE)
-            }) as u32,
+            queues: args
+                .queues
+                .unwrap_or_else(|| scsi_cntlr_auto_queues_num(nr_cpus)) as u32,
             boot_prefix: None,
             queue_size,
         };
@@ -940,7 +1096,7 @@ impl StdMachine {
             let dev = NetworkInterfaceConfig {
                 id: args.id.clone(),
                 host_dev_name: conf.ifname.clone(),
-                mac: args.mac.clone(),
+                mac: Some(locked_vmconfig.get_net_mac(None, args.mac.as_ref())?),
                 tap_fds: conf.tap_fds.clone(),
                 vhost_type: conf.vhost_type.clone(),
                 vhost_fds: conf.vhost_fds.clone(),
@@ -949,6 +1105,10 @@ impl StdMachine {
                 mq: conf.queues > 2,
                 socket_path,
                 queue_size,
+                rx_max_packets: args.rx_max_packets.unwrap_or(0),
+                rx_usecs: args.rx_usecs.unwrap_or(0),
+                offload: NetOffloadConfig::default(),
+                boot_index: args.boot_index,
             };
             dev.check()?;
             dev
@@ -958,7 +1118,12 @@ impl StdMachine {
         locked_vmconfig.add_net_device_config(args);
         drop(locked_vmconfig);
 
-        if dev.vhost_type.is_some() {
+        if let Some(bootindex) = args.boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Fail to add virtio pci net device for invalid bootindex")?;
+        }
+
+        let pci_dev = if dev.vhost_type.is_some() {
             let mut need_irqfd = false;
             let net: Arc<Mutex<dyn VirtioDevice>> =
                 if dev.vhost_type == Some(String::from("vhost-kernel")) {
@@ -968,13 +1133,21 @@ impl StdMachine {
                     Arc::new(Mutex::new(VhostUser::Net::new(&dev, self.get_sys_mem())))
                 };
             self.add_virtio_pci_device(&args.id, pci_bdf, net, multifunction, need_irqfd)
-                .with_context(|| "Failed to add vhost-kernel/vhost-user net device")?;
+                .with_context(|| "Failed to add vhost-kernel/vhost-user net device")?
         } else {
             let net_id = dev.id.clone();
             let net = Arc::new(Mutex::new(virtio::Net::new(dev)));
-            self.add_virtio_pci_device(&args.id, pci_bdf, net.clone(), multifunction, false)
+            let pci_dev = self
+                .add_virtio_pci_device(&args.id, pci_bdf, net.clone(), multifunction, false)
                 .with_context(|| "Failed to add virtio net device")?;
             MigrationManager::register_device_instance(VirtioNetState::descriptor(), net, &net_id);
+            pci_dev
+        };
+
+        if let Some(bootindex) = args.boot_index {
+            if let Some(dev_path) = pci_dev.lock().unwrap().get_dev_path() {
+                self.add_bootindex_devices(bootindex, &dev_path, &args.id);
+            }
         }
 
         Ok(())
@@ -1021,25 +1194,55 @@ impl StdMachine {
 
 impl DeviceInterface for StdMachine {
     fn query_status(&self) -> Response {
-        let vm_state = self.get_vm_state();
-        let vmstate = vm_state.deref().0.lock().unwrap();
-        let qmp_state = match *vmstate {
-            KvmVmState::Running => qmp_schema::StatusInfo {
-                singlestep: false,
-                running: true,
-                status: qmp_schema::RunState::running,
-            },
-            KvmVmState::Paused => qmp_schema::StatusInfo {
-                singlestep: false,
-                running: false,
-                status: qmp_schema::RunState::paused,
-            },
-            _ => Default::default(),
-        };
-
+        let qmp_state: qmp_schema::StatusInfo = self.query_vm_status(self.get_cpus()).into();
         Response::create_response(serde_json::to_value(&qmp_state).unwrap(), None)
     }
 
+    fn vcpu_step(&self, cpu_index: u8) -> Response {
+        match self.step_vcpu(self.get_cpus(), cpu_index) {
+            Ok(pc) => {
+                let ret = qmp_schema::VcpuStepInfo { pc };
+                Response::create_response(serde_json::to_value(&ret).unwrap(), None)
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn memsave(&self, val: u64, size: u64, filename: String) -> Response {
+        match self.save_memory(&self.sys_mem, val, size, &filename) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn dump_guest_memory(&self, paging: bool, protocol: String) -> Response {
+        let ret = parse_dump_protocol(paging, &protocol).and_then(|filename| {
+            // Pause the running VM during the dump, and resume it afterwards.
+            let running = *self.get_vm_state().deref().0.lock().unwrap() == KvmVmState::Running;
+            if running && !self.pause() {
+                bail!("Failed to pause VM");
+            }
+            let ret = self.dump_guest_core(&self.sys_mem, self.get_cpus(), filename);
+            if running && !self.resume() {
+                error!("Failed to resume VM after dumping guest memory");
+            }
+            ret
+        });
+        match ret {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
     fn query_cpus(&self) -> Response {
         let mut cpu_vec: Vec<serde_json::Value> = Vec::new();
         let cpu_topo = self.get_cpu_topo();
@@ -1108,6 +1311,50 @@ impl DeviceInterface for StdMachine {
         )
     }
 
+    fn query_pinned_memory(&self) -> Response {
+        let pinned: Vec<qmp_schema::PinnedMemoryInfo> = self
+            .sys_mem
+            .pinned_ranges()
+            .iter()
+            .map(|p| qmp_schema::PinnedMemoryInfo {
+                owner: p.owner.clone(),
+                base: p.range.base.raw_value(),
+                size: p.range.size,
+            })
+            .collect();
+        Response::create_response(serde_json::to_value(&pinned).unwrap(), None)
+    }
+
+    fn query_mtree(&self) -> Response {
+        let mtree = vec![
+            qmp_schema::MtreeInfo {
+                address_space: "memory".to_string(),
+                regions: self.sys_mem.mtree(),
+            },
+            #[cfg(target_arch = "x86_64")]
+            qmp_schema::MtreeInfo {
+                address_space: "I/O".to_string(),
+                regions: self.sys_io.mtree(),
+            },
+        ];
+        Response::create_response(serde_json::to_value(&mtree).unwrap(), None)
+    }
+
+    fn query_pci(&self) -> Response {
+        let root_bus = match self.get_pci_host() {
+            Ok(pci_host) => pci_host.lock().unwrap().root_bus.clone(),
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
+        let mut pci = Vec::new();
+        PciBus::query_info(&root_bus, 0, &mut pci);
+        Response::create_response(serde_json::to_value(&pci).unwrap(), None)
+    }
+
     fn query_vnc(&self) -> Response {
         #[cfg(not(target_env = "musl"))]
         if let Some(vnc_info) = qmp_query_vnc() {
@@ -1264,23 +1511,50 @@ impl DeviceInterface for StdMachine {
 
     fn blockdev_add(&self, args: Box<qmp_schema::BlockDevAddArgument>) -> Response {
         let read_only = args.read_only.unwrap_or(false);
+        let no_flush = args
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.no_flush)
+            .unwrap_or(false);
         let direct = if let Some(cache) = args.cache {
             cache.direct.unwrap_or(true)
         } else {
             true
         };
+        let werror = match args.werror.as_ref() {
+            Some(werror) => match werror.parse::<BlockErrorPolicy>() {
+                Ok(policy) => policy,
+                Err(_) => {
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(format!(
+                            "Invalid werror {}",
+                            werror
+                        )),
+                        None,
+                    );
+                }
+            },
+            None => BlockErrorPolicy::Report,
+        };
         let config = DriveConfig {
             id: args.node_name,
             path_on_host: args.file.filename.clone(),
             read_only,
             direct,
-            iops: args.iops,
+            no_flush,
+            throttle: IoLimits {
+                iops_total: args.iops.unwrap_or(0),
+                ..IoLimits::default()
+            },
             // TODO Add aio option by qmp, now we set it based on "direct".
             aio: if direct {
                 AioEngine::Native
             } else {
                 AioEngine::Off
             },
+            werror,
+            discard: args.discard.as_deref() == Some("unmap"),
+            ..DriveConfig::default()
         };
 
         if let Err(e) = config.check() {
@@ -1344,6 +1618,53 @@ impl DeviceInterface for StdMachine {
         }
     }
 
+    fn block_set_io_throttle(&self, args: qmp_schema::BlockSetIoThrottleArgument) -> Response {
+        let limits = IoLimits {
+            iops_total: args.iops,
+            iops_read: args.iops_rd,
+            iops_write: args.iops_wr,
+            bps_total: args.bps,
+            bps_read: args.bps_rd,
+            bps_write: args.bps_wr,
+        };
+        if let Err(e) = check_io_limits(&limits) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+        match qmp_block_set_io_throttle(&args.device, limits) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn block_resize(&mut self, args: qmp_schema::BlockResizeArgument) -> Response {
+        let mut result = qmp_block_resize(&args.device, args.size);
+        if result.is_none() {
+            result = self
+                .get_scsi_device(&args.device)
+                .map(|dev| dev.lock().unwrap().resize(args.size));
+        }
+        match result {
+            Some(Ok(())) => Response::create_empty_response(),
+            Some(Err(e)) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+            None => Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                    "Block device {} is not found",
+                    args.device
+                )),
+                None,
+            ),
+        }
+    }
+
     fn chardev_add(&mut self, args: qmp_schema::CharDevAddArgument) -> Response {
         let config = match get_chardev_config(args) {
             Ok(conf) => conf,
@@ -1602,6 +1923,63 @@ impl DeviceInterface for StdMachine {
             ),
         }
     }
+
+    fn change(&mut self, device: String, target: String) -> Response {
+        let dev = match self.get_scsi_device(&device) {
+            Some(dev) => dev,
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                        "Scsi device {} is not found",
+                        device
+                    )),
+                    None,
+                )
+            }
+        };
+        let result = dev.lock().unwrap().change_medium(&target);
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn eject(&mut self, device: String) -> Response {
+        let dev = match self.get_scsi_device(&device) {
+            Some(dev) => dev,
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                        "Scsi device {} is not found",
+                        device
+                    )),
+                    None,
+                )
+            }
+        };
+        let result = dev.lock().unwrap().eject();
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    #[cfg(not(target_env = "musl"))]
+    fn screendump(&self, filename: String) -> Response {
+        match console_screendump(&filename) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
 }
 
 #[cfg(not(target_env = "musl"))]
@@ -89,6 +89,7 @@ impl LPCBridge {
         let mut pm_base_addr = 0_u32;
         self.config
             .read(PM_BASE_OFFSET as usize, pm_base_addr.as_mut_bytes());
+        pmtmr_region.set_name("pm-timer");
         self.sys_io
             .root()
             .add_subregion(pmtmr_region, pm_base_addr as u64 + PM_TIMER_OFFSET as u64)?;
@@ -136,6 +137,7 @@ impl LPCBridge {
             write: Arc::new(write_ops),
         };
         let rst_ctrl_region = Region::init_io_region(0x1, ops);
+        rst_ctrl_region.set_name("reset-control");
         self.sys_io
             .root()
             .add_subregion(rst_ctrl_region, RST_CTRL_OFFSET as u64)?;
@@ -163,6 +165,7 @@ impl LPCBridge {
             write: Arc::new(write_ops),
         };
         let sleep_reg_region = Region::init_io_region(0x1, ops);
+        sleep_reg_region.set_name("sleep-control");
         self.sys_io
             .root()
             .add_subregion(sleep_reg_region, SLEEP_CTRL_OFFSET as u64)?;
@@ -185,6 +188,7 @@ impl LPCBridge {
             write: Arc::new(write_ops),
         };
         let pm_evt_region = Region::init_io_region(0x4, ops);
+        pm_evt_region.set_name("pm-event");
         self.sys_io
             .root()
             .add_subregion(pm_evt_region, PM_EVENT_OFFSET as u64)?;
@@ -215,6 +219,7 @@ impl LPCBridge {
             write: Arc::new(write_ops),
         };
         let pm_ctrl_region = Region::init_io_region(0x4, ops);
+        pm_ctrl_region.set_name("pm-control");
         self.sys_io
             .root()
             .add_subregion(pm_ctrl_region, PM_CTRL_OFFSET as u64)?;
@@ -299,4 +304,8 @@ impl PciDevOps for LPCBridge {
     fn name(&self) -> String {
         "ICH9 LPC bridge".to_string()
     }
+
+    fn pci_config(&self) -> Option<&PciConfig> {
+        Some(&self.config)
+    }
 }
@@ -91,6 +91,7 @@ impl Mch {
         }
         if enable == 0x1 {
             let region = Region::init_io_region(length, self.mmconfig_ops.clone());
+            region.set_name("pcie-ecam");
             let base_addr: u64 = pciexbar & addr_mask;
             self.parent_bus
                 .upgrade()
@@ -170,4 +171,8 @@ impl PciDevOps for Mch {
     fn name(&self) -> String {
         "Memory Controller Hub".to_string()
     }
+
+    fn pci_config(&self) -> Option<&PciConfig> {
+        Some(&self.config)
+    }
 }
@@ -32,14 +32,15 @@ use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
 use cpu::{CPUBootConfig, CPUInterface, CPUTopology, CpuTopology, CPU};
 use devices::legacy::{
-    error::LegacyError as DevErrorKind, FwCfgEntryType, FwCfgIO, FwCfgOps, PFlash, Serial, RTC,
-    SERIAL_ADDR,
+    error::LegacyError as DevErrorKind, FwCfgEntryType, FwCfgIO, FwCfgOps, PFlash, PvPanic, Serial,
+    PVPANIC_PORT, PVPANIC_REGION_SIZE, RTC, SERIAL_ADDR,
 };
 use hypervisor::kvm::KVM_FDS;
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use machine_manager::config::{
-    parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    parse_incoming_uri, parse_pvpanic, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode,
+    NumaNode, NumaNodes, PFlashConfig, PvPanicAction, RtcConfig, SerialConfig, ShutdownAction,
+    TripleFaultAction, VmConfig,
 };
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
@@ -60,7 +61,7 @@ use util::{
 use self::ich9_lpc::SLEEP_CTRL_OFFSET;
 use super::error::StandardVmError;
 use super::{AcpiBuilder, StdMachineOps};
-use crate::{vm_state, MachineOps};
+use crate::{gdbstub, vm_state, MachineOps};
 use anyhow::{anyhow, bail, Context, Result};
 #[cfg(not(target_env = "musl"))]
 use ui::vnc;
@@ -115,6 +116,8 @@ pub struct StdMachine {
     boot_source: Arc<Mutex<BootSource>>,
     /// Reset request, handle VM `Reset` event.
     reset_req: Arc<EventFd>,
+    /// Panic request of pvpanic device and the action taken on it.
+    pvpanic: Option<(Arc<EventFd>, PvPanicAction)>,
     /// All configuration information of virtual machine.
     vm_config: Arc<Mutex<VmConfig>>,
     /// List of guest NUMA nodes information.
@@ -173,6 +176,7 @@ impl StdMachine {
             reset_req: Arc::new(EventFd::new(libc::EFD_NONBLOCK).with_context(|| {
                 anyhow!(MachineError::InitEventFdErr("reset request".to_string()))
             })?),
+            pvpanic: None,
             vm_config: Arc::new(Mutex::new(vm_config.clone())),
             numa_nodes: None,
             boot_order_list: Arc::new(Mutex::new(Vec::new())),
@@ -207,6 +211,20 @@ impl StdMachine {
         for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
             cpu.reset()
                 .with_context(|| format!("Failed to reset vcpu{}", cpu_index))?;
+        }
+
+        let vm_state = *locked_vm.vm_state.0.lock().unwrap();
+        if vm_state == KvmVmState::Paused
+            && locked_vm.get_shutdown_action() == ShutdownAction::ShutdownActionPause
+        {
+            // The VM was paused by guest poweroff with "-no-shutdown", boot it again.
+            if !locked_vm.resume() {
+                bail!("Failed to resume VM after reset");
+            }
+            return Ok(());
+        }
+
+        for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
             cpu.resume()
                 .with_context(|| format!("Failed to resume vcpu{}", cpu_index))?;
         }
@@ -214,8 +232,24 @@ impl StdMachine {
         Ok(())
     }
 
+    /// Handle the ACPI shutdown request from guest, returns `true` if the VM is shut down.
     pub fn handle_shutdown_request(vm: &Arc<Mutex<Self>>) -> bool {
         let locked_vm = vm.lock().unwrap();
+        if locked_vm.get_shutdown_action() == ShutdownAction::ShutdownActionPause {
+            // Keep the VM alive with "-no-shutdown", so that it can be rebooted by `system_reset`.
+            if QmpChannel::is_connected() {
+                let shutdown_msg = qmp_schema::Shutdown {
+                    guest: true,
+                    reason: "guest-shutdown".to_string(),
+                };
+                event!(Shutdown; shutdown_msg);
+            }
+            if !locked_vm.pause() {
+                error!("Failed to pause VM on guest shutdown");
+            }
+            return false;
+        }
+
         for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
             if let Err(e) = cpu.destroy() {
                 error!("Failed to destroy vcpu{}, error is {:?}", cpu_index, e);
@@ -272,6 +306,7 @@ impl StdMachineOps for StdMachine {
             MEM_LAYOUT[LayoutEntryType::PcieEcam as usize].1,
             mmconfig_region_ops.clone(),
         );
+        mmconfig_region.set_name("pcie-ecam");
         self.sys_mem
             .root()
             .add_subregion(
@@ -282,12 +317,14 @@ impl StdMachineOps for StdMachine {
 
         let pio_addr_ops = PciHost::build_pio_addr_ops(self.pci_host.clone());
         let pio_addr_region = Region::init_io_region(4, pio_addr_ops);
+        pio_addr_region.set_name("pci-config-address");
         self.sys_io
             .root()
             .add_subregion(pio_addr_region, 0xcf8)
             .with_context(|| "Failed to register CONFIG_ADDR port in I/O space.")?;
         let pio_data_ops = PciHost::build_pio_data_ops(self.pci_host.clone());
         let pio_data_region = Region::init_io_region(4, pio_data_ops);
+        pio_data_region.set_name("pci-config-data");
         self.sys_io
             .root()
             .add_subregion(pio_data_region, 0xcfc)
@@ -390,8 +427,10 @@ impl MachineOps for StdMachine {
         })
     }
 
-    fn add_rtc_device(&mut self, mem_size: u64) -> Result<()> {
+    fn add_rtc_device(&mut self, rtc_config: &RtcConfig, mem_size: u64) -> Result<()> {
         let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        rtc.set_start_time(rtc_config.base.start_time());
+        rtc.set_driftfix(rtc_config.driftfix);
         rtc.set_memory(
             mem_size,
             MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].0
@@ -402,6 +441,19 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn add_pvpanic_device(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_pvpanic(vm_config, cfg_args)?;
+        let panic_req =
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK).with_context(|| {
+                anyhow!(MachineError::InitEventFdErr("panic request".to_string()))
+            })?);
+        PvPanic::new(panic_req.clone())
+            .realize(&mut self.sysbus, PVPANIC_PORT, PVPANIC_REGION_SIZE)
+            .with_context(|| "Failed to realize pvpanic device")?;
+        self.pvpanic = Some((panic_req, device_cfg.action));
+        Ok(())
+    }
+
     fn add_serial_device(&mut self, config: &SerialConfig) -> Result<()> {
         let region_base: u64 = SERIAL_ADDR;
         let region_size: u64 = 8;
@@ -443,6 +495,11 @@ impl MachineOps for StdMachine {
             .init_ich9_lpc(clone_vm)
             .with_context(|| "Fail to init LPC bridge")?;
         locked_vm.add_devices(vm_config)?;
+        if let Some((panic_req, action)) = locked_vm.pvpanic.clone() {
+            locked_vm
+                .register_pvpanic_event(panic_req, action, vm.clone())
+                .with_context(|| "Fail to register pvpanic event")?;
+        }
         #[cfg(not(target_env = "musl"))]
         vnc::vnc_init(&vm_config.vnc, &vm_config.object)
             .with_context(|| "Failed to init VNC server!")?;
@@ -459,23 +516,40 @@ impl MachineOps for StdMachine {
             vm_config.machine_config.nr_cores,
             vm_config.machine_config.nr_dies,
         ));
+        let cpu_config = Some(locked_vm.load_cpu_features(vm_config)?);
         locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
             vm.clone(),
             nr_cpus,
             &topology,
             &boot_config,
+            &cpu_config,
         )?);
 
         if migrate.0 == MigrateMode::Unknown && fwcfg.is_some() {
             locked_vm
-                .build_acpi_tables(&fwcfg.unwrap())
+                .build_acpi_tables(fwcfg.as_ref().unwrap(), &vm_config.acpi_tables)
                 .with_context(|| "Failed to create ACPI tables")?;
         }
+        if let Some(fw_cfg) = fwcfg.as_ref() {
+            locked_vm
+                .add_fw_cfg_file(fw_cfg, &vm_config.fw_cfgs)
+                .with_context(|| "Failed to add user-specified fw_cfg files")?;
+        }
 
         locked_vm
             .reset_fwcfg_boot_order()
             .with_context(|| "Fail to update boot order imformation to FwCfg device")?;
 
+        gdbstub::gdb_init(
+            &vm_config.gdb,
+            vm.clone(),
+            &locked_vm.cpus,
+            &locked_vm.sys_mem,
+        )
+        .with_context(|| "Failed to init gdb server")?;
+        metrics::metrics_init(&vm_config.metrics, &locked_vm.cpus)
+            .with_context(|| "Failed to init metrics server")?;
+
         MigrationManager::register_vm_config(locked_vm.get_vm_config());
         MigrationManager::register_vm_instance(vm.clone());
         MigrationManager::register_kvm_instance(
@@ -495,9 +569,28 @@ impl MachineOps for StdMachine {
         // The two PFlash devices locates below 4GB, this variable represents the end address
         // of current PFlash device.
         let mut flash_end: u64 = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
+        // PFlash devices can't overlap with the identity map and TSS.
+        let flash_window_base = MEM_LAYOUT[LayoutEntryType::IdentTss as usize].0
+            + MEM_LAYOUT[LayoutEntryType::IdentTss as usize].1;
+        let sector_len: u32 = 1024 * 4;
         for config in configs_vec {
             let mut fd = self.fetch_drive_file(&config.path_on_host)?;
             let pfl_size = fd.metadata().unwrap().len();
+            if pfl_size == 0 || pfl_size % sector_len as u64 != 0 {
+                bail!(
+                    "Size 0x{:X} of PFlash {} is not a multiple of sector size 0x{:X}",
+                    pfl_size,
+                    config.path_on_host,
+                    sector_len
+                );
+            }
+            if flash_end - flash_window_base < pfl_size {
+                bail!(
+                    "PFlash {} exceeds the flash window, only 0x{:X} bytes are left",
+                    config.path_on_host,
+                    flash_end - flash_window_base
+                );
+            }
 
             if config.unit == 0 {
                 // According to the Linux/x86 boot protocol, the memory region of
@@ -505,6 +598,13 @@ impl MachineOps for StdMachine {
                 // KiB is for BIOS code which is stored in the first PFlash.
                 let rom_base = 0xe0000;
                 let rom_size = 0x20000;
+                if pfl_size < rom_size {
+                    bail!(
+                        "PFlash {} is smaller than the firmware rom size {}",
+                        config.path_on_host,
+                        rom_size
+                    );
+                }
                 fd.seek(SeekFrom::Start(pfl_size - rom_size))?;
 
                 let ram1 = Arc::new(HostMemMapping::new(
@@ -519,12 +619,12 @@ impl MachineOps for StdMachine {
                 let rom_region = Region::init_ram_region(ram1);
                 rom_region.write(&mut fd, GuestAddress(rom_base), 0, rom_size)?;
                 rom_region.set_priority(10);
+                rom_region.set_name("bios-rom");
                 self.sys_mem.root().add_subregion(rom_region, rom_base)?;
 
                 fd.seek(SeekFrom::Start(0))?;
             }
 
-            let sector_len: u32 = 1024 * 4;
             let backend = Some(fd);
             let pflash = PFlash::new(
                 pfl_size,
@@ -817,6 +917,12 @@ impl MachineLifecycle for StdMachine {
             return false;
         }
 
+        // Data cached by devices, e.g. UEFI variables in pflash, must be durable
+        // after VM shuts down.
+        if let Err(e) = self.sysbus.flush() {
+            error!("Failed to flush sysbus devices: {:?}", e);
+        }
+
         if let Some(ctx) = EventLoop::get_ctx(None) {
             info!("vm destroy");
             ctx.kick();
@@ -824,6 +930,22 @@ impl MachineLifecycle for StdMachine {
         true
     }
 
+    fn get_shutdown_action(&self) -> ShutdownAction {
+        self.vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .shutdown_action
+    }
+
+    fn get_triple_fault_action(&self) -> TripleFaultAction {
+        self.vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .triple_fault_action
+    }
+
     fn reset(&mut self) -> bool {
         if self.reset_req.write(1).is_err() {
             error!("X86 standard vm write reset request failed");
@@ -908,6 +1030,10 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::migrate_set_parameters(args)
+    }
 }
 
 impl MachineInterface for StdMachine {}
@@ -56,8 +56,8 @@ const KVM_RUN: u32 = 0xae80;
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * x86_64-unknown-gnu: 82 syscalls
-/// * x86_64-unknown-musl: 62 syscalls
+/// * x86_64-unknown-gnu: 77 syscalls
+/// * x86_64-unknown-musl: 57 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -72,9 +72,6 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_io_getevents),
         BpfRule::new(libc::SYS_io_submit),
         BpfRule::new(libc::SYS_io_destroy),
-        BpfRule::new(libc::SYS_io_uring_setup),
-        BpfRule::new(libc::SYS_io_uring_register),
-        BpfRule::new(libc::SYS_io_uring_enter),
         BpfRule::new(libc::SYS_dup),
         BpfRule::new(libc::SYS_close),
         BpfRule::new(libc::SYS_eventfd2),
@@ -130,10 +127,8 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_msync),
         BpfRule::new(libc::SYS_readlinkat),
         BpfRule::new(libc::SYS_readlink),
-        BpfRule::new(libc::SYS_socket),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_bind),
-        BpfRule::new(libc::SYS_connect),
         BpfRule::new(libc::SYS_getcwd),
         #[cfg(target_env = "musl")]
         BpfRule::new(libc::SYS_clone),
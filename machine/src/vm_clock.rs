@@ -0,0 +1,136 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use cpu::CPU;
+#[cfg(target_arch = "x86_64")]
+use hypervisor::kvm::{KVMFds, KVM_FDS};
+#[cfg(target_arch = "x86_64")]
+use kvm_bindings::kvm_clock_data;
+use once_cell::sync::Lazy;
+
+/// Virtual counter of arch timer, which is shared by all vcpus of VM.
+/// See: https://elixir.bootlin.com/linux/v5.10/source/arch/arm64/include/uapi/asm/kvm.h#L265
+#[cfg(target_arch = "aarch64")]
+const KVM_REG_ARM_TIMER_CNT: u64 = 0x6030_0000_0013_df1a;
+
+/// Guest clock saved when VM is paused, which is restored when VM is resumed, so that
+/// the guest time doesn't leap forward by the paused time.
+static PAUSED_CLOCK: Lazy<Mutex<Option<u64>>> = Lazy::new(|| Mutex::new(None));
+
+/// Save the guest clock after all vcpus are paused.
+///
+/// # Arguments
+///
+/// * `cpus` - Cpus vector restore cpu structure.
+pub fn save_guest_clock(cpus: &[Arc<CPU>]) -> Result<()> {
+    let mut paused_clock = PAUSED_CLOCK.lock().unwrap();
+    if paused_clock.is_none() {
+        *paused_clock = Some(get_guest_clock(cpus)?);
+    }
+    Ok(())
+}
+
+/// Restore the guest clock saved on pause before vcpus are resumed.
+///
+/// # Arguments
+///
+/// * `cpus` - Cpus vector restore cpu structure.
+pub fn restore_guest_clock(cpus: &[Arc<CPU>]) -> Result<()> {
+    if let Some(clock) = PAUSED_CLOCK.lock().unwrap().take() {
+        set_guest_clock(cpus, clock)?;
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn get_guest_clock(_cpus: &[Arc<CPU>]) -> Result<u64> {
+    get_kvm_clock(&KVM_FDS.load())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn set_guest_clock(_cpus: &[Arc<CPU>], clock: u64) -> Result<()> {
+    set_kvm_clock(&KVM_FDS.load(), clock)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn get_kvm_clock(kvm_fds: &KVMFds) -> Result<u64> {
+    let kvm_clock = kvm_fds
+        .vm_fd
+        .as_ref()
+        .unwrap()
+        .get_clock()
+        .with_context(|| "Failed to get kvm clock")?;
+    Ok(kvm_clock.clock)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn set_kvm_clock(kvm_fds: &KVMFds, clock: u64) -> Result<()> {
+    let kvm_clock = kvm_clock_data {
+        clock,
+        ..Default::default()
+    };
+    kvm_fds
+        .vm_fd
+        .as_ref()
+        .unwrap()
+        .set_clock(&kvm_clock)
+        .with_context(|| "Failed to set kvm clock")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn get_guest_clock(cpus: &[Arc<CPU>]) -> Result<u64> {
+    let counter = cpus[0]
+        .fd()
+        .get_one_reg(KVM_REG_ARM_TIMER_CNT)
+        .with_context(|| "Failed to get virtual counter of arch timer")?;
+    Ok(counter as u64)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn set_guest_clock(cpus: &[Arc<CPU>], clock: u64) -> Result<()> {
+    // Setting the counter on one vcpu updates the offset of all vcpus.
+    cpus[0]
+        .fd()
+        .set_one_reg(KVM_REG_ARM_TIMER_CNT, clock as u128)
+        .with_context(|| "Failed to set virtual counter of arch timer")
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_kvm_clock_across_pause() {
+        use std::thread::sleep;
+        use std::time::Duration;
+
+        use super::*;
+
+        let kvm_fds = KVMFds::new();
+        if kvm_fds.vm_fd.is_none() {
+            return;
+        }
+
+        let paused_clock = get_kvm_clock(&kvm_fds).unwrap();
+        sleep(Duration::from_millis(500));
+        let clock = get_kvm_clock(&kvm_fds).unwrap();
+        assert!(clock - paused_clock >= 500_000_000);
+
+        // The guest clock continues from the paused time after resume.
+        set_kvm_clock(&kvm_fds, paused_clock).unwrap();
+        let resumed_clock = get_kvm_clock(&kvm_fds).unwrap();
+        assert!(resumed_clock >= paused_clock);
+        assert!(resumed_clock - paused_clock < 500_000_000);
+    }
+}
@@ -17,7 +17,7 @@ use util::arg_parser::{Arg, ArgMatches, ArgParser};
 use util::unix::{limit_permission, parse_unix_uri};
 
 use crate::{
-    config::{add_trace_events, ChardevType, CmdParser, MachineType, VmConfig},
+    config::{add_trace_events, ChardevType, CmdParser, ConfigFile, ExBool, MachineType, VmConfig},
     temp_cleaner::TempCleaner,
 };
 
@@ -83,6 +83,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("set the name of the guest.")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("config")
+            .long("config")
+            .value_name("<json_file_path>")
+            .help("load VM definition from a json config file, options in command line override it")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("machine")
             .long("machine")
@@ -114,11 +121,19 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("cpu")
             .long("cpu")
-            .value_name("host[,pmu=on|off]")
+            .value_name("host[,pmu=on|off][,+flag][,-flag]")
             .help("set CPU model and features.")
             .can_no_value(false)
             .takes_value(true)
         )
+        .arg(
+            Arg::with_name("vcpu-affinity")
+            .multiple(true)
+            .long("vcpu-affinity")
+            .value_name("<vcpu>=<cpu>[-<cpu>][,<cpu>[-<cpu>]]")
+            .help("pin the vcpu to the given host cpus, e.g. -vcpu-affinity 0=0-3,8")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("freeze_cpu")
             .short("S")
@@ -130,7 +145,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("memory")
             .long("m")
-            .value_name("[size=]<megs>[m|M|g|G]")
+            .value_name("[size=]<megs>[m|M|g|G][,hugepages=<size>]")
             .help("configure guest RAM(default unit: MiB).")
             .takes_value(true),
         )
@@ -148,6 +163,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(false)
             .required(false),
         )
+        .arg(
+            Arg::with_name("mem-lock")
+            .long("mem-lock")
+            .help("Lock memory of VM in host RAM")
+            .takes_value(false)
+            .required(false),
+        )
         .arg(
             Arg::with_name("numa")
             .multiple(true)
@@ -161,7 +183,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             Arg::with_name("kernel")
             .long("kernel")
             .value_name("<kernel_path>")
-            .help("use uncompressed kernel image")
+            .help("use uncompressed, gzip or lz4 compressed kernel image")
             .takes_value(true),
         )
         .arg(
@@ -186,6 +208,14 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("set QMP's unix socket path")
             .takes_value(true)
         )
+        .arg(
+            Arg::with_name("control-socket")
+            .multiple(true)
+            .long("control-socket")
+            .value_name("<socket_path>[,readonly=on|off]")
+            .help("add an extra QMP unix socket, which serves one client at a time")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("mod-test")
             .long("mod-test")
@@ -198,7 +228,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .multiple(true)
             .long("drive")
             .value_name("<parameters>")
-            .help("\n\t\tset block drive image: -drive id=<drive_id>,file=<path_on_host>[,readonly=on|off][,direct=on|off][,throttling.iops-total=<200>]; \
+            .help("\n\t\tset block drive image: -drive id=<drive_id>,file=<path_on_host>[,readonly=on|off][,direct=on|off][,throttling.{iops|bps}-{total|read|write}=<200>]; \
                    \n\t\tset pflash drive image: -drive file=<pflash_path>,if=pflash,unit=0|1[,readonly=true|false]; \
                    \n\t\tset scsi drive image: -drive id=<drive-scsi0-0-0-0>,file=<path_on_host>[,readonly=true|false]")
             .takes_values(true),
@@ -251,6 +281,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    \n\t\tadd vhost user fs: -device vhost-user-fs-pci,id=<device_id>,chardev=<chardev_id>,tag=<mount_tag>")
             .takes_values(true),
         )
+        .arg(
+            Arg::with_name("hotplug-manifest")
+            .long("hotplug-manifest")
+            .value_name("<json_file_path>")
+            .help("hot plug the devices in json file after VM is running, the same arguments as QMP device_add")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("serial")
             .long("serial")
@@ -266,6 +303,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(true)
             .can_no_value(true),
         )
+        .arg(
+            Arg::with_name("log-format")
+            .long("log-format")
+            .value_name("<text|json>")
+            .help("set the format of log output, json outputs one object per line (default text)")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("pidfile")
             .long("pidfile")
@@ -281,6 +325,14 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(false)
             .required(false),
         )
+        .arg(
+            Arg::with_name("dry-run")
+            .long("dry-run")
+            .value_name("")
+            .help("validate the configuration and exit without creating the VM")
+            .takes_value(false)
+            .required(false),
+        )
         .arg(
             Arg::with_name("disable-seccomp")
             .long("disable-seccomp")
@@ -289,6 +341,15 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(false)
             .required(false),
         )
+        .arg(
+            Arg::with_name("seccomp-mode")
+            .long("seccomp-mode")
+            .value_name("<trap|log>")
+            .help("action of seccomp sandbox on not allowed syscall: trap (default) or log")
+            .takes_value(true)
+            .possible_values(vec!["trap", "log"])
+            .required(false),
+        )
         .arg(
             Arg::with_name("incoming")
             .long("incoming")
@@ -298,6 +359,61 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    \n\t\tdo the virtual machine snapshot: -incoming file:<file path>")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("rtc")
+            .long("rtc")
+            .value_name("[base=utc|localtime|<datetime>][,driftfix=none|slew]")
+            .help("\n\t\tset the start time of guest RTC: base=utc|localtime|<YYYY-MM-DDTHH:MM:SS>|<YYYY-MM-DD>; \
+                   \n\t\tcatch up the time while VM is paused by running guest RTC faster: driftfix=slew")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vcpu-liveness")
+            .long("vcpu-liveness")
+            .value_name("window=<secs>")
+            .help("report vcpu which doesn't make progress in VMM within the window by QMP event VCPU_STALLED")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics")
+            .long("metrics")
+            .value_name("<parameters>")
+            .help("\n\t\tserve metrics in prometheus text format on tcp socket: -metrics tcp:[ip]:<port>; \
+                   \n\t\tserve metrics in prometheus text format on unix socket: -metrics unix:<socket path>")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("guest-agent")
+            .long("guest-agent")
+            .value_name("<socket path>")
+            .help("add guest agent channel org.qemu.guest_agent.0 backed by unix socket server")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gdb")
+            .long("gdb")
+            .value_name("<parameters>")
+            .help("\n\t\tstart gdb stub server on tcp socket: -gdb tcp:[ip]:<port>; \
+                   \n\t\tstart gdb stub server on unix socket: -gdb unix:<socket path>")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("acpitable")
+            .multiple(true)
+            .long("acpitable")
+            .value_name("file=<path>")
+            .help("add ACPI table from file, such as SLIC or OEM specific table")
+            .takes_values(true),
+        )
+        .arg(
+            Arg::with_name("fw_cfg")
+            .multiple(true)
+            .long("fw_cfg")
+            .value_name("<parameters>")
+            .help("\n\t\tadd fw_cfg file from host file: -fw_cfg name=<name>,file=<path>; \
+                   \n\t\tadd fw_cfg file from string: -fw_cfg name=<name>,string=<str>")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("object")
             .multiple(true)
@@ -363,14 +479,6 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .can_no_value(true)
             .takes_value(true),
         )
-        .arg(
-            Arg::with_name("rtc")
-            .long("rtc")
-            .value_name("[base=utc]")
-            .hidden(true)
-            .can_no_value(true)
-            .takes_value(true),
-        )
         .arg(
             Arg::with_name("no-shutdown")
             .long("no-shutdown")
@@ -457,49 +565,73 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
 /// Input arguments is illegal for `VmConfig` or `VmConfig`'s health check
 /// failed -- with this unhealthy `VmConfig`, VM will not boot successfully.
 pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
-    // Parse config-file json.
-    // VmConfig can be transformed by json file which described VmConfig
-    // directly.
+    // Parse config-file json, which describes VM with the same options as cmdline.
+    // The single-value options in cmdline override the ones in config file, and the
+    // multi-value options in cmdline are added after the ones in config file.
+    let config_file = match args.value_of("config") {
+        Some(path) => ConfigFile::from_file(&path)?,
+        None => ConfigFile::default(),
+    };
+    let value_of = |name| args.value_of(name).or_else(|| config_file.value_of(name));
+    let values_of = |name| match (config_file.values_of(name), args.values_of(name)) {
+        (Some(mut values), Some(args_values)) => {
+            values.extend(args_values);
+            Some(values)
+        }
+        (values, args_values) => values.or(args_values),
+    };
+    let is_present = |name| args.is_present(name) || config_file.is_present(name);
     let mut vm_cfg = VmConfig::default();
 
     // Parse cmdline args which need to set in VmConfig
-    add_args_to_config!((args.value_of("name")), vm_cfg, add_name);
-    add_args_to_config!((args.value_of("machine")), vm_cfg, add_machine);
-    add_args_to_config!((args.value_of("accel")), vm_cfg, add_accel);
-    add_args_to_config!((args.value_of("memory")), vm_cfg, add_memory);
-    add_args_to_config!((args.value_of("mem-path")), vm_cfg, add_mem_path);
-    add_args_to_config!((args.value_of("smp")), vm_cfg, add_cpu);
-    add_args_to_config!((args.value_of("cpu")), vm_cfg, add_cpu_feature);
-    add_args_to_config!((args.value_of("kernel")), vm_cfg, add_kernel);
-    add_args_to_config!((args.value_of("initrd-file")), vm_cfg, add_initrd);
-    add_args_to_config!((args.value_of("serial")), vm_cfg, add_serial);
-    add_args_to_config!((args.value_of("incoming")), vm_cfg, add_incoming);
-    add_args_to_config!((args.value_of("vnc")), vm_cfg, add_vnc);
-    add_args_to_config!(
-        (args.is_present("no-shutdown")),
-        vm_cfg,
-        add_no_shutdown,
-        bool
-    );
+    add_args_to_config!((value_of("name")), vm_cfg, add_name);
+    add_args_to_config!((value_of("machine")), vm_cfg, add_machine);
+    add_args_to_config!((value_of("accel")), vm_cfg, add_accel);
+    add_args_to_config!((value_of("memory")), vm_cfg, add_memory);
+    add_args_to_config!((value_of("mem-path")), vm_cfg, add_mem_path);
+    add_args_to_config!((value_of("smp")), vm_cfg, add_cpu);
+    add_args_to_config!((value_of("cpu")), vm_cfg, add_cpu_feature);
+    add_args_to_config!((value_of("kernel")), vm_cfg, add_kernel);
+    add_args_to_config!((value_of("initrd-file")), vm_cfg, add_initrd);
+    add_args_to_config!((value_of("incoming")), vm_cfg, add_incoming);
+    add_args_to_config!((value_of("gdb")), vm_cfg, add_gdb);
+    add_args_to_config!((value_of("rtc")), vm_cfg, add_rtc);
+    add_args_to_config!((value_of("vcpu-liveness")), vm_cfg, add_vcpu_liveness);
+    add_args_to_config!((value_of("metrics")), vm_cfg, add_metrics);
+    add_args_to_config!((value_of("guest-agent")), vm_cfg, add_guest_agent);
+    add_args_to_config!((value_of("vnc")), vm_cfg, add_vnc);
+    add_args_to_config!((is_present("no-shutdown")), vm_cfg, add_no_shutdown, bool);
     add_args_to_config!(
-        (args.is_present("mem-prealloc")),
+        (is_present("mem-prealloc")),
         vm_cfg,
         enable_mem_prealloc,
         bool
     );
+    add_args_to_config!((is_present("mem-lock")), vm_cfg, enable_mem_lock, bool);
     add_args_to_config!(
-        (args.values_of("kernel-cmdline")),
+        (args
+            .values_of("kernel-cmdline")
+            .or_else(|| config_file.values_of("kernel-cmdline"))),
         vm_cfg,
         add_kernel_cmdline,
         vec
     );
-    add_args_to_config_multi!((args.values_of("drive")), vm_cfg, add_drive);
-    add_args_to_config_multi!((args.values_of("object")), vm_cfg, add_object);
-    add_args_to_config_multi!((args.values_of("netdev")), vm_cfg, add_netdev);
-    add_args_to_config_multi!((args.values_of("chardev")), vm_cfg, add_chardev);
-    add_args_to_config_multi!((args.values_of("device")), vm_cfg, add_device);
-    add_args_to_config_multi!((args.values_of("global")), vm_cfg, add_global_config);
-    add_args_to_config_multi!((args.values_of("numa")), vm_cfg, add_numa);
+    add_args_to_config_multi!((values_of("drive")), vm_cfg, add_drive);
+    add_args_to_config_multi!((values_of("object")), vm_cfg, add_object);
+    add_args_to_config_multi!((values_of("netdev")), vm_cfg, add_netdev);
+    add_args_to_config_multi!((values_of("chardev")), vm_cfg, add_chardev);
+    // Serial may refer to a chardev or tls-creds object, parse it after them.
+    add_args_to_config!((value_of("serial")), vm_cfg, add_serial);
+    add_args_to_config_multi!((values_of("device")), vm_cfg, add_device);
+    add_args_to_config_multi!((values_of("global")), vm_cfg, add_global_config);
+    add_args_to_config_multi!((values_of("numa")), vm_cfg, add_numa);
+    add_args_to_config_multi!((values_of("fw_cfg")), vm_cfg, add_fw_cfg);
+    add_args_to_config_multi!((values_of("acpitable")), vm_cfg, add_acpi_table);
+    add_args_to_config_multi!((values_of("vcpu-affinity")), vm_cfg, add_vcpu_affinity);
+    add_args_to_config!((value_of("hotplug-manifest")), vm_cfg, add_hotplug_manifest);
+    vm_cfg
+        .assign_pci_addr()
+        .with_context(|| "Failed to assign pci address")?;
 
     if let Some(s) = args.value_of("trace") {
         add_trace_events(&s)?;
@@ -600,7 +732,32 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
     Ok(listeners)
 }
 
-fn bind_socket(path: String) -> Result<UnixListener> {
+/// Parse the config of `-control-socket`, and get the socket path and whether the socket
+/// is read-only.
+///
+/// # Arguments
+///
+/// * `config` - The value of `-control-socket`.
+pub fn parse_control_socket(config: &str) -> Result<(String, bool)> {
+    let mut cmd_parser = CmdParser::new("control-socket");
+    cmd_parser.push("").push("readonly");
+
+    cmd_parser.parse(config)?;
+    let path = cmd_parser
+        .get_value::<String>("")?
+        .with_context(|| "No path found for control socket")?;
+    let read_only = cmd_parser
+        .get_value::<ExBool>("readonly")?
+        .map_or(false, bool::from);
+    Ok((path, read_only))
+}
+
+/// Bind a unix socket for QMP, the socket file is removed when VM exits.
+///
+/// # Arguments
+///
+/// * `path` - Path of the socket file.
+pub fn bind_socket(path: String) -> Result<UnixListener> {
     let listener = UnixListener::bind(&path)
         .with_context(|| format!("Failed to bind socket file {}", &path))?;
     // Add file to temporary pool, so it could be cleaned when vm exits.
@@ -0,0 +1,150 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{CmdParser, ConfigError, VmConfig, MAX_PATH_LENGTH};
+
+/// Length of the common ACPI table header.
+const ACPI_TABLE_HEADER_LEN: usize = 36;
+/// Max size of the ACPI table specified by user.
+pub const MAX_ACPI_TABLE_SIZE: usize = 1024 * 1024;
+/// Signatures of the ACPI tables which are built by StratoVirt, and can't be added by user.
+const RESERVED_ACPI_TABLES: [&[u8; 4]; 13] = [
+    b"RSDT", b"XSDT", b"FACP", b"FACS", b"DSDT", b"APIC", b"MCFG", b"SRAT", b"SLIT", b"PPTT",
+    b"GTDT", b"IORT", b"SPCR",
+];
+
+/// Config of the ACPI table specified by user, such as SLIC or OEM specific tables.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcpiTableConfig {
+    /// Path of the file which contains the whole ACPI table, including the header.
+    pub file: String,
+}
+
+impl AcpiTableConfig {
+    /// Read the ACPI table from file, and check its header. The checksum is not checked,
+    /// as it is recalculated by firmware when the table is loaded.
+    pub fn get_data(&self) -> Result<Vec<u8>> {
+        let data = std::fs::read(&self.file)
+            .with_context(|| format!("Failed to read ACPI table file {}", self.file))?;
+        if data.len() < ACPI_TABLE_HEADER_LEN || data.len() > MAX_ACPI_TABLE_SIZE {
+            bail!(
+                "Size of ACPI table {} is {} bytes, which should be between {} and {} bytes",
+                self.file,
+                data.len(),
+                ACPI_TABLE_HEADER_LEN,
+                MAX_ACPI_TABLE_SIZE
+            );
+        }
+
+        let signature = &data[0..4];
+        if !signature
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || *c == b'_')
+        {
+            bail!(
+                "Invalid signature {:?} of ACPI table {}",
+                signature,
+                self.file
+            );
+        }
+        if RESERVED_ACPI_TABLES.iter().any(|s| &s[..] == signature) {
+            bail!(
+                "ACPI table {} is built by StratoVirt and can't be added",
+                String::from_utf8_lossy(signature)
+            );
+        }
+
+        let length = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        if length != data.len() {
+            bail!(
+                "Length {} in the header of ACPI table {} mismatches file size {}",
+                length,
+                self.file,
+                data.len()
+            );
+        }
+
+        Ok(data)
+    }
+}
+
+impl VmConfig {
+    /// Add ACPI table: "-acpitable file=<path>".
+    pub fn add_acpi_table(&mut self, acpi_table_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("acpitable");
+        cmd_parser.push("file");
+        cmd_parser.parse(acpi_table_config)?;
+
+        let file = if let Some(file) = cmd_parser.get_value::<String>("file")? {
+            file
+        } else {
+            return Err(anyhow!(ConfigError::FieldIsMissing("file", "acpitable")));
+        };
+        if file.len() > MAX_PATH_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "acpitable file path".to_string(),
+                MAX_PATH_LENGTH,
+            )));
+        }
+
+        let acpi_table = AcpiTableConfig { file };
+        acpi_table.get_data()?;
+        self.acpi_tables.push(acpi_table);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_table(path: &str, signature: &[u8; 4], length: u32, size: usize) {
+        let mut data = vec![0_u8; size];
+        data[0..4].copy_from_slice(signature);
+        data[4..8].copy_from_slice(&length.to_le_bytes());
+        std::fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_add_acpi_table() {
+        let path = "/tmp/test_add_acpi_table";
+        let mut vm_config = VmConfig::default();
+
+        create_table(path, b"SLIC", 374, 374);
+        assert!(vm_config.add_acpi_table(&format!("file={}", path)).is_ok());
+        assert_eq!(vm_config.acpi_tables.len(), 1);
+        assert_eq!(vm_config.acpi_tables[0].get_data().unwrap().len(), 374);
+
+        // Length in header mismatches the file size.
+        create_table(path, b"SLIC", 375, 374);
+        assert!(vm_config.add_acpi_table(&format!("file={}", path)).is_err());
+        // Truncated header.
+        create_table(path, b"SLIC", 35, 35);
+        assert!(vm_config.add_acpi_table(&format!("file={}", path)).is_err());
+        // Invalid or reserved signature.
+        create_table(path, b"sl\0c", 36, 36);
+        assert!(vm_config.add_acpi_table(&format!("file={}", path)).is_err());
+        create_table(path, b"DSDT", 36, 36);
+        assert!(vm_config.add_acpi_table(&format!("file={}", path)).is_err());
+        // Missing or nonexistent file.
+        assert!(vm_config.add_acpi_table("sig=SLIC").is_err());
+        assert!(vm_config
+            .add_acpi_table("file=/tmp/test_add_acpi_table_nonexistent")
+            .is_err());
+        assert_eq!(vm_config.acpi_tables.len(), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
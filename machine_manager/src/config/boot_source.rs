@@ -15,9 +15,14 @@ use std::path::PathBuf;
 
 use super::error::ConfigError;
 use crate::config::{ConfigCheck, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 
+/// Max length of kernel cmdline without the terminating NUL. Linux kernel limits the cmdline
+/// to `COMMAND_LINE_SIZE` (2048 bytes including the NUL) on both x86_64, where it's passed by
+/// boot protocol, and aarch64, where it's passed by `bootargs` of FDT `chosen` node.
+pub const MAX_KERNEL_CMDLINE_LEN: usize = 2047;
+
 /// Config struct for boot-source.
 /// Contains `kernel_file`, `kernel_cmdline` and `initrd`.
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
@@ -118,6 +123,20 @@ impl ConfigCheck for KernelParams {
             }
         }
 
+        let cmdline = self.to_string();
+        if cmdline.len() > MAX_KERNEL_CMDLINE_LEN {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "kernel cmdline".to_string(),
+                MAX_KERNEL_CMDLINE_LEN,
+            )));
+        }
+        if let Some(c) = cmdline.chars().find(|c| !c.is_ascii_graphic() && *c != ' ') {
+            bail!(
+                "Invalid character {:?} in kernel cmdline, only printable ASCII characters are allowed",
+                c
+            );
+        }
+
         Ok(())
     }
 }
@@ -267,6 +286,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_kernel_params_check() {
+        // 7 params of 255 bytes, and the spaces between them.
+        let mut params = vec!["a".repeat(MAX_STRING_LENGTH); 7];
+        params.push("b".repeat(MAX_KERNEL_CMDLINE_LEN - 7 * (MAX_STRING_LENGTH + 1)));
+        let kernel_params = KernelParams::from_str(params.join(" "));
+        assert_eq!(kernel_params.to_string().len(), MAX_KERNEL_CMDLINE_LEN);
+        assert!(kernel_params.check().is_ok());
+
+        params.push("c".to_string());
+        params[7].pop();
+        let kernel_params = KernelParams::from_str(params.join(" "));
+        assert_eq!(kernel_params.to_string().len(), MAX_KERNEL_CMDLINE_LEN + 1);
+        assert!(kernel_params.check().is_err());
+
+        let kernel_params = KernelParams::from_str("console=ttyS0 root=/dev/vda".to_string());
+        assert!(kernel_params.check().is_ok());
+        let kernel_params = KernelParams::from_str("console=ttyS0\troot=/dev/vda".to_string());
+        assert!(kernel_params.check().is_err());
+        let kernel_params = KernelParams::from_str("console=ttyS0 root=/dev/vdé".to_string());
+        assert!(kernel_params.check().is_err());
+    }
+
     #[test]
     fn test_bootsource_cmdline_parser() {
         let kernel_path = String::from("vmlinux.bin");
@@ -14,12 +14,19 @@ use anyhow::{anyhow, bail, Context, Result};
 use log::error;
 use serde::{Deserialize, Serialize};
 
-use super::{error::ConfigError, get_pci_bdf, pci_args_check, PciBdf};
-use crate::config::{CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH};
+use super::{error::ConfigError, get_pci_bdf, pci_args_check, PciBdf, TlsCredObjConfig};
+use crate::config::{
+    CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH, MAX_SOCK_PATH_LENGTH,
+    MAX_STRING_LENGTH,
+};
 use crate::qmp::qmp_schema;
 
 const MAX_GUEST_CID: u64 = 4_294_967_295;
 const MIN_GUEST_CID: u64 = 3;
+const MIN_SERIAL_BAUD: u64 = 50;
+const MAX_SERIAL_BAUD: u64 = 115_200;
+const DEFAULT_RINGBUF_SIZE: u64 = 65_536;
+const MAX_RINGBUF_SIZE: u64 = 16 * 1024 * 1024;
 
 /// Charecter device options.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -31,7 +38,19 @@ pub enum ChardevType {
         server: bool,
         nowait: bool,
     },
+    /// Tcp socket, which optionally speaks telnet protocol with the client.
+    Tcp {
+        host: String,
+        port: u16,
+        server: bool,
+        nowait: bool,
+        telnet: bool,
+    },
     File(String),
+    /// Fixed-size ring buffer keeping the latest output, the oldest data is overwritten.
+    Ringbuf {
+        size: u64,
+    },
 }
 
 /// Config structure for virtio-console.
@@ -39,6 +58,8 @@ pub enum ChardevType {
 pub struct VirtioConsole {
     pub id: String,
     pub chardev: ChardevConfig,
+    /// Name of the port, exposed to guest as `/dev/virtio-ports/<name>`.
+    pub name: Option<String>,
 }
 
 /// Config structure for character device.
@@ -46,6 +67,8 @@ pub struct VirtioConsole {
 pub struct ChardevConfig {
     pub id: String,
     pub backend: ChardevType,
+    /// Tls credentials to encrypt the connections of socket-type chardev.
+    pub tls_creds: Option<TlsCredObjConfig>,
 }
 
 impl ConfigCheck for ChardevConfig {
@@ -57,6 +80,15 @@ impl ConfigCheck for ChardevConfig {
             )));
         }
 
+        if let ChardevType::Tcp { host, .. } = &self.backend {
+            if host.len() > MAX_STRING_LENGTH {
+                return Err(anyhow!(ConfigError::StringLengthTooLong(
+                    "socket host".to_string(),
+                    MAX_STRING_LENGTH
+                )));
+            }
+        }
+
         let len = match &self.backend {
             ChardevType::Socket { path, .. } => path.len(),
             ChardevType::File(path) => path.len(),
@@ -78,8 +110,15 @@ fn check_chardev_args(cmd_parser: CmdParser) -> Result<()> {
         let chardev_str = chardev_type.as_str();
         let server = cmd_parser.get_value::<String>("server")?;
         let nowait = cmd_parser.get_value::<String>("nowait")?;
+        let telnet = cmd_parser.get_value::<String>("telnet")?;
+        if chardev_str != "ringbuf" && cmd_parser.get_value::<String>("size")?.is_some() {
+            bail!(
+                "Chardev of {}-type does not support \'size\' argument",
+                chardev_str
+            );
+        }
         match chardev_str {
-            "stdio" | "pty" | "file" => {
+            "stdio" | "pty" | "file" | "ringbuf" => {
                 if server.is_some() {
                     bail!(
                         "Chardev of {}-type does not support \'server\' argument",
@@ -92,6 +131,12 @@ fn check_chardev_args(cmd_parser: CmdParser) -> Result<()> {
                         chardev_str
                     );
                 }
+                if telnet.is_some() {
+                    bail!(
+                        "Chardev of {}-type does not support \'telnet\' argument",
+                        chardev_str
+                    );
+                }
             }
             "socket" => {
                 if let Some(server) = server {
@@ -104,6 +149,11 @@ fn check_chardev_args(cmd_parser: CmdParser) -> Result<()> {
                         bail!("No parameter needed for nowait");
                     }
                 }
+                if let Some(telnet) = telnet {
+                    if telnet.ne("") {
+                        bail!("No parameter needed for telnet");
+                    }
+                }
             }
             _ => (),
         }
@@ -135,12 +185,41 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     } else {
         false
     };
+    let host = cmd_parser.get_value::<String>("host")?;
+    let port = cmd_parser.get_value::<u16>("port")?;
+    let telnet = cmd_parser.get_value::<String>("telnet")?.is_some();
+    let size = cmd_parser.get_size("size")?;
     check_chardev_args(cmd_parser)?;
     let chardev_type = if let Some(backend) = backend {
         match backend.as_str() {
             "stdio" => ChardevType::Stdio,
             "pty" => ChardevType::Pty,
+            "socket" if host.is_some() || port.is_some() => {
+                if path.is_some() {
+                    bail!("Argument \'path\' can't be set together with \'host\' and \'port\'");
+                }
+                let port = if let Some(port) = port {
+                    port
+                } else {
+                    return Err(anyhow!(ConfigError::FieldIsMissing(
+                        "port",
+                        "tcp-type chardev"
+                    )));
+                };
+                ChardevType::Tcp {
+                    host: host
+                        .filter(|host| !host.is_empty())
+                        .unwrap_or_else(|| "127.0.0.1".to_string()),
+                    port,
+                    server,
+                    nowait,
+                    telnet,
+                }
+            }
             "socket" => {
+                if telnet {
+                    bail!("Argument \'telnet\' is only supported by tcp-type chardev");
+                }
                 if let Some(path) = path {
                     ChardevType::Socket {
                         path,
@@ -164,6 +243,19 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
                     )));
                 }
             }
+            "ringbuf" => {
+                let size = size.unwrap_or(DEFAULT_RINGBUF_SIZE);
+                if !(1..=MAX_RINGBUF_SIZE).contains(&size) {
+                    return Err(anyhow!(ConfigError::IllegalValue(
+                        "size of ringbuf-type chardev".to_string(),
+                        1,
+                        true,
+                        MAX_RINGBUF_SIZE,
+                        true,
+                    )));
+                }
+                ChardevType::Ringbuf { size }
+            }
             _ => {
                 return Err(anyhow!(ConfigError::InvalidParam(
                     backend,
@@ -178,6 +270,7 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     Ok(ChardevConfig {
         id: chardev_id,
         backend: chardev_type,
+        tls_creds: None,
     })
 }
 
@@ -220,6 +313,7 @@ pub fn get_chardev_config(args: qmp_schema::CharDevAddArgument) -> Result<Charde
             server: data.server,
             nowait: false,
         },
+        tls_creds: None,
     })
 }
 
@@ -256,7 +350,7 @@ pub fn get_chardev_socket_path(chardev: &str, vm_config: &mut VmConfig) -> Resul
 
 pub fn parse_virtconsole(vm_config: &mut VmConfig, config_args: &str) -> Result<VirtioConsole> {
     let mut cmd_parser = CmdParser::new("virtconsole");
-    cmd_parser.push("").push("id").push("chardev");
+    cmd_parser.push("").push("id").push("chardev").push("name");
     cmd_parser.parse(config_args)?;
 
     let chardev_name = if let Some(chardev) = cmd_parser.get_value::<String>("chardev")? {
@@ -278,6 +372,7 @@ pub fn parse_virtconsole(vm_config: &mut VmConfig, config_args: &str) -> Result<
         return Ok(VirtioConsole {
             id,
             chardev: char_dev,
+            name: cmd_parser.get_value::<String>("name")?,
         });
     }
     bail!("Chardev {:?} not found or is in use", &chardev_name);
@@ -291,12 +386,21 @@ impl VmConfig {
             .push("")
             .push("id")
             .push("path")
+            .push("host")
+            .push("port")
             .push("server")
-            .push("nowait");
+            .push("nowait")
+            .push("telnet")
+            .push("size")
+            .push("tls-creds");
 
         cmd_parser.parse(chardev_config)?;
 
-        let chardev = parse_chardev(cmd_parser)?;
+        let tls_creds = cmd_parser.get_value::<String>("tls-creds")?;
+        let mut chardev = parse_chardev(cmd_parser)?;
+        if let Some(tls_creds) = tls_creds {
+            chardev.tls_creds = Some(self.get_chardev_tls_creds(&chardev, &tls_creds)?);
+        }
         chardev.check()?;
         let chardev_id = chardev.id.clone();
         if self.chardev.get(&chardev_id).is_none() {
@@ -307,6 +411,36 @@ impl VmConfig {
         Ok(())
     }
 
+    fn get_chardev_tls_creds(
+        &self,
+        chardev: &ChardevConfig,
+        tls_creds: &str,
+    ) -> Result<TlsCredObjConfig> {
+        if !matches!(
+            chardev.backend,
+            ChardevType::Socket { .. } | ChardevType::Tcp { .. }
+        ) {
+            bail!(
+                "Tls-creds is only supported by socket-type chardev, chardev \'{}\'",
+                chardev.id
+            );
+        }
+        let tls_cred = if let Some(tls_cred) = self.object.tls_object.get(tls_creds) {
+            tls_cred
+        } else {
+            bail!("Tls-creds \'{}\' not found for chardev", tls_creds);
+        };
+        if let Some(endpoint) = &tls_cred.endpoint {
+            if endpoint != "server" {
+                bail!(
+                    "Tls-creds \'{}\' must be server endpoint for chardev",
+                    tls_creds
+                );
+            }
+        }
+        Ok(tls_cred.clone())
+    }
+
     /// Add chardev config to vm config.
     ///
     /// # Arguments
@@ -345,12 +479,79 @@ impl VmConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SerialConfig {
     pub chardev: ChardevConfig,
+    /// Hold input data until the guest asserts RTS.
+    pub flow_control: bool,
+    /// Emulated baud rate used to pace output.
+    pub baud: Option<u32>,
 }
 
 impl VmConfig {
     pub fn add_serial(&mut self, serial_config: &str) -> Result<()> {
+        // Take the serial options away, the rest describes the chardev.
+        let mut flow_control = false;
+        let mut baud = None;
+        let mut chardev_args = Vec::new();
+        for arg in serial_config.split(',') {
+            if let Some(value) = arg.strip_prefix("flow-control=") {
+                flow_control = value
+                    .parse::<ExBool>()
+                    .map_err(|_| {
+                        anyhow!(ConfigError::ConvertValueFailed(
+                            "flow-control".to_string(),
+                            value.to_string()
+                        ))
+                    })?
+                    .into();
+            } else if let Some(value) = arg.strip_prefix("baud=") {
+                let value = value.parse::<u32>().map_err(|_| {
+                    anyhow!(ConfigError::ConvertValueFailed(
+                        "baud".to_string(),
+                        value.to_string()
+                    ))
+                })?;
+                if !(MIN_SERIAL_BAUD..=MAX_SERIAL_BAUD).contains(&(value as u64)) {
+                    return Err(anyhow!(ConfigError::IllegalValue(
+                        "baud of serial".to_string(),
+                        MIN_SERIAL_BAUD,
+                        true,
+                        MAX_SERIAL_BAUD,
+                        true,
+                    )));
+                }
+                baud = Some(value);
+            } else {
+                chardev_args.push(arg);
+            }
+        }
+        let serial_config = chardev_args.join(",");
+
         let parse_vec: Vec<&str> = serial_config.split(':').collect();
         let chardev_id = match parse_vec[0] {
+            // Tcp socket in the form of `tcp:<host>:<port>[,<options>]`.
+            "tcp" | "telnet" => {
+                if parse_vec.len() != 3 {
+                    return Err(anyhow!(ConfigError::InvalidParam(
+                        serial_config.to_string(),
+                        "serial".to_string(),
+                    )));
+                }
+                let (port, options) = match parse_vec[2].split_once(',') {
+                    Some((port, options)) => (port, format!(",{}", options)),
+                    None => (parse_vec[2], String::new()),
+                };
+                let telnet = if parse_vec[0] == "telnet" {
+                    ",telnet"
+                } else {
+                    ""
+                };
+                let chardev_config = format!(
+                    "socket,id=serial_chardev,host={},port={}{}{}",
+                    parse_vec[1], port, telnet, options
+                );
+                self.add_chardev(&chardev_config)
+                    .with_context(|| "Failed to add chardev")?;
+                "serial_chardev"
+            }
             "chardev" => {
                 if parse_vec.len() == 2 {
                     parse_vec[1]
@@ -369,11 +570,30 @@ impl VmConfig {
             }
         };
         if let Some(char_dev) = self.chardev.remove(chardev_id) {
-            self.serial = Some(SerialConfig { chardev: char_dev });
+            self.serial = Some(SerialConfig {
+                chardev: char_dev,
+                flow_control,
+                baud,
+            });
             return Ok(());
         }
         bail!("Chardev {:?} not found or is in use", chardev_id);
     }
+
+    /// Add guest agent channel: "-guest-agent <socket path>".
+    pub fn add_guest_agent(&mut self, socket_path: &str) -> Result<()> {
+        if socket_path.is_empty() {
+            bail!("Socket path of guest agent channel is empty");
+        }
+        if socket_path.len() > MAX_SOCK_PATH_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "guest agent socket path".to_string(),
+                MAX_SOCK_PATH_LENGTH,
+            )));
+        }
+        self.guest_agent = Some(socket_path.to_string());
+        Ok(())
+    }
 }
 
 /// Config structure for virtio-vsock.
@@ -407,7 +627,37 @@ impl ConfigCheck for VsockConfig {
     }
 }
 
-pub fn parse_vsock(vsock_config: &str) -> Result<VsockConfig> {
+/// Parse the config of vsock device, and check that its guest-cid is not used by other
+/// vsock devices of the vm.
+///
+/// # Arguments
+///
+/// * `vm_config` - VM configuration.
+/// * `vsock_config` - Config args of the vsock device.
+pub fn parse_vsock(vm_config: &VmConfig, vsock_config: &str) -> Result<VsockConfig> {
+    let vsock = parse_vsock_args(vsock_config)?;
+    vsock.check()?;
+
+    for (dev_type, dev_config) in vm_config.devices.iter() {
+        if dev_type != "vhost-vsock-pci" && dev_type != "vhost-vsock-device" {
+            continue;
+        }
+        // Invalid config of other vsock devices is reported when they are parsed.
+        if let Ok(other) = parse_vsock_args(dev_config) {
+            if other.id != vsock.id && other.guest_cid == vsock.guest_cid {
+                bail!(
+                    "Vsock guest-cid {} is used by both {} and {}",
+                    vsock.guest_cid,
+                    other.id,
+                    vsock.id
+                );
+            }
+        }
+    }
+    Ok(vsock)
+}
+
+fn parse_vsock_args(vsock_config: &str) -> Result<VsockConfig> {
     let mut cmd_parser = CmdParser::new("vhost-vsock");
     cmd_parser
         .push("")
@@ -524,6 +774,7 @@ mod tests {
         assert!(virt_console.is_ok());
         let console_cfg = virt_console.unwrap();
         assert_eq!(console_cfg.id, "console1");
+        assert_eq!(console_cfg.name, None);
         assert_eq!(
             console_cfg.chardev.backend,
             ChardevType::Socket {
@@ -533,6 +784,18 @@ mod tests {
             }
         );
 
+        let mut vm_config = VmConfig::default();
+        assert!(parse_virtio_serial(&mut vm_config, "virtio-serial-device").is_ok());
+        assert!(vm_config
+            .add_chardev("socket,id=test_port,path=/path/to/socket,server,nowait")
+            .is_ok());
+        let console_cfg = parse_virtconsole(
+            &mut vm_config,
+            "virtconsole,chardev=test_port,id=port1,name=org.qemu.guest_agent.0",
+        )
+        .unwrap();
+        assert_eq!(console_cfg.name, Some("org.qemu.guest_agent.0".to_string()));
+
         let mut vm_config = VmConfig::default();
         assert!(
             parse_virtio_serial(&mut vm_config, "virtio-serial-device,bus=pcie.0,addr=0x1")
@@ -597,7 +860,8 @@ mod tests {
 
     #[test]
     fn test_vsock_config_cmdline_parser() {
-        let vsock_cfg_op = parse_vsock("vhost-vsock-device,id=test_vsock,guest-cid=3");
+        let vm_config = VmConfig::default();
+        let vsock_cfg_op = parse_vsock(&vm_config, "vhost-vsock-device,id=test_vsock,guest-cid=3");
         assert!(vsock_cfg_op.is_ok());
 
         let vsock_config = vsock_cfg_op.unwrap();
@@ -606,7 +870,10 @@ mod tests {
         assert_eq!(vsock_config.vhost_fd, None);
         assert!(vsock_config.check().is_ok());
 
-        let vsock_cfg_op = parse_vsock("vhost-vsock-device,id=test_vsock,guest-cid=3,vhostfd=4");
+        let vsock_cfg_op = parse_vsock(
+            &vm_config,
+            "vhost-vsock-device,id=test_vsock,guest-cid=3,vhostfd=4",
+        );
         assert!(vsock_cfg_op.is_ok());
 
         let vsock_config = vsock_cfg_op.unwrap();
@@ -614,6 +881,24 @@ mod tests {
         assert_eq!(vsock_config.guest_cid, 3);
         assert_eq!(vsock_config.vhost_fd, Some(4));
         assert!(vsock_config.check().is_ok());
+
+        // Guest-cid 0, 1 and 2 are reserved.
+        assert!(parse_vsock(&vm_config, "vhost-vsock-device,id=test_vsock,guest-cid=2").is_err());
+
+        // Guest-cid is used by another vsock device.
+        let mut vm_config = VmConfig::default();
+        let vsock0 = "vhost-vsock-pci,id=vsock0,guest-cid=3,bus=pcie.0,addr=0x3";
+        let vsock1 = "vhost-vsock-pci,id=vsock1,guest-cid=3,bus=pcie.0,addr=0x4";
+        vm_config.add_device(vsock0).unwrap();
+        assert!(parse_vsock(&vm_config, vsock0).is_ok());
+        vm_config.add_device(vsock1).unwrap();
+        assert!(parse_vsock(&vm_config, vsock0).is_err());
+        assert!(parse_vsock(&vm_config, vsock1).is_err());
+        assert!(parse_vsock(
+            &vm_config,
+            "vhost-vsock-pci,id=vsock2,guest-cid=4,bus=pcie.0,addr=0x5"
+        )
+        .is_ok());
     }
 
     #[test]
@@ -637,5 +922,180 @@ mod tests {
         } else {
             assert!(false);
         }
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_chardev("socket,id=test_id,host=127.0.0.1,port=4444,server,nowait,telnet")
+            .is_ok());
+        assert_eq!(
+            vm_config.chardev["test_id"].backend,
+            ChardevType::Tcp {
+                host: "127.0.0.1".to_string(),
+                port: 4444,
+                server: true,
+                nowait: true,
+                telnet: true,
+            }
+        );
+        // Port is required by tcp-type chardev.
+        assert!(vm_config
+            .add_chardev("socket,id=test_id1,host=127.0.0.1,server,nowait")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=test_id1,path=/path/to/socket,port=4444")
+            .is_err());
+        // Telnet is only supported by tcp-type chardev.
+        assert!(vm_config
+            .add_chardev("socket,id=test_id1,path=/path/to/socket,server,nowait,telnet")
+            .is_err());
+        assert!(vm_config.add_chardev("pty,id=test_id1,telnet").is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_chardev("ringbuf,id=test_id").is_ok());
+        assert!(vm_config.add_chardev("ringbuf,id=test_id1,size=1M").is_ok());
+        assert_eq!(
+            vm_config.chardev["test_id"].backend,
+            ChardevType::Ringbuf { size: 65536 }
+        );
+        assert_eq!(
+            vm_config.chardev["test_id1"].backend,
+            ChardevType::Ringbuf { size: 1048576 }
+        );
+        assert!(vm_config.add_chardev("ringbuf,id=test_id2,size=0").is_err());
+        assert!(vm_config
+            .add_chardev("ringbuf,id=test_id2,size=32M")
+            .is_err());
+        assert!(vm_config.add_chardev("ringbuf,id=test_id2,server").is_err());
+        // Size is only supported by ringbuf-type chardev.
+        assert!(vm_config.add_chardev("pty,id=test_id2,size=64K").is_err());
+    }
+
+    #[test]
+    fn test_chardev_tls_creds() {
+        let mut vm_config = VmConfig::default();
+        // Tls-creds object is not added.
+        assert!(vm_config
+            .add_chardev("socket,id=test_id,path=/path/to/socket,server,nowait,tls-creds=tls0")
+            .is_err());
+
+        let tmp_dir = std::env::temp_dir();
+        let tls_config = format!(
+            "tls-creds-x509,id=tls0,dir={},endpoint=server",
+            tmp_dir.to_str().unwrap()
+        );
+        vm_config.add_object(&tls_config).unwrap();
+        let tls_config = format!(
+            "tls-creds-x509,id=tls1,dir={},endpoint=client",
+            tmp_dir.to_str().unwrap()
+        );
+        vm_config.add_object(&tls_config).unwrap();
+
+        // Only socket-type chardev supports tls.
+        assert!(vm_config
+            .add_chardev("stdio,id=test_id,tls-creds=tls0")
+            .is_err());
+        // Tls-creds must be server endpoint.
+        assert!(vm_config
+            .add_chardev("socket,id=test_id,path=/path/to/socket,server,nowait,tls-creds=tls1")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=test_id,path=/path/to/socket,server,nowait,tls-creds=tls0")
+            .is_ok());
+        let tls_creds = vm_config.chardev["test_id"].tls_creds.as_ref().unwrap();
+        assert_eq!(tls_creds.id, "tls0");
+
+        assert!(vm_config
+            .add_serial("socket,path=/path/to/serial,server,nowait,tls-creds=tls0")
+            .is_ok());
+        assert!(vm_config.serial.unwrap().chardev.tls_creds.is_some());
+
+        // Tcp-type chardev supports tls too.
+        let mut vm_config = VmConfig::default();
+        for (id, endpoint) in [("tls0", "server"), ("tls1", "client")] {
+            let tls_config = format!(
+                "tls-creds-x509,id={},dir={},endpoint={}",
+                id,
+                tmp_dir.to_str().unwrap(),
+                endpoint
+            );
+            vm_config.add_object(&tls_config).unwrap();
+        }
+        assert!(vm_config
+            .add_chardev("socket,id=test_id,port=4444,server,nowait,tls-creds=tls1")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=test_id,port=4444,server,nowait,telnet,tls-creds=tls0")
+            .is_ok());
+        assert!(vm_config.chardev["test_id"].tls_creds.is_some());
+    }
+
+    #[test]
+    fn test_serial_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_serial("stdio").is_ok());
+        let serial = vm_config.serial.as_ref().unwrap();
+        assert_eq!(serial.chardev.backend, ChardevType::Stdio);
+        assert!(!serial.flow_control);
+        assert_eq!(serial.baud, None);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_serial("socket,path=/path/to/socket,server,nowait,flow-control=on,baud=9600")
+            .is_ok());
+        let serial = vm_config.serial.as_ref().unwrap();
+        assert!(serial.flow_control);
+        assert_eq!(serial.baud, Some(9600));
+
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_chardev("socket,id=test_id,path=/path/to/socket")
+            .unwrap();
+        assert!(vm_config.add_serial("chardev:test_id,baud=115200").is_ok());
+        assert_eq!(vm_config.serial.as_ref().unwrap().baud, Some(115200));
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_serial("tcp::4444,server,nowait,baud=9600")
+            .is_ok());
+        let serial = vm_config.serial.as_ref().unwrap();
+        assert_eq!(
+            serial.chardev.backend,
+            ChardevType::Tcp {
+                host: "127.0.0.1".to_string(),
+                port: 4444,
+                server: true,
+                nowait: true,
+                telnet: false,
+            }
+        );
+        assert_eq!(serial.baud, Some(9600));
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_serial("telnet:127.0.0.1:4444,server,nowait")
+            .is_ok());
+        assert!(matches!(
+            vm_config.serial.as_ref().unwrap().chardev.backend,
+            ChardevType::Tcp { telnet: true, .. }
+        ));
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_serial("tcp:127.0.0.1,server,nowait").is_err());
+        assert!(vm_config.add_serial("tcp:127.0.0.1:port").is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_serial("stdio,baud=0").is_err());
+        assert!(vm_config.add_serial("stdio,baud=230400").is_err());
+        assert!(vm_config.add_serial("stdio,flow-control=maybe").is_err());
+    }
+
+    #[test]
+    fn test_add_guest_agent() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.guest_agent.is_none());
+        assert!(vm_config.add_guest_agent("/path/to/qga.sock").is_ok());
+        assert_eq!(vm_config.guest_agent.as_deref(), Some("/path/to/qga.sock"));
+
+        assert!(vm_config.add_guest_agent("").is_err());
+        let path = format!("/{}", "a".repeat(MAX_SOCK_PATH_LENGTH));
+        assert!(vm_config.add_guest_agent(&path).is_err());
     }
 }
@@ -0,0 +1,236 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+
+/// Kind of the option in config file.
+enum OptionKind {
+    /// Option with a single value, which is overridden by command line.
+    Single,
+    /// Option with a list of values, the values in command line are appended.
+    Multiple,
+    /// Switch option, which is a bool in config file.
+    Switch,
+}
+
+/// Options supported by config file: the key in config file, which is the same as the
+/// command line option, the name of command line argument, and the kind of option.
+const CONFIG_FILE_OPTIONS: [(&str, &str, OptionKind); 32] = [
+    ("name", "name", OptionKind::Single),
+    ("machine", "machine", OptionKind::Single),
+    ("accel", "accel", OptionKind::Single),
+    ("m", "memory", OptionKind::Single),
+    ("mem-path", "mem-path", OptionKind::Single),
+    ("smp", "smp", OptionKind::Single),
+    ("cpu", "cpu", OptionKind::Single),
+    ("kernel", "kernel", OptionKind::Single),
+    ("append", "kernel-cmdline", OptionKind::Single),
+    ("initrd", "initrd-file", OptionKind::Single),
+    ("incoming", "incoming", OptionKind::Single),
+    ("gdb", "gdb", OptionKind::Single),
+    ("rtc", "rtc", OptionKind::Single),
+    ("vcpu-liveness", "vcpu-liveness", OptionKind::Single),
+    ("metrics", "metrics", OptionKind::Single),
+    ("guest-agent", "guest-agent", OptionKind::Single),
+    ("vnc", "vnc", OptionKind::Single),
+    ("serial", "serial", OptionKind::Single),
+    ("hotplug-manifest", "hotplug-manifest", OptionKind::Single),
+    ("no-shutdown", "no-shutdown", OptionKind::Switch),
+    ("mem-prealloc", "mem-prealloc", OptionKind::Switch),
+    ("mem-lock", "mem-lock", OptionKind::Switch),
+    ("drive", "drive", OptionKind::Multiple),
+    ("object", "object", OptionKind::Multiple),
+    ("netdev", "netdev", OptionKind::Multiple),
+    ("chardev", "chardev", OptionKind::Multiple),
+    ("device", "device", OptionKind::Multiple),
+    ("global", "global", OptionKind::Multiple),
+    ("numa", "numa", OptionKind::Multiple),
+    ("fw_cfg", "fw_cfg", OptionKind::Multiple),
+    ("acpitable", "acpitable", OptionKind::Multiple),
+    ("vcpu-affinity", "vcpu-affinity", OptionKind::Multiple),
+];
+
+/// VM definition loaded from a json config file. The options are converted to the
+/// same strings as command line, so that they are parsed and checked in the same way.
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    /// Values of the options, keyed by the name of command line argument.
+    values: HashMap<&'static str, Vec<String>>,
+}
+
+impl ConfigFile {
+    /// Load VM definition from the json config file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the config file.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path))?;
+        Self::from_json(&content).with_context(|| format!("Invalid config file {}", path))
+    }
+
+    fn from_json(content: &str) -> Result<Self> {
+        let json: Value = serde_json::from_str(content).with_context(|| "Failed to parse json")?;
+        let options = match json {
+            Value::Object(options) => options,
+            _ => bail!("VM definition must be a json object"),
+        };
+
+        let mut config_file = ConfigFile::default();
+        for (key, value) in options.iter() {
+            let (_, name, kind) = CONFIG_FILE_OPTIONS
+                .iter()
+                .find(|(option, _, _)| option == key)
+                .ok_or_else(|| anyhow!("Unsupported option {} in config file", key))?;
+            let values = match (kind, value) {
+                (OptionKind::Single, value) => vec![option_string(key, value)?],
+                (OptionKind::Multiple, Value::Array(items)) => items
+                    .iter()
+                    .map(|item| option_string(key, item))
+                    .collect::<Result<Vec<String>>>()?,
+                (OptionKind::Multiple, _) => bail!("Option {} must be an array", key),
+                (OptionKind::Switch, Value::Bool(true)) => Vec::new(),
+                (OptionKind::Switch, Value::Bool(false)) => continue,
+                (OptionKind::Switch, _) => bail!("Option {} must be a bool", key),
+            };
+            config_file.values.insert(name, values);
+        }
+        Ok(config_file)
+    }
+
+    /// Get the value of single-value option by the name of command line argument.
+    pub fn value_of(&self, name: &str) -> Option<String> {
+        self.values
+            .get(name)
+            .and_then(|values| values.first().cloned())
+    }
+
+    /// Get the values of multi-value option by the name of command line argument.
+    pub fn values_of(&self, name: &str) -> Option<Vec<String>> {
+        self.values.get(name).cloned()
+    }
+
+    /// Whether the switch option is set by the name of command line argument.
+    pub fn is_present(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+}
+
+/// Convert the value of option in config file to the string of command line. An object
+/// is converted to comma separated properties, in which the `type` property is the first
+/// without key, e.g. the driver of device, and `true` is a switch property without value.
+fn option_string(key: &str, value: &Value) -> Result<String> {
+    let props = match value {
+        Value::Object(props) => props,
+        value => return scalar_string(value).with_context(|| format!("Invalid option {}", key)),
+    };
+
+    let mut items = Vec::new();
+    if let Some(value) = props.get("type") {
+        items.push(scalar_string(value).with_context(|| format!("Invalid type of {}", key))?);
+    }
+    for (prop, value) in props.iter().filter(|(prop, _)| *prop != "type") {
+        match value {
+            Value::Bool(true) => items.push(prop.clone()),
+            Value::Bool(false) => (),
+            value => {
+                let value = scalar_string(value)
+                    .with_context(|| format!("Invalid property {} of {}", prop, key))?;
+                items.push(format!("{}={}", prop, value));
+            }
+        }
+    }
+    Ok(items.join(","))
+}
+
+fn scalar_string(value: &Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        value => bail!("Value {} should be a string or number", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MachineType, VmConfig};
+
+    #[test]
+    fn test_config_file_parser() {
+        let json = r#"
+        {
+            "machine": { "type": "microvm", "mem-share": "on" },
+            "m": "2G",
+            "smp": { "cpus": 4, "maxcpus": 4 },
+            "kernel": "/path/to/vmlinux",
+            "append": "console=ttyS0 root=/dev/vda",
+            "mem-prealloc": true,
+            "mem-lock": false,
+            "drive": [ "id=drive0,file=/path/to/rootfs,readonly=off" ],
+            "chardev": [ { "type": "socket", "id": "chardev0", "path": "/path/to/sock",
+                "server": true, "nowait": true } ],
+            "device": [ { "type": "virtio-blk-pci", "id": "blk0", "drive": "drive0",
+                "bus": "pcie.0", "addr": "0x2" } ]
+        }
+        "#;
+        let config_file = ConfigFile::from_json(json).unwrap();
+        assert_eq!(
+            config_file.value_of("machine").unwrap(),
+            "microvm,mem-share=on"
+        );
+        assert_eq!(config_file.value_of("smp").unwrap(), "cpus=4,maxcpus=4");
+        assert_eq!(
+            config_file.values_of("kernel-cmdline").unwrap(),
+            vec!["console=ttyS0 root=/dev/vda".to_string()]
+        );
+        assert!(config_file.is_present("mem-prealloc"));
+        assert!(!config_file.is_present("mem-lock"));
+        assert!(!config_file.is_present("no-shutdown"));
+        assert_eq!(
+            config_file.values_of("chardev").unwrap(),
+            vec!["socket,id=chardev0,nowait,path=/path/to/sock,server".to_string()]
+        );
+        assert_eq!(
+            config_file.values_of("device").unwrap(),
+            vec!["virtio-blk-pci,addr=0x2,bus=pcie.0,drive=drive0,id=blk0".to_string()]
+        );
+
+        // The options are parsed in the same way as command line.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_machine(&config_file.value_of("machine").unwrap())
+            .unwrap();
+        vm_config
+            .add_memory(&config_file.value_of("memory").unwrap())
+            .unwrap();
+        vm_config
+            .add_cpu(&config_file.value_of("smp").unwrap())
+            .unwrap();
+        assert_eq!(vm_config.machine_config.mach_type, MachineType::MicroVm);
+        assert!(vm_config.machine_config.mem_config.mem_share);
+        assert_eq!(vm_config.machine_config.mem_config.mem_size, 2 << 30);
+        assert_eq!(vm_config.machine_config.nr_cpus, 4);
+
+        // Invalid config files.
+        assert!(ConfigFile::from_json("[]").is_err());
+        assert!(ConfigFile::from_json(r#"{ "qmp": "unix:/path/to/sock" }"#).is_err());
+        assert!(ConfigFile::from_json(r#"{ "device": "virtio-blk-pci" }"#).is_err());
+        assert!(ConfigFile::from_json(r#"{ "mem-lock": "on" }"#).is_err());
+        assert!(ConfigFile::from_json(r#"{ "m": ["2G"] }"#).is_err());
+        assert!(ConfigFile::from_json(r#"{ "drive": [ { "id": ["drive0"] } ] }"#).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use serde::{Deserialize, Serialize};
+
+/// Output register of CPUID instruction.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CpuidReg {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// Location of a x86_64 cpu feature flag in CPUID.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CpuidFeature {
+    /// CPUID leaf, the input value of EAX.
+    pub function: u32,
+    /// CPUID sub-leaf, the input value of ECX.
+    pub index: u32,
+    /// Output register which holds the flag.
+    pub reg: CpuidReg,
+    /// Bit of the flag in the output register.
+    pub bit: u32,
+}
+
+/// Cpu feature flag which is forced on or masked off by user.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CpuFeatureFlag {
+    pub name: String,
+    pub cpuid: CpuidFeature,
+    pub enabled: bool,
+}
+
+/// Known x86_64 cpu feature flags, named as in linux `/proc/cpuinfo`.
+/// See: https://elixir.bootlin.com/linux/v5.10/source/arch/x86/include/asm/cpufeatures.h
+const X86_CPU_FEATURES: &[(&str, u32, u32, CpuidReg, u32)] = &[
+    // CPUID[1].EDX
+    ("fpu", 0x1, 0, CpuidReg::Edx, 0),
+    ("vme", 0x1, 0, CpuidReg::Edx, 1),
+    ("de", 0x1, 0, CpuidReg::Edx, 2),
+    ("pse", 0x1, 0, CpuidReg::Edx, 3),
+    ("tsc", 0x1, 0, CpuidReg::Edx, 4),
+    ("msr", 0x1, 0, CpuidReg::Edx, 5),
+    ("pae", 0x1, 0, CpuidReg::Edx, 6),
+    ("mce", 0x1, 0, CpuidReg::Edx, 7),
+    ("cx8", 0x1, 0, CpuidReg::Edx, 8),
+    ("apic", 0x1, 0, CpuidReg::Edx, 9),
+    ("sep", 0x1, 0, CpuidReg::Edx, 11),
+    ("mtrr", 0x1, 0, CpuidReg::Edx, 12),
+    ("pge", 0x1, 0, CpuidReg::Edx, 13),
+    ("mca", 0x1, 0, CpuidReg::Edx, 14),
+    ("cmov", 0x1, 0, CpuidReg::Edx, 15),
+    ("pat", 0x1, 0, CpuidReg::Edx, 16),
+    ("pse36", 0x1, 0, CpuidReg::Edx, 17),
+    ("clflush", 0x1, 0, CpuidReg::Edx, 19),
+    ("mmx", 0x1, 0, CpuidReg::Edx, 23),
+    ("fxsr", 0x1, 0, CpuidReg::Edx, 24),
+    ("sse", 0x1, 0, CpuidReg::Edx, 25),
+    ("sse2", 0x1, 0, CpuidReg::Edx, 26),
+    ("ss", 0x1, 0, CpuidReg::Edx, 27),
+    ("ht", 0x1, 0, CpuidReg::Edx, 28),
+    // CPUID[1].ECX
+    ("sse3", 0x1, 0, CpuidReg::Ecx, 0),
+    ("pclmulqdq", 0x1, 0, CpuidReg::Ecx, 1),
+    ("ssse3", 0x1, 0, CpuidReg::Ecx, 9),
+    ("fma", 0x1, 0, CpuidReg::Ecx, 12),
+    ("cx16", 0x1, 0, CpuidReg::Ecx, 13),
+    ("pcid", 0x1, 0, CpuidReg::Ecx, 17),
+    ("sse4_1", 0x1, 0, CpuidReg::Ecx, 19),
+    ("sse4_2", 0x1, 0, CpuidReg::Ecx, 20),
+    ("x2apic", 0x1, 0, CpuidReg::Ecx, 21),
+    ("movbe", 0x1, 0, CpuidReg::Ecx, 22),
+    ("popcnt", 0x1, 0, CpuidReg::Ecx, 23),
+    ("aes", 0x1, 0, CpuidReg::Ecx, 25),
+    ("xsave", 0x1, 0, CpuidReg::Ecx, 26),
+    ("avx", 0x1, 0, CpuidReg::Ecx, 28),
+    ("f16c", 0x1, 0, CpuidReg::Ecx, 29),
+    ("rdrand", 0x1, 0, CpuidReg::Ecx, 30),
+    // CPUID[7,0].EBX
+    ("fsgsbase", 0x7, 0, CpuidReg::Ebx, 0),
+    ("bmi1", 0x7, 0, CpuidReg::Ebx, 3),
+    ("hle", 0x7, 0, CpuidReg::Ebx, 4),
+    ("avx2", 0x7, 0, CpuidReg::Ebx, 5),
+    ("smep", 0x7, 0, CpuidReg::Ebx, 7),
+    ("bmi2", 0x7, 0, CpuidReg::Ebx, 8),
+    ("erms", 0x7, 0, CpuidReg::Ebx, 9),
+    ("invpcid", 0x7, 0, CpuidReg::Ebx, 10),
+    ("rtm", 0x7, 0, CpuidReg::Ebx, 11),
+    ("mpx", 0x7, 0, CpuidReg::Ebx, 14),
+    ("avx512f", 0x7, 0, CpuidReg::Ebx, 16),
+    ("avx512dq", 0x7, 0, CpuidReg::Ebx, 17),
+    ("rdseed", 0x7, 0, CpuidReg::Ebx, 18),
+    ("adx", 0x7, 0, CpuidReg::Ebx, 19),
+    ("smap", 0x7, 0, CpuidReg::Ebx, 20),
+    ("avx512ifma", 0x7, 0, CpuidReg::Ebx, 21),
+    ("clflushopt", 0x7, 0, CpuidReg::Ebx, 23),
+    ("clwb", 0x7, 0, CpuidReg::Ebx, 24),
+    ("avx512pf", 0x7, 0, CpuidReg::Ebx, 26),
+    ("avx512er", 0x7, 0, CpuidReg::Ebx, 27),
+    ("avx512cd", 0x7, 0, CpuidReg::Ebx, 28),
+    ("sha_ni", 0x7, 0, CpuidReg::Ebx, 29),
+    ("avx512bw", 0x7, 0, CpuidReg::Ebx, 30),
+    ("avx512vl", 0x7, 0, CpuidReg::Ebx, 31),
+    // CPUID[7,0].ECX
+    ("avx512vbmi", 0x7, 0, CpuidReg::Ecx, 1),
+    ("umip", 0x7, 0, CpuidReg::Ecx, 2),
+    ("pku", 0x7, 0, CpuidReg::Ecx, 3),
+    ("avx512_vbmi2", 0x7, 0, CpuidReg::Ecx, 6),
+    ("gfni", 0x7, 0, CpuidReg::Ecx, 8),
+    ("vaes", 0x7, 0, CpuidReg::Ecx, 9),
+    ("vpclmulqdq", 0x7, 0, CpuidReg::Ecx, 10),
+    ("avx512_vnni", 0x7, 0, CpuidReg::Ecx, 11),
+    ("avx512_bitalg", 0x7, 0, CpuidReg::Ecx, 12),
+    ("avx512_vpopcntdq", 0x7, 0, CpuidReg::Ecx, 14),
+    ("rdpid", 0x7, 0, CpuidReg::Ecx, 22),
+    // CPUID[7,0].EDX
+    ("avx512_4vnniw", 0x7, 0, CpuidReg::Edx, 2),
+    ("avx512_4fmaps", 0x7, 0, CpuidReg::Edx, 3),
+    ("md_clear", 0x7, 0, CpuidReg::Edx, 10),
+    ("spec_ctrl", 0x7, 0, CpuidReg::Edx, 26),
+    ("stibp", 0x7, 0, CpuidReg::Edx, 27),
+    ("arch_capabilities", 0x7, 0, CpuidReg::Edx, 29),
+    ("ssbd", 0x7, 0, CpuidReg::Edx, 31),
+    // CPUID[0xd,1].EAX
+    ("xsaveopt", 0xd, 1, CpuidReg::Eax, 0),
+    ("xsavec", 0xd, 1, CpuidReg::Eax, 1),
+    ("xgetbv1", 0xd, 1, CpuidReg::Eax, 2),
+    ("xsaves", 0xd, 1, CpuidReg::Eax, 3),
+    // CPUID[0x8000_0001].EDX
+    ("syscall", 0x8000_0001, 0, CpuidReg::Edx, 11),
+    ("nx", 0x8000_0001, 0, CpuidReg::Edx, 20),
+    ("pdpe1gb", 0x8000_0001, 0, CpuidReg::Edx, 26),
+    ("rdtscp", 0x8000_0001, 0, CpuidReg::Edx, 27),
+    ("lm", 0x8000_0001, 0, CpuidReg::Edx, 29),
+    // CPUID[0x8000_0001].ECX
+    ("lahf_lm", 0x8000_0001, 0, CpuidReg::Ecx, 0),
+    ("abm", 0x8000_0001, 0, CpuidReg::Ecx, 5),
+    ("sse4a", 0x8000_0001, 0, CpuidReg::Ecx, 6),
+    ("3dnowprefetch", 0x8000_0001, 0, CpuidReg::Ecx, 8),
+    ("xop", 0x8000_0001, 0, CpuidReg::Ecx, 11),
+    ("fma4", 0x8000_0001, 0, CpuidReg::Ecx, 16),
+    ("tbm", 0x8000_0001, 0, CpuidReg::Ecx, 21),
+    ("topoext", 0x8000_0001, 0, CpuidReg::Ecx, 22),
+];
+
+/// Look up the CPUID location of a x86_64 cpu feature flag by its name.
+/// Both `_` and `-` (or `.`) are accepted as the separator in name, e.g.
+/// `sse4_1`, `sse4-1` and `sse4.1` are the same flag.
+///
+/// # Arguments
+///
+/// * `name` - Name of the cpu feature flag.
+pub fn x86_cpuid_feature(name: &str) -> Option<CpuidFeature> {
+    let name = name.to_lowercase().replace(['-', '.'], "_");
+    X86_CPU_FEATURES
+        .iter()
+        .find(|(n, ..)| *n == name)
+        .map(|(_, function, index, reg, bit)| CpuidFeature {
+            function: *function,
+            index: *index,
+            reg: *reg,
+            bit: *bit,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x86_cpuid_feature() {
+        let ssse3 = x86_cpuid_feature("ssse3").unwrap();
+        assert_eq!(ssse3.function, 0x1);
+        assert_eq!(ssse3.reg, CpuidReg::Ecx);
+        assert_eq!(ssse3.bit, 9);
+
+        let avx512f = x86_cpuid_feature("avx512f").unwrap();
+        assert_eq!(avx512f.function, 0x7);
+        assert_eq!(avx512f.index, 0);
+        assert_eq!(avx512f.reg, CpuidReg::Ebx);
+        assert_eq!(avx512f.bit, 16);
+
+        assert_eq!(x86_cpuid_feature("sse4.1"), x86_cpuid_feature("sse4_1"));
+        assert_eq!(x86_cpuid_feature("sse4-2"), x86_cpuid_feature("sse4_2"));
+        assert!(x86_cpuid_feature("sse4_1").is_some());
+        assert!(x86_cpuid_feature("xsaves").is_some());
+        assert!(x86_cpuid_feature("avx1024").is_none());
+        assert!(x86_cpuid_feature("").is_none());
+    }
+}
@@ -10,10 +10,20 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use super::{CmdParser, VmConfig};
-use anyhow::Result;
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, Context, Result};
 use regex::Regex;
 
+use super::{
+    get_pci_bdf, parse_balloon, parse_blk, parse_demo_dev, parse_fs, parse_net, parse_pvpanic,
+    parse_rng_dev, parse_root_port, parse_scsi_controller, parse_scsi_device, parse_vfio,
+    parse_vhost_user_blk_pci, parse_virtconsole, parse_virtio_serial, parse_vsock,
+    scsi_cntlr_auto_queues_num, CmdParser, ConfigError, VmConfig,
+};
+#[cfg(not(target_env = "musl"))]
+use super::{parse_gpu, parse_input, parse_usb_keyboard, parse_usb_tablet, parse_xhci};
+
 impl VmConfig {
     pub fn add_device(&mut self, device_config: &str) -> Result<()> {
         let mut cmd_params = CmdParser::new("device");
@@ -38,6 +48,158 @@ impl VmConfig {
             }
         }
     }
+
+    /// Validate the whole `VmConfig` without creating the virtual machine.
+    ///
+    /// Every device is parsed and checked as it would be at realize, and
+    /// cross-device conflicts (repeated device id, repeated pci address) are
+    /// reported. Nothing in `self` is consumed or changed.
+    pub fn validate(&self) -> Result<()> {
+        self.check_vmconfig(false)?;
+
+        // Parsers take away the drives and chardevs they use, so work on a copy.
+        let mut vm_config = self.clone();
+        let mut ids = HashSet::new();
+        let mut pci_addrs = HashSet::new();
+        for (dev_type, cfg_args) in &self.devices {
+            let id = parse_device_id(cfg_args)?;
+            if !id.is_empty() && !ids.insert(id.clone()) {
+                return Err(anyhow!(ConfigError::IdRepeat("device".to_string(), id)));
+            }
+            if let Ok(bdf) = get_pci_bdf(cfg_args) {
+                if !pci_addrs.insert((bdf.bus.clone(), bdf.addr)) {
+                    bail!(
+                        "Pci address {}.{} on bus {} is used by more than one device",
+                        bdf.addr.0,
+                        bdf.addr.1,
+                        bdf.bus
+                    );
+                }
+            }
+
+            vm_config
+                .validate_device(dev_type, cfg_args)
+                .with_context(|| format!("Invalid device config: {}", cfg_args))?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_device(&mut self, dev_type: &str, cfg_args: &str) -> Result<()> {
+        let parser = device_parsers()
+            .into_iter()
+            .find(|(name, _)| *name == dev_type)
+            .map(|(_, parser)| parser)
+            .ok_or_else(|| anyhow!(ConfigError::UnknownDeviceType(dev_type.to_string())))?;
+        parser(self, cfg_args)
+    }
+}
+
+/// Parser of the device given by `-device`, which checks the config args of the device.
+pub type DeviceParser = fn(&mut VmConfig, &str) -> Result<()>;
+
+/// Get the device types supported by `-device` and the parser of each of them.
+///
+/// This is the registry of `-device` types. Dry run validates devices through it, and
+/// machines only create devices whose type is registered here.
+pub fn device_parsers() -> Vec<(&'static str, DeviceParser)> {
+    let parsers: &[(&'static str, DeviceParser)] = &[
+        ("virtio-blk-device", |vm_config, cfg_args| {
+            parse_blk(vm_config, cfg_args, None).map(|_| ())
+        }),
+        ("virtio-blk-pci", |vm_config, cfg_args| {
+            parse_blk(vm_config, cfg_args, None).map(|_| ())
+        }),
+        ("virtio-scsi-pci", |vm_config, cfg_args| {
+            let queues_auto = Some(scsi_cntlr_auto_queues_num(vm_config.machine_config.nr_cpus));
+            parse_scsi_controller(cfg_args, queues_auto).map(|_| ())
+        }),
+        ("scsi-hd", |vm_config, cfg_args| {
+            parse_scsi_device(vm_config, cfg_args).map(|_| ())
+        }),
+        ("scsi-cd", |vm_config, cfg_args| {
+            parse_scsi_device(vm_config, cfg_args).map(|_| ())
+        }),
+        ("virtio-net-device", |vm_config, cfg_args| {
+            parse_net(vm_config, cfg_args).map(|_| ())
+        }),
+        ("virtio-net-pci", |vm_config, cfg_args| {
+            parse_net(vm_config, cfg_args).map(|_| ())
+        }),
+        ("pcie-root-port", |_, cfg_args| {
+            parse_root_port(cfg_args).map(|_| ())
+        }),
+        ("vhost-vsock-pci", |vm_config, cfg_args| {
+            parse_vsock(vm_config, cfg_args).map(|_| ())
+        }),
+        ("vhost-vsock-device", |vm_config, cfg_args| {
+            parse_vsock(vm_config, cfg_args).map(|_| ())
+        }),
+        ("virtio-balloon-device", |vm_config, cfg_args| {
+            parse_balloon(vm_config, cfg_args).map(|_| ())
+        }),
+        ("virtio-balloon-pci", |vm_config, cfg_args| {
+            parse_balloon(vm_config, cfg_args).map(|_| ())
+        }),
+        ("virtio-serial-device", |vm_config, cfg_args| {
+            parse_virtio_serial(vm_config, cfg_args)
+        }),
+        ("virtio-serial-pci", |vm_config, cfg_args| {
+            parse_virtio_serial(vm_config, cfg_args)
+        }),
+        ("virtconsole", |vm_config, cfg_args| {
+            parse_virtconsole(vm_config, cfg_args).map(|_| ())
+        }),
+        ("virtio-rng-device", |vm_config, cfg_args| {
+            parse_rng_dev(vm_config, cfg_args).map(|_| ())
+        }),
+        ("virtio-rng-pci", |vm_config, cfg_args| {
+            parse_rng_dev(vm_config, cfg_args).map(|_| ())
+        }),
+        ("vfio-pci", |_, cfg_args| parse_vfio(cfg_args).map(|_| ())),
+        ("vhost-user-blk-pci", |vm_config, cfg_args| {
+            parse_vhost_user_blk_pci(vm_config, cfg_args, None).map(|_| ())
+        }),
+        ("vhost-user-fs-pci", |vm_config, cfg_args| {
+            parse_fs(vm_config, cfg_args).map(|_| ())
+        }),
+        ("vhost-user-fs-device", |vm_config, cfg_args| {
+            parse_fs(vm_config, cfg_args).map(|_| ())
+        }),
+        #[cfg(not(target_env = "musl"))]
+        ("nec-usb-xhci", |_, cfg_args| {
+            parse_xhci(cfg_args).map(|_| ())
+        }),
+        #[cfg(not(target_env = "musl"))]
+        ("usb-kbd", |_, cfg_args| {
+            parse_usb_keyboard(cfg_args).map(|_| ())
+        }),
+        #[cfg(not(target_env = "musl"))]
+        ("usb-tablet", |_, cfg_args| {
+            parse_usb_tablet(cfg_args).map(|_| ())
+        }),
+        #[cfg(not(target_env = "musl"))]
+        ("virtio-gpu-pci", |_, cfg_args| {
+            parse_gpu(cfg_args).map(|_| ())
+        }),
+        #[cfg(not(target_env = "musl"))]
+        ("virtio-input-device", |_, cfg_args| {
+            parse_input(cfg_args).map(|_| ())
+        }),
+        #[cfg(not(target_env = "musl"))]
+        ("virtio-input-pci", |_, cfg_args| {
+            parse_input(cfg_args).map(|_| ())
+        }),
+        #[cfg(not(target_env = "musl"))]
+        ("ramfb", |_, _| Ok(())),
+        ("pvpanic", |vm_config, cfg_args| {
+            parse_pvpanic(vm_config, cfg_args).map(|_| ())
+        }),
+        ("pcie-demo-dev", |vm_config, cfg_args| {
+            parse_demo_dev(vm_config, cfg_args.to_string()).map(|_| ())
+        }),
+    ];
+    parsers.to_vec()
 }
 
 pub fn parse_device_id(device_config: &str) -> Result<String> {
@@ -52,9 +214,19 @@ pub fn parse_device_id(device_config: &str) -> Result<String> {
     }
 }
 
+/// Get the boot index of the device, which decides the firmware boot order.
+pub fn parse_device_bootindex(device_config: &str) -> Result<Option<u8>> {
+    let mut cmd_parser = CmdParser::new("device");
+    cmd_parser.push("bootindex");
+
+    cmd_parser.get_parameters(device_config)?;
+    cmd_parser.get_value::<u8>("bootindex")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::MachineType;
 
     #[test]
     fn test_parse_device_id() {
@@ -70,4 +242,68 @@ mod tests {
         let id = ret.unwrap();
         assert_eq!("", id);
     }
+
+    #[test]
+    fn test_validate_vmconfig() {
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mach_type = MachineType::StandardVm;
+        vm_config
+            .add_chardev("socket,id=test_console,path=/path/to/socket,server,nowait")
+            .unwrap();
+        vm_config
+            .add_device("virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,num-queues=2")
+            .unwrap();
+        vm_config
+            .add_device("virtio-serial-pci,id=serial0,bus=pcie.0,addr=0x4")
+            .unwrap();
+        vm_config
+            .add_device("virtconsole,id=console0,chardev=test_console")
+            .unwrap();
+        assert!(vm_config.validate().is_ok());
+        // Validation leaves the config untouched.
+        assert!(vm_config.chardev.contains_key("test_console"));
+        assert!(vm_config.validate().is_ok());
+
+        let mut good_config = vm_config.clone();
+        good_config.add_device("pvpanic,id=pvpanic0").unwrap();
+        #[cfg(not(target_env = "musl"))]
+        good_config
+            .add_device("virtio-input-pci,id=input0,type=keyboard,bus=pcie.0,addr=0x6")
+            .unwrap();
+        assert!(good_config.validate().is_ok());
+
+        let mut bad_config = vm_config.clone();
+        bad_config
+            .add_device("virtio-scsi-pci,id=scsi1,bus=pcie.0,addr=0x5,num-queues=0")
+            .unwrap();
+        assert!(bad_config.validate().is_err());
+
+        let mut bad_config = vm_config.clone();
+        bad_config
+            .add_device("virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x5")
+            .unwrap();
+        assert!(bad_config.validate().is_err());
+
+        let mut bad_config = vm_config.clone();
+        bad_config
+            .add_device("virtio-scsi-pci,id=scsi1,bus=pcie.0,addr=0x3")
+            .unwrap();
+        assert!(bad_config.validate().is_err());
+
+        let mut bad_config = vm_config.clone();
+        bad_config
+            .add_device("virtio-unknown-pci,id=unknown0")
+            .unwrap();
+        assert!(bad_config.validate().is_err());
+
+        // Repeated bootindex.
+        let mut bad_config = vm_config;
+        bad_config
+            .add_device("scsi-hd,id=disk0,bus=scsi0.0,scsi-id=0,lun=0,drive=drive0,bootindex=1")
+            .unwrap();
+        bad_config
+            .add_device("scsi-hd,id=disk1,bus=scsi0.0,scsi-id=0,lun=1,drive=drive1,bootindex=1")
+            .unwrap();
+        assert!(bad_config.check_vmconfig(false).is_err());
+    }
 }
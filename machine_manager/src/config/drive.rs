@@ -13,6 +13,7 @@
 use std::fs::{metadata, File};
 use std::os::linux::fs::MetadataExt;
 use std::path::Path;
+use std::str::FromStr;
 
 use anyhow::{anyhow, bail, Result};
 use log::error;
@@ -25,14 +26,139 @@ use crate::config::{
 };
 use crate::qmp::qmp_schema;
 use util::aio::{aio_probe, AioEngine};
+use util::leak_bucket::IoLimits;
 const MAX_SERIAL_NUM: usize = 20;
 const MAX_IOPS: u64 = 1_000_000;
+const MAX_BPS: u64 = 1 << 40;
 const MAX_UNIT_ID: usize = 2;
 
 // Seg_max = queue_size - 2. So, size of each virtqueue for virtio-blk should be larger than 2.
 const MIN_QUEUE_SIZE_BLK: u16 = 2;
 // Max size of each virtqueue for virtio-blk.
 const MAX_QUEUE_SIZE_BLK: u16 = 1024;
+// Default interval(ms) between the retries of failed block request.
+pub const DEFAULT_RETRY_INTERVAL: u64 = 1000;
+// Default timeout(ms) before giving up retrying failed block request.
+pub const DEFAULT_RETRY_TIMEOUT: u64 = 30000;
+
+/// Action taken by block device on read or write error.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum BlockErrorPolicy {
+    /// Report the error to the guest.
+    Report,
+    /// Pause the VM on any error. The request is resubmitted when VM resumes.
+    Stop,
+    /// Pause the VM on ENOSPC, report other errors to the guest.
+    Enospc,
+    /// Resubmit the request after `retry-interval` until `retry-timeout` expires,
+    /// non-retryable errors are reported to the guest directly.
+    Retry,
+}
+
+impl FromStr for BlockErrorPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "report" => Ok(BlockErrorPolicy::Report),
+            "stop" => Ok(BlockErrorPolicy::Stop),
+            "enospc" => Ok(BlockErrorPolicy::Enospc),
+            "retry" => Ok(BlockErrorPolicy::Retry),
+            _ => Err(()),
+        }
+    }
+}
+
+impl BlockErrorPolicy {
+    /// Whether the VM should be paused on the error.
+    ///
+    /// # Arguments
+    ///
+    /// * `errno` - The errno of failed request.
+    pub fn should_stop(&self, errno: i32) -> bool {
+        match self {
+            BlockErrorPolicy::Report | BlockErrorPolicy::Retry => false,
+            BlockErrorPolicy::Stop => true,
+            BlockErrorPolicy::Enospc => errno == libc::ENOSPC,
+        }
+    }
+
+    /// Whether the failed request should be resubmitted later. Errors which
+    /// won't go away by retrying, e.g. EINVAL and ENOSPC, are not retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `errno` - The errno of failed request.
+    pub fn should_retry(&self, errno: i32) -> bool {
+        *self == BlockErrorPolicy::Retry && errno != libc::EINVAL && errno != libc::ENOSPC
+    }
+}
+
+/// Format of the block image.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum DiskFormat {
+    Raw,
+    Qcow2,
+}
+
+impl FromStr for DiskFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(DiskFormat::Raw),
+            "qcow2" => Ok(DiskFormat::Qcow2),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Cache mode of the drive, which is a shorthand of `direct`, write cache and flush behavior.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum CacheMode {
+    /// Page cache of host is used, guest flushes are honored.
+    Writeback,
+    /// Page cache of host is used, each write is synced before completed.
+    Writethrough,
+    /// Page cache of host is bypassed, guest flushes are honored.
+    None,
+    /// Page cache of host is bypassed, each write is synced before completed.
+    Directsync,
+    /// Page cache of host is used, guest flushes are ignored.
+    Unsafe,
+}
+
+impl FromStr for CacheMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "writeback" => Ok(CacheMode::Writeback),
+            "writethrough" => Ok(CacheMode::Writethrough),
+            "none" => Ok(CacheMode::None),
+            "directsync" => Ok(CacheMode::Directsync),
+            "unsafe" => Ok(CacheMode::Unsafe),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CacheMode {
+    /// Whether the image is opened with `O_DIRECT`.
+    pub fn direct(&self) -> bool {
+        matches!(self, CacheMode::None | CacheMode::Directsync)
+    }
+
+    /// Whether the device has a volatile write cache.
+    pub fn write_cache(&self) -> bool {
+        !matches!(self, CacheMode::Writethrough | CacheMode::Directsync)
+    }
+
+    /// Whether the flush requests from guest are ignored.
+    pub fn no_flush(&self) -> bool {
+        *self == CacheMode::Unsafe
+    }
+}
 
 /// Represent a single drive backend file.
 pub struct DriveFile {
@@ -44,6 +170,10 @@ pub struct DriveFile {
     pub path: String,
     /// File is read only or not.
     pub read_only: bool,
+    /// File can be shared by writable drives or not.
+    pub share_rw: bool,
+    /// Device and inode number of the file, which identify the file referenced by links.
+    pub ident: (u64, u64),
     /// File lock status.
     pub locked: bool,
     /// The align requirement of request(offset/len).
@@ -59,15 +189,33 @@ pub struct BlkDevConfig {
     pub path_on_host: String,
     pub read_only: bool,
     pub direct: bool,
+    /// Device has a volatile write cache, otherwise each write is synced before completed.
+    pub write_cache: bool,
+    /// Flush requests from guest are ignored.
+    pub no_flush: bool,
     pub serial_num: Option<String>,
     pub iothread: Option<String>,
-    pub iops: Option<u64>,
+    /// Iothreads which the request queues are mapped to in turn.
+    pub iothreads: Vec<String>,
+    pub throttle: IoLimits,
     pub queues: u16,
     pub boot_index: Option<u8>,
     pub chardev: Option<String>,
     pub socket_path: Option<String>,
     pub aio: AioEngine,
     pub queue_size: u16,
+    pub werror: BlockErrorPolicy,
+    pub rerror: BlockErrorPolicy,
+    pub retry_interval: u64,
+    pub retry_timeout: u64,
+    pub discard: bool,
+    pub write_zeroes: bool,
+    /// Writable overlay file of the read-only image, which stores the data written by guest.
+    pub overlay: Option<String>,
+    /// Data written by guest goes to an anonymous overlay, which is discarded at VM exit.
+    pub snapshot: bool,
+    /// Format of the image, it's probed by the magic of image if not set.
+    pub format: Option<DiskFormat>,
 }
 
 #[derive(Debug, Clone)]
@@ -77,6 +225,20 @@ pub struct BootIndexInfo {
     pub dev_path: String,
 }
 
+impl BlkDevConfig {
+    /// Get the iothread handling the request queue, which is the main thread if it's None.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue_index` - The index of the request queue.
+    pub fn queue_iothread(&self, queue_index: usize) -> Option<&String> {
+        if self.iothreads.is_empty() {
+            return self.iothread.as_ref();
+        }
+        self.iothreads.get(queue_index % self.iothreads.len())
+    }
+}
+
 impl Default for BlkDevConfig {
     fn default() -> Self {
         BlkDevConfig {
@@ -84,15 +246,27 @@ impl Default for BlkDevConfig {
             path_on_host: "".to_string(),
             read_only: false,
             direct: true,
+            write_cache: true,
+            no_flush: false,
             serial_num: None,
             iothread: None,
-            iops: None,
+            iothreads: Vec::new(),
+            throttle: IoLimits::default(),
             queues: 1,
             boot_index: None,
             chardev: None,
             socket_path: None,
             aio: AioEngine::Native,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            werror: BlockErrorPolicy::Report,
+            rerror: BlockErrorPolicy::Report,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            retry_timeout: DEFAULT_RETRY_TIMEOUT,
+            discard: false,
+            write_zeroes: false,
+            overlay: None,
+            snapshot: false,
+            format: None,
         }
     }
 }
@@ -106,8 +280,26 @@ pub struct DriveConfig {
     pub path_on_host: String,
     pub read_only: bool,
     pub direct: bool,
-    pub iops: Option<u64>,
+    /// Device has a volatile write cache, otherwise each write is synced before completed.
+    pub write_cache: bool,
+    /// Flush requests from guest are ignored.
+    pub no_flush: bool,
+    pub throttle: IoLimits,
     pub aio: AioEngine,
+    pub werror: BlockErrorPolicy,
+    pub rerror: BlockErrorPolicy,
+    pub retry_interval: u64,
+    pub retry_timeout: u64,
+    pub discard: bool,
+    pub write_zeroes: bool,
+    /// Writable overlay file of the read-only image, which stores the data written by guest.
+    pub overlay: Option<String>,
+    /// Data written by guest goes to an anonymous overlay, which is discarded at VM exit.
+    pub snapshot: bool,
+    /// Format of the image, it's probed by the magic of image if not set.
+    pub format: Option<DiskFormat>,
+    /// The image can be shared with other writable drives.
+    pub share_rw: bool,
 }
 
 impl Default for DriveConfig {
@@ -117,12 +309,60 @@ impl Default for DriveConfig {
             path_on_host: "".to_string(),
             read_only: false,
             direct: true,
-            iops: None,
+            write_cache: true,
+            no_flush: false,
+            throttle: IoLimits::default(),
             aio: AioEngine::Native,
+            werror: BlockErrorPolicy::Report,
+            rerror: BlockErrorPolicy::Report,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            retry_timeout: DEFAULT_RETRY_TIMEOUT,
+            discard: false,
+            write_zeroes: false,
+            overlay: None,
+            snapshot: false,
+            format: None,
+            share_rw: false,
         }
     }
 }
 
+/// Check the IO throttling limits of block device, zero value means no limit. The read/write
+/// limits can't be set together with the total limit.
+pub fn check_io_limits(limits: &IoLimits) -> Result<()> {
+    for (name, value, max) in [
+        ("iops-total", limits.iops_total, MAX_IOPS),
+        ("iops-read", limits.iops_read, MAX_IOPS),
+        ("iops-write", limits.iops_write, MAX_IOPS),
+        ("bps-total", limits.bps_total, MAX_BPS),
+        ("bps-read", limits.bps_read, MAX_BPS),
+        ("bps-write", limits.bps_write, MAX_BPS),
+    ] {
+        if value > max {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                format!("{} of block device", name),
+                0,
+                true,
+                max,
+                true,
+            )));
+        }
+    }
+    if limits.iops_total != 0 && (limits.iops_read != 0 || limits.iops_write != 0) {
+        return Err(anyhow!(ConfigError::InvalidParam(
+            "iops-read/iops-write".to_string(),
+            "block device with iops-total".to_string(),
+        )));
+    }
+    if limits.bps_total != 0 && (limits.bps_read != 0 || limits.bps_write != 0) {
+        return Err(anyhow!(ConfigError::InvalidParam(
+            "bps-read/bps-write".to_string(),
+            "block device with bps-total".to_string(),
+        )));
+    }
+    Ok(())
+}
+
 impl DriveConfig {
     /// Check whether the drive file path on the host is valid.
     pub fn check_path(&self) -> Result<()> {
@@ -177,15 +417,7 @@ impl ConfigCheck for DriveConfig {
                 MAX_PATH_LENGTH,
             )));
         }
-        if self.iops.is_some() && self.iops.unwrap() > MAX_IOPS {
-            return Err(anyhow!(ConfigError::IllegalValue(
-                "iops of block device".to_string(),
-                0,
-                true,
-                MAX_IOPS,
-                true,
-            )));
-        }
+        check_io_limits(&self.throttle)?;
         if self.aio != AioEngine::Off {
             if self.aio == AioEngine::Native && !self.direct {
                 return Err(anyhow!(ConfigError::InvalidParam(
@@ -200,6 +432,58 @@ impl ConfigCheck for DriveConfig {
                 "low performance expected when use sync io with \"direct\" on".to_string(),
             )));
         }
+        if self.retry_interval == 0 {
+            return Err(anyhow!(ConfigError::IllegalValueUnilateral(
+                "retry-interval of block device".to_string(),
+                true,
+                false,
+                0,
+            )));
+        }
+        if let Some(overlay) = self.overlay.as_ref() {
+            if overlay.len() > MAX_PATH_LENGTH {
+                return Err(anyhow!(ConfigError::StringLengthTooLong(
+                    "Drive overlay path".to_string(),
+                    MAX_PATH_LENGTH,
+                )));
+            }
+            if overlay == &self.path_on_host {
+                bail!("Overlay file of drive can't be the same as its image file");
+            }
+            if self.snapshot {
+                bail!("\"overlay\" and \"snapshot\" of drive can't be set together");
+            }
+        }
+        // Snapshot mode uses an anonymous overlay, so it has the same limits as overlay.
+        let param = if self.snapshot { "snapshot" } else { "overlay" };
+        if self.overlay.is_some() || self.snapshot {
+            if self.read_only {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    param.to_string(),
+                    "read-only drive".to_string(),
+                )));
+            }
+            if self.direct {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    param.to_string(),
+                    "drive with \"direct\" on".to_string(),
+                )));
+            }
+            if self.discard || self.write_zeroes {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    param.to_string(),
+                    "drive with discard or write-zeroes".to_string(),
+                )));
+            }
+        }
+        // Qcow2 image is opened read-only under the anonymous overlay of snapshot mode,
+        // but an overlay file can't be put on top of it.
+        if self.overlay.is_some() && self.format == Some(DiskFormat::Qcow2) {
+            return Err(anyhow!(ConfigError::InvalidParam(
+                "overlay".to_string(),
+                "qcow2 image".to_string(),
+            )));
+        }
         Ok(())
     }
 }
@@ -237,6 +521,27 @@ impl ConfigCheck for BlkDevConfig {
             )));
         }
 
+        if !self.iothreads.is_empty() {
+            if self.iothread.is_some() {
+                bail!("\"iothread\" and \"iothreads\" of block device can't be set together");
+            }
+            if self.iothreads.len() > self.queues as usize {
+                bail!(
+                    "The number of iothreads {} is more than the number of queues {}",
+                    self.iothreads.len(),
+                    self.queues
+                );
+            }
+            for iothread in self.iothreads.iter() {
+                if iothread.is_empty() || iothread.len() > MAX_STRING_LENGTH {
+                    return Err(anyhow!(ConfigError::StringLengthTooLong(
+                        "iothread name".to_string(),
+                        MAX_STRING_LENGTH,
+                    )));
+                }
+            }
+        }
+
         if self.queue_size <= MIN_QUEUE_SIZE_BLK || self.queue_size > MAX_QUEUE_SIZE_BLK {
             return Err(anyhow!(ConfigError::IllegalValue(
                 "queue size of block device".to_string(),
@@ -254,7 +559,7 @@ impl ConfigCheck for BlkDevConfig {
         let fake_drive = DriveConfig {
             path_on_host: self.path_on_host.clone(),
             direct: self.direct,
-            iops: self.iops,
+            throttle: self.throttle,
             aio: self.aio,
             ..Default::default()
         };
@@ -271,11 +576,7 @@ impl ConfigCheck for BlkDevConfig {
 fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
     let mut drive = DriveConfig::default();
 
-    if let Some(format) = cmd_parser.get_value::<String>("format")? {
-        if format.ne("raw") {
-            bail!("Only \'raw\' type of block is supported");
-        }
-    }
+    drive.format = cmd_parser.get_value::<DiskFormat>("format")?;
 
     if let Some(id) = cmd_parser.get_value::<String>("id")? {
         drive.id = id;
@@ -295,7 +596,44 @@ fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
     if let Some(direct) = cmd_parser.get_value::<ExBool>("direct")? {
         drive.direct = direct.into();
     }
-    drive.iops = cmd_parser.get_value::<u64>("throttling.iops-total")?;
+    if let Some(cache) = cmd_parser.get_value::<CacheMode>("cache")? {
+        if cmd_parser.get_value::<ExBool>("direct")?.is_some() {
+            bail!("\"cache\" and \"direct\" of drive can't be set together");
+        }
+        drive.direct = cache.direct();
+        drive.write_cache = cache.write_cache();
+        drive.no_flush = cache.no_flush();
+    }
+    if let Some(snapshot) = cmd_parser.get_value::<ExBool>("snapshot")? {
+        drive.snapshot = snapshot.into();
+    }
+    // Anonymous overlay of snapshot mode is written by the block driver, which doesn't
+    // use direct io, so snapshot implies `direct` off unless it's set explicitly.
+    if drive.snapshot
+        && cmd_parser.get_value::<ExBool>("direct")?.is_none()
+        && cmd_parser.get_value::<CacheMode>("cache")?.is_none()
+    {
+        drive.direct = false;
+    }
+    let limits = &mut drive.throttle;
+    for (name, value) in [
+        ("throttling.iops-total", &mut limits.iops_total),
+        ("throttling.iops-read", &mut limits.iops_read),
+        ("throttling.iops-write", &mut limits.iops_write),
+    ] {
+        if let Some(limit) = cmd_parser.get_value::<u64>(name)? {
+            *value = limit;
+        }
+    }
+    for (name, value) in [
+        ("throttling.bps-total", &mut limits.bps_total),
+        ("throttling.bps-read", &mut limits.bps_read),
+        ("throttling.bps-write", &mut limits.bps_write),
+    ] {
+        if let Some(limit) = cmd_parser.get_size(name)? {
+            *value = limit;
+        }
+    }
     drive.aio = cmd_parser.get_value::<AioEngine>("aio")?.unwrap_or({
         if drive.direct {
             AioEngine::Native
@@ -303,6 +641,28 @@ fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
             AioEngine::Off
         }
     });
+    if let Some(werror) = cmd_parser.get_value::<BlockErrorPolicy>("werror")? {
+        drive.werror = werror;
+    }
+    if let Some(rerror) = cmd_parser.get_value::<BlockErrorPolicy>("rerror")? {
+        drive.rerror = rerror;
+    }
+    if let Some(retry_interval) = cmd_parser.get_value::<u64>("retry-interval")? {
+        drive.retry_interval = retry_interval;
+    }
+    if let Some(retry_timeout) = cmd_parser.get_value::<u64>("retry-timeout")? {
+        drive.retry_timeout = retry_timeout;
+    }
+    if let Some(discard) = cmd_parser.get_value::<ExBool>("discard")? {
+        drive.discard = discard.into();
+    }
+    if let Some(write_zeroes) = cmd_parser.get_value::<ExBool>("write-zeroes")? {
+        drive.write_zeroes = write_zeroes.into();
+    }
+    drive.overlay = cmd_parser.get_value::<String>("overlay")?;
+    if let Some(share_rw) = cmd_parser.get_value::<ExBool>("share-rw")? {
+        drive.share_rw = share_rw.into();
+    }
     drive.check()?;
     #[cfg(not(test))]
     drive.check_path()?;
@@ -325,6 +685,7 @@ pub fn parse_blk(
         .push("bootindex")
         .push("serial")
         .push("iothread")
+        .push("iothreads")
         .push("num-queues")
         .push("queue-size");
 
@@ -347,6 +708,10 @@ pub fn parse_blk(
         blkdevcfg.iothread = Some(iothread);
     }
 
+    if let Some(iothreads) = cmd_parser.get_value::<String>("iothreads")? {
+        blkdevcfg.iothreads = iothreads.split(':').map(String::from).collect();
+    }
+
     if let Some(serial) = cmd_parser.get_value::<String>("serial")? {
         blkdevcfg.serial_num = Some(serial);
     }
@@ -371,8 +736,19 @@ pub fn parse_blk(
         blkdevcfg.path_on_host = drive_arg.path_on_host.clone();
         blkdevcfg.read_only = drive_arg.read_only;
         blkdevcfg.direct = drive_arg.direct;
-        blkdevcfg.iops = drive_arg.iops;
+        blkdevcfg.write_cache = drive_arg.write_cache;
+        blkdevcfg.no_flush = drive_arg.no_flush;
+        blkdevcfg.throttle = drive_arg.throttle;
         blkdevcfg.aio = drive_arg.aio;
+        blkdevcfg.werror = drive_arg.werror;
+        blkdevcfg.rerror = drive_arg.rerror;
+        blkdevcfg.retry_interval = drive_arg.retry_interval;
+        blkdevcfg.retry_timeout = drive_arg.retry_timeout;
+        blkdevcfg.discard = drive_arg.discard;
+        blkdevcfg.write_zeroes = drive_arg.write_zeroes;
+        blkdevcfg.overlay = drive_arg.overlay.clone();
+        blkdevcfg.snapshot = drive_arg.snapshot;
+        blkdevcfg.format = drive_arg.format;
     } else {
         bail!("No drive configured matched for blk device");
     }
@@ -502,10 +878,25 @@ impl VmConfig {
             .push("id")
             .push("readonly")
             .push("direct")
+            .push("cache")
             .push("format")
             .push("if")
             .push("throttling.iops-total")
-            .push("aio");
+            .push("throttling.iops-read")
+            .push("throttling.iops-write")
+            .push("throttling.bps-total")
+            .push("throttling.bps-read")
+            .push("throttling.bps-write")
+            .push("aio")
+            .push("werror")
+            .push("rerror")
+            .push("retry-interval")
+            .push("retry-timeout")
+            .push("discard")
+            .push("write-zeroes")
+            .push("overlay")
+            .push("snapshot")
+            .push("share-rw");
 
         cmd_parser.parse(block_config)?;
         let drive_cfg = parse_drive(cmd_parser)?;
@@ -605,6 +996,17 @@ impl VmConfig {
         Ok(())
     }
 
+    /// Check units of pflash devices, unit 0 stores firmware code and unit 1 stores
+    /// firmware variables, firmware can't boot from variables only.
+    pub fn check_pflash_units(&self) -> Result<()> {
+        if let Some(pflashs) = self.pflashs.as_ref() {
+            if !pflashs.iter().any(|pf| pf.unit == 0) {
+                bail!("PFlash unit 1 for firmware variables requires unit 0 for firmware code");
+            }
+        }
+        Ok(())
+    }
+
     /// Add '-pflash ...' pflash config to `VmConfig`.
     pub fn add_pflash(&mut self, pflash_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("pflash");
@@ -672,6 +1074,170 @@ mod tests {
         assert_eq!(blk_device_config.read_only, false);
         assert_eq!(blk_device_config.serial_num, Some(String::from("111111")));
         assert_eq!(blk_device_config.queues, 4);
+        assert_eq!(blk_device_config.werror, BlockErrorPolicy::Report);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,num-queues=4,iothreads=iothread1:iothread2",
+            None,
+        )
+        .unwrap();
+        assert_eq!(blk_cfg.iothreads, vec!["iothread1", "iothread2"]);
+        assert_eq!(blk_cfg.queue_iothread(0).unwrap(), "iothread1");
+        assert_eq!(blk_cfg.queue_iothread(1).unwrap(), "iothread2");
+        assert_eq!(blk_cfg.queue_iothread(2).unwrap(), "iothread1");
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs")
+            .is_ok());
+        // More iothreads than queues.
+        assert!(parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,num-queues=1,iothreads=iothread1:iothread2",
+            None,
+        )
+        .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs")
+            .is_ok());
+        assert!(parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,num-queues=2,iothread=iot1,iothreads=iot1:iot2",
+            None,
+        )
+        .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,werror=stop")
+            .is_ok());
+        let blk_cfg_res = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        );
+        assert!(blk_cfg_res.is_ok());
+        assert_eq!(blk_cfg_res.unwrap().werror, BlockErrorPolicy::Stop);
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,werror=ignore")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,rerror=retry,werror=retry,retry-interval=100,retry-timeout=5000"
+            )
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert_eq!(blk_cfg.rerror, BlockErrorPolicy::Retry);
+        assert_eq!(blk_cfg.werror, BlockErrorPolicy::Retry);
+        assert_eq!(blk_cfg.retry_interval, 100);
+        assert_eq!(blk_cfg.retry_timeout, 5000);
+        assert!(blk_cfg.rerror.should_retry(libc::EIO));
+        assert!(!blk_cfg.rerror.should_retry(libc::EINVAL));
+        assert!(!blk_cfg.werror.should_retry(libc::ENOSPC));
+        assert!(!BlockErrorPolicy::Report.should_retry(libc::EIO));
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,rerror=retry,retry-interval=0")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,discard=on,write-zeroes=on")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert!(blk_cfg.discard);
+        assert!(blk_cfg.write_zeroes);
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,discard=unmap")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,cache=writethrough")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert!(!blk_cfg.direct);
+        assert!(!blk_cfg.write_cache);
+        assert!(!blk_cfg.no_flush);
+        assert_eq!(blk_cfg.aio, AioEngine::Off);
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,cache=none")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert!(blk_cfg.direct);
+        assert!(blk_cfg.write_cache);
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,cache=unsafe")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert!(!blk_cfg.direct);
+        assert!(blk_cfg.no_flush);
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,cache=writearound")
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,cache=none,direct=off")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,share-rw=on")
+            .is_ok());
+        assert!(vm_config.drives.get("rootfs").unwrap().share_rw);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,format=qcow2")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert_eq!(blk_cfg.format, Some(DiskFormat::Qcow2));
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,format=vmdk")
+            .is_err());
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
@@ -768,6 +1334,21 @@ mod tests {
         assert_eq!(pflash_cfg.unit, 1);
         assert_eq!(pflash_cfg.path_on_host, "flash1.fd".to_string());
         assert_eq!(pflash_cfg.read_only, false);
+
+        // Unit 1 without unit 0.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("if=pflash,file=flash1.fd,unit=1")
+            .is_ok());
+        assert!(vm_config.check_pflash_units().is_err());
+        assert!(vm_config
+            .add_drive("if=pflash,readonly=on,file=flash0.fd,unit=0")
+            .is_ok());
+        assert!(vm_config.check_pflash_units().is_ok());
+        // Repeated unit.
+        assert!(vm_config
+            .add_drive("if=pflash,file=flash2.fd,unit=1")
+            .is_err());
     }
 
     #[test]
@@ -793,16 +1374,114 @@ mod tests {
         assert!(drive_conf.check().is_err());
 
         let mut drive_conf = DriveConfig::default();
-        drive_conf.iops = Some(MAX_IOPS);
+        drive_conf.throttle.iops_total = MAX_IOPS;
         assert!(drive_conf.check().is_ok());
 
         let mut drive_conf = DriveConfig::default();
-        drive_conf.iops = None;
+        drive_conf.throttle.iops_total = 0;
         assert!(drive_conf.check().is_ok());
 
         // Overflow
-        drive_conf.iops = Some(MAX_IOPS + 1);
+        drive_conf.throttle.iops_total = MAX_IOPS + 1;
+        assert!(drive_conf.check().is_err());
+
+        let mut drive_conf = DriveConfig::default();
+        drive_conf.throttle.bps_write = MAX_BPS + 1;
         assert!(drive_conf.check().is_err());
+
+        let mut drive_conf = DriveConfig {
+            path_on_host: "/path/to/base.img".to_string(),
+            overlay: Some("/path/to/overlay.img".to_string()),
+            direct: false,
+            ..Default::default()
+        };
+        assert!(drive_conf.check().is_ok());
+        // Overlay requires a writable drive with direct off.
+        drive_conf.direct = true;
+        assert!(drive_conf.check().is_err());
+        drive_conf.direct = false;
+        drive_conf.read_only = true;
+        assert!(drive_conf.check().is_err());
+        drive_conf.read_only = false;
+        drive_conf.discard = true;
+        assert!(drive_conf.check().is_err());
+        drive_conf.discard = false;
+        drive_conf.format = Some(DiskFormat::Qcow2);
+        assert!(drive_conf.check().is_err());
+        drive_conf.format = None;
+        drive_conf.overlay = Some(drive_conf.path_on_host.clone());
+        assert!(drive_conf.check().is_err());
+
+        // Snapshot has the same limits as overlay, and they can't be set together.
+        let mut drive_conf = DriveConfig {
+            path_on_host: "/path/to/base.img".to_string(),
+            snapshot: true,
+            direct: false,
+            ..Default::default()
+        };
+        assert!(drive_conf.check().is_ok());
+        drive_conf.overlay = Some("/path/to/overlay.img".to_string());
+        assert!(drive_conf.check().is_err());
+        drive_conf.overlay = None;
+        drive_conf.direct = true;
+        assert!(drive_conf.check().is_err());
+        drive_conf.direct = false;
+        // Qcow2 image is supported in snapshot mode.
+        drive_conf.format = Some(DiskFormat::Qcow2);
+        assert!(drive_conf.check().is_ok());
+    }
+
+    #[test]
+    fn test_drive_io_limits() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,throttling.iops-read=100,throttling.iops-write=200,throttling.bps-total=1048576"
+            )
+            .is_ok());
+        let throttle = vm_config.drives.get("rootfs").unwrap().throttle;
+        assert_eq!(throttle.iops_total, 0);
+        assert_eq!(throttle.iops_read, 100);
+        assert_eq!(throttle.iops_write, 200);
+        assert_eq!(throttle.bps_total, 1048576);
+        assert_eq!(throttle.bps_read, 0);
+
+        // Bps limits accept unit suffixes.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,throttling.bps-read=10M,throttling.bps-write=512Ki"
+            )
+            .is_ok());
+        let throttle = vm_config.drives.get("rootfs").unwrap().throttle;
+        assert_eq!(throttle.bps_read, 10 * 1024 * 1024);
+        assert_eq!(throttle.bps_write, 512 * 1024);
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,throttling.bps-read=10T")
+            .is_err());
+
+        // Zero value disables the limit, so it can coexist with read/write limits.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,throttling.iops-total=0,throttling.iops-read=100"
+            )
+            .is_ok());
+
+        // Read/write limits can't coexist with total limit.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,throttling.iops-total=100,throttling.iops-read=100"
+            )
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,throttling.bps-total=100,throttling.bps-write=100"
+            )
+            .is_err());
     }
 
     #[test]
@@ -0,0 +1,190 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{CmdParser, ConfigError, VmConfig, MAX_PATH_LENGTH};
+
+/// Max length of fw_cfg file name, the last byte of the 56 bytes is reserved for '\0'.
+pub const MAX_FW_CFG_NAME_LENGTH: usize = 55;
+/// Max size of the blob injected into fw_cfg by user.
+pub const MAX_FW_CFG_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// Prefix of the fw_cfg file names which are reserved for user.
+const FW_CFG_USER_PREFIX: &str = "opt/";
+
+/// Config of the guest-visible fw_cfg file specified by user.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FwCfgConfig {
+    /// Name of the fw_cfg file, such as `opt/org.example/seed`.
+    pub name: String,
+    /// Host file path whose content is used as the fw_cfg file data.
+    pub file: Option<String>,
+    /// String content used as the fw_cfg file data.
+    pub string: Option<String>,
+}
+
+impl FwCfgConfig {
+    fn check(&self) -> Result<()> {
+        if self.name.is_empty() {
+            bail!("Name of fw_cfg file can't be empty");
+        }
+        if self.name.len() > MAX_FW_CFG_NAME_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "fw_cfg name".to_string(),
+                MAX_FW_CFG_NAME_LENGTH,
+            )));
+        }
+        if !self.name.starts_with(FW_CFG_USER_PREFIX) {
+            warn!(
+                "Externally provided fw_cfg item name {} should be prefixed with \"{}\"",
+                self.name, FW_CFG_USER_PREFIX
+            );
+        }
+
+        match (&self.file, &self.string) {
+            (Some(file), None) => {
+                if file.len() > MAX_PATH_LENGTH {
+                    return Err(anyhow!(ConfigError::StringLengthTooLong(
+                        "fw_cfg file path".to_string(),
+                        MAX_PATH_LENGTH,
+                    )));
+                }
+                let size = std::fs::metadata(file)
+                    .with_context(|| format!("Failed to get metadata of fw_cfg file {}", file))?
+                    .len();
+                check_fw_cfg_size(&self.name, size)?;
+            }
+            (None, Some(string)) => check_fw_cfg_size(&self.name, string.len() as u64)?,
+            _ => bail!(
+                "Exactly one of \"file\" and \"string\" should be set for fw_cfg {}",
+                self.name
+            ),
+        }
+        Ok(())
+    }
+
+    /// Get the data of fw_cfg file, which is read from host file or taken from string.
+    pub fn get_data(&self) -> Result<Vec<u8>> {
+        let data = if let Some(file) = &self.file {
+            std::fs::read(file).with_context(|| format!("Failed to read fw_cfg file {}", file))?
+        } else if let Some(string) = &self.string {
+            string.as_bytes().to_vec()
+        } else {
+            bail!("No data is set for fw_cfg {}", self.name);
+        };
+        // The host file may be changed after the cmdline is parsed.
+        check_fw_cfg_size(&self.name, data.len() as u64)?;
+        Ok(data)
+    }
+}
+
+fn check_fw_cfg_size(name: &str, size: u64) -> Result<()> {
+    if size > MAX_FW_CFG_FILE_SIZE {
+        bail!(
+            "Size of fw_cfg {} is {} bytes, which exceeds the maximum {} bytes",
+            name,
+            size,
+            MAX_FW_CFG_FILE_SIZE
+        );
+    }
+    Ok(())
+}
+
+impl VmConfig {
+    /// Add guest-visible fw_cfg file: "-fw_cfg name=<name>,file=<path>" or
+    /// "-fw_cfg name=<name>,string=<str>".
+    pub fn add_fw_cfg(&mut self, fw_cfg_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("fw_cfg");
+        cmd_parser.push("name").push("file").push("string");
+        cmd_parser.parse(fw_cfg_config)?;
+
+        let name = if let Some(name) = cmd_parser.get_value::<String>("name")? {
+            name
+        } else {
+            return Err(anyhow!(ConfigError::FieldIsMissing("name", "fw_cfg")));
+        };
+        let fw_cfg = FwCfgConfig {
+            name,
+            file: cmd_parser.get_value::<String>("file")?,
+            string: cmd_parser.get_value::<String>("string")?,
+        };
+        fw_cfg.check()?;
+
+        if self.fw_cfgs.iter().any(|f| f.name == fw_cfg.name) {
+            bail!("fw_cfg {} has been added", fw_cfg.name);
+        }
+        self.fw_cfgs.push(fw_cfg);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_add_fw_cfg() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_fw_cfg("name=opt/org.test/cmdline,string=console=ttyS0")
+            .is_ok());
+        assert_eq!(vm_config.fw_cfgs.len(), 1);
+        assert_eq!(
+            vm_config.fw_cfgs[0].get_data().unwrap(),
+            b"console=ttyS0".to_vec()
+        );
+
+        let file_path = "/tmp/test_fw_cfg_seed";
+        let mut file = std::fs::File::create(file_path).unwrap();
+        file.write_all(b"instance-id: test").unwrap();
+        assert!(vm_config
+            .add_fw_cfg(&format!("name=opt/org.test/seed,file={}", file_path))
+            .is_ok());
+        assert_eq!(
+            vm_config.fw_cfgs[1].get_data().unwrap(),
+            b"instance-id: test".to_vec()
+        );
+
+        // Name collision.
+        assert!(vm_config
+            .add_fw_cfg("name=opt/org.test/seed,string=seed")
+            .is_err());
+        // Both or none of the data sources.
+        assert!(vm_config
+            .add_fw_cfg(&format!("name=opt/org.test/a,file={},string=a", file_path))
+            .is_err());
+        assert!(vm_config.add_fw_cfg("name=opt/org.test/b").is_err());
+        // Missing or too long name.
+        assert!(vm_config.add_fw_cfg("string=seed").is_err());
+        assert!(vm_config
+            .add_fw_cfg(&format!("name=opt/{},string=a", "a".repeat(52)))
+            .is_err());
+        // Nonexistent file.
+        assert!(vm_config
+            .add_fw_cfg("name=opt/org.test/c,file=/tmp/test_fw_cfg_nonexistent")
+            .is_err());
+
+        // Oversized blob.
+        file.set_len(MAX_FW_CFG_FILE_SIZE + 1).unwrap();
+        assert!(vm_config
+            .add_fw_cfg(&format!("name=opt/org.test/d,file={}", file_path))
+            .is_err());
+        assert!(vm_config.fw_cfgs[1].get_data().is_err());
+        assert_eq!(vm_config.fw_cfgs.len(), 2);
+
+        std::fs::remove_file(file_path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::net::Ipv4Addr;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{ConfigError, VmConfig, MAX_SOCK_PATH_LENGTH};
+
+/// Default listening ip of gdb stub server, only local debugger is allowed.
+const DEFAULT_GDB_IP: &str = "127.0.0.1";
+
+/// Listening address of gdb stub server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GdbConfig {
+    /// Tcp socket address as `ip:port`.
+    Tcp(String),
+    /// Unix socket path.
+    Unix(String),
+}
+
+impl VmConfig {
+    /// Add gdb stub server: "-gdb tcp:[ip]:<port>" or "-gdb unix:<path>".
+    pub fn add_gdb(&mut self, gdb_config: &str) -> Result<()> {
+        let gdb = match gdb_config.split_once(':') {
+            Some(("tcp", addr)) => {
+                let (ip, port) = match addr.split_once(':') {
+                    Some(("", port)) => (DEFAULT_GDB_IP, port),
+                    Some((ip, port)) => (ip, port),
+                    None => bail!("Invalid gdb server address {}", gdb_config),
+                };
+                if ip.parse::<Ipv4Addr>().is_err() {
+                    bail!("Invalid ip address {} for gdb server", ip);
+                }
+                if port.parse::<u16>().is_err() {
+                    bail!("Invalid ip port {} for gdb server", port);
+                }
+                GdbConfig::Tcp(format!("{}:{}", ip, port))
+            }
+            Some(("unix", path)) if !path.is_empty() => {
+                if path.len() > MAX_SOCK_PATH_LENGTH {
+                    return Err(anyhow!(ConfigError::StringLengthTooLong(
+                        "gdb socket path".to_string(),
+                        MAX_SOCK_PATH_LENGTH,
+                    )));
+                }
+                GdbConfig::Unix(path.to_string())
+            }
+            _ => bail!("Invalid gdb server address {}", gdb_config),
+        };
+
+        self.gdb = Some(gdb);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_gdb() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_gdb("tcp::1234").is_ok());
+        assert_eq!(
+            vm_config.gdb,
+            Some(GdbConfig::Tcp("127.0.0.1:1234".to_string()))
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_gdb("tcp:0.0.0.0:1234").is_ok());
+        assert_eq!(
+            vm_config.gdb,
+            Some(GdbConfig::Tcp("0.0.0.0:1234".to_string()))
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_gdb("unix:/tmp/gdb.sock").is_ok());
+        assert_eq!(
+            vm_config.gdb,
+            Some(GdbConfig::Unix("/tmp/gdb.sock".to_string()))
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_gdb("tcp:1234").is_err());
+        assert!(vm_config.add_gdb("tcp:300.0.0.1:1234").is_err());
+        assert!(vm_config.add_gdb("tcp::65536").is_err());
+        assert!(vm_config.add_gdb("unix:").is_err());
+        assert!(vm_config.add_gdb("file:/tmp/gdb").is_err());
+        assert!(vm_config
+            .add_gdb(&format!("unix:/{}", "a".repeat(108)))
+            .is_err());
+        assert!(vm_config.gdb.is_none());
+    }
+}
@@ -113,7 +113,7 @@ pub fn parse_gpu(gpu_config: &str) -> Result<GpuDevConfig> {
     if let Some(yres) = cmd_parser.get_value::<u32>("yres")? {
         gpu_cfg.yres = yres;
     }
-    if let Some(max_hostmem) = cmd_parser.get_value::<u64>("max_hostmem")? {
+    if let Some(max_hostmem) = cmd_parser.get_size("max_hostmem")? {
         gpu_cfg.max_hostmem = max_hostmem;
     }
     gpu_cfg.check()?;
@@ -0,0 +1,100 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{ConfigError, VmConfig, MAX_STRING_LENGTH};
+use crate::qmp::qmp_schema;
+
+impl VmConfig {
+    /// Add '-hotplug-manifest' config to `VmConfig`. The manifest is a json file of
+    /// the arguments of QMP command `device_add`, the devices are hot plugged in the
+    /// order in the file after VM is running.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the hotplug manifest.
+    pub fn add_hotplug_manifest(&mut self, path: &str) -> Result<()> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read hotplug manifest {}", path))?;
+        let devices = parse_hotplug_manifest(&content)
+            .with_context(|| format!("Invalid hotplug manifest {}", path))?;
+        self.hotplug_devices = devices;
+        Ok(())
+    }
+}
+
+fn parse_hotplug_manifest(content: &str) -> Result<Vec<qmp_schema::DeviceAddArgument>> {
+    let devices: Vec<qmp_schema::DeviceAddArgument> =
+        serde_json::from_str(content).with_context(|| "Failed to parse device list")?;
+
+    let mut ids = HashSet::new();
+    for device in devices.iter() {
+        if device.id.is_empty() {
+            return Err(anyhow!(ConfigError::FieldIsMissing("id", "hotplug device")));
+        }
+        if device.id.len() > MAX_STRING_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "hotplug device id".to_string(),
+                MAX_STRING_LENGTH,
+            )));
+        }
+        if !ids.insert(device.id.as_str()) {
+            bail!("Device id {} is repeated in hotplug manifest", device.id);
+        }
+    }
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hotplug_manifest_parser() {
+        let manifest = r#"
+        [
+            { "id": "blk1", "driver": "virtio-blk-pci", "drive": "drive1", "bus": "pcie.0",
+              "addr": "0x5" },
+            { "id": "net1", "driver": "virtio-net-pci", "netdev": "netdev1", "bus": "pcie.0",
+              "addr": "0x6", "mac": "12:34:56:78:9a:bc" }
+        ]
+        "#;
+        let devices = parse_hotplug_manifest(manifest).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].id, "blk1");
+        assert_eq!(devices[0].drive.as_deref(), Some("drive1"));
+        assert_eq!(devices[1].driver, "virtio-net-pci");
+        assert_eq!(devices[1].addr.as_deref(), Some("0x6"));
+
+        // Unknown argument of device_add.
+        let manifest = r#"[ { "id": "blk1", "driver": "virtio-blk-pci", "size": 1 } ]"#;
+        assert!(parse_hotplug_manifest(manifest).is_err());
+        // Missing driver.
+        assert!(parse_hotplug_manifest(r#"[ { "id": "blk1" } ]"#).is_err());
+        // Empty or repeated id.
+        let manifest = r#"[ { "id": "", "driver": "virtio-blk-pci" } ]"#;
+        assert!(parse_hotplug_manifest(manifest).is_err());
+        let manifest = r#"
+        [
+            { "id": "blk1", "driver": "virtio-blk-pci" },
+            { "id": "blk1", "driver": "virtio-net-pci" }
+        ]
+        "#;
+        assert!(parse_hotplug_manifest(manifest).is_err());
+        // Not a list.
+        let manifest = r#"{ "id": "blk1", "driver": "virtio-blk-pci" }"#;
+        assert!(parse_hotplug_manifest(manifest).is_err());
+    }
+}
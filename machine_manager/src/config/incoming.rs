@@ -36,6 +36,25 @@ impl From<&str> for MigrateMode {
     }
 }
 
+/// Check whether `host` is a valid host name, as described in RFC 1123.
+/// The top-level label can't be all numeric, so that a bad ip address
+/// won't be treated as a host name.
+fn is_valid_hostname(host: &str) -> bool {
+    if host.is_empty() || host.len() > 253 {
+        return false;
+    }
+
+    let labels: Vec<&str> = host.split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    labels.iter().all(valid_label) && !labels.last().unwrap().chars().all(|c| c.is_ascii_digit())
+}
+
 /// Parse `-incoming` cmdline to migrate mode and path.
 pub fn parse_incoming_uri(uri: &str) -> Result<(MigrateMode, String)> {
     let parse_vec: Vec<&str> = uri.split(':').collect();
@@ -48,8 +67,8 @@ pub fn parse_incoming_uri(uri: &str) -> Result<(MigrateMode, String)> {
     } else if parse_vec.len() == 3 {
         match MigrateMode::from(parse_vec[0]) {
             MigrateMode::Tcp => {
-                if parse_vec[1].parse::<Ipv4Addr>().is_err() {
-                    bail!("Invalid ip address {}", parse_vec[1]);
+                if parse_vec[1].parse::<Ipv4Addr>().is_err() && !is_valid_hostname(parse_vec[1]) {
+                    bail!("Invalid ip address or host name {}", parse_vec[1]);
                 }
                 if parse_vec[2].parse::<u16>().is_err() {
                     bail!("Invalid ip port {}", parse_vec[2]);
@@ -127,6 +146,14 @@ mod tests {
         let incoming_case5 = "tcp:192.168.1.2:65568";
         let result_5 = parse_incoming_uri(incoming_case5);
         assert!(result_5.is_err());
+
+        let incoming_case6 = "tcp:dest-host.example.com:4444";
+        let result_6 = parse_incoming_uri(incoming_case6).unwrap();
+        assert_eq!(result_6.0, MigrateMode::Tcp);
+        assert_eq!(result_6.1, "dest-host.example.com:4444".to_string());
+
+        let incoming_case7 = "tcp:-bad_host:4444";
+        assert!(parse_incoming_uri(incoming_case7).is_err());
     }
 
     #[test]
@@ -0,0 +1,132 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{error::ConfigError, pci_args_check, ConfigCheck, MAX_STRING_LENGTH};
+use crate::config::CmdParser;
+
+/// Max length of the serial of virtio input device, which is limited by the size of
+/// the string in device config space.
+const MAX_INPUT_SERIAL_LENGTH: usize = 128;
+
+/// Type of the virtio input device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputType {
+    Keyboard,
+    Mouse,
+    Tablet,
+}
+
+impl FromStr for InputType {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "keyboard" => Ok(InputType::Keyboard),
+            "mouse" => Ok(InputType::Mouse),
+            "tablet" => Ok(InputType::Tablet),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Config of the virtio input device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputConfig {
+    pub id: String,
+    pub input_type: InputType,
+    /// Serial of the device reported to guest.
+    pub serial: Option<String>,
+}
+
+impl ConfigCheck for InputConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "input id".to_string(),
+                MAX_STRING_LENGTH,
+            )));
+        }
+
+        if let Some(serial) = self.serial.as_ref() {
+            if serial.len() > MAX_INPUT_SERIAL_LENGTH {
+                return Err(anyhow!(ConfigError::StringLengthTooLong(
+                    "input serial".to_string(),
+                    MAX_INPUT_SERIAL_LENGTH,
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_input(input_config: &str) -> Result<InputConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-input");
+    cmd_parser
+        .push("")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("id")
+        .push("type")
+        .push("serial");
+    cmd_parser.parse(input_config)?;
+
+    pci_args_check(&cmd_parser)?;
+    let input = InputConfig {
+        id: cmd_parser
+            .get_value::<String>("id")?
+            .ok_or_else(|| anyhow!(ConfigError::FieldIsMissing("id", "virtio-input")))?,
+        input_type: cmd_parser
+            .get_value::<InputType>("type")?
+            .ok_or_else(|| anyhow!(ConfigError::FieldIsMissing("type", "virtio-input")))?,
+        serial: cmd_parser.get_value::<String>("serial")?,
+    };
+    input.check()?;
+    Ok(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_config_cmdline_parser() {
+        let input_cfg =
+            parse_input("virtio-input-pci,id=input0,type=keyboard,bus=pcie.0,addr=0x3").unwrap();
+        assert_eq!(input_cfg.id, "input0");
+        assert_eq!(input_cfg.input_type, InputType::Keyboard);
+        assert!(input_cfg.serial.is_none());
+
+        let input_cfg =
+            parse_input("virtio-input-device,id=input1,type=tablet,serial=tbl1").unwrap();
+        assert_eq!(input_cfg.input_type, InputType::Tablet);
+        assert_eq!(input_cfg.serial.as_deref(), Some("tbl1"));
+
+        // Type and id are required.
+        assert!(parse_input("virtio-input-pci,id=input0,bus=pcie.0,addr=0x3").is_err());
+        assert!(parse_input("virtio-input-pci,type=mouse,bus=pcie.0,addr=0x3").is_err());
+        // Unknown type.
+        assert!(parse_input("virtio-input-pci,id=input0,type=joystick").is_err());
+        // Mmio device does not support pci arguments.
+        assert!(parse_input("virtio-input-device,id=input0,type=mouse,bus=pcie.0").is_err());
+        // Serial is too long.
+        let serial = "s".repeat(MAX_INPUT_SERIAL_LENGTH + 1);
+        let cfg = format!("virtio-input-pci,id=input0,type=mouse,serial={}", serial);
+        assert!(parse_input(&cfg).is_err());
+    }
+}
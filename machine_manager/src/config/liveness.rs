@@ -0,0 +1,72 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{CmdParser, ConfigError, VmConfig};
+
+/// Max window of vcpu liveness monitor in seconds.
+const MAX_LIVENESS_WINDOW: u64 = 3600;
+
+/// Config of vcpu liveness monitor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LivenessConfig {
+    /// A vcpu is reported as stalled if it doesn't make progress in VMM within
+    /// the window, in seconds.
+    pub window: u64,
+}
+
+impl VmConfig {
+    /// Add '-vcpu-liveness' config to `VmConfig`.
+    pub fn add_vcpu_liveness(&mut self, liveness_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("vcpu-liveness");
+        cmd_parser.push("window");
+        cmd_parser.parse(liveness_config)?;
+
+        let window = cmd_parser
+            .get_value::<u64>("window")?
+            .ok_or_else(|| anyhow!(ConfigError::FieldIsMissing("window", "vcpu-liveness")))?;
+        if window == 0 || window > MAX_LIVENESS_WINDOW {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "vcpu liveness window".to_string(),
+                1,
+                true,
+                MAX_LIVENESS_WINDOW,
+                true,
+            )));
+        }
+        self.vcpu_liveness = Some(LivenessConfig { window });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_vcpu_liveness() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.vcpu_liveness.is_none());
+        assert!(vm_config.add_vcpu_liveness("window=5").is_ok());
+        assert_eq!(vm_config.vcpu_liveness, Some(LivenessConfig { window: 5 }));
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_vcpu_liveness("window=0").is_err());
+        assert!(vm_config.add_vcpu_liveness("window=3601").is_err());
+        assert!(vm_config.add_vcpu_liveness("window=-1").is_err());
+        assert!(vm_config.add_vcpu_liveness("interval=5").is_err());
+        assert!(vm_config.add_vcpu_liveness("").is_err());
+        assert!(vm_config.vcpu_liveness.is_none());
+    }
+}
@@ -10,14 +10,17 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use util::unix::{host_cpu_num, host_page_size};
 
 use super::error::ConfigError;
 use crate::config::{
-    CmdParser, ConfigCheck, ExBool, IntegerList, VmConfig, MAX_NODES, MAX_STRING_LENGTH,
+    parse_size, x86_cpuid_feature, CmdParser, ConfigCheck, CpuFeatureFlag, ExBool, IntegerList,
+    VmConfig, MAX_NODES, MAX_STRING_LENGTH,
 };
 
 const DEFAULT_CPUS: u8 = 1;
@@ -32,6 +35,9 @@ const MAX_NR_CPUS: u64 = 254;
 const MIN_NR_CPUS: u64 = 1;
 const MAX_MEMSIZE: u64 = 549_755_813_888;
 const MIN_MEMSIZE: u64 = 134_217_728;
+const DEFAULT_DESTROY_TIMEOUT: u64 = 1000;
+const MAX_DESTROY_TIMEOUT: u64 = 60_000;
+pub const K: u64 = 1024;
 pub const M: u64 = 1024 * 1024;
 pub const G: u64 = 1024 * 1024 * 1024;
 
@@ -107,6 +113,10 @@ pub struct MachineMemConfig {
     pub dump_guest_core: bool,
     pub mem_share: bool,
     pub mem_prealloc: bool,
+    /// Lock guest memory in host RAM to keep it from being swapped.
+    pub mem_lock: bool,
+    /// Size of huge page that backs guest memory, `None` means normal page.
+    pub hugepage_size: Option<u64>,
     pub mem_zones: Option<Vec<MemZoneConfig>>,
 }
 
@@ -118,6 +128,8 @@ impl Default for MachineMemConfig {
             dump_guest_core: true,
             mem_share: false,
             mem_prealloc: false,
+            mem_lock: false,
+            hugepage_size: None,
             mem_zones: None,
         }
     }
@@ -126,6 +138,8 @@ impl Default for MachineMemConfig {
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct CpuConfig {
     pub pmu: PmuConfig,
+    /// CPUID feature flags to be forced on or masked off, only for x86_64.
+    pub features: Vec<CpuFeatureFlag>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -152,6 +166,37 @@ pub enum ShutdownAction {
     ShutdownActionPause,
 }
 
+/// Action taken by VM when a vcpu hits triple fault, which is reported as
+/// `KVM_EXIT_SHUTDOWN` on x86_64.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TripleFaultAction {
+    /// Handle it as guest poweroff, which follows `ShutdownAction`.
+    Poweroff,
+    /// Reboot the VM.
+    Reboot,
+    /// Pause the VM and report it as guest panic.
+    Pause,
+}
+
+impl Default for TripleFaultAction {
+    fn default() -> Self {
+        TripleFaultAction::Poweroff
+    }
+}
+
+impl FromStr for TripleFaultAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "poweroff" => Ok(TripleFaultAction::Poweroff),
+            "reboot" => Ok(TripleFaultAction::Reboot),
+            "pause" => Ok(TripleFaultAction::Pause),
+            _ => Err(()),
+        }
+    }
+}
+
 /// Config struct for machine-config.
 /// Contains some basic Vm config about cpu, memory, name.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -167,6 +212,12 @@ pub struct MachineConfig {
     pub mem_config: MachineMemConfig,
     pub cpu_config: CpuConfig,
     pub shutdown_action: ShutdownAction,
+    /// Action taken when a vcpu hits triple fault.
+    pub triple_fault_action: TripleFaultAction,
+    /// Host cpus which each vcpu is pinned to, indexed by vcpu id.
+    pub vcpu_affinity: BTreeMap<u8, Vec<u64>>,
+    /// Max time in milliseconds to wait for all vcpus to stop when VM is destroyed.
+    pub destroy_timeout: u64,
 }
 
 impl Default for MachineConfig {
@@ -184,6 +235,9 @@ impl Default for MachineConfig {
             mem_config: MachineMemConfig::default(),
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
+            triple_fault_action: TripleFaultAction::default(),
+            vcpu_affinity: BTreeMap::new(),
+            destroy_timeout: DEFAULT_DESTROY_TIMEOUT,
         }
     }
 }
@@ -195,6 +249,41 @@ impl ConfigCheck for MachineConfig {
             &self.mem_config.mem_size);
         }
 
+        if let Some(hugepage_size) = self.mem_config.hugepage_size {
+            if self.mem_config.mem_path.is_some() {
+                bail!("\'hugepages\' conflicts with mem-path, whose page size is decided by hugetlbfs");
+            }
+            if self.mem_config.mem_size % hugepage_size != 0 {
+                return Err(anyhow!(ConfigError::Unaligned(
+                    "memory size".to_string(),
+                    self.mem_config.mem_size,
+                    hugepage_size
+                )));
+            }
+        }
+
+        let host_cpus = host_cpu_num();
+        for (vcpu, host_cpu_list) in self.vcpu_affinity.iter() {
+            if *vcpu >= self.max_cpus {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "vcpu id of vcpu-affinity".to_string(),
+                    0,
+                    true,
+                    self.max_cpus as u64,
+                    false,
+                )));
+            }
+            if host_cpu_list.iter().any(|cpu| *cpu >= host_cpus) {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    format!("host cpu of vcpu{}", vcpu),
+                    0,
+                    true,
+                    host_cpus,
+                    false,
+                )));
+            }
+        }
+
         Ok(())
     }
 }
@@ -213,9 +302,12 @@ impl VmConfig {
             .push("accel")
             .push("usb")
             .push("dump-guest-core")
-            .push("mem-share");
+            .push("mem-share")
+            .push("destroy-timeout");
         #[cfg(target_arch = "aarch64")]
         cmd_parser.push("gic-version");
+        #[cfg(target_arch = "x86_64")]
+        cmd_parser.push("triple-fault");
         cmd_parser.parse(mach_config)?;
 
         #[cfg(target_arch = "aarch64")]
@@ -254,6 +346,22 @@ impl VmConfig {
         if let Some(mem_share) = cmd_parser.get_value::<ExBool>("mem-share")? {
             self.machine_config.mem_config.mem_share = mem_share.into();
         }
+        if let Some(timeout) = cmd_parser.get_value::<u64>("destroy-timeout")? {
+            if timeout == 0 || timeout > MAX_DESTROY_TIMEOUT {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "destroy-timeout".to_string(),
+                    1,
+                    true,
+                    MAX_DESTROY_TIMEOUT,
+                    true
+                )));
+            }
+            self.machine_config.destroy_timeout = timeout;
+        }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(action) = cmd_parser.get_value::<TripleFaultAction>("triple-fault")? {
+            self.machine_config.triple_fault_action = action;
+        }
 
         Ok(())
     }
@@ -276,7 +384,7 @@ impl VmConfig {
     /// Add '-m' memory config to `VmConfig`.
     pub fn add_memory(&mut self, mem_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("m");
-        cmd_parser.push("").push("size");
+        cmd_parser.push("").push("size").push("hugepages");
 
         cmd_parser.parse(mem_config)?;
 
@@ -290,6 +398,17 @@ impl VmConfig {
 
         self.machine_config.mem_config.mem_size = mem;
 
+        if let Some(hugepage_size) = cmd_parser.get_value::<String>("hugepages")? {
+            let hugepage_size = memory_unit_conversion(&hugepage_size)?;
+            if !hugepage_size.is_power_of_two() || hugepage_size <= host_page_size() {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "hugepages".to_string(),
+                    "m".to_string()
+                )));
+            }
+            self.machine_config.mem_config.hugepage_size = Some(hugepage_size);
+        }
+
         Ok(())
     }
 
@@ -386,11 +505,61 @@ impl VmConfig {
         Ok(())
     }
 
+    /// Add '-vcpu-affinity' config to `VmConfig`.
+    ///
+    /// # Arguments
+    ///
+    /// * `affinity` - Affinity config as `<vcpu>=<host cpu list>`, e.g. `0=0-3,8`.
+    pub fn add_vcpu_affinity(&mut self, affinity: &str) -> Result<()> {
+        let (vcpu, host_cpus) = affinity
+            .split_once('=')
+            .with_context(|| format!("Invalid vcpu-affinity {}", affinity))?;
+        let vcpu = vcpu.trim().parse::<u8>().map_err(|_| {
+            anyhow!(ConfigError::ConvertValueFailed(
+                vcpu.to_string(),
+                "vcpu-affinity".to_string()
+            ))
+        })?;
+        // Host cpus are separated by ',', which is the same as ':' in `IntegerList`.
+        let host_cpus = IntegerList::from_str(&host_cpus.replace(',', ":")).map_err(|_| {
+            anyhow!(ConfigError::ConvertValueFailed(
+                host_cpus.to_string(),
+                "vcpu-affinity".to_string()
+            ))
+        })?;
+
+        if self
+            .machine_config
+            .vcpu_affinity
+            .insert(vcpu, host_cpus.0)
+            .is_some()
+        {
+            return Err(anyhow!(ConfigError::FieldRepeat(
+                format!("vcpu{}", vcpu),
+                "vcpu-affinity".to_string()
+            )));
+        }
+        Ok(())
+    }
+
     pub fn add_cpu_feature(&mut self, features: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("cpu");
         cmd_parser.push("");
         cmd_parser.push("pmu");
-        cmd_parser.parse(features)?;
+
+        // Feature flags are given as `+flag` or `-flag`, which can't be
+        // parsed by `CmdParser`, so pick them out first.
+        let mut props = Vec::new();
+        for prop in features.split(',') {
+            if let Some(name) = prop.strip_prefix('+') {
+                self.add_cpu_feature_flag(name, true)?;
+            } else if let Some(name) = prop.strip_prefix('-') {
+                self.add_cpu_feature_flag(name, false)?;
+            } else {
+                props.push(prop);
+            }
+        }
+        cmd_parser.parse(&props.join(","))?;
         //Check PMU when actually enabling PMU.
         if let Some(k) = cmd_parser.get_value::<String>("pmu")? {
             self.machine_config.cpu_config.pmu = match k.as_ref() {
@@ -402,6 +571,28 @@ impl VmConfig {
         Ok(())
     }
 
+    fn add_cpu_feature_flag(&mut self, name: &str, enabled: bool) -> Result<()> {
+        if cfg!(not(target_arch = "x86_64")) {
+            bail!("CPU feature flags are only supported on x86_64");
+        }
+        let cpuid = x86_cpuid_feature(name).with_context(|| {
+            ConfigError::InvalidParam(
+                format!("{}{}", if enabled { "+" } else { "-" }, name),
+                "cpu".to_string(),
+            )
+        })?;
+
+        // The latter one takes effect if the flag is offered more than once.
+        let features = &mut self.machine_config.cpu_config.features;
+        features.retain(|flag| flag.cpuid != cpuid);
+        features.push(CpuFeatureFlag {
+            name: name.to_string(),
+            cpuid,
+            enabled,
+        });
+        Ok(())
+    }
+
     pub fn add_mem_path(&mut self, mem_path: &str) -> Result<()> {
         self.machine_config.mem_config.mem_path = Some(mem_path.replace('\"', ""));
         Ok(())
@@ -411,6 +602,10 @@ impl VmConfig {
         self.machine_config.mem_config.mem_prealloc = true;
     }
 
+    pub fn enable_mem_lock(&mut self) {
+        self.machine_config.mem_config.mem_lock = true;
+    }
+
     pub fn add_no_shutdown(&mut self) -> bool {
         self.machine_config.shutdown_action = ShutdownAction::ShutdownActionPause;
         true
@@ -587,45 +782,13 @@ fn adjust_topology(
     (max_cpus, sockets, cores, threads)
 }
 
-/// Convert memory units from GiB, Mib to Byte.
+/// Convert memory units from GiB, Mib to Byte. Value without unit is in MiB.
 ///
 /// # Arguments
 ///
 /// * `origin_value` - The origin memory value from user.
 fn memory_unit_conversion(origin_value: &str) -> Result<u64> {
-    if (origin_value.ends_with('M') | origin_value.ends_with('m'))
-        && (origin_value.contains('M') ^ origin_value.contains('m'))
-    {
-        let value = origin_value.replacen('M', "", 1);
-        let value = value.replacen('m', "", 1);
-        get_inner(
-            value
-                .parse::<u64>()
-                .map_err(|_| {
-                    anyhow!(ConfigError::ConvertValueFailed(
-                        origin_value.to_string(),
-                        String::from("u64")
-                    ))
-                })?
-                .checked_mul(M),
-        )
-    } else if (origin_value.ends_with('G') | origin_value.ends_with('g'))
-        && (origin_value.contains('G') ^ origin_value.contains('g'))
-    {
-        let value = origin_value.replacen('G', "", 1);
-        let value = value.replacen('g', "", 1);
-        get_inner(
-            value
-                .parse::<u64>()
-                .map_err(|_| {
-                    anyhow!(ConfigError::ConvertValueFailed(
-                        origin_value.to_string(),
-                        String::from("u64")
-                    ))
-                })?
-                .checked_mul(G),
-        )
-    } else {
+    if origin_value.ends_with(|c: char| c.is_ascii_digit()) {
         let size = origin_value.parse::<u64>().map_err(|_| {
             anyhow!(ConfigError::ConvertValueFailed(
                 origin_value.to_string(),
@@ -636,6 +799,8 @@ fn memory_unit_conversion(origin_value: &str) -> Result<u64> {
         let memory_size = size.checked_mul(M);
 
         get_inner(memory_size)
+    } else {
+        parse_size("memory size", origin_value)
     }
 }
 
@@ -659,6 +824,8 @@ mod tests {
             mem_share: false,
             dump_guest_core: false,
             mem_prealloc: false,
+            mem_lock: false,
+            hugepage_size: None,
             mem_zones: None,
         };
         let mut machine_config = MachineConfig {
@@ -673,6 +840,9 @@ mod tests {
             mem_config: memory_config,
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
+            triple_fault_action: TripleFaultAction::default(),
+            vcpu_affinity: BTreeMap::new(),
+            destroy_timeout: DEFAULT_DESTROY_TIMEOUT,
         };
         assert!(machine_config.check().is_ok());
 
@@ -871,6 +1041,28 @@ mod tests {
         assert!(mem_cfg_ret.is_ok());
         let mem_size = vm_config.machine_config.mem_config.mem_size;
         assert_eq!(mem_size, 8 * 1024 * 1024 * 1024);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_memory("4G,hugepages=2M").is_ok());
+        let mem_config = &vm_config.machine_config.mem_config;
+        assert_eq!(mem_config.hugepage_size, Some(2 * M));
+        assert!(vm_config.machine_config.check().is_ok());
+        assert!(vm_config.add_memory("size=4G,hugepages=1G").is_ok());
+        assert_eq!(vm_config.machine_config.mem_config.hugepage_size, Some(G));
+
+        // Memory size unaligned with huge page size.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_memory("3073M,hugepages=2M").is_ok());
+        assert!(vm_config.machine_config.check().is_err());
+        // Huge page size conflicts with mem-path.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_memory("4G,hugepages=2M").is_ok());
+        assert!(vm_config.add_mem_path("/dev/hugepages").is_ok());
+        assert!(vm_config.machine_config.check().is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_memory("4G,hugepages=3M").is_err());
+        assert!(vm_config.add_memory("4G,hugepages=0").is_err());
     }
 
     #[test]
@@ -903,6 +1095,41 @@ mod tests {
         let machine_cfg_ret = vm_config.add_machine(memory_cfg_str);
         assert!(machine_cfg_ret.is_err());
 
+        let mut vm_config = VmConfig::default();
+        assert_eq!(
+            vm_config.machine_config.destroy_timeout,
+            DEFAULT_DESTROY_TIMEOUT
+        );
+        assert!(vm_config
+            .add_machine("type=none,destroy-timeout=200")
+            .is_ok());
+        assert_eq!(vm_config.machine_config.destroy_timeout, 200);
+        assert!(vm_config
+            .add_machine("type=none,destroy-timeout=0")
+            .is_err());
+        assert!(vm_config
+            .add_machine("type=none,destroy-timeout=60001")
+            .is_err());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut vm_config = VmConfig::default();
+            assert_eq!(
+                vm_config.machine_config.triple_fault_action,
+                TripleFaultAction::Poweroff
+            );
+            assert!(vm_config
+                .add_machine("type=q35,triple-fault=reboot")
+                .is_ok());
+            assert_eq!(
+                vm_config.machine_config.triple_fault_action,
+                TripleFaultAction::Reboot
+            );
+            assert!(vm_config
+                .add_machine("type=q35,triple-fault=reset")
+                .is_err());
+        }
+
         #[cfg(target_arch = "aarch64")]
         {
             let mut vm_config = VmConfig::default();
@@ -958,6 +1185,15 @@ mod tests {
         assert_eq!(mem_prealloc, true);
     }
 
+    #[test]
+    fn test_enable_memory_lock() {
+        let mut vm_config = VmConfig::default();
+        // default value is false.
+        assert!(!vm_config.machine_config.mem_config.mem_lock);
+        vm_config.enable_mem_lock();
+        assert!(vm_config.machine_config.mem_config.mem_lock);
+    }
+
     #[test]
     fn test_add_cpu() {
         let mut vm_config = VmConfig::default();
@@ -967,6 +1203,21 @@ mod tests {
         let nr_cpu = vm_config.machine_config.nr_cpus;
         assert_eq!(nr_cpu, 8);
 
+        let mut vm_config = VmConfig::default();
+        let cpu_cfg_str = "8,sockets=2,cores=4,threads=1";
+        let cpu_cfg_ret = vm_config.add_cpu(cpu_cfg_str);
+        assert!(cpu_cfg_ret.is_ok());
+        assert_eq!(vm_config.machine_config.nr_cpus, 8);
+        assert_eq!(vm_config.machine_config.nr_sockets, 2);
+        assert_eq!(vm_config.machine_config.nr_cores, 4);
+        assert_eq!(vm_config.machine_config.nr_threads, 1);
+        assert_eq!(vm_config.machine_config.max_cpus, 8);
+
+        let mut vm_config = VmConfig::default();
+        let cpu_cfg_str = "8,sockets=2,cores=2,threads=1";
+        let cpu_cfg_ret = vm_config.add_cpu(cpu_cfg_str);
+        assert!(cpu_cfg_ret.is_err());
+
         let mut vm_config = VmConfig::default();
         let cpu_cfg_str = "cpus=9,sockets=8,cores=1,threads=1";
         let cpu_cfg_ret = vm_config.add_cpu(cpu_cfg_str);
@@ -1048,4 +1299,65 @@ mod tests {
         vm_config.add_cpu_feature("pmu=on").unwrap();
         assert!(vm_config.machine_config.cpu_config.pmu == PmuConfig::On);
     }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_cpu_feature_flags() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        assert!(vm_config.machine_config.cpu_config.features.is_empty());
+
+        vm_config
+            .add_cpu_feature("host,+ssse3,-avx512f,-sse4.1")
+            .unwrap();
+        let features = &vm_config.machine_config.cpu_config.features;
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0].name, "ssse3");
+        assert!(features[0].enabled);
+        assert_eq!(features[1].name, "avx512f");
+        assert!(!features[1].enabled);
+        assert_eq!(features[2].cpuid, x86_cpuid_feature("sse4_1").unwrap());
+
+        // The latter one overrides the former one of the same flag.
+        vm_config.add_cpu_feature("host,+avx512f").unwrap();
+        let features = &vm_config.machine_config.cpu_config.features;
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[2].name, "avx512f");
+        assert!(features[2].enabled);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_cpu_feature("host,+avx1024").is_err());
+        assert!(vm_config.add_cpu_feature("host,-").is_err());
+        assert!(vm_config.add_cpu_feature("host,avx2").is_err());
+        assert!(vm_config.machine_config.cpu_config.features.is_empty());
+    }
+
+    #[test]
+    fn test_vcpu_affinity() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu("cpus=4").unwrap();
+        vm_config.add_vcpu_affinity("0=0").unwrap();
+        vm_config.add_vcpu_affinity("3=0-1,0").unwrap();
+        let affinity = &vm_config.machine_config.vcpu_affinity;
+        assert_eq!(affinity.get(&0), Some(&vec![0]));
+        assert_eq!(affinity.get(&3), Some(&vec![0, 1, 0]));
+        assert!(vm_config.machine_config.check().is_ok());
+
+        // Affinity of one vcpu can only be set once.
+        assert!(vm_config.add_vcpu_affinity("0=1").is_err());
+        assert!(vm_config.add_vcpu_affinity("1").is_err());
+        assert!(vm_config.add_vcpu_affinity("a=1").is_err());
+        assert!(vm_config.add_vcpu_affinity("1=3-1").is_err());
+
+        // Vcpu id is out of range.
+        vm_config.add_vcpu_affinity("4=0").unwrap();
+        assert!(vm_config.machine_config.check().is_err());
+        vm_config.machine_config.vcpu_affinity.remove(&4);
+
+        // Host cpu is out of range.
+        vm_config
+            .add_vcpu_affinity(&format!("1={}", host_cpu_num()))
+            .unwrap();
+        assert!(vm_config.machine_config.check().is_err());
+    }
 }
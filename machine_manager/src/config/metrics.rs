@@ -0,0 +1,95 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::net::Ipv4Addr;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{ConfigError, VmConfig, MAX_SOCK_PATH_LENGTH};
+
+/// Default listening ip of metrics server, only local scraper is allowed.
+const DEFAULT_METRICS_IP: &str = "127.0.0.1";
+
+/// Listening address of metrics server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MetricsConfig {
+    /// Tcp socket address as `ip:port`.
+    Tcp(String),
+    /// Unix socket path.
+    Unix(String),
+}
+
+impl VmConfig {
+    /// Add metrics server: "-metrics tcp:[ip]:<port>" or "-metrics unix:<path>".
+    pub fn add_metrics(&mut self, metrics_config: &str) -> Result<()> {
+        let metrics = match metrics_config.split_once(':') {
+            Some(("tcp", addr)) => {
+                let (ip, port) = match addr.split_once(':') {
+                    Some(("", port)) => (DEFAULT_METRICS_IP, port),
+                    Some((ip, port)) => (ip, port),
+                    None => bail!("Invalid metrics server address {}", metrics_config),
+                };
+                if ip.parse::<Ipv4Addr>().is_err() {
+                    bail!("Invalid ip address {} for metrics server", ip);
+                }
+                if port.parse::<u16>().is_err() {
+                    bail!("Invalid ip port {} for metrics server", port);
+                }
+                MetricsConfig::Tcp(format!("{}:{}", ip, port))
+            }
+            Some(("unix", path)) if !path.is_empty() => {
+                if path.len() > MAX_SOCK_PATH_LENGTH {
+                    return Err(anyhow!(ConfigError::StringLengthTooLong(
+                        "metrics socket path".to_string(),
+                        MAX_SOCK_PATH_LENGTH,
+                    )));
+                }
+                MetricsConfig::Unix(path.to_string())
+            }
+            _ => bail!("Invalid metrics server address {}", metrics_config),
+        };
+
+        self.metrics = Some(metrics);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_metrics() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_metrics("tcp::9100").is_ok());
+        assert_eq!(
+            vm_config.metrics,
+            Some(MetricsConfig::Tcp("127.0.0.1:9100".to_string()))
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_metrics("unix:/tmp/metrics.sock").is_ok());
+        assert_eq!(
+            vm_config.metrics,
+            Some(MetricsConfig::Unix("/tmp/metrics.sock".to_string()))
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_metrics("tcp:9100").is_err());
+        assert!(vm_config.add_metrics("tcp:localhost:9100").is_err());
+        assert!(vm_config.add_metrics("tcp::0x10").is_err());
+        assert!(vm_config.add_metrics("unix:").is_err());
+        assert!(vm_config.add_metrics("http://127.0.0.1:9100").is_err());
+        assert!(vm_config.metrics.is_none());
+    }
+}
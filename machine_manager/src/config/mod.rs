@@ -10,22 +10,32 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub use acpi_table::*;
 pub use balloon::*;
 pub use boot_source::*;
 pub use chardev::*;
+pub use config_file::ConfigFile;
+pub use cpu_feature::*;
 pub use demo_dev::*;
 pub use devices::*;
 pub use drive::*;
 pub use error::ConfigError;
 pub use fs::*;
+pub use fw_cfg::*;
+pub use gdb::*;
 pub use gpu::*;
 pub use incoming::*;
+pub use input::*;
 pub use iothread::*;
+pub use liveness::*;
 pub use machine_config::*;
+pub use metrics::*;
 pub use network::*;
 pub use numa::*;
 pub use pci::*;
+pub use pvpanic::*;
 pub use rng::*;
+pub use rtc::*;
 pub use sasl_auth::*;
 pub use scsi::*;
 pub use tls_creds::*;
@@ -33,22 +43,33 @@ pub use usb::*;
 pub use vfio::*;
 pub use vnc::*;
 
+mod acpi_table;
 mod balloon;
 mod boot_source;
 mod chardev;
+mod config_file;
+mod cpu_feature;
 mod demo_dev;
 mod devices;
 mod drive;
 pub mod error;
 mod fs;
+mod fw_cfg;
+mod gdb;
 mod gpu;
+mod hotplug;
 mod incoming;
+mod input;
 mod iothread;
+mod liveness;
 mod machine_config;
+mod metrics;
 mod network;
 mod numa;
 mod pci;
+mod pvpanic;
 mod rng;
+mod rtc;
 mod sasl_auth;
 mod scsi;
 mod tls_creds;
@@ -56,9 +77,10 @@ mod usb;
 mod vfio;
 pub mod vnc;
 
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{metadata, File, OpenOptions};
 use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -74,6 +96,8 @@ use util::{
     AsAny,
 };
 
+use crate::qmp::qmp_schema;
+
 pub const MAX_STRING_LENGTH: usize = 255;
 pub const MAX_PATH_LENGTH: usize = 4096;
 // Maximum length of the socket path is restricted by linux.
@@ -82,6 +106,8 @@ pub const MAX_SOCK_PATH_LENGTH: usize = 108;
 pub const MAX_VIRTIO_QUEUE: usize = 32;
 pub const FAST_UNPLUG_ON: &str = "1";
 pub const FAST_UNPLUG_OFF: &str = "0";
+pub const PACKED_RING_ON: &str = "1";
+pub const PACKED_RING_OFF: &str = "0";
 pub const MAX_TAG_LENGTH: usize = 36;
 pub const MAX_NODES: u32 = 128;
 /// Default virtqueue size for virtio devices excepts virtio-fs.
@@ -115,6 +141,16 @@ pub struct VmConfig {
     pub numa_nodes: Vec<(String, String)>,
     pub incoming: Option<Incoming>,
     pub vnc: Option<VncConfig>,
+    pub gdb: Option<GdbConfig>,
+    pub fw_cfgs: Vec<FwCfgConfig>,
+    pub acpi_tables: Vec<AcpiTableConfig>,
+    pub rtc: RtcConfig,
+    pub vcpu_liveness: Option<LivenessConfig>,
+    pub metrics: Option<MetricsConfig>,
+    /// Unix socket path of the guest agent channel.
+    pub guest_agent: Option<String>,
+    /// Devices hot plugged after VM is running, loaded from hotplug manifest.
+    pub hotplug_devices: Vec<qmp_schema::DeviceAddArgument>,
 }
 
 impl VmConfig {
@@ -129,12 +165,18 @@ impl VmConfig {
                 MAX_STRING_LENGTH,
             )));
         }
+        // Microvm can boot from the firmware stored in pflash instead of kernel.
         if self.boot_source.kernel_file.is_none()
             && self.machine_config.mach_type == MachineType::MicroVm
+            && self.pflashs.is_none()
         {
-            bail!("kernel file is required for microvm machine type, which is not provided");
+            bail!(
+                "kernel file or pflash is required for microvm machine type, which is not provided"
+            );
         }
 
+        self.check_pflash_units()?;
+
         if self.boot_source.initrd.is_none()
             && self.drives.is_empty()
             && self.chardev.is_empty()
@@ -161,6 +203,15 @@ impl VmConfig {
             bail!("Can't set multiple devices redirected to stdio");
         }
 
+        let mut boot_indexes = HashSet::new();
+        for (_, cfg_args) in &self.devices {
+            if let Some(boot_index) = parse_device_bootindex(cfg_args)? {
+                if !boot_indexes.insert(boot_index) {
+                    bail!("Bootindex {} is used by more than one device", boot_index);
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -233,7 +284,9 @@ impl VmConfig {
     /// * `global_config` - The args of global config.
     pub fn add_global_config(&mut self, global_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("global");
-        cmd_parser.push("pcie-root-port.fast-unplug");
+        cmd_parser
+            .push("pcie-root-port.fast-unplug")
+            .push("virtio.packed");
         cmd_parser.parse(global_config)?;
 
         if let Some(fast_unplug_value) =
@@ -250,6 +303,43 @@ impl VmConfig {
                 bail!("Global config {} has been added", fast_unplug_key);
             }
         }
+        if let Some(packed_value) = cmd_parser.get_value::<String>("virtio.packed")? {
+            if packed_value != PACKED_RING_ON && packed_value != PACKED_RING_OFF {
+                bail!("The value of packed is invalid: {}", packed_value);
+            }
+            let packed_key = String::from("virtio.packed");
+            if self.global_config.get(&packed_key).is_none() {
+                self.global_config.insert(packed_key, packed_value);
+            } else {
+                bail!("Global config {} has been added", packed_key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the file in drive file store can be shared by a new drive.
+    fn check_drive_file_share(
+        drive_file: &DriveFile,
+        path: &str,
+        read_only: bool,
+        share_rw: bool,
+    ) -> Result<()> {
+        // File can be shared by read-only drives, or by writable drives all with share-rw.
+        let shareable = if read_only {
+            drive_file.read_only
+        } else {
+            !drive_file.read_only && drive_file.share_rw && share_rw
+        };
+        if !shareable {
+            bail!(
+                "Failed to add drive {}, it's the same file as drive file {}, which can only be \
+                shared with read_only or share-rw=on. Concurrent writes from different drives \
+                corrupt the image unless the guest coordinates them, e.g. by a clustered \
+                filesystem, set share-rw=on only if the data integrity is ensured by guest.",
+                path,
+                drive_file.path
+            );
+        }
         Ok(())
     }
 
@@ -259,21 +349,33 @@ impl VmConfig {
         path: &str,
         read_only: bool,
         direct: bool,
+        share_rw: bool,
     ) -> Result<()> {
         if let Some(drive_file) = drive_files.get_mut(path) {
-            if drive_file.read_only && read_only {
-                // File can be shared with read_only.
-                drive_file.count += 1;
-                return Ok(());
-            } else {
-                return Err(anyhow!(
-                    "Failed to add drive {}, file can only be shared with read_only. \
-                    Is it used more than once or another process using the same file?",
-                    path
-                ));
-            }
+            Self::check_drive_file_share(drive_file, path, read_only, share_rw)?;
+            drive_file.count += 1;
+            return Ok(());
         }
-        let mut file = open_file(path, read_only, direct)?;
+        // The same file may be referenced by another path through symlinks or hardlinks.
+        let same_file = metadata(path).ok().and_then(|meta| {
+            drive_files
+                .values()
+                .find(|drive_file| drive_file.ident == (meta.dev(), meta.ino()))
+        });
+        let mut file = match same_file {
+            Some(drive_file) => {
+                Self::check_drive_file_share(drive_file, path, read_only, share_rw)?;
+                // Share the opened file, so that the file lock is held by the same description.
+                drive_file
+                    .file
+                    .try_clone()
+                    .with_context(|| format!("Failed to clone drive backend file {}", path))?
+            }
+            None => open_file(path, read_only, direct)?,
+        };
+        let meta = file
+            .metadata()
+            .with_context(|| format!("Failed to get metadata of drive file {}", path))?;
         let (req_align, buf_align) = get_file_alignment(&file, direct);
         if req_align == 0 || buf_align == 0 {
             bail!(
@@ -289,6 +391,8 @@ impl VmConfig {
             file,
             count: 1,
             read_only,
+            share_rw,
+            ident: (meta.dev(), meta.ino()),
             path: path.to_string(),
             locked: false,
             req_align,
@@ -343,12 +447,22 @@ impl VmConfig {
     pub fn init_drive_files(&self) -> Result<HashMap<String, DriveFile>> {
         let mut drive_files: HashMap<String, DriveFile> = HashMap::new();
         for drive in self.drives.values() {
+            // The image is never modified if guest writes go to the overlay.
             Self::add_drive_file(
                 &mut drive_files,
                 &drive.path_on_host,
-                drive.read_only,
+                drive.read_only || drive.overlay.is_some() || drive.snapshot,
                 drive.direct,
+                drive.share_rw,
             )?;
+            if let Some(overlay) = drive.overlay.as_ref() {
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .open(overlay)
+                    .with_context(|| format!("Failed to create overlay file {}", overlay))?;
+                Self::add_drive_file(&mut drive_files, overlay, false, false, false)?;
+            }
         }
         if let Some(pflashs) = self.pflashs.as_ref() {
             for pflash in pflashs {
@@ -357,6 +471,7 @@ impl VmConfig {
                     &pflash.path_on_host,
                     pflash.read_only,
                     false,
+                    false,
                 )?;
             }
         }
@@ -541,6 +656,52 @@ impl CmdParser {
             None => Ok(None),
         }
     }
+
+    /// Get cmdline parameters value from param field name as a size in bytes.
+    /// The value can have a `K`, `M`, `G` (or `Ki`, `Mi`, `Gi`) suffix.
+    ///
+    /// # Arguments
+    ///
+    /// * `param_field`: The cmdline parameter field name.
+    pub fn get_size(&self, param_field: &str) -> Result<Option<u64>> {
+        match self.get_value::<String>(param_field)? {
+            Some(value) => Ok(Some(parse_size(param_field, &value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Parse a size string with an optional binary unit suffix into bytes.
+/// `K`/`Ki`, `M`/`Mi` and `G`/`Gi` are all power of 1024, e.g. `4K` is 4096.
+///
+/// # Arguments
+///
+/// * `field` - The name of the config field, used in error message.
+/// * `value` - The size string from user.
+pub fn parse_size(field: &str, value: &str) -> Result<u64> {
+    let illegal_value = || {
+        anyhow!(ConfigError::IllegalValue(
+            format!("{} \'{}\'", field, value),
+            0,
+            true,
+            u64::MAX,
+            true
+        ))
+    };
+    let unit_pos = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (size, unit) = value.split_at(unit_pos);
+    let size = size.parse::<u64>().map_err(|_| illegal_value())?;
+    let multiple = match unit {
+        "" => 1,
+        "K" | "k" | "Ki" | "ki" => K,
+        "M" | "m" | "Mi" | "mi" => M,
+        "G" | "g" | "Gi" | "gi" => G,
+        _ => return Err(illegal_value()),
+    };
+
+    size.checked_mul(multiple).ok_or_else(illegal_value)
 }
 
 /// This struct is a wrapper for `bool`.
@@ -711,6 +872,76 @@ mod tests {
         assert!(cmd_parser.parse("random=false").is_err());
     }
 
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("size", "4096").unwrap(), 4096);
+        assert_eq!(parse_size("size", "4K").unwrap(), 4 * 1024);
+        assert_eq!(parse_size("size", "4ki").unwrap(), 4 * 1024);
+        assert_eq!(parse_size("size", "2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("size", "2Mi").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("size", "1g").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(parse_size("size", "1Gi").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_size("size", "").is_err());
+        assert!(parse_size("size", "G").is_err());
+        assert!(parse_size("size", "1T").is_err());
+        assert!(parse_size("size", "1KB").is_err());
+        assert!(parse_size("size", "1.5G").is_err());
+        assert!(parse_size("size", "-1K").is_err());
+        assert!(parse_size("size", "17179869184G").is_err());
+
+        let mut cmd_parser = CmdParser::new("test");
+        cmd_parser.push("size").push("bad");
+        cmd_parser.parse("size=8M,bad=8X").unwrap();
+        assert_eq!(cmd_parser.get_size("size").unwrap(), Some(8 * 1024 * 1024));
+        assert!(cmd_parser.get_size("bad").is_err());
+        assert_eq!(cmd_parser.get_size("none").unwrap(), None);
+    }
+
+    #[test]
+    fn test_add_drive_file_share() {
+        use std::os::unix::fs::symlink;
+        use vmm_sys_util::tempfile::TempFile;
+
+        let image = TempFile::new().unwrap();
+        let path = image.as_path().to_str().unwrap().to_string();
+        let symlink_path = format!("{}.symlink", path);
+        let hardlink_path = format!("{}.hardlink", path);
+        symlink(&path, &symlink_path).unwrap();
+        std::fs::hard_link(&path, &hardlink_path).unwrap();
+
+        // Writable file can't be shared, whatever path references it.
+        let mut drive_files = HashMap::new();
+        VmConfig::add_drive_file(&mut drive_files, &path, false, false, false).unwrap();
+        assert!(VmConfig::add_drive_file(&mut drive_files, &path, false, false, false).is_err());
+        assert!(
+            VmConfig::add_drive_file(&mut drive_files, &symlink_path, false, false, false).is_err()
+        );
+        assert!(
+            VmConfig::add_drive_file(&mut drive_files, &hardlink_path, true, false, false).is_err()
+        );
+
+        // Read-only file can be shared.
+        let mut drive_files = HashMap::new();
+        VmConfig::add_drive_file(&mut drive_files, &path, true, false, false).unwrap();
+        VmConfig::add_drive_file(&mut drive_files, &hardlink_path, true, false, false).unwrap();
+        assert!(
+            VmConfig::add_drive_file(&mut drive_files, &symlink_path, false, false, true).is_err()
+        );
+
+        // Writable file can be shared if all drives set share-rw.
+        let mut drive_files = HashMap::new();
+        VmConfig::add_drive_file(&mut drive_files, &path, false, false, true).unwrap();
+        VmConfig::add_drive_file(&mut drive_files, &symlink_path, false, false, true).unwrap();
+        assert!(
+            VmConfig::add_drive_file(&mut drive_files, &hardlink_path, false, false, false)
+                .is_err()
+        );
+        assert_eq!(drive_files.len(), 2);
+
+        std::fs::remove_file(symlink_path).unwrap();
+        std::fs::remove_file(hardlink_path).unwrap();
+    }
+
     #[test]
     fn test_add_trace_events_01() {
         assert!(add_trace_events("event=test_trace_events").is_err());
@@ -765,5 +996,13 @@ mod tests {
         assert!(res.is_ok());
         let res = vm_config.add_global_config("pcie-root-port.fast-unplug=1");
         assert!(res.is_err());
+
+        let mut vm_config = VmConfig::default();
+        vm_config.add_global_config("virtio.packed=1").unwrap();
+        let packed = vm_config.global_config.get("virtio.packed");
+        assert_eq!(packed.unwrap(), PACKED_RING_ON);
+        assert!(vm_config.add_global_config("virtio.packed=0").is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_global_config("virtio.packed=on").is_err());
     }
 }
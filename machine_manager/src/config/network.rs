@@ -24,11 +24,15 @@ use crate::config::{
 use crate::qmp::{qmp_schema, QmpChannel};
 
 const MAC_ADDRESS_LENGTH: usize = 17;
+/// The locally administered unicast prefix of generated mac addresses.
+const GENERATED_MAC_PREFIX: [u8; 2] = [0x52, 0x54];
 
 /// Max virtqueue size of each virtqueue.
 pub const MAX_QUEUE_SIZE_NET: u16 = 4096;
 /// Max num of virtqueues.
 const MAX_QUEUE_PAIRS: usize = MAX_VIRTIO_QUEUE / 2;
+/// Max time(in microseconds) to delay the rx interrupt for coalescing.
+pub const MAX_RX_COALESCE_USECS: u32 = 100_000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetDevcfg {
@@ -91,6 +95,38 @@ impl ConfigCheck for NetDevcfg {
     }
 }
 
+/// The offload features of net device which can be advertised to the guest driver.
+/// All of them are enabled by default.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetOffloadConfig {
+    /// Device handles packets with partial checksum, required by host tso/ufo.
+    pub csum: bool,
+    /// Driver handles packets with partial checksum, required by guest tso/ufo.
+    pub guest_csum: bool,
+    pub guest_tso4: bool,
+    pub guest_tso6: bool,
+    pub guest_ufo: bool,
+    pub host_tso4: bool,
+    pub host_tso6: bool,
+    pub host_ufo: bool,
+}
+
+impl Default for NetOffloadConfig {
+    fn default() -> Self {
+        NetOffloadConfig {
+            csum: true,
+            guest_csum: true,
+            guest_tso4: true,
+            guest_tso6: true,
+            guest_ufo: true,
+            host_tso4: true,
+            host_tso6: true,
+            host_ufo: true,
+        }
+    }
+}
+
 /// Config struct for network
 /// Contains network device config, such as `host_dev_name`, `mac`...
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,6 +144,13 @@ pub struct NetworkInterfaceConfig {
     pub socket_path: Option<String>,
     /// All queues of a net device have the same queue size now.
     pub queue_size: u16,
+    /// Max number of used rx buffers before raising an interrupt, 0 means no limit.
+    pub rx_max_packets: u32,
+    /// Max time(in microseconds) to delay the rx interrupt, 0 disables coalescing.
+    pub rx_usecs: u32,
+    pub offload: NetOffloadConfig,
+    /// The boot order of the net device used by firmware.
+    pub boot_index: Option<u8>,
 }
 
 impl Default for NetworkInterfaceConfig {
@@ -124,6 +167,10 @@ impl Default for NetworkInterfaceConfig {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            rx_max_packets: 0,
+            rx_usecs: 0,
+            offload: NetOffloadConfig::default(),
+            boot_index: None,
         }
     }
 }
@@ -177,6 +224,34 @@ impl ConfigCheck for NetworkInterfaceConfig {
             bail!("queue size of net device should be power of 2!");
         }
 
+        if self.rx_usecs > MAX_RX_COALESCE_USECS {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "rx-usecs of net device".to_string(),
+                0,
+                true,
+                MAX_RX_COALESCE_USECS as u64,
+                true
+            )));
+        }
+
+        if self.rx_max_packets > self.queue_size as u32 {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "rx-max-packets of net device".to_string(),
+                0,
+                true,
+                self.queue_size as u64,
+                true
+            )));
+        }
+
+        if self.rx_max_packets != 0 && self.rx_usecs == 0 {
+            bail!("rx-max-packets of net device requires a non-zero rx-usecs");
+        }
+
+        if (self.rx_usecs != 0) && self.vhost_type.is_some() {
+            bail!("rx coalescing is not supported by vhost net device");
+        }
+
         Ok(())
     }
 }
@@ -296,7 +371,19 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         .push("multifunction")
         .push("mac")
         .push("iothread")
-        .push("queue-size");
+        .push("queue-size")
+        .push("rx-max-packets")
+        .push("rx-usecs")
+        .push("csum")
+        .push("guest_csum")
+        .push("guest_tso4")
+        .push("guest_tso6")
+        .push("guest_ufo")
+        .push("host_tso4")
+        .push("host_tso6")
+        .push("host_ufo")
+        .push("vhost")
+        .push("bootindex");
 
     cmd_parser.parse(net_config)?;
     pci_args_check(&cmd_parser)?;
@@ -317,10 +404,33 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         netdevinterfacecfg.mq = mq.inner;
     }
     netdevinterfacecfg.iothread = cmd_parser.get_value::<String>("iothread")?;
-    netdevinterfacecfg.mac = cmd_parser.get_value::<String>("mac")?;
+    let mac = cmd_parser.get_value::<String>("mac")?;
+    netdevinterfacecfg.mac = Some(vm_config.get_net_mac(Some(net_config), mac.as_ref())?);
     if let Some(queue_size) = cmd_parser.get_value::<u16>("queue-size")? {
         netdevinterfacecfg.queue_size = queue_size;
     }
+    if let Some(rx_max_packets) = cmd_parser.get_value::<u32>("rx-max-packets")? {
+        netdevinterfacecfg.rx_max_packets = rx_max_packets;
+    }
+    if let Some(rx_usecs) = cmd_parser.get_value::<u32>("rx-usecs")? {
+        netdevinterfacecfg.rx_usecs = rx_usecs;
+    }
+    netdevinterfacecfg.boot_index = cmd_parser.get_value::<u8>("bootindex")?;
+    let offload = &mut netdevinterfacecfg.offload;
+    for (name, value) in [
+        ("csum", &mut offload.csum),
+        ("guest_csum", &mut offload.guest_csum),
+        ("guest_tso4", &mut offload.guest_tso4),
+        ("guest_tso6", &mut offload.guest_tso6),
+        ("guest_ufo", &mut offload.guest_ufo),
+        ("host_tso4", &mut offload.host_tso4),
+        ("host_tso6", &mut offload.host_tso6),
+        ("host_ufo", &mut offload.host_ufo),
+    ] {
+        if let Some(enable) = cmd_parser.get_value::<ExBool>(name)? {
+            *value = enable.inner;
+        }
+    }
 
     if let Some(netcfg) = &vm_config.netdevs.remove(&netdev) {
         netdevinterfacecfg.id = netid;
@@ -328,6 +438,20 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         netdevinterfacecfg.tap_fds = netcfg.tap_fds.clone();
         netdevinterfacecfg.vhost_fds = netcfg.vhost_fds.clone();
         netdevinterfacecfg.vhost_type = netcfg.vhost_type.clone();
+        // The vhost of net device overrides the one of its tap netdev.
+        if let Some(vhost) = cmd_parser.get_value::<ExBool>("vhost")? {
+            if netcfg.vhost_type == Some(String::from("vhost-user")) {
+                bail!(
+                    "vhost of net device {} is not supported with vhost-user netdev",
+                    netdevinterfacecfg.id
+                );
+            }
+            netdevinterfacecfg.vhost_type = if vhost.into() {
+                Some(String::from("vhost-kernel"))
+            } else {
+                None
+            };
+        }
         netdevinterfacecfg.queues = netcfg.queues;
         if let Some(chardev) = &netcfg.chardev {
             netdevinterfacecfg.socket_path = Some(get_chardev_socket_path(chardev, vm_config)?);
@@ -491,6 +615,59 @@ impl VmConfig {
         }
         Ok(())
     }
+    /// Get the mac address of the net device configured by `net_config`.
+    ///
+    /// The explicit `mac` must not be used by any other net device. Without it,
+    /// a locally administered mac is generated from the vm name and the device
+    /// index, so it keeps stable across reboots of the same vm.
+    ///
+    /// # Arguments
+    ///
+    /// * `net_config` - The config args of the net device, `None` for the hot plugged one.
+    /// * `mac` - The mac address given by user.
+    pub fn get_net_mac(&self, net_config: Option<&str>, mac: Option<&String>) -> Result<String> {
+        let mut index = None;
+        let mut used_macs = Vec::new();
+        let net_devices = self
+            .devices
+            .iter()
+            .filter(|(driver, _)| driver == "virtio-net-device" || driver == "virtio-net-pci");
+        for (i, (_, dev_info)) in net_devices.enumerate() {
+            if index.is_none() && Some(dev_info.as_str()) == net_config {
+                index = Some(i);
+                continue;
+            }
+            let dev_mac = dev_info
+                .split(',')
+                .find_map(|param| param.strip_prefix("mac="))
+                .and_then(parse_mac_address);
+            used_macs.push(dev_mac.unwrap_or_else(|| gen_mac_address(&self.guest_name, i)));
+        }
+
+        if let Some(mac) = mac {
+            let bytes =
+                parse_mac_address(mac).ok_or_else(|| anyhow!(ConfigError::MacFormatError))?;
+            if used_macs.contains(&bytes) {
+                bail!("Mac address {} is used by more than one net device", mac);
+            }
+            return Ok(mac.clone());
+        }
+
+        // Skip the generated mac which is already used, it is rare.
+        let mut index = index.unwrap_or(used_macs.len());
+        loop {
+            let bytes = gen_mac_address(&self.guest_name, index);
+            if !used_macs.contains(&bytes) {
+                return Ok(bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<String>>()
+                    .join(":"));
+            }
+            index += 1;
+        }
+    }
+
     /// Add 'net devices' to `VmConfig devices`.
     pub fn add_net_device_config(&mut self, args: &qmp_schema::DeviceAddArgument) {
         let mut device_info = args.driver.clone();
@@ -529,6 +706,10 @@ impl VmConfig {
             device_info = format!("{},mq={}", device_info, mq);
         }
 
+        if let Some(boot_index) = &args.boot_index {
+            device_info = format!("{},bootindex={}", device_info, boot_index);
+        }
+
         self.devices.push((args.driver.clone(), device_info));
     }
 }
@@ -559,9 +740,41 @@ fn check_mac_address(mac: &str) -> bool {
         }
     }
 
+    // Multicast and all-zero mac addresses can't be used by a net device.
+    let first_byte = u8::from_str_radix(&mac[..2], 16).unwrap();
+    if first_byte & 0x1 != 0 || mac.split(':').all(|b| b == "00") {
+        return false;
+    }
+
     true
 }
 
+/// Parse the well-formed unicast mac address.
+fn parse_mac_address(mac: &str) -> Option<[u8; 6]> {
+    if !check_mac_address(mac) {
+        return None;
+    }
+    let mut bytes = [0_u8; 6];
+    for (i, s) in mac.split(':').enumerate() {
+        bytes[i] = u8::from_str_radix(s, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Generate a locally administered unicast mac address from vm name and
+/// device index. FNV-1a is used to keep it the same across builds.
+fn gen_mac_address(vm_name: &str, index: usize) -> [u8; 6] {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in vm_name.bytes().chain((index as u64).to_le_bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    let mut bytes = [0_u8; 6];
+    bytes[..2].copy_from_slice(&GENERATED_MAC_PREFIX);
+    bytes[2..].copy_from_slice(&hash.to_le_bytes()[..4]);
+    bytes
+}
+
 fn is_netdev_queues_valid(queues: u16) -> bool {
     queues >= 1 && queues <= MAX_VIRTIO_QUEUE as u16
 }
@@ -585,7 +798,10 @@ mod tests {
         assert_eq!(network_configs.id, "net0");
         assert_eq!(network_configs.host_dev_name, "tap0");
         assert_eq!(network_configs.iothread, Some("iothread0".to_string()));
-        assert!(network_configs.mac.is_none());
+        assert_eq!(
+            network_configs.mac,
+            Some(vm_config.get_net_mac(None, None).unwrap())
+        );
         assert!(network_configs.tap_fds.is_none());
         assert!(network_configs.vhost_type.is_none());
         assert!(network_configs.vhost_fds.is_none());
@@ -735,6 +951,155 @@ mod tests {
         assert!(net_cfg_res.is_err());
     }
 
+    #[test]
+    fn test_network_rx_coalesce_config() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,ifname=tap0").is_ok());
+        let net_cfg_res = parse_net(
+            &mut vm_config,
+            "virtio-net-device,id=net0,netdev=eth0,rx-max-packets=32,rx-usecs=50",
+        );
+        assert!(net_cfg_res.is_ok());
+        let network_configs = net_cfg_res.unwrap();
+        assert_eq!(network_configs.rx_max_packets, 32);
+        assert_eq!(network_configs.rx_usecs, 50);
+        assert!(network_configs.boot_index.is_none());
+
+        let mut net_cfg = NetworkInterfaceConfig::default();
+        net_cfg.rx_usecs = MAX_RX_COALESCE_USECS;
+        assert!(net_cfg.check().is_ok());
+        net_cfg.rx_usecs = MAX_RX_COALESCE_USECS + 1;
+        assert!(net_cfg.check().is_err());
+
+        net_cfg.rx_usecs = 50;
+        net_cfg.rx_max_packets = net_cfg.queue_size as u32;
+        assert!(net_cfg.check().is_ok());
+        net_cfg.rx_max_packets = net_cfg.queue_size as u32 + 1;
+        assert!(net_cfg.check().is_err());
+
+        // rx-max-packets takes effect only with a non-zero rx-usecs.
+        net_cfg.rx_max_packets = 32;
+        net_cfg.rx_usecs = 0;
+        assert!(net_cfg.check().is_err());
+
+        net_cfg.rx_usecs = 50;
+        net_cfg.vhost_type = Some("vhost-kernel".to_string());
+        assert!(net_cfg.check().is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,ifname=tap0").is_ok());
+        assert!(parse_net(
+            &mut vm_config,
+            "virtio-net-device,id=net0,netdev=eth0,rx-usecs=-1"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_network_offload_config() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,ifname=tap0").is_ok());
+        let net_cfg_res = parse_net(&mut vm_config, "virtio-net-device,id=net0,netdev=eth0");
+        assert!(net_cfg_res.is_ok());
+        let offload = net_cfg_res.unwrap().offload;
+        assert!(offload.csum && offload.guest_csum);
+        assert!(offload.guest_tso4 && offload.guest_tso6 && offload.guest_ufo);
+        assert!(offload.host_tso4 && offload.host_tso6 && offload.host_ufo);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,ifname=tap0").is_ok());
+        let net_cfg_res = parse_net(
+            &mut vm_config,
+            "virtio-net-device,id=net0,netdev=eth0,guest_tso4=off,guest_tso6=off,host_ufo=false",
+        );
+        assert!(net_cfg_res.is_ok());
+        let offload = net_cfg_res.unwrap().offload;
+        assert!(!offload.guest_tso4 && !offload.guest_tso6 && !offload.host_ufo);
+        assert!(offload.csum && offload.guest_ufo && offload.host_tso4);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,ifname=tap0").is_ok());
+        assert!(parse_net(
+            &mut vm_config,
+            "virtio-net-device,id=net0,netdev=eth0,csum=of"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_network_mac_address() {
+        // Multicast and all-zero mac addresses are illegal.
+        assert!(check_mac_address("52:54:00:12:34:56"));
+        assert!(!check_mac_address("01:00:5E:00:00:01"));
+        assert!(!check_mac_address("00:00:00:00:00:00"));
+        assert!(!check_mac_address("52:54:00:12:34"));
+
+        let mut vm_config = VmConfig::default();
+        vm_config.guest_name = "vm1".to_string();
+        let net0 = "virtio-net-pci,id=net0,netdev=eth0,bus=pcie.0,addr=0x2";
+        let net1 = "virtio-net-pci,id=net1,netdev=eth1,bus=pcie.0,addr=0x3";
+        assert!(vm_config.add_device(net0).is_ok());
+        assert!(vm_config.add_device(net1).is_ok());
+
+        // The generated mac is locally administered unicast, and stable for the same vm.
+        let mac0 = vm_config.get_net_mac(Some(net0), None).unwrap();
+        let mac1 = vm_config.get_net_mac(Some(net1), None).unwrap();
+        let bytes = parse_mac_address(&mac0).unwrap();
+        assert_eq!(bytes[0] & 0x3, 0x2);
+        assert_ne!(mac0, mac1);
+        assert_eq!(
+            mac0,
+            vm_config.clone().get_net_mac(Some(net0), None).unwrap()
+        );
+        let mut other_vm = vm_config.clone();
+        other_vm.guest_name = "vm2".to_string();
+        assert_ne!(mac0, other_vm.get_net_mac(Some(net0), None).unwrap());
+
+        // Explicit mac used by another net device is rejected.
+        let net2 = "virtio-net-pci,id=net2,netdev=eth2,bus=pcie.0,addr=0x4,mac=12:34:56:78:9A:BC";
+        assert!(vm_config.add_device(net2).is_ok());
+        let mac = "12:34:56:78:9a:bc".to_string();
+        assert!(vm_config.get_net_mac(Some(net2), Some(&mac)).is_ok());
+        assert!(vm_config.get_net_mac(Some(net0), Some(&mac)).is_err());
+        assert!(vm_config.get_net_mac(Some(net0), Some(&mac1)).is_err());
+        let mac = "13:34:56:78:9A:BC".to_string();
+        assert!(vm_config.get_net_mac(Some(net0), Some(&mac)).is_err());
+    }
+
+    #[test]
+    fn test_network_vhost_toggle() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,ifname=tap0").is_ok());
+        let net_cfg_res = parse_net(
+            &mut vm_config,
+            "virtio-net-device,id=net0,netdev=eth0,vhost=on",
+        );
+        assert!(net_cfg_res.is_ok());
+        assert_eq!(
+            net_cfg_res.unwrap().vhost_type,
+            Some("vhost-kernel".to_string())
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,vhost=on,vhostfd=4")
+            .is_ok());
+        let net_cfg_res = parse_net(
+            &mut vm_config,
+            "virtio-net-device,id=net0,netdev=eth0,vhost=off",
+        );
+        assert!(net_cfg_res.is_ok());
+        assert!(net_cfg_res.unwrap().vhost_type.is_none());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("vhost-user,id=eth0").is_ok());
+        let net_cfg_res = parse_net(
+            &mut vm_config,
+            "virtio-net-pci,id=net0,netdev=eth0,bus=pcie.0,addr=0x2,vhost=off",
+        );
+        assert!(net_cfg_res.is_err());
+    }
+
     #[test]
     fn test_netdev_config_check() {
         let mut netdev_conf = NetDevcfg::default();
@@ -10,14 +10,28 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::BTreeMap;
+
 use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
-use super::{CmdParser, ConfigCheck, MAX_STRING_LENGTH};
+use super::{parse_device_id, CmdParser, ConfigCheck, VmConfig, MAX_STRING_LENGTH};
 use crate::config::ExBool;
 use util::num_ops::str_to_usize;
 
+/// Name of the PCIe root bus.
+const PCIE_ROOT_BUS: &str = "pcie.0";
+/// Max slot number of a PCI bus.
+const MAX_PCI_SLOT: u8 = 31;
+/// Max function number of a PCI slot.
+const MAX_PCI_FUNC: u8 = 7;
+/// Slots of the root bus occupied by the bridges built by StratoVirt.
+#[cfg(target_arch = "x86_64")]
+const ROOT_BUS_RESERVED_SLOTS: [u8; 2] = [0, 0x1f];
+#[cfg(not(target_arch = "x86_64"))]
+const ROOT_BUS_RESERVED_SLOTS: [u8; 1] = [0];
+
 /// Basic information of pci devices such as bus number,
 /// slot number and function number.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -176,6 +190,112 @@ pub fn parse_root_port(rootport_cfg: &str) -> Result<RootPortConfig> {
     Ok(root_port)
 }
 
+fn is_pci_device(dev_type: &str) -> bool {
+    dev_type.ends_with("-pci")
+        || matches!(
+            dev_type,
+            "pcie-root-port" | "nec-usb-xhci" | "pcie-demo-dev"
+        )
+}
+
+impl VmConfig {
+    /// Check the addresses of pci devices, and assign a free slot to the ones given
+    /// without `addr`. Devices on the same slot and function, reserved slots, and
+    /// function 1~7 of a slot without a multifunction device on function 0 are rejected.
+    pub fn assign_pci_addr(&mut self) -> Result<()> {
+        // (bus, slot, function) -> (device id, multifunction).
+        let mut used: BTreeMap<(String, u8, u8), (String, bool)> = BTreeMap::new();
+        let mut unassigned = Vec::new();
+        for (index, (dev_type, cfg_args)) in self.devices.iter().enumerate() {
+            if !is_pci_device(dev_type) {
+                continue;
+            }
+            let mut cmd_parser = CmdParser::new("bdf");
+            cmd_parser.push("").push("bus").push("addr");
+            cmd_parser.get_parameters(cfg_args)?;
+            // Missing bus is reported when the device is parsed.
+            let bus = if let Some(bus) = cmd_parser.get_value::<String>("bus")? {
+                bus
+            } else {
+                continue;
+            };
+            let id = parse_device_id(cfg_args)?;
+            let (slot, func) = if let Some(addr) = cmd_parser.get_value::<String>("addr")? {
+                get_pci_df(&addr).with_context(|| format!("Invalid addr of device {}", id))?
+            } else {
+                unassigned.push((index, bus, id));
+                continue;
+            };
+
+            if bus == PCIE_ROOT_BUS && ROOT_BUS_RESERVED_SLOTS.contains(&slot) {
+                bail!(
+                    "PCI slot {}:{:02x}.{:x} requested by {} is reserved",
+                    bus,
+                    slot,
+                    func,
+                    id
+                );
+            }
+            let multifunction = get_multi_function(cfg_args)?;
+            if let Some((used_id, _)) = used.get(&(bus.clone(), slot, func)) {
+                bail!(
+                    "PCI slot {}:{:02x}.{:x} requested by {} already in use by {}",
+                    bus,
+                    slot,
+                    func,
+                    id,
+                    used_id
+                );
+            }
+            used.insert((bus, slot, func), (id, multifunction));
+        }
+
+        for ((bus, slot, func), (id, _)) in used.iter().filter(|(k, _)| k.2 != 0) {
+            match used.get(&(bus.clone(), *slot, 0)) {
+                Some((_, true)) => (),
+                Some((func0_id, false)) => bail!(
+                    "PCI slot {}:{:02x}.{:x} used by {} requires {} to be multifunction",
+                    bus,
+                    slot,
+                    func,
+                    id,
+                    func0_id
+                ),
+                None => bail!(
+                    "PCI slot {}:{:02x}.{:x} is used by {}, but function 0 is not populated",
+                    bus,
+                    slot,
+                    func,
+                    id
+                ),
+            }
+        }
+
+        for (index, bus, id) in unassigned {
+            // Devices behind the root port can only be attached to slot 0.
+            let max_slot = if bus == PCIE_ROOT_BUS {
+                MAX_PCI_SLOT
+            } else {
+                0
+            };
+            let slot = (0..=max_slot)
+                .filter(|s| bus != PCIE_ROOT_BUS || !ROOT_BUS_RESERVED_SLOTS.contains(s))
+                .find(|s| {
+                    used.range((bus.clone(), *s, 0)..=(bus.clone(), *s, MAX_PCI_FUNC))
+                        .next()
+                        .is_none()
+                })
+                .ok_or_else(|| anyhow!("No free PCI slot on bus {} for device {}", bus, id))?;
+
+            let cfg_args = &mut self.devices[index].1;
+            let multifunction = get_multi_function(cfg_args)?;
+            cfg_args.push_str(&format!(",addr={:#x}", slot));
+            used.insert((bus, slot, 0), (id, multifunction));
+        }
+        Ok(())
+    }
+}
+
 pub fn pci_args_check(cmd_parser: &CmdParser) -> Result<()> {
     let device_type = cmd_parser.get_value::<String>("")?;
     let dev_type = device_type.unwrap();
@@ -277,4 +397,65 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn test_assign_pci_addr() {
+        let mut vm_config = VmConfig::default();
+        for dev in [
+            "virtio-balloon-pci,id=balloon0,bus=pcie.0,addr=0x1",
+            "pcie-root-port,id=pcie.1,port=0x1,bus=pcie.0",
+            "virtio-rng-pci,id=rng0,rng=objrng0,bus=pcie.1",
+            "virtio-serial-pci,id=serial0,bus=pcie.0,addr=0x3.0x0,multifunction=on",
+            "virtio-blk-pci,id=blk0,drive=drive0,bus=pcie.0,addr=0x3.0x1",
+            "virtio-blk-device,id=blk1,drive=drive1",
+        ] {
+            vm_config.add_device(dev).unwrap();
+        }
+        assert!(vm_config.assign_pci_addr().is_ok());
+        assert_eq!(get_pci_bdf(&vm_config.devices[1].1).unwrap().addr, (2, 0));
+        assert_eq!(get_pci_bdf(&vm_config.devices[2].1).unwrap().addr, (0, 0));
+        assert_eq!(
+            vm_config.devices[5].1,
+            "virtio-blk-device,id=blk1,drive=drive1"
+        );
+
+        // Slot conflict.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("virtio-balloon-pci,id=balloon0,bus=pcie.0,addr=0x1")
+            .unwrap();
+        vm_config
+            .add_device("virtio-rng-pci,id=rng0,rng=objrng0,bus=pcie.0,addr=0x1.0x0")
+            .unwrap();
+        let err = vm_config.assign_pci_addr().unwrap_err();
+        assert!(err.to_string().contains("already in use by balloon0"));
+
+        // Reserved slot of the host bridge.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("virtio-balloon-pci,id=balloon0,bus=pcie.0,addr=0x0")
+            .unwrap();
+        assert!(vm_config.assign_pci_addr().is_err());
+
+        // Function 1 without multifunction function 0.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("virtio-balloon-pci,id=balloon0,bus=pcie.0,addr=0x2.0x1")
+            .unwrap();
+        assert!(vm_config.assign_pci_addr().is_err());
+        vm_config
+            .add_device("virtio-rng-pci,id=rng0,rng=objrng0,bus=pcie.0,addr=0x2")
+            .unwrap();
+        assert!(vm_config.assign_pci_addr().is_err());
+
+        // No free slot behind the root port.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("virtio-balloon-pci,id=balloon0,bus=pcie.1,addr=0x0")
+            .unwrap();
+        vm_config
+            .add_device("virtio-rng-pci,id=rng0,rng=objrng0,bus=pcie.1")
+            .unwrap();
+        assert!(vm_config.assign_pci_addr().is_err());
+    }
 }
@@ -0,0 +1,114 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{error::ConfigError, ConfigCheck, MAX_STRING_LENGTH};
+use crate::config::{CmdParser, VmConfig};
+
+/// Action taken by VM when guest panic is notified by pvpanic device.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum PvPanicAction {
+    /// Only report the panic to QMP client.
+    None,
+    /// Pause the VM, so that a crash dump can be taken.
+    Pause,
+}
+
+impl FromStr for PvPanicAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(PvPanicAction::None),
+            "pause" => Ok(PvPanicAction::Pause),
+            _ => Err(()),
+        }
+    }
+}
+
+impl PvPanicAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PvPanicAction::None => "none",
+            PvPanicAction::Pause => "pause",
+        }
+    }
+}
+
+/// Config of the pvpanic device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PvPanicConfig {
+    pub id: String,
+    pub action: PvPanicAction,
+}
+
+impl ConfigCheck for PvPanicConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "pvpanic id".to_string(),
+                MAX_STRING_LENGTH,
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_pvpanic(vm_config: &mut VmConfig, pvpanic_config: &str) -> Result<PvPanicConfig> {
+    if vm_config.dev_name.get("pvpanic").is_some() {
+        bail!("Only one pvpanic device is supported for each vm.");
+    }
+    let mut cmd_parser = CmdParser::new("pvpanic");
+    cmd_parser.push("").push("id").push("action");
+    cmd_parser.parse(pvpanic_config)?;
+
+    let pvpanic = PvPanicConfig {
+        id: cmd_parser
+            .get_value::<String>("id")?
+            .ok_or_else(|| anyhow!(ConfigError::FieldIsMissing("id", "pvpanic")))?,
+        action: cmd_parser
+            .get_value::<PvPanicAction>("action")?
+            .unwrap_or(PvPanicAction::None),
+    };
+    pvpanic.check()?;
+    vm_config.dev_name.insert("pvpanic".to_string(), 1);
+    Ok(pvpanic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pvpanic_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        let pvpanic_cfg = parse_pvpanic(&mut vm_config, "pvpanic,id=pvpanic0").unwrap();
+        assert_eq!(pvpanic_cfg.id, "pvpanic0");
+        assert_eq!(pvpanic_cfg.action, PvPanicAction::None);
+        // Only one pvpanic device is supported.
+        assert!(parse_pvpanic(&mut vm_config, "pvpanic,id=pvpanic1").is_err());
+
+        let mut vm_config = VmConfig::default();
+        let pvpanic_cfg =
+            parse_pvpanic(&mut vm_config, "pvpanic,id=pvpanic0,action=pause").unwrap();
+        assert_eq!(pvpanic_cfg.action, PvPanicAction::Pause);
+
+        let mut vm_config = VmConfig::default();
+        assert!(parse_pvpanic(&mut vm_config, "pvpanic,id=pvpanic0,action=reset").is_err());
+        assert!(parse_pvpanic(&mut vm_config, "pvpanic,action=pause").is_err());
+    }
+}
@@ -10,7 +10,9 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use anyhow::{anyhow, bail, Result};
+use std::fs::File;
+
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
@@ -50,6 +52,9 @@ impl ConfigCheck for RngConfig {
             )));
         }
 
+        File::open(&self.random_file)
+            .with_context(|| format!("Failed to open random file {}", self.random_file))?;
+
         if let Some(bytes_per_sec) = self.bytes_per_sec {
             if !(MIN_BYTES_PER_SEC..=MAX_BYTES_PER_SEC).contains(&bytes_per_sec) {
                 return Err(anyhow!(ConfigError::IllegalValue(
@@ -156,17 +161,17 @@ mod tests {
     fn test_rng_config_cmdline_parser_01() {
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_config = parse_rng_dev(&mut vm_config, "virtio-rng-device,rng=objrng0");
         assert!(rng_config.is_ok());
         let config = rng_config.unwrap();
-        assert_eq!(config.random_file, "/path/to/random_file");
+        assert_eq!(config.random_file, "/dev/urandom");
         assert_eq!(config.bytes_per_sec, None);
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_config = parse_rng_dev(
             &mut vm_config,
@@ -174,15 +179,23 @@ mod tests {
         );
         assert!(rng_config.is_ok());
         let config = rng_config.unwrap();
-        assert_eq!(config.random_file, "/path/to/random_file");
+        assert_eq!(config.random_file, "/dev/urandom");
         assert_eq!(config.bytes_per_sec, Some(1234));
+
+        // The random file must be readable.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .is_ok());
+        let rng_config = parse_rng_dev(&mut vm_config, "virtio-rng-device,rng=objrng0");
+        assert!(rng_config.is_err());
     }
 
     #[test]
     fn test_rng_config_cmdline_parser_02() {
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_config = parse_rng_dev(
             &mut vm_config,
@@ -192,7 +205,7 @@ mod tests {
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_config = parse_rng_dev(
             &mut vm_config,
@@ -200,12 +213,12 @@ mod tests {
         );
         assert!(rng_config.is_ok());
         let config = rng_config.unwrap();
-        assert_eq!(config.random_file, "/path/to/random_file");
+        assert_eq!(config.random_file, "/dev/urandom");
         assert_eq!(config.bytes_per_sec, Some(64));
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_config = parse_rng_dev(
             &mut vm_config,
@@ -213,12 +226,12 @@ mod tests {
         );
         assert!(rng_config.is_ok());
         let config = rng_config.unwrap();
-        assert_eq!(config.random_file, "/path/to/random_file");
+        assert_eq!(config.random_file, "/dev/urandom");
         assert_eq!(config.bytes_per_sec, Some(1000000000));
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_config = parse_rng_dev(
             &mut vm_config,
@@ -231,13 +244,13 @@ mod tests {
     fn test_pci_rng_config_cmdline_parser() {
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_cfg = "virtio-rng-pci,rng=objrng0,bus=pcie.0,addr=0x1.0x3";
         let rng_config = parse_rng_dev(&mut vm_config, rng_cfg);
         assert!(rng_config.is_ok());
         let config = rng_config.unwrap();
-        assert_eq!(config.random_file, "/path/to/random_file");
+        assert_eq!(config.random_file, "/dev/urandom");
         assert_eq!(config.bytes_per_sec, None);
         let pci_bdf = get_pci_bdf(rng_cfg);
         assert!(pci_bdf.is_ok());
@@ -251,7 +264,7 @@ mod tests {
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_cfg = "virtio-rng-device,rng=objrng0,bus=pcie.0,addr=0x1.0x3";
         let rng_config = parse_rng_dev(&mut vm_config, rng_cfg);
@@ -259,7 +272,7 @@ mod tests {
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
-            .add_object("rng-random,id=objrng0,filename=/path/to/random_file")
+            .add_object("rng-random,id=objrng0,filename=/dev/urandom")
             .is_ok());
         let rng_cfg = "virtio-rng-pci,rng=objrng0,bus=pcie.0,addr=0x1.0x3,multifunction=on";
         assert!(parse_rng_dev(&mut vm_config, rng_cfg).is_ok());
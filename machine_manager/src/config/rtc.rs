@@ -0,0 +1,218 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{CmdParser, VmConfig};
+use util::time::mktime64;
+
+/// Initial time of the guest RTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RtcBase {
+    /// Host time in UTC.
+    Utc,
+    /// Host time in local timezone.
+    LocalTime,
+    /// Given time, in seconds since 1970-01-01 00:00:00.
+    Time(u64),
+}
+
+impl Default for RtcBase {
+    fn default() -> Self {
+        RtcBase::Utc
+    }
+}
+
+impl FromStr for RtcBase {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "utc" => Ok(RtcBase::Utc),
+            "localtime" => Ok(RtcBase::LocalTime),
+            _ => parse_rtc_time(s).map(RtcBase::Time).ok_or(()),
+        }
+    }
+}
+
+impl RtcBase {
+    /// Get the time which the guest RTC starts from, in seconds since 1970-01-01 00:00:00.
+    pub fn start_time(&self) -> u64 {
+        // Since 1970-01-01 00:00:00, it never cause overflow.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time wrong")
+            .as_secs();
+        match self {
+            RtcBase::Utc => now,
+            RtcBase::LocalTime => (now as i64 + local_utc_offset(now)) as u64,
+            RtcBase::Time(time) => *time,
+        }
+    }
+}
+
+/// Policy to correct the drift of guest RTC after VM is resumed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RtcDriftFix {
+    /// Guest RTC follows host time, it jumps forward by the paused time on resume.
+    None,
+    /// Guest RTC is stopped while VM is paused, and runs faster after resume until
+    /// the paused time is caught up.
+    Slew,
+}
+
+impl Default for RtcDriftFix {
+    fn default() -> Self {
+        RtcDriftFix::None
+    }
+}
+
+impl FromStr for RtcDriftFix {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RtcDriftFix::None),
+            "slew" => Ok(RtcDriftFix::Slew),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Config of the guest RTC.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RtcConfig {
+    pub base: RtcBase,
+    pub driftfix: RtcDriftFix,
+}
+
+impl VmConfig {
+    /// Add '-rtc' config to `VmConfig`.
+    pub fn add_rtc(&mut self, rtc_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("rtc");
+        cmd_parser.push("base").push("driftfix");
+        cmd_parser.parse(rtc_config)?;
+
+        if let Some(base) = cmd_parser.get_value::<RtcBase>("base")? {
+            self.rtc.base = base;
+        }
+        if let Some(driftfix) = cmd_parser.get_value::<RtcDriftFix>("driftfix")? {
+            self.rtc.driftfix = driftfix;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the time of format `YYYY-MM-DDTHH:MM:SS` or `YYYY-MM-DD` to seconds since
+/// 1970-01-01 00:00:00. Return None if the format or the date is invalid.
+fn parse_rtc_time(time: &str) -> Option<u64> {
+    let (date, clock) = match time.split_once('T') {
+        Some((date, clock)) => (date, Some(clock)),
+        None => (time, None),
+    };
+    let date = parse_fields(date, '-', &[4, 2, 2])?;
+    let clock = match clock {
+        Some(clock) => parse_fields(clock, ':', &[2, 2, 2])?,
+        None => vec![0, 0, 0],
+    };
+
+    let (year, mon, day) = (date[0], date[1], date[2]);
+    let (hour, min, sec) = (clock[0], clock[1], clock[2]);
+    if year < 1970 || !(1..=12).contains(&mon) || day < 1 || day > days_of_month(year, mon) {
+        return None;
+    }
+    if hour > 23 || min > 59 || sec > 59 {
+        return None;
+    }
+    Some(mktime64(year, mon, day, hour, min, sec))
+}
+
+/// Parse the decimal fields separated by `sep`, each of which has the given number of digits.
+fn parse_fields(s: &str, sep: char, widths: &[usize]) -> Option<Vec<u64>> {
+    let fields: Vec<&str> = s.split(sep).collect();
+    if fields.len() != widths.len() {
+        return None;
+    }
+    fields
+        .iter()
+        .zip(widths.iter())
+        .map(|(field, width)| {
+            if field.len() != *width || !field.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            field.parse::<u64>().ok()
+        })
+        .collect()
+}
+
+fn days_of_month(year: u64, mon: u64) -> u64 {
+    match mon {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Get the offset of local timezone from UTC in seconds at the time `now`.
+fn local_utc_offset(now: u64) -> i64 {
+    // SAFETY: all zero is a valid value of `libc::tm`.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let time = now as libc::time_t;
+    // SAFETY: `libc::localtime_r` just converts calendar time to broken-down local
+    // time, and saves it to `tm`.
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_rtc() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.rtc.base, RtcBase::Utc);
+        assert_eq!(vm_config.rtc.driftfix, RtcDriftFix::None);
+
+        assert!(vm_config.add_rtc("base=localtime,driftfix=slew").is_ok());
+        assert_eq!(vm_config.rtc.base, RtcBase::LocalTime);
+        assert_eq!(vm_config.rtc.driftfix, RtcDriftFix::Slew);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_rtc("base=2006-06-17T16:01:21").is_ok());
+        assert_eq!(vm_config.rtc.base, RtcBase::Time(1150560081));
+        assert_eq!(vm_config.rtc.base.start_time(), 1150560081);
+        assert!(vm_config.add_rtc("base=2000-02-29").is_ok());
+        assert_eq!(vm_config.rtc.base, RtcBase::Time(951782400));
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_rtc("base=1969-12-31T23:59:59").is_err());
+        assert!(vm_config.add_rtc("base=2023-02-29").is_err());
+        assert!(vm_config.add_rtc("base=2023-13-01").is_err());
+        assert!(vm_config.add_rtc("base=2023-1-01").is_err());
+        assert!(vm_config.add_rtc("base=2023-01-01T24:00:00").is_err());
+        assert!(vm_config.add_rtc("base=2023-01-01T12:00").is_err());
+        assert!(vm_config.add_rtc("base=2023-01-01 12:00:00").is_err());
+        assert!(vm_config.add_rtc("base=+023-01-01").is_err());
+        assert!(vm_config.add_rtc("base=gmt").is_err());
+        assert!(vm_config.add_rtc("driftfix=catchup").is_err());
+        assert!(vm_config.add_rtc("clock=host").is_err());
+        assert_eq!(vm_config.rtc, RtcConfig::default());
+    }
+}
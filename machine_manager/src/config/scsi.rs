@@ -10,13 +10,17 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp::min;
+
 use anyhow::{anyhow, bail, Result};
 
 use super::{error::ConfigError, pci_args_check};
 use crate::config::{
-    CmdParser, ConfigCheck, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
+    CmdParser, ConfigCheck, DiskFormat, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_STRING_LENGTH,
+    MAX_VIRTIO_QUEUE,
 };
 use util::aio::AioEngine;
+use util::leak_bucket::IoLimits;
 
 /// According to Virtio Spec.
 /// Max_channel should be 0.
@@ -34,6 +38,13 @@ const MIN_QUEUE_SIZE_SCSI: u16 = 2;
 // Max size of each virtqueue for virtio-scsi.
 const MAX_QUEUE_SIZE_SCSI: u16 = 1024;
 
+/// Max length of the identification strings in standard INQUIRY data.
+const SCSI_VENDOR_MAX_LEN: usize = 8;
+const SCSI_PRODUCT_MAX_LEN: usize = 16;
+const SCSI_PRODUCT_REV_MAX_LEN: usize = 4;
+/// Number of hex digits of WWN, which is a 64 bits NAA identifier.
+const SCSI_WWN_HEX_LEN: usize = 16;
+
 #[derive(Debug, Clone)]
 pub struct ScsiCntlrConfig {
     /// Virtio-scsi-pci device id.
@@ -105,6 +116,17 @@ impl ConfigCheck for ScsiCntlrConfig {
     }
 }
 
+/// Get the number of scsi cmd queues used when `num-queues` is not specified.
+/// Each vcpu gets its own cmd queue with one more left over, but no more than
+/// `MAX_VIRTIO_QUEUE` in total.
+///
+/// # Arguments
+///
+/// * `nr_cpus` - Number of vcpus of the VM.
+pub fn scsi_cntlr_auto_queues_num(nr_cpus: u8) -> u16 {
+    min(nr_cpus as u16 + 1, MAX_VIRTIO_QUEUE as u16)
+}
+
 pub fn parse_scsi_controller(
     drive_config: &str,
     queues_auto: Option<u16>,
@@ -161,14 +183,34 @@ pub struct ScsiDevConfig {
     pub path_on_host: String,
     /// Serial number of the scsi device.
     pub serial: Option<String>,
+    /// Vendor identification reported in INQUIRY data.
+    pub vendor: Option<String>,
+    /// Product identification reported in INQUIRY data.
+    pub product: Option<String>,
+    /// Product revision level reported in INQUIRY data.
+    pub product_rev: Option<String>,
+    /// World wide name reported as NAA designator in Device Identification VPD page.
+    pub wwn: Option<u64>,
     /// Scsi bus which the scsi device attaches to.
     pub bus: String,
     /// Scsi device can not do write operation.
     pub read_only: bool,
     /// If true, use direct access io.
     pub direct: bool,
+    /// Device has a volatile write cache, otherwise each write is synced before completed.
+    pub write_cache: bool,
+    /// SYNCHRONIZE CACHE commands from guest are ignored.
+    pub no_flush: bool,
     /// Async IO type.
     pub aio_type: AioEngine,
+    /// Translate UNMAP command to deallocation of the image range.
+    pub discard: bool,
+    /// Translate WRITE SAME command with zeroed data to zeroing of the image range.
+    pub write_zeroes: bool,
+    /// Data written by guest goes to an anonymous overlay, which is discarded at VM exit.
+    pub snapshot: bool,
+    /// IO throttling limits, zero means no limit.
+    pub throttle: IoLimits,
     /// Boot order.
     pub boot_index: Option<u8>,
     /// Scsi four level hierarchical address(host, channel, target, lun).
@@ -183,10 +225,20 @@ impl Default for ScsiDevConfig {
             id: "".to_string(),
             path_on_host: "".to_string(),
             serial: None,
+            vendor: None,
+            product: None,
+            product_rev: None,
+            wwn: None,
             bus: "".to_string(),
             read_only: false,
             direct: true,
+            write_cache: true,
+            no_flush: false,
             aio_type: AioEngine::Native,
+            discard: false,
+            write_zeroes: false,
+            snapshot: false,
+            throttle: IoLimits::default(),
             boot_index: None,
             channel: 0,
             target: 0,
@@ -204,6 +256,10 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
         .push("scsi-id")
         .push("lun")
         .push("serial")
+        .push("vendor")
+        .push("product")
+        .push("product_rev")
+        .push("wwn")
         .push("bootindex")
         .push("drive");
 
@@ -225,6 +281,15 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
         scsi_dev_cfg.serial = Some(serial);
     }
 
+    scsi_dev_cfg.vendor = get_inquiry_string(&cmd_parser, "vendor", SCSI_VENDOR_MAX_LEN)?;
+    scsi_dev_cfg.product = get_inquiry_string(&cmd_parser, "product", SCSI_PRODUCT_MAX_LEN)?;
+    scsi_dev_cfg.product_rev =
+        get_inquiry_string(&cmd_parser, "product_rev", SCSI_PRODUCT_REV_MAX_LEN)?;
+
+    if let Some(wwn) = cmd_parser.get_value::<String>("wwn")? {
+        scsi_dev_cfg.wwn = Some(parse_wwn(&wwn)?);
+    }
+
     if let Some(id) = cmd_parser.get_value::<String>("id")? {
         scsi_dev_cfg.id = id;
     } else {
@@ -267,11 +332,173 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
     }
 
     if let Some(drive_arg) = &vm_config.drives.remove(&scsi_drive) {
+        if drive_arg.overlay.is_some() {
+            bail!("Overlay is not supported for scsi device");
+        }
+        if drive_arg.format == Some(DiskFormat::Qcow2) {
+            bail!("Qcow2 image is not supported for scsi device");
+        }
         scsi_dev_cfg.path_on_host = drive_arg.path_on_host.clone();
         scsi_dev_cfg.read_only = drive_arg.read_only;
         scsi_dev_cfg.direct = drive_arg.direct;
+        scsi_dev_cfg.write_cache = drive_arg.write_cache;
+        scsi_dev_cfg.no_flush = drive_arg.no_flush;
         scsi_dev_cfg.aio_type = drive_arg.aio;
+        scsi_dev_cfg.discard = drive_arg.discard;
+        scsi_dev_cfg.write_zeroes = drive_arg.write_zeroes;
+        scsi_dev_cfg.snapshot = drive_arg.snapshot;
+        scsi_dev_cfg.throttle = drive_arg.throttle;
     }
 
     Ok(scsi_dev_cfg)
 }
+
+/// Get the identification string of INQUIRY data, which must be printable ASCII
+/// and no longer than the field of INQUIRY data.
+fn get_inquiry_string(
+    cmd_parser: &CmdParser,
+    name: &str,
+    max_len: usize,
+) -> Result<Option<String>> {
+    let value = match cmd_parser.get_value::<String>(name)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    if value.len() > max_len {
+        return Err(anyhow!(ConfigError::StringLengthTooLong(
+            format!("scsi device {}", name),
+            max_len,
+        )));
+    }
+    if !value.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        return Err(anyhow!(ConfigError::InvalidParam(
+            name.to_string(),
+            "scsi device".to_string(),
+        )));
+    }
+    Ok(Some(value))
+}
+
+/// Parse WWN of 16 hex digits, with optional `0x` prefix.
+fn parse_wwn(wwn: &str) -> Result<u64> {
+    let digits = wwn
+        .strip_prefix("0x")
+        .or_else(|| wwn.strip_prefix("0X"))
+        .unwrap_or(wwn);
+    if digits.len() != SCSI_WWN_HEX_LEN || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!(
+            "WWN {} of scsi device must be {} hex digits",
+            wwn,
+            SCSI_WWN_HEX_LEN
+        );
+    }
+    u64::from_str_radix(digits, 16).map_err(|_| {
+        anyhow!(ConfigError::ConvertValueFailed(
+            wwn.to_string(),
+            "wwn".to_string()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scsi_cntlr_auto_queues() {
+        assert_eq!(scsi_cntlr_auto_queues_num(1), 2);
+        assert_eq!(scsi_cntlr_auto_queues_num(8), 9);
+        assert_eq!(scsi_cntlr_auto_queues_num(254), MAX_VIRTIO_QUEUE as u16);
+
+        let cfg = "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3";
+        let cntlr_cfg = parse_scsi_controller(cfg, Some(scsi_cntlr_auto_queues_num(8))).unwrap();
+        assert_eq!(cntlr_cfg.queues, 9);
+        let cntlr_cfg =
+            parse_scsi_controller(cfg, Some(scsi_cntlr_auto_queues_num(u8::MAX))).unwrap();
+        assert_eq!(cntlr_cfg.queues, MAX_VIRTIO_QUEUE as u32);
+
+        // Explicit num-queues takes precedence over the auto value.
+        let cfg = "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,num-queues=4";
+        let cntlr_cfg = parse_scsi_controller(cfg, Some(scsi_cntlr_auto_queues_num(8))).unwrap();
+        assert_eq!(cntlr_cfg.queues, 4);
+    }
+
+    #[test]
+    fn test_scsi_device_identification() {
+        let mut vm_config = VmConfig::default();
+        let cfg = "scsi-hd,bus=scsi0.0,scsi-id=0,lun=0,drive=drive0,id=scsi0-0-0-0";
+        let dev_cfg = parse_scsi_device(&mut vm_config, cfg).unwrap();
+        assert!(dev_cfg.vendor.is_none());
+        assert!(dev_cfg.product.is_none());
+        assert!(dev_cfg.product_rev.is_none());
+        assert!(dev_cfg.wwn.is_none());
+
+        let cfg = format!(
+            "{},vendor=ACME,product=SuperDisk 2000,product_rev=1.0,wwn=0x5000c500a1b2c3d4",
+            cfg
+        );
+        let dev_cfg = parse_scsi_device(&mut vm_config, &cfg).unwrap();
+        assert_eq!(dev_cfg.vendor.as_deref(), Some("ACME"));
+        assert_eq!(dev_cfg.product.as_deref(), Some("SuperDisk 2000"));
+        assert_eq!(dev_cfg.product_rev.as_deref(), Some("1.0"));
+        assert_eq!(dev_cfg.wwn, Some(0x5000c500a1b2c3d4));
+
+        assert_eq!(parse_wwn("5000C500A1B2C3D4").unwrap(), 0x5000c500a1b2c3d4);
+        // WWN must be exactly 16 hex digits.
+        assert!(parse_wwn("0x5000c500a1b2c3").is_err());
+        assert!(parse_wwn("0x5000c500a1b2c3d4e5").is_err());
+        assert!(parse_wwn("0x5000c500a1b2c3dg").is_err());
+        assert!(parse_wwn("0x+000c500a1b2c3d").is_err());
+
+        // Identification strings are limited by the fields of INQUIRY data.
+        let cfg = "scsi-hd,bus=scsi0.0,drive=drive0,id=scsi0-0-0-0";
+        assert!(parse_scsi_device(&mut vm_config, &format!("{},vendor=VENDOR123", cfg)).is_err());
+        let product = "p".repeat(SCSI_PRODUCT_MAX_LEN + 1);
+        let dev_args = format!("{},product={}", cfg, product);
+        assert!(parse_scsi_device(&mut vm_config, &dev_args).is_err());
+        assert!(parse_scsi_device(&mut vm_config, &format!("{},product_rev=1.0.1", cfg)).is_err());
+        // Only printable ASCII is allowed.
+        let dev_args = format!("{},vendor={}", cfg, "AC\u{7f}ME");
+        assert!(parse_scsi_device(&mut vm_config, &dev_args).is_err());
+    }
+
+    #[test]
+    fn test_scsi_device_snapshot() {
+        let mut vm_config = VmConfig::default();
+        let cfg = "scsi-hd,bus=scsi0.0,scsi-id=0,lun=0,drive=drive0,id=scsi0-0-0-0";
+        // Snapshot implies direct off.
+        vm_config
+            .add_drive("id=drive0,file=/path/to/rootfs,snapshot=on")
+            .unwrap();
+        let dev_cfg = parse_scsi_device(&mut vm_config, cfg).unwrap();
+        assert!(dev_cfg.snapshot);
+        assert!(!dev_cfg.direct);
+        assert_eq!(dev_cfg.aio_type, AioEngine::Off);
+
+        vm_config
+            .add_drive("id=drive0,file=/path/to/rootfs,overlay=/path/to/overlay,direct=off")
+            .unwrap();
+        assert!(parse_scsi_device(&mut vm_config, cfg).is_err());
+    }
+
+    #[test]
+    fn test_scsi_device_throttle() {
+        let mut vm_config = VmConfig::default();
+        let cfg = "scsi-hd,bus=scsi0.0,scsi-id=0,lun=0,drive=drive0,id=scsi0-0-0-0";
+        vm_config
+            .add_drive(
+                "id=drive0,file=/path/to/rootfs,throttling.iops-total=100,throttling.bps-write=1024",
+            )
+            .unwrap();
+        let dev_cfg = parse_scsi_device(&mut vm_config, cfg).unwrap();
+        assert_eq!(dev_cfg.throttle.iops_total, 100);
+        assert_eq!(dev_cfg.throttle.bps_write, 1024);
+        assert_eq!(dev_cfg.throttle.bps_total, 0);
+
+        vm_config
+            .add_drive("id=drive0,file=/path/to/rootfs")
+            .unwrap();
+        let dev_cfg = parse_scsi_device(&mut vm_config, cfg).unwrap();
+        assert_eq!(dev_cfg.throttle, IoLimits::default());
+    }
+}
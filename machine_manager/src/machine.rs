@@ -10,17 +10,24 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::{HashMap, VecDeque};
 use std::os::unix::io::RawFd;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+use log::error;
 use once_cell::sync::Lazy;
 use strum::VariantNames;
+use util::aio::OpCode;
+use vmm_sys_util::eventfd::EventFd;
 
-use crate::config::ShutdownAction;
+use crate::config::{ShutdownAction, TripleFaultAction};
 use crate::qmp::qmp_schema::{
-    BlockDevAddArgument, CharDevAddArgument, ChardevInfo, Cmd, CmdLine, DeviceAddArgument,
-    DeviceProps, Events, GicCap, IothreadInfo, KvmInfo, MachineInfo, MigrateCapabilities,
-    NetDevAddArgument, PropList, QmpCommand, QmpEvent, Target, TypeLists, UpdateRegionArgument,
+    BlockDevAddArgument, BlockDeviceStats, BlockResizeArgument, BlockSetIoThrottleArgument,
+    BlockStatsInfo, CharDevAddArgument, ChardevInfo, Cmd, CmdLine, DeviceAddArgument, DeviceProps,
+    Events, GicCap, IothreadInfo, KvmInfo, LogLevelInfo, MachineInfo, MigrateCapabilities,
+    MigrateSetParametersArgument, NetDevAddArgument, PropList, QmpCommand, QmpErrorClass, QmpEvent,
+    Target, TypeLists, UpdateRegionArgument,
 };
 use crate::qmp::{Response, Version};
 
@@ -114,6 +121,11 @@ pub trait MachineLifecycle {
     fn get_shutdown_action(&self) -> ShutdownAction {
         ShutdownAction::ShutdownActionPoweroff
     }
+
+    /// Get triple_fault_action to determine the operation on vcpu triple fault.
+    fn get_triple_fault_action(&self) -> TripleFaultAction {
+        TripleFaultAction::Poweroff
+    }
 }
 
 /// `AddressSpace` access interface of `Machine`.
@@ -154,6 +166,15 @@ pub trait DeviceInterface {
     /// Query each cpu's the topology info.
     fn query_cpus(&self) -> Response;
 
+    /// Execute one guest instruction on a vcpu while VM is paused.
+    fn vcpu_step(&self, cpu_index: u8) -> Response;
+
+    /// Save guest physical memory to a file.
+    fn memsave(&self, val: u64, size: u64, filename: String) -> Response;
+
+    /// Dump guest memory and vcpu registers to an ELF core file.
+    fn dump_guest_memory(&self, paging: bool, protocol: String) -> Response;
+
     /// Query each `hotpluggable_cpus`'s topology info and hotplug message.
     fn query_hotpluggable_cpus(&self) -> Response;
 
@@ -169,6 +190,12 @@ pub trait DeviceInterface {
     /// Delete a block device.
     fn blockdev_del(&self, node_name: String) -> Response;
 
+    /// Change the IO limits of a block device.
+    fn block_set_io_throttle(&self, args: BlockSetIoThrottleArgument) -> Response;
+
+    /// Grow the capacity of a block or scsi device.
+    fn block_resize(&mut self, args: BlockResizeArgument) -> Response;
+
     /// Create a new network device.
     fn netdev_add(&mut self, args: Box<NetDevAddArgument>) -> Response;
 
@@ -192,6 +219,15 @@ pub trait DeviceInterface {
     /// Set balloon's size.
     fn balloon(&self, size: u64) -> Response;
 
+    /// Query guest memory ranges pinned for DMA.
+    fn query_pinned_memory(&self) -> Response;
+
+    /// Query the region tree of guest address spaces.
+    fn query_mtree(&self) -> Response;
+
+    /// Query the PCI buses and devices.
+    fn query_pci(&self) -> Response;
+
     /// Query the version of StratoVirt.
     fn query_version(&self) -> Response {
         let version = Version::new(1, 0, 5);
@@ -406,8 +442,54 @@ pub trait DeviceInterface {
     }
 
     fn query_blockstats(&self) -> Response {
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
-        Response::create_response(serde_json::to_value(vec_cmd).unwrap(), None)
+        let vec_stats = get_block_stats();
+        Response::create_response(serde_json::to_value(&vec_stats).unwrap(), None)
+    }
+
+    /// Read and consume at most `size` bytes from the ring buffer of ringbuf-type chardev.
+    fn ringbuf_read(&self, device: String, size: u64, format: Option<String>) -> Response {
+        let ringbuf = match RINGBUF_CHARDEVS.lock().unwrap().get(&device) {
+            Some(ringbuf) => ringbuf.clone(),
+            None => {
+                return Response::create_error_response(
+                    QmpErrorClass::GenericError(format!(
+                        "Chardev {} is not found or is not ringbuf-type",
+                        device
+                    )),
+                    None,
+                );
+            }
+        };
+        let data = match format.as_deref() {
+            None | Some("utf8") => {
+                String::from_utf8_lossy(&ringbuf.lock().unwrap().read_data(size as usize))
+                    .to_string()
+            }
+            Some("base64") => base64_encode(&ringbuf.lock().unwrap().read_data(size as usize)),
+            Some(format) => {
+                return Response::create_error_response(
+                    QmpErrorClass::GenericError(format!(
+                        "Invalid data format {}, utf8 or base64 is supported",
+                        format
+                    )),
+                    None,
+                );
+            }
+        };
+        Response::create_response(serde_json::to_value(data).unwrap(), None)
+    }
+
+    /// Set the log level of the module at runtime, and return the previous level.
+    fn set_log_level(&self, module: Option<String>, level: String) -> Response {
+        match util::logger::set_log_level(module.as_deref(), &level) {
+            Ok(previous) => {
+                let info = LogLevelInfo { previous };
+                Response::create_response(serde_json::to_value(info).unwrap(), None)
+            }
+            Err(e) => {
+                Response::create_error_response(QmpErrorClass::GenericError(e.to_string()), None)
+            }
+        }
     }
 
     fn query_block_jobs(&self) -> Response {
@@ -436,6 +518,30 @@ pub trait DeviceInterface {
     fn input_event(&self, _k: String, _v: String) -> Response {
         Response::create_empty_response()
     }
+
+    /// Save the image of guest display to a file.
+    fn screendump(&self, _filename: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("Screendump is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Change the medium of a removable device.
+    fn change(&mut self, _device: String, _target: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("Changing medium is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Eject the medium of a removable device.
+    fn eject(&mut self, _device: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("Ejecting medium is not supported".to_string()),
+            None,
+        )
+    }
 }
 
 /// Migrate external api
@@ -457,6 +563,11 @@ pub trait MigrateInterface {
     fn cancel_migrate(&self) -> Response {
         Response::create_empty_response()
     }
+
+    /// Set parameters of migration, such as bandwidth and downtime limit.
+    fn migrate_set_parameters(&self, _args: MigrateSetParametersArgument) -> Response {
+        Response::create_empty_response()
+    }
 }
 
 /// Machine interface which is exposed to inner hypervisor.
@@ -470,3 +581,402 @@ pub trait MachineTestInterface: MachineAddressInterface {}
 
 pub static PTY_PATH: Lazy<Mutex<Vec<PathInfo>>> = Lazy::new(|| Mutex::new(Vec::new()));
 pub static IOTHREADS: Lazy<Mutex<Vec<IothreadInfo>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Callback notified with the new state after VM state changes.
+pub type VmStateNotifier = Arc<dyn Fn(KvmVmState) + Send + Sync>;
+
+/// Eventfd for devices to request pausing the VM, which is handled in main loop.
+static VM_PAUSE_REQ: Lazy<Mutex<Option<Arc<EventFd>>>> = Lazy::new(|| Mutex::new(None));
+/// VM state notifiers registered by devices, keyed by device id.
+static VM_STATE_NOTIFIERS: Lazy<Mutex<HashMap<String, VmStateNotifier>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Set the eventfd which is used by devices to request pausing the VM.
+pub fn set_vm_pause_req(pause_req: Arc<EventFd>) {
+    *VM_PAUSE_REQ.lock().unwrap() = Some(pause_req);
+}
+
+/// Request to pause the VM from device, e.g. when block device fails to write with
+/// `werror=stop`. Return false if the request can not be handled.
+pub fn request_vm_pause() -> bool {
+    match VM_PAUSE_REQ.lock().unwrap().as_ref() {
+        Some(pause_req) => {
+            if let Err(e) = pause_req.write(1) {
+                error!("Failed to request pausing VM: {:?}", e);
+                return false;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Register the notifier which is called when VM state changes.
+///
+/// # Arguments
+///
+/// * `id` - Id of the device which registers the notifier.
+/// * `notifier` - Callback for VM state change.
+pub fn register_vm_state_notifier(id: &str, notifier: VmStateNotifier) {
+    VM_STATE_NOTIFIERS
+        .lock()
+        .unwrap()
+        .insert(id.to_string(), notifier);
+}
+
+/// Unregister the VM state notifier of the device.
+pub fn unregister_vm_state_notifier(id: &str) {
+    VM_STATE_NOTIFIERS.lock().unwrap().remove(id);
+}
+
+/// Notify all the registered devices of the new VM state.
+pub fn notify_vm_state(state: KvmVmState) {
+    for notifier in VM_STATE_NOTIFIERS.lock().unwrap().values() {
+        notifier(state);
+    }
+}
+
+/// IO statistics of a block device, which are accounted when the requests complete.
+#[derive(Default)]
+pub struct BlockStats {
+    rd_bytes: AtomicU64,
+    wr_bytes: AtomicU64,
+    rd_operations: AtomicU64,
+    wr_operations: AtomicU64,
+    flush_operations: AtomicU64,
+    failed_rd_operations: AtomicU64,
+    failed_wr_operations: AtomicU64,
+    failed_flush_operations: AtomicU64,
+}
+
+impl BlockStats {
+    /// Account a completed request, only read, write and flush requests are accounted.
+    ///
+    /// # Arguments
+    ///
+    /// * `opcode` - Operation of the request.
+    /// * `nbytes` - Bytes transferred by the request.
+    /// * `succeed` - Whether the request succeeds.
+    pub fn account(&self, opcode: OpCode, nbytes: u64, succeed: bool) {
+        let (bytes, ops, failed_ops) = match opcode {
+            OpCode::Preadv => (
+                Some(&self.rd_bytes),
+                &self.rd_operations,
+                &self.failed_rd_operations,
+            ),
+            OpCode::Pwritev => (
+                Some(&self.wr_bytes),
+                &self.wr_operations,
+                &self.failed_wr_operations,
+            ),
+            OpCode::Fdsync => (None, &self.flush_operations, &self.failed_flush_operations),
+            _ => return,
+        };
+        if !succeed {
+            failed_ops.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        ops.fetch_add(1, Ordering::Relaxed);
+        if let Some(bytes) = bytes {
+            bytes.fetch_add(nbytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Reset all the statistics to zero.
+    pub fn reset(&self) {
+        for stat in [
+            &self.rd_bytes,
+            &self.wr_bytes,
+            &self.rd_operations,
+            &self.wr_operations,
+            &self.flush_operations,
+            &self.failed_rd_operations,
+            &self.failed_wr_operations,
+            &self.failed_flush_operations,
+        ] {
+            stat.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get_info(&self) -> BlockDeviceStats {
+        BlockDeviceStats {
+            rd_bytes: self.rd_bytes.load(Ordering::Relaxed),
+            wr_bytes: self.wr_bytes.load(Ordering::Relaxed),
+            rd_operations: self.rd_operations.load(Ordering::Relaxed),
+            wr_operations: self.wr_operations.load(Ordering::Relaxed),
+            flush_operations: self.flush_operations.load(Ordering::Relaxed),
+            failed_rd_operations: self.failed_rd_operations.load(Ordering::Relaxed),
+            failed_wr_operations: self.failed_wr_operations.load(Ordering::Relaxed),
+            failed_flush_operations: self.failed_flush_operations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// IO statistics of the realized block devices, keyed by device id.
+static BLOCK_STATS: Lazy<Mutex<HashMap<String, Arc<BlockStats>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Register the IO statistics of the block device, which can be queried by QMP.
+///
+/// # Arguments
+///
+/// * `id` - Id of the block device.
+/// * `stats` - IO statistics of the block device.
+pub fn register_block_stats(id: &str, stats: Arc<BlockStats>) {
+    BLOCK_STATS.lock().unwrap().insert(id.to_string(), stats);
+}
+
+/// Unregister the IO statistics of the block device.
+pub fn unregister_block_stats(id: &str) {
+    BLOCK_STATS.lock().unwrap().remove(id);
+}
+
+/// Get the IO statistics of all the registered block devices, sorted by device id.
+pub fn get_block_stats() -> Vec<BlockStatsInfo> {
+    let mut vec_stats: Vec<BlockStatsInfo> = BLOCK_STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, stats)| BlockStatsInfo {
+            device: id.clone(),
+            stats: stats.get_info(),
+        })
+        .collect();
+    vec_stats.sort_by(|a, b| a.device.cmp(&b.device));
+    vec_stats
+}
+
+/// Traffic statistics of a network device, which are accounted when the packets are
+/// delivered to guest or sent to backend.
+#[derive(Default)]
+pub struct NetStats {
+    rx_bytes: AtomicU64,
+    rx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_packets: AtomicU64,
+}
+
+/// Snapshot of the traffic statistics of a network device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NetDeviceStats {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+}
+
+impl NetStats {
+    /// Account a packet received by guest.
+    pub fn account_rx(&self, nbytes: u64) {
+        self.rx_bytes.fetch_add(nbytes, Ordering::Relaxed);
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a packet sent by guest.
+    pub fn account_tx(&self, nbytes: u64) {
+        self.tx_bytes.fetch_add(nbytes, Ordering::Relaxed);
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reset all the statistics to zero.
+    pub fn reset(&self) {
+        for stat in [
+            &self.rx_bytes,
+            &self.rx_packets,
+            &self.tx_bytes,
+            &self.tx_packets,
+        ] {
+            stat.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get_info(&self) -> NetDeviceStats {
+        NetDeviceStats {
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Traffic statistics of the realized network devices, keyed by device id.
+static NET_STATS: Lazy<Mutex<HashMap<String, Arc<NetStats>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Register the traffic statistics of the network device.
+///
+/// # Arguments
+///
+/// * `id` - Id of the network device.
+/// * `stats` - Traffic statistics of the network device.
+pub fn register_net_stats(id: &str, stats: Arc<NetStats>) {
+    NET_STATS.lock().unwrap().insert(id.to_string(), stats);
+}
+
+/// Unregister the traffic statistics of the network device.
+pub fn unregister_net_stats(id: &str) {
+    NET_STATS.lock().unwrap().remove(id);
+}
+
+/// Get the traffic statistics of all the registered network devices, sorted by device id.
+pub fn get_net_stats() -> Vec<(String, NetDeviceStats)> {
+    let mut vec_stats: Vec<(String, NetDeviceStats)> = NET_STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, stats)| (id.clone(), stats.get_info()))
+        .collect();
+    vec_stats.sort_by(|a, b| a.0.cmp(&b.0));
+    vec_stats
+}
+
+/// Ring buffer of ringbuf-type chardev, which keeps the latest output of guest.
+pub struct ChardevRingBuf {
+    /// Data in the buffer, the oldest in the front.
+    buf: VecDeque<u8>,
+    /// Max size of the buffer in bytes.
+    size: usize,
+}
+
+impl ChardevRingBuf {
+    pub fn new(size: usize) -> Self {
+        ChardevRingBuf {
+            buf: VecDeque::with_capacity(size),
+            size,
+        }
+    }
+
+    /// Append the data to the buffer, the oldest data is overwritten when it's full.
+    pub fn write_data(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.size)..];
+        let overflow = (self.buf.len() + data.len()).saturating_sub(self.size);
+        self.buf.drain(..overflow);
+        self.buf.extend(data);
+    }
+
+    /// Read and remove at most `size` bytes of the oldest data in the buffer.
+    pub fn read_data(&mut self, size: usize) -> Vec<u8> {
+        let len = std::cmp::min(size, self.buf.len());
+        self.buf.drain(..len).collect()
+    }
+}
+
+impl std::io::Write for ChardevRingBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_data(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Ring buffers of the realized ringbuf-type chardevs, keyed by chardev id.
+static RINGBUF_CHARDEVS: Lazy<Mutex<HashMap<String, Arc<Mutex<ChardevRingBuf>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Register the ring buffer of ringbuf-type chardev, which can be read by QMP.
+///
+/// # Arguments
+///
+/// * `id` - Id of the chardev.
+/// * `ringbuf` - Ring buffer of the chardev.
+pub fn register_ringbuf_chardev(id: &str, ringbuf: Arc<Mutex<ChardevRingBuf>>) {
+    RINGBUF_CHARDEVS
+        .lock()
+        .unwrap()
+        .insert(id.to_string(), ringbuf);
+}
+
+/// Encode the data in standard base64 with padding, see RFC 4648.
+fn base64_encode(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let mut bytes = [0_u8; 3];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let group = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(TABLE[((group >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chardev_ringbuf() {
+        let mut ringbuf = ChardevRingBuf::new(8);
+        ringbuf.write_data(b"hello");
+        assert_eq!(ringbuf.read_data(3), b"hel");
+        assert_eq!(ringbuf.read_data(16), b"lo");
+        assert!(ringbuf.read_data(16).is_empty());
+
+        // The oldest data is overwritten.
+        ringbuf.write_data(b"hello");
+        ringbuf.write_data(b"world");
+        assert_eq!(ringbuf.read_data(16), b"lloworld");
+        ringbuf.write_data(b"0123456789");
+        assert_eq!(ringbuf.read_data(16), b"23456789");
+
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_block_stats() {
+        let stats = BlockStats::default();
+        stats.account(OpCode::Preadv, 4096, true);
+        stats.account(OpCode::Preadv, 512, false);
+        stats.account(OpCode::Pwritev, 1024, true);
+        stats.account(OpCode::Pwritev, 1024, true);
+        stats.account(OpCode::Fdsync, 0, true);
+        stats.account(OpCode::Fdsync, 0, false);
+        stats.account(OpCode::Discard, 4096, true);
+
+        let info = stats.get_info();
+        assert_eq!(info.rd_bytes, 4096);
+        assert_eq!(info.rd_operations, 1);
+        assert_eq!(info.failed_rd_operations, 1);
+        assert_eq!(info.wr_bytes, 2048);
+        assert_eq!(info.wr_operations, 2);
+        assert_eq!(info.failed_wr_operations, 0);
+        assert_eq!(info.flush_operations, 1);
+        assert_eq!(info.failed_flush_operations, 1);
+
+        stats.reset();
+        let info = stats.get_info();
+        assert_eq!(info.rd_bytes, 0);
+        assert_eq!(info.wr_operations, 0);
+        assert_eq!(info.failed_flush_operations, 0);
+    }
+
+    #[test]
+    fn test_net_stats() {
+        let stats = NetStats::default();
+        stats.account_rx(1500);
+        stats.account_rx(60);
+        stats.account_tx(100);
+
+        let info = stats.get_info();
+        assert_eq!(info.rx_bytes, 1560);
+        assert_eq!(info.rx_packets, 2);
+        assert_eq!(info.tx_bytes, 100);
+        assert_eq!(info.tx_packets, 1);
+
+        stats.reset();
+        assert_eq!(stats.get_info(), NetDeviceStats::default());
+    }
+}
@@ -270,6 +270,11 @@ impl Response {
         }
     }
 
+    /// Get the description of error, if it's an error response.
+    pub fn error_desc(&self) -> Option<&str> {
+        self.error.as_ref().map(|err| err.desc.as_str())
+    }
+
     fn change_id(&mut self, id: Option<String>) {
         self.id = id;
     }
@@ -349,6 +354,7 @@ pub fn create_timestamp() -> TimeStamp {
 /// * `stream_fd` - The input stream file description.
 /// * `controller` - The controller which execute actual qmp command.
 /// * `leak_bucket` - The LeakBucket flow controller for qmp command.
+/// * `read_only` - Only `query-*` commands are executed, others are refused.
 ///
 /// # Errors
 ///
@@ -357,6 +363,7 @@ pub fn handle_qmp(
     stream_fd: RawFd,
     controller: &Arc<Mutex<dyn MachineExternalInterface>>,
     leak_bucket: &mut LeakBucket,
+    read_only: bool,
 ) -> Result<()> {
     let mut qmp_service = crate::socket::SocketHandler::new(stream_fd);
 
@@ -378,6 +385,22 @@ pub fn handle_qmp(
         (Ok(buffer), if_fd) => {
             info!("QMP: <-- {:?}", buffer);
             let qmp_command: schema::QmpCommand = buffer.unwrap();
+            if read_only {
+                let command = serde_json::to_value(&qmp_command)?;
+                let name = command["execute"].as_str().unwrap_or_default();
+                if !name.starts_with("query-") {
+                    let err_resp = schema::QmpErrorClass::GenericError(format!(
+                        "Command {} is not allowed on read-only QMP socket",
+                        name
+                    ));
+                    let id = command["id"].as_str().map(String::from);
+                    let return_msg =
+                        serde_json::to_string(&Response::create_error_response(err_resp, id))?;
+                    info!("QMP: --> {:?}", return_msg);
+                    qmp_service.send_str(&return_msg)?;
+                    return Ok(());
+                }
+            }
             let (return_msg, shutdown_flag) = qmp_command_exec(qmp_command, controller, if_fd);
             info!("QMP: --> {:?}", return_msg);
             qmp_service.send_str(&return_msg)?;
@@ -452,6 +475,9 @@ fn qmp_command_exec(
         (query_cpus, query_cpus),
         (query_balloon, query_balloon),
         (query_vnc, query_vnc),
+        (query_pinned_memory, query_pinned_memory),
+        (query_mtree, query_mtree),
+        (query_pci, query_pci),
         (list_type, list_type),
         (query_hotpluggable_cpus, query_hotpluggable_cpus);
         (input_event, input_event, key, value),
@@ -461,12 +487,23 @@ fn qmp_command_exec(
         (netdev_del, netdev_del, id),
         (chardev_remove, chardev_remove, id),
         (balloon, balloon, value),
+        (vcpu_step, vcpu_step, cpu_index),
+        (memsave, memsave, val, size, filename),
+        (ringbuf_read, ringbuf_read, device, size, format),
+        (set_log_level, set_log_level, module, level),
+        (screendump, screendump, filename),
+        (change, change, device, target),
+        (eject, eject, device),
+        (dump_guest_memory, dump_guest_memory, paging, protocol),
         (migrate, migrate, uri);
         (device_add, device_add),
         (blockdev_add, blockdev_add),
         (netdev_add, netdev_add),
         (chardev_add, chardev_add),
-        (update_region, update_region)
+        (block_set_io_throttle, block_set_io_throttle),
+        (block_resize, block_resize),
+        (update_region, update_region),
+        (migrate_set_parameters, migrate_set_parameters)
     );
 
     // Handle the Qmp command which macro can't cover
@@ -637,6 +674,7 @@ mod tests {
             singlestep: false,
             running: true,
             status: schema::RunState::running,
+            vcpus: Vec::new(),
         };
         let resp = Response::create_response(serde_json::to_value(&resp_value).unwrap(), None);
 
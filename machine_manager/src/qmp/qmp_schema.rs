@@ -147,6 +147,54 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "vcpu-step")]
+    #[strum(serialize = "vcpu-step")]
+    vcpu_step {
+        arguments: vcpu_step,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    memsave {
+        arguments: memsave,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "dump-guest-memory")]
+    #[strum(serialize = "dump-guest-memory")]
+    dump_guest_memory {
+        arguments: dump_guest_memory,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "ringbuf-read")]
+    #[strum(serialize = "ringbuf-read")]
+    ringbuf_read {
+        arguments: ringbuf_read,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-log-level")]
+    #[strum(serialize = "set-log-level")]
+    set_log_level {
+        arguments: set_log_level,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    screendump {
+        arguments: screendump,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    change {
+        arguments: change,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    eject {
+        arguments: eject,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     getfd {
         arguments: getfd,
         #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -186,6 +234,30 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "query-pinned-memory")]
+    #[strum(serialize = "query-pinned-memory")]
+    query_pinned_memory {
+        #[serde(default)]
+        arguments: query_pinned_memory,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-mtree")]
+    #[strum(serialize = "query-mtree")]
+    query_mtree {
+        #[serde(default)]
+        arguments: query_mtree,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-pci")]
+    #[strum(serialize = "query-pci")]
+    query_pci {
+        #[serde(default)]
+        arguments: query_pci,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "migrate")]
     migrate {
         arguments: migrate,
@@ -199,6 +271,12 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "migrate-set-parameters")]
+    migrate_set_parameters {
+        arguments: migrate_set_parameters,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "migrate_cancel")]
     cancel_migrate {
         #[serde(default)]
@@ -385,6 +463,18 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "block_set_io_throttle")]
+    block_set_io_throttle {
+        arguments: block_set_io_throttle,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "block_resize")]
+    block_resize {
+        arguments: block_resize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "update_region")]
     #[strum(serialize = "update_region")]
     update_region {
@@ -595,6 +685,10 @@ pub struct device_add {
     pub sysfsdev: Option<String>,
     #[serde(rename = "queue-size")]
     pub queue_size: Option<u16>,
+    #[serde(rename = "rx-max-packets")]
+    pub rx_max_packets: Option<u32>,
+    #[serde(rename = "rx-usecs")]
+    pub rx_usecs: Option<u32>,
 }
 
 pub type DeviceAddArgument = device_add;
@@ -607,6 +701,84 @@ impl Command for device_add {
     }
 }
 
+/// block_set_io_throttle
+///
+/// Change the IO limits of a block device, zero means no limit.
+///
+/// # Arguments
+///
+/// * `device` - the id of the block device.
+/// * `iops` - total IO operations per second.
+/// * `iops_rd` - read IO operations per second.
+/// * `iops_wr` - write IO operations per second.
+/// * `bps` - total bytes per second.
+/// * `bps_rd` - read bytes per second.
+/// * `bps_wr` - write bytes per second.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "block_set_io_throttle",
+///      "arguments": { "device": "drive-0", "iops": 0, "iops_rd": 1000, "iops_wr": 500,
+///                     "bps": 10485760, "bps_rd": 0, "bps_wr": 0 } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct block_set_io_throttle {
+    pub device: String,
+    pub iops: u64,
+    pub iops_rd: u64,
+    pub iops_wr: u64,
+    pub bps: u64,
+    pub bps_rd: u64,
+    pub bps_wr: u64,
+}
+
+pub type BlockSetIoThrottleArgument = block_set_io_throttle;
+
+impl Command for block_set_io_throttle {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// block_resize
+///
+/// Grow the capacity of a block or scsi device at runtime, the image file must
+/// have been resized on the host.
+///
+/// # Arguments
+///
+/// * `device` - the id of the block or scsi device.
+/// * `size` - the new size of the device in bytes.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "block_resize",
+///      "arguments": { "device": "drive-0", "size": 21474836480 } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct block_resize {
+    pub device: String,
+    pub size: u64,
+}
+
+pub type BlockResizeArgument = block_resize;
+
+impl Command for block_resize {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
 /// update_region
 ///
 /// # Arguments
@@ -688,6 +860,7 @@ pub struct CacheOptions {
 /// * `file` - the backend file information.
 /// * `cache` - if use direct io.
 /// * `read_only` - if readonly.
+/// * `werror` - action on write error: "report", "stop", "enospc" or "retry".
 ///
 /// Additional arguments depend on the type.
 ///
@@ -718,6 +891,7 @@ pub struct blockdev_add {
     pub options: Option<String>,
     #[serde(rename = "throttling.iops-total")]
     pub iops: Option<u64>,
+    pub werror: Option<String>,
 }
 
 pub type BlockDevAddArgument = blockdev_add;
@@ -1161,6 +1335,279 @@ pub struct StatusInfo {
     pub running: bool,
     #[serde(rename = "status")]
     pub status: RunState,
+    #[serde(rename = "vcpus", default, skip_serializing_if = "Vec::is_empty")]
+    pub vcpus: Vec<VcpuStatusInfo>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VcpuStatusInfo {
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: u8,
+    #[serde(rename = "running")]
+    pub running: bool,
+    #[serde(rename = "paused")]
+    pub paused: bool,
+}
+
+/// vcpu-step
+///
+/// Execute one guest instruction on a vcpu while VM is paused, and return
+/// the new program counter of the vcpu. The VM keeps paused afterwards.
+///
+/// # Arguments
+///
+/// * `cpu-index` - Index of the vcpu.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "vcpu-step", "arguments": { "cpu-index": 0 } }
+/// <- { "return": { "pc": 18446744071583125314 } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct vcpu_step {
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: u8,
+}
+
+impl Command for vcpu_step {
+    type Res = VcpuStepInfo;
+
+    fn back(self) -> VcpuStepInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VcpuStepInfo {
+    #[serde(rename = "pc")]
+    pub pc: u64,
+}
+
+/// memsave
+///
+/// Save guest physical memory to a file on the host.
+///
+/// # Arguments
+///
+/// * `val` - Start guest physical address.
+/// * `size` - Size of memory to save.
+/// * `filename` - Path of the file.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "memsave",
+///      "arguments": { "val": 4096, "size": 1024, "filename": "/tmp/mem.bin" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct memsave {
+    pub val: u64,
+    pub size: u64,
+    pub filename: String,
+}
+
+impl Command for memsave {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// ringbuf-read
+///
+/// Read and consume the data from the ring buffer of ringbuf-type chardev.
+///
+/// # Arguments
+///
+/// * `device` - Id of the chardev.
+/// * `size` - Max size of data to read in bytes.
+/// * `format` - Format of the returned data, `utf8` (default) or `base64`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "ringbuf-read",
+///      "arguments": { "device": "ringbuf0", "size": 1024, "format": "utf8" } }
+/// <- { "return": "Welcome to openEuler\r\n" }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ringbuf_read {
+    pub device: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+impl Command for ringbuf_read {
+    type Res = String;
+
+    fn back(self) -> String {
+        Default::default()
+    }
+}
+
+/// set-log-level
+///
+/// Set the log level of a module at runtime.
+///
+/// # Arguments
+///
+/// * `module` - Module path, e.g. `virtio` or `virtio::block`. The default level of
+///   all the modules is set if it's omitted.
+/// * `level` - Log level, `off`, `error`, `warn`, `info`, `debug` or `trace`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "set-log-level",
+///      "arguments": { "module": "virtio::block", "level": "debug" } }
+/// <- { "return": { "previous": "info" } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct set_log_level {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    pub level: String,
+}
+
+impl Command for set_log_level {
+    type Res = LogLevelInfo;
+
+    fn back(self) -> LogLevelInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct LogLevelInfo {
+    /// The log level of the module before it's set.
+    pub previous: String,
+}
+
+/// screendump
+///
+/// Save the image of guest display to a file in PPM format.
+///
+/// # Arguments
+///
+/// * `filename` - Path of the image file.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "screendump", "arguments": { "filename": "/path/to/image.ppm" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct screendump {
+    pub filename: String,
+}
+
+impl Command for screendump {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// change
+///
+/// Change the medium of a scsi cdrom, the guest is notified of the new medium.
+///
+/// # Arguments
+///
+/// * `device` - the id of the scsi cdrom.
+/// * `target` - path of the new image file.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "change",
+///      "arguments": { "device": "scsi0-0-1-0", "target": "/path/to/image.iso" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct change {
+    pub device: String,
+    pub target: String,
+}
+
+impl Command for change {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// eject
+///
+/// Eject the medium of a scsi cdrom.
+///
+/// # Arguments
+///
+/// * `device` - the id of the scsi cdrom.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "eject", "arguments": { "device": "scsi0-0-1-0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct eject {
+    pub device: String,
+}
+
+impl Command for eject {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// dump-guest-memory
+///
+/// Dump guest memory and vcpu registers to an ELF core file on the host.
+/// The running VM is paused during the dump.
+///
+/// # Arguments
+///
+/// * `paging` - Whether to dump with guest paging, only `false` is supported.
+/// * `protocol` - Destination of the dump, `file:<path>`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "dump-guest-memory",
+///      "arguments": { "paging": false, "protocol": "file:/tmp/vmcore" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct dump_guest_memory {
+    pub paging: bool,
+    pub protocol: String,
+}
+
+impl Command for dump_guest_memory {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1242,6 +1689,47 @@ impl Command for query_migrate {
     }
 }
 
+/// migrate-set-parameters:
+///
+/// Set parameters of migration, they take effect even if migration is running.
+///
+/// # Arguments
+///
+/// * `max-bandwidth` - max bandwidth of migration in bytes per second, zero means no limit.
+/// * `downtime-limit` - max downtime of VM in milliseconds.
+/// * `compress` - compression method of memory data, "zstd" or "none".
+/// * `compress-level` - compression level of zstd.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "migrate-set-parameters",
+///      "arguments": { "max-bandwidth": 104857600, "downtime-limit": 300 } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct migrate_set_parameters {
+    #[serde(rename = "max-bandwidth")]
+    pub max_bandwidth: Option<u64>,
+    #[serde(rename = "downtime-limit")]
+    pub downtime_limit: Option<u64>,
+    #[serde(rename = "compress")]
+    pub compress: Option<String>,
+    #[serde(rename = "compress-level")]
+    pub compress_level: Option<i32>,
+}
+
+pub type MigrateSetParametersArgument = migrate_set_parameters;
+
+impl Command for migrate_set_parameters {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
 /// cancel-migrate:
 ///
 /// Cancel migrate the current VM.
@@ -1260,6 +1748,22 @@ impl Command for cancel_migrate {
 pub struct MigrationInfo {
     #[serde(rename = "status", default, skip_serializing_if = "Option::is_none")]
     pub status: Option<String>,
+    #[serde(rename = "phase", default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+    #[serde(rename = "ram", default, skip_serializing_if = "Option::is_none")]
+    pub ram: Option<MigrationRamInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationRamInfo {
+    #[serde(rename = "total")]
+    pub total: u64,
+    #[serde(rename = "transferred")]
+    pub transferred: u64,
+    #[serde(rename = "remaining")]
+    pub remaining: u64,
+    #[serde(rename = "dirty-pages-rate")]
+    pub dirty_pages_rate: u64,
 }
 
 /// getfd
@@ -1336,38 +1840,135 @@ pub struct Stop {}
 /// Emitted when the virtual machine resumes execution
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(deny_unknown_fields)]
-pub struct Resume {}
+pub struct Resume {}
+
+/// Powerdown
+///
+/// Emitted when the virtual machine powerdown execution
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Powerdown {}
+
+/// DeviceDeleted
+///
+/// Emitted whenever the device removal completion is acknowledged by the guest.
+/// At this point, it's safe to reuse the specified device ID. Device removal can
+/// be initiated by the guest or by HMP/QMP commands.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "DEVICE_DELETED",
+///      "data": { "device": "virtio-net-mmio-0",
+///                "path": "/machine/peripheral/virtio-net-mmio-0" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceDeleted {
+    /// Device name.
+    #[serde(rename = "device", default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Device path.
+    #[serde(rename = "path")]
+    pub path: String,
+}
+
+/// BlockIoError
+///
+/// Emitted when a block device request fails, and the action is taken according to
+/// the error policy of the drive.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "BLOCK_IO_ERROR",
+///      "data": { "device": "drive-0", "operation": "write", "action": "stop",
+///                "nospace": true, "reason": "enospc" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BlockIoError {
+    /// Device name.
+    pub device: String,
+    /// I/O operation, "read" or "write".
+    pub operation: String,
+    /// Action that has been taken, "report", "stop" or "retry".
+    pub action: String,
+    /// Whether the error is caused by no space left on the host storage.
+    pub nospace: bool,
+    /// Reason of the error.
+    pub reason: String,
+}
+
+/// BlockIoRecovered
+///
+/// Emitted when a failed block device request succeeds after being retried.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "BLOCK_IO_RECOVERED",
+///      "data": { "device": "drive-0", "operation": "read" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BlockIoRecovered {
+    /// Device name.
+    pub device: String,
+    /// I/O operation, "read" or "write".
+    pub operation: String,
+}
 
-/// Powerdown
+/// GuestPanicked
 ///
-/// Emitted when the virtual machine powerdown execution
+/// Emitted when a vcpu hits an unrecoverable KVM exit, and the VM is paused,
+/// or when guest kernel notifies its panic through pvpanic device.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "GUEST_PANICKED",
+///      "data": { "action": "pause", "cpu-index": 0, "reason": "internal-error" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// <- { "event": "GUEST_PANICKED",
+///      "data": { "action": "none", "reason": "pvpanic" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(deny_unknown_fields)]
-pub struct Powerdown {}
+pub struct GuestPanicked {
+    /// Action that has been taken, "pause" or "none".
+    pub action: String,
+    /// Id of the vcpu which hits the error, absent for the panic notified by pvpanic.
+    #[serde(rename = "cpu-index", skip_serializing_if = "Option::is_none")]
+    pub cpu_index: Option<u8>,
+    /// KVM exit reason of the vcpu, or "pvpanic".
+    pub reason: String,
+}
 
-/// DeviceDeleted
+/// VcpuStalled
 ///
-/// Emitted whenever the device removal completion is acknowledged by the guest.
-/// At this point, it's safe to reuse the specified device ID. Device removal can
-/// be initiated by the guest or by HMP/QMP commands.
+/// Emitted when a vcpu doesn't make progress in VMM within the window of vcpu
+/// liveness monitor while it is running.
 ///
 /// # Examples
 ///
 /// ```text
-/// <- { "event": "DEVICE_DELETED",
-///      "data": { "device": "virtio-net-mmio-0",
-///                "path": "/machine/peripheral/virtio-net-mmio-0" },
+/// <- { "event": "VCPU_STALLED",
+///      "data": { "cpu-index": 1, "seconds": 10 },
 ///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
 /// ```
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(deny_unknown_fields)]
-pub struct DeviceDeleted {
-    /// Device name.
-    #[serde(rename = "device", default, skip_serializing_if = "Option::is_none")]
-    pub device: Option<String>,
-    /// Device path.
-    #[serde(rename = "path")]
-    pub path: String,
+pub struct VcpuStalled {
+    /// Id of the stalled vcpu.
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: u8,
+    /// Time in seconds since the vcpu made progress last time.
+    pub seconds: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString)]
@@ -1408,6 +2009,26 @@ pub enum QmpEvent {
         data: BalloonInfo,
         timestamp: TimeStamp,
     },
+    #[serde(rename = "BLOCK_IO_ERROR")]
+    BlockIoError {
+        data: BlockIoError,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "BLOCK_IO_RECOVERED")]
+    BlockIoRecovered {
+        data: BlockIoRecovered,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "GUEST_PANICKED")]
+    GuestPanicked {
+        data: GuestPanicked,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "VCPU_STALLED")]
+    VcpuStalled {
+        data: VcpuStalled,
+        timestamp: TimeStamp,
+    },
 }
 
 /// query-balloon:
@@ -1495,6 +2116,152 @@ pub struct VncClientInfo {
     pub family: String,
 }
 
+/// query-pinned-memory:
+///
+/// Query the guest memory ranges pinned by devices doing DMA (vfio, vhost-user).
+/// Pinned ranges can't be reclaimed by balloon. It's used for debugging.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-pinned-memory" }
+/// <- {"return":[{"owner":"vfio","base":0,"size":3221225472}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_pinned_memory {}
+impl Command for query_pinned_memory {
+    type Res = Vec<PinnedMemoryInfo>;
+    fn back(self) -> Vec<PinnedMemoryInfo> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedMemoryInfo {
+    pub owner: String,
+    pub base: u64,
+    pub size: u64,
+}
+
+/// query-mtree:
+///
+/// Query the region tree of every address space, showing where RAM, ROM,
+/// MMIO and PIO ranges of devices are mapped. It's used for debugging.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-mtree" }
+/// <- {"return":[{"address-space":"memory","regions":[
+///      "0000000000000000-ffffffffffffffff (prio 0, container): unnamed",
+///      "  0000000040000000-00000000bfffffff (prio 0, ram): ram"]}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_mtree {}
+impl Command for query_mtree {
+    type Res = Vec<MtreeInfo>;
+    fn back(self) -> Vec<MtreeInfo> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct MtreeInfo {
+    #[serde(rename = "address-space")]
+    pub address_space: String,
+    pub regions: Vec<String>,
+}
+
+/// query-pci:
+///
+/// Query the PCI topology, including every bus and the devices attached to it.
+/// It's used for debugging.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-pci" }
+/// <- {"return":[{"bus":0,"devices":[{"bus":0,"slot":1,"function":0,"class":256,
+///      "vendor-id":6900,"device-id":4162,"subsystem-vendor-id":6900,"subsystem-id":4162,
+///      "qdev-id":"blk0","regions":[{"bar":1,"type":"memory","address":549755813888,
+///      "size":16384,"mem-type-64":true}],"msix":{"enabled":true,"masked":false,
+///      "vectors":2,"routes":[{"vector":0,"gsi":24},{"vector":1,"gsi":25}]}}]}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_pci {}
+impl Command for query_pci {
+    type Res = Vec<PciInfo>;
+    fn back(self) -> Vec<PciInfo> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PciInfo {
+    pub bus: u8,
+    pub devices: Vec<PciDeviceInfo>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PciDeviceInfo {
+    pub bus: u8,
+    pub slot: u8,
+    pub function: u8,
+    pub class: u16,
+    #[serde(rename = "vendor-id")]
+    pub vendor_id: u16,
+    #[serde(rename = "device-id")]
+    pub device_id: u16,
+    #[serde(rename = "subsystem-vendor-id")]
+    pub subsystem_vendor_id: u16,
+    #[serde(rename = "subsystem-id")]
+    pub subsystem_id: u16,
+    #[serde(rename = "qdev-id")]
+    pub qdev_id: String,
+    pub regions: Vec<PciBarInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub irq: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msix: Option<PciMsixInfo>,
+    #[serde(
+        rename = "pci-bridge",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pci_bridge: Option<PciBridgeInfo>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PciBarInfo {
+    pub bar: u8,
+    #[serde(rename = "type")]
+    pub bar_type: String,
+    pub address: u64,
+    pub size: u64,
+    #[serde(rename = "mem-type-64")]
+    pub mem_type_64: bool,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PciMsixInfo {
+    pub enabled: bool,
+    pub masked: bool,
+    pub vectors: u16,
+    pub routes: Vec<PciMsixRoute>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PciMsixRoute {
+    pub vector: u16,
+    pub gsi: i32,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PciBridgeInfo {
+    pub secondary: u8,
+    pub subordinate: u8,
+}
+
 /// balloon:
 ///
 /// Advice VM to change memory size with the argument `value`.
@@ -2018,25 +2785,45 @@ impl Command for query_named_block_nodes {
     }
 }
 
-/// Query status of blocks.
+/// Query IO statistics of blocks.
 ///
 /// # Example
 ///
 /// ```text
 /// -> { "execute": "query-blockstats" }
-/// <- {"return":[]}
+/// <- {"return":[{"device":"drive-0","stats":{"rd_bytes":4096,"wr_bytes":1024,
+///     "rd_operations":1,"wr_operations":2,"flush_operations":1,"failed_rd_operations":0,
+///     "failed_wr_operations":0,"failed_flush_operations":0}}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct query_blockstats {}
 
 impl Command for query_blockstats {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockStatsInfo>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockStatsInfo> {
         Default::default()
     }
 }
 
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockStatsInfo {
+    pub device: String,
+    pub stats: BlockDeviceStats,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDeviceStats {
+    pub rd_bytes: u64,
+    pub wr_bytes: u64,
+    pub rd_operations: u64,
+    pub wr_operations: u64,
+    pub flush_operations: u64,
+    pub failed_rd_operations: u64,
+    pub failed_wr_operations: u64,
+    pub failed_flush_operations: u64,
+}
+
 /// Query jobs of blocks.
 ///
 /// # Example
@@ -2512,6 +3299,188 @@ mod tests {
         };
         let ret_msg = r#"ok"#;
         assert!(err_msg == ret_msg);
+
+        // right arguments for vcpu-step.
+        let json_msg = r#"
+        {
+            "execute": "vcpu-step",
+            "arguments": {
+                "cpu-index": 1
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
+
+        // missing arguments for vcpu-step.
+        let json_msg = r#"
+        {
+            "execute": "vcpu-step",
+            "arguments": {}
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"missing field `cpu-index`"#;
+        assert!(err_msg == ret_msg);
+
+        // right arguments for memsave.
+        let json_msg = r#"
+        {
+            "execute": "memsave",
+            "arguments": {
+                "val": 4096,
+                "size": 1024,
+                "filename": "/path/to/mem"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
+
+        // right arguments for ringbuf-read.
+        let json_msg = r#"
+        {
+            "execute": "ringbuf-read",
+            "arguments": {
+                "device": "ringbuf0",
+                "size": 1024,
+                "format": "base64"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
+
+        // right arguments for set-log-level.
+        let json_msg = r#"
+        {
+            "execute": "set-log-level",
+            "arguments": {
+                "module": "virtio::block",
+                "level": "debug"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
+
+        // right arguments for screendump.
+        let json_msg = r#"
+        {
+            "execute": "screendump",
+            "arguments": {
+                "filename": "/path/to/image.ppm"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
+
+        // right arguments for change.
+        let json_msg = r#"
+        {
+            "execute": "change",
+            "arguments": {
+                "device": "scsi0-0-1-0",
+                "target": "/path/to/image.iso"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
+
+        // missing arguments for change.
+        let json_msg = r#"
+        {
+            "execute": "change",
+            "arguments": {
+                "device": "scsi0-0-1-0"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"missing field `target`"#;
+        assert!(err_msg == ret_msg);
+
+        // right arguments for eject.
+        let json_msg = r#"
+        {
+            "execute": "eject",
+            "arguments": {
+                "device": "scsi0-0-1-0"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
+
+        // missing arguments for memsave.
+        let json_msg = r#"
+        {
+            "execute": "memsave",
+            "arguments": {
+                "val": 4096,
+                "size": 1024
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"missing field `filename`"#;
+        assert!(err_msg == ret_msg);
+
+        // right arguments for dump-guest-memory.
+        let json_msg = r#"
+        {
+            "execute": "dump-guest-memory",
+            "arguments": {
+                "paging": false,
+                "protocol": "file:/path/to/vmcore"
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let ret_msg = r#"ok"#;
+        assert!(err_msg == ret_msg);
     }
 
     #[test]
@@ -2586,6 +3555,22 @@ mod tests {
         };
         let part_msg = r#"ok"#;
         assert!(err_msg.contains(part_msg));
+        // migrate-set-parameters
+        let json_msg = r#"
+        {
+            "execute": "migrate-set-parameters",
+            "arguments": {
+                "max-bandwidth": 104857600
+            }
+        }
+        "#;
+        let cmd = serde_json::from_str::<QmpCommand>(json_msg).unwrap();
+        if let QmpCommand::migrate_set_parameters { arguments, .. } = cmd {
+            assert_eq!(arguments.max_bandwidth, Some(104857600));
+            assert!(arguments.downtime_limit.is_none());
+        } else {
+            panic!("Unexpected qmp command");
+        }
     }
 
     #[test]
@@ -73,6 +73,10 @@ pub struct Socket {
     stream: RwLock<Option<SocketStream>>,
     /// Perform socket command
     performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
+    /// Whether async events are sent to the client of the socket.
+    event_enabled: bool,
+    /// Whether only query commands are accepted from the client of the socket.
+    read_only: bool,
 }
 
 impl Socket {
@@ -91,6 +95,28 @@ impl Socket {
             listener,
             stream: RwLock::new(None),
             performer,
+            event_enabled: true,
+            read_only: false,
+        }
+    }
+
+    /// Allocates a new extra control `Socket` with `UnixListener`. Async events are not
+    /// sent to its client, they are left to the client of `-qmp` or `-mon`.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - The `UnixListener` bind to `Socket`.
+    /// * `performer` - The `VM` to perform socket command.
+    /// * `read_only` - Only accept `query-*` commands.
+    pub fn from_control_listener(
+        listener: UnixListener,
+        performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
+        read_only: bool,
+    ) -> Self {
+        Socket {
+            event_enabled: false,
+            read_only,
+            ..Self::from_unix_listener(listener, performer)
         }
     }
 
@@ -100,13 +126,14 @@ impl Socket {
     }
 
     /// Accept stream and bind to Socket.
-    pub fn accept(&self) {
+    pub fn accept(&self) -> std::io::Result<()> {
         match self.sock_type {
             SocketType::Unix => {
-                let stream = self.accept_unix_stream();
+                let (stream, _) = self.listener.accept()?;
                 self.bind_unix_stream(stream);
             }
         }
+        Ok(())
     }
 
     /// Accept a new incoming connection unix stream from unix listener.
@@ -177,20 +204,36 @@ impl Socket {
     fn create_event_notifier(&mut self, shared_socket: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut notifiers = Vec::new();
 
+        // The listener is parked while the accepted stream is registered, so one client
+        // is served at a time, and the others wait in the backlog until it hangs up.
+        if let Err(e) = self.accept() {
+            // Nonblocking listener may be woken up without pending connection.
+            if e.kind() != ErrorKind::WouldBlock {
+                error!("Failed to accept QMP client: {:?}", e);
+            }
+            return notifiers;
+        }
+
         let leak_bucket = LeakBucket::new(LEAK_BUCKET_LIMIT);
         if let Err(e) = leak_bucket {
             error!("Failed to create leak bucket, {:?}", e);
+            self.drop_stream();
             return notifiers;
         }
         let leak_bucket = Arc::new(Mutex::new(leak_bucket.unwrap()));
         let shared_leak_bucket = leak_bucket.clone();
         let leak_bucket_fd = leak_bucket.lock().unwrap().as_raw_fd();
 
-        self.accept();
-        QmpChannel::bind_writer(SocketRWHandler::new(self.get_stream_fd()));
+        let event_enabled = self.event_enabled;
+        if event_enabled {
+            QmpChannel::bind_writer(SocketRWHandler::new(self.get_stream_fd()));
+        }
         if let Err(e) = self.send_response(true) {
             error!("{:?}", e);
-            QmpChannel::unbind();
+            if event_enabled {
+                QmpChannel::unbind();
+            }
+            self.drop_stream();
             return notifiers;
         }
         let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
@@ -203,6 +246,7 @@ impl Socket {
                     stream_fd,
                     performer,
                     &mut shared_leak_bucket.lock().unwrap(),
+                    socket_mutexed.read_only,
                 ) {
                     error!("{:?}", e);
                 }
@@ -211,7 +255,9 @@ impl Socket {
                 let socket_mutexed = shared_socket.lock().unwrap();
                 let stream_fd = socket_mutexed.get_stream_fd();
 
-                QmpChannel::unbind();
+                if event_enabled {
+                    QmpChannel::unbind();
+                }
                 Some(gen_delete_notifiers(&[stream_fd, leak_bucket_fd]))
             } else {
                 None
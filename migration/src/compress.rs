@@ -0,0 +1,162 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Compression of memory data in migration stream.
+//!
+//! Memory data of a block is encoded as:
+//! ```text
+//! | page flags (1 byte per page) | length of compressed data (u64) | zstd compressed data |
+//! ```
+//! Zero pages are marked in page flags and not included in the compressed data.
+
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+
+/// Size of page for detecting zero pages, it is fixed so that source and
+/// destination with different host page size can work together.
+const COMPRESS_PAGE_SIZE: usize = 4096;
+/// Page flag for zero page.
+const PAGE_FLAG_ZERO: u8 = 0;
+/// Page flag for page whose data is in compressed data.
+const PAGE_FLAG_DATA: u8 = 1;
+
+/// Default compression level of zstd.
+pub const DEFAULT_COMPRESS_LEVEL: i32 = 3;
+/// Min compression level of zstd.
+pub const MIN_COMPRESS_LEVEL: i32 = 1;
+/// Max compression level of zstd.
+pub const MAX_COMPRESS_LEVEL: i32 = 19;
+
+fn pages_num(len: usize) -> usize {
+    (len + COMPRESS_PAGE_SIZE - 1) / COMPRESS_PAGE_SIZE
+}
+
+/// Encode memory data of a block, zero pages are marked and the others are
+/// compressed by zstd.
+///
+/// # Arguments
+///
+/// * `data` - Memory data of the block.
+/// * `level` - Compression level of zstd.
+pub fn compress_block(data: &[u8], level: i32) -> Result<Vec<u8>> {
+    let mut flags = Vec::with_capacity(pages_num(data.len()));
+    let mut pages = Vec::with_capacity(data.len());
+    for page in data.chunks(COMPRESS_PAGE_SIZE) {
+        if page.iter().all(|b| *b == 0) {
+            flags.push(PAGE_FLAG_ZERO);
+        } else {
+            flags.push(PAGE_FLAG_DATA);
+            pages.extend_from_slice(page);
+        }
+    }
+
+    let compressed = if pages.is_empty() {
+        Vec::new()
+    } else {
+        zstd::bulk::compress(&pages, level).with_context(|| "Failed to compress memory data")?
+    };
+
+    let mut encoded = flags;
+    encoded.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+    encoded.extend_from_slice(&compressed);
+    Ok(encoded)
+}
+
+/// Decode memory data of a block from `Read`.
+///
+/// # Arguments
+///
+/// * `fd` - The `Read` trait object to receive encoded data.
+/// * `len` - Length of memory data of the block.
+pub fn decompress_block(fd: &mut dyn Read, len: usize) -> Result<Vec<u8>> {
+    let mut flags = vec![0_u8; pages_num(len)];
+    fd.read_exact(&mut flags)
+        .with_context(|| "Failed to read page flags")?;
+    let mut compressed_len = [0_u8; 8];
+    fd.read_exact(&mut compressed_len)
+        .with_context(|| "Failed to read length of compressed data")?;
+    let compressed_len = u64::from_le_bytes(compressed_len) as usize;
+    if compressed_len > zstd::zstd_safe::compress_bound(len) {
+        bail!("Invalid length of compressed data {}", compressed_len);
+    }
+    let mut compressed = vec![0_u8; compressed_len];
+    fd.read_exact(&mut compressed)
+        .with_context(|| "Failed to read compressed data")?;
+
+    let pages = if compressed.is_empty() {
+        Vec::new()
+    } else {
+        zstd::bulk::decompress(&compressed, len)
+            .with_context(|| "Failed to decompress memory data")?
+    };
+
+    let mut data = vec![0_u8; len];
+    let mut offset = 0;
+    for (page, flag) in data.chunks_mut(COMPRESS_PAGE_SIZE).zip(flags.iter()) {
+        match *flag {
+            PAGE_FLAG_ZERO => {}
+            PAGE_FLAG_DATA => {
+                if offset + page.len() > pages.len() {
+                    bail!("Compressed data is shorter than page flags");
+                }
+                page.copy_from_slice(&pages[offset..offset + page.len()]);
+                offset += page.len();
+            }
+            _ => bail!("Invalid page flag {}", flag),
+        }
+    }
+    if offset != pages.len() {
+        bail!("Compressed data is longer than page flags");
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_block() {
+        let mut data = vec![0_u8; COMPRESS_PAGE_SIZE * 4 + 100];
+        data[COMPRESS_PAGE_SIZE + 1] = 0x5a;
+        data[COMPRESS_PAGE_SIZE * 4 + 99] = 0xa5;
+
+        let encoded = compress_block(&data, DEFAULT_COMPRESS_LEVEL).unwrap();
+        assert_eq!(
+            &encoded[..5],
+            &[
+                PAGE_FLAG_ZERO,
+                PAGE_FLAG_DATA,
+                PAGE_FLAG_ZERO,
+                PAGE_FLAG_ZERO,
+                PAGE_FLAG_DATA
+            ]
+        );
+        assert!(encoded.len() < data.len());
+        let decoded = decompress_block(&mut encoded.as_slice(), data.len()).unwrap();
+        assert_eq!(decoded, data);
+
+        // All zero pages have no compressed data.
+        let data = vec![0_u8; COMPRESS_PAGE_SIZE * 2];
+        let encoded = compress_block(&data, DEFAULT_COMPRESS_LEVEL).unwrap();
+        assert_eq!(encoded.len(), 2 + 8);
+        let decoded = decompress_block(&mut encoded.as_slice(), data.len()).unwrap();
+        assert_eq!(decoded, data);
+
+        // Broken page flag.
+        let mut encoded = encoded;
+        encoded[0] = 2;
+        assert!(decompress_block(&mut encoded.as_slice(), data.len()).is_err());
+    }
+}
@@ -217,6 +217,26 @@ pub trait Lifecycle {
         Ok(())
     }
 
+    /// Check all restored transports and devices before VM resumes.
+    fn post_restore() -> Result<()> {
+        let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
+        let instances = locked_vmm
+            .transports
+            .iter()
+            .chain(locked_vmm.devices.iter());
+        for (id, instance) in instances {
+            instance.lock().unwrap().post_restore().with_context(|| {
+                let name = locked_vmm
+                    .instance_names
+                    .get(id)
+                    .map_or_else(|| format!("{:#x}", id), |name| name.clone());
+                format!("Failed to post restore device {}", name)
+            })?;
+        }
+
+        Ok(())
+    }
+
     /// Resume VM during migration.
     fn resume() -> Result<()> {
         let locked_transports = &MIGRATION_MANAGER.vmm.read().unwrap().transports;
@@ -14,6 +14,7 @@
 //!
 //! Offer snapshot and migration interface for VM.
 
+pub mod compress;
 pub mod general;
 pub mod manager;
 pub mod migration;
@@ -25,19 +26,32 @@ use std::{net::TcpStream, os::unix::net::UnixStream, thread};
 
 use anyhow::anyhow;
 pub use anyhow::Result;
+use compress::{MAX_COMPRESS_LEVEL, MIN_COMPRESS_LEVEL};
 use log::error;
 use machine_manager::qmp::{qmp_schema, Response};
+use manager::MIGRATION_MANAGER;
 pub use manager::{MigrationHook, MigrationManager};
-pub use protocol::{DeviceStateDesc, FieldDesc, MemBlock, MigrationStatus, StateTransfer};
+pub use protocol::{
+    DeviceStateDesc, FieldDesc, MemBlock, MigrationPhase, MigrationStatus, StateTransfer,
+};
 pub mod error;
 pub use error::MigrationError;
 
+/// Max downtime limit of migration in milliseconds.
+const MAX_DOWNTIME_LIMIT: u64 = 2_000_000;
+
 /// Start to snapshot VM.
 ///
 /// # Arguments
 ///
 /// * `path` - snapshot dir path. If path dir not exists, will create it.
 pub fn snapshot(path: String) -> Response {
+    if let Err(e) = MigrationManager::check_migration_blockers() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
     if let Err(e) = MigrationManager::save_snapshot(&path) {
         error!("Failed to migrate to path \'{:?}\': {:?}", path, e);
         let _ = MigrationManager::set_status(MigrationStatus::Failed).map_err(|e| anyhow!("{}", e));
@@ -56,6 +70,12 @@ pub fn snapshot(path: String) -> Response {
 ///
 /// * `path` - Unix socket path, as /tmp/migration.socket.
 pub fn migration_unix_mode(path: String) -> Response {
+    if let Err(e) = MigrationManager::check_migration_blockers() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
     let mut socket = match UnixStream::connect(path) {
         Ok(_sock) => {
             // Specify the tcp receiving or send timeout.
@@ -81,7 +101,7 @@ pub fn migration_unix_mode(path: String) -> Response {
         .spawn(move || {
             if let Err(e) = MigrationManager::send_migration(&mut socket) {
                 error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
+                let _ = MigrationManager::recover_from_migration().map_err(|e| error!("{:?}", e));
                 let _ = MigrationManager::set_status(MigrationStatus::Failed)
                     .map_err(|e| error!("{}", e));
             }
@@ -102,6 +122,12 @@ pub fn migration_unix_mode(path: String) -> Response {
 ///
 /// * `path` - Tcp ip and port, as 192.168.1.1:4446.
 pub fn migration_tcp_mode(path: String) -> Response {
+    if let Err(e) = MigrationManager::check_migration_blockers() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
     let mut socket = match TcpStream::connect(path) {
         Ok(_sock) => {
             // Specify the tcp receiving or send timeout.
@@ -127,7 +153,7 @@ pub fn migration_tcp_mode(path: String) -> Response {
         .spawn(move || {
             if let Err(e) = MigrationManager::send_migration(&mut socket) {
                 error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
+                let _ = MigrationManager::recover_from_migration().map_err(|e| error!("{:?}", e));
                 let _ = MigrationManager::set_status(MigrationStatus::Failed)
                     .map_err(|e| error!("{}", e));
             }
@@ -145,13 +171,75 @@ pub fn migration_tcp_mode(path: String) -> Response {
 /// Query the current migration status.
 pub fn query_migrate() -> Response {
     let status_str = MigrationManager::status().to_string();
-    let migration_info = qmp_schema::MigrationInfo {
+    let stats = MIGRATION_MANAGER.stats.read().unwrap().clone();
+    let mut migration_info = qmp_schema::MigrationInfo {
         status: Some(status_str),
+        ..Default::default()
     };
+    // Memory statistics is only available at source VM after migration is started.
+    if stats.phase != MigrationPhase::None {
+        migration_info.phase = Some(stats.phase.to_string());
+        migration_info.ram = Some(qmp_schema::MigrationRamInfo {
+            total: stats.total_bytes,
+            transferred: stats.transferred_bytes,
+            remaining: stats.remaining_bytes,
+            dirty_pages_rate: stats.dirty_pages_rate,
+        });
+    }
 
     Response::create_response(serde_json::to_value(migration_info).unwrap(), None)
 }
 
+/// Set parameters of migration. Bandwidth and downtime limit take effect even
+/// if migration is running, compression takes effect from the next migration.
+///
+/// # Arguments
+///
+/// * `args` - Parameters of migration.
+pub fn migrate_set_parameters(args: qmp_schema::MigrateSetParametersArgument) -> Response {
+    let err_resp = |msg: String| {
+        Response::create_error_response(qmp_schema::QmpErrorClass::GenericError(msg), None)
+    };
+    if let Some(downtime) = args.downtime_limit {
+        if downtime == 0 || downtime > MAX_DOWNTIME_LIMIT {
+            return err_resp(format!(
+                "downtime-limit must be in range [1, {}] ms",
+                MAX_DOWNTIME_LIMIT
+            ));
+        }
+    }
+    let compress = match args.compress.as_deref() {
+        Some("zstd") => Some(true),
+        Some("none") => Some(false),
+        Some(method) => return err_resp(format!("Unsupported compress method {}", method)),
+        None => None,
+    };
+    if let Some(level) = args.compress_level {
+        if !(MIN_COMPRESS_LEVEL..=MAX_COMPRESS_LEVEL).contains(&level) {
+            return err_resp(format!(
+                "compress-level must be in range [{}, {}]",
+                MIN_COMPRESS_LEVEL, MAX_COMPRESS_LEVEL
+            ));
+        }
+    }
+
+    let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+    if let Some(bandwidth) = args.max_bandwidth {
+        limit.max_bandwidth = bandwidth;
+    }
+    if let Some(downtime) = args.downtime_limit {
+        limit.limit_downtime = downtime;
+    }
+    if let Some(compress) = compress {
+        limit.compress = compress;
+    }
+    if let Some(level) = args.compress_level {
+        limit.compress_level = level;
+    }
+
+    Response::create_empty_response()
+}
+
 /// Cancel the current migration.
 pub fn cancel_migrate() -> Response {
     if let Err(e) = MigrationManager::set_status(MigrationStatus::Canceled) {
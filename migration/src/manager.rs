@@ -10,20 +10,23 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp::min;
 use std::collections::HashMap;
 use std::fs::File;
 use std::hash::Hash;
 use std::io::{Read, Write};
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use log::info;
 use once_cell::sync::Lazy;
 
+use crate::compress::DEFAULT_COMPRESS_LEVEL;
 use crate::general::translate_id;
 use crate::migration::DirtyBitmap;
-use crate::protocol::{DeviceStateDesc, MemBlock, MigrationStatus, StateTransfer};
-use anyhow::{Context, Result};
+use crate::protocol::{DeviceStateDesc, MemBlock, MigrationPhase, MigrationStatus, StateTransfer};
+use crate::MigrationError;
+use anyhow::{anyhow, Context, Result};
 use machine_manager::config::VmConfig;
 use machine_manager::machine::MachineLifecycle;
 use util::byte_code::ByteCode;
@@ -35,6 +38,9 @@ pub(crate) static MIGRATION_MANAGER: Lazy<MigrationManager> = Lazy::new(|| Migra
     status: Arc::new(RwLock::new(MigrationStatus::None)),
     vmm_bitmaps: Arc::new(RwLock::new(HashMap::new())),
     limit: Arc::new(RwLock::new(MigrationLimit::default())),
+    stats: Arc::new(RwLock::new(MigrationStats::default())),
+    compress_level: Arc::new(RwLock::new(None)),
+    blockers: Arc::new(RwLock::new(HashMap::new())),
 });
 
 /// A hook for `Device` to save device state to `Write` object and load device
@@ -129,6 +135,16 @@ pub trait MigrationHook: StateTransfer {
         Ok(())
     }
 
+    /// Check the device after its state is restored and before vcpus resume.
+    ///
+    /// # Notes
+    ///
+    /// Device can re-validate the restored state against guest memory or
+    /// re-open its backend here, an error will abort the resume.
+    fn post_restore(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Resume the recover device.
     ///
     /// # Notes
@@ -171,6 +187,8 @@ pub struct Vmm {
     pub transports: HashMap<u64, Arc<Mutex<dyn MigrationHook + Send + Sync>>>,
     /// Trait to represent devices.
     pub devices: HashMap<u64, Arc<Mutex<dyn MigrationHook + Send + Sync>>>,
+    /// Names of registered transports and devices, used for error reporting.
+    pub instance_names: HashMap<u64, String>,
     #[cfg(target_arch = "aarch64")]
     /// Trait to represent GIC devices(GICv3, GICv3 ITS).
     pub gic_group: HashMap<u64, Arc<dyn MigrationHook + Send + Sync>>,
@@ -187,6 +205,16 @@ pub struct MigrationLimit {
     pub limit_downtime: u64,
     /// Max number of iterations during iteratively sending dirty memory.
     pub max_dirty_iterations: u16,
+    /// Max bandwidth of sending memory in bytes per second, 0 means unlimited.
+    pub max_bandwidth: u64,
+    /// Tokens of bandwidth throttle in bytes, negative value means overdraft.
+    pub bandwidth_tokens: i64,
+    /// Last time of refilling bandwidth tokens.
+    pub tokens_refill_time: Instant,
+    /// Whether to compress memory data by zstd.
+    pub compress: bool,
+    /// Compression level of zstd.
+    pub compress_level: i32,
 }
 
 impl Default for MigrationLimit {
@@ -195,10 +223,119 @@ impl Default for MigrationLimit {
             iteration_start_time: Instant::now(),
             limit_downtime: 50,
             max_dirty_iterations: 30,
+            max_bandwidth: 0,
+            bandwidth_tokens: 0,
+            tokens_refill_time: Instant::now(),
+            compress: false,
+            compress_level: DEFAULT_COMPRESS_LEVEL,
+        }
+    }
+}
+
+impl MigrationLimit {
+    /// Reset tokens of bandwidth throttle.
+    pub fn reset_bandwidth_tokens(&mut self) {
+        self.bandwidth_tokens = 0;
+        self.tokens_refill_time = Instant::now();
+    }
+
+    /// Consume tokens of bandwidth throttle to send `len` bytes. Tokens are
+    /// refilled at `max_bandwidth` and at most one second of tokens are kept.
+    /// Return the time to wait if tokens are overdrawn, then try again.
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - Bytes to be sent.
+    pub fn consume_bandwidth(&mut self, len: u64) -> Option<Duration> {
+        if self.max_bandwidth == 0 {
+            self.reset_bandwidth_tokens();
+            return None;
+        }
+
+        let bandwidth = min(self.max_bandwidth, i64::MAX as u64) as i64;
+        let elapsed_us = self.tokens_refill_time.elapsed().as_micros();
+        let refill = min(
+            bandwidth as u128 * elapsed_us / 1_000_000,
+            bandwidth as u128,
+        ) as i64;
+        // Keep the refill time for small refill, otherwise the fraction will be lost.
+        if refill > 0 {
+            self.bandwidth_tokens = min(self.bandwidth_tokens.saturating_add(refill), bandwidth);
+            self.tokens_refill_time = Instant::now();
+        }
+
+        if self.bandwidth_tokens < 0 {
+            let wait_us = (-self.bandwidth_tokens) as u128 * 1_000_000 / bandwidth as u128;
+            // Wait at most 100ms, so that the change of bandwidth takes effect in time.
+            return Some(min(
+                Duration::from_micros(wait_us as u64 + 1),
+                Duration::from_millis(100),
+            ));
+        }
+        self.bandwidth_tokens = self
+            .bandwidth_tokens
+            .saturating_sub(min(len, i64::MAX as u64) as i64);
+
+        None
+    }
+}
+
+/// Statistics of live migration at source VM.
+#[derive(Clone, Debug)]
+pub struct MigrationStats {
+    /// Current phase of migration.
+    pub phase: MigrationPhase,
+    /// Total bytes of VM memory.
+    pub total_bytes: u64,
+    /// Bytes of VM memory have been sent, including the resent dirty memory.
+    pub transferred_bytes: u64,
+    /// Bytes of VM memory still to be sent, estimated by the last dirty scan.
+    pub remaining_bytes: u64,
+    /// Dirty pages per second, measured by the last dirty scan.
+    pub dirty_pages_rate: u64,
+    /// Time of the last dirty scan.
+    pub last_scan_time: Instant,
+}
+
+impl Default for MigrationStats {
+    fn default() -> Self {
+        Self {
+            phase: MigrationPhase::None,
+            total_bytes: 0,
+            transferred_bytes: 0,
+            remaining_bytes: 0,
+            dirty_pages_rate: 0,
+            last_scan_time: Instant::now(),
         }
     }
 }
 
+impl MigrationStats {
+    /// Update statistics with the result of a dirty scan.
+    ///
+    /// # Arguments
+    ///
+    /// * `dirty_bytes` - Bytes of dirty memory found in this scan.
+    /// * `page_size` - Host page size.
+    pub fn update_dirty_scan(&mut self, dirty_bytes: u64, page_size: u64) {
+        let now = Instant::now();
+        let elapsed_ms = now.duration_since(self.last_scan_time).as_millis() as u64;
+        self.dirty_pages_rate = dirty_bytes / page_size * 1000 / elapsed_ms.max(1);
+        self.remaining_bytes = dirty_bytes;
+        self.last_scan_time = now;
+    }
+
+    /// Update statistics after memory is sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `sent_bytes` - Bytes of memory have been sent.
+    pub fn update_sent(&mut self, sent_bytes: u64) {
+        self.transferred_bytes += sent_bytes;
+        self.remaining_bytes = self.remaining_bytes.saturating_sub(sent_bytes);
+    }
+}
+
 /// This structure is to manage all resource during migration.
 /// It is also the only way to call on `MIGRATION_MANAGER`.
 pub struct MigrationManager {
@@ -212,6 +349,12 @@ pub struct MigrationManager {
     pub vmm_bitmaps: Arc<RwLock<HashMap<u32, DirtyBitmap>>>,
     /// Limiting elements of migration.
     pub limit: Arc<RwLock<MigrationLimit>>,
+    /// Statistics of the current migration.
+    pub stats: Arc<RwLock<MigrationStats>>,
+    /// Negotiated zstd compression level of the current migration, None means uncompressed.
+    pub compress_level: Arc<RwLock<Option<i32>>>,
+    /// Devices which block migration, the key is device id and the value is the reason.
+    pub blockers: Arc<RwLock<HashMap<String, String>>>,
 }
 
 impl MigrationManager {
@@ -302,6 +445,7 @@ impl MigrationManager {
 
         let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
         locked_vmm.transports.insert(translate_id(&name), device);
+        locked_vmm.instance_names.insert(translate_id(&name), name);
     }
 
     /// Register device instance to vmm.
@@ -323,6 +467,7 @@ impl MigrationManager {
 
         let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
         locked_vmm.devices.insert(translate_id(&name), device);
+        locked_vmm.instance_names.insert(translate_id(&name), name);
     }
 
     /// Register kvm instance to vmm.
@@ -369,6 +514,7 @@ impl MigrationManager {
         let name = device_desc.name + "/" + id;
         let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
         locked_vmm.transports.remove(&translate_id(&name));
+        locked_vmm.instance_names.remove(&translate_id(&name));
     }
 
     /// Unregister device instance from vmm.
@@ -381,6 +527,43 @@ impl MigrationManager {
         let name = device_desc.name + "/" + id;
         let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
         locked_vmm.devices.remove(&translate_id(&name));
+        locked_vmm.instance_names.remove(&translate_id(&name));
+    }
+
+    /// Register a device whose state can't be migrated, such as passthrough device.
+    /// Snapshot and migration are refused until the device is unregistered.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The unique id for device.
+    /// * `reason` - The reason why the device blocks migration.
+    pub fn register_migration_blocker(id: &str, reason: &str) {
+        MIGRATION_MANAGER
+            .blockers
+            .write()
+            .unwrap()
+            .insert(id.to_string(), reason.to_string());
+    }
+
+    /// Unregister the device which blocks migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The unique id for device.
+    pub fn unregister_migration_blocker(id: &str) {
+        MIGRATION_MANAGER.blockers.write().unwrap().remove(id);
+    }
+
+    /// Check whether migration is blocked by any device.
+    pub fn check_migration_blockers() -> Result<()> {
+        let blockers = MIGRATION_MANAGER.blockers.read().unwrap();
+        if let Some((id, reason)) = blockers.iter().min() {
+            return Err(anyhow!(MigrationError::MigrationBlocked(
+                id.clone(),
+                reason.clone()
+            )));
+        }
+        Ok(())
     }
 }
 
@@ -389,11 +572,31 @@ mod tests {
     use std::sync::{Arc, Mutex};
 
     use super::*;
+    use crate::general::Lifecycle;
     use crate::protocol::tests::{DeviceV1, DeviceV1State, DeviceV2, DeviceV2State};
 
     impl MigrationHook for DeviceV1 {}
     impl MigrationHook for DeviceV2 {}
 
+    #[derive(Default)]
+    struct BrokenDevice {}
+
+    impl StateTransfer for BrokenDevice {
+        fn get_state_vec(&self) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn get_device_alias(&self) -> u64 {
+            0
+        }
+    }
+
+    impl MigrationHook for BrokenDevice {
+        fn post_restore(&self) -> Result<()> {
+            anyhow::bail!("backend is gone")
+        }
+    }
+
     #[test]
     fn test_register_device() {
         let device_v1_mutex = Arc::new(Mutex::new(DeviceV1::default()));
@@ -423,4 +626,73 @@ mod tests {
             translate_id("DeviceV2State")
         );
     }
+
+    #[test]
+    fn test_post_restore() {
+        MigrationManager::register_device_instance(
+            DeviceV1State::descriptor(),
+            Arc::new(Mutex::new(BrokenDevice::default())),
+            "broken_device",
+        );
+
+        let err = MigrationManager::post_restore().unwrap_err();
+        assert!(format!("{:?}", err).contains("DeviceV1State/broken_device"));
+
+        MigrationManager::unregister_device_instance(DeviceV1State::descriptor(), "broken_device");
+        assert!(MigrationManager::post_restore().is_ok());
+    }
+
+    #[test]
+    fn test_migration_blockers() {
+        assert!(MigrationManager::check_migration_blockers().is_ok());
+        MigrationManager::register_migration_blocker("vfio0", "passthrough device");
+        let err = MigrationManager::check_migration_blockers().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Migration is blocked by device vfio0: passthrough device"
+        );
+        MigrationManager::unregister_migration_blocker("vfio0");
+        assert!(MigrationManager::check_migration_blockers().is_ok());
+    }
+
+    #[test]
+    fn test_migration_stats() {
+        let mut stats = MigrationStats {
+            total_bytes: 0x10000,
+            remaining_bytes: 0x10000,
+            ..Default::default()
+        };
+        stats.update_sent(0x10000);
+        assert_eq!(stats.transferred_bytes, 0x10000);
+        assert_eq!(stats.remaining_bytes, 0);
+
+        stats.last_scan_time = Instant::now() - Duration::from_millis(500);
+        stats.update_dirty_scan(0x4000, 0x1000);
+        assert_eq!(stats.remaining_bytes, 0x4000);
+        assert!(stats.dirty_pages_rate > 0 && stats.dirty_pages_rate <= 8);
+
+        stats.update_sent(0x4000);
+        assert_eq!(stats.transferred_bytes, 0x14000);
+        assert_eq!(stats.remaining_bytes, 0);
+    }
+
+    #[test]
+    fn test_migration_bandwidth_throttle() {
+        let mut limit = MigrationLimit::default();
+        // Unlimited bandwidth.
+        assert!(limit.consume_bandwidth(u64::MAX).is_none());
+        assert_eq!(limit.bandwidth_tokens, 0);
+
+        limit.max_bandwidth = 1000;
+        limit.reset_bandwidth_tokens();
+        assert!(limit.consume_bandwidth(500).is_none());
+        assert_eq!(limit.bandwidth_tokens, -500);
+        let wait = limit.consume_bandwidth(500).unwrap();
+        assert!(wait <= Duration::from_millis(100));
+
+        // Tokens are refilled after three seconds, but no more than bandwidth.
+        limit.tokens_refill_time = Instant::now() - Duration::from_secs(3);
+        assert!(limit.consume_bandwidth(500).is_none());
+        assert_eq!(limit.bandwidth_tokens, 0);
+    }
 }
@@ -10,24 +10,34 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp::min;
 use std::collections::HashMap;
 use std::io::{Read, Write};
 use std::mem::size_of;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use kvm_bindings::kvm_userspace_memory_region as MemorySlot;
 use log::{info, warn};
 
+use crate::compress::{compress_block, decompress_block, DEFAULT_COMPRESS_LEVEL};
 use crate::general::Lifecycle;
-use crate::manager::MIGRATION_MANAGER;
-use crate::protocol::{MemBlock, MigrationStatus, Request, Response, TransStatus};
+use crate::manager::{MigrationStats, MIGRATION_MANAGER};
+use crate::protocol::{
+    MemBlock, MigrationPhase, MigrationStatus, Request, Response, TransStatus,
+    MIGRATION_CAP_COMPRESS_ZSTD,
+};
 use crate::{MigrationError, MigrationManager};
 use anyhow::{anyhow, bail, Context, Result};
 use hypervisor::kvm::KVM_FDS;
 use machine_manager::config::{get_pci_bdf, PciBdf, VmConfig};
 use util::unix::host_page_size;
 
+/// Max length of memory block in one sending, so that bandwidth throttle
+/// can work in a fine granularity.
+const MIGRATION_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
 impl MigrationManager {
     /// Start VM live migration at source VM.
     ///
@@ -40,6 +50,15 @@ impl MigrationManager {
     where
         T: Read + Write,
     {
+        // Reset statistics of the last migration.
+        *MIGRATION_MANAGER.stats.write().unwrap() = MigrationStats::default();
+        Self::set_phase(MigrationPhase::Setup);
+        MIGRATION_MANAGER
+            .limit
+            .write()
+            .unwrap()
+            .reset_bandwidth_tokens();
+
         // Activate the migration status of source and destination virtual machine.
         Self::active_migration(fd).with_context(|| "Failed to active migration")?;
 
@@ -53,6 +72,7 @@ impl MigrationManager {
         Self::send_vm_memory(fd).with_context(|| "Failed to send VM memory")?;
 
         // Iteratively send virtual machine dirty memory.
+        Self::set_phase(MigrationPhase::Iterative);
         let iterations = MIGRATION_MANAGER.limit.read().unwrap().max_dirty_iterations;
         for _ in 0..iterations {
             // Check the migration is active.
@@ -73,6 +93,7 @@ impl MigrationManager {
         }
 
         // Pause virtual machine.
+        Self::set_phase(MigrationPhase::StopAndCopy);
         Self::pause()?;
 
         // Send remaining virtual machine dirty memory.
@@ -109,7 +130,14 @@ impl MigrationManager {
         if request.status == TransStatus::Active {
             info!("Active the migration");
             Self::set_status(MigrationStatus::Active)?;
-            Response::send_msg(fd, TransStatus::Ok)?;
+            // Capabilities of source are in the length of request.
+            if request.length & MIGRATION_CAP_COMPRESS_ZSTD != 0 {
+                *MIGRATION_MANAGER.compress_level.write().unwrap() = Some(DEFAULT_COMPRESS_LEVEL);
+                Response::send_msg(fd, TransStatus::Compress)?;
+            } else {
+                *MIGRATION_MANAGER.compress_level.write().unwrap() = None;
+                Response::send_msg(fd, TransStatus::Ok)?;
+            }
         } else {
             Response::send_msg(fd, TransStatus::Error)?;
             return Err(anyhow!(MigrationError::MigrationStatusErr(
@@ -328,15 +356,19 @@ impl MigrationManager {
             )
         })?;
 
+        let compressed = MIGRATION_MANAGER.compress_level.read().unwrap().is_some();
         if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
             for block in blocks.iter() {
-                locked_memory.recv_memory(
-                    fd,
-                    MemBlock {
-                        gpa: block.gpa,
-                        len: block.len,
-                    },
-                )?;
+                let range = MemBlock {
+                    gpa: block.gpa,
+                    len: block.len,
+                };
+                if compressed {
+                    let data = decompress_block(fd, block.len as usize)?;
+                    locked_memory.recv_memory(&mut data.as_slice(), range)?;
+                } else {
+                    locked_memory.recv_memory(fd, range)?;
+                }
             }
         }
 
@@ -355,21 +387,31 @@ impl MigrationManager {
     where
         T: Read + Write,
     {
+        let blocks = Self::split_blocks(blocks);
         let len = size_of::<MemBlock>() * blocks.len();
+        let sent_bytes = blocks.iter().map(|block| block.len).sum();
         Request::send_msg(fd, TransStatus::Memory, len as u64)?;
         fd.write_all(unsafe {
             std::slice::from_raw_parts(blocks.as_ptr() as *const MemBlock as *const u8, len)
         })?;
 
+        let compress_level = *MIGRATION_MANAGER.compress_level.read().unwrap();
         if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
             for block in blocks.iter() {
-                locked_memory.send_memory(
-                    fd,
-                    MemBlock {
-                        gpa: block.gpa,
-                        len: block.len,
-                    },
-                )?;
+                let range = MemBlock {
+                    gpa: block.gpa,
+                    len: block.len,
+                };
+                if let Some(level) = compress_level {
+                    let mut data = Vec::with_capacity(block.len as usize);
+                    locked_memory.send_memory(&mut data, range)?;
+                    let encoded = compress_block(&data, level)?;
+                    Self::throttle_bandwidth(encoded.len() as u64);
+                    fd.write_all(&encoded)?;
+                } else {
+                    Self::throttle_bandwidth(block.len);
+                    locked_memory.send_memory(fd, range)?;
+                }
             }
         }
 
@@ -377,10 +419,57 @@ impl MigrationManager {
         if result.is_err() {
             return Err(anyhow!(MigrationError::ResponseErr));
         }
+        MIGRATION_MANAGER
+            .stats
+            .write()
+            .unwrap()
+            .update_sent(sent_bytes);
 
         Ok(())
     }
 
+    /// Split memory blocks into chunks no longer than `MIGRATION_CHUNK_SIZE`.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The memory blocks need to be sent.
+    fn split_blocks(blocks: Vec<MemBlock>) -> Vec<MemBlock> {
+        let mut chunks = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            let mut offset = 0;
+            while offset < block.len {
+                let len = min(MIGRATION_CHUNK_SIZE, block.len - offset);
+                chunks.push(MemBlock {
+                    gpa: block.gpa + offset,
+                    len,
+                });
+                offset += len;
+            }
+        }
+
+        chunks
+    }
+
+    /// Wait until the bandwidth throttle allows to send `len` bytes.
+    /// Bandwidth is read on every check, so it can be adjusted during migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - Bytes to be sent.
+    fn throttle_bandwidth(len: u64) {
+        loop {
+            let wait = MIGRATION_MANAGER
+                .limit
+                .write()
+                .unwrap()
+                .consume_bandwidth(len);
+            match wait {
+                Some(duration) => thread::sleep(duration),
+                None => break,
+            }
+        }
+    }
+
     /// Send entire VM memory data to destination VM.
     ///
     /// # Arguments
@@ -399,6 +488,15 @@ impl MigrationManager {
             });
         }
 
+        let total_bytes = blocks.iter().map(|block| block.len).sum();
+        {
+            let mut stats = MIGRATION_MANAGER.stats.write().unwrap();
+            stats.phase = MigrationPhase::Bulk;
+            stats.total_bytes = total_bytes;
+            stats.remaining_bytes = total_bytes;
+            stats.last_scan_time = Instant::now();
+        }
+
         Self::send_memory(fd, blocks)?;
 
         Ok(())
@@ -420,6 +518,13 @@ impl MigrationManager {
             blocks.extend(sub_blocks);
         }
 
+        let dirty_bytes = blocks.iter().map(|block| block.len).sum();
+        MIGRATION_MANAGER
+            .stats
+            .write()
+            .unwrap()
+            .update_dirty_scan(dirty_bytes, host_page_size());
+
         if blocks.is_empty() {
             return Ok(false);
         }
@@ -463,6 +568,7 @@ impl MigrationManager {
         let desc_db = Self::restore_desc_db(fd, header.desc_len)
             .with_context(|| "Failed to load device descriptor db")?;
         Self::restore_vmstate(desc_db, fd).with_context(|| "Failed to load snapshot device")?;
+        Self::post_restore()?;
         Self::resume()?;
 
         Response::send_msg(fd, TransStatus::Ok)?;
@@ -480,11 +586,30 @@ impl MigrationManager {
         T: Read + Write,
     {
         Self::set_status(MigrationStatus::Active)?;
-        Request::send_msg(fd, TransStatus::Active, 0)?;
+        let (compress, level) = {
+            let limit = MIGRATION_MANAGER.limit.read().unwrap();
+            (limit.compress, limit.compress_level)
+        };
+        let caps = if compress {
+            MIGRATION_CAP_COMPRESS_ZSTD
+        } else {
+            0
+        };
+        Request::send_msg(fd, TransStatus::Active, caps)?;
+
+        // Destination which doesn't support compression always responses `Ok`.
         let result = Response::recv_msg(fd)?;
-        if result.is_err() {
-            return Err(anyhow!(MigrationError::ResponseErr));
-        }
+        let compress_level = match result.status {
+            TransStatus::Compress if compress => Some(level),
+            TransStatus::Ok => {
+                if compress {
+                    warn!("Destination doesn't support compression, migrate uncompressed");
+                }
+                None
+            }
+            _ => return Err(anyhow!(MigrationError::ResponseErr)),
+        };
+        *MIGRATION_MANAGER.compress_level.write().unwrap() = compress_level;
 
         Ok(())
     }
@@ -563,8 +688,28 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Set the phase of current migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - The new phase of migration.
+    fn set_phase(phase: MigrationPhase) {
+        MIGRATION_MANAGER.stats.write().unwrap().phase = phase;
+    }
+
     /// Recover the virtual machine if migration is failed.
     pub fn recover_from_migration() -> Result<()> {
+        // Dirty log is still running if migration is aborted in the middle.
+        if !MIGRATION_MANAGER.vmm_bitmaps.read().unwrap().is_empty() {
+            if let Err(e) = Self::stop_dirty_log() {
+                warn!("Failed to stop logging dirty page: {:?}", e);
+            }
+        }
+
+        // VM is only paused in stop-and-copy phase, keep it running otherwise.
+        if MIGRATION_MANAGER.stats.read().unwrap().phase != MigrationPhase::StopAndCopy {
+            return Ok(());
+        }
         if let Some(locked_vm) = &MIGRATION_MANAGER.vmm.read().unwrap().vm {
             locked_vm.lock().unwrap().resume();
         }
@@ -49,6 +49,37 @@ pub enum MigrationStatus {
     Canceled,
 }
 
+/// Phase of live migration at source VM.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MigrationPhase {
+    /// No migration has been started.
+    None,
+    /// Migration is activated and VM config is being sent.
+    Setup,
+    /// The entire VM memory is being sent.
+    Bulk,
+    /// Dirty memory is being sent iteratively while VM is running.
+    Iterative,
+    /// VM is paused, the remaining dirty memory and devices state are being sent.
+    StopAndCopy,
+}
+
+impl std::fmt::Display for MigrationPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MigrationPhase::None => "none",
+                MigrationPhase::Setup => "setup",
+                MigrationPhase::Bulk => "bulk",
+                MigrationPhase::Iterative => "iterative",
+                MigrationPhase::StopAndCopy => "stop-and-copy",
+            }
+        )
+    }
+}
+
 impl std::fmt::Display for MigrationStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -131,8 +162,14 @@ pub enum TransStatus {
     Error,
     /// Unknown status in migration .
     Unknown,
+    /// Destination agrees to receive compressed memory data.
+    Compress,
 }
 
+/// Capability of migration: compress memory data by zstd. Capabilities are
+/// sent by source in the length of `Active` request.
+pub const MIGRATION_CAP_COMPRESS_ZSTD: u64 = 1;
+
 impl Default for TransStatus {
     fn default() -> Self {
         Self::Unknown
@@ -154,6 +191,7 @@ impl std::fmt::Display for TransStatus {
                 TransStatus::Ok => "Ok",
                 TransStatus::Error => "Error",
                 TransStatus::Unknown => "Unknown",
+                TransStatus::Compress => "Compress",
             }
         )
     }
@@ -129,6 +129,7 @@ impl MigrationManager {
                 .with_context(|| "Failed to load device descriptor db")?;
         Self::restore_vmstate(snapshot_desc_db, &mut device_state_file)
             .with_context(|| "Failed to load snapshot device state")?;
+        Self::post_restore()?;
         Self::resume()?;
 
         // Set status to `Completed`
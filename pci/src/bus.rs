@@ -15,12 +15,13 @@ use std::sync::{Arc, Mutex, Weak};
 
 use address_space::Region;
 use log::debug;
+use machine_manager::qmp::qmp_schema::{PciDeviceInfo, PciInfo};
 
 use super::config::{
     BRIDGE_CONTROL, BRIDGE_CTL_SEC_BUS_RESET, SECONDARY_BUS_NUM, SUBORDINATE_BUS_NUM,
 };
 use super::hotplug::HotplugOps;
-use super::PciDevOps;
+use super::{pci_func, pci_slot, PciDevOps};
 use anyhow::{bail, Context, Result};
 
 type DeviceBusInfo = (Arc<Mutex<PciBus>>, Arc<Mutex<dyn PciDevOps>>);
@@ -96,6 +97,26 @@ impl PciBus {
         None
     }
 
+    /// Attach the device to the bus, fails if the slot and function is already in use.
+    ///
+    /// # Arguments
+    ///
+    /// * `devfn` - Slot number << 3 | function number.
+    /// * `dev` - Device attached to the bus.
+    pub fn attach_device(&mut self, devfn: u8, dev: Arc<Mutex<dyn PciDevOps>>) -> Result<()> {
+        if let Some(used) = self.devices.get(&devfn) {
+            bail!(
+                "PCI slot {}:{:02x}.{:x} already in use by {}",
+                self.name,
+                pci_slot(devfn),
+                pci_func(devfn),
+                used.lock().unwrap().name()
+            );
+        }
+        self.devices.insert(devfn, dev);
+        Ok(())
+    }
+
     fn in_range(&self, bus_num: u8) -> bool {
         if self.is_during_reset() {
             return false;
@@ -149,6 +170,51 @@ impl PciBus {
         None
     }
 
+    /// Get the info of the bus and its devices, and then the child buses.
+    ///
+    /// # Arguments
+    ///
+    /// * `bus` - Bus to query.
+    /// * `bus_num` - Number of the bus.
+    /// * `info` - Info of the buses is appended to it.
+    pub fn query_info(bus: &Arc<Mutex<Self>>, bus_num: u8, info: &mut Vec<PciInfo>) {
+        // Unlock the bus before locking devices, as the bus number of the child bus is
+        // read from its bridge.
+        let locked_bus = bus.lock().unwrap();
+        let mut devices: Vec<(u8, Arc<Mutex<dyn PciDevOps>>)> = locked_bus
+            .devices
+            .iter()
+            .map(|(devfn, dev)| (*devfn, dev.clone()))
+            .collect();
+        let child_buses = locked_bus.child_buses.clone();
+        drop(locked_bus);
+        devices.sort_by_key(|(devfn, _)| *devfn);
+
+        let devices = devices
+            .iter()
+            .map(|(devfn, dev)| {
+                let locked_dev = dev.lock().unwrap();
+                let mut dev_info = locked_dev
+                    .pci_config()
+                    .map_or_else(PciDeviceInfo::default, |config| config.get_device_info());
+                dev_info.bus = bus_num;
+                dev_info.slot = pci_slot(*devfn);
+                dev_info.function = pci_func(*devfn);
+                dev_info.qdev_id = locked_dev.name();
+                dev_info
+            })
+            .collect();
+        info.push(PciInfo {
+            bus: bus_num,
+            devices,
+        });
+
+        for child_bus in child_buses {
+            let child_num = child_bus.lock().unwrap().number(SECONDARY_BUS_NUM as usize);
+            PciBus::query_info(&child_bus, child_num, info);
+        }
+    }
+
     /// Find the bus to which the device is attached.
     ///
     /// # Arguments
@@ -316,6 +382,10 @@ mod tests {
         fn devfn(&self) -> Option<u8> {
             Some(0)
         }
+
+        fn pci_config(&self) -> Option<&PciConfig> {
+            Some(&self.config)
+        }
     }
 
     pub fn create_pci_host() -> Arc<Mutex<PciHost>> {
@@ -379,6 +449,48 @@ mod tests {
         assert_eq!(dev.lock().unwrap().name(), "test2");
     }
 
+    #[test]
+    fn test_query_info() {
+        let pci_host = create_pci_host();
+        let locked_pci_host = pci_host.lock().unwrap();
+        let root_bus = Arc::downgrade(&locked_pci_host.root_bus);
+
+        let root_port = RootPort::new("pcie.1".to_string(), 8, 0, root_bus.clone(), false);
+        root_port.realize().unwrap();
+        let pci_dev = PciDevice {
+            name: String::from("test1"),
+            devfn: 10,
+            config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, 0),
+            parent_bus: root_bus,
+        };
+        pci_dev.realize().unwrap();
+        let bus = PciBus::find_bus_by_name(&locked_pci_host.root_bus, "pcie.1").unwrap();
+        let pci_dev = PciDevice {
+            name: String::from("test2"),
+            devfn: 0,
+            config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, 0),
+            parent_bus: Arc::downgrade(&bus),
+        };
+        pci_dev.realize().unwrap();
+
+        let mut info = Vec::new();
+        PciBus::query_info(&locked_pci_host.root_bus, 0, &mut info);
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0].devices.len(), 2);
+        assert_eq!(info[0].devices[0].qdev_id, "pcie.1");
+        assert!(info[0].devices[0].pci_bridge.is_some());
+        assert_eq!(info[0].devices[0].regions.len(), 1);
+        assert!(info[0].devices[0].msix.is_some());
+        assert_eq!(info[0].devices[1].qdev_id, "test1");
+        assert_eq!(
+            (info[0].devices[1].slot, info[0].devices[1].function),
+            (1, 2)
+        );
+        assert!(info[0].devices[1].pci_bridge.is_none());
+        assert_eq!(info[1].devices.len(), 1);
+        assert_eq!(info[1].devices[0].qdev_id, "test2");
+    }
+
     #[test]
     fn test_detach_device() {
         let pci_host = create_pci_host();
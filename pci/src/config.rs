@@ -15,6 +15,7 @@ use std::sync::{Arc, Mutex};
 
 use address_space::Region;
 use log::{error, warn};
+use machine_manager::qmp::qmp_schema::{PciBarInfo, PciBridgeInfo, PciDeviceInfo};
 
 use crate::msix::Msix;
 use crate::{
@@ -102,6 +103,7 @@ pub const IO_LIMIT: u8 = 0x1d;
 pub const PREF_MEM_BASE_UPPER: u8 = 0x28;
 const CAP_LIST: u8 = 0x34;
 const INTERRUPT_LINE: u8 = 0x3c;
+const INTERRUPT_PIN: u8 = 0x3d;
 pub const BRIDGE_CONTROL: u8 = 0x3e;
 
 const BRIDGE_CTL_PARITY_ENABLE: u16 = 0x0001;
@@ -693,6 +695,59 @@ impl PciConfig {
         }
     }
 
+    /// Get the device info from configuration space, such as IDs, BARs and MSI-X routing.
+    /// The location and name of the device are not filled in.
+    pub fn get_device_info(&self) -> PciDeviceInfo {
+        let mut regions = Vec::new();
+        for (id, bar) in self.bars.iter().enumerate() {
+            if bar.size == 0 {
+                continue;
+            }
+            let bar_type = match bar.region_type {
+                RegionType::Io => "io",
+                _ => "memory",
+            };
+            regions.push(PciBarInfo {
+                bar: id as u8,
+                bar_type: bar_type.to_string(),
+                address: self.get_bar_address(id),
+                size: bar.size,
+                mem_type_64: bar.region_type == RegionType::Mem64Bit,
+            });
+        }
+
+        let irq = if self.config[INTERRUPT_PIN as usize] != 0 {
+            Some(self.config[INTERRUPT_LINE as usize])
+        } else {
+            None
+        };
+        let header_type = self.config[HEADER_TYPE as usize] & !HEADER_TYPE_MULTIFUNC;
+        let pci_bridge = if header_type == HEADER_TYPE_BRIDGE {
+            Some(PciBridgeInfo {
+                secondary: self.config[SECONDARY_BUS_NUM as usize],
+                subordinate: self.config[SUBORDINATE_BUS_NUM as usize],
+            })
+        } else {
+            None
+        };
+
+        PciDeviceInfo {
+            class: le_read_u16(&self.config, SUB_CLASS_CODE as usize).unwrap(),
+            vendor_id: le_read_u16(&self.config, VENDOR_ID as usize).unwrap(),
+            device_id: le_read_u16(&self.config, DEVICE_ID as usize).unwrap(),
+            subsystem_vendor_id: le_read_u16(&self.config, SUBSYSTEM_VENDOR_ID).unwrap(),
+            subsystem_id: le_read_u16(&self.config, SUBSYSTEM_ID).unwrap(),
+            regions,
+            irq,
+            msix: self
+                .msix
+                .as_ref()
+                .map(|msix| msix.lock().unwrap().get_info()),
+            pci_bridge,
+            ..Default::default()
+        }
+    }
+
     /// Register a bar in PciConfig::bars.
     ///
     /// # Arguments
@@ -736,6 +791,9 @@ impl PciConfig {
         self.bars[id].region_type = region_type;
         self.bars[id].address = BAR_SPACE_UNMAPPED;
         self.bars[id].size = size;
+        if region.name().is_empty() {
+            region.set_name(&format!("pci-bar{}", id));
+        }
         self.bars[id].region = Some(region);
         Ok(())
     }
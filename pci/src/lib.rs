@@ -36,7 +36,7 @@ use std::{
 pub use anyhow::{bail, Result};
 use byteorder::{ByteOrder, LittleEndian};
 
-use crate::config::{HEADER_TYPE, HEADER_TYPE_MULTIFUNC, MAX_FUNC};
+use crate::config::{PciConfig, HEADER_TYPE, HEADER_TYPE_MULTIFUNC, MAX_FUNC};
 
 const BDF_FUNC_SHIFT: u8 = 3;
 
@@ -221,6 +221,11 @@ pub trait PciDevOps: Send + AsAny {
     fn get_dev_path(&self) -> Option<String> {
         None
     }
+
+    /// Get configuration space of the device, which is used to query the device info.
+    fn pci_config(&self) -> Option<&PciConfig> {
+        None
+    }
 }
 
 /// Init multifunction for pci devices.
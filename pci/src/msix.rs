@@ -18,6 +18,7 @@ use std::sync::{Arc, Mutex, Weak};
 use address_space::{GuestAddress, Region, RegionOps};
 use hypervisor::kvm::{MsiVector, KVM_FDS};
 use log::{error, warn};
+use machine_manager::qmp::qmp_schema::{PciMsixInfo, PciMsixRoute};
 use migration::{
     DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
 };
@@ -128,8 +129,8 @@ impl Msix {
     }
 
     pub fn reset(&mut self) {
-        self.table.resize_with(self.table.len(), || 0);
-        self.pba.resize_with(self.pba.len(), || 0);
+        self.table.fill(0);
+        self.pba.fill(0);
         self.func_masked = true;
         self.enabled = true;
         self.mask_all_vectors();
@@ -143,6 +144,26 @@ impl Msix {
         }
     }
 
+    /// Get the MSI-X state and the GSIs routed for the vectors.
+    pub fn get_info(&self) -> PciMsixInfo {
+        let mut routes: Vec<PciMsixRoute> = self
+            .gsi_msi_routes
+            .iter()
+            .map(|(vector, route)| PciMsixRoute {
+                vector: *vector,
+                gsi: route.gsi,
+            })
+            .collect();
+        routes.sort_by_key(|route| route.vector);
+
+        PciMsixInfo {
+            enabled: self.enabled,
+            masked: self.func_masked,
+            vectors: (self.table.len() / MSIX_TABLE_ENTRY_SIZE as usize) as u16,
+            routes,
+        }
+    }
+
     pub fn is_vector_masked(&self, vector: u16) -> bool {
         if !self.enabled || self.func_masked {
             return true;
@@ -390,6 +411,7 @@ impl Msix {
             write: Arc::new(table_write),
         };
         let table_region = Region::init_io_region(table_size, table_region_ops);
+        table_region.set_name("msix-table");
         region
             .add_subregion(table_region, table_offset)
             .with_context(|| "Failed to register MSI-X table region.")?;
@@ -414,6 +436,7 @@ impl Msix {
             write: Arc::new(pba_write),
         };
         let pba_region = Region::init_io_region(pba_size, pba_region_ops);
+        pba_region.set_name("msix-pba");
         region
             .add_subregion(pba_region, pba_offset)
             .with_context(|| "Failed to register MSI-X PBA region.")?;
@@ -820,6 +843,27 @@ mod tests {
         assert!(!msix.is_vector_pending(0));
     }
 
+    #[test]
+    fn test_reset() {
+        let mut msix = Msix::new(
+            MSIX_TABLE_ENTRY_SIZE as u32,
+            64,
+            64,
+            Arc::new(AtomicU16::new(0)),
+        );
+
+        le_write_u32(&mut msix.table, 0, 0xfee0_0000).unwrap();
+        msix.table[MSIX_TABLE_VEC_CTL as usize] &= !MSIX_TABLE_MASK_BIT;
+        msix.set_pending_vector(0);
+        msix.func_masked = false;
+
+        msix.reset();
+        assert_eq!(msix.get_message(0).address_lo, 0);
+        assert!(msix.table[MSIX_TABLE_VEC_CTL as usize] & MSIX_TABLE_MASK_BIT > 0);
+        assert!(!msix.is_vector_pending(0));
+        assert!(msix.is_vector_masked(0));
+    }
+
     #[test]
     fn test_get_message() {
         let mut msix = Msix::new(
@@ -356,21 +356,10 @@ impl PciDevOps for RootPort {
             Some(Arc::downgrade(&root_port) as Weak<Mutex<dyn PciDevOps>>);
         locked_root_port.sec_bus.lock().unwrap().hotplug_controller =
             Some(Arc::downgrade(&root_port) as Weak<Mutex<dyn HotplugOps>>);
-        let pci_device = locked_parent_bus.devices.get(&locked_root_port.devfn);
-        if pci_device.is_none() {
-            locked_parent_bus
-                .child_buses
-                .push(locked_root_port.sec_bus.clone());
-            locked_parent_bus
-                .devices
-                .insert(locked_root_port.devfn, root_port.clone());
-        } else {
-            bail!(
-                "Devfn {:?} has been used by {:?}",
-                locked_root_port.devfn,
-                pci_device.unwrap().lock().unwrap().name()
-            );
-        }
+        locked_parent_bus.attach_device(locked_root_port.devfn, root_port.clone())?;
+        locked_parent_bus
+            .child_buses
+            .push(locked_root_port.sec_bus.clone());
         // Need to drop locked_root_port in order to register root_port instance.
         drop(locked_root_port);
         MigrationManager::register_device_instance(RootPortState::descriptor(), root_port, &name);
@@ -439,6 +428,10 @@ impl PciDevOps for RootPort {
         self.name.clone()
     }
 
+    fn pci_config(&self) -> Option<&PciConfig> {
+        Some(&self.config)
+    }
+
     /// Only set slot status to on, and no other device reset actions are implemented.
     fn reset(&mut self, reset_child_device: bool) -> Result<()> {
         if reset_child_device {
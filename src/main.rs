@@ -12,16 +12,19 @@
 
 use std::io::Write;
 use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::{error, info};
-use machine::{LightMachine, MachineOps, StdMachine};
+use machine::{LightMachine, MachineOps, SeccompDevices, StdMachine};
 use machine_manager::{
-    cmdline::{check_api_channel, create_args_parser, create_vmconfig},
+    cmdline::{check_api_channel, create_args_parser, create_vmconfig, parse_control_socket},
     config::MachineType,
     config::VmConfig,
     event_loop::EventLoop,
+    machine::MachineExternalInterface,
     qmp::QmpChannel,
     signal_handler::{exit_with_code, register_kill_signal, VM_EXIT_GENE_ERR},
     socket::Socket,
@@ -29,6 +32,7 @@ use machine_manager::{
     test_server::TestSock,
 };
 use util::loop_context::EventNotifierHelper;
+use util::seccomp::SeccompOpt;
 use util::test_helper::{is_test_enabled, set_test_enabled};
 use util::{arg_parser, daemonize::daemonize, logger, set_termi_canon_mode};
 
@@ -94,9 +98,14 @@ fn run() -> Result<()> {
         set_test_enabled();
     }
 
+    let log_format = match cmd_args.value_of("log-format") {
+        Some(format) => logger::LogFormat::from_str(&format)
+            .map_err(|_| anyhow!("Invalid log format {}, text or json is expected", format))?,
+        None => logger::LogFormat::default(),
+    };
     if let Some(logfile_path) = cmd_args.value_of("display log") {
         if logfile_path.is_empty() {
-            logger::init_logger_with_env(Some(Box::new(std::io::stdout())))
+            logger::init_logger_with_env(Some(Box::new(std::io::stdout())), log_format)
                 .with_context(|| "Failed to init logger.")?;
         } else {
             let logfile = std::fs::OpenOptions::new()
@@ -107,7 +116,7 @@ fn run() -> Result<()> {
                 .mode(0o640)
                 .open(logfile_path)
                 .with_context(|| "Failed to open log file")?;
-            logger::init_logger_with_env(Some(Box::new(logfile)))
+            logger::init_logger_with_env(Some(Box::new(logfile)), log_format)
                 .with_context(|| "Failed to init logger.")?;
         }
     }
@@ -131,6 +140,14 @@ fn run() -> Result<()> {
     let mut vm_config: VmConfig = create_vmconfig(&cmd_args)?;
     info!("VmConfig is {:?}", vm_config);
 
+    if cmd_args.is_present("dry-run") {
+        vm_config
+            .validate()
+            .with_context(|| "VmConfig is invalid")?;
+        info!("VmConfig is valid, exit without creating VM");
+        return Ok(());
+    }
+
     match real_main(&cmd_args, &mut vm_config) {
         Ok(()) => {
             info!("MainLoop over, Vm exit");
@@ -187,6 +204,11 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             ));
             MachineOps::realize(&vm, vm_config).with_context(|| "Failed to realize micro VM.")?;
             EventLoop::set_manager(vm.clone(), None);
+            machine::register_vm_pause_event(vm.clone())
+                .with_context(|| "Failed to register pause event for micro VM.")?;
+            machine::register_hotplug_manifest(vm.clone(), vm_config.hotplug_devices.clone())
+                .with_context(|| "Failed to register hotplug manifest for micro VM.")?;
+            register_control_sockets(&vm, cmd_args)?;
 
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
@@ -200,6 +222,11 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             MachineOps::realize(&vm, vm_config)
                 .with_context(|| "Failed to realize standard VM.")?;
             EventLoop::set_manager(vm.clone(), None);
+            machine::register_vm_pause_event(vm.clone())
+                .with_context(|| "Failed to register pause event for standard VM.")?;
+            machine::register_hotplug_manifest(vm.clone(), vm_config.hotplug_devices.clone())
+                .with_context(|| "Failed to register hotplug manifest for standard VM.")?;
+            register_control_sockets(&vm, cmd_args)?;
 
             if is_test_enabled() {
                 let sock_path = cmd_args.value_of("mod-test");
@@ -244,14 +271,33 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
 
     machine::vm_run(&vm, cmd_args).with_context(|| "Failed to start VM.")?;
 
-    let balloon_switch_on = vm_config.dev_name.get("balloon").is_some();
+    let seccomp_devices = SeccompDevices::from_vm_config(vm_config);
+    let seccomp_opt = match cmd_args.value_of("seccomp-mode") {
+        Some(mode) => {
+            SeccompOpt::from_str(&mode).map_err(|_| anyhow!("Invalid seccomp mode {}", mode))?
+        }
+        None => SeccompOpt::Trap,
+    };
     if !cmd_args.is_present("disable-seccomp") {
         vm.lock()
             .unwrap()
-            .register_seccomp(balloon_switch_on)
+            .register_seccomp(seccomp_devices, seccomp_opt)
             .with_context(|| "Failed to register seccomp rules.")?;
     }
 
     EventLoop::loop_run().with_context(|| "MainLoop exits unexpectedly: error occurs")?;
     Ok(())
 }
+
+/// Register the extra QMP control sockets given by `-control-socket`.
+fn register_control_sockets<T>(vm: &Arc<Mutex<T>>, cmd_args: &arg_parser::ArgMatches) -> Result<()>
+where
+    T: MachineOps + MachineExternalInterface + Send + Sync + 'static,
+{
+    for config in cmd_args.values_of("control-socket").unwrap_or_default() {
+        let (path, read_only) = parse_control_socket(&config)?;
+        MachineOps::register_control_socket(vm, Path::new(&path), read_only)
+            .with_context(|| format!("Failed to register control socket {}", path))?;
+    }
+    Ok(())
+}
@@ -29,4 +29,6 @@ pub enum SysBusError {
         #[from]
         source: kvm_ioctls::Error,
     },
+    #[error("No free virtio-mmio slot, {0} slots have been used.")]
+    NoFreeMmioSlot(usize),
 }
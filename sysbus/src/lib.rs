@@ -12,12 +12,13 @@
 
 pub mod error;
 pub use error::SysBusError;
+use std::cmp::min;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
 use acpi::{AmlBuilder, AmlScope};
 use address_space::{AddressSpace, GuestAddress, Region, RegionIoEventFd, RegionOps};
-pub use anyhow::{bail, Context, Result};
+pub use anyhow::{anyhow, bail, Context, Result};
 use hypervisor::kvm::KVM_FDS;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -89,6 +90,46 @@ impl SysBus {
         }
     }
 
+    /// Get the number of free slots for the devices which occupy a mmio region
+    /// and an irq, such as virtio-mmio devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `region_size` - Size of the mmio region of one slot.
+    pub fn free_mmio_slots(&self, region_size: u64) -> usize {
+        let free_regions = self.mmio_region.1.saturating_sub(self.min_free_base) / region_size;
+        let free_irqs = (self.free_irqs.1 - self.min_free_irq + 1).max(0) as u64;
+        min(free_regions, free_irqs) as usize
+    }
+
+    /// Get the number of used slots for the devices which occupy a mmio region
+    /// and an irq, such as virtio-mmio devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `region_size` - Size of the mmio region of one slot.
+    pub fn used_mmio_slots(&self, region_size: u64) -> usize {
+        ((self.min_free_base - self.mmio_region.0) / region_size) as usize
+    }
+
+    /// Allocate a slot for the device which occupies a mmio region and an irq,
+    /// returns the base address of the mmio region. The irq is allocated when
+    /// the device sets its sys resource.
+    ///
+    /// # Arguments
+    ///
+    /// * `region_size` - Size of the mmio region of one slot.
+    pub fn alloc_mmio_slot(&mut self, region_size: u64) -> Result<u64> {
+        if self.free_mmio_slots(region_size) == 0 {
+            return Err(anyhow!(SysBusError::NoFreeMmioSlot(
+                self.used_mmio_slots(region_size)
+            )));
+        }
+        let region_base = self.min_free_base;
+        self.min_free_base += region_size;
+        Ok(region_base)
+    }
+
     pub fn build_region_ops<T: 'static + SysBusDevOps>(&self, dev: &Arc<Mutex<T>>) -> RegionOps {
         let cloned_dev = dev.clone();
         let read_ops = move |data: &mut [u8], addr: GuestAddress, offset: u64| -> bool {
@@ -106,6 +147,14 @@ impl SysBus {
         }
     }
 
+    /// Sync data cached by all devices to their backends.
+    pub fn flush(&self) -> Result<()> {
+        for dev in self.devices.iter() {
+            dev.lock().unwrap().flush()?;
+        }
+        Ok(())
+    }
+
     pub fn attach_device<T: 'static + SysBusDevOps>(
         &mut self,
         dev: &Arc<Mutex<T>>,
@@ -116,6 +165,7 @@ impl SysBus {
         let region = Region::init_io_region(region_size, region_ops);
         let locked_dev = dev.lock().unwrap();
 
+        region.set_name(&format!("{:?}", locked_dev.get_type()).to_lowercase());
         region.set_ioeventfds(&locked_dev.ioeventfds());
         match locked_dev.get_type() {
             SysBusDevType::Serial if cfg!(target_arch = "x86_64") => {
@@ -142,7 +192,7 @@ impl SysBus {
                         )
                     })?;
             }
-            SysBusDevType::Rtc if cfg!(target_arch = "x86_64") => {
+            SysBusDevType::Rtc | SysBusDevType::PvPanic if cfg!(target_arch = "x86_64") => {
                 #[cfg(target_arch = "x86_64")]
                 self.sys_io
                     .root()
@@ -197,7 +247,7 @@ impl Default for SysRes {
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq)]
 pub enum SysBusDevType {
     Serial,
     Rtc,
@@ -207,6 +257,7 @@ pub enum SysBusDevType {
     FwCfg,
     Flash,
     Ramfb,
+    PvPanic,
     Others,
 }
 
@@ -281,6 +332,11 @@ pub trait SysBusDevOps: Send + AmlBuilder {
     fn reset(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Sync data cached by device to its backend, e.g. before VM shuts down.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 impl AmlBuilder for SysBus {
@@ -293,3 +349,48 @@ impl AmlBuilder for SysBus {
         scope.aml_bytes()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_alloc_mmio_slot() {
+        let sys_mem = AddressSpace::new(Region::init_container_region(u64::max_value())).unwrap();
+        #[cfg(target_arch = "x86_64")]
+        let sys_io = AddressSpace::new(Region::init_container_region(1 << 16)).unwrap();
+        let region_size = 0x200;
+        // Mmio regions are enough for 4 slots, but there are only 3 irqs.
+        let mut sysbus = SysBus::new(
+            #[cfg(target_arch = "x86_64")]
+            &sys_io,
+            &sys_mem,
+            (IRQ_BASE, IRQ_BASE + 2),
+            (0x1000, 0x1000 + 4 * region_size),
+        );
+        assert_eq!(sysbus.free_mmio_slots(region_size), 3);
+        assert_eq!(sysbus.alloc_mmio_slot(region_size).unwrap(), 0x1000);
+        assert_eq!(sysbus.used_mmio_slots(region_size), 1);
+        // Irq is allocated by device, one slot is left.
+        sysbus.min_free_irq += 2;
+        assert_eq!(sysbus.free_mmio_slots(region_size), 1);
+        assert_eq!(sysbus.alloc_mmio_slot(region_size).unwrap(), 0x1200);
+        sysbus.min_free_irq += 1;
+        assert_eq!(sysbus.free_mmio_slots(region_size), 0);
+        assert!(sysbus.alloc_mmio_slot(region_size).is_err());
+        assert_eq!(sysbus.used_mmio_slots(region_size), 2);
+
+        // Mmio regions are exhausted before irqs.
+        let mut sysbus = SysBus::new(
+            #[cfg(target_arch = "x86_64")]
+            &sys_io,
+            &sys_mem,
+            (IRQ_BASE, IRQ_MAX),
+            (0x1000, 0x1000 + 2 * region_size),
+        );
+        assert_eq!(sysbus.free_mmio_slots(region_size), 2);
+        assert!(sysbus.alloc_mmio_slot(region_size).is_ok());
+        assert!(sysbus.alloc_mmio_slot(region_size).is_ok());
+        assert!(sysbus.alloc_mmio_slot(region_size).is_err());
+    }
+}
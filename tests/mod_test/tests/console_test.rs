@@ -20,11 +20,12 @@ use std::path::Path;
 use std::rc::Rc;
 use std::time;
 
+use mod_test::libdriver::machine::TestStdMachine;
 use mod_test::libdriver::malloc::GuestAllocator;
 use mod_test::libdriver::virtio::{TestVirtQueue, VirtioDeviceOps};
 use mod_test::libdriver::virtio_console::{create_console, ChardevType};
 use mod_test::libdriver::virtio_pci_modern::TestVirtioPciDev;
-use mod_test::libtest::TestState;
+use mod_test::libtest::{test_init, TestState};
 
 const TIMEOUT_US: u64 = 15 * 1000 * 1000;
 const ROWS_DEFAULT: u16 = 0;
@@ -34,6 +35,14 @@ const VIRTIO_CONSOLE_F_SIZE: u64 = 0;
 const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1;
 const VIRTIO_CONSOLE_F_EMERG_WRITE: u64 = 2;
 const BUFFER_LEN: usize = 96;
+/// Control events of multiport, refer to Virtio Spec.
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_PORT_ADD: u16 = 1;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+/// Size of the control message header: id(u32), event(u16), value(u16).
+const CTRL_MSG_LEN: usize = 8;
 
 fn console_setup(
     console: Rc<RefCell<TestVirtioPciDev>>,
@@ -509,3 +518,168 @@ fn console_parallel_req() {
     console.borrow_mut().destroy_device(alloc, vqs);
     test_state.borrow_mut().stop();
 }
+
+/// Send a control message to device by the control transmitq.
+fn send_ctrl_msg(
+    console: Rc<RefCell<TestVirtioPciDev>>,
+    test_state: Rc<RefCell<TestState>>,
+    alloc: Rc<RefCell<GuestAllocator>>,
+    ctrl_queue: Rc<RefCell<TestVirtQueue>>,
+    msg: (u32, u16, u16),
+) {
+    let (id, event, value) = msg;
+    let mut data = id.to_le_bytes().to_vec();
+    data.extend_from_slice(&event.to_le_bytes());
+    data.extend_from_slice(&value.to_le_bytes());
+
+    let addr = alloc.borrow_mut().alloc(CTRL_MSG_LEN as u64);
+    test_state.borrow().memwrite(addr, &data);
+    let free_head =
+        ctrl_queue
+            .borrow_mut()
+            .add(test_state.clone(), addr, CTRL_MSG_LEN as u32, false);
+    console
+        .borrow()
+        .kick_virtqueue(test_state.clone(), ctrl_queue.clone());
+    console.borrow().poll_used_elem(
+        test_state, ctrl_queue, free_head, TIMEOUT_US, &mut None, false,
+    );
+}
+
+/// Receive a control message from device by the control receiveq, return the message header
+/// and the extra data following it.
+fn recv_ctrl_msg(
+    console: Rc<RefCell<TestVirtioPciDev>>,
+    test_state: Rc<RefCell<TestState>>,
+    ctrl_queue: Rc<RefCell<TestVirtQueue>>,
+    (free_head, addr): (u32, u64),
+) -> ((u32, u16, u16), Vec<u8>) {
+    let mut len = Some(0);
+    console.borrow().poll_used_elem(
+        test_state.clone(),
+        ctrl_queue,
+        free_head,
+        TIMEOUT_US,
+        &mut len,
+        false,
+    );
+    let len = len.unwrap() as usize;
+    assert!(len >= CTRL_MSG_LEN);
+
+    let data = test_state.borrow().memread(addr, len as u64);
+    let id = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let event = u16::from_le_bytes(data[4..6].try_into().unwrap());
+    let value = u16::from_le_bytes(data[6..8].try_into().unwrap());
+    ((id, event, value), data[CTRL_MSG_LEN..].to_vec())
+}
+
+#[test]
+fn console_guest_agent_channel() {
+    let socket_path = "/tmp/test-guest-agent0.sock";
+    if Path::new(socket_path).exists() {
+        fs::remove_file(socket_path).unwrap();
+    }
+    // Guest agent channel takes the first free slot after the explicit console at slot 1.
+    let args = format!(
+        "-machine virt -device virtio-serial-pci,id=serial0,bus=pcie.0,addr=0x1.0 \
+         -chardev pty,id=charconsole0 -device virtconsole,chardev=charconsole0,id=console0 \
+         -guest-agent {}",
+        socket_path
+    );
+    let test_state = Rc::new(RefCell::new(test_init(args.split(' ').collect())));
+    let machine = TestStdMachine::new(test_state.clone());
+    let alloc = machine.allocator.clone();
+    let agent = Rc::new(RefCell::new(TestVirtioPciDev::new(machine.pci_bus)));
+    agent.borrow_mut().init(0x2, 0x0);
+
+    // The named port is announced to guest by multiport.
+    let features = agent.borrow().get_device_features();
+    assert_ne!(features & (1 << VIRTIO_CONSOLE_F_MULTIPORT), 0);
+    let vqs = agent
+        .borrow_mut()
+        .init_device(test_state.clone(), alloc.clone(), features, 4);
+    let output_queue = vqs[1].clone();
+    let ctrl_input_queue = vqs[2].clone();
+    let ctrl_output_queue = vqs[3].clone();
+
+    // Buffers for PORT_ADD, PORT_NAME and PORT_OPEN from device.
+    let mut ctrl_bufs = Vec::new();
+    for _ in 0..3 {
+        let addr = alloc.borrow_mut().alloc(BUFFER_LEN as u64);
+        let free_head =
+            ctrl_input_queue
+                .borrow_mut()
+                .add(test_state.clone(), addr, BUFFER_LEN as u32, true);
+        ctrl_bufs.push((free_head, addr));
+    }
+    agent
+        .borrow()
+        .kick_virtqueue(test_state.clone(), ctrl_input_queue.clone());
+
+    send_ctrl_msg(
+        agent.clone(),
+        test_state.clone(),
+        alloc.clone(),
+        ctrl_output_queue.clone(),
+        (!0, VIRTIO_CONSOLE_DEVICE_READY, 1),
+    );
+    let (msg, _) = recv_ctrl_msg(
+        agent.clone(),
+        test_state.clone(),
+        ctrl_input_queue.clone(),
+        ctrl_bufs[0],
+    );
+    assert_eq!(msg, (0, VIRTIO_CONSOLE_PORT_ADD, 1));
+
+    send_ctrl_msg(
+        agent.clone(),
+        test_state.clone(),
+        alloc.clone(),
+        ctrl_output_queue.clone(),
+        (0, VIRTIO_CONSOLE_PORT_READY, 1),
+    );
+    let (msg, name) = recv_ctrl_msg(
+        agent.clone(),
+        test_state.clone(),
+        ctrl_input_queue.clone(),
+        ctrl_bufs[1],
+    );
+    assert_eq!(msg, (0, VIRTIO_CONSOLE_PORT_NAME, 1));
+    assert_eq!(String::from_utf8(name).unwrap(), "org.qemu.guest_agent.0");
+    let (msg, _) = recv_ctrl_msg(
+        agent.clone(),
+        test_state.clone(),
+        ctrl_input_queue.clone(),
+        ctrl_bufs[2],
+    );
+    assert_eq!(msg, (0, VIRTIO_CONSOLE_PORT_OPEN, 1));
+
+    let mut stream = UnixStream::connect(socket_path).expect("Couldn't connect socket");
+    let test_data = String::from("Test\n");
+    let addr = alloc.borrow_mut().alloc(test_data.len() as u64);
+    test_state.borrow().memwrite(addr, test_data.as_bytes());
+    let free_head = output_queue.borrow_mut().add(
+        test_state.clone(),
+        addr,
+        test_data.len().try_into().unwrap(),
+        false,
+    );
+    agent
+        .borrow()
+        .kick_virtqueue(test_state.clone(), output_queue.clone());
+    agent.borrow().poll_used_elem(
+        test_state.clone(),
+        output_queue.clone(),
+        free_head,
+        TIMEOUT_US,
+        &mut None,
+        false,
+    );
+    verify_input_data(&mut stream, &test_data);
+
+    stream
+        .shutdown(Shutdown::Both)
+        .expect("shutdown function failed");
+    agent.borrow_mut().destroy_device(alloc, vqs);
+    test_state.borrow_mut().stop();
+}
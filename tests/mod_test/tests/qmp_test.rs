@@ -0,0 +1,116 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use mod_test::libtest::test_init;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn read_json(reader: &mut BufReader<UnixStream>) -> Value {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+fn execute(reader: &mut BufReader<UnixStream>, cmd: &str) -> Value {
+    reader
+        .get_mut()
+        .write_all(format!("{{\"execute\": \"{}\", \"id\": \"{}\"}}\n", cmd, cmd).as_bytes())
+        .unwrap();
+    read_json(reader)
+}
+
+fn query_status(reader: &mut BufReader<UnixStream>) -> Value {
+    execute(reader, "query-status")
+}
+
+fn connect_control_socket(socket_path: &str) -> BufReader<UnixStream> {
+    let client = UnixStream::connect(socket_path).expect("Couldn't connect control socket");
+    client.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut client = BufReader::new(client);
+    assert!(read_json(&mut client).get("QMP").is_some());
+    client
+}
+
+#[test]
+fn qmp_control_socket() {
+    let socket_path = "/tmp/test-control0.sock";
+    if Path::new(socket_path).exists() {
+        fs::remove_file(socket_path).unwrap();
+    }
+    let mut test_state = test_init(vec!["-machine", "virt", "-control-socket", socket_path]);
+
+    // Commands are handled by the same QMP handler as `-qmp`.
+    let mut client = connect_control_socket(socket_path);
+    let resp = query_status(&mut client);
+    assert!(resp["return"]["status"].is_string());
+
+    // The second client is not served until the first one hangs up.
+    let waiter = UnixStream::connect(socket_path).expect("Couldn't connect control socket");
+    waiter
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    let mut waiter = BufReader::new(waiter);
+    let mut line = String::new();
+    assert!(waiter.read_line(&mut line).is_err());
+
+    client.get_ref().shutdown(Shutdown::Both).unwrap();
+    drop(client);
+    waiter.get_ref().set_read_timeout(Some(TIMEOUT)).unwrap();
+    assert!(read_json(&mut waiter).get("QMP").is_some());
+    let resp = query_status(&mut waiter);
+    assert!(resp["return"]["status"].is_string());
+
+    // Async events are sent to the client of `-qmp` only, even after a control socket
+    // client hung up.
+    let resp = execute(&mut waiter, "stop");
+    assert!(resp.get("event").is_none());
+    assert_eq!(resp["id"], "stop");
+    assert_eq!(test_state.wait_qmp_event()["event"], "STOP");
+
+    test_state.stop();
+}
+
+#[test]
+fn qmp_control_socket_readonly() {
+    let socket_path = "/tmp/test-control1.sock";
+    if Path::new(socket_path).exists() {
+        fs::remove_file(socket_path).unwrap();
+    }
+    let socket_arg = format!("{},readonly=on", socket_path);
+    let mut test_state = test_init(vec!["-machine", "virt", "-control-socket", &socket_arg]);
+
+    let mut client = connect_control_socket(socket_path);
+    let status = query_status(&mut client)["return"]["status"].clone();
+    assert!(status.is_string());
+
+    // Only `query-*` commands are accepted.
+    for cmd in ["stop", "quit"] {
+        let resp = execute(&mut client, cmd);
+        assert_eq!(resp["id"], cmd);
+        assert!(resp["error"]["desc"]
+            .as_str()
+            .unwrap()
+            .contains("read-only"));
+    }
+    assert_eq!(query_status(&mut client)["return"]["status"], status);
+
+    test_state.stop();
+}
@@ -146,6 +146,7 @@ impl VirtioScsiTest {
             direct: false,
             aio: TestAioType::AioOff,
             serial: Some(DEFAULT_SCSI_SERIAL.to_string()),
+            iops: None,
         }];
 
         let (cntlr, state, alloc) = scsi_test_init(cntlrcfg, scsi_devices.clone());
@@ -524,6 +525,7 @@ struct ScsiDeviceConfig {
     direct: bool,
     aio: TestAioType,
     serial: Option<String>,
+    iops: Option<u64>,
 }
 
 impl ScsiDeviceConfig {
@@ -540,9 +542,21 @@ impl ScsiDeviceConfig {
             self.lun, serial_args,
         );
 
+        let iops_args = if let Some(iops) = self.iops {
+            format!(",throttling.iops-total={}", iops)
+        } else {
+            "".to_string()
+        };
+
         let drive_args = format!(
-            "-drive file={},id=drive-scsi0-0-{}-{},direct={},readonly={},aio={}",
-            self.image_path, self.target, self.lun, self.direct, self.read_only, self.aio,
+            "-drive file={},id=drive-scsi0-0-{}-{},direct={},readonly={},aio={}{}",
+            self.image_path,
+            self.target,
+            self.lun,
+            self.direct,
+            self.read_only,
+            self.aio,
+            iops_args,
         );
 
         format!("{} {} ", device_args, drive_args)
@@ -1827,6 +1841,7 @@ fn aio_model_test() {
             direct: false,
             aio: TestAioType::AioIOUring,
             serial: None,
+            iops: None,
         });
 
         // Scsi Disk 2. AIO io_uring. Direct true.
@@ -1842,6 +1857,7 @@ fn aio_model_test() {
             direct: true,
             aio: TestAioType::AioIOUring,
             serial: None,
+            iops: None,
         });
     }
 
@@ -1861,6 +1877,7 @@ fn aio_model_test() {
         direct: false,
         aio: TestAioType::AioOff,
         serial: None,
+        iops: None,
     });
     // Scsi Disk 5. AIO native. Direct false. This is not allowed.
     // Stratovirt will report "native aio type should be used with direct on"
@@ -1879,6 +1896,7 @@ fn aio_model_test() {
             direct: true,
             aio: TestAioType::AioNative,
             serial: None,
+            iops: None,
         });
     }
 
@@ -1904,6 +1922,132 @@ fn aio_model_test() {
     vst.testcase_tear_down();
 }
 
+/// Virtio Scsi hard disk IO limits test, configured as 'throttling.iops-total=1'.
+/// TestStep:
+///   1. Init a scsi harddisk configured as 'throttling.iops-total=1'.
+///   2. Send READ_10 requests in one kick, the last one is throttled.
+///   3. Step the clock until the throttled request is completed.
+///   4. Destroy device.
+/// Expect:
+///   1/2/3/4: success.
+///   2: The last request is not completed before the clock is stepped.
+///   3: The last request is completed with VIRTIO_SCSI_S_OK.
+#[test]
+fn iops_test() {
+    let cntlrcfg = CntlrConfig {
+        id: 0,
+        use_iothread: false,
+    };
+    let target = 0x1;
+    let lun = 0x2;
+    let image_path = Rc::new(create_img(TEST_IMAGE_SIZE, 0));
+    let device_vec = vec![ScsiDeviceConfig {
+        cntlr_id: 0,
+        device_type: ScsiDeviceType::ScsiHd,
+        image_path: image_path.clone(),
+        target,
+        lun,
+        read_only: false,
+        direct: false,
+        aio: TestAioType::AioOff,
+        serial: None,
+        iops: Some(1),
+    }];
+
+    let (cntlr, state, alloc) = scsi_test_init(cntlrcfg, device_vec.clone());
+    let features = virtio_scsi_defalut_feature(cntlr.clone());
+    let queues = cntlr
+        .borrow_mut()
+        .init_device(state.clone(), alloc.clone(), features, 3);
+
+    let mut vst = VirtioScsiTest {
+        cntlr,
+        scsi_devices: device_vec,
+        state,
+        alloc,
+        queues,
+    };
+
+    let mut read_cdb = [0_u8; TEST_VIRTIO_SCSI_CDB_SIZE];
+    read_cdb[0] = READ_10;
+    read_cdb[8] = 0x1; // 1 sector.
+    let virtqueue = vst.queues[2].clone();
+    let cmdreq_len = size_of::<TestVirtioScsiCmdReq>() as u64;
+    let cmdresp_len = size_of::<TestVirtioScsiCmdResp>() as u64;
+    let mut free_head = 0_u32;
+    let mut resp_addr = 0_u64;
+
+    // The leak bucket allows the IO operations of one second before it is full.
+    for _ in 0..3 {
+        let req = TestVirtioScsiCmdReq::new(target, lun, read_cdb);
+        let req_addr = vst.alloc.borrow_mut().alloc(cmdreq_len);
+        vst.state.borrow().memwrite(req_addr, req.as_bytes());
+        resp_addr = vst.alloc.borrow_mut().alloc(cmdresp_len + 512);
+        let resp = TestVirtioScsiCmdResp {
+            response: VIRTIO_SCSI_S_FAILURE,
+            ..TestVirtioScsiCmdResp::default()
+        };
+        vst.state.borrow().memwrite(resp_addr, resp.as_bytes());
+
+        let data_entries = vec![
+            TestVringDescEntry {
+                data: req_addr,
+                len: cmdreq_len as u32,
+                write: false,
+            },
+            TestVringDescEntry {
+                data: resp_addr,
+                len: cmdresp_len as u32,
+                write: true,
+            },
+            TestVringDescEntry {
+                data: resp_addr + cmdresp_len,
+                len: 512,
+                write: true,
+            },
+        ];
+        free_head = virtqueue
+            .borrow_mut()
+            .add_chained(vst.state.clone(), data_entries);
+    }
+    vst.cntlr
+        .borrow()
+        .kick_virtqueue(vst.state.clone(), virtqueue.clone());
+
+    loop {
+        vst.state.borrow().clock_step_ns(100);
+
+        if vst.cntlr.borrow().queue_was_notified(virtqueue.clone())
+            && virtqueue.borrow_mut().get_buf(vst.state.clone())
+        {
+            assert!(!virtqueue.borrow().desc_len.contains_key(&free_head));
+            break;
+        }
+    }
+
+    let response_addr = resp_addr + offset_of!(TestVirtioScsiCmdResp, response) as u64;
+    assert_eq!(
+        vst.state.borrow().readb(response_addr),
+        VIRTIO_SCSI_S_FAILURE
+    );
+
+    let time_out = time::Instant::now() + time::Duration::from_micros(TIMEOUT_US);
+    loop {
+        vst.state.borrow().clock_step();
+
+        if vst.cntlr.borrow().queue_was_notified(virtqueue.clone())
+            && virtqueue.borrow_mut().get_buf(vst.state.clone())
+            && virtqueue.borrow().desc_len.contains_key(&free_head)
+        {
+            break;
+        }
+        assert!(time::Instant::now() <= time_out);
+    }
+    assert_eq!(vst.state.borrow().readb(response_addr), VIRTIO_SCSI_S_OK);
+
+    vst.testcase_tear_down();
+}
+
 /// Virtio Scsi random CDB test.
 /// TestStep:
 ///   1. Init process.
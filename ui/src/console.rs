@@ -11,10 +11,11 @@
 // See the Mulan PSL v2 for more details.
 
 use crate::pixman::{
-    create_pixman_image, get_image_height, get_image_width, pixman_glyph_from_vgafont,
-    pixman_glyph_render, unref_pixman_image, ColorNames, COLOR_TABLE_RGB,
+    create_pixman_image, get_image_data, get_image_height, get_image_width,
+    pixman_glyph_from_vgafont, pixman_glyph_render, pixman_image_linebuf_create,
+    pixman_image_linebuf_fill, unref_pixman_image, ColorNames, COLOR_TABLE_RGB,
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::error;
 use machine_manager::event_loop::EventLoop;
 use once_cell::sync::Lazy;
@@ -133,6 +134,8 @@ pub struct DisplayConsole {
     pub width: i32,
     pub height: i32,
     pub surface: Option<DisplaySurface>,
+    /// Whether the surface is set by guest, otherwise it's a place holder message.
+    pub active: bool,
     pub console_list: Weak<Mutex<ConsoleList>>,
     dev_opts: Arc<dyn HardWareOperations>,
 }
@@ -149,6 +152,7 @@ impl DisplayConsole {
             height: DEFAULT_SURFACE_HEIGHT,
             console_list,
             surface: None,
+            active: false,
             dev_opts,
         }
     }
@@ -284,6 +288,7 @@ pub fn display_replace_surface(
 
     let mut locked_con = con.lock().unwrap();
     let old_surface = locked_con.surface;
+    locked_con.active = surface.is_some();
     if surface.is_none() {
         // Create a place holder message.
         locked_con.surface = create_msg_surface(
@@ -612,6 +617,59 @@ pub fn console_select(con_id: Option<usize>) -> Result<()> {
     display_graphic_update(&Some(Arc::downgrade(&con)), 0, 0, width, height)
 }
 
+/// Save the image of the activate console to a file in PPM format.
+///
+/// # Arguments
+///
+/// * `filename` - Path of the image file.
+pub fn console_screendump(filename: &str) -> Result<()> {
+    let console = CONSOLES.lock().unwrap().get_console_by_id(None);
+    let con = match console {
+        Some(c) => c,
+        None => bail!("This VM has no graphic display device"),
+    };
+    let locked_con = con.lock().unwrap();
+    let surface = match locked_con.surface {
+        Some(s) if locked_con.active => s,
+        _ => bail!("Display is not active"),
+    };
+    // The image is converted with the console locked, so it's not released by guest.
+    let ppm = image_to_ppm(surface.image)?;
+    drop(locked_con);
+
+    if let Err(e) = std::fs::write(filename, ppm) {
+        bail!("Failed to write image file {}: {}", filename, e);
+    }
+    Ok(())
+}
+
+/// Convert the image to PPM format. Each line of image is converted to x8r8g8b8 format
+/// through a line buffer, so that images of any pixel format are supported.
+fn image_to_ppm(image: *mut pixman_image_t) -> Result<Vec<u8>> {
+    let width = get_image_width(image);
+    let height = get_image_height(image);
+    if width <= 0 || height <= 0 {
+        bail!("Invalid size of image: {}x{}", width, height);
+    }
+    let line_buf = pixman_image_linebuf_create(pixman_format_code_t::PIXMAN_x8r8g8b8, width);
+    if line_buf.is_null() {
+        bail!("Failed to create line buffer for image");
+    }
+
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    ppm.reserve(width as usize * height as usize * 3);
+    for y in 0..height {
+        pixman_image_linebuf_fill(line_buf, image, width, 0, y);
+        // SAFETY: The line buffer is a valid image with `width` pixels of 4 bytes.
+        let line = unsafe { std::slice::from_raw_parts(get_image_data(line_buf), width as usize) };
+        for pixel in line {
+            ppm.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+        }
+    }
+    unref_pixman_image(line_buf);
+    Ok(ppm)
+}
+
 /// Create a default image to display messages.
 ///
 /// # Arguments
@@ -681,6 +739,25 @@ mod tests {
     struct HwOpts {}
     impl HardWareOperations for HwOpts {}
 
+    #[test]
+    fn test_image_to_ppm() {
+        // Red and blue pixels in a8b8g8r8 format.
+        let mut data: Vec<u32> = vec![0xff00_00ff, 0xffff_0000];
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_a8b8g8r8,
+            2,
+            1,
+            data.as_mut_ptr(),
+            8,
+        );
+        assert!(!image.is_null());
+        let ppm = image_to_ppm(image).unwrap();
+        assert_eq!(ppm, b"P6\n2 1\n255\n\xff\x00\x00\x00\x00\xff".to_vec());
+        unref_pixman_image(image);
+
+        assert!(image_to_ppm(ptr::null_mut()).is_err());
+    }
+
     #[test]
     fn test_console_select() {
         let con_opts = Arc::new(HwOpts {});
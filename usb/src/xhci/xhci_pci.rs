@@ -235,16 +235,7 @@ impl PciDevOps for XhciPciDevice {
         // Attach to the PCI bus.
         let pci_bus = dev.lock().unwrap().parent_bus.upgrade().unwrap();
         let mut locked_pci_bus = pci_bus.lock().unwrap();
-        let pci_device = locked_pci_bus.devices.get(&devfn);
-        if pci_device.is_none() {
-            locked_pci_bus.devices.insert(devfn, dev);
-        } else {
-            bail!(
-                "Devfn {:?} has been used by {:?}",
-                &devfn,
-                pci_device.unwrap().lock().unwrap().name()
-            );
-        }
+        locked_pci_bus.attach_device(devfn, dev)?;
         Ok(())
     }
 
@@ -279,6 +270,10 @@ impl PciDevOps for XhciPciDevice {
         self.name.clone()
     }
 
+    fn pci_config(&self) -> Option<&PciConfig> {
+        Some(&self.pci_config)
+    }
+
     fn reset(&mut self, _reset_child_device: bool) -> pci::Result<()> {
         self.xhci.lock().unwrap().reset();
 
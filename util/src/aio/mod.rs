@@ -27,6 +27,7 @@ use vmm_sys_util::eventfd::EventFd;
 
 use super::link_list::{List, Node};
 use crate::num_ops::{round_down, round_up};
+use crate::seccomp::BpfRule;
 use crate::unix::host_page_size;
 use anyhow::{anyhow, bail, Context, Result};
 use libaio::LibaioContext;
@@ -91,8 +92,13 @@ pub enum OpCode {
     Preadv = 1,
     Pwritev = 2,
     Fdsync = 3,
+    /// Deallocate the range `[offset, offset + nbytes)`.
+    Discard = 4,
+    /// Zero the range `[offset, offset + nbytes)`.
+    WriteZeroes = 5,
 }
 
+#[derive(Clone)]
 pub struct AioCb<T: Clone> {
     pub direct: bool,
     pub req_align: u32,
@@ -106,6 +112,8 @@ pub struct AioCb<T: Clone> {
     pub iocompletecb: T,
 }
 
+/// Callback of completed request. The result is the number of bytes transferred
+/// on success, or the negative errno on failure.
 pub type AioCompleteFunc<T> = fn(&AioCb<T>, i64) -> Result<()>;
 
 pub struct Aio<T: Clone + 'static> {
@@ -133,6 +141,15 @@ pub fn aio_probe(engine: AioEngine) -> Result<()> {
     Ok(())
 }
 
+/// Create a syscall bpf rule for aio engine `io_uring`.
+pub fn io_uring_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
+    syscall_allow_list.extend(vec![
+        BpfRule::new(libc::SYS_io_uring_setup),
+        BpfRule::new(libc::SYS_io_uring_register),
+        BpfRule::new(libc::SYS_io_uring_enter),
+    ])
+}
+
 impl<T: Clone + 'static> Aio<T> {
     pub fn new(func: Arc<AioCompleteFunc<T>>, engine: AioEngine) -> Result<Self> {
         let max_events: usize = 128;
@@ -201,6 +218,7 @@ impl<T: Clone + 'static> Aio<T> {
                     self.flush_sync(cb)
                 }
             }
+            OpCode::Discard | OpCode::WriteZeroes => self.fallocate_sync(cb),
             OpCode::Noop => Err(anyhow!("Aio opcode is not specified.")),
         }
     }
@@ -231,7 +249,7 @@ impl<T: Clone + 'static> Aio<T> {
                         "Async IO request failed, status {} res {}",
                         evt.status, evt.res
                     );
-                    -1
+                    aio_error_code(&(*node).value, evt.res)
                 };
 
                 (self.complete_func)(&(*node).value, res)?;
@@ -317,7 +335,7 @@ impl<T: Clone + 'static> Aio<T> {
             error!("Failed to do sync read/write.");
         } else if ret as u64 != cb.nbytes {
             error!("Incomplete sync read/write.");
-            ret = -1;
+            ret = aio_error_code(&cb, ret);
         }
         (self.complete_func)(&cb, ret)
     }
@@ -488,6 +506,31 @@ impl<T: Clone + 'static> Aio<T> {
         }
         (self.complete_func)(&cb, ret)
     }
+
+    // Discard and write zeroes requests are not submitted to the aio engines,
+    // they are always handled synchronously by fallocate.
+    fn fallocate_sync(&mut self, cb: AioCb<T>) -> Result<()> {
+        let ret = match cb.opcode {
+            OpCode::Discard => raw_discard(cb.file_fd, cb.offset, cb.nbytes),
+            _ => raw_write_zeroes(cb.file_fd, cb.offset, cb.nbytes),
+        };
+        if ret < 0 {
+            error!("Failed to do sync discard/write zeroes.");
+        }
+        (self.complete_func)(&cb, ret)
+    }
+}
+
+/// Convert the result of a failed request to negative errno.
+/// Incomplete write means there is no space left on the device.
+fn aio_error_code<T: Clone>(cb: &AioCb<T>, res: i64) -> i64 {
+    if res < 0 {
+        res
+    } else if cb.opcode == OpCode::Pwritev {
+        -(libc::ENOSPC as i64)
+    } else {
+        -(libc::EIO as i64)
+    }
 }
 
 pub fn mem_from_buf(buf: &[u8], hva: u64) -> Result<()> {
@@ -11,10 +11,16 @@
 // See the Mulan PSL v2 for more details.
 
 use super::Iovec;
-use libc::{c_int, c_void, fdatasync, iovec, off_t, pread, preadv, pwrite, pwritev, size_t};
+use libc::{
+    c_int, c_void, fallocate, fdatasync, iovec, off_t, pread, preadv, pwrite, pwritev, size_t,
+    FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FALLOC_FL_ZERO_RANGE,
+};
 use log::error;
 use std::os::unix::io::RawFd;
 
+// The raw IO functions return the number of bytes transferred (or 0) on success,
+// and the negative errno on failure.
+
 pub fn raw_read(fd: RawFd, buf: u64, size: usize, offset: usize) -> i64 {
     let mut ret;
     loop {
@@ -32,13 +38,12 @@ pub fn raw_read(fd: RawFd, buf: u64, size: usize, offset: usize) -> i64 {
         }
     }
     if ret < 0 {
+        let err = errno::errno().0;
         error!(
             "Failed to pread: buf{}, size{}, offset{}, errno{}.",
-            buf,
-            size,
-            offset,
-            errno::errno().0
+            buf, size, offset, err
         );
+        ret = -(err as i64);
     }
     ret
 }
@@ -60,11 +65,9 @@ pub fn raw_readv(fd: RawFd, iovec: &[Iovec], offset: usize) -> i64 {
         }
     }
     if ret < 0 {
-        error!(
-            "Failed to preadv: offset{}, errno{}.",
-            offset,
-            errno::errno().0,
-        );
+        let err = errno::errno().0;
+        error!("Failed to preadv: offset{}, errno{}.", offset, err);
+        ret = -(err as i64);
     }
     ret
 }
@@ -86,13 +89,12 @@ pub fn raw_write(fd: RawFd, buf: u64, size: usize, offset: usize) -> i64 {
         }
     }
     if ret < 0 {
+        let err = errno::errno().0;
         error!(
             "Failed to pwrite: buf{}, size{}, offset{}, errno{}.",
-            buf,
-            size,
-            offset,
-            errno::errno().0,
+            buf, size, offset, err
         );
+        ret = -(err as i64);
     }
     ret
 }
@@ -114,20 +116,50 @@ pub fn raw_writev(fd: RawFd, iovec: &[Iovec], offset: usize) -> i64 {
         }
     }
     if ret < 0 {
+        let err = errno::errno().0;
+        error!("Failed to pwritev: offset{}, errno{}.", offset, err);
+        ret = -(err as i64);
+    }
+    ret
+}
+
+fn raw_fallocate(fd: RawFd, mode: c_int, offset: usize, size: u64) -> i64 {
+    let mut ret;
+    loop {
+        // SAFETY: fd is valid.
+        ret = unsafe { i64::from(fallocate(fd, mode, offset as off_t, size as off_t)) };
+        if !(ret < 0 && errno::errno().0 == libc::EINTR) {
+            break;
+        }
+    }
+    if ret < 0 {
+        let err = errno::errno().0;
         error!(
-            "Failed to pwritev: offset{}, errno{}.",
-            offset,
-            errno::errno().0,
+            "Failed to fallocate: mode{}, offset{}, size{}, errno{}.",
+            mode, offset, size, err
         );
+        ret = -(err as i64);
     }
     ret
 }
 
+/// Deallocate the space of the range, subsequent reads of the range return zeroes.
+pub fn raw_discard(fd: RawFd, offset: usize, size: u64) -> i64 {
+    raw_fallocate(fd, FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, offset, size)
+}
+
+/// Zero the range without deallocating its space.
+pub fn raw_write_zeroes(fd: RawFd, offset: usize, size: u64) -> i64 {
+    raw_fallocate(fd, FALLOC_FL_ZERO_RANGE | FALLOC_FL_KEEP_SIZE, offset, size)
+}
+
 pub fn raw_datasync(fd: RawFd) -> i64 {
     // SAFETY: fd is valid.
-    let ret = unsafe { i64::from(fdatasync(fd)) };
+    let mut ret = unsafe { i64::from(fdatasync(fd)) };
     if ret < 0 {
-        error!("Failed to fdatasync: errno{}.", errno::errno().0);
+        let err = errno::errno().0;
+        error!("Failed to fdatasync: errno{}.", err);
+        ret = -(err as i64);
     }
     ret
 }
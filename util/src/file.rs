@@ -25,13 +25,19 @@ pub fn open_file(path: &str, read_only: bool, direct: bool) -> Result<File> {
     if direct {
         options.custom_flags(libc::O_DIRECT);
     }
-    let file = options.open(path).with_context(|| {
-        format!(
-            "failed to open the file for block {}. Error: {}",
-            path,
-            std::io::Error::last_os_error(),
-        )
-    })?;
+    let file = match options.open(path) {
+        Ok(file) => file,
+        Err(e) if direct && e.raw_os_error() == Some(libc::EINVAL) => {
+            bail!(
+                "failed to open the file for block {} with O_DIRECT, the filesystem may not \
+                support direct access, use cache=writeback or direct=off instead",
+                path
+            );
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to open the file for block {}", path));
+        }
+    };
 
     Ok(file)
 }
@@ -16,6 +16,7 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use log::error;
+use serde::{Deserialize, Serialize};
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::loop_context::{get_current_time, EventLoopContext};
@@ -47,13 +48,26 @@ impl LeakBucket {
     ///
     /// * `units_ps` - units per second.
     pub fn new(units_ps: u64) -> Result<Self> {
-        Ok(LeakBucket {
+        Ok(Self::with_timer(
+            units_ps,
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+        ))
+    }
+
+    /// Construct function with the wakeup event which may be shared by other buckets.
+    ///
+    /// # Arguments
+    ///
+    /// * `units_ps` - units per second.
+    /// * `timer_wakeup` - written when bucket is ready for allowing more IO operation.
+    pub fn with_timer(units_ps: u64, timer_wakeup: Arc<EventFd>) -> Self {
+        LeakBucket {
             capacity: units_ps * ACCURACY_SCALE,
             level: 0,
             prev_time: get_current_time(),
             timer_started: false,
-            timer_wakeup: Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
-        })
+            timer_wakeup,
+        }
     }
 
     /// Return true if the bucket is full, and caller must return directly instead of launching IO.
@@ -63,6 +77,20 @@ impl LeakBucket {
     ///
     /// * `loop_context` - used for delay function call.
     pub fn throttled(&mut self, loop_context: &mut EventLoopContext, need_units: u64) -> bool {
+        if self.is_full(loop_context) {
+            return true;
+        }
+        self.consume(need_units);
+        false
+    }
+
+    /// Return true if the bucket is full, and the timer is started to wake up the caller when
+    /// the bucket is ready. The units are not consumed.
+    ///
+    /// # Arguments
+    ///
+    /// * `loop_context` - used for delay function call.
+    pub fn is_full(&mut self, loop_context: &mut EventLoopContext) -> bool {
         // capacity value is zero, indicating that there is no need to limit
         if self.capacity == 0 {
             return false;
@@ -71,13 +99,15 @@ impl LeakBucket {
             return true;
         }
 
-        // update the water level
+        // update the water level, calculate in u128 to avoid overflow of large capacity.
         let now = get_current_time();
         let nanos = (now - self.prev_time).as_nanos();
-        if nanos > (self.level * NANOSECONDS_PER_SECOND / self.capacity) as u128 {
+        let capacity = self.capacity as u128;
+        let ns_per_sec = NANOSECONDS_PER_SECOND as u128;
+        if nanos > self.level as u128 * ns_per_sec / capacity {
             self.level = 0;
         } else {
-            self.level -= nanos as u64 * self.capacity / NANOSECONDS_PER_SECOND;
+            self.level -= (nanos * capacity / ns_per_sec) as u64;
         }
 
         self.prev_time = now;
@@ -93,7 +123,7 @@ impl LeakBucket {
 
             loop_context.delay_call(
                 func,
-                (self.level - self.capacity) * NANOSECONDS_PER_SECOND / self.capacity,
+                ((self.level - self.capacity) as u128 * ns_per_sec / capacity) as u64,
             );
 
             self.timer_started = true;
@@ -101,11 +131,18 @@ impl LeakBucket {
             return true;
         }
 
-        self.level += need_units * ACCURACY_SCALE;
-
         false
     }
 
+    /// Consume the units without checking the water level.
+    pub fn consume(&mut self, units: u64) {
+        if self.capacity != 0 {
+            self.level = self
+                .level
+                .saturating_add(units.saturating_mul(ACCURACY_SCALE));
+        }
+    }
+
     /// Clear the timer state.
     pub fn clear_timer(&mut self) {
         self.timer_started = false;
@@ -116,3 +153,137 @@ impl LeakBucket {
         self.timer_wakeup.as_raw_fd()
     }
 }
+
+/// Limits of IO throttling, zero means no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoLimits {
+    /// Total IO operations per second.
+    pub iops_total: u64,
+    /// Read IO operations per second.
+    pub iops_read: u64,
+    /// Write IO operations per second.
+    pub iops_write: u64,
+    /// Total bytes per second.
+    pub bps_total: u64,
+    /// Read bytes per second.
+    pub bps_read: u64,
+    /// Write bytes per second.
+    pub bps_write: u64,
+}
+
+/// IO throttle which limits both the operations and the bytes of IO. The total, read and
+/// write limits are enforced by separate leaky buckets sharing the same wakeup event.
+pub struct IoThrottle {
+    /// Current limits.
+    limits: IoLimits,
+    /// Buckets of total, read and write operations.
+    iops: [LeakBucket; 3],
+    /// Buckets of total, read and write bytes.
+    bps: [LeakBucket; 3],
+    /// Written when any bucket is ready for allowing more IO operation.
+    timer_wakeup: Arc<EventFd>,
+}
+
+impl IoThrottle {
+    pub fn new(limits: IoLimits) -> Result<Self> {
+        Ok(Self::with_timer(
+            limits,
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+        ))
+    }
+
+    /// Construct function with the wakeup event which may be shared by other throttles.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - the IO limits.
+    /// * `timer_wakeup` - written when any bucket is ready for allowing more IO operation.
+    pub fn with_timer(limits: IoLimits, timer_wakeup: Arc<EventFd>) -> Self {
+        let bucket = |units_ps| LeakBucket::with_timer(units_ps, timer_wakeup.clone());
+        IoThrottle {
+            limits,
+            iops: [
+                bucket(limits.iops_total),
+                bucket(limits.iops_read),
+                bucket(limits.iops_write),
+            ],
+            bps: [
+                bucket(limits.bps_total),
+                bucket(limits.bps_read),
+                bucket(limits.bps_write),
+            ],
+            timer_wakeup,
+        }
+    }
+
+    /// Get current limits.
+    pub fn limits(&self) -> IoLimits {
+        self.limits
+    }
+
+    /// Update the limits, the buckets are reset if the limits are changed.
+    pub fn set_limits(&mut self, limits: IoLimits) {
+        if self.limits == limits {
+            return;
+        }
+        let bucket = |units_ps| LeakBucket::with_timer(units_ps, self.timer_wakeup.clone());
+        self.iops = [
+            bucket(limits.iops_total),
+            bucket(limits.iops_read),
+            bucket(limits.iops_write),
+        ];
+        self.bps = [
+            bucket(limits.bps_total),
+            bucket(limits.bps_read),
+            bucket(limits.bps_write),
+        ];
+        self.limits = limits;
+    }
+
+    /// Return true if the IO should be throttled, and caller must return directly instead of
+    /// launching IO. Otherwise the IO is accounted.
+    ///
+    /// # Arguments
+    ///
+    /// * `loop_context` - used for delay function call.
+    /// * `write` - whether the IO is write or read.
+    /// * `bytes` - the bytes of the IO.
+    pub fn throttled(
+        &mut self,
+        loop_context: &mut EventLoopContext,
+        write: bool,
+        bytes: u64,
+    ) -> bool {
+        let idx = if write { 2 } else { 1 };
+        for i in [0, idx] {
+            if self.iops[i].is_full(loop_context) || self.bps[i].is_full(loop_context) {
+                return true;
+            }
+        }
+        for i in [0, idx] {
+            self.iops[i].consume(1);
+            self.bps[i].consume(bytes);
+        }
+        false
+    }
+
+    /// Return true if any bucket is full.
+    pub fn is_full(&mut self, loop_context: &mut EventLoopContext) -> bool {
+        self.iops
+            .iter_mut()
+            .chain(self.bps.iter_mut())
+            .any(|bucket| bucket.is_full(loop_context))
+    }
+
+    /// Clear the timer state.
+    pub fn clear_timer(&mut self) {
+        for bucket in self.iops.iter_mut().chain(self.bps.iter_mut()) {
+            bucket.clear_timer();
+        }
+    }
+
+    /// Get raw fd of wakeup event.
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.timer_wakeup.as_raw_fd()
+    }
+}
@@ -10,11 +10,156 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::prelude::*;
-use std::sync::Mutex;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
 
 use crate::unix::gettid;
+use anyhow::{bail, Result};
 use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use once_cell::sync::Lazy;
+
+/// Modules whose log level can be set, which are the crates of StratoVirt.
+const LOG_MODULES: [&str; 17] = [
+    "acpi",
+    "address_space",
+    "boot_loader",
+    "cpu",
+    "devices",
+    "hypervisor",
+    "machine",
+    "machine_manager",
+    "migration",
+    "pci",
+    "stratovirt",
+    "sysbus",
+    "ui",
+    "usb",
+    "util",
+    "vfio",
+    "virtio",
+];
+
+/// Log levels of modules, which can be changed at runtime.
+static LOG_LEVELS: Lazy<RwLock<LogLevels>> = Lazy::new(|| RwLock::new(LogLevels::default()));
+
+thread_local! {
+    /// Key-value context of the current thread, which is added to the json logs.
+    static LOG_CONTEXT: RefCell<Vec<(&'static str, String)>> = RefCell::new(Vec::new());
+}
+
+/// Format of log output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable text, one record per line.
+    Text,
+    /// Json object with timestamp, level, module, message and the context of thread,
+    /// one record per line.
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Guard of a key-value log context, the context is restored when it's dropped.
+pub struct LogContextGuard {
+    key: &'static str,
+    prev: Option<String>,
+}
+
+impl Drop for LogContextGuard {
+    fn drop(&mut self) {
+        LOG_CONTEXT.with(|ctx| {
+            let mut ctx = ctx.borrow_mut();
+            match self.prev.take() {
+                Some(prev) => {
+                    if let Some((_, value)) = ctx.iter_mut().find(|(k, _)| *k == self.key) {
+                        *value = prev;
+                    }
+                }
+                None => ctx.retain(|(k, _)| *k != self.key),
+            }
+        });
+    }
+}
+
+/// Add a key-value context to the json logs of current thread until the returned
+/// guard is dropped, e.g. `let _ctx = log_context("vcpu", 0);`.
+///
+/// # Arguments
+///
+/// * `key` - Name of the field in json logs.
+/// * `value` - Value of the field.
+pub fn log_context(key: &'static str, value: impl ToString) -> LogContextGuard {
+    let value = value.to_string();
+    let prev = LOG_CONTEXT.with(|ctx| {
+        let mut ctx = ctx.borrow_mut();
+        match ctx.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, old)) => Some(std::mem::replace(old, value)),
+            None => {
+                ctx.push((key, value));
+                None
+            }
+        }
+    });
+    LogContextGuard { key, prev }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format the record as a json object in one line.
+fn format_json(record: &Record, timestamp: &str, pid: i32, tid: u64) -> String {
+    let mut json = format!(
+        "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"module\":\"{}\",\"file\":\"{}\",\
+         \"line\":{},\"pid\":{},\"tid\":{},\"message\":\"{}\"",
+        timestamp,
+        record.level(),
+        json_escape(record.module_path().unwrap_or_else(|| record.target())),
+        json_escape(record.file().unwrap_or("")),
+        record.line().unwrap_or(0),
+        pid,
+        tid,
+        json_escape(&record.args().to_string())
+    );
+    LOG_CONTEXT.with(|ctx| {
+        for (key, value) in ctx.borrow().iter() {
+            json.push_str(&format!(",\"{}\":\"{}\"", key, json_escape(value)));
+        }
+    });
+    json.push_str("}\n");
+    json
+}
 
 fn format_now() -> String {
     let mut ts = libc::timespec {
@@ -40,15 +185,99 @@ fn format_now() -> String {
     )
 }
 
+/// Log level of all the modules and the ones set for specific modules.
+struct LogLevels {
+    default: LevelFilter,
+    /// Levels of modules, keyed by module path, e.g. `virtio` or `virtio::block`.
+    modules: HashMap<String, LevelFilter>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        LogLevels {
+            default: LevelFilter::Info,
+            modules: HashMap::new(),
+        }
+    }
+}
+
+impl LogLevels {
+    /// Get the level of the module path, the level of the longest matched module wins.
+    fn level(&self, target: &str) -> LevelFilter {
+        let mut level = self.default;
+        let mut matched = 0;
+        for (module, module_level) in self.modules.iter() {
+            let is_match = target
+                .strip_prefix(module.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"));
+            if is_match && module.len() > matched {
+                level = *module_level;
+                matched = module.len();
+            }
+        }
+        level
+    }
+
+    /// Set the level of module, or the default level if module is None. Return the
+    /// previous level of the module.
+    fn set(&mut self, module: Option<&str>, level: &str) -> Result<LevelFilter> {
+        let level = match level {
+            "off" | "error" | "warn" | "info" | "debug" | "trace" => {
+                LevelFilter::from_str(level).unwrap()
+            }
+            _ => bail!(
+                "Invalid log level {}, off, error, warn, info, debug or trace is expected",
+                level
+            ),
+        };
+        let module = match module {
+            Some(module) => module,
+            None => {
+                return Ok(std::mem::replace(&mut self.default, level));
+            }
+        };
+
+        let valid = module.split("::").enumerate().all(|(index, name)| {
+            if index == 0 {
+                return LOG_MODULES.contains(&name);
+            }
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        if !valid {
+            bail!(
+                "Invalid log module {}, it should be one of {} or their submodules",
+                module,
+                LOG_MODULES.join(", ")
+            );
+        }
+        let previous = self.level(module);
+        self.modules.insert(module.to_string(), level);
+        Ok(previous)
+    }
+}
+
+/// Set the log level at runtime. Return the previous level of the module.
+///
+/// # Arguments
+///
+/// * `module` - Module path, e.g. `virtio` or `virtio::block`. The default level of all
+///   modules is set if it's None.
+/// * `level` - Log level, `off`, `error`, `warn`, `info`, `debug` or `trace`.
+pub fn set_log_level(module: Option<&str>, level: &str) -> Result<String> {
+    let previous = LOG_LEVELS.write().unwrap().set(module, level)?;
+    Ok(previous.to_string().to_lowercase())
+}
+
 /// Format like "%year-%mon-%dayT%hour:%min:%sec.%nsec
 struct VmLogger {
     handler: Option<Mutex<Box<dyn Write + Send>>>,
-    level: Level,
+    format: LogFormat,
 }
 
 impl Log for VmLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        self.handler.is_some() && metadata.level() <= self.level
+        self.handler.is_some()
+            && metadata.level() <= LOG_LEVELS.read().unwrap().level(metadata.target())
     }
 
     fn log(&self, record: &Record) {
@@ -56,8 +285,8 @@ impl Log for VmLogger {
             let pid = unsafe { libc::getpid() };
             let tid = gettid();
 
-            self.handler.as_ref().map(|writer| {
-                writer.lock().unwrap().write_fmt(format_args!(
+            self.handler.as_ref().map(|writer| match self.format {
+                LogFormat::Text => writer.lock().unwrap().write_fmt(format_args!(
                     "{:<5}: [{}][{}][{}: {}]:{}: {}\n",
                     format_now(),
                     pid,
@@ -66,7 +295,11 @@ impl Log for VmLogger {
                     record.line().unwrap_or(0),
                     record.level(),
                     record.args()
-                ))
+                )),
+                LogFormat::Json => writer
+                    .lock()
+                    .unwrap()
+                    .write_all(format_json(record, &format_now(), pid, tid).as_bytes()),
             });
         }
     }
@@ -77,17 +310,22 @@ impl Log for VmLogger {
 fn init_vm_logger(
     level: Option<Level>,
     logfile: Option<Box<dyn Write + Send>>,
+    format: LogFormat,
 ) -> Result<(), log::SetLoggerError> {
     let buffer = logfile.map(Mutex::new);
     let logger = VmLogger {
-        level: level.unwrap_or(Level::Info),
         handler: buffer,
+        format,
     };
+    LOG_LEVELS.write().unwrap().default = level.unwrap_or(Level::Info).to_level_filter();
 
     log::set_boxed_logger(Box::new(logger)).map(|()| log::set_max_level(LevelFilter::Trace))
 }
 
-pub fn init_logger_with_env(logfile: Option<Box<dyn Write + Send>>) -> Result<(), SetLoggerError> {
+pub fn init_logger_with_env(
+    logfile: Option<Box<dyn Write + Send>>,
+    format: LogFormat,
+) -> Result<(), SetLoggerError> {
     let level = match std::env::var("STRATOVIRT_LOG_LEVEL") {
         Ok(l) => match l.to_lowercase().as_str() {
             "error" => Level::Error,
@@ -100,7 +338,81 @@ pub fn init_logger_with_env(logfile: Option<Box<dyn Write + Send>>) -> Result<()
         _ => Level::Info,
     };
 
-    init_vm_logger(Some(level), logfile)?;
+    init_vm_logger(Some(level), logfile, format)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_json() {
+        let json = format_json(
+            &Record::builder()
+                .args(format_args!("Failed to read \"{}\"\n", "a\\b"))
+                .level(Level::Warn)
+                .target("virtio")
+                .module_path(Some("virtio::block"))
+                .file(Some("virtio/src/block.rs"))
+                .line(Some(10))
+                .build(),
+            "2023-01-01T00:00:00.000000000",
+            1,
+            2,
+        );
+        assert_eq!(
+            json,
+            "{\"timestamp\":\"2023-01-01T00:00:00.000000000\",\"level\":\"WARN\",\
+             \"module\":\"virtio::block\",\"file\":\"virtio/src/block.rs\",\"line\":10,\
+             \"pid\":1,\"tid\":2,\"message\":\"Failed to read \\\"a\\\\b\\\"\\n\"}\n"
+        );
+
+        let _vcpu = log_context("vcpu", 0);
+        {
+            let _dev = log_context("device", "drive-0");
+            let _vcpu = log_context("vcpu", 1);
+            let json = format_json(&Record::builder().build(), "", 1, 2);
+            assert!(json.ends_with(",\"vcpu\":\"1\",\"device\":\"drive-0\"}\n"));
+        }
+        let json = format_json(&Record::builder().build(), "", 1, 2);
+        assert!(json.ends_with(",\"message\":\"\",\"vcpu\":\"0\"}\n"));
+    }
+
+    #[test]
+    fn test_set_log_level() {
+        let mut levels = LogLevels::default();
+        assert_eq!(levels.level("virtio::block"), LevelFilter::Info);
+
+        assert_eq!(
+            levels.set(Some("virtio"), "debug").unwrap(),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            levels.set(Some("virtio::block"), "trace").unwrap(),
+            LevelFilter::Debug
+        );
+        assert_eq!(levels.level("virtio"), LevelFilter::Debug);
+        assert_eq!(levels.level("virtio::net"), LevelFilter::Debug);
+        assert_eq!(levels.level("virtio::block"), LevelFilter::Trace);
+        assert_eq!(levels.level("virtio::block::raw"), LevelFilter::Trace);
+        assert_eq!(levels.level("virtio_fs"), LevelFilter::Info);
+
+        assert_eq!(levels.set(None, "error").unwrap(), LevelFilter::Info);
+        assert_eq!(levels.level("cpu"), LevelFilter::Error);
+        assert_eq!(
+            levels.set(Some("virtio"), "off").unwrap(),
+            LevelFilter::Debug
+        );
+        assert_eq!(levels.level("virtio::net"), LevelFilter::Off);
+
+        assert!(levels.set(Some("virtio"), "DEBUG").is_err());
+        assert!(levels.set(Some("virtio"), "verbose").is_err());
+        assert!(levels.set(Some("qemu"), "debug").is_err());
+        assert!(levels.set(Some("virtio::"), "debug").is_err());
+        assert!(levels.set(Some("virtio::bl-ock"), "debug").is_err());
+        assert!(levels.set(Some(""), "debug").is_err());
+        assert_eq!(levels.level("virtio::block"), LevelFilter::Trace);
+    }
+}
@@ -77,6 +77,8 @@
 //! ```
 //! This programe will be trapped.
 
+use std::str::FromStr;
+
 use anyhow::bail;
 
 use crate::offset_of;
@@ -188,6 +190,20 @@ impl From<SeccompOpt> for u32 {
     }
 }
 
+impl FromStr for SeccompOpt {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "kill" => Ok(SeccompOpt::Kill),
+            "trap" => Ok(SeccompOpt::Trap),
+            "allow" => Ok(SeccompOpt::Allow),
+            "log" => Ok(SeccompOpt::Log),
+            _ => Err(()),
+        }
+    }
+}
+
 /// The format of BPF programe executes over.
 ///
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/linux/seccomp.h#L56
@@ -589,4 +605,16 @@ mod tests {
 
         assert_eq!(seccomp_filter.sock_filters, bpf_vec);
     }
+
+    #[test]
+    fn test_seccomp_opt_from_str() {
+        assert_eq!(SeccompOpt::from_str("trap"), Ok(SeccompOpt::Trap));
+        assert_eq!(SeccompOpt::from_str("log"), Ok(SeccompOpt::Log));
+        assert_eq!(SeccompOpt::from_str("kill"), Ok(SeccompOpt::Kill));
+        assert_eq!(SeccompOpt::from_str("allow"), Ok(SeccompOpt::Allow));
+        assert!(SeccompOpt::from_str("errno").is_err());
+
+        let seccomp_filter = SyscallFilter::new(SeccompOpt::Log);
+        assert_eq!(u32::from(seccomp_filter.opt), 0x7ffc_0000);
+    }
 }
@@ -50,6 +50,45 @@ pub fn host_page_size() -> u64 {
     unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
 }
 
+/// Gets the number of cpus configured in host.
+pub fn host_cpu_num() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) as u64 }
+}
+
+/// Bind the thread to the given host cpus using `sched_setaffinity`.
+///
+/// # Arguments
+///
+/// * `tid` - Thread ID of the thread.
+/// * `cpus` - Host cpus which the thread is allowed to run on.
+pub fn set_thread_affinity(tid: u64, cpus: &[u64]) -> Result<()> {
+    // SAFETY: cpu_set_t is a plain bitmap which is valid when zeroed.
+    let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        if *cpu as usize >= libc::CPU_SETSIZE as usize {
+            bail!("Host cpu {} exceeds the maximum cpu set size", cpu);
+        }
+        unsafe { libc::CPU_SET(*cpu as usize, &mut cpu_set) };
+    }
+
+    let ret = unsafe {
+        libc::sched_setaffinity(
+            tid as libc::pid_t,
+            size_of::<libc::cpu_set_t>(),
+            &cpu_set as *const libc::cpu_set_t,
+        )
+    };
+    if ret != 0 {
+        bail!(
+            "Failed to set affinity of thread {} to cpus {:?}: {}",
+            tid,
+            cpus,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
 /// Parse unix uri to unix path.
 ///
 /// # Notions
@@ -284,6 +284,10 @@ impl Listener for VfioContainer {
         self.enabled = false;
     }
 
+    fn pin_owner(&self) -> Option<String> {
+        Some("vfio".to_string())
+    }
+
     fn handle_request(
         &self,
         range: Option<&FlatRange>,
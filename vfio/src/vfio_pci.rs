@@ -22,6 +22,7 @@ use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
 use hypervisor::kvm::{MsiVector, KVM_FDS};
 use log::error;
+use migration::MigrationManager;
 #[cfg(target_arch = "aarch64")]
 use pci::config::SECONDARY_BUS_NUM;
 use pci::config::{
@@ -800,6 +801,7 @@ impl VfioPciDevice {
                 CONTAINERS.lock().unwrap().remove(&container_fd);
             }
         }
+        MigrationManager::unregister_migration_blocker(&self.name);
         Ok(())
     }
 }
@@ -858,19 +860,13 @@ impl PciDevOps for VfioPciDevice {
         pci::Result::with_context(self.register_bars(), || "Failed to register bars")?;
 
         let devfn = self.devfn;
+        let name = self.name.clone();
         let dev = Arc::new(Mutex::new(self));
         let pci_bus = dev.lock().unwrap().parent_bus.upgrade().unwrap();
         let mut locked_pci_bus = pci_bus.lock().unwrap();
-        let pci_device = locked_pci_bus.devices.get(&devfn);
-        if pci_device.is_none() {
-            locked_pci_bus.devices.insert(devfn, dev);
-        } else {
-            bail!(
-                "Devfn {:?} has been used by {:?}",
-                &devfn,
-                pci_device.unwrap().lock().unwrap().name()
-            );
-        }
+        locked_pci_bus.attach_device(devfn, dev)?;
+        // The state of the host device is not visible to StratoVirt.
+        MigrationManager::register_migration_blocker(&name, "vfio-pci device can't be migrated");
 
         Ok(())
     }
@@ -997,6 +993,10 @@ impl PciDevOps for VfioPciDevice {
         self.name.clone()
     }
 
+    fn pci_config(&self) -> Option<&PciConfig> {
+        Some(&self.pci_config)
+    }
+
     fn reset(&mut self, _reset_child_device: bool) -> pci::Result<()> {
         pci::Result::with_context(self.vfio_device.lock().unwrap().reset(), || {
             "Fail to reset vfio dev"
@@ -209,8 +209,11 @@ fn update_capabilities(cmd_args: &ArgMatches) -> Result<()> {
 
 fn init_log(logfile_path: String) -> Result<()> {
     if logfile_path.is_empty() {
-        logger::init_logger_with_env(Some(Box::new(std::io::stdout())))
-            .with_context(|| "Failed to init logger")?;
+        logger::init_logger_with_env(
+            Some(Box::new(std::io::stdout())),
+            logger::LogFormat::default(),
+        )
+        .with_context(|| "Failed to init logger")?;
     } else {
         let logfile = std::fs::OpenOptions::new()
             .read(false)
@@ -220,7 +223,7 @@ fn init_log(logfile_path: String) -> Result<()> {
             .mode(0o640)
             .open(logfile_path.clone())
             .with_context(|| format!("Failed to open log file {}", logfile_path))?;
-        logger::init_logger_with_env(Some(Box::new(logfile)))
+        logger::init_logger_with_env(Some(Box::new(logfile)), logger::LogFormat::default())
             .with_context(|| format!("Failed to init logger {}", logfile_path))?;
     }
 
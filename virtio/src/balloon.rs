@@ -22,7 +22,8 @@ use std::{
 
 use crate::report_virtio_error;
 use address_space::{
-    AddressSpace, FlatRange, GuestAddress, Listener, ListenerReqType, RegionIoEventFd, RegionType,
+    AddressRange, AddressSpace, FlatRange, GuestAddress, Listener, ListenerReqType,
+    RegionIoEventFd, RegionType,
 };
 use anyhow::{anyhow, Context, Result};
 use log::{error, warn};
@@ -231,6 +232,16 @@ impl Request {
             while let Some(pfn) = iov_to_buf::<u32>(address_space, iov, offset) {
                 offset += std::mem::size_of::<u32>() as u64;
                 let gpa: GuestAddress = GuestAddress((pfn as u64) << VIRTIO_BALLOON_PFN_SHIFT);
+                // Pages pinned for DMA are left to the guest, they must stay resident.
+                if req_type
+                    && address_space.is_range_pinned(AddressRange::new(gpa, BALLOON_PAGE_SIZE))
+                {
+                    warn!(
+                        "Balloon: skip inflating pinned page, gpa: 0x{:x}",
+                        gpa.raw_value()
+                    );
+                    continue;
+                }
                 let hva = match mem.lock().unwrap().get_host_address(gpa) {
                     Some(addr) => addr,
                     None => {
@@ -315,7 +326,7 @@ impl Request {
         }
     }
 
-    fn release_pages(&self, mem: &Arc<Mutex<BlnMemInfo>>) {
+    fn release_pages(&self, address_space: &Arc<AddressSpace>, mem: &Arc<Mutex<BlnMemInfo>>) {
         for iov in self.iovec.iter() {
             let advice = if mem.lock().unwrap().mem_share() {
                 libc::MADV_REMOVE
@@ -323,6 +334,14 @@ impl Request {
                 libc::MADV_DONTNEED
             };
             let gpa: GuestAddress = iov.iov_base;
+            if address_space.is_range_pinned(AddressRange::new(gpa, iov.iov_len)) {
+                warn!(
+                    "Balloon: skip releasing pinned pages, gpa: 0x{:x}, len: 0x{:x}",
+                    gpa.raw_value(),
+                    iov.iov_len
+                );
+                continue;
+            }
             let hva = match mem.lock().unwrap().get_host_address(gpa) {
                 Some(addr) => addr,
                 None => {
@@ -594,7 +613,7 @@ impl BalloonIoHandler {
             let req = Request::parse(&elem, IN_IOVEC)
                 .with_context(|| "Fail to parse available descriptor chain")?;
             if !self.mem_info.lock().unwrap().has_huge_page() {
-                req.release_pages(&self.mem_info);
+                req.release_pages(&self.mem_space, &self.mem_info);
             }
             locked_queue
                 .vring
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cell::RefCell;
 use std::cmp;
 use std::collections::HashMap;
 use std::fs::File;
@@ -19,32 +20,49 @@ use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
+use super::block_overlay::{anonymous_overlay, BlockOverlay};
+use super::qcow2::{is_qcow2, Qcow2Driver};
 use super::{
-    iov_discard_back, iov_discard_front, iov_to_buf, report_virtio_error, virtio_has_feature,
-    Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX,
-    VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
-    VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
+    iov_discard_back, iov_discard_front, iov_to_buf, packed_ring_feature, report_virtio_error,
+    virtio_has_feature, Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VirtioTrace, VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO,
+    VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR,
+    VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP, VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH,
+    VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES,
+    VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
 };
 use crate::VirtioError;
 use address_space::{AddressSpace, GuestAddress};
 use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
 use log::{error, warn};
-use machine_manager::config::{BlkDevConfig, ConfigCheck, DriveFile, VmConfig};
+use machine_manager::config::{
+    BlkDevConfig, BlockErrorPolicy, ConfigCheck, DiskFormat, DriveFile, VmConfig,
+};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper, EventLoop};
+use machine_manager::{
+    event,
+    machine::{
+        register_block_stats, register_vm_state_notifier, request_vm_pause, unregister_block_stats,
+        unregister_vm_state_notifier, BlockStats, KvmVmState,
+    },
+    qmp::{qmp_schema, QmpChannel},
+};
 use migration::{
     migration::Migratable, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
     StateTransfer,
 };
 use migration_derive::{ByteCode, Desc};
-use util::aio::{iov_from_buf_direct, raw_datasync, Aio, AioCb, AioEngine, Iovec, OpCode};
+use once_cell::sync::Lazy;
+use util::aio::{
+    iov_from_buf_direct, iov_to_buf_direct, raw_datasync, Aio, AioCb, AioEngine, Iovec, OpCode,
+};
 use util::byte_code::ByteCode;
-use util::leak_bucket::LeakBucket;
+use util::leak_bucket::{IoLimits, IoThrottle};
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
@@ -67,6 +85,14 @@ const MAX_NUM_MERGE_IOVS: usize = 1024;
 const MAX_NUM_MERGE_BYTES: u64 = i32::MAX as u64;
 /// Max time for every round of process queue.
 const MAX_MILLIS_TIME_PROCESS_QUEUE: u16 = 100;
+/// Max number sectors of a discard or write zeroes segment.
+const MAX_DISCARD_SECTORS: u32 = (MAX_NUM_MERGE_BYTES >> SECTOR_SHIFT) as u32;
+
+/// IO limits of the realized block devices, indexed by device id.
+static BLOCK_IO_LIMITS: Lazy<Mutex<HashMap<String, Arc<Mutex<IoLimits>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+/// Block devices which can be resized at runtime.
+static BLOCK_DEVICES: Lazy<Mutex<Vec<Weak<Mutex<Block>>>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
 type SenderConfig = (
     Option<Arc<File>>,
@@ -76,8 +102,85 @@ type SenderConfig = (
     Option<String>,
     bool,
     AioEngine,
+    Option<Arc<Mutex<dyn BlockDriverOps>>>,
 );
 
+/// Block driver which maps the disk to host files itself, such as the image format or
+/// the overlay. Its data requests are handled synchronously instead of by aio.
+pub(crate) trait BlockDriverOps: Send {
+    /// Size of the disk in bytes.
+    fn disk_size(&self) -> u64;
+
+    /// Read data of the disk from `offset` to `buf`.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()>;
+
+    /// Write data in `buf` to `offset` of the disk.
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> std::io::Result<()>;
+
+    /// Sync the data written to the host storage.
+    fn sync(&self) -> std::io::Result<()>;
+
+    /// Get the file descriptor of the host file which stores the data written by guest.
+    fn data_fd(&self) -> RawFd;
+
+    /// Read data to `iovec` from `offset` of the disk. Return the number of bytes
+    /// read on success, or the negative errno on failure.
+    fn readv(&self, iovec: &[Iovec], offset: u64) -> i64 {
+        let len: u64 = iovec.iter().map(|iov| iov.iov_len).sum();
+        if offset
+            .checked_add(len)
+            .filter(|&end| end <= self.disk_size())
+            .is_none()
+        {
+            return -(libc::EINVAL as i64);
+        }
+        let mut buf = vec![0_u8; len as usize];
+        if let Err(e) = self.read_at(&mut buf, offset) {
+            error!("Failed to read block driver at {}: {:?}", offset, e);
+            return -(e.raw_os_error().unwrap_or(libc::EIO) as i64);
+        }
+        if let Err(e) = iov_from_buf_direct(iovec, &buf) {
+            error!("Failed to copy data of block driver: {:?}", e);
+            return -(libc::EIO as i64);
+        }
+        len as i64
+    }
+
+    /// Write data in `iovec` to `offset` of the disk. Return the number of bytes
+    /// written on success, or the negative errno on failure.
+    fn writev(&mut self, iovec: &[Iovec], offset: u64) -> i64 {
+        let len: u64 = iovec.iter().map(|iov| iov.iov_len).sum();
+        if offset
+            .checked_add(len)
+            .filter(|&end| end <= self.disk_size())
+            .is_none()
+        {
+            return -(libc::EINVAL as i64);
+        }
+        let mut buf = vec![0_u8; len as usize];
+        if let Err(e) = iov_to_buf_direct(iovec, &mut buf) {
+            error!("Failed to copy data for block driver: {:?}", e);
+            return -(libc::EIO as i64);
+        }
+        if let Err(e) = self.write_at(&buf, offset) {
+            error!("Failed to write block driver at {}: {:?}", offset, e);
+            return -(e.raw_os_error().unwrap_or(libc::EIO) as i64);
+        }
+        len as i64
+    }
+
+    /// Flush the written data. Return 0 on success, or the negative errno on failure.
+    fn flush(&self) -> i64 {
+        match self.sync() {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Failed to flush block driver: {:?}", e);
+                -(e.raw_os_error().unwrap_or(libc::EIO) as i64)
+            }
+        }
+    }
+}
+
 fn get_serial_num_config(serial_num: &str) -> Vec<u8> {
     let mut id_bytes = vec![0; VIRTIO_BLK_ID_BYTES as usize];
     let bytes_to_copy = cmp::min(serial_num.len(), VIRTIO_BLK_ID_BYTES as usize);
@@ -97,6 +200,126 @@ struct RequestOutHeader {
 
 impl ByteCode for RequestOutHeader {}
 
+/// The segment of discard and write zeroes request.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct DiscardWriteZeroesSeg {
+    sector: u64,
+    num_sectors: u32,
+    flags: u32,
+}
+
+impl ByteCode for DiscardWriteZeroesSeg {}
+
+/// Context to handle the failed requests according to the error policy.
+pub struct BlockErrorCtx {
+    /// Id of the block device.
+    dev_id: String,
+    /// Error policy for read requests.
+    rerror: BlockErrorPolicy,
+    /// Error policy for write requests.
+    werror: BlockErrorPolicy,
+    /// Interval(ms) between the retries of failed request.
+    retry_interval: u64,
+    /// Timeout(ms) before giving up retrying failed request.
+    retry_timeout: u64,
+    /// The iothread in which the retry timer is scheduled.
+    iothread: Option<String>,
+    /// Eventfd to resubmit the failed requests when retry timer expires.
+    retry_evt: Arc<EventFd>,
+    /// The failed requests held until the VM is resumed.
+    held_reqs: RefCell<Vec<AioCb<AioCompleteCb>>>,
+    /// The failed requests waiting for retry.
+    retry_reqs: RefCell<Vec<AioCb<AioCompleteCb>>>,
+}
+
+impl BlockErrorCtx {
+    fn new(blk_cfg: &BlkDevConfig, iothread: Option<String>, retry_evt: Arc<EventFd>) -> Self {
+        BlockErrorCtx {
+            dev_id: blk_cfg.id.clone(),
+            rerror: blk_cfg.rerror,
+            werror: blk_cfg.werror,
+            retry_interval: blk_cfg.retry_interval,
+            retry_timeout: blk_cfg.retry_timeout,
+            iothread,
+            retry_evt,
+            held_reqs: RefCell::new(Vec::new()),
+            retry_reqs: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Handle the failed request according to the error policy. Return true if the
+    /// request is held to be resubmitted later, otherwise it should be completed with error.
+    fn handle_error(&self, aiocb: &AioCb<AioCompleteCb>, operation: &str, errno: i32) -> bool {
+        let policy = if operation == "read" {
+            self.rerror
+        } else {
+            self.werror
+        };
+        let first_failure = aiocb.iocompletecb.first_failure;
+        let action = if policy.should_retry(errno) {
+            let start = first_failure.unwrap_or_else(Instant::now);
+            if start.elapsed() < Duration::from_millis(self.retry_timeout)
+                && self.schedule_retry(aiocb, start)
+            {
+                "retry"
+            } else {
+                "report"
+            }
+        } else if policy.should_stop(errno) && request_vm_pause() {
+            // Hold the request and resubmit it after the VM is resumed.
+            self.held_reqs.borrow_mut().push(aiocb.clone());
+            "stop"
+        } else {
+            "report"
+        };
+
+        // Only the first failure and the final result of retried request are reported.
+        if action != "retry" || first_failure.is_none() {
+            let nospace = errno == libc::ENOSPC;
+            event!(
+                BlockIoError;
+                qmp_schema::BlockIoError {
+                    device: self.dev_id.clone(),
+                    operation: operation.to_string(),
+                    action: action.to_string(),
+                    nospace,
+                    reason: if nospace { "enospc" } else { "eio" }.to_string(),
+                }
+            );
+        }
+        action != "report"
+    }
+
+    fn schedule_retry(&self, aiocb: &AioCb<AioCompleteCb>, first_failure: Instant) -> bool {
+        let ctx = match EventLoop::get_ctx(self.iothread.as_ref()) {
+            Some(ctx) => ctx,
+            None => {
+                error!(
+                    "Failed to get ctx to retry block request of {}",
+                    self.dev_id
+                );
+                return false;
+            }
+        };
+        let mut retry_aiocb = aiocb.clone();
+        retry_aiocb.iocompletecb.first_failure = Some(first_failure);
+        self.retry_reqs.borrow_mut().push(retry_aiocb);
+
+        let retry_evt = self.retry_evt.clone();
+        let func = Box::new(move || {
+            if let Err(e) = retry_evt.write(1) {
+                error!("Failed to write retry event for block: {:?}", e);
+            }
+        });
+        ctx.delay_call(
+            func,
+            Duration::from_millis(self.retry_interval).as_nanos() as u64,
+        );
+        true
+    }
+}
+
 #[derive(Clone)]
 pub struct AioCompleteCb {
     queue: Arc<Mutex<Queue>>,
@@ -105,6 +328,11 @@ pub struct AioCompleteCb {
     req: Rc<Request>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
+    err_ctx: Rc<BlockErrorCtx>,
+    /// The time when the request fails for the first time, only set for retried request.
+    first_failure: Option<Instant>,
+    /// IO statistics of the block device.
+    stats: Arc<BlockStats>,
 }
 
 impl AioCompleteCb {
@@ -114,6 +342,8 @@ impl AioCompleteCb {
         req: Rc<Request>,
         interrupt_cb: Arc<VirtioInterrupt>,
         driver_features: u64,
+        err_ctx: Rc<BlockErrorCtx>,
+        stats: Arc<BlockStats>,
     ) -> Self {
         AioCompleteCb {
             queue,
@@ -121,6 +351,9 @@ impl AioCompleteCb {
             req,
             interrupt_cb,
             driver_features,
+            err_ctx,
+            first_failure: None,
+            stats,
         }
     }
 
@@ -134,6 +367,15 @@ impl AioCompleteCb {
     }
 
     fn complete_one_request(&self, req: &Request, status: u8) -> Result<()> {
+        let opcode = match req.out_header.request_type {
+            VIRTIO_BLK_T_IN => OpCode::Preadv,
+            VIRTIO_BLK_T_OUT => OpCode::Pwritev,
+            VIRTIO_BLK_T_FLUSH => OpCode::Fdsync,
+            _ => OpCode::Noop,
+        };
+        self.stats
+            .account(opcode, req.data_len, status == VIRTIO_BLK_S_OK);
+
         if let Err(ref e) = self.mem_space.write_object(&status, req.in_header) {
             bail!("Failed to write the status (blk io completion) {:?}", e);
         }
@@ -174,6 +416,8 @@ struct Request {
     data_len: u64,
     in_len: u32,
     in_header: GuestAddress,
+    /// Deallocate the sectors of write zeroes request.
+    unmap: bool,
     /// Point to the next merged Request.
     next: Box<Option<Request>>,
 }
@@ -221,6 +465,7 @@ impl Request {
             data_len: 0,
             in_len: 0,
             in_header,
+            unmap: false,
             next: Box::new(None),
         };
 
@@ -259,19 +504,79 @@ impl Request {
                 }
             }
             VIRTIO_BLK_T_FLUSH => (),
+            VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                let feature = if out_header.request_type == VIRTIO_BLK_T_DISCARD {
+                    VIRTIO_BLK_F_DISCARD
+                } else {
+                    VIRTIO_BLK_F_WRITE_ZEROES
+                };
+                if virtio_has_feature(handler.driver_features, feature) {
+                    request.parse_discard_write_zeroes(handler, elem, status)?;
+                } else {
+                    error!(
+                        "Request type {} is not negotiated for block",
+                        out_header.request_type
+                    );
+                    *status = VIRTIO_BLK_S_UNSUPP;
+                }
+            }
             others => {
                 error!("Request type {} is not supported for block", others);
                 *status = VIRTIO_BLK_S_UNSUPP;
             }
         }
 
-        if !request.io_range_valid(handler.disk_sectors) {
+        if *status == VIRTIO_BLK_S_OK && !request.io_range_valid(handler.disk_sectors) {
             *status = VIRTIO_BLK_S_IOERR;
         }
 
         Ok(request)
     }
 
+    fn parse_discard_write_zeroes(
+        &mut self,
+        handler: &BlockIoHandler,
+        elem: &mut Element,
+        status: &mut u8,
+    ) -> Result<()> {
+        let data_iovec =
+            iov_discard_front(&mut elem.out_iovec, size_of::<RequestOutHeader>() as u64)
+                .with_context(|| "Empty data for discard or write zeroes request")?;
+        let data_len: u64 = data_iovec.iter().map(|iov| u64::from(iov.len)).sum();
+        // Only one segment is supported, see max_discard_seg and max_write_zeroes_seg.
+        if data_len != size_of::<DiscardWriteZeroesSeg>() as u64 {
+            error!(
+                "Invalid segments length {} of discard or write zeroes request",
+                data_len
+            );
+            *status = VIRTIO_BLK_S_UNSUPP;
+            return Ok(());
+        }
+
+        let mut seg = DiscardWriteZeroesSeg::default();
+        iov_to_buf(&handler.mem_space, data_iovec, seg.as_mut_bytes())?;
+        let flags = LittleEndian::read_u32(seg.flags.as_bytes());
+        let valid_flags = match self.out_header.request_type {
+            VIRTIO_BLK_T_DISCARD => 0,
+            _ => VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP,
+        };
+        if flags & !valid_flags != 0 {
+            error!("Invalid flags {} of discard or write zeroes request", flags);
+            *status = VIRTIO_BLK_S_UNSUPP;
+            return Ok(());
+        }
+
+        // The sector of out header is reserved for discard and write zeroes request,
+        // so reuse it and data_len to describe the range of the segment.
+        self.out_header.sector = LittleEndian::read_u64(seg.sector.as_bytes());
+        self.data_len =
+            u64::from(LittleEndian::read_u32(seg.num_sectors.as_bytes())) << SECTOR_SHIFT;
+        // Deallocating is allowed only if discard is enabled.
+        self.unmap = flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0
+            && virtio_has_feature(handler.driver_features, VIRTIO_BLK_F_DISCARD);
+        Ok(())
+    }
+
     fn execute(
         &self,
         iohandler: &mut BlockIoHandler,
@@ -303,26 +608,52 @@ impl Request {
             }
         }
 
-        let aio = &mut iohandler.aio;
-        let serial_num = &iohandler.serial_num;
         match request_type {
             VIRTIO_BLK_T_IN => {
                 aiocb.opcode = OpCode::Preadv;
-                aio.submit_request(aiocb)
+                iohandler
+                    .submit_request(aiocb)
                     .with_context(|| "Failed to process block request for reading")?;
             }
             VIRTIO_BLK_T_OUT => {
                 aiocb.opcode = OpCode::Pwritev;
-                aio.submit_request(aiocb)
+                iohandler
+                    .submit_request(aiocb)
                     .with_context(|| "Failed to process block request for writing")?;
             }
             VIRTIO_BLK_T_FLUSH => {
+                if iohandler.no_flush {
+                    aiocb.iocompletecb.complete_request(VIRTIO_BLK_S_OK)?;
+                    return Ok(());
+                }
                 aiocb.opcode = OpCode::Fdsync;
-                aio.submit_request(aiocb)
+                iohandler
+                    .submit_request(aiocb)
                     .with_context(|| "Failed to process block request for flushing")?;
             }
+            VIRTIO_BLK_T_DISCARD => {
+                aiocb.opcode = OpCode::Discard;
+                aiocb.nbytes = self.data_len;
+                iohandler
+                    .submit_request(aiocb)
+                    .with_context(|| "Failed to process block request for discarding")?;
+            }
+            VIRTIO_BLK_T_WRITE_ZEROES => {
+                aiocb.opcode = if self.unmap {
+                    OpCode::Discard
+                } else {
+                    OpCode::WriteZeroes
+                };
+                aiocb.nbytes = self.data_len;
+                iohandler
+                    .submit_request(aiocb)
+                    .with_context(|| "Failed to process block request for writing zeroes")?;
+            }
             VIRTIO_BLK_T_GET_ID => {
-                let serial = serial_num.clone().unwrap_or_else(|| String::from(""));
+                let serial = iohandler
+                    .serial_num
+                    .clone()
+                    .unwrap_or_else(|| String::from(""));
                 let serial_vec = get_serial_num_config(&serial);
                 let status = iov_from_buf_direct(&self.iovec, &serial_vec).map_or_else(
                     |e| {
@@ -341,7 +672,10 @@ impl Request {
 
     fn io_range_valid(&self, disk_sectors: u64) -> bool {
         match self.out_header.request_type {
-            VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT => {
+            VIRTIO_BLK_T_IN
+            | VIRTIO_BLK_T_OUT
+            | VIRTIO_BLK_T_DISCARD
+            | VIRTIO_BLK_T_WRITE_ZEROES => {
                 if self.data_len % SECTOR_SIZE != 0 {
                     error!("Failed to process block request with size not aligned to 512B");
                     return false;
@@ -379,6 +713,8 @@ struct BlockIoHandler {
     mem_space: Arc<AddressSpace>,
     /// The image file opened by the block device.
     disk_image: Option<Arc<File>>,
+    /// Block driver of the image file, which serves the data requests if exists.
+    driver: Option<Arc<Mutex<dyn BlockDriverOps>>>,
     /// The align requirement of request(offset/len).
     pub req_align: u32,
     /// The align requirement of buffer(iova_base).
@@ -389,6 +725,8 @@ struct BlockIoHandler {
     serial_num: Option<String>,
     /// If use direct access io.
     direct: bool,
+    /// Flush requests from guest are ignored.
+    no_flush: bool,
     /// Aio context.
     aio: Box<Aio<AioCompleteCb>>,
     /// Bit mask of features negotiated by the backend and the frontend.
@@ -403,8 +741,16 @@ struct BlockIoHandler {
     interrupt_cb: Arc<VirtioInterrupt>,
     /// thread name of io handler
     iothread: Option<String>,
-    /// Using the leak bucket to implement IO limits
-    leak_bucket: Option<LeakBucket>,
+    /// Using the leak buckets to implement IO limits.
+    throttle: IoThrottle,
+    /// IO limits shared with the device, which may be changed at runtime.
+    io_limits: Arc<Mutex<IoLimits>>,
+    /// Context to handle the failed requests.
+    err_ctx: Rc<BlockErrorCtx>,
+    /// Eventfd to resubmit the held requests after the VM is resumed.
+    resume_evt: Arc<EventFd>,
+    /// IO statistics of the block device.
+    stats: Arc<BlockStats>,
 }
 
 impl BlockIoHandler {
@@ -466,16 +812,6 @@ impl BlockIoHandler {
                 break;
             }
 
-            // limit io operations if iops is configured
-            if let Some(lb) = self.leak_bucket.as_mut() {
-                if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
-                    if lb.throttled(ctx, 1_u64) {
-                        queue.vring.push_back();
-                        break;
-                    }
-                };
-            }
-
             // Init and put valid request into request queue.
             let mut status = VIRTIO_BLK_S_OK;
             let req = Request::new(self, &mut elem, &mut status)?;
@@ -486,12 +822,27 @@ impl BlockIoHandler {
                     Rc::new(req),
                     self.interrupt_cb.clone(),
                     self.driver_features,
+                    self.err_ctx.clone(),
+                    self.stats.clone(),
                 );
                 // unlock queue, because it will be hold below.
                 drop(queue);
                 aiocompletecb.complete_request(status)?;
                 continue;
             }
+            // Limit io operations and bandwidth if io limits are configured.
+            if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
+                let req_type = req.out_header.request_type;
+                let write = req_type != VIRTIO_BLK_T_IN && req_type != VIRTIO_BLK_T_GET_ID;
+                let bytes = match req_type {
+                    VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT => req.data_len,
+                    _ => 0,
+                };
+                if self.throttle.throttled(ctx, write, bytes) {
+                    queue.vring.push_back();
+                    break;
+                }
+            }
             // Avoid bogus guest stuck IO thread.
             if req_queue.len() >= queue.vring.actual_size() as usize {
                 bail!("The front driver may be damaged, avail requests more than queue size");
@@ -513,13 +864,20 @@ impl BlockIoHandler {
                 req_rc.clone(),
                 self.interrupt_cb.clone(),
                 self.driver_features,
+                self.err_ctx.clone(),
+                self.stats.clone(),
             );
             if let Some(disk_img) = self.disk_image.as_ref() {
+                // Data written by guest is synced to the file of block driver if exists.
+                let file_fd = match self.driver.as_ref() {
+                    Some(driver) => driver.lock().unwrap().data_fd(),
+                    None => disk_img.as_raw_fd(),
+                };
                 let aiocb = AioCb {
                     direct: self.direct,
                     req_align: self.req_align,
                     buf_align: self.buf_align,
-                    file_fd: disk_img.as_raw_fd(),
+                    file_fd,
                     opcode: OpCode::Noop,
                     iovec: Vec::new(),
                     offset: (req_rc.out_header.sector << SECTOR_SHIFT) as usize,
@@ -546,6 +904,9 @@ impl BlockIoHandler {
             done = true;
             return Ok(done);
         }
+        // Pick up the io limits which may be changed by QMP.
+        let limits = *self.io_limits.lock().unwrap();
+        self.throttle.set_limits(limits);
         while self
             .queue
             .lock()
@@ -577,11 +938,9 @@ impl BlockIoHandler {
             )?;
 
             // See whether we have been throttled.
-            if let Some(lb) = self.leak_bucket.as_mut() {
-                if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
-                    if lb.throttled(ctx, 0) {
-                        break;
-                    }
+            if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
+                if self.throttle.is_full(ctx) {
+                    break;
                 }
             }
         }
@@ -602,13 +961,39 @@ impl BlockIoHandler {
     }
 
     fn complete_func(aiocb: &AioCb<AioCompleteCb>, ret: i64) -> Result<()> {
+        let complete_cb = &aiocb.iocompletecb;
+        let operation = match aiocb.opcode {
+            OpCode::Preadv => Some("read"),
+            OpCode::Pwritev | OpCode::Fdsync | OpCode::Discard | OpCode::WriteZeroes => {
+                Some("write")
+            }
+            _ => None,
+        };
+        if let Some(operation) = operation {
+            if ret < 0 {
+                if complete_cb
+                    .err_ctx
+                    .handle_error(aiocb, operation, -ret as i32)
+                {
+                    return Ok(());
+                }
+            } else if complete_cb.first_failure.is_some() {
+                event!(
+                    BlockIoRecovered;
+                    qmp_schema::BlockIoRecovered {
+                        device: complete_cb.err_ctx.dev_id.clone(),
+                        operation: operation.to_string(),
+                    }
+                );
+            }
+        }
+
         let mut status = if ret < 0 {
             VIRTIO_BLK_S_IOERR
         } else {
             VIRTIO_BLK_S_OK
         };
 
-        let complete_cb = &aiocb.iocompletecb;
         // When driver does not accept FLUSH feature, the device must be of
         // writethrough cache type, so flush data before updating used ring.
         if !virtio_has_feature(complete_cb.driver_features, VIRTIO_BLK_F_FLUSH)
@@ -623,6 +1008,34 @@ impl BlockIoHandler {
         complete_cb.complete_request(status)
     }
 
+    /// Submit the request to aio, or handle it synchronously by the block driver if exists.
+    fn submit_request(&mut self, aiocb: AioCb<AioCompleteCb>) -> Result<()> {
+        let driver = match self.driver.as_ref() {
+            Some(driver) => driver,
+            None => return self.aio.submit_request(aiocb),
+        };
+        let offset = aiocb.offset as u64;
+        let ret = match aiocb.opcode {
+            OpCode::Preadv => driver.lock().unwrap().readv(&aiocb.iovec, offset),
+            OpCode::Pwritev => driver.lock().unwrap().writev(&aiocb.iovec, offset),
+            OpCode::Fdsync => driver.lock().unwrap().flush(),
+            // Discard and write zeroes are not negotiated with the block driver.
+            _ => -(libc::EOPNOTSUPP as i64),
+        };
+        Self::complete_func(&aiocb, ret)
+    }
+
+    fn resubmit_requests(&mut self, reqs: Vec<AioCb<AioCompleteCb>>) -> Result<()> {
+        if reqs.is_empty() {
+            return Ok(());
+        }
+        for aiocb in reqs {
+            self.submit_request(aiocb)
+                .with_context(|| "Failed to resubmit the failed block request")?;
+        }
+        self.aio.flush_request()
+    }
+
     fn aio_complete_handler(&mut self) -> Result<bool> {
         self.aio.handle_complete().map_err(|e| {
             report_virtio_error(
@@ -637,9 +1050,10 @@ impl BlockIoHandler {
     fn update_evt_handler(&mut self) {
         let aio_engine;
         match self.receiver.recv() {
-            Ok((image, req_align, buf_align, disk_sectors, serial_num, direct, aio)) => {
+            Ok((image, req_align, buf_align, disk_sectors, serial_num, direct, aio, driver)) => {
                 self.disk_sectors = disk_sectors;
                 self.disk_image = image;
+                self.driver = driver;
                 self.req_align = req_align;
                 self.buf_align = buf_align;
                 self.serial_num = serial_num;
@@ -650,6 +1064,7 @@ impl BlockIoHandler {
                 error!("Failed to receive config in updating handler {:?}", e);
                 self.disk_sectors = 0;
                 self.disk_image = None;
+                self.driver = None;
                 self.req_align = 1;
                 self.buf_align = 1;
                 self.serial_num = None;
@@ -733,6 +1148,46 @@ impl EventNotifierHelper for BlockIoHandler {
             None,
         ));
 
+        // Register event notifier for resume_evt.
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut h_lock = h_clone.lock().unwrap();
+            if h_lock.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            let held_reqs = h_lock.err_ctx.held_reqs.borrow_mut().drain(..).collect();
+            if let Err(ref e) = h_lock.resubmit_requests(held_reqs) {
+                error!("Failed to resubmit the held block requests {:?}", e);
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            handler_raw.resume_evt.as_raw_fd(),
+            vec![h],
+            None,
+        ));
+
+        // Register event notifier for retry_evt.
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut h_lock = h_clone.lock().unwrap();
+            if h_lock.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            let retry_reqs = h_lock.err_ctx.retry_reqs.borrow_mut().drain(..).collect();
+            if let Err(ref e) = h_lock.resubmit_requests(retry_reqs) {
+                error!("Failed to retry the failed block requests {:?}", e);
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            handler_raw.err_ctx.retry_evt.as_raw_fd(),
+            vec![h],
+            None,
+        ));
+
         // Register event notifier for queue_evt.
         let h_clone = handler.clone();
         let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
@@ -773,24 +1228,24 @@ impl EventNotifierHelper for BlockIoHandler {
         ));
 
         // Register timer event notifier for IO limits
-        if let Some(lb) = handler_raw.leak_bucket.as_ref() {
-            let h_clone = handler.clone();
-            let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-                read_fd(fd);
-                let mut h_lock = h_clone.lock().unwrap();
-                if h_lock.device_broken.load(Ordering::SeqCst) {
-                    return None;
-                }
-                if let Some(lb) = h_lock.leak_bucket.as_mut() {
-                    lb.clear_timer();
-                }
-                if let Err(ref e) = h_lock.process_queue() {
-                    error!("Failed to handle block IO {:?}", e);
-                }
-                None
-            });
-            notifiers.push(build_event_notifier(lb.as_raw_fd(), vec![h], None));
-        }
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut h_lock = h_clone.lock().unwrap();
+            if h_lock.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            h_lock.throttle.clear_timer();
+            if let Err(ref e) = h_lock.process_queue() {
+                error!("Failed to handle block IO {:?}", e);
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            handler_raw.throttle.as_raw_fd(),
+            vec![h],
+            None,
+        ));
 
         // Register event notifier for aio.
         let h_clone = handler.clone();
@@ -914,6 +1369,8 @@ pub struct Block {
     blk_cfg: BlkDevConfig,
     /// Image file opened.
     disk_image: Option<Arc<File>>,
+    /// Block driver of the image file, such as qcow2 or the overlay.
+    driver: Option<Arc<Mutex<dyn BlockDriverOps>>>,
     /// The align requirement of request(offset/len).
     pub req_align: u32,
     /// The align requirement of buffer(iova_base).
@@ -928,12 +1385,18 @@ pub struct Block {
     senders: Vec<Sender<SenderConfig>>,
     /// Eventfd for config space update.
     update_evts: Vec<Arc<EventFd>>,
-    /// Eventfd for device deactivate.
-    deactivate_evts: Vec<RawFd>,
+    /// Eventfd to resubmit the held write requests after the VM is resumed.
+    resume_evts: Vec<Arc<EventFd>>,
+    /// Eventfd for device deactivate, grouped by the iothread handling them.
+    deactivate_evts: HashMap<Option<String>, Vec<RawFd>>,
     /// Device is broken or not.
     broken: Arc<AtomicBool>,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    /// IO limits shared with the io handlers.
+    io_limits: Arc<Mutex<IoLimits>>,
+    /// IO statistics shared with the io handlers.
+    stats: Arc<BlockStats>,
 }
 
 impl Block {
@@ -944,6 +1407,7 @@ impl Block {
         Self {
             blk_cfg,
             disk_image: None,
+            driver: None,
             req_align: 1,
             buf_align: 1,
             disk_sectors: 0,
@@ -951,9 +1415,12 @@ impl Block {
             interrupt_cb: None,
             senders: Vec::new(),
             update_evts: Vec::new(),
-            deactivate_evts: Vec::new(),
+            resume_evts: Vec::new(),
+            deactivate_evts: HashMap::new(),
             broken: Arc::new(AtomicBool::new(false)),
             drive_files,
+            io_limits: Arc::new(Mutex::new(IoLimits::default())),
+            stats: Arc::new(BlockStats::default()),
         }
     }
 
@@ -963,38 +1430,129 @@ impl Block {
         self.state.config_space.capacity = num_sectors;
         // seg_max = queue_size - 2: 32bits
         self.state.config_space.seg_max = self.queue_size() as u32 - 2;
+
+        let discard = virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_DISCARD);
+        let write_zeroes =
+            virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_WRITE_ZEROES);
+        self.state.config_space.max_discard_sectors = if discard { MAX_DISCARD_SECTORS } else { 0 };
+        self.state.config_space.max_discard_seg = discard as u32;
+        self.state.config_space.discard_sector_alignment = discard as u32;
+        self.state.config_space.max_write_zeroes_sectors =
+            if write_zeroes { MAX_DISCARD_SECTORS } else { 0 };
+        self.state.config_space.max_write_zeroes_seg = write_zeroes as u32;
+        self.state.config_space.write_zeroes_may_unmap = (write_zeroes && discard) as u8;
     }
-}
 
-impl VirtioDevice for Block {
-    /// Realize virtio block device.
-    fn realize(&mut self) -> Result<()> {
-        // if iothread not found, return err
-        if self.blk_cfg.iothread.is_some()
-            && EventLoop::get_ctx(self.blk_cfg.iothread.as_ref()).is_none()
-        {
+    /// Register the block device for global use, so that it can be resized by QMP.
+    pub fn object_init(dev: Arc<Mutex<Block>>) {
+        let mut devices = BLOCK_DEVICES.lock().unwrap();
+        devices.retain(|dev| dev.strong_count() > 0);
+        devices.push(Arc::downgrade(&dev));
+    }
+
+    /// Grow the capacity of the block device to `size` bytes, and notify the
+    /// guest by config change interrupt.
+    fn resize(&mut self, size: u64) -> Result<()> {
+        let disk_image = self
+            .disk_image
+            .as_ref()
+            .with_context(|| format!("Block device {} has no image file", self.blk_cfg.id))?;
+        if self.driver.is_some() {
+            bail!(
+                "Resizing block device {} with qcow2 image or overlay is not supported",
+                self.blk_cfg.id
+            );
+        }
+        if size % SECTOR_SIZE != 0 {
             bail!(
-                "IOThread {:?} of Block is not configured in params.",
-                self.blk_cfg.iothread,
+                "Size {} is not aligned to sector size {}",
+                size,
+                SECTOR_SIZE
+            );
+        }
+        let disk_sectors = size >> SECTOR_SHIFT;
+        if disk_sectors < self.disk_sectors {
+            bail!(
+                "Shrinking block device {} from {} to {} bytes is not supported",
+                self.blk_cfg.id,
+                self.disk_sectors << SECTOR_SHIFT,
+                size
+            );
+        }
+        let mut file: &File = disk_image;
+        let file_size = file
+            .seek(SeekFrom::End(0))
+            .with_context(|| "Failed to seek the end for block")?;
+        if file_size < size {
+            bail!(
+                "Image file of block device {} is {} bytes, it must be resized on host first",
+                self.blk_cfg.id,
+                file_size
             );
         }
 
-        self.state.device_features = (1_u64 << VIRTIO_F_VERSION_1) | (1_u64 << VIRTIO_BLK_F_FLUSH);
-        if self.blk_cfg.read_only {
-            self.state.device_features |= 1_u64 << VIRTIO_BLK_F_RO;
-        };
-        self.state.device_features |= 1_u64 << VIRTIO_F_RING_INDIRECT_DESC;
-        self.state.device_features |= 1_u64 << VIRTIO_BLK_F_SEG_MAX;
-        self.state.device_features |= 1_u64 << VIRTIO_F_RING_EVENT_IDX;
+        self.disk_sectors = disk_sectors;
+        self.state.config_space.capacity = disk_sectors;
+        self.update_io_handlers()
+    }
 
-        self.build_device_config_space();
+    /// Send the image file and its configuration to the io handlers, which will
+    /// notify the guest of the config change.
+    fn update_io_handlers(&self) -> Result<()> {
+        for sender in &self.senders {
+            sender
+                .send((
+                    self.disk_image.clone(),
+                    self.req_align,
+                    self.buf_align,
+                    self.disk_sectors,
+                    self.blk_cfg.serial_num.clone(),
+                    self.blk_cfg.direct,
+                    self.blk_cfg.aio,
+                    self.driver.clone(),
+                ))
+                .with_context(|| anyhow!(VirtioError::ChannelSend("image fd".to_string())))?;
+        }
+        for update_evt in &self.update_evts {
+            update_evt
+                .write(1)
+                .with_context(|| anyhow!(VirtioError::EventFdWrite))?;
+        }
+        Ok(())
+    }
 
-        if self.blk_cfg.queues > 1 {
-            self.state.device_features |= 1_u64 << VIRTIO_BLK_F_MQ;
-            self.state.config_space.num_queues = self.blk_cfg.queues;
+    fn get_blk_config_size(&self) -> u64 {
+        // The discard related config exists only if discard or write zeroes is supported.
+        if virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_DISCARD)
+            || virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_WRITE_ZEROES)
+        {
+            size_of::<VirtioBlkConfig>() as u64
+        } else {
+            offset_of!(VirtioBlkConfig, max_discard_sectors) as u64
+        }
+    }
+}
+
+impl VirtioDevice for Block {
+    /// Realize virtio block device.
+    fn realize(&mut self) -> Result<()> {
+        // if iothread not found, return err
+        for iothread in self
+            .blk_cfg
+            .iothread
+            .iter()
+            .chain(self.blk_cfg.iothreads.iter())
+        {
+            if EventLoop::get_ctx(Some(iothread)).is_none() {
+                bail!(
+                    "IOThread {:?} of Block is not configured in params.",
+                    iothread,
+                );
+            }
         }
 
         self.disk_image = None;
+        self.driver = None;
         self.disk_sectors = DUMMY_IMG_SIZE >> SECTOR_SHIFT;
         self.req_align = 1;
         self.buf_align = 1;
@@ -1002,22 +1560,99 @@ impl VirtioDevice for Block {
             let drive_files = self.drive_files.lock().unwrap();
             let mut file = VmConfig::fetch_drive_file(&drive_files, &self.blk_cfg.path_on_host)?;
             let alignments = VmConfig::fetch_drive_align(&drive_files, &self.blk_cfg.path_on_host)?;
-            let disk_size = file
+            let mut disk_size = file
                 .seek(SeekFrom::End(0))
                 .with_context(|| "Failed to seek the end for block")?;
 
+            let qcow2 = match self.blk_cfg.format {
+                Some(format) => format == DiskFormat::Qcow2,
+                None => is_qcow2(&self.blk_cfg.path_on_host)?,
+            };
+            if qcow2 {
+                if self.blk_cfg.overlay.is_some() {
+                    bail!("Overlay can't be used with qcow2 image, use its backing file");
+                }
+                // The qcow2 image is never modified in snapshot mode, as the data written
+                // by guest goes to the anonymous overlay.
+                let read_only = self.blk_cfg.read_only || self.blk_cfg.snapshot;
+                let qcow2 = Qcow2Driver::new(&self.blk_cfg.path_on_host, read_only)?;
+                disk_size = qcow2.disk_size();
+                if self.blk_cfg.snapshot {
+                    let block_overlay =
+                        BlockOverlay::with_driver(Box::new(qcow2), anonymous_overlay()?)
+                            .with_context(|| "Failed to create overlay for snapshot")?;
+                    self.driver = Some(Arc::new(Mutex::new(block_overlay)));
+                } else {
+                    self.driver = Some(Arc::new(Mutex::new(qcow2)));
+                }
+            } else if let Some(overlay) = self.blk_cfg.overlay.as_ref() {
+                let base = file
+                    .try_clone()
+                    .with_context(|| "Failed to clone the image file for overlay")?;
+                let overlay_file = VmConfig::fetch_drive_file(&drive_files, overlay)?;
+                let block_overlay = BlockOverlay::new(base, overlay_file)
+                    .with_context(|| format!("Failed to create overlay {}", overlay))?;
+                self.driver = Some(Arc::new(Mutex::new(block_overlay)));
+            } else if self.blk_cfg.snapshot {
+                let base = file
+                    .try_clone()
+                    .with_context(|| "Failed to clone the image file for snapshot")?;
+                let block_overlay = BlockOverlay::new(base, anonymous_overlay()?)
+                    .with_context(|| "Failed to create overlay for snapshot")?;
+                self.driver = Some(Arc::new(Mutex::new(block_overlay)));
+            }
+
             self.disk_image = Some(Arc::new(file));
             self.disk_sectors = disk_size >> SECTOR_SHIFT;
             self.req_align = alignments.0;
             self.buf_align = alignments.1;
         }
+
+        self.state.device_features = 1_u64 << VIRTIO_F_VERSION_1;
+        // Device without FLUSH feature is write-through, each write is synced before completed.
+        if self.blk_cfg.write_cache {
+            self.state.device_features |= 1_u64 << VIRTIO_BLK_F_FLUSH;
+        }
+        if self.blk_cfg.read_only {
+            self.state.device_features |= 1_u64 << VIRTIO_BLK_F_RO;
+        };
+        self.state.device_features |= 1_u64 << VIRTIO_F_RING_INDIRECT_DESC;
+        self.state.device_features |= 1_u64 << VIRTIO_BLK_F_SEG_MAX;
+        self.state.device_features |= 1_u64 << VIRTIO_F_RING_EVENT_IDX;
+        self.state.device_features |= packed_ring_feature();
+        // Guest must not modify the image of a read-only drive. Block driver only
+        // handles read, write and flush requests.
+        if !self.blk_cfg.read_only && self.driver.is_none() {
+            if self.blk_cfg.discard {
+                self.state.device_features |= 1_u64 << VIRTIO_BLK_F_DISCARD;
+            }
+            if self.blk_cfg.write_zeroes {
+                self.state.device_features |= 1_u64 << VIRTIO_BLK_F_WRITE_ZEROES;
+            }
+        }
+
+        self.build_device_config_space();
+
+        if self.blk_cfg.queues > 1 {
+            self.state.device_features |= 1_u64 << VIRTIO_BLK_F_MQ;
+            self.state.config_space.num_queues = self.blk_cfg.queues;
+        }
         self.state.config_space.capacity = self.disk_sectors;
 
+        *self.io_limits.lock().unwrap() = self.blk_cfg.throttle;
+        self.stats.reset();
+        if !self.blk_cfg.id.is_empty() {
+            register_io_limits(&self.blk_cfg.id, self.io_limits.clone());
+            register_block_stats(&self.blk_cfg.id, self.stats.clone());
+        }
+
         Ok(())
     }
 
     fn unrealize(&mut self) -> Result<()> {
         MigrationManager::unregister_device_instance(BlockState::descriptor(), &self.blk_cfg.id);
+        unregister_io_limits(&self.blk_cfg.id);
+        unregister_block_stats(&self.blk_cfg.id);
         Ok(())
     }
 
@@ -1053,8 +1688,7 @@ impl VirtioDevice for Block {
 
     /// Read data of config from guest.
     fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
-        // F_DISCARD is not supported for now, so related config does not exist.
-        let config_len = offset_of!(VirtioBlkConfig, max_discard_sectors) as u64;
+        let config_len = self.get_blk_config_size();
         let read_end = offset as usize + data.len();
         if offset
             .checked_add(data.len() as u64)
@@ -1072,8 +1706,7 @@ impl VirtioDevice for Block {
 
     /// Write data to config from guest.
     fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
-        // F_DISCARD is not supported for now, so related config does not exist.
-        let config_len = offset_of!(VirtioBlkConfig, max_discard_sectors) as u64;
+        let config_len = self.get_blk_config_size();
         if offset
             .checked_add(data.len() as u64)
             .filter(|&end| end <= config_len)
@@ -1097,13 +1730,17 @@ impl VirtioDevice for Block {
         mut queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
         self.interrupt_cb = Some(interrupt_cb.clone());
-        for queue in queues.iter() {
+        for (index, queue) in queues.iter().enumerate() {
             let queue_evt = queue_evts.remove(0);
             if !queue.lock().unwrap().is_enabled() {
                 continue;
             }
+            // All requests of a queue are handled in the same iothread, to keep them in order.
+            let iothread = self.blk_cfg.queue_iothread(index).cloned();
             let (sender, receiver) = channel();
             let update_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
+            let resume_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
+            let retry_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
             let aio = Box::new(Aio::new(
                 Arc::new(BlockIoHandler::complete_func),
                 self.blk_cfg.aio,
@@ -1113,10 +1750,12 @@ impl VirtioDevice for Block {
                 queue_evt,
                 mem_space: mem_space.clone(),
                 disk_image: self.disk_image.clone(),
+                driver: self.driver.clone(),
                 req_align: self.req_align,
                 buf_align: self.buf_align,
                 disk_sectors: self.disk_sectors,
                 direct: self.blk_cfg.direct,
+                no_flush: self.blk_cfg.no_flush,
                 serial_num: self.blk_cfg.serial_num.clone(),
                 aio,
                 driver_features: self.state.driver_features,
@@ -1124,35 +1763,68 @@ impl VirtioDevice for Block {
                 update_evt: update_evt.clone(),
                 device_broken: self.broken.clone(),
                 interrupt_cb: interrupt_cb.clone(),
-                iothread: self.blk_cfg.iothread.clone(),
-                leak_bucket: match self.blk_cfg.iops {
-                    Some(iops) => Some(LeakBucket::new(iops)?),
-                    None => None,
-                },
+                iothread: iothread.clone(),
+                throttle: IoThrottle::new(*self.io_limits.lock().unwrap())?,
+                io_limits: self.io_limits.clone(),
+                err_ctx: Rc::new(BlockErrorCtx::new(
+                    &self.blk_cfg,
+                    iothread.clone(),
+                    retry_evt,
+                )),
+                resume_evt: resume_evt.clone(),
+                stats: self.stats.clone(),
             };
 
             let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
             register_event_helper(
                 notifiers,
-                self.blk_cfg.iothread.as_ref(),
-                &mut self.deactivate_evts,
+                iothread.as_ref(),
+                self.deactivate_evts.entry(iothread.clone()).or_default(),
             )?;
             self.update_evts.push(update_evt);
+            self.resume_evts.push(resume_evt);
             self.senders.push(sender);
         }
         self.broken.store(false, Ordering::SeqCst);
 
+        let can_stop = |policy: BlockErrorPolicy| {
+            matches!(policy, BlockErrorPolicy::Stop | BlockErrorPolicy::Enospc)
+        };
+        if can_stop(self.blk_cfg.werror) || can_stop(self.blk_cfg.rerror) {
+            let resume_evts = self.resume_evts.clone();
+            register_vm_state_notifier(
+                &self.blk_cfg.id,
+                Arc::new(move |state: KvmVmState| {
+                    if state != KvmVmState::Running {
+                        return;
+                    }
+                    for resume_evt in resume_evts.iter() {
+                        if let Err(e) = resume_evt.write(1) {
+                            error!("Failed to write resume event for block: {:?}", e);
+                        }
+                    }
+                }),
+            );
+        }
+
         Ok(())
     }
 
     fn deactivate(&mut self) -> Result<()> {
-        unregister_event_helper(self.blk_cfg.iothread.as_ref(), &mut self.deactivate_evts)?;
+        for (iothread, deactivate_evts) in self.deactivate_evts.iter_mut() {
+            unregister_event_helper(iothread.as_ref(), deactivate_evts)?;
+        }
+        self.deactivate_evts.clear();
+        unregister_vm_state_notifier(&self.blk_cfg.id);
         self.update_evts.clear();
+        self.resume_evts.clear();
         self.senders.clear();
         Ok(())
     }
 
     fn update_config(&mut self, dev_config: Option<Arc<dyn ConfigCheck>>) -> Result<()> {
+        unregister_io_limits(&self.blk_cfg.id);
+        unregister_block_stats(&self.blk_cfg.id);
         if let Some(conf) = dev_config {
             self.blk_cfg = conf
                 .as_any()
@@ -1166,27 +1838,7 @@ impl VirtioDevice for Block {
         }
 
         self.realize()?;
-
-        for sender in &self.senders {
-            sender
-                .send((
-                    self.disk_image.clone(),
-                    self.req_align,
-                    self.buf_align,
-                    self.disk_sectors,
-                    self.blk_cfg.serial_num.clone(),
-                    self.blk_cfg.direct,
-                    self.blk_cfg.aio,
-                ))
-                .with_context(|| anyhow!(VirtioError::ChannelSend("image fd".to_string())))?;
-        }
-        for update_evt in &self.update_evts {
-            update_evt
-                .write(1)
-                .with_context(|| anyhow!(VirtioError::EventFdWrite))?;
-        }
-
-        Ok(())
+        self.update_io_handlers()
     }
 }
 
@@ -1221,6 +1873,57 @@ impl StateTransfer for Block {
 impl MigrationHook for Block {}
 
 impl VirtioTrace for BlockIoHandler {}
+
+/// Register the io limits of the device, which can be changed by `qmp_block_set_io_throttle`.
+pub(crate) fn register_io_limits(id: &str, io_limits: Arc<Mutex<IoLimits>>) {
+    BLOCK_IO_LIMITS
+        .lock()
+        .unwrap()
+        .insert(id.to_string(), io_limits);
+}
+
+/// Unregister the io limits of the device.
+pub(crate) fn unregister_io_limits(id: &str) {
+    BLOCK_IO_LIMITS.lock().unwrap().remove(id);
+}
+
+/// Change the io limits of the block device at runtime, zero means no limit.
+///
+/// # Arguments
+///
+/// * `id` - The id of the virtio-blk or scsi device.
+/// * `limits` - The new io limits, which have been checked by the caller.
+pub fn qmp_block_set_io_throttle(id: &str, limits: IoLimits) -> Result<()> {
+    let io_limits = BLOCK_IO_LIMITS
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .with_context(|| format!("Block device {} is not found", id))?;
+    *io_limits.lock().unwrap() = limits;
+    Ok(())
+}
+
+/// Grow the capacity of the block device at runtime.
+///
+/// # Arguments
+///
+/// * `id` - The id of the block device.
+/// * `size` - The new size of the block device in bytes.
+///
+/// # Returns
+///
+/// `None` if the block device is not found, otherwise the result of resizing.
+pub fn qmp_block_resize(id: &str, size: u64) -> Option<Result<()>> {
+    let devices = BLOCK_DEVICES.lock().unwrap();
+    for dev in devices.iter().filter_map(|dev| dev.upgrade()) {
+        let mut locked_dev = dev.lock().unwrap();
+        if !locked_dev.blk_cfg.id.is_empty() && locked_dev.blk_cfg.id == id {
+            return Some(locked_dev.resize(size));
+        }
+    }
+    None
+}
 impl VirtioTrace for AioCompleteCb {}
 
 #[cfg(test)]
@@ -1244,6 +1947,7 @@ mod tests {
             Block {
                 blk_cfg: Default::default(),
                 disk_image: None,
+                driver: None,
                 req_align: 1,
                 buf_align: 1,
                 disk_sectors: 0,
@@ -1251,9 +1955,12 @@ mod tests {
                 interrupt_cb: None,
                 senders: Vec::new(),
                 update_evts: Vec::new(),
-                deactivate_evts: Vec::new(),
+                resume_evts: Vec::new(),
+                deactivate_evts: HashMap::new(),
                 broken: Arc::new(AtomicBool::new(false)),
                 drive_files: Arc::new(Mutex::new(HashMap::new())),
+                io_limits: Arc::new(Mutex::new(IoLimits::default())),
+                stats: Arc::new(BlockStats::default()),
             }
         }
     }
@@ -1307,6 +2014,7 @@ mod tests {
             &block.blk_cfg.path_on_host,
             block.blk_cfg.read_only,
             block.blk_cfg.direct,
+            false,
         )
         .unwrap();
         assert!(block.realize().is_ok());
@@ -1397,6 +2105,131 @@ mod tests {
         block.state.driver_features = 0;
     }
 
+    // Test discard and write zeroes features, which must not be advertised for read-only drive.
+    #[test]
+    fn test_discard_write_zeroes_features() {
+        let mut block = Block::default();
+        block.blk_cfg.discard = true;
+        block.blk_cfg.write_zeroes = true;
+        assert!(block.realize().is_ok());
+        assert!(virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_DISCARD
+        ));
+        assert!(virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_WRITE_ZEROES
+        ));
+        assert_eq!({ block.state.config_space.max_discard_seg }, 1);
+        assert_eq!({ block.state.config_space.write_zeroes_may_unmap }, 1);
+        // The discard related config can be read now.
+        let mut data = [0_u8; 4];
+        let offset = offset_of!(VirtioBlkConfig, max_discard_seg) as u64;
+        assert!(block.read_config(offset, &mut data).is_ok());
+        assert_eq!(LittleEndian::read_u32(&data), 1);
+
+        let mut block = Block::default();
+        block.blk_cfg.discard = true;
+        block.blk_cfg.write_zeroes = true;
+        block.blk_cfg.read_only = true;
+        assert!(block.realize().is_ok());
+        assert!(!virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_DISCARD
+        ));
+        assert!(!virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_WRITE_ZEROES
+        ));
+        assert_eq!({ block.state.config_space.max_discard_sectors }, 0);
+        assert!(block.read_config(offset, &mut data).is_err());
+    }
+
+    // Test FLUSH feature is not advertised for write-through cache mode.
+    #[test]
+    fn test_write_cache_feature() {
+        let mut block = Block::default();
+        assert!(block.realize().is_ok());
+        assert!(virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_FLUSH
+        ));
+
+        let mut block = Block::default();
+        block.blk_cfg.write_cache = false;
+        assert!(block.realize().is_ok());
+        assert!(!virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_FLUSH
+        ));
+    }
+
+    // Test the io limits of block device can be changed by `qmp_block_set_io_throttle`.
+    #[test]
+    fn test_block_set_io_throttle() {
+        let mut block = Block::default();
+        block.blk_cfg.id = "blk-throttle".to_string();
+        block.blk_cfg.throttle.iops_total = 100;
+        assert!(block.realize().is_ok());
+        assert_eq!(block.io_limits.lock().unwrap().iops_total, 100);
+
+        let limits = IoLimits {
+            iops_read: 50,
+            bps_write: 1 << 20,
+            ..IoLimits::default()
+        };
+        assert!(qmp_block_set_io_throttle("blk-throttle", limits).is_ok());
+        assert_eq!(*block.io_limits.lock().unwrap(), limits);
+        assert!(qmp_block_set_io_throttle("blk-none", limits).is_err());
+
+        assert!(block.unrealize().is_ok());
+        assert!(qmp_block_set_io_throttle("blk-throttle", limits).is_err());
+    }
+
+    // Test the capacity of block device can be grown by `qmp_block_resize`.
+    #[test]
+    fn test_block_resize() {
+        let block = Arc::new(Mutex::new(Block::default()));
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(1 << 20).unwrap();
+        let mut locked_block = block.lock().unwrap();
+        locked_block.blk_cfg.id = "blk-resize".to_string();
+        locked_block.blk_cfg.path_on_host = file.as_path().to_str().unwrap().to_string();
+        locked_block.blk_cfg.direct = false;
+        VmConfig::add_drive_file(
+            &mut locked_block.drive_files.lock().unwrap(),
+            &locked_block.blk_cfg.path_on_host,
+            locked_block.blk_cfg.read_only,
+            locked_block.blk_cfg.direct,
+            false,
+        )
+        .unwrap();
+        assert!(locked_block.realize().is_ok());
+        assert_eq!(locked_block.disk_sectors, (1 << 20) >> SECTOR_SHIFT);
+        drop(locked_block);
+        Block::object_init(block.clone());
+
+        // The image file is not resized on host.
+        assert!(qmp_block_resize("blk-resize", 2 << 20).unwrap().is_err());
+        file.as_file().set_len(2 << 20).unwrap();
+        // Unaligned size.
+        assert!(qmp_block_resize("blk-resize", (1 << 20) + 1)
+            .unwrap()
+            .is_err());
+        // Shrink is not supported.
+        assert!(qmp_block_resize("blk-resize", 1 << 19).unwrap().is_err());
+        assert!(qmp_block_resize("blk-resize", 2 << 20).unwrap().is_ok());
+        let locked_block = block.lock().unwrap();
+        assert_eq!(locked_block.disk_sectors, (2 << 20) >> SECTOR_SHIFT);
+        let capacity = locked_block.state.config_space.capacity;
+        assert_eq!(capacity, (2 << 20) >> SECTOR_SHIFT);
+        drop(locked_block);
+
+        assert!(qmp_block_resize("blk-none", 2 << 20).is_none());
+        drop(block);
+        assert!(qmp_block_resize("blk-resize", 2 << 20).is_none());
+    }
+
     // Test `get_serial_num_config`. The function will output the shorter length between 20
     // with serial_num length.
     #[test]
@@ -1438,13 +2271,14 @@ mod tests {
 
         // config iothread and iops
         block.blk_cfg.iothread = Some(thread_name);
-        block.blk_cfg.iops = Some(100);
+        block.blk_cfg.throttle.iops_total = 100;
 
         VmConfig::add_drive_file(
             &mut block.drive_files.lock().unwrap(),
             &block.blk_cfg.path_on_host,
             block.blk_cfg.read_only,
             block.blk_cfg.direct,
+            false,
         )
         .unwrap();
 
@@ -1560,4 +2394,126 @@ mod tests {
             }
         }
     }
+
+    // Test the retry policy of block device. A read request fails with transient EIO, then
+    // succeeds after being retried. Non-retryable error is reported to guest directly.
+    #[test]
+    fn test_retry_transient_error() {
+        let thread_name = "io1".to_string();
+        let io_conf = IothreadConfig {
+            id: thread_name.clone(),
+        };
+        EventLoop::object_init(&Some(vec![io_conf])).unwrap();
+        QmpChannel::object_init();
+
+        let mem_space = address_space_init();
+        let mut queue_config = QueueConfig::new(DEFAULT_VIRTQUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(0);
+        queue_config.addr_cache.desc_table_host =
+            mem_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.avail_ring = GuestAddress(16 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.avail_ring_host =
+            mem_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.used_ring = GuestAddress(32 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.used_ring_host =
+            mem_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.size = DEFAULT_VIRTQUEUE_SIZE;
+        queue_config.ready = true;
+        let queue = Arc::new(Mutex::new(Queue::new(queue_config, 1).unwrap()));
+        let interrupt_cb = Arc::new(Box::new(
+            move |_int_type: &VirtioInterruptType, _queue: Option<&Queue>, _needs_reset: bool| {
+                Ok(())
+            },
+        ) as VirtioInterrupt);
+
+        let blk_cfg = BlkDevConfig {
+            id: "blk0".to_string(),
+            iothread: Some(thread_name),
+            rerror: BlockErrorPolicy::Retry,
+            retry_interval: 10,
+            ..Default::default()
+        };
+        let retry_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let err_ctx = Rc::new(BlockErrorCtx::new(
+            &blk_cfg,
+            blk_cfg.iothread.clone(),
+            retry_evt.clone(),
+        ));
+        let status_addr = GuestAddress(0x1000);
+        let new_read_aiocb = || {
+            let req = Request {
+                desc_index: 0,
+                out_header: RequestOutHeader {
+                    request_type: VIRTIO_BLK_T_IN,
+                    io_prio: 0,
+                    sector: 0,
+                },
+                iovec: Vec::new(),
+                data_len: 0,
+                in_len: 1,
+                in_header: status_addr,
+                next: Box::new(None),
+            };
+            AioCb {
+                direct: false,
+                req_align: 1,
+                buf_align: 1,
+                file_fd: -1,
+                opcode: OpCode::Preadv,
+                iovec: Vec::new(),
+                offset: 0,
+                nbytes: 0,
+                user_data: 0,
+                iocompletecb: AioCompleteCb::new(
+                    queue.clone(),
+                    mem_space.clone(),
+                    Rc::new(req),
+                    interrupt_cb.clone(),
+                    0,
+                    err_ctx.clone(),
+                    Arc::new(BlockStats::default()),
+                ),
+            }
+        };
+        let used_idx = || {
+            mem_space
+                .read_object::<u16>(GuestAddress(queue_config.used_ring.0 + 2))
+                .unwrap()
+        };
+
+        // Transient EIO: the request is held for retry instead of being completed.
+        BlockIoHandler::complete_func(&new_read_aiocb(), -(libc::EIO as i64)).unwrap();
+        assert_eq!(used_idx(), 0);
+        assert_eq!(err_ctx.retry_reqs.borrow().len(), 1);
+        assert!(err_ctx.retry_reqs.borrow()[0]
+            .iocompletecb
+            .first_failure
+            .is_some());
+
+        // The retry timer kicks the retry event after retry-interval.
+        let mut wait = 10;
+        while retry_evt.read().is_err() {
+            thread::sleep(Duration::from_millis(100));
+            wait -= 1;
+            assert_ne!(wait, 0);
+        }
+
+        // The retried request succeeds.
+        let retry_aiocb = err_ctx.retry_reqs.borrow_mut().pop().unwrap();
+        BlockIoHandler::complete_func(&retry_aiocb, 0).unwrap();
+        assert_eq!(used_idx(), 1);
+        assert_eq!(
+            mem_space.read_object::<u8>(status_addr).unwrap(),
+            VIRTIO_BLK_S_OK
+        );
+
+        // Non-retryable error is reported directly.
+        BlockIoHandler::complete_func(&new_read_aiocb(), -(libc::EINVAL as i64)).unwrap();
+        assert_eq!(used_idx(), 2);
+        assert!(err_ctx.retry_reqs.borrow().is_empty());
+        assert_eq!(
+            mem_space.read_object::<u8>(status_addr).unwrap(),
+            VIRTIO_BLK_S_IOERR
+        );
+    }
 }
@@ -0,0 +1,334 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Error, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use util::bitmap::Bitmap;
+use vmm_sys_util::tempfile::TempFile;
+
+use crate::block::BlockDriverOps;
+
+/// Granularity of copy-on-write. It must be no less than the block size of the
+/// filesystem where the overlay file is, so that the data extents of the sparse
+/// overlay file are exactly the clusters written.
+const OVERLAY_CLUSTER_SIZE: u64 = 0x10000;
+/// Directory of the anonymous overlay if `TMPDIR` is not set. It's usually not a
+/// tmpfs, so the data written by guest doesn't occupy host memory.
+const DEFAULT_OVERLAY_DIR: &str = "/var/tmp";
+
+/// Create an anonymous overlay file in `TMPDIR`. The file is unlinked once it's created,
+/// so it's released when it's closed and the data written by guest is discarded at VM exit.
+pub fn anonymous_overlay() -> Result<File> {
+    let dir = std::env::var("TMPDIR").unwrap_or_else(|_| DEFAULT_OVERLAY_DIR.to_string());
+    let mut temp_file = TempFile::new_in(Path::new(&dir))
+        .with_context(|| format!("Failed to create anonymous overlay file in {}", dir))?;
+    temp_file
+        .remove()
+        .with_context(|| "Failed to unlink anonymous overlay file")?;
+    Ok(temp_file.into_file())
+}
+
+/// Read-only base image of the overlay.
+enum OverlayBase {
+    /// Raw image file.
+    File(File),
+    /// Image of a format which is mapped by the block driver, such as qcow2.
+    Driver(Box<dyn BlockDriverOps>),
+}
+
+impl OverlayBase {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        match self {
+            OverlayBase::File(file) => file.read_exact_at(buf, offset),
+            OverlayBase::Driver(driver) => driver.read_at(buf, offset),
+        }
+    }
+}
+
+/// Copy-on-write overlay on top of a read-only base image. Data written by guest goes
+/// to the sparse overlay file in whole clusters, and reads of the clusters never written
+/// fall through to the base image, which is never modified.
+pub struct BlockOverlay {
+    /// The read-only base image.
+    base: OverlayBase,
+    /// The writable overlay file, which has the same size as base image.
+    overlay: File,
+    /// Size of the disk in bytes.
+    size: u64,
+    /// Clusters which have been copied to the overlay file.
+    allocated: Bitmap<u64>,
+}
+
+impl BlockOverlay {
+    /// Create the overlay. The clusters written before are got from the data
+    /// extents of the overlay file.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The base image, which is opened read-only.
+    /// * `overlay` - The overlay file.
+    pub fn new(mut base: File, overlay: File) -> Result<Self> {
+        let size = base
+            .seek(SeekFrom::End(0))
+            .with_context(|| "Failed to get size of base image")?;
+        Self::with_base(OverlayBase::File(base), size, overlay)
+    }
+
+    /// Create the overlay on top of the image mapped by a block driver.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The block driver of base image, which is opened read-only.
+    /// * `overlay` - The overlay file.
+    pub(crate) fn with_driver(base: Box<dyn BlockDriverOps>, overlay: File) -> Result<Self> {
+        let size = base.disk_size();
+        Self::with_base(OverlayBase::Driver(base), size, overlay)
+    }
+
+    fn with_base(base: OverlayBase, size: u64, overlay: File) -> Result<Self> {
+        let overlay_size = overlay
+            .metadata()
+            .with_context(|| "Failed to get size of overlay file")?
+            .len();
+        if overlay_size > size {
+            bail!(
+                "Overlay file is {} bytes, which is larger than base image of {} bytes",
+                overlay_size,
+                size
+            );
+        }
+        if overlay_size < size {
+            overlay
+                .set_len(size)
+                .with_context(|| "Failed to extend overlay file")?;
+        }
+
+        let clusters = ((size + OVERLAY_CLUSTER_SIZE - 1) / OVERLAY_CLUSTER_SIZE) as usize;
+        let mut overlay = BlockOverlay {
+            base,
+            overlay,
+            size,
+            allocated: Bitmap::<u64>::new(clusters / 64 + 1),
+        };
+        overlay.load_allocated(clusters)?;
+        if overlay_size == 0 && overlay.allocated.find_next_bit(0)? < clusters {
+            bail!("Filesystem of overlay file doesn't support sparse file");
+        }
+
+        Ok(overlay)
+    }
+
+    /// Mark the clusters in the data extents of overlay file as allocated.
+    fn load_allocated(&mut self, clusters: usize) -> Result<()> {
+        let fd = self.overlay.as_raw_fd();
+        let mut offset = 0_u64;
+        while offset < self.size {
+            // SAFETY: fd is valid as the overlay file is opened.
+            let data = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+            if data < 0 {
+                let err = Error::last_os_error();
+                if err.raw_os_error() == Some(libc::ENXIO) {
+                    // No more data after offset.
+                    break;
+                }
+                bail!("Failed to seek data of overlay file: {:?}", err);
+            }
+            // SAFETY: fd is valid as the overlay file is opened.
+            let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+            if hole < 0 {
+                bail!(
+                    "Failed to seek hole of overlay file: {:?}",
+                    Error::last_os_error()
+                );
+            }
+
+            let start = data as usize / OVERLAY_CLUSTER_SIZE as usize;
+            let end = cmp::min(
+                (hole as u64 + OVERLAY_CLUSTER_SIZE - 1) / OVERLAY_CLUSTER_SIZE,
+                clusters as u64,
+            ) as usize;
+            for cluster in start..end {
+                self.allocated.set(cluster)?;
+            }
+            offset = hole as u64;
+        }
+        Ok(())
+    }
+
+    fn is_allocated(&self, offset: u64) -> bool {
+        self.allocated
+            .contain((offset / OVERLAY_CLUSTER_SIZE) as usize)
+            .unwrap_or(false)
+    }
+}
+
+impl BlockDriverOps for BlockOverlay {
+    fn disk_size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        let mut done = 0_usize;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let len = cmp::min(
+                buf.len() - done,
+                (OVERLAY_CLUSTER_SIZE - pos % OVERLAY_CLUSTER_SIZE) as usize,
+            );
+            if self.is_allocated(pos) {
+                self.overlay
+                    .read_exact_at(&mut buf[done..done + len], pos)?;
+            } else {
+                self.base.read_exact_at(&mut buf[done..done + len], pos)?;
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        let mut done = 0_usize;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let cluster_start = pos - pos % OVERLAY_CLUSTER_SIZE;
+            let cluster_end = cmp::min(cluster_start + OVERLAY_CLUSTER_SIZE, self.size);
+            let len = cmp::min(buf.len() - done, (cluster_end - pos) as usize);
+            let data = &buf[done..done + len];
+
+            if self.is_allocated(pos) || (pos == cluster_start && pos + len as u64 == cluster_end) {
+                self.overlay.write_all_at(data, pos)?;
+            } else {
+                // Copy the rest of the cluster from base image, as the whole cluster
+                // is read from overlay file once it's allocated.
+                let mut cluster = vec![0_u8; (cluster_end - cluster_start) as usize];
+                self.base.read_exact_at(&mut cluster, cluster_start)?;
+                let start = (pos - cluster_start) as usize;
+                cluster[start..start + len].copy_from_slice(data);
+                self.overlay.write_all_at(&cluster, cluster_start)?;
+            }
+            self.allocated
+                .set((pos / OVERLAY_CLUSTER_SIZE) as usize)
+                .map_err(|_| Error::from_raw_os_error(libc::EIO))?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.overlay.sync_all()
+    }
+
+    fn data_fd(&self) -> RawFd {
+        self.overlay.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use util::aio::Iovec;
+
+    fn iovec(buf: &mut [u8]) -> Vec<Iovec> {
+        vec![Iovec {
+            iov_base: buf.as_mut_ptr() as u64,
+            iov_len: buf.len() as u64,
+        }]
+    }
+
+    #[test]
+    fn test_block_overlay_rw() {
+        let size = 3 * OVERLAY_CLUSTER_SIZE as usize;
+        let base_file = TempFile::new().unwrap();
+        let overlay_file = TempFile::new().unwrap();
+        base_file.as_file().write_all(&vec![0xaa_u8; size]).unwrap();
+
+        let base = File::open(base_file.as_path()).unwrap();
+        let overlay = overlay_file.as_file().try_clone().unwrap();
+        let mut disk = BlockOverlay::new(base, overlay).unwrap();
+        assert_eq!(
+            overlay_file.as_file().metadata().unwrap().len(),
+            size as u64
+        );
+
+        // Partial write of the second cluster.
+        let offset = OVERLAY_CLUSTER_SIZE + 512;
+        let mut data = vec![0xbb_u8; 1024];
+        assert_eq!(disk.writev(&iovec(&mut data), offset), 1024);
+        // Write out of range.
+        assert!(disk.writev(&iovec(&mut data), size as u64 - 512) < 0);
+
+        let check = |disk: &BlockOverlay| {
+            let mut buf = vec![0_u8; size];
+            assert_eq!(disk.readv(&iovec(&mut buf), 0), size as i64);
+            for (i, byte) in buf.iter().enumerate() {
+                let written = i as u64 >= offset && (i as u64) < offset + 1024;
+                assert_eq!(*byte, if written { 0xbb } else { 0xaa });
+            }
+        };
+        check(&disk);
+        assert_eq!(disk.flush(), 0);
+
+        // Base image is never modified.
+        let mut base_data = vec![0_u8; size];
+        base_file
+            .as_file()
+            .read_exact_at(&mut base_data, 0)
+            .unwrap();
+        assert!(base_data.iter().all(|byte| *byte == 0xaa));
+
+        // The allocated clusters are restored from overlay file.
+        let base = File::open(base_file.as_path()).unwrap();
+        let overlay = overlay_file.as_file().try_clone().unwrap();
+        let disk = BlockOverlay::new(base, overlay).unwrap();
+        assert!(!disk.is_allocated(0));
+        assert!(disk.is_allocated(OVERLAY_CLUSTER_SIZE));
+        assert!(!disk.is_allocated(2 * OVERLAY_CLUSTER_SIZE));
+        check(&disk);
+    }
+
+    #[test]
+    fn test_block_overlay_anonymous() {
+        let size = OVERLAY_CLUSTER_SIZE as usize;
+        let base_file = TempFile::new().unwrap();
+        base_file.as_file().write_all(&vec![0xaa_u8; size]).unwrap();
+
+        let base = File::open(base_file.as_path()).unwrap();
+        let mut disk = BlockOverlay::new(base, anonymous_overlay().unwrap()).unwrap();
+        let mut data = vec![0xbb_u8; 512];
+        assert_eq!(disk.writev(&iovec(&mut data), 0), 512);
+        let mut buf = vec![0_u8; 1024];
+        assert_eq!(disk.readv(&iovec(&mut buf), 0), 1024);
+        assert!(buf[..512].iter().all(|byte| *byte == 0xbb));
+        assert!(buf[512..].iter().all(|byte| *byte == 0xaa));
+
+        // The data written by guest is discarded with the anonymous overlay.
+        drop(disk);
+        let base = File::open(base_file.as_path()).unwrap();
+        let disk = BlockOverlay::new(base, anonymous_overlay().unwrap()).unwrap();
+        assert!(!disk.is_allocated(0));
+        let mut base_data = vec![0_u8; size];
+        base_file
+            .as_file()
+            .read_exact_at(&mut base_data, 0)
+            .unwrap();
+        assert!(base_data.iter().all(|byte| *byte == 0xaa));
+    }
+}
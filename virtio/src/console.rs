@@ -17,14 +17,15 @@ use std::sync::{Arc, Mutex};
 use std::{cmp, usize};
 
 use super::{
-    Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace, VIRTIO_CONSOLE_F_SIZE,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_CONSOLE,
+    iov_to_buf, packed_ring_feature, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VirtioTrace, VIRTIO_CONSOLE_F_MULTIPORT, VIRTIO_CONSOLE_F_SIZE, VIRTIO_F_VERSION_1,
+    VIRTIO_TYPE_CONSOLE,
 };
 use crate::VirtioError;
 use address_space::AddressSpace;
 use anyhow::{anyhow, bail, Context, Result};
 use devices::legacy::{Chardev, InputReceiver};
-use log::{debug, error};
+use log::{debug, error, warn};
 use machine_manager::{
     config::{VirtioConsole, DEFAULT_VIRTQUEUE_SIZE},
     event_loop::EventLoop,
@@ -42,6 +43,17 @@ use vmm_sys_util::eventfd::EventFd;
 
 /// Number of virtqueues.
 const QUEUE_NUM_CONSOLE: usize = 2;
+/// Number of virtqueues with multiport: port0 receiveq/transmitq, control receiveq/transmitq.
+const QUEUE_NUM_CONSOLE_MULTIPORT: usize = 4;
+
+/// Port id used by guest for device-wide control messages.
+const VIRTIO_CONSOLE_BAD_ID: u32 = !0;
+/// Control events, refer to Virtio Spec.
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_PORT_ADD: u16 = 1;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
 
 const BUFF_SIZE: usize = 4096;
 
@@ -56,6 +68,17 @@ struct VirtioConsoleConfig {
 
 impl ByteCode for VirtioConsoleConfig {}
 
+/// Control message exchanged on the control virtqueues.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct VirtioConsoleControl {
+    id: u32,
+    event: u16,
+    value: u16,
+}
+
+impl ByteCode for VirtioConsoleControl {}
+
 impl VirtioConsoleConfig {
     /// Create configuration of virtio-console devices.
     pub fn new() -> Self {
@@ -237,6 +260,178 @@ impl EventNotifierHelper for ConsoleHandler {
     }
 }
 
+/// Handler of the control virtqueues, which announces the single named port to guest.
+struct ConsoleCtrlHandler {
+    input_queue: Arc<Mutex<Queue>>,
+    output_queue: Arc<Mutex<Queue>>,
+    output_queue_evt: Arc<EventFd>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    port_name: String,
+}
+
+impl ConsoleCtrlHandler {
+    fn output_handle(&mut self) {
+        let mut requests = Vec::new();
+        let mut queue_lock = self.output_queue.lock().unwrap();
+        while let Ok(elem) = queue_lock
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            if elem.desc_num == 0 {
+                break;
+            }
+            let mut ctrl = VirtioConsoleControl::default();
+            match iov_to_buf(&self.mem_space, &elem.out_iovec, ctrl.as_mut_bytes()) {
+                Ok(size) if size == std::mem::size_of::<VirtioConsoleControl>() => {
+                    requests.push(ctrl)
+                }
+                Ok(size) => error!("Invalid console control message size {}", size),
+                Err(e) => error!("Failed to read console control message: {:?}", e),
+            }
+
+            if let Err(ref e) = queue_lock.vring.add_used(&self.mem_space, elem.index, 0) {
+                error!(
+                    "Failed to add used ring for console control, index: {} {:?}",
+                    elem.index, e
+                );
+                break;
+            }
+        }
+        if let Err(ref e) =
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+        {
+            error!(
+                "Failed to trigger interrupt for console control, int-type {:?} {:?}",
+                VirtioInterruptType::Vring,
+                e
+            );
+        }
+        drop(queue_lock);
+
+        for ctrl in requests {
+            self.handle_ctrl_msg(ctrl);
+        }
+    }
+
+    fn handle_ctrl_msg(&mut self, ctrl: VirtioConsoleControl) {
+        match ctrl.event {
+            VIRTIO_CONSOLE_DEVICE_READY => {
+                if ctrl.value == 0 {
+                    error!("Guest failed to initialize console device");
+                    return;
+                }
+                self.send_ctrl_msg(0, VIRTIO_CONSOLE_PORT_ADD, 1, &[]);
+            }
+            VIRTIO_CONSOLE_PORT_READY => {
+                if ctrl.id != 0 || ctrl.value == 0 {
+                    error!("Guest failed to add console port {}", ctrl.id);
+                    return;
+                }
+                let name = self.port_name.clone();
+                self.send_ctrl_msg(0, VIRTIO_CONSOLE_PORT_NAME, 1, name.as_bytes());
+                // Guest blocks writing to the port until host side is opened.
+                self.send_ctrl_msg(0, VIRTIO_CONSOLE_PORT_OPEN, 1, &[]);
+            }
+            VIRTIO_CONSOLE_PORT_OPEN => {
+                debug!(
+                    "Guest {} console port {}",
+                    if ctrl.value == 0 { "closed" } else { "opened" },
+                    ctrl.id
+                );
+            }
+            _ => {
+                if ctrl.id == VIRTIO_CONSOLE_BAD_ID {
+                    warn!("Unsupported console control event {}", ctrl.event);
+                } else {
+                    warn!(
+                        "Unsupported console control event {} for port {}",
+                        ctrl.event, ctrl.id
+                    );
+                }
+            }
+        }
+    }
+
+    fn send_ctrl_msg(&mut self, id: u32, event: u16, value: u16, extra: &[u8]) {
+        let ctrl = VirtioConsoleControl { id, event, value };
+        let mut msg = ctrl.as_bytes().to_vec();
+        msg.extend_from_slice(extra);
+
+        let mut queue_lock = self.input_queue.lock().unwrap();
+        let elem = match queue_lock
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            Ok(elem) if elem.desc_num != 0 => elem,
+            _ => {
+                error!("No buffer for console control event {}", event);
+                return;
+            }
+        };
+
+        let mut write_count = 0_usize;
+        for elem_iov in elem.in_iovec.iter() {
+            if write_count >= msg.len() {
+                break;
+            }
+            let end = cmp::min(write_count + elem_iov.len as usize, msg.len());
+            let mut slice = &msg[write_count..end];
+            if let Err(ref e) =
+                self.mem_space
+                    .write(&mut slice, elem_iov.addr, (end - write_count) as u64)
+            {
+                error!(
+                    "Failed to write console control message: addr {:X} {:?}",
+                    elem_iov.addr.0, e
+                );
+                break;
+            }
+            write_count = end;
+        }
+
+        if let Err(ref e) =
+            queue_lock
+                .vring
+                .add_used(&self.mem_space, elem.index, write_count as u32)
+        {
+            error!(
+                "Failed to add used ring for console control, index: {} {:?}",
+                elem.index, e
+            );
+            return;
+        }
+        if let Err(ref e) =
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+        {
+            error!(
+                "Failed to trigger interrupt for console control, int-type {:?} {:?}",
+                VirtioInterruptType::Vring,
+                e
+            );
+        }
+    }
+}
+
+impl EventNotifierHelper for ConsoleCtrlHandler {
+    fn internal_notifiers(ctrl_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_handler = ctrl_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            cloned_handler.lock().unwrap().output_handle();
+            None
+        });
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            ctrl_handler.lock().unwrap().output_queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
 /// Status of console device.
 #[repr(C)]
 #[derive(Copy, Clone, Desc, ByteCode)]
@@ -258,6 +453,8 @@ pub struct Console {
     deactivate_evts: Vec<RawFd>,
     /// Character device for redirection.
     chardev: Arc<Mutex<Chardev>>,
+    /// Name of the port. If set, the port is announced to guest by multiport protocol.
+    port_name: Option<String>,
 }
 
 impl Console {
@@ -275,6 +472,7 @@ impl Console {
             },
             deactivate_evts: Vec::new(),
             chardev: Arc::new(Mutex::new(Chardev::new(console_cfg.chardev))),
+            port_name: console_cfg.name,
         }
     }
 }
@@ -282,7 +480,11 @@ impl Console {
 impl VirtioDevice for Console {
     /// Realize virtio console device.
     fn realize(&mut self) -> Result<()> {
-        self.state.device_features = 1_u64 << VIRTIO_F_VERSION_1 | 1_u64 << VIRTIO_CONSOLE_F_SIZE;
+        self.state.device_features =
+            1_u64 << VIRTIO_F_VERSION_1 | 1_u64 << VIRTIO_CONSOLE_F_SIZE | packed_ring_feature();
+        if self.port_name.is_some() {
+            self.state.device_features |= 1_u64 << VIRTIO_CONSOLE_F_MULTIPORT;
+        }
         self.chardev
             .lock()
             .unwrap()
@@ -303,7 +505,11 @@ impl VirtioDevice for Console {
 
     /// Get the count of virtio device queues.
     fn queue_num(&self) -> usize {
-        QUEUE_NUM_CONSOLE
+        if self.port_name.is_some() {
+            QUEUE_NUM_CONSOLE_MULTIPORT
+        } else {
+            QUEUE_NUM_CONSOLE
+        }
     }
 
     /// Get the queue size of virtio device.
@@ -361,8 +567,8 @@ impl VirtioDevice for Console {
             input_queue: queues[0].clone(),
             output_queue: queues[1].clone(),
             output_queue_evt: queue_evts.remove(0),
-            mem_space,
-            interrupt_cb,
+            mem_space: mem_space.clone(),
+            interrupt_cb: interrupt_cb.clone(),
             driver_features: self.state.driver_features,
             chardev: self.chardev.clone(),
         };
@@ -371,6 +577,25 @@ impl VirtioDevice for Console {
         let notifiers = EventNotifierHelper::internal_notifiers(dev.clone());
         register_event_helper(notifiers, None, &mut self.deactivate_evts)?;
 
+        let multiport = self.state.driver_features & (1_u64 << VIRTIO_CONSOLE_F_MULTIPORT) != 0;
+        if let (true, Some(port_name)) = (multiport, &self.port_name) {
+            if queues.len() < QUEUE_NUM_CONSOLE_MULTIPORT {
+                bail!("Control queues of console are not enabled by guest");
+            }
+            let ctrl_handler = ConsoleCtrlHandler {
+                input_queue: queues[2].clone(),
+                output_queue: queues[3].clone(),
+                output_queue_evt: queue_evts.remove(1),
+                mem_space,
+                interrupt_cb,
+                driver_features: self.state.driver_features,
+                port_name: port_name.clone(),
+            };
+            let notifiers =
+                EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(ctrl_handler)));
+            register_event_helper(notifiers, None, &mut self.deactivate_evts)?;
+        }
+
         self.chardev.lock().unwrap().set_input_callback(&dev);
         self.chardev.lock().unwrap().deactivated = false;
         Ok(())
@@ -422,10 +647,12 @@ mod tests {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            tls_creds: None,
         };
         let mut console = Console::new(VirtioConsole {
             id: "console".to_string(),
             chardev: chardev_cfg.clone(),
+            name: None,
         });
         let mut chardev = Chardev::new(chardev_cfg);
         chardev.output = Some(Arc::new(Mutex::new(std::io::stdout())));
@@ -493,10 +720,12 @@ mod tests {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            tls_creds: None,
         };
         let mut console = Console::new(VirtioConsole {
             id: "console".to_string(),
             chardev: chardev_cfg.clone(),
+            name: None,
         });
         let mut chardev = Chardev::new(chardev_cfg);
         chardev.output = Some(Arc::new(Mutex::new(std::io::stdout())));
@@ -520,4 +749,29 @@ mod tests {
         assert_eq!(console.read_config(offset, &mut read_data).is_ok(), true);
         assert_eq!(read_data, expect_data);
     }
+
+    #[test]
+    fn test_multiport_console() {
+        assert_eq!(size_of::<VirtioConsoleControl>(), 8);
+
+        let chardev_cfg = ChardevConfig {
+            id: "chardev".to_string(),
+            backend: ChardevType::Stdio,
+            tls_creds: None,
+        };
+        let console = Console::new(VirtioConsole {
+            id: "console".to_string(),
+            chardev: chardev_cfg.clone(),
+            name: None,
+        });
+        assert_eq!(console.queue_num(), QUEUE_NUM_CONSOLE);
+
+        let console = Console::new(VirtioConsole {
+            id: "port".to_string(),
+            chardev: chardev_cfg,
+            name: Some("org.qemu.guest_agent.0".to_string()),
+        });
+        assert_eq!(console.queue_num(), QUEUE_NUM_CONSOLE_MULTIPORT);
+        assert_eq!(console.port_name.as_deref(), Some("org.qemu.guest_agent.0"));
+    }
 }
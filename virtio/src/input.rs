@@ -0,0 +1,875 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use address_space::AddressSpace;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error};
+use machine_manager::{
+    config::{InputConfig, InputType, DEFAULT_VIRTQUEUE_SIZE},
+    event_loop::{register_event_helper, unregister_event_helper},
+};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
+use ui::input::{
+    register_keyboard, register_pointer, KeyboardOpts, PointerOpts, ABS_MAX, INPUT_POINT_LEFT,
+    INPUT_POINT_MIDDLE, INPUT_POINT_RIGHT,
+};
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use util::num_ops::read_u32;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::{
+    packed_ring_feature, ElemIovec, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VIRTIO_F_VERSION_1, VIRTIO_TYPE_INPUT,
+};
+use crate::error::VirtioError;
+
+/// Number of virtqueues: eventq and statusq.
+const QUEUE_NUM_INPUT: usize = 2;
+/// Max number of events waiting for the buffers of guest.
+const MAX_PENDING_EVENTS: usize = 256;
+
+/// Selectors of the device config space, refer to Virtio Spec.
+const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
+const VIRTIO_INPUT_CFG_ID_SERIAL: u8 = 0x02;
+const VIRTIO_INPUT_CFG_ID_DEVIDS: u8 = 0x03;
+const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;
+const VIRTIO_INPUT_CFG_ABS_INFO: u8 = 0x12;
+/// Size of the union in device config space.
+const VIRTIO_INPUT_CFG_DATA_SIZE: usize = 128;
+
+/// Event types and codes of evdev.
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const EV_LED: u16 = 0x11;
+const SYN_REPORT: u16 = 0x00;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const LED_NUML: u16 = 0x00;
+const LED_CAPSL: u16 = 0x01;
+const LED_SCROLLL: u16 = 0x02;
+
+/// Ids of the device reported to guest.
+const BUS_VIRTUAL: u16 = 0x06;
+const INPUT_VENDOR_ID: u16 = 0x0627;
+const INPUT_VERSION: u16 = 0x0001;
+
+/// Pointer buttons and wheel of the events from ui.
+const INPUT_BUTTON_WHEEL_UP: u32 = 0x08;
+const INPUT_BUTTON_WHEEL_DOWN: u32 = 0x10;
+const INPUT_BUTTON_MASK: u32 = 0x7;
+const POINTER_BUTTONS: [(u8, u16); 3] = [
+    (INPUT_POINT_LEFT, BTN_LEFT),
+    (INPUT_POINT_MIDDLE, BTN_MIDDLE),
+    (INPUT_POINT_RIGHT, BTN_RIGHT),
+];
+
+/// Keycodes from ui are scancodes of set 1, in which the keys with prefix 0xe0 are
+/// marked with 0x80.
+const SCANCODE_GREY: u16 = 0x80;
+/// The keys without prefix up to F12 have the same evdev codes as scancodes.
+const MAX_PLAIN_SCANCODE: u16 = 0x58;
+/// Scancodes of the keys with prefix 0xe0 and their evdev codes.
+const GREY_SCANCODES: [(u16, u16); 18] = [
+    (0x1c, 96),  // KEY_KPENTER
+    (0x1d, 97),  // KEY_RIGHTCTRL
+    (0x35, 98),  // KEY_KPSLASH
+    (0x37, 99),  // KEY_SYSRQ
+    (0x38, 100), // KEY_RIGHTALT
+    (0x47, 102), // KEY_HOME
+    (0x48, 103), // KEY_UP
+    (0x49, 104), // KEY_PAGEUP
+    (0x4b, 105), // KEY_LEFT
+    (0x4d, 106), // KEY_RIGHT
+    (0x4f, 107), // KEY_END
+    (0x50, 108), // KEY_DOWN
+    (0x51, 109), // KEY_PAGEDOWN
+    (0x52, 110), // KEY_INSERT
+    (0x53, 111), // KEY_DELETE
+    (0x5b, 125), // KEY_LEFTMETA
+    (0x5c, 126), // KEY_RIGHTMETA
+    (0x5d, 127), // KEY_COMPOSE
+];
+
+/// Event passed through virtqueues, refer to Virtio Spec.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct VirtioInputEvent {
+    ev_type: u16,
+    code: u16,
+    value: u32,
+}
+
+impl ByteCode for VirtioInputEvent {}
+
+impl VirtioInputEvent {
+    fn new(ev_type: u16, code: u16, value: u32) -> Self {
+        VirtioInputEvent {
+            ev_type,
+            code,
+            value,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VirtioInputAbsInfo {
+    min: u32,
+    max: u32,
+    fuzz: u32,
+    flat: u32,
+    res: u32,
+}
+
+impl ByteCode for VirtioInputAbsInfo {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VirtioInputDevIds {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+impl ByteCode for VirtioInputDevIds {}
+
+/// Device config space of virtio input, refer to Virtio Spec.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct VirtioInputConfig {
+    select: u8,
+    subsel: u8,
+    size: u8,
+    reserved: [u8; 5],
+    data: [u8; VIRTIO_INPUT_CFG_DATA_SIZE],
+}
+
+impl ByteCode for VirtioInputConfig {}
+
+impl Default for VirtioInputConfig {
+    fn default() -> Self {
+        VirtioInputConfig {
+            select: 0,
+            subsel: 0,
+            size: 0,
+            reserved: [0; 5],
+            data: [0; VIRTIO_INPUT_CFG_DATA_SIZE],
+        }
+    }
+}
+
+/// Convert the scancode from ui to evdev code.
+fn scancode_to_evdev(keycode: u16) -> Option<u16> {
+    if keycode & SCANCODE_GREY != 0 {
+        let scancode = keycode & !SCANCODE_GREY;
+        GREY_SCANCODES
+            .iter()
+            .find(|(grey, _)| *grey == scancode)
+            .map(|(_, code)| *code)
+    } else if (1..=MAX_PLAIN_SCANCODE).contains(&keycode) {
+        Some(keycode)
+    } else {
+        None
+    }
+}
+
+/// Codes of the event type supported by the type of input device.
+fn supported_codes(input_type: InputType, ev_type: u16) -> Vec<u16> {
+    match (input_type, ev_type) {
+        (InputType::Keyboard, EV_KEY) => (1..=MAX_PLAIN_SCANCODE)
+            .chain(GREY_SCANCODES.iter().map(|(_, code)| *code))
+            .collect(),
+        (InputType::Keyboard, EV_LED) => vec![LED_NUML, LED_CAPSL, LED_SCROLLL],
+        (InputType::Mouse, EV_KEY) | (InputType::Tablet, EV_KEY) => {
+            POINTER_BUTTONS.iter().map(|(_, code)| *code).collect()
+        }
+        (InputType::Mouse, EV_REL) => vec![REL_X, REL_Y, REL_WHEEL],
+        (InputType::Tablet, EV_REL) => vec![REL_WHEEL],
+        (InputType::Tablet, EV_ABS) => vec![ABS_X, ABS_Y],
+        _ => Vec::new(),
+    }
+}
+
+/// The eventq and the context to use it, which exist after device is activated.
+struct EventQueue {
+    queue: Arc<Mutex<Queue>>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+}
+
+/// Events of the virtio input device. It receives the input of ui, which is translated
+/// to evdev events and sent to guest through eventq.
+struct InputEvents {
+    input_type: InputType,
+    /// Events waiting for the buffers of guest.
+    pending: VecDeque<VirtioInputEvent>,
+    eventq: Option<EventQueue>,
+    /// Buttons pressed of the pointer.
+    buttons: u32,
+    /// Last position of the pointer, which is used to get the motion of mouse.
+    position: Option<(u32, u32)>,
+}
+
+impl InputEvents {
+    fn new(input_type: InputType) -> Self {
+        InputEvents {
+            input_type,
+            pending: VecDeque::new(),
+            eventq: None,
+            buttons: 0,
+            position: None,
+        }
+    }
+
+    /// Queue a group of events, followed by a report event.
+    fn push_events(&mut self, mut events: Vec<VirtioInputEvent>) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        events.push(VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0));
+        if self.pending.len() + events.len() > MAX_PENDING_EVENTS {
+            debug!("Virtio input event queue is full!");
+            // Return ok to ignore the events.
+            return Ok(());
+        }
+        self.pending.extend(events);
+        self.flush()
+    }
+
+    /// Send the pending events to guest with the buffers in eventq.
+    fn flush(&mut self) -> Result<()> {
+        let eventq = match self.eventq.as_ref() {
+            Some(eventq) => eventq,
+            None => return Ok(()),
+        };
+        let mut queue_lock = eventq.queue.lock().unwrap();
+        if !queue_lock.is_enabled() {
+            return Ok(());
+        }
+
+        let mut need_interrupt = false;
+        while let Some(event) = self.pending.front() {
+            let elem = queue_lock
+                .vring
+                .pop_avail(&eventq.mem_space, eventq.driver_features)
+                .with_context(|| "Failed to pop avail ring for virtio input eventq")?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            let len = write_event(&eventq.mem_space, &elem.in_iovec, event)?;
+            queue_lock
+                .vring
+                .add_used(&eventq.mem_space, elem.index, len)
+                .with_context(|| {
+                    format!(
+                        "Failed to add used ring for virtio input, index: {}",
+                        elem.index
+                    )
+                })?;
+            self.pending.pop_front();
+            need_interrupt = true;
+        }
+
+        if need_interrupt {
+            (eventq.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+                .with_context(|| {
+                    anyhow!(VirtioError::InterruptTrigger(
+                        "input",
+                        VirtioInterruptType::Vring
+                    ))
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_event(
+    mem_space: &AddressSpace,
+    in_iov: &[ElemIovec],
+    event: &VirtioInputEvent,
+) -> Result<u32> {
+    let buffer = event.as_bytes();
+    let mut offset = 0_usize;
+    for iov in in_iov {
+        let len = cmp::min(iov.len as usize, buffer.len() - offset);
+        if len == 0 {
+            break;
+        }
+        mem_space
+            .write(&mut buffer[offset..].as_ref(), iov.addr, len as u64)
+            .with_context(|| "Failed to write event for virtio input")?;
+        offset += len;
+    }
+
+    Ok(offset as u32)
+}
+
+impl KeyboardOpts for InputEvents {
+    fn do_key_event(&mut self, keycode: u16, down: bool) -> Result<()> {
+        let code = match scancode_to_evdev(keycode) {
+            Some(code) => code,
+            None => {
+                debug!("Unsupported keycode {:#x} for virtio input", keycode);
+                return Ok(());
+            }
+        };
+        self.push_events(vec![VirtioInputEvent::new(EV_KEY, code, down as u32)])
+    }
+}
+
+impl PointerOpts for InputEvents {
+    fn do_point_event(&mut self, button: u32, x: u32, y: u32) -> Result<()> {
+        let mut events = Vec::new();
+        let buttons = button & INPUT_BUTTON_MASK;
+        for (mask, code) in POINTER_BUTTONS.iter() {
+            let mask = *mask as u32;
+            if (buttons ^ self.buttons) & mask != 0 {
+                events.push(VirtioInputEvent::new(
+                    EV_KEY,
+                    *code,
+                    (buttons & mask != 0) as u32,
+                ));
+            }
+        }
+        self.buttons = buttons;
+
+        if button & INPUT_BUTTON_WHEEL_UP != 0 {
+            events.push(VirtioInputEvent::new(EV_REL, REL_WHEEL, 1));
+        } else if button & INPUT_BUTTON_WHEEL_DOWN != 0 {
+            events.push(VirtioInputEvent::new(EV_REL, REL_WHEEL, -1_i32 as u32));
+        }
+
+        match self.input_type {
+            InputType::Tablet => {
+                if self.position != Some((x, y)) {
+                    events.push(VirtioInputEvent::new(EV_ABS, ABS_X, x));
+                    events.push(VirtioInputEvent::new(EV_ABS, ABS_Y, y));
+                }
+            }
+            InputType::Mouse => {
+                // The motion of mouse is the change of the absolute position.
+                if let Some((last_x, last_y)) = self.position {
+                    if x != last_x {
+                        let dx = x as i32 - last_x as i32;
+                        events.push(VirtioInputEvent::new(EV_REL, REL_X, dx as u32));
+                    }
+                    if y != last_y {
+                        let dy = y as i32 - last_y as i32;
+                        events.push(VirtioInputEvent::new(EV_REL, REL_Y, dy as u32));
+                    }
+                }
+            }
+            InputType::Keyboard => (),
+        }
+        self.position = Some((x, y));
+
+        self.push_events(events)
+    }
+}
+
+struct InputHandler {
+    events: Arc<Mutex<InputEvents>>,
+    event_queue_evt: Arc<EventFd>,
+    status_queue: Arc<Mutex<Queue>>,
+    status_queue_evt: Arc<EventFd>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+}
+
+impl InputHandler {
+    /// Consume the status events from guest, e.g. LED of keyboard, which are not
+    /// reflected to ui.
+    fn process_status_queue(&mut self) -> Result<()> {
+        let mut queue_lock = self.status_queue.lock().unwrap();
+        if !queue_lock.is_enabled() {
+            return Ok(());
+        }
+
+        let mut need_interrupt = false;
+        while let Ok(elem) = queue_lock
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            if elem.desc_num == 0 {
+                break;
+            }
+            queue_lock
+                .vring
+                .add_used(&self.mem_space, elem.index, 0)
+                .with_context(|| {
+                    format!(
+                        "Failed to add used ring for virtio input, index: {}",
+                        elem.index
+                    )
+                })?;
+            need_interrupt = true;
+        }
+
+        if need_interrupt {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+                .with_context(|| {
+                    anyhow!(VirtioError::InterruptTrigger(
+                        "input",
+                        VirtioInterruptType::Vring
+                    ))
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl EventNotifierHelper for InputHandler {
+    fn internal_notifiers(input_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+        let locked_handler = input_handler.lock().unwrap();
+
+        // Register event notifier for eventq, the pending events are sent to the
+        // buffers added by guest.
+        let events = locked_handler.events.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            if let Err(ref e) = events.lock().unwrap().flush() {
+                error!("Failed to process eventq for virtio input, err: {:?}", e);
+            }
+            None
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            locked_handler.event_queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        ));
+
+        // Register event notifier for statusq.
+        let input_handler_clone = input_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            if let Err(ref e) = input_handler_clone.lock().unwrap().process_status_queue() {
+                error!("Failed to process statusq for virtio input, err: {:?}", e);
+            }
+            None
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            locked_handler.status_queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        ));
+
+        notifiers
+    }
+}
+
+/// State of virtio input device.
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct InputState {
+    /// Bitmask of features supported by the backend.
+    device_features: u64,
+    /// Bitmask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+}
+
+/// Virtio input device structure, which is a keyboard, mouse or tablet.
+pub struct Input {
+    /// Configuration of virtio input device.
+    input_cfg: InputConfig,
+    /// Device config space.
+    config_space: VirtioInputConfig,
+    /// Events from ui to guest.
+    events: Arc<Mutex<InputEvents>>,
+    /// The state of input device.
+    state: InputState,
+    /// Eventfd for device deactivate.
+    deactivate_evts: Vec<RawFd>,
+}
+
+impl Input {
+    pub fn new(input_cfg: InputConfig) -> Self {
+        let input_type = input_cfg.input_type;
+        Input {
+            input_cfg,
+            config_space: VirtioInputConfig::default(),
+            events: Arc::new(Mutex::new(InputEvents::new(input_type))),
+            state: InputState {
+                device_features: 0,
+                driver_features: 0,
+            },
+            deactivate_evts: Vec::new(),
+        }
+    }
+
+    /// Fill the data of config space selected by guest.
+    fn update_config(&mut self) {
+        let input_type = self.input_cfg.input_type;
+        let subsel = self.config_space.subsel;
+        let data = match (self.config_space.select, subsel) {
+            (VIRTIO_INPUT_CFG_ID_NAME, 0) => {
+                let name = match input_type {
+                    InputType::Keyboard => "StratoVirt Virtio Keyboard",
+                    InputType::Mouse => "StratoVirt Virtio Mouse",
+                    InputType::Tablet => "StratoVirt Virtio Tablet",
+                };
+                name.as_bytes().to_vec()
+            }
+            (VIRTIO_INPUT_CFG_ID_SERIAL, 0) => self
+                .input_cfg
+                .serial
+                .as_ref()
+                .map(|serial| serial.as_bytes().to_vec())
+                .unwrap_or_default(),
+            (VIRTIO_INPUT_CFG_ID_DEVIDS, 0) => {
+                let product = match input_type {
+                    InputType::Keyboard => 0x0001,
+                    InputType::Mouse => 0x0002,
+                    InputType::Tablet => 0x0003,
+                };
+                let ids = VirtioInputDevIds {
+                    bustype: BUS_VIRTUAL,
+                    vendor: INPUT_VENDOR_ID,
+                    product,
+                    version: INPUT_VERSION,
+                };
+                ids.as_bytes().to_vec()
+            }
+            (VIRTIO_INPUT_CFG_EV_BITS, ev_type) => {
+                let codes = supported_codes(input_type, ev_type as u16);
+                let mut bitmap = Vec::new();
+                for code in codes {
+                    let index = code as usize / 8;
+                    if bitmap.len() <= index {
+                        bitmap.resize(index + 1, 0_u8);
+                    }
+                    bitmap[index] |= 1 << (code % 8);
+                }
+                bitmap
+            }
+            (VIRTIO_INPUT_CFG_ABS_INFO, axis)
+                if supported_codes(input_type, EV_ABS).contains(&(axis as u16)) =>
+            {
+                let abs_info = VirtioInputAbsInfo {
+                    max: ABS_MAX as u32,
+                    ..Default::default()
+                };
+                abs_info.as_bytes().to_vec()
+            }
+            _ => Vec::new(),
+        };
+
+        let size = cmp::min(data.len(), VIRTIO_INPUT_CFG_DATA_SIZE);
+        self.config_space.data = [0; VIRTIO_INPUT_CFG_DATA_SIZE];
+        self.config_space.data[..size].copy_from_slice(&data[..size]);
+        self.config_space.size = size as u8;
+    }
+}
+
+impl VirtioDevice for Input {
+    /// Realize virtio input device.
+    fn realize(&mut self) -> Result<()> {
+        self.state.device_features = 1 << VIRTIO_F_VERSION_1 as u64 | packed_ring_feature();
+        match self.input_cfg.input_type {
+            InputType::Keyboard => register_keyboard(&self.input_cfg.id, self.events.clone()),
+            InputType::Mouse | InputType::Tablet => {
+                register_pointer(&self.input_cfg.id, self.events.clone())
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the virtio device type, refer to Virtio Spec.
+    fn device_type(&self) -> u32 {
+        VIRTIO_TYPE_INPUT
+    }
+
+    /// Get the count of virtio device queues.
+    fn queue_num(&self) -> usize {
+        QUEUE_NUM_INPUT
+    }
+
+    /// Get the queue size of virtio device.
+    fn queue_size(&self) -> u16 {
+        DEFAULT_VIRTQUEUE_SIZE
+    }
+
+    /// Get device features from host.
+    fn get_device_features(&self, features_select: u32) -> u32 {
+        read_u32(self.state.device_features, features_select)
+    }
+
+    /// Set driver features by guest.
+    fn set_driver_features(&mut self, page: u32, value: u32) {
+        self.state.driver_features = self.checked_driver_features(page, value);
+    }
+
+    /// Get driver features by guest.
+    fn get_driver_features(&self, features_select: u32) -> u32 {
+        read_u32(self.state.driver_features, features_select)
+    }
+
+    /// Read data of config from guest.
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
+        let config_slice = self.config_space.as_bytes();
+        let config_len = config_slice.len() as u64;
+        if offset
+            .checked_add(data.len() as u64)
+            .filter(|&end| end <= config_len)
+            .is_none()
+        {
+            return Err(anyhow!(VirtioError::DevConfigOverflow(offset, config_len)));
+        }
+
+        let read_end = offset as usize + data.len();
+        data.write_all(&config_slice[offset as usize..read_end])?;
+
+        Ok(())
+    }
+
+    /// Write data to config from guest, only select and subsel are writable.
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let config_slice = self.config_space.as_mut_bytes();
+        let config_len = config_slice.len() as u64;
+        if offset
+            .checked_add(data.len() as u64)
+            .filter(|&end| end <= config_len)
+            .is_none()
+        {
+            return Err(anyhow!(VirtioError::DevConfigOverflow(offset, config_len)));
+        }
+
+        config_slice[(offset as usize)..(offset as usize + data.len())].copy_from_slice(data);
+        self.update_config();
+
+        Ok(())
+    }
+
+    /// Activate the virtio device, this function is called by vcpu thread when frontend
+    /// virtio driver is ready and write `DRIVER_OK` to backend.
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queues: &[Arc<Mutex<Queue>>],
+        mut queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        self.events.lock().unwrap().eventq = Some(EventQueue {
+            queue: queues[0].clone(),
+            mem_space: mem_space.clone(),
+            interrupt_cb: interrupt_cb.clone(),
+            driver_features: self.state.driver_features,
+        });
+
+        let handler = InputHandler {
+            events: self.events.clone(),
+            event_queue_evt: queue_evts.remove(0),
+            status_queue: queues[1].clone(),
+            status_queue_evt: queue_evts.remove(0),
+            mem_space,
+            interrupt_cb,
+            driver_features: self.state.driver_features,
+        };
+
+        let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
+        register_event_helper(notifiers, None, &mut self.deactivate_evts)?;
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        let mut locked_events = self.events.lock().unwrap();
+        locked_events.eventq = None;
+        locked_events.pending.clear();
+        drop(locked_events);
+        unregister_event_helper(None, &mut self.deactivate_evts)
+    }
+}
+
+impl StateTransfer for Input {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        Ok(self.state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        self.state = *InputState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::error::MigrationError::FromBytesError("INPUT")))?;
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        if let Some(alias) = MigrationManager::get_desc_alias(&InputState::descriptor().name) {
+            alias
+        } else {
+            !0
+        }
+    }
+}
+
+impl MigrationHook for Input {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input_config(input_type: InputType, serial: Option<&str>) -> InputConfig {
+        InputConfig {
+            id: "input0".to_string(),
+            input_type,
+            serial: serial.map(|serial| serial.to_string()),
+        }
+    }
+
+    fn select_config(input: &mut Input, select: u8, subsel: u8) -> Vec<u8> {
+        input.write_config(0, &[select, subsel]).unwrap();
+        let mut size = [0_u8];
+        input.read_config(2, &mut size).unwrap();
+        let mut data = vec![0_u8; size[0] as usize];
+        input.read_config(8, &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_input_config_space() {
+        let mut input = Input::new(input_config(InputType::Tablet, Some("tablet0")));
+        assert_eq!(input.device_type(), VIRTIO_TYPE_INPUT);
+        assert_eq!(input.queue_num(), QUEUE_NUM_INPUT);
+
+        assert_eq!(
+            select_config(&mut input, VIRTIO_INPUT_CFG_ID_NAME, 0),
+            b"StratoVirt Virtio Tablet".to_vec()
+        );
+        assert_eq!(
+            select_config(&mut input, VIRTIO_INPUT_CFG_ID_SERIAL, 0),
+            b"tablet0".to_vec()
+        );
+        assert_eq!(
+            select_config(&mut input, VIRTIO_INPUT_CFG_ID_DEVIDS, 0),
+            vec![0x06, 0x00, 0x27, 0x06, 0x03, 0x00, 0x01, 0x00]
+        );
+
+        // Buttons of pointer: BTN_LEFT, BTN_RIGHT and BTN_MIDDLE.
+        let bits = select_config(&mut input, VIRTIO_INPUT_CFG_EV_BITS, EV_KEY as u8);
+        assert_eq!(bits.len(), 35);
+        assert_eq!(bits[34], 0x07);
+        let bits = select_config(&mut input, VIRTIO_INPUT_CFG_EV_BITS, EV_ABS as u8);
+        assert_eq!(bits, vec![0x03]);
+        assert!(select_config(&mut input, VIRTIO_INPUT_CFG_EV_BITS, EV_LED as u8).is_empty());
+
+        let abs_info = select_config(&mut input, VIRTIO_INPUT_CFG_ABS_INFO, ABS_Y as u8);
+        assert_eq!(abs_info.len(), 20);
+        assert_eq!(abs_info[4..8], (ABS_MAX as u32).to_le_bytes());
+        assert!(select_config(&mut input, VIRTIO_INPUT_CFG_ABS_INFO, 0x10).is_empty());
+
+        // Unknown selector.
+        assert!(select_config(&mut input, 0x20, 0).is_empty());
+        // Out of config space.
+        let mut data = [0_u8; 4];
+        assert!(input.read_config(134, &mut data).is_err());
+        assert!(input.write_config(136, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_input_keyboard_events() {
+        let mut input = Input::new(input_config(InputType::Keyboard, None));
+        assert!(select_config(&mut input, VIRTIO_INPUT_CFG_ID_SERIAL, 0).is_empty());
+        let bits = select_config(&mut input, VIRTIO_INPUT_CFG_EV_BITS, EV_LED as u8);
+        assert_eq!(bits, vec![0x07]);
+
+        let mut events = InputEvents::new(InputType::Keyboard);
+        // Key 'A' is pressed.
+        events.do_key_event(0x1e, true).unwrap();
+        // Key 'Up' is released.
+        events.do_key_event(0x48 | SCANCODE_GREY, false).unwrap();
+        // Unsupported key.
+        events.do_key_event(0x10 | SCANCODE_GREY, true).unwrap();
+        let pending: Vec<VirtioInputEvent> = events.pending.iter().copied().collect();
+        assert_eq!(
+            pending,
+            vec![
+                VirtioInputEvent::new(EV_KEY, 30, 1),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+                VirtioInputEvent::new(EV_KEY, 103, 0),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+
+        // The events are dropped if guest doesn't receive them.
+        for _ in 0..MAX_PENDING_EVENTS {
+            events.do_key_event(0x1e, true).unwrap();
+        }
+        assert_eq!(events.pending.len(), MAX_PENDING_EVENTS);
+    }
+
+    #[test]
+    fn test_input_pointer_events() {
+        let mut events = InputEvents::new(InputType::Mouse);
+        events.do_point_event(0, 100, 100).unwrap();
+        assert!(events.pending.is_empty());
+        events.do_point_event(1, 90, 110).unwrap();
+        events
+            .do_point_event(INPUT_BUTTON_WHEEL_DOWN, 90, 110)
+            .unwrap();
+        let pending: Vec<VirtioInputEvent> = events.pending.drain(..).collect();
+        assert_eq!(
+            pending,
+            vec![
+                VirtioInputEvent::new(EV_KEY, BTN_LEFT, 1),
+                VirtioInputEvent::new(EV_REL, REL_X, -10_i32 as u32),
+                VirtioInputEvent::new(EV_REL, REL_Y, 10),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+                VirtioInputEvent::new(EV_KEY, BTN_LEFT, 0),
+                VirtioInputEvent::new(EV_REL, REL_WHEEL, -1_i32 as u32),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+
+        let mut events = InputEvents::new(InputType::Tablet);
+        events.do_point_event(4, 0x100, 0x200).unwrap();
+        let pending: Vec<VirtioInputEvent> = events.pending.drain(..).collect();
+        assert_eq!(
+            pending,
+            vec![
+                VirtioInputEvent::new(EV_KEY, BTN_RIGHT, 1),
+                VirtioInputEvent::new(EV_ABS, ABS_X, 0x100),
+                VirtioInputEvent::new(EV_ABS, ABS_Y, 0x200),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+    }
+}
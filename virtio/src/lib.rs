@@ -27,11 +27,15 @@
 
 mod balloon;
 pub mod block;
+mod block_overlay;
 mod console;
 pub mod error;
 #[cfg(not(target_env = "musl"))]
 mod gpu;
+#[cfg(not(target_env = "musl"))]
+mod input;
 mod net;
+mod qcow2;
 mod rng;
 mod scsi;
 pub mod vhost;
@@ -40,12 +44,14 @@ mod virtio_pci;
 mod virtqueue;
 pub use anyhow::Result;
 pub use balloon::*;
-pub use block::{Block, BlockState};
+pub use block::{qmp_block_resize, qmp_block_set_io_throttle, Block, BlockState};
 pub use console::{Console, VirtioConsoleState};
 pub use error::VirtioError;
 pub use error::*;
 #[cfg(not(target_env = "musl"))]
 pub use gpu::*;
+#[cfg(not(target_env = "musl"))]
+pub use input::{Input, InputState};
 use log::{error, warn};
 pub use net::*;
 pub use rng::{Rng, RngState};
@@ -66,6 +72,7 @@ use address_space::AddressSpace;
 use anyhow::anyhow;
 use anyhow::bail;
 use machine_manager::config::ConfigCheck;
+use once_cell::sync::OnceCell;
 use util::aio::mem_to_buf;
 use util::num_ops::write_u32;
 use vmm_sys_util::eventfd::EventFd;
@@ -75,6 +82,25 @@ pub fn virtio_has_feature(feature: u64, fbit: u32) -> bool {
     feature & (1 << fbit) != 0
 }
 
+/// Offer packed virtqueue to guest or not, split virtqueue is always supported.
+static PACKED_RING_FEATURE: OnceCell<bool> = OnceCell::new();
+
+pub fn set_packed_ring_feature(v: bool) {
+    if let Err(v) = PACKED_RING_FEATURE.set(v) {
+        error!("Failed to set packed ring feature: {}", v);
+    }
+}
+
+/// Get the feature bits of packed virtqueue for the devices processing the virtqueue
+/// themselves, it's empty unless packed virtqueue is enabled.
+pub fn packed_ring_feature() -> u64 {
+    if let Some(&true) = PACKED_RING_FEATURE.get() {
+        1_u64 << VIRTIO_F_RING_PACKED
+    } else {
+        0
+    }
+}
+
 /// Identifier of different virtio device, refer to Virtio Spec.
 pub const VIRTIO_TYPE_NET: u32 = 1;
 pub const VIRTIO_TYPE_BLOCK: u32 = 2;
@@ -83,6 +109,7 @@ pub const VIRTIO_TYPE_RNG: u32 = 4;
 pub const VIRTIO_TYPE_BALLOON: u32 = 5;
 pub const VIRTIO_TYPE_SCSI: u32 = 8;
 pub const VIRTIO_TYPE_GPU: u32 = 16;
+pub const VIRTIO_TYPE_INPUT: u32 = 18;
 pub const VIRTIO_TYPE_VSOCK: u32 = 19;
 pub const VIRTIO_TYPE_FS: u32 = 26;
 
@@ -142,8 +169,12 @@ pub const VIRTIO_NET_F_CTRL_RX_EXTRA: u32 = 20;
 pub const VIRTIO_NET_F_MQ: u32 = 22;
 /// Set Mac Address through control channel.
 pub const VIRTIO_NET_F_CTRL_MAC_ADDR: u32 = 23;
+/// Device supports notifications coalescing.
+pub const VIRTIO_NET_F_NOTF_COAL: u32 = 53;
 /// Configuration cols and rows are valid.
 pub const VIRTIO_CONSOLE_F_SIZE: u64 = 0;
+/// Device has support for multiple ports and control virtqueues.
+pub const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1;
 /// Maximum size of any single segment is in size_max.
 pub const VIRTIO_BLK_F_SIZE_MAX: u32 = 1;
 /// Maximum number of segments in a request is in seg_max.
@@ -207,6 +238,13 @@ pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u16 = 0;
 pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN: u16 = 1;
 /// The maximum pairs of multiple queue.
 pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX: u16 = 0x8000;
+
+/// The driver can send control commands for notifications coalescing.
+pub const VIRTIO_NET_CTRL_NOTF_COAL: u8 = 6;
+/// The driver sets the tx notifications coalescing parameters.
+pub const VIRTIO_NET_CTRL_NOTF_COAL_TX_SET: u8 = 0;
+/// The driver sets the rx notifications coalescing parameters.
+pub const VIRTIO_NET_CTRL_NOTF_COAL_RX_SET: u8 = 1;
 /// Support more than one virtqueue.
 pub const VIRTIO_BLK_F_MQ: u32 = 12;
 
@@ -230,6 +268,12 @@ pub const VIRTIO_BLK_T_OUT: u32 = 1;
 pub const VIRTIO_BLK_T_FLUSH: u32 = 4;
 /// Device id
 pub const VIRTIO_BLK_T_GET_ID: u32 = 8;
+/// Discard.
+pub const VIRTIO_BLK_T_DISCARD: u32 = 11;
+/// Write zeroes.
+pub const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+/// The flag of write zeroes request which allows the device to deallocate the sectors.
+pub const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1;
 /// Device id length
 pub const VIRTIO_BLK_ID_BYTES: u32 = 20;
 /// Success
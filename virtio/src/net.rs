@@ -22,11 +22,12 @@ use std::sync::{Arc, Mutex};
 use std::{cmp, fs, mem};
 
 use super::{
-    Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioNetHdr, VirtioTrace,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC,
-    VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
+    packed_ring_feature, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioNetHdr,
+    VirtioTrace, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
+    VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MAC_TABLE_SET,
+    VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_NOTF_COAL, VIRTIO_NET_CTRL_NOTF_COAL_RX_SET,
+    VIRTIO_NET_CTRL_NOTF_COAL_TX_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
     VIRTIO_NET_CTRL_RX_ALLUNI, VIRTIO_NET_CTRL_RX_NOBCAST, VIRTIO_NET_CTRL_RX_NOMULTI,
     VIRTIO_NET_CTRL_RX_NOUNI, VIRTIO_NET_CTRL_RX_PROMISC, VIRTIO_NET_CTRL_VLAN,
     VIRTIO_NET_CTRL_VLAN_ADD, VIRTIO_NET_CTRL_VLAN_DEL, VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM,
@@ -34,7 +35,7 @@ use super::{
     VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN,
     VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO,
     VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
-    VIRTIO_NET_F_MQ, VIRTIO_NET_OK, VIRTIO_TYPE_NET,
+    VIRTIO_NET_F_MQ, VIRTIO_NET_F_NOTF_COAL, VIRTIO_NET_OK, VIRTIO_TYPE_NET,
 };
 use crate::{
     iov_discard_front, iov_to_buf, mem_to_buf, report_virtio_error, virtio_has_feature, ElemIovec,
@@ -45,12 +46,13 @@ use anyhow::{anyhow, bail, Context, Result};
 use log::{error, warn};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use machine_manager::{
-    config::{ConfigCheck, NetworkInterfaceConfig},
+    config::{ConfigCheck, NetOffloadConfig, NetworkInterfaceConfig, MAX_RX_COALESCE_USECS},
     event_loop::EventLoop,
+    machine::{register_net_stats, unregister_net_stats, NetStats},
 };
 use migration::{
-    migration::Migratable, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
-    StateTransfer,
+    migration::Migratable, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
+    MigrationManager, StateTransfer,
 };
 use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
@@ -160,6 +162,18 @@ struct CtrlMacInfo {
     multi_mac_of: bool,
 }
 
+/// The parameters of notifications coalescing, zero usecs means no coalescing.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CtrlCoalesce {
+    /// Max number of used buffers before notifying the driver, 0 means no limit.
+    max_packets: u32,
+    /// Max time(in microseconds) to delay the notification.
+    usecs: u32,
+}
+
+impl ByteCode for CtrlCoalesce {}
+
 pub struct CtrlInfo {
     /// The control rx mode for packet receive filtering.
     rx_mode: CtrlRxMode,
@@ -167,6 +181,10 @@ pub struct CtrlInfo {
     mac_info: CtrlMacInfo,
     /// The map of all the vlan ids.
     vlan_map: HashMap<u16, u32>,
+    /// The notifications coalescing parameters of rx queues.
+    rx_coal: CtrlCoalesce,
+    /// The notifications coalescing parameters of tx queues.
+    tx_coal: CtrlCoalesce,
     /// The net device status.
     state: Arc<Mutex<VirtioNetState>>,
 }
@@ -177,6 +195,8 @@ impl CtrlInfo {
             rx_mode: CtrlRxMode::default(),
             mac_info: CtrlMacInfo::default(),
             vlan_map: HashMap::new(),
+            rx_coal: CtrlCoalesce::default(),
+            tx_coal: CtrlCoalesce::default(),
             state,
         }
     }
@@ -396,6 +416,44 @@ impl CtrlInfo {
         ack
     }
 
+    fn handle_notf_coal(
+        &mut self,
+        mem_space: &AddressSpace,
+        cmd: u8,
+        data_iovec: &mut Vec<ElemIovec>,
+    ) -> u8 {
+        let mut coal = CtrlCoalesce::default();
+        match get_buf_and_discard(mem_space, data_iovec, coal.as_mut_bytes()) {
+            Ok(iovec) => *data_iovec = iovec,
+            Err(e) => {
+                error!("Failed to get notifications coalescing parameters {:?}", e);
+                return VIRTIO_NET_ERR;
+            }
+        }
+
+        if coal.usecs > MAX_RX_COALESCE_USECS {
+            error!(
+                "Invalid notifications coalescing usecs {}, max is {}",
+                coal.usecs, MAX_RX_COALESCE_USECS
+            );
+            return VIRTIO_NET_ERR;
+        }
+
+        match cmd {
+            VIRTIO_NET_CTRL_NOTF_COAL_TX_SET => self.tx_coal = coal,
+            VIRTIO_NET_CTRL_NOTF_COAL_RX_SET => self.rx_coal = coal,
+            _ => {
+                error!(
+                    "Invalid cmd {} when handling control notifications coalescing",
+                    cmd
+                );
+                return VIRTIO_NET_ERR;
+            }
+        }
+
+        VIRTIO_NET_OK
+    }
+
     fn filter_packets(&mut self, buf: &[u8]) -> bool {
         // Broadcast address: 0xff:0xff:0xff:0xff:0xff:0xff.
         let bcast = [0xff; MAC_ADDR_LEN];
@@ -592,6 +650,13 @@ impl NetCtrlHandler {
                         &mut data_iovec,
                     );
                 }
+                VIRTIO_NET_CTRL_NOTF_COAL => {
+                    ack = self.ctrl.ctrl_info.lock().unwrap().handle_notf_coal(
+                        &self.mem_space,
+                        ctrl_hdr.cmd,
+                        &mut data_iovec,
+                    );
+                }
                 _ => {
                     error!(
                         "Control queue header class {} not supported",
@@ -691,6 +756,69 @@ impl RxVirtio {
     }
 }
 
+/// Defer the used ring notification of a queue until enough used buffers
+/// are accumulated or the coalescing timer expires.
+struct NotifyCoalesce {
+    /// Number of used buffers which have not been notified to the driver.
+    pending: u32,
+    /// If the coalescing timer has been armed.
+    timer_armed: bool,
+    /// The eventfd written when the coalescing timer expires.
+    timer_evt: Arc<EventFd>,
+}
+
+impl NotifyCoalesce {
+    fn new() -> Result<Self> {
+        Ok(NotifyCoalesce {
+            pending: 0,
+            timer_armed: false,
+            timer_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+        })
+    }
+
+    /// Account a used buffer, return true if the driver should be notified now.
+    fn count_used(&mut self, coal: CtrlCoalesce, iothread: Option<&String>) -> bool {
+        if coal.usecs == 0 {
+            return true;
+        }
+
+        self.pending += 1;
+        if coal.max_packets != 0 && self.pending >= coal.max_packets {
+            self.pending = 0;
+            return true;
+        }
+
+        if !self.timer_armed {
+            let ctx = match EventLoop::get_ctx(iothread) {
+                Some(ctx) => ctx,
+                None => {
+                    error!("Failed to get ctx to arm the coalescing timer for net");
+                    self.pending = 0;
+                    return true;
+                }
+            };
+            let timer_evt = self.timer_evt.clone();
+            let func = Box::new(move || {
+                if let Err(e) = timer_evt.write(1) {
+                    error!("Failed to write coalescing timer event for net: {:?}", e);
+                }
+            });
+            ctx.delay_call(func, coal.usecs as u64 * 1000);
+            self.timer_armed = true;
+        }
+        false
+    }
+
+    /// Called when the coalescing timer expires, return true if some used
+    /// buffers are still waiting for notification.
+    fn flush(&mut self) -> bool {
+        self.timer_armed = false;
+        let pending = self.pending != 0;
+        self.pending = 0;
+        pending
+    }
+}
+
 struct NetIoHandler {
     rx: RxVirtio,
     tx: TxVirtio,
@@ -705,6 +833,10 @@ struct NetIoHandler {
     is_listening: bool,
     ctrl_info: Arc<Mutex<CtrlInfo>>,
     queue_size: u16,
+    iothread: Option<String>,
+    rx_coal: NotifyCoalesce,
+    tx_coal: NotifyCoalesce,
+    stats: Arc<NetStats>,
 }
 
 impl NetIoHandler {
@@ -767,6 +899,7 @@ impl NetIoHandler {
             return Ok(());
         }
 
+        let coal = self.ctrl_info.lock().unwrap().rx_coal;
         let mut rx_packets = 0;
         while let Some(tap) = self.tap.as_mut() {
             let elem = queue
@@ -830,10 +963,13 @@ impl NetIoHandler {
                         elem.index, size
                     )
                 })?;
+            self.stats
+                .account_rx((size as usize - NET_HDR_LENGTH) as u64);
 
-            if queue
-                .vring
-                .should_notify(&self.mem_space, self.driver_features)
+            if self.rx_coal.count_used(coal, self.iothread.as_ref())
+                && queue
+                    .vring
+                    .should_notify(&self.mem_space, self.driver_features)
             {
                 (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
                     .with_context(|| {
@@ -888,6 +1024,7 @@ impl NetIoHandler {
         if !queue.is_enabled() {
             return Ok(());
         }
+        let coal = self.ctrl_info.lock().unwrap().tx_coal;
         let mut tx_packets = 0;
         loop {
             let elem = queue
@@ -917,15 +1054,21 @@ impl NetIoHandler {
                 })?;
                 return Ok(());
             }
+            if tap_fd != -1 {
+                let size: usize = iovecs.iter().map(|iov| iov.iov_len).sum();
+                self.stats
+                    .account_tx(size.saturating_sub(NET_HDR_LENGTH) as u64);
+            }
 
             queue
                 .vring
                 .add_used(&self.mem_space, elem.index, 0)
                 .with_context(|| format!("Net tx: Failed to add used ring {}", elem.index))?;
 
-            if queue
-                .vring
-                .should_notify(&self.mem_space, self.driver_features)
+            if self.tx_coal.count_used(coal, self.iothread.as_ref())
+                && queue
+                    .vring
+                    .should_notify(&self.mem_space, self.driver_features)
             {
                 (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
                     .with_context(|| {
@@ -949,6 +1092,34 @@ impl NetIoHandler {
         Ok(())
     }
 
+    fn flush_coalesced(&mut self, is_rx: bool) -> Result<()> {
+        let (coal, queue) = if is_rx {
+            (&mut self.rx_coal, &self.rx.queue)
+        } else {
+            (&mut self.tx_coal, &self.tx.queue)
+        };
+        if !coal.flush() {
+            return Ok(());
+        }
+
+        let queue = queue.lock().unwrap();
+        if queue
+            .vring
+            .should_notify(&self.mem_space, self.driver_features)
+        {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false).with_context(
+                || {
+                    anyhow!(VirtioError::InterruptTrigger(
+                        "net",
+                        VirtioInterruptType::Vring
+                    ))
+                },
+            )?;
+            self.trace_send_interrupt("Net".to_string());
+        }
+        Ok(())
+    }
+
     fn update_evt_handler(net_io: &Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut locked_net_io = net_io.lock().unwrap();
         locked_net_io.tap = match locked_net_io.receiver.recv() {
@@ -968,6 +1139,8 @@ impl NetIoHandler {
             locked_net_io.update_evt.as_raw_fd(),
             locked_net_io.rx.queue_evt.as_raw_fd(),
             locked_net_io.tx.queue_evt.as_raw_fd(),
+            locked_net_io.rx_coal.timer_evt.as_raw_fd(),
+            locked_net_io.tx_coal.timer_evt.as_raw_fd(),
         ];
         if old_tap_fd != -1 {
             notifiers_fds.push(old_tap_fd);
@@ -1092,6 +1265,38 @@ impl EventNotifierHelper for NetIoHandler {
             EventSet::IN,
         ));
 
+        // Register event notifiers for the coalescing timers of rx and tx.
+        for is_rx in [true, false] {
+            let cloned_net_io = net_io.clone();
+            let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                let mut locked_net_io = cloned_net_io.lock().unwrap();
+                if locked_net_io.device_broken.load(Ordering::SeqCst) {
+                    return None;
+                }
+                if let Err(ref e) = locked_net_io.flush_coalesced(is_rx) {
+                    error!("Failed to flush coalesced notification for net, {:?}", e);
+                    report_virtio_error(
+                        locked_net_io.interrupt_cb.clone(),
+                        locked_net_io.driver_features,
+                        &locked_net_io.device_broken,
+                    );
+                }
+                None
+            });
+            let timer_fd = if is_rx {
+                locked_net_io.rx_coal.timer_evt.as_raw_fd()
+            } else {
+                locked_net_io.tx_coal.timer_evt.as_raw_fd()
+            };
+            notifiers.push(build_event_notifier(
+                timer_fd,
+                Some(handler),
+                NotifierOperation::AddShared,
+                EventSet::IN,
+            ));
+        }
+
         // Register event notifier for tap.
         let cloned_net_io = net_io.clone();
         if let Some(tap) = locked_net_io.tap.as_ref() {
@@ -1153,6 +1358,8 @@ pub struct VirtioNetState {
     pub config_space: VirtioNetConfig,
     /// Device broken status.
     broken: bool,
+    /// The rings are processed by vhost-net kernel module, rather than the VMM io thread.
+    pub vhost: bool,
 }
 
 /// Network device structure.
@@ -1173,6 +1380,8 @@ pub struct Net {
     broken: Arc<AtomicBool>,
     /// The information about control command.
     ctrl_info: Option<Arc<Mutex<CtrlInfo>>>,
+    /// Traffic statistics of the device.
+    stats: Arc<NetStats>,
 }
 
 impl Default for Net {
@@ -1186,6 +1395,7 @@ impl Default for Net {
             deactivate_evts: Vec::new(),
             broken: Arc::new(AtomicBool::new(false)),
             ctrl_info: None,
+            stats: Arc::new(NetStats::default()),
         }
     }
 }
@@ -1201,6 +1411,7 @@ impl Net {
             deactivate_evts: Vec::new(),
             broken: Arc::new(AtomicBool::new(false)),
             ctrl_info: None,
+            stats: Arc::new(NetStats::default()),
         }
     }
 }
@@ -1343,7 +1554,7 @@ pub fn create_tap(
 /// # Arguments
 ///
 /// * `features` - The driver features.
-fn get_tap_offload_flags(features: u64) -> u32 {
+pub fn get_tap_offload_flags(features: u64) -> u32 {
     let mut flags: u32 = 0;
     if virtio_has_feature(features, VIRTIO_NET_F_GUEST_CSUM) {
         flags |= TUN_F_CSUM;
@@ -1363,6 +1574,40 @@ fn get_tap_offload_flags(features: u64) -> u32 {
     flags
 }
 
+/// Get the offload features disabled by the configuration. The tso and ufo
+/// features are disabled along with the checksum feature they depend on.
+///
+/// # Arguments
+///
+/// * `offload` - The offload configuration of net device.
+pub fn get_disabled_offload_features(offload: &NetOffloadConfig) -> u64 {
+    let mut features: u64 = 0;
+    for (enable, feature) in [
+        (offload.csum, VIRTIO_NET_F_CSUM),
+        (offload.guest_csum, VIRTIO_NET_F_GUEST_CSUM),
+        (offload.csum && offload.host_tso4, VIRTIO_NET_F_HOST_TSO4),
+        (offload.csum && offload.host_tso6, VIRTIO_NET_F_HOST_TSO6),
+        (offload.csum && offload.host_ufo, VIRTIO_NET_F_HOST_UFO),
+        (
+            offload.guest_csum && offload.guest_tso4,
+            VIRTIO_NET_F_GUEST_TSO4,
+        ),
+        (
+            offload.guest_csum && offload.guest_tso6,
+            VIRTIO_NET_F_GUEST_TSO6,
+        ),
+        (
+            offload.guest_csum && offload.guest_ufo,
+            VIRTIO_NET_F_GUEST_UFO,
+        ),
+    ] {
+        if !enable {
+            features |= 1 << feature;
+        }
+    }
+    features
+}
+
 impl VirtioDevice for Net {
     /// Realize virtio network device.
     fn realize(&mut self) -> Result<()> {
@@ -1391,8 +1636,11 @@ impl VirtioDevice for Net {
             | 1 << VIRTIO_NET_F_CTRL_RX_EXTRA
             | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
             | 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_NOTF_COAL
             | 1 << VIRTIO_F_RING_INDIRECT_DESC
-            | 1 << VIRTIO_F_RING_EVENT_IDX;
+            | 1 << VIRTIO_F_RING_EVENT_IDX
+            | packed_ring_feature();
+        locked_state.device_features &= !get_disabled_offload_features(&self.net_cfg.offload);
 
         let queue_pairs = self.net_cfg.queues / 2;
         if self.net_cfg.mq
@@ -1447,6 +1695,11 @@ impl VirtioDevice for Net {
             locked_state.device_features |= 1 << VIRTIO_NET_F_MAC;
         }
 
+        self.stats.reset();
+        if !self.net_cfg.id.is_empty() {
+            register_net_stats(&self.net_cfg.id, self.stats.clone());
+        }
+
         Ok(())
     }
 
@@ -1456,6 +1709,7 @@ impl VirtioDevice for Net {
             VirtioNetState::descriptor(),
             &self.net_cfg.id,
         );
+        unregister_net_stats(&self.net_cfg.id);
         Ok(())
     }
 
@@ -1548,7 +1802,14 @@ impl VirtioDevice for Net {
         mut queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
         let queue_num = queues.len();
-        let ctrl_info = Arc::new(Mutex::new(CtrlInfo::new(self.state.clone())));
+        let mut ctrl_info = CtrlInfo::new(self.state.clone());
+        // The configured rx coalescing is applied on host side, the driver
+        // negotiated VIRTIO_NET_F_NOTF_COAL can override it by control queue.
+        ctrl_info.rx_coal = CtrlCoalesce {
+            max_packets: self.net_cfg.rx_max_packets,
+            usecs: self.net_cfg.rx_usecs,
+        };
+        let ctrl_info = Arc::new(Mutex::new(ctrl_info));
         self.ctrl_info = Some(ctrl_info.clone());
         let driver_features = self.state.lock().unwrap().driver_features;
         if (driver_features & 1 << VIRTIO_NET_F_CTRL_VQ != 0) && (queue_num % 2 != 0) {
@@ -1607,6 +1868,10 @@ impl VirtioDevice for Net {
                 is_listening: true,
                 ctrl_info: ctrl_info.clone(),
                 queue_size: self.queue_size(),
+                iothread: self.net_cfg.iothread.clone(),
+                rx_coal: NotifyCoalesce::new()?,
+                tx_coal: NotifyCoalesce::new()?,
+                stats: self.stats.clone(),
             };
             if let Some(tap) = &handler.tap {
                 handler.tap_fd = tap.as_raw_fd();
@@ -1627,6 +1892,7 @@ impl VirtioDevice for Net {
     }
 
     fn update_config(&mut self, dev_config: Option<Arc<dyn ConfigCheck>>) -> Result<()> {
+        unregister_net_stats(&self.net_cfg.id);
         if let Some(conf) = dev_config {
             self.net_cfg = conf
                 .as_any()
@@ -1704,6 +1970,14 @@ impl StateTransfer for Net {
         }
         let mut locked_state = self.state.lock().unwrap();
         locked_state.as_mut_bytes().copy_from_slice(state);
+        // The state from older version doesn't carry vhost, which is off.
+        if locked_state.vhost {
+            return Err(anyhow!(MigrationError::MigrationConfigErr(
+                format!("vhost of net device {}", self.net_cfg.id),
+                "on".to_string(),
+                "off".to_string(),
+            )));
+        }
         self.broken.store(locked_state.broken, Ordering::SeqCst);
 
         Ok(())
@@ -1840,6 +2114,65 @@ mod tests {
         assert_eq!(ctrl_info.filter_packets(&buf), false);
     }
 
+    #[test]
+    fn test_net_notify_coalesce() {
+        let mut notify_coal = NotifyCoalesce::new().unwrap();
+        // Zero usecs disables coalescing, notify every used buffer.
+        let coal = CtrlCoalesce::default();
+        assert_eq!(notify_coal.count_used(coal, None), true);
+        assert_eq!(notify_coal.pending, 0);
+
+        // Reach max packets, notify without arming the timer.
+        let coal = CtrlCoalesce {
+            max_packets: 1,
+            usecs: 50,
+        };
+        assert_eq!(notify_coal.count_used(coal, None), true);
+        assert_eq!(notify_coal.pending, 0);
+        assert_eq!(notify_coal.timer_armed, false);
+
+        notify_coal.pending = 3;
+        notify_coal.timer_armed = true;
+        assert_eq!(notify_coal.flush(), true);
+        assert_eq!(notify_coal.flush(), false);
+        assert_eq!(notify_coal.timer_armed, false);
+    }
+
+    #[test]
+    fn test_net_offload_features() {
+        let mut offload = NetOffloadConfig::default();
+        assert_eq!(get_disabled_offload_features(&offload), 0);
+
+        offload.guest_tso4 = false;
+        offload.host_ufo = false;
+        let features = get_disabled_offload_features(&offload);
+        assert_eq!(
+            features,
+            1 << VIRTIO_NET_F_GUEST_TSO4 | 1 << VIRTIO_NET_F_HOST_UFO
+        );
+        // The tap offload flags follow the features left to the driver.
+        let flags = get_tap_offload_flags(0xffff_ffff & !features);
+        assert_eq!(flags & TUN_F_TSO4, 0);
+        assert_ne!(flags & TUN_F_TSO6, 0);
+
+        // Disabling the checksum disables the tso and ufo depending on it.
+        let mut offload = NetOffloadConfig::default();
+        offload.guest_csum = false;
+        let features = get_disabled_offload_features(&offload);
+        assert_eq!(
+            features,
+            1 << VIRTIO_NET_F_GUEST_CSUM
+                | 1 << VIRTIO_NET_F_GUEST_TSO4
+                | 1 << VIRTIO_NET_F_GUEST_TSO6
+                | 1 << VIRTIO_NET_F_GUEST_UFO
+        );
+        let flags = get_tap_offload_flags(0xffff_ffff & !features);
+        assert_eq!(
+            flags & (TUN_F_CSUM | TUN_F_TSO4 | TUN_F_TSO6 | TUN_F_UFO),
+            0
+        );
+    }
+
     #[test]
     fn test_net_config_space() {
         let mut net_config = VirtioNetConfig::default();
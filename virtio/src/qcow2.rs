@@ -0,0 +1,758 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder};
+use log::error;
+use util::file::open_file;
+
+use crate::block::BlockDriverOps;
+
+const QCOW2_MAGIC: u32 = 0x5146_49fb;
+const QCOW2_V2_HEADER_SIZE: usize = 72;
+const QCOW2_V3_HEADER_SIZE: usize = 104;
+/// Offset of the autoclear features in the header of version 3.
+const QCOW2_AUTOCLEAR_FEATURES_OFFSET: u64 = 88;
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+/// Only the default 16-bit refcount is supported.
+const REFCOUNT_ORDER: u32 = 4;
+const MAX_BACKING_FILE_NAME: u32 = 1023;
+const MAX_BACKING_DEPTH: u32 = 16;
+
+/// Refcounts of the image may be inconsistent as it's not closed cleanly.
+const INCOMPAT_DIRTY: u64 = 1 << 0;
+/// The image is marked corrupted, writing to it is unsafe.
+const INCOMPAT_CORRUPT: u64 = 1 << 1;
+
+const L1E_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const L2E_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const REFT_OFFSET_MASK: u64 = 0xffff_ffff_ffff_fe00;
+/// The refcount of the cluster is exactly one, so it can be written in place.
+const QCOW2_OFLAG_COPIED: u64 = 1 << 63;
+const QCOW2_OFLAG_COMPRESSED: u64 = 1 << 62;
+/// The cluster reads as all zeros, only valid in version 3.
+const QCOW2_OFLAG_ZERO: u64 = 1 << 0;
+
+/// Check whether the image file is in qcow2 format by its magic.
+pub fn is_qcow2(path: &str) -> Result<bool> {
+    let file = File::open(path).with_context(|| format!("Failed to open image {}", path))?;
+    let mut magic = [0_u8; 4];
+    match file.read_exact_at(&mut magic, 0) {
+        Ok(()) => Ok(BigEndian::read_u32(&magic) == QCOW2_MAGIC),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(anyhow!(e)).with_context(|| format!("Failed to read image {}", path)),
+    }
+}
+
+struct Qcow2Header {
+    version: u32,
+    backing_file_offset: u64,
+    backing_file_size: u32,
+    cluster_bits: u32,
+    size: u64,
+    crypt_method: u32,
+    l1_size: u32,
+    l1_table_offset: u64,
+    refcount_table_offset: u64,
+    refcount_table_clusters: u32,
+    incompatible_features: u64,
+    autoclear_features: u64,
+    refcount_order: u32,
+    header_length: u32,
+}
+
+impl Qcow2Header {
+    fn from_buf(buf: &[u8]) -> Result<Self> {
+        if buf.len() < QCOW2_V2_HEADER_SIZE {
+            bail!("Header is truncated");
+        }
+        if BigEndian::read_u32(&buf[0..4]) != QCOW2_MAGIC {
+            bail!("Invalid magic");
+        }
+        let mut header = Qcow2Header {
+            version: BigEndian::read_u32(&buf[4..8]),
+            backing_file_offset: BigEndian::read_u64(&buf[8..16]),
+            backing_file_size: BigEndian::read_u32(&buf[16..20]),
+            cluster_bits: BigEndian::read_u32(&buf[20..24]),
+            size: BigEndian::read_u64(&buf[24..32]),
+            crypt_method: BigEndian::read_u32(&buf[32..36]),
+            l1_size: BigEndian::read_u32(&buf[36..40]),
+            l1_table_offset: BigEndian::read_u64(&buf[40..48]),
+            refcount_table_offset: BigEndian::read_u64(&buf[48..56]),
+            refcount_table_clusters: BigEndian::read_u32(&buf[56..60]),
+            incompatible_features: 0,
+            autoclear_features: 0,
+            refcount_order: REFCOUNT_ORDER,
+            header_length: QCOW2_V2_HEADER_SIZE as u32,
+        };
+        match header.version {
+            2 => {}
+            3 => {
+                if buf.len() < QCOW2_V3_HEADER_SIZE {
+                    bail!("Header is truncated");
+                }
+                header.incompatible_features = BigEndian::read_u64(&buf[72..80]);
+                header.autoclear_features = BigEndian::read_u64(&buf[88..96]);
+                header.refcount_order = BigEndian::read_u32(&buf[96..100]);
+                header.header_length = BigEndian::read_u32(&buf[100..104]);
+            }
+            version => bail!("Unsupported version {}", version),
+        }
+        Ok(header)
+    }
+
+    fn check(&self, read_only: bool) -> Result<()> {
+        if !(MIN_CLUSTER_BITS..=MAX_CLUSTER_BITS).contains(&self.cluster_bits) {
+            bail!("Unsupported cluster bits {}", self.cluster_bits);
+        }
+        if self.crypt_method != 0 {
+            bail!("Encrypted image is not supported");
+        }
+        if self.refcount_order != REFCOUNT_ORDER {
+            bail!("Unsupported refcount order {}", self.refcount_order);
+        }
+        if self.version == 3 && (self.header_length as usize) < QCOW2_V3_HEADER_SIZE {
+            bail!("Invalid header length {}", self.header_length);
+        }
+        let unknown = self.incompatible_features & !(INCOMPAT_DIRTY | INCOMPAT_CORRUPT);
+        if unknown != 0 {
+            bail!("Unsupported incompatible features {:#x}", unknown);
+        }
+        if !read_only && self.incompatible_features & INCOMPAT_CORRUPT != 0 {
+            bail!("Image is marked corrupted, it can only be opened read-only");
+        }
+        if !read_only && self.incompatible_features & INCOMPAT_DIRTY != 0 {
+            bail!("Image is not closed cleanly, repair it by \"qemu-img check -r all\" first");
+        }
+        // Each L2 table has (cluster_size / 8) entries.
+        let l2_coverage = 1_u64 << (2 * self.cluster_bits - 3);
+        if (self.l1_size as u64) < (self.size + l2_coverage - 1) / l2_coverage {
+            bail!("L1 table is too small for the disk size {}", self.size);
+        }
+        Ok(())
+    }
+}
+
+/// Backing image, which is always opened read-only.
+enum Backing {
+    Raw { file: File, size: u64 },
+    Qcow2(Box<Qcow2Driver>),
+}
+
+impl Backing {
+    fn open(path: &str, depth: u32) -> Result<Self> {
+        if is_qcow2(path)? {
+            return Ok(Backing::Qcow2(Box::new(Qcow2Driver::open(
+                path, true, depth,
+            )?)));
+        }
+        let file = open_file(path, true, false)?;
+        let size = file
+            .metadata()
+            .with_context(|| format!("Failed to get size of backing file {}", path))?
+            .len();
+        Ok(Backing::Raw { file, size })
+    }
+
+    /// Read the backing image, the data beyond its end reads as zeros.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        buf.fill(0);
+        let size = match self {
+            Backing::Raw { size, .. } => *size,
+            Backing::Qcow2(qcow2) => qcow2.size,
+        };
+        if offset >= size {
+            return Ok(());
+        }
+        let len = cmp::min(buf.len() as u64, size - offset) as usize;
+        match self {
+            Backing::Raw { file, .. } => file.read_exact_at(&mut buf[..len], offset),
+            Backing::Qcow2(qcow2) => qcow2.read_at(&mut buf[..len], offset),
+        }
+    }
+}
+
+/// Driver of qcow2 image. The guest offset is mapped to the host cluster by the
+/// two-level L1/L2 tables, and the clusters are allocated at the end of image file
+/// with their refcounts updated. The clusters shared with internal snapshots are
+/// copied on write. Compressed clusters are not supported.
+pub struct Qcow2Driver {
+    /// Path of the image file.
+    path: String,
+    /// The image file, opened without direct access.
+    file: File,
+    read_only: bool,
+    version: u32,
+    cluster_bits: u32,
+    cluster_size: u64,
+    /// The number of entries in an L2 table is (1 << l2_bits).
+    l2_bits: u32,
+    /// The number of entries in a refcount block is (1 << refblock_bits).
+    refblock_bits: u32,
+    /// Size of the disk in bytes.
+    size: u64,
+    l1_table_offset: u64,
+    l1_table: Vec<u64>,
+    refcount_table_offset: u64,
+    refcount_table: Vec<u64>,
+    /// Host offset where the next cluster is allocated, it's the end of image file.
+    free_offset: u64,
+    backing: Option<Backing>,
+    /// Corruption is found in metadata, the image is not written any more.
+    corrupted: bool,
+}
+
+impl Qcow2Driver {
+    /// Open the qcow2 image and its backing chain.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the image file.
+    /// * `read_only` - If the image is opened read-only.
+    pub fn new(path: &str, read_only: bool) -> Result<Self> {
+        Self::open(path, read_only, 0)
+    }
+
+    fn open(path: &str, read_only: bool, depth: u32) -> Result<Self> {
+        let file = open_file(path, read_only, false)?;
+        let file_size = file
+            .metadata()
+            .with_context(|| format!("Failed to get size of qcow2 image {}", path))?
+            .len();
+        let mut buf = [0_u8; QCOW2_V3_HEADER_SIZE];
+        let len = cmp::min(file_size, QCOW2_V3_HEADER_SIZE as u64) as usize;
+        file.read_exact_at(&mut buf[..len], 0)
+            .with_context(|| format!("Failed to read header of qcow2 image {}", path))?;
+        let header = Qcow2Header::from_buf(&buf[..len])
+            .and_then(|header| header.check(read_only).map(|_| header))
+            .with_context(|| format!("Invalid header of qcow2 image {}", path))?;
+
+        let cluster_size = 1_u64 << header.cluster_bits;
+        let read_table = |offset: u64, entries: u64, name: &str| -> Result<Vec<u64>> {
+            let len = entries * 8;
+            if offset % cluster_size != 0
+                || offset
+                    .checked_add(len)
+                    .filter(|&end| end <= file_size)
+                    .is_none()
+            {
+                bail!(
+                    "{} of qcow2 image {} is unaligned or truncated, offset {:#x}",
+                    name,
+                    path,
+                    offset
+                );
+            }
+            let mut buf = vec![0_u8; len as usize];
+            file.read_exact_at(&mut buf, offset)
+                .with_context(|| format!("Failed to read {} of qcow2 image {}", name, path))?;
+            Ok(buf.chunks_exact(8).map(BigEndian::read_u64).collect())
+        };
+        let l1_table = read_table(header.l1_table_offset, header.l1_size as u64, "L1 table")?;
+        let refcount_table = read_table(
+            header.refcount_table_offset,
+            (header.refcount_table_clusters as u64) << (header.cluster_bits - 3),
+            "Refcount table",
+        )?;
+
+        let backing = if header.backing_file_offset != 0 {
+            if header.backing_file_size > MAX_BACKING_FILE_NAME {
+                bail!("Backing file name of qcow2 image {} is too long", path);
+            }
+            if depth >= MAX_BACKING_DEPTH {
+                bail!("Backing chain of qcow2 image {} is too long", path);
+            }
+            let mut name = vec![0_u8; header.backing_file_size as usize];
+            file.read_exact_at(&mut name, header.backing_file_offset)
+                .with_context(|| format!("Failed to read backing file name of {}", path))?;
+            let name = String::from_utf8(name)
+                .with_context(|| format!("Invalid backing file name of {}", path))?;
+            // Relative path of backing file is based on the directory of the image.
+            let backing_path = match Path::new(path).parent() {
+                Some(dir) => dir.join(&name),
+                None => Path::new(&name).to_path_buf(),
+            };
+            let backing_path = backing_path.to_string_lossy().to_string();
+            Some(
+                Backing::open(&backing_path, depth + 1)
+                    .with_context(|| format!("Failed to open backing file of {}", path))?,
+            )
+        } else {
+            None
+        };
+
+        if !read_only && header.autoclear_features != 0 {
+            // The extensions of autoclear features are not maintained by the writes.
+            file.write_all_at(&[0_u8; 8], QCOW2_AUTOCLEAR_FEATURES_OFFSET)
+                .with_context(|| format!("Failed to clear autoclear features of {}", path))?;
+        }
+
+        Ok(Qcow2Driver {
+            path: path.to_string(),
+            file,
+            read_only,
+            version: header.version,
+            cluster_bits: header.cluster_bits,
+            cluster_size,
+            l2_bits: header.cluster_bits - 3,
+            refblock_bits: header.cluster_bits + 3 - header.refcount_order,
+            size: header.size,
+            l1_table_offset: header.l1_table_offset,
+            l1_table,
+            refcount_table_offset: header.refcount_table_offset,
+            refcount_table,
+            free_offset: (file_size + cluster_size - 1) & !(cluster_size - 1),
+            backing,
+            corrupted: false,
+        })
+    }
+
+    fn corruption(&self, msg: String) -> Error {
+        error!("Qcow2 image {} is corrupted: {}", self.path, msg);
+        Error::new(ErrorKind::InvalidData, msg)
+    }
+
+    fn unsupported(&self, msg: &str) -> Error {
+        error!("Qcow2 image {}: {} is not supported", self.path, msg);
+        Error::from_raw_os_error(libc::ENOTSUP)
+    }
+
+    /// Check that the cluster of metadata or data is aligned and in the image file.
+    fn check_cluster(&self, offset: u64, name: &str) -> std::io::Result<()> {
+        if offset % self.cluster_size != 0 || offset + self.cluster_size > self.free_offset {
+            return Err(
+                self.corruption(format!("{} at {:#x} is not a valid cluster", name, offset))
+            );
+        }
+        Ok(())
+    }
+
+    fn read_image(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        self.file.read_exact_at(buf, offset).map_err(|e| {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                self.corruption(format!("image file is truncated at {:#x}", offset))
+            } else {
+                e
+            }
+        })
+    }
+
+    fn read_u64(&self, offset: u64) -> std::io::Result<u64> {
+        let mut buf = [0_u8; 8];
+        self.read_image(&mut buf, offset)?;
+        Ok(BigEndian::read_u64(&buf))
+    }
+
+    fn write_u64(&self, offset: u64, value: u64) -> std::io::Result<()> {
+        let mut buf = [0_u8; 8];
+        BigEndian::write_u64(&mut buf, value);
+        self.file.write_all_at(&buf, offset)
+    }
+
+    fn l1_index(&self, offset: u64) -> usize {
+        (offset >> (self.cluster_bits + self.l2_bits)) as usize
+    }
+
+    fn l2_index(&self, offset: u64) -> u64 {
+        (offset >> self.cluster_bits) & ((1 << self.l2_bits) - 1)
+    }
+
+    fn l1_entry(&self, offset: u64) -> std::io::Result<u64> {
+        self.l1_table
+            .get(self.l1_index(offset))
+            .copied()
+            .ok_or_else(|| self.corruption(format!("offset {:#x} exceeds L1 table", offset)))
+    }
+
+    /// Get the L2 entry of the cluster at guest `offset`, 0 if the L2 table is not allocated.
+    fn l2_entry(&self, offset: u64) -> std::io::Result<u64> {
+        let l2_table = self.l1_entry(offset)? & L1E_OFFSET_MASK;
+        if l2_table == 0 {
+            return Ok(0);
+        }
+        self.check_cluster(l2_table, "L2 table")?;
+        self.read_u64(l2_table + self.l2_index(offset) * 8)
+    }
+
+    /// Read the cluster described by L2 `entry` to `buf`, from guest `offset` in it.
+    fn read_cluster(&self, entry: u64, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        if entry & QCOW2_OFLAG_COMPRESSED != 0 {
+            return Err(self.unsupported("compressed cluster"));
+        }
+        let host = entry & L2E_OFFSET_MASK;
+        if self.version >= 3 && entry & QCOW2_OFLAG_ZERO != 0 {
+            buf.fill(0);
+        } else if host == 0 {
+            match self.backing.as_ref() {
+                Some(backing) => backing.read_at(buf, offset)?,
+                None => buf.fill(0),
+            }
+        } else {
+            self.check_cluster(host, "data cluster")?;
+            self.read_image(buf, host + offset % self.cluster_size)?;
+        }
+        Ok(())
+    }
+
+    /// Update the refcount of the cluster at host `offset`. The refcount block is
+    /// allocated if it doesn't exist.
+    fn update_refcount(&mut self, offset: u64, addend: i32) -> std::io::Result<()> {
+        let cluster = offset >> self.cluster_bits;
+        let table_index = (cluster >> self.refblock_bits) as usize;
+        if table_index >= self.refcount_table.len() {
+            error!("Refcount table of qcow2 image {} is full", self.path);
+            return Err(Error::from_raw_os_error(libc::EFBIG));
+        }
+        let mut block = self.refcount_table[table_index] & REFT_OFFSET_MASK;
+        if block == 0 {
+            if addend < 0 {
+                return Err(self.corruption(format!("refcount of {:#x} is not allocated", offset)));
+            }
+            block = self.free_offset;
+            self.free_offset += self.cluster_size;
+            self.file
+                .write_all_at(&vec![0_u8; self.cluster_size as usize], block)?;
+            self.refcount_table[table_index] = block;
+            self.write_u64(self.refcount_table_offset + table_index as u64 * 8, block)?;
+            // The new refcount block is covered by itself or the next one.
+            self.update_refcount(block, 1)?;
+        } else {
+            self.check_cluster(block, "refcount block")?;
+        }
+
+        let entry_offset = block + (cluster & ((1 << self.refblock_bits) - 1)) * 2;
+        let mut buf = [0_u8; 2];
+        self.read_image(&mut buf, entry_offset)?;
+        let refcount = BigEndian::read_u16(&buf) as i32 + addend;
+        if !(0..=u16::MAX as i32).contains(&refcount) {
+            return Err(self.corruption(format!("refcount of {:#x} is out of range", offset)));
+        }
+        BigEndian::write_u16(&mut buf, refcount as u16);
+        self.file.write_all_at(&buf, entry_offset)
+    }
+
+    /// Allocate a cluster at the end of image file.
+    fn alloc_cluster(&mut self) -> std::io::Result<u64> {
+        let offset = self.free_offset;
+        self.free_offset += self.cluster_size;
+        self.update_refcount(offset, 1)?;
+        Ok(offset)
+    }
+
+    /// Get the L2 table of guest `offset` which can be written in place. The table
+    /// is allocated if it doesn't exist, or copied if it's shared with snapshots.
+    fn writable_l2_table(&mut self, offset: u64) -> std::io::Result<u64> {
+        let l1_entry = self.l1_entry(offset)?;
+        let old_table = l1_entry & L1E_OFFSET_MASK;
+        if old_table != 0 {
+            self.check_cluster(old_table, "L2 table")?;
+            if l1_entry & QCOW2_OFLAG_COPIED != 0 {
+                return Ok(old_table);
+            }
+        }
+
+        let mut table = vec![0_u8; self.cluster_size as usize];
+        if old_table != 0 {
+            self.read_image(&mut table, old_table)?;
+        }
+        let new_table = self.alloc_cluster()?;
+        self.file.write_all_at(&table, new_table)?;
+        let l1_index = self.l1_index(offset);
+        self.l1_table[l1_index] = new_table | QCOW2_OFLAG_COPIED;
+        self.write_u64(
+            self.l1_table_offset + l1_index as u64 * 8,
+            new_table | QCOW2_OFLAG_COPIED,
+        )?;
+        if old_table != 0 {
+            self.update_refcount(old_table, -1)?;
+        }
+        Ok(new_table)
+    }
+
+    /// Write `data` to guest `offset`, which doesn't cross the cluster boundary.
+    fn write_cluster(&mut self, data: &[u8], offset: u64) -> std::io::Result<()> {
+        let in_cluster = offset % self.cluster_size;
+        let l2_table = self.writable_l2_table(offset)?;
+        let entry_offset = l2_table + self.l2_index(offset) * 8;
+        let entry = self.read_u64(entry_offset)?;
+        let old_cluster = entry & L2E_OFFSET_MASK;
+        let zero = self.version >= 3 && entry & QCOW2_OFLAG_ZERO != 0;
+        if old_cluster != 0
+            && entry & (QCOW2_OFLAG_COPIED | QCOW2_OFLAG_COMPRESSED) == QCOW2_OFLAG_COPIED
+            && !zero
+        {
+            self.check_cluster(old_cluster, "data cluster")?;
+            return self.file.write_all_at(data, old_cluster + in_cluster);
+        }
+
+        // Allocate a new cluster with the old data which is not overwritten, the
+        // data is written before it's referenced by L2 table.
+        let mut cluster = vec![0_u8; self.cluster_size as usize];
+        if data.len() as u64 != self.cluster_size {
+            self.read_cluster(entry, &mut cluster, offset - in_cluster)?;
+        }
+        cluster[in_cluster as usize..in_cluster as usize + data.len()].copy_from_slice(data);
+        let new_cluster = self.alloc_cluster()?;
+        self.file.write_all_at(&cluster, new_cluster)?;
+        self.write_u64(entry_offset, new_cluster | QCOW2_OFLAG_COPIED)?;
+        if old_cluster != 0 && entry & QCOW2_OFLAG_COMPRESSED == 0 {
+            self.update_refcount(old_cluster, -1)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDriverOps for Qcow2Driver {
+    fn disk_size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        let mut done = 0_usize;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let len = cmp::min(
+                buf.len() - done,
+                (self.cluster_size - pos % self.cluster_size) as usize,
+            );
+            let entry = self.l2_entry(pos)?;
+            self.read_cluster(entry, &mut buf[done..done + len], pos)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(Error::from_raw_os_error(libc::EROFS));
+        }
+        if self.corrupted {
+            error!("Qcow2 image {} is corrupted, refuse to write", self.path);
+            return Err(Error::from_raw_os_error(libc::EIO));
+        }
+        let mut done = 0_usize;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let len = cmp::min(
+                buf.len() - done,
+                (self.cluster_size - pos % self.cluster_size) as usize,
+            );
+            if let Err(e) = self.write_cluster(&buf[done..done + len], pos) {
+                if e.kind() == ErrorKind::InvalidData {
+                    self.corrupted = true;
+                }
+                return Err(e);
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.file.sync_all()
+    }
+
+    fn data_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::block_overlay::{anonymous_overlay, BlockOverlay};
+
+    use util::aio::Iovec;
+    use vmm_sys_util::tempfile::TempFile;
+
+    const CLUSTER_SIZE: u64 = 1 << 16;
+    const DISK_SIZE: u64 = 4 * 1024 * 1024;
+
+    fn iovec(buf: &mut [u8]) -> Vec<Iovec> {
+        vec![Iovec {
+            iov_base: buf.as_mut_ptr() as u64,
+            iov_len: buf.len() as u64,
+        }]
+    }
+
+    /// Create a qcow2 image of version 3, whose clusters are header, refcount table,
+    /// refcount block and L1 table in order.
+    fn create_qcow2(path: &str, backing: Option<&str>) {
+        let cs = CLUSTER_SIZE as usize;
+        let mut image = vec![0_u8; 4 * cs];
+        BigEndian::write_u32(&mut image[0..4], QCOW2_MAGIC);
+        BigEndian::write_u32(&mut image[4..8], 3);
+        if let Some(name) = backing {
+            BigEndian::write_u64(&mut image[8..16], 112);
+            BigEndian::write_u32(&mut image[16..20], name.len() as u32);
+            image[112..112 + name.len()].copy_from_slice(name.as_bytes());
+        }
+        BigEndian::write_u32(&mut image[20..24], 16);
+        BigEndian::write_u64(&mut image[24..32], DISK_SIZE);
+        BigEndian::write_u32(&mut image[36..40], 1);
+        BigEndian::write_u64(&mut image[40..48], 3 * CLUSTER_SIZE);
+        BigEndian::write_u64(&mut image[48..56], CLUSTER_SIZE);
+        BigEndian::write_u32(&mut image[56..60], 1);
+        BigEndian::write_u32(&mut image[96..100], REFCOUNT_ORDER);
+        BigEndian::write_u32(&mut image[100..104], QCOW2_V3_HEADER_SIZE as u32);
+        BigEndian::write_u64(&mut image[cs..cs + 8], 2 * CLUSTER_SIZE);
+        for i in 0..4 {
+            BigEndian::write_u16(&mut image[2 * cs + i * 2..2 * cs + i * 2 + 2], 1);
+        }
+        std::fs::write(path, image).unwrap();
+    }
+
+    fn refcount(qcow2: &Qcow2Driver, offset: u64) -> u16 {
+        let mut buf = [0_u8; 2];
+        qcow2
+            .file
+            .read_exact_at(&mut buf, 2 * CLUSTER_SIZE + (offset / CLUSTER_SIZE) * 2)
+            .unwrap();
+        BigEndian::read_u16(&buf)
+    }
+
+    #[test]
+    fn test_qcow2_rw() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap();
+        assert!(!is_qcow2(path).unwrap());
+        create_qcow2(path, None);
+        assert!(is_qcow2(path).unwrap());
+
+        let mut qcow2 = Qcow2Driver::new(path, false).unwrap();
+        assert_eq!(qcow2.disk_size(), DISK_SIZE);
+        let mut buf = vec![0xff_u8; 2 * CLUSTER_SIZE as usize];
+        assert_eq!(qcow2.readv(&iovec(&mut buf), 0), buf.len() as i64);
+        assert!(buf.iter().all(|byte| *byte == 0));
+
+        let offset = CLUSTER_SIZE + 512;
+        let mut data = vec![0xbb_u8; 1024];
+        assert_eq!(qcow2.writev(&iovec(&mut data), offset), 1024);
+        assert!(qcow2.writev(&iovec(&mut data), DISK_SIZE - 512) < 0);
+        assert_eq!(qcow2.flush(), 0);
+
+        // L2 table and data cluster are allocated at the end of image file.
+        let mut qcow2 = Qcow2Driver::new(path, true).unwrap();
+        assert_eq!(qcow2.l1_table[0], 4 * CLUSTER_SIZE | QCOW2_OFLAG_COPIED);
+        assert_eq!(qcow2.free_offset, 6 * CLUSTER_SIZE);
+        assert_eq!(refcount(&qcow2, 4 * CLUSTER_SIZE), 1);
+        assert_eq!(refcount(&qcow2, 5 * CLUSTER_SIZE), 1);
+        assert_eq!(qcow2.readv(&iovec(&mut buf), 0), buf.len() as i64);
+        for (i, byte) in buf.iter().enumerate() {
+            let written = i as u64 >= offset && (i as u64) < offset + 1024;
+            assert_eq!(*byte, if written { 0xbb } else { 0 });
+        }
+
+        // Read-only image can't be written.
+        assert_eq!(qcow2.writev(&iovec(&mut data), 0), -(libc::EROFS as i64));
+    }
+
+    #[test]
+    fn test_qcow2_backing_file() {
+        let backing_file = TempFile::new().unwrap();
+        let backing_path = backing_file.as_path().to_str().unwrap();
+        std::fs::write(backing_path, vec![0xaa_u8; 2 * CLUSTER_SIZE as usize]).unwrap();
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap();
+        // Backing file in the same directory with relative path.
+        let backing_name = backing_file.as_path().file_name().unwrap();
+        create_qcow2(path, Some(backing_name.to_str().unwrap()));
+
+        let mut qcow2 = Qcow2Driver::new(path, false).unwrap();
+        let mut data = vec![0xbb_u8; 512];
+        assert_eq!(qcow2.writev(&iovec(&mut data), 100), 512);
+
+        // Data beyond the end of backing file reads as zeros.
+        let mut buf = vec![0_u8; 3 * CLUSTER_SIZE as usize];
+        assert_eq!(qcow2.readv(&iovec(&mut buf), 0), buf.len() as i64);
+        for (i, byte) in buf.iter().enumerate() {
+            let expected = if (100..612).contains(&i) {
+                0xbb
+            } else if (i as u64) < 2 * CLUSTER_SIZE {
+                0xaa
+            } else {
+                0
+            };
+            assert_eq!(*byte, expected);
+        }
+
+        // Backing file is never modified.
+        let backing_data = std::fs::read(backing_path).unwrap();
+        assert!(backing_data.iter().all(|byte| *byte == 0xaa));
+    }
+
+    #[test]
+    fn test_qcow2_corruption() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap();
+        create_qcow2(path, None);
+        let mut qcow2 = Qcow2Driver::new(path, false).unwrap();
+        let mut data = vec![0xbb_u8; 512];
+        assert_eq!(qcow2.writev(&iovec(&mut data), 0), 512);
+
+        // Data cluster is truncated.
+        file.as_file().set_len(5 * CLUSTER_SIZE + 4096).unwrap();
+        let qcow2 = Qcow2Driver::new(path, false).unwrap();
+        let mut buf = vec![0_u8; CLUSTER_SIZE as usize];
+        assert_eq!(qcow2.readv(&iovec(&mut buf), 0), -(libc::EIO as i64));
+
+        // L2 table is beyond the end of file, and the image is not written any more.
+        qcow2
+            .write_u64(3 * CLUSTER_SIZE, 100 * CLUSTER_SIZE | QCOW2_OFLAG_COPIED)
+            .unwrap();
+        let mut qcow2 = Qcow2Driver::new(path, false).unwrap();
+        assert_eq!(qcow2.readv(&iovec(&mut buf), 0), -(libc::EIO as i64));
+        assert_eq!(qcow2.writev(&iovec(&mut data), 0), -(libc::EIO as i64));
+        assert!(qcow2.corrupted);
+
+        // Header is truncated.
+        file.as_file().set_len(80).unwrap();
+        assert!(Qcow2Driver::new(path, true).is_err());
+    }
+
+    #[test]
+    fn test_qcow2_snapshot() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap();
+        create_qcow2(path, None);
+        let image_len = file.as_file().metadata().unwrap().len();
+
+        // The read-only image is under the anonymous overlay in snapshot mode.
+        let qcow2 = Qcow2Driver::new(path, true).unwrap();
+        let mut disk =
+            BlockOverlay::with_driver(Box::new(qcow2), anonymous_overlay().unwrap()).unwrap();
+        assert_eq!(disk.disk_size(), DISK_SIZE);
+        let mut data = vec![0xbb_u8; 512];
+        assert_eq!(disk.writev(&iovec(&mut data), CLUSTER_SIZE), 512);
+        let mut buf = vec![0xff_u8; 2 * CLUSTER_SIZE as usize];
+        assert_eq!(disk.readv(&iovec(&mut buf), 0), buf.len() as i64);
+        for (i, byte) in buf.iter().enumerate() {
+            let written = i as u64 >= CLUSTER_SIZE && (i as u64) < CLUSTER_SIZE + 512;
+            assert_eq!(*byte, if written { 0xbb } else { 0 });
+        }
+
+        // The qcow2 image is never modified.
+        assert_eq!(file.as_file().metadata().unwrap().len(), image_len);
+        let qcow2 = Qcow2Driver::new(path, true).unwrap();
+        assert_eq!(qcow2.readv(&iovec(&mut buf), 0), buf.len() as i64);
+        assert!(buf.iter().all(|byte| *byte == 0));
+    }
+}
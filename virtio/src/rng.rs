@@ -38,8 +38,8 @@ use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
 use super::{
-    ElemIovec, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_RNG,
+    packed_ring_feature, ElemIovec, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VirtioTrace, VIRTIO_F_VERSION_1, VIRTIO_TYPE_RNG,
 };
 use crate::error::VirtioError;
 use anyhow::{anyhow, bail, Context, Result};
@@ -72,10 +72,15 @@ impl RngHandler {
     fn write_req_data(&self, in_iov: &[ElemIovec], buffer: &mut [u8]) -> Result<()> {
         let mut offset = 0_usize;
         for iov in in_iov {
+            // The random file may return less data than requested.
+            let len = std::cmp::min(iov.len as usize, buffer.len() - offset);
+            if len == 0 {
+                break;
+            }
             self.mem_space
-                .write(&mut buffer[offset..].as_ref(), iov.addr, iov.len as u64)
+                .write(&mut buffer[offset..].as_ref(), iov.addr, len as u64)
                 .with_context(|| "Failed to write request data for virtio rng")?;
-            offset += iov.len as usize;
+            offset += len;
         }
 
         Ok(())
@@ -118,19 +123,27 @@ impl RngHandler {
                 size as usize,
                 0,
             );
-            if ret < 0 || ret as u32 != size {
-                bail!("Failed to read random file, size: {}", size);
-            }
-
-            self.write_req_data(&elem.in_iovec, &mut buffer)?;
+            // Complete the request with no data if random file can't be read, so that
+            // guest doesn't wait for it forever and can request again.
+            let len = if ret < 0 {
+                error!(
+                    "Failed to read random file for virtio rng, size: {}, errno: {}",
+                    size, -ret
+                );
+                0
+            } else {
+                ret as u32
+            };
+
+            self.write_req_data(&elem.in_iovec, &mut buffer[..len as usize])?;
 
             queue_lock
                 .vring
-                .add_used(&self.mem_space, elem.index, size)
+                .add_used(&self.mem_space, elem.index, len)
                 .with_context(|| {
                     format!(
                         "Failed to add used ring, index: {}, size: {}",
-                        elem.index, size
+                        elem.index, len
                     )
                 })?;
 
@@ -264,7 +277,7 @@ impl VirtioDevice for Rng {
             .with_context(|| "Failed to open file of random number generator")?;
 
         self.random_file = Some(file);
-        self.state.device_features = 1 << VIRTIO_F_VERSION_1 as u64;
+        self.state.device_features = 1 << VIRTIO_F_VERSION_1 as u64 | packed_ring_feature();
         Ok(())
     }
 
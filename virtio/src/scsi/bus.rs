@@ -18,7 +18,7 @@ use std::sync::{Arc, Mutex, Weak};
 use anyhow::{anyhow, bail, Context, Result};
 
 use crate::ScsiCntlr::{
-    ScsiCntlr, ScsiCompleteCb, ScsiXferMode, VirtioScsiCmdReq, VirtioScsiCmdResp,
+    ScsiCmdHandler, ScsiCntlr, ScsiCompleteCb, ScsiXferMode, VirtioScsiCmdReq, VirtioScsiCmdResp,
     VirtioScsiRequest, VIRTIO_SCSI_CDB_DEFAULT_SIZE, VIRTIO_SCSI_S_OK,
 };
 use crate::ScsiDisk::{
@@ -29,7 +29,8 @@ use crate::ScsiDisk::{
 use address_space::AddressSpace;
 use byteorder::{BigEndian, ByteOrder};
 use log::{debug, error, info};
-use util::aio::{Aio, AioCb, Iovec, OpCode};
+use util::aio::{iov_to_buf_direct, Aio, AioCb, Iovec, OpCode};
+use vmm_sys_util::eventfd::EventFd;
 
 /// Scsi Operation code.
 pub const TEST_UNIT_READY: u8 = 0x00;
@@ -271,6 +272,13 @@ pub const SCSI_INQUIRY_VPD_SERIAL_NUMBER_MAX_LEN: usize = 32;
 
 const SCSI_TARGET_INQUIRY_LEN: u32 = 36;
 
+/// Length of the UNMAP parameter list header.
+const SCSI_UNMAP_HEADER_LEN: usize = 8;
+/// Length of the UNMAP block descriptor.
+const SCSI_UNMAP_DESC_LEN: usize = 16;
+/// Bit 3 of byte 1 in WRITE SAME command: UNMAP.
+const SCSI_WRITE_SAME_UNMAP: u8 = 0x08;
+
 /// |     bit7 - bit 5     |     bit 4 - bit 0      |
 /// | Peripheral Qualifier | Peripheral Device Type |
 /// Unknown or no device type.
@@ -363,15 +371,18 @@ pub struct ScsiBus {
     pub devices: HashMap<(u8, u16), Arc<Mutex<ScsiDevice>>>,
     /// Scsi Controller which the bus orignates from.
     pub parent_cntlr: Weak<Mutex<ScsiCntlr>>,
+    /// Written when the IO throttle of any device on the bus is ready for more requests.
+    pub throttle_evt: Arc<EventFd>,
 }
 
 impl ScsiBus {
-    pub fn new(bus_name: String, parent_cntlr: Weak<Mutex<ScsiCntlr>>) -> ScsiBus {
-        ScsiBus {
+    pub fn new(bus_name: String, parent_cntlr: Weak<Mutex<ScsiCntlr>>) -> Result<ScsiBus> {
+        Ok(ScsiBus {
             name: bus_name,
             devices: HashMap::new(),
             parent_cntlr,
-        }
+            throttle_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+        })
     }
 
     /// Get device by the target number and the lun number.
@@ -443,7 +454,7 @@ impl ScsiBus {
 
 pub fn create_scsi_bus(bus_name: &str, scsi_cntlr: &Arc<Mutex<ScsiCntlr>>) -> Result<()> {
     let mut locked_scsi_cntlr = scsi_cntlr.lock().unwrap();
-    let bus = ScsiBus::new(bus_name.to_string(), Arc::downgrade(scsi_cntlr));
+    let bus = ScsiBus::new(bus_name.to_string(), Arc::downgrade(scsi_cntlr))?;
     locked_scsi_cntlr.bus = Some(Arc::new(Mutex::new(bus)));
     Ok(())
 }
@@ -535,6 +546,23 @@ impl ScsiRequest {
         }
     }
 
+    /// Get the direction and the data length of the request for IO throttling.
+    pub(crate) fn io_size(&self) -> (bool, u64) {
+        let write = !matches!(self.cmd.mode, ScsiXferMode::ScsiXferFromDev);
+        let bytes = match self.cmd.command {
+            UNMAP | WRITE_SAME_10 | WRITE_SAME_16 | SYNCHRONIZE_CACHE => 0,
+            _ => self
+                .virtioscsireq
+                .lock()
+                .unwrap()
+                .iovec
+                .iter()
+                .map(|iov| iov.iov_len)
+                .sum(),
+        };
+        (write, bytes)
+    }
+
     pub fn execute(
         &self,
         aio: &mut Box<Aio<ScsiCompleteCb>>,
@@ -557,9 +585,78 @@ impl ScsiRequest {
             aiocb.nbytes += iov.iov_len;
         }
 
+        if matches!(self.cmd.command, UNMAP | WRITE_SAME_10 | WRITE_SAME_16) {
+            let enabled = match self.cmd.command {
+                UNMAP => dev_lock.discard_enabled(),
+                _ => dev_lock.discard_enabled() || dev_lock.write_zeroes_enabled(),
+            };
+            if !enabled {
+                info!(
+                    "scsi command {:#x} is not enabled for the device",
+                    self.cmd.command
+                );
+                self.cmd_complete(
+                    &aiocb.iocompletecb.mem_space,
+                    VIRTIO_SCSI_S_OK,
+                    CHECK_CONDITION,
+                    Some(SCSI_SENSE_INVALID_OPCODE),
+                    &Vec::new(),
+                )?;
+                return Ok(0);
+            }
+
+            let range = match self.cmd.command {
+                UNMAP => self.get_unmap_range(&dev_lock, &aiocb.iovec),
+                _ => self.get_write_same_range(&dev_lock, &aiocb.iovec),
+            };
+            match range {
+                Ok(Some((opcode, offset, nbytes))) => {
+                    aiocb.opcode = opcode;
+                    aiocb.offset = offset as usize;
+                    aiocb.nbytes = nbytes;
+                    aiocb.iovec.clear();
+                    aio.submit_request(aiocb)
+                        .with_context(|| "Failed to process scsi request for discarding")?;
+                }
+                Ok(None) => {
+                    self.cmd_complete(
+                        &aiocb.iocompletecb.mem_space,
+                        VIRTIO_SCSI_S_OK,
+                        GOOD,
+                        None,
+                        &Vec::new(),
+                    )?;
+                }
+                Err(ref e) => {
+                    error!(
+                        "Error in processing scsi command {:#x}, err is {:?}",
+                        self.cmd.command, e
+                    );
+                    self.cmd_complete(
+                        &aiocb.iocompletecb.mem_space,
+                        VIRTIO_SCSI_S_OK,
+                        CHECK_CONDITION,
+                        Some(SCSI_SENSE_INVALID_FIELD),
+                        &Vec::new(),
+                    )?;
+                }
+            }
+            return Ok(0);
+        }
+
         if self.cmd.command == SYNCHRONIZE_CACHE {
+            if dev_lock.config.no_flush {
+                self.cmd_complete(
+                    &aiocb.iocompletecb.mem_space,
+                    VIRTIO_SCSI_S_OK,
+                    GOOD,
+                    None,
+                    &Vec::new(),
+                )?;
+                return Ok(0);
+            }
             aiocb.opcode = OpCode::Fdsync;
-            aio.submit_request(aiocb)
+            Self::submit_request(aio, &dev_lock, aiocb)
                 .with_context(|| "Failed to process scsi request for flushing")?;
             return Ok(0);
         }
@@ -567,12 +664,12 @@ impl ScsiRequest {
         match self.cmd.mode {
             ScsiXferMode::ScsiXferFromDev => {
                 aiocb.opcode = OpCode::Preadv;
-                aio.submit_request(aiocb)
+                Self::submit_request(aio, &dev_lock, aiocb)
                     .with_context(|| "Failed to process scsi request for reading")?;
             }
             ScsiXferMode::ScsiXferToDev => {
                 aiocb.opcode = OpCode::Pwritev;
-                aio.submit_request(aiocb)
+                Self::submit_request(aio, &dev_lock, aiocb)
                     .with_context(|| "Failed to process block request for writing")?;
             }
             _ => {
@@ -582,6 +679,125 @@ impl ScsiRequest {
         Ok(0)
     }
 
+    /// Submit the request to aio, or handle it synchronously by the overlay of
+    /// snapshot mode if exists.
+    fn submit_request(
+        aio: &mut Box<Aio<ScsiCompleteCb>>,
+        dev: &ScsiDevice,
+        aiocb: AioCb<ScsiCompleteCb>,
+    ) -> Result<()> {
+        let driver = match dev.driver.as_ref() {
+            Some(driver) => driver,
+            None => return aio.submit_request(aiocb),
+        };
+        let offset = aiocb.offset as u64;
+        let ret = match aiocb.opcode {
+            OpCode::Preadv => driver.lock().unwrap().readv(&aiocb.iovec, offset),
+            OpCode::Pwritev => driver.lock().unwrap().writev(&aiocb.iovec, offset),
+            OpCode::Fdsync => driver.lock().unwrap().flush(),
+            _ => -(libc::EOPNOTSUPP as i64),
+        };
+        ScsiCmdHandler::complete_func(&aiocb, ret)
+    }
+
+    /// Get the byte range of the image described by `lba` and `blocks`.
+    fn get_block_range(disk_sectors: u64, lba: u64, blocks: u64) -> Result<(u64, u64)> {
+        let disk_size = disk_sectors << SECTOR_SHIFT;
+        let block_size = 1_u64 << SCSI_DISK_DEFAULT_BLOCK_SIZE_SHIFT;
+        let offset = lba
+            .checked_mul(block_size)
+            .with_context(|| format!("Too large lba {}", lba))?;
+        let nbytes = blocks
+            .checked_mul(block_size)
+            .with_context(|| format!("Too large number of blocks {}", blocks))?;
+        if offset
+            .checked_add(nbytes)
+            .filter(|&end| end <= disk_size)
+            .is_none()
+        {
+            bail!(
+                "Range {} blocks from lba {} is larger than disk size {}",
+                blocks,
+                lba,
+                disk_size
+            );
+        }
+        Ok((offset, nbytes))
+    }
+
+    /// Parse the parameter list of UNMAP command, only one block descriptor is supported.
+    fn get_unmap_range(
+        &self,
+        dev: &ScsiDevice,
+        iovec: &[Iovec],
+    ) -> Result<Option<(OpCode, u64, u64)>> {
+        // Byte[7-8]: Parameter list length.
+        let param_len = BigEndian::read_u16(&self.cmd.buf[7..9]) as usize;
+        if param_len == 0 {
+            return Ok(None);
+        }
+
+        let mut param = [0_u8; SCSI_UNMAP_HEADER_LEN + SCSI_UNMAP_DESC_LEN];
+        let size = iov_to_buf_direct(iovec, &mut param)?;
+        if size < SCSI_UNMAP_HEADER_LEN || param_len < SCSI_UNMAP_HEADER_LEN {
+            bail!("Invalid unmap parameter list length {}", param_len);
+        }
+        // Header: Byte[0-1]: Unmap data length. Byte[2-3]: Unmap block descriptor data length.
+        let desc_len = BigEndian::read_u16(&param[2..4]) as usize;
+        if desc_len == 0 {
+            return Ok(None);
+        }
+        if desc_len != SCSI_UNMAP_DESC_LEN || size < SCSI_UNMAP_HEADER_LEN + SCSI_UNMAP_DESC_LEN {
+            bail!(
+                "Unsupported unmap block descriptor data length {}",
+                desc_len
+            );
+        }
+
+        // Descriptor: Byte[0-7]: Unmap logical block address. Byte[8-11]: Number of blocks.
+        let desc = &param[SCSI_UNMAP_HEADER_LEN..];
+        let lba = BigEndian::read_u64(&desc[0..8]);
+        let blocks = BigEndian::read_u32(&desc[8..12]) as u64;
+        if blocks == 0 {
+            return Ok(None);
+        }
+        let (offset, nbytes) = Self::get_block_range(dev.disk_sectors, lba, blocks)?;
+        Ok(Some((OpCode::Discard, offset, nbytes)))
+    }
+
+    /// Parse WRITE SAME command, only zeroed data block is supported.
+    fn get_write_same_range(
+        &self,
+        dev: &ScsiDevice,
+        iovec: &[Iovec],
+    ) -> Result<Option<(OpCode, u64, u64)>> {
+        // Number of blocks 0 is not supported, as WSNZ is set in Block Limits VPD page.
+        let blocks = self.cmd.xfer as u64;
+        if blocks == 0 {
+            bail!("Write same with zero number of blocks is not supported");
+        }
+
+        let mut block = vec![0_u8; dev.block_size as usize];
+        let size = iov_to_buf_direct(iovec, &mut block)?;
+        if size < block.len() {
+            bail!("Invalid data length {} for write same", size);
+        }
+        if block.iter().any(|&b| b != 0) {
+            bail!("Write same with non-zero data is not supported");
+        }
+
+        let unmap = self.cmd.buf[1] & SCSI_WRITE_SAME_UNMAP != 0;
+        let opcode = if unmap && dev.discard_enabled() {
+            OpCode::Discard
+        } else if dev.write_zeroes_enabled() {
+            OpCode::WriteZeroes
+        } else {
+            bail!("Write same without unmap is not enabled");
+        };
+        let (offset, nbytes) = Self::get_block_range(dev.disk_sectors, self.cmd.lba, blocks)?;
+        Ok(Some((opcode, offset, nbytes)))
+    }
+
     pub fn emulate_execute(
         &self,
         iocompletecb: ScsiCompleteCb,
@@ -749,9 +965,8 @@ pub const NON_EMULATE_SCSI_OPS: u32 = 1;
 fn scsi_operation_type(op: u8) -> u32 {
     match op {
         READ_6 | READ_10 | READ_12 | READ_16 | WRITE_6 | WRITE_10 | WRITE_12 | WRITE_16
-        | WRITE_VERIFY_10 | WRITE_VERIFY_12 | WRITE_VERIFY_16 | SYNCHRONIZE_CACHE => {
-            NON_EMULATE_SCSI_OPS
-        }
+        | WRITE_VERIFY_10 | WRITE_VERIFY_12 | WRITE_VERIFY_16 | SYNCHRONIZE_CACHE | UNMAP
+        | WRITE_SAME_10 | WRITE_SAME_16 => NON_EMULATE_SCSI_OPS,
         _ => EMULATE_SCSI_OPS,
     }
 }
@@ -958,6 +1173,15 @@ fn scsi_command_emulate_vpd_page(
                 device_id_vec.truncate(len as usize);
                 outbuf.append(&mut device_id_vec);
             }
+
+            if let Some(wwn) = dev_lock.state.wwn {
+                // 0x1: Code Set: Binary, Protocol Identifier: reserved.
+                // 0x3: Identifier Type: NAA, Association: logical unit, Piv: 0.
+                // 0: Reserved.
+                // 8: identifier length.
+                outbuf.append(&mut [0x1_u8, 0x3_u8, 0_u8, 8_u8].to_vec());
+                outbuf.extend_from_slice(&wwn.to_be_bytes());
+            }
             buflen = outbuf.len();
         }
         0xb0 => {
@@ -987,6 +1211,10 @@ fn scsi_command_emulate_vpd_page(
             let max_xfer_length: u32 = u32::MAX / 512;
             BigEndian::write_u32(&mut outbuf[8..12], max_xfer_length);
             BigEndian::write_u64(&mut outbuf[36..44], max_xfer_length as u64);
+            if dev_lock.discard_enabled() {
+                BigEndian::write_u32(&mut outbuf[20..24], max_xfer_length);
+                BigEndian::write_u32(&mut outbuf[24..28], 1);
+            }
             buflen = outbuf.len();
         }
         0xb1 => {
@@ -1001,10 +1229,16 @@ fn scsi_command_emulate_vpd_page(
         0xb2 => {
             // Logical Block Provisioning.
             // 0: Threshold exponent.
-            // 0xe0: LBPU(bit 7) | LBPWS | LBPWS10 | LBPRZ | ANC_SUP | DP.
-            // 0: Threshold percentage | Provisioning Type.
+            // 0x60 or 0xe0: LBPU(bit 7) | LBPWS | LBPWS10 | LBPRZ | ANC_SUP | DP.
+            // 1: Threshold percentage | Provisioning Type.
             // 0: Threshold percentage.
-            outbuf.append(&mut [0_u8, 0x60_u8, 1_u8, 0_u8].to_vec());
+            // LBPU is set only if UNMAP command is enabled.
+            let lbp = if dev_lock.discard_enabled() {
+                0xe0_u8
+            } else {
+                0x60_u8
+            };
+            outbuf.append(&mut [0_u8, lbp, 1_u8, 0_u8].to_vec());
             buflen = 8;
         }
         _ => {
@@ -1173,6 +1407,7 @@ fn scsi_command_emulate_mode_sense(
     let mut nb_sectors = dev_lock.disk_sectors as u32;
     let scsi_type = dev_lock.scsi_type;
     let block_size = dev_lock.block_size;
+    let write_cache = dev_lock.config.write_cache;
     nb_sectors /= block_size / DEFAULT_SECTOR_SIZE;
 
     debug!(
@@ -1237,10 +1472,22 @@ fn scsi_command_emulate_mode_sense(
     if page_code == 0x3f {
         // 3Fh Return all pages not including subpages.
         for pg in 0..page_code {
-            let _ = scsi_command_emulate_mode_sense_page(pg, page_control, &mut outbuf, scsi_type);
+            let _ = scsi_command_emulate_mode_sense_page(
+                pg,
+                page_control,
+                &mut outbuf,
+                scsi_type,
+                write_cache,
+            );
         }
     } else {
-        scsi_command_emulate_mode_sense_page(page_code, page_control, &mut outbuf, scsi_type)?;
+        scsi_command_emulate_mode_sense_page(
+            page_code,
+            page_control,
+            &mut outbuf,
+            scsi_type,
+            write_cache,
+        )?;
     }
 
     // The Mode Data Length field indicates the length in bytes of the following data
@@ -1262,6 +1509,7 @@ fn scsi_command_emulate_mode_sense_page(
     page_control: u8,
     outbuf: &mut Vec<u8>,
     scsi_type: u32,
+    write_cache: bool,
 ) -> Result<Vec<u8>> {
     if scsi_type == SCSI_TYPE_DISK
         && ![
@@ -1295,7 +1543,9 @@ fn scsi_command_emulate_mode_sense_page(
             outbuf[buflen] = page;
             outbuf[buflen + 1] = 18;
             // 0x4: WCE(Write Cache Enable).
-            outbuf[buflen + 2] = 0x4;
+            if write_cache {
+                outbuf[buflen + 2] = 0x4;
+            }
         }
         MODE_PAGE_R_W_ERROR => {
             // Read-Write Error Recovery mode page.
@@ -1430,13 +1680,18 @@ fn scsi_command_emulate_service_action_in_16(
         let mut nb_sectors = dev_lock.disk_sectors;
         nb_sectors /= (block_size / DEFAULT_SECTOR_SIZE) as u64;
         nb_sectors -= 1;
+        let discard = dev_lock.discard_enabled();
 
         drop(dev_lock);
 
         // Byte[0-7]: Returned Logical BLock Address(the logical block address of the last logical block).
         // Byte[8-11]: Logical Block Length in Bytes.
+        // Byte[14]: bit 7: LBPME(Logical Block Provisioning Management Enabled).
         BigEndian::write_u64(&mut outbuf[0..8], nb_sectors);
         BigEndian::write_u32(&mut outbuf[8..12], block_size);
+        if discard {
+            outbuf[14] = 0x80;
+        }
 
         return Ok(outbuf);
     }
@@ -1627,7 +1882,7 @@ fn scsi_command_emulate_get_event_status_notification(
 ) -> Result<Vec<u8>> {
     // Byte4: Notification Class Request.
     let notification_class_request = cmd.buf[4];
-    let dev_lock = dev.lock().unwrap();
+    let mut dev_lock = dev.lock().unwrap();
 
     if dev_lock.scsi_type != SCSI_TYPE_ROM {
         bail!("Invalid scsi tye {}", dev_lock.scsi_type);
@@ -1661,10 +1916,14 @@ fn scsi_command_emulate_get_event_status_notification(
         // Byte6: Start Slot.
         // Byte7: End Slot.
 
-        // Do not support hot-plug/hot-unplug scsi cd which will be present all the time once vm starts.
-        // To do: this outbuf event code and media status should be changed after allowing hot-plug.
-        outbuf[4] = GESN_EC_NOCHG;
-        outbuf[5] = 1 << GESN_MS_MEDIA_PRESENT_BIT;
+        // The media event is reported only once after the medium is changed or ejected.
+        // The tray is open when there is no medium.
+        outbuf[4] = dev_lock.media_event;
+        dev_lock.media_event = GESN_EC_NOCHG;
+        outbuf[5] = match dev_lock.disk_image {
+            Some(_) => 1 << GESN_MS_MEDIA_PRESENT_BIT,
+            None => 1 << GESN_MS_DOOR_OR_TRAY_OPEN_BIT,
+        };
     } else {
         // NCE = 1.
         outbuf[2] = 0x80;
@@ -1742,3 +2001,30 @@ fn cdrom_read_formatted_toc(nb_sectors: u32, msf: u8, track_number: u8) -> Resul
 
     Ok(outbuf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_block_range() {
+        // 1MiB disk with 512-byte blocks.
+        let disk_sectors = 2048;
+        assert_eq!(
+            ScsiRequest::get_block_range(disk_sectors, 0, 2048).unwrap(),
+            (0, 1 << 20)
+        );
+        assert_eq!(
+            ScsiRequest::get_block_range(disk_sectors, 2047, 1).unwrap(),
+            ((1 << 20) - 512, 512)
+        );
+        assert!(ScsiRequest::get_block_range(disk_sectors, 2047, 2).is_err());
+        assert!(ScsiRequest::get_block_range(disk_sectors, 2048, 1).is_err());
+
+        // The byte offset of lba overflows, which must not wrap to a small offset.
+        assert!(ScsiRequest::get_block_range(disk_sectors, (1 << 55) + 1, 1).is_err());
+        assert!(ScsiRequest::get_block_range(disk_sectors, u64::MAX, 1).is_err());
+        assert!(ScsiRequest::get_block_range(disk_sectors, 1, (1 << 55) + 1).is_err());
+        assert!(ScsiRequest::get_block_range(disk_sectors, 1, u64::MAX).is_err());
+    }
+}
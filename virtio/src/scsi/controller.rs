@@ -22,23 +22,26 @@ use std::sync::{Arc, Mutex};
 use anyhow::{anyhow, bail, Context, Result};
 
 use super::super::{
-    report_virtio_error, Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_SCSI_F_CHANGE,
-    VIRTIO_SCSI_F_HOTPLUG, VIRTIO_TYPE_SCSI,
+    packed_ring_feature, report_virtio_error, Element, Queue, VirtioDevice, VirtioInterrupt,
+    VirtioInterruptType, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
+    VIRTIO_SCSI_F_CHANGE, VIRTIO_SCSI_F_HOTPLUG, VIRTIO_TYPE_SCSI,
 };
+use crate::block::unregister_io_limits;
 use crate::ScsiBus::{
     virtio_scsi_get_lun, ScsiBus, ScsiRequest, ScsiSense, CHECK_CONDITION, EMULATE_SCSI_OPS, GOOD,
     SCSI_SENSE_INVALID_OPCODE,
 };
+use crate::ScsiDisk::ScsiDevice;
 use crate::VirtioError;
 use address_space::{AddressSpace, GuestAddress};
 use log::{debug, error, info};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use machine_manager::machine::{unregister_block_stats, BlockStats};
 use machine_manager::{
     config::{ScsiCntlrConfig, VIRTIO_SCSI_MAX_LUN, VIRTIO_SCSI_MAX_TARGET},
     event_loop::EventLoop,
 };
-use util::aio::{Aio, AioCb, AioEngine, Iovec, OpCode};
+use util::aio::{raw_datasync, Aio, AioCb, AioEngine, Iovec, OpCode};
 use util::byte_code::ByteCode;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
@@ -189,12 +192,20 @@ impl VirtioDevice for ScsiCntlr {
             | (1_u64 << VIRTIO_SCSI_F_HOTPLUG)
             | (1_u64 << VIRTIO_SCSI_F_CHANGE)
             | (1_u64 << VIRTIO_F_RING_EVENT_IDX)
-            | (1_u64 << VIRTIO_F_RING_INDIRECT_DESC);
+            | (1_u64 << VIRTIO_F_RING_INDIRECT_DESC)
+            | packed_ring_feature();
 
         Ok(())
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        if let Some(bus) = &self.bus {
+            for dev in bus.lock().unwrap().devices.values() {
+                let id = dev.lock().unwrap().config.id.clone();
+                unregister_io_limits(&id);
+                unregister_block_stats(&id);
+            }
+        }
         Ok(())
     }
 
@@ -311,33 +322,66 @@ impl VirtioDevice for ScsiCntlr {
             &mut self.deactivate_evts,
         )?;
 
+        let bus = self
+            .bus
+            .clone()
+            .with_context(|| "Scsi controller has no bus!")?;
         let queues_num = queues.len();
+        let mut cmd_queue_evts = Vec::new();
         for cmd_queue in queues.iter().take(queues_num).skip(2) {
-            if let Some(bus) = &self.bus {
-                let mut cmd_handler = ScsiCmdHandler {
-                    aio: None,
-                    scsibus: bus.clone(),
-                    queue: cmd_queue.clone(),
-                    queue_evt: queue_evts.remove(0),
-                    mem_space: mem_space.clone(),
-                    interrupt_cb: interrupt_cb.clone(),
-                    driver_features: self.state.driver_features,
-                    device_broken: self.broken.clone(),
-                };
+            let queue_evt = queue_evts.remove(0);
+            cmd_queue_evts.push(queue_evt.clone());
+            let mut cmd_handler = ScsiCmdHandler {
+                aio: None,
+                scsibus: bus.clone(),
+                queue: cmd_queue.clone(),
+                queue_evt,
+                mem_space: mem_space.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+                driver_features: self.state.driver_features,
+                iothread: self.config.iothread.clone(),
+                device_broken: self.broken.clone(),
+            };
 
-                cmd_handler.aio = Some(cmd_handler.build_aio()?);
+            cmd_handler.aio = Some(cmd_handler.build_aio()?);
 
-                let notifiers =
-                    EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(cmd_handler)));
-                register_event_helper(
-                    notifiers,
-                    self.config.iothread.as_ref(),
-                    &mut self.deactivate_evts,
-                )?;
-            } else {
-                bail!("Scsi controller has no bus!");
-            }
+            let notifiers =
+                EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(cmd_handler)));
+            register_event_helper(
+                notifiers,
+                self.config.iothread.as_ref(),
+                &mut self.deactivate_evts,
+            )?;
         }
+
+        // Register timer event notifier for IO limits of the devices on the bus, the
+        // throttled requests are resubmitted by kicking the cmd queues.
+        let throttle_evt = bus.lock().unwrap().throttle_evt.clone();
+        let device_broken = self.broken.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            if device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            let devices: Vec<Arc<Mutex<ScsiDevice>>> =
+                bus.lock().unwrap().devices.values().cloned().collect();
+            for dev in devices {
+                if let Some(throttle) = &dev.lock().unwrap().throttle {
+                    throttle.lock().unwrap().clear_timer();
+                }
+            }
+            for queue_evt in cmd_queue_evts.iter() {
+                queue_evt
+                    .write(1)
+                    .unwrap_or_else(|e| error!("Failed to kick scsi cmd queue, err is {:?}", e));
+            }
+            None
+        });
+        register_event_helper(
+            vec![build_event_notifier(throttle_evt.as_raw_fd(), handler)],
+            self.config.iothread.as_ref(),
+            &mut self.deactivate_evts,
+        )?;
         self.broken.store(false, Ordering::SeqCst);
 
         Ok(())
@@ -793,6 +837,8 @@ pub struct ScsiCmdHandler {
     driver_features: u64,
     /// Aio context.
     aio: Option<Box<Aio<ScsiCompleteCb>>>,
+    /// Thread name of io handler.
+    iothread: Option<String>,
     /// Device is broken or not.
     device_broken: Arc<AtomicBool>,
 }
@@ -902,6 +948,20 @@ impl ScsiCmdHandler {
             };
             drop(scsibus);
 
+            // Report the unit attention, no medium or write protected condition of the
+            // device before the command is executed.
+            let mut scsi_device_lock = scsidevice.lock().unwrap();
+            if scsi_device_lock.config.lun == req_lun_id {
+                if let Some(sense) = scsi_device_lock.check_condition(cmd.req.cdb[0]) {
+                    drop(scsi_device_lock);
+                    cmd.resp.set_scsi_sense(sense);
+                    cmd.resp.status = CHECK_CONDITION;
+                    cmd.complete(&self.mem_space)?;
+                    continue;
+                }
+            }
+            drop(scsi_device_lock);
+
             let cmd_h = Arc::new(Mutex::new(cmd));
             let scsi_req = if let Ok(req) =
                 ScsiRequest::new(cmd_h.clone(), self.scsibus.clone(), scsidevice.clone())
@@ -922,30 +982,51 @@ impl ScsiCmdHandler {
             let scsi_device_lock = scsidevice.lock().unwrap();
             if scsi_req.opstype == EMULATE_SCSI_OPS {
                 let lun = scsi_device_lock.config.lun;
+                let stats = scsi_device_lock.stats.clone();
                 drop(scsi_device_lock);
                 let scsicompletecb = ScsiCompleteCb::new(
                     self.mem_space.clone(),
                     Arc::new(Mutex::new(scsi_req.clone())),
+                    stats,
+                    true,
                 );
                 // If found device's lun id is not equal to request lun id, this request is a target request.
                 scsi_req.emulate_execute(scsicompletecb, req_lun_id, lun)?;
             } else {
+                // Limit io operations and bandwidth if io limits are configured.
+                if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
+                    let (write, bytes) = scsi_req.io_size();
+                    if scsi_device_lock.throttled(ctx, write, bytes) {
+                        drop(scsi_device_lock);
+                        self.queue.lock().unwrap().vring.push_back();
+                        break;
+                    }
+                }
                 let direct = scsi_device_lock.config.direct;
                 let disk_img = scsi_device_lock.disk_image.as_ref().unwrap().clone();
+                // Data written by guest is synced to the overlay file in snapshot mode.
+                let file_fd = match scsi_device_lock.driver.as_ref() {
+                    Some(driver) => driver.lock().unwrap().data_fd(),
+                    None => disk_img.as_raw_fd(),
+                };
                 let req_align = scsi_device_lock.req_align;
                 let buf_align = scsi_device_lock.buf_align;
+                let stats = scsi_device_lock.stats.clone();
+                let write_cache = scsi_device_lock.config.write_cache;
                 drop(scsi_device_lock);
 
                 let scsicompletecb = ScsiCompleteCb::new(
                     self.mem_space.clone(),
                     Arc::new(Mutex::new(scsi_req.clone())),
+                    stats,
+                    write_cache,
                 );
                 if let Some(ref mut aio) = self.aio {
                     let aiocb = AioCb {
                         direct,
                         req_align,
                         buf_align,
-                        file_fd: disk_img.as_raw_fd(),
+                        file_fd,
                         opcode: OpCode::Noop,
                         iovec: Vec::new(),
                         offset: 0,
@@ -962,12 +1043,23 @@ impl ScsiCmdHandler {
         Ok(())
     }
 
-    fn complete_func(aiocb: &AioCb<ScsiCompleteCb>, ret: i64) -> Result<()> {
+    pub(crate) fn complete_func(aiocb: &AioCb<ScsiCompleteCb>, ret: i64) -> Result<()> {
         let complete_cb = &aiocb.iocompletecb;
+        complete_cb
+            .stats
+            .account(aiocb.opcode, aiocb.nbytes, ret >= 0);
         let request = &aiocb.iocompletecb.req.lock().unwrap();
         let mut virtio_scsi_req = request.virtioscsireq.lock().unwrap();
 
-        virtio_scsi_req.resp.response = if ret < 0 {
+        // Without write cache, data is synced before the write is completed.
+        let synced = complete_cb.write_cache
+            || aiocb.opcode != OpCode::Pwritev
+            || ret < 0
+            || raw_datasync(aiocb.file_fd) >= 0;
+        if !synced {
+            error!("Failed to sync data of scsi write request");
+        }
+        virtio_scsi_req.resp.response = if ret < 0 || !synced {
             VIRTIO_SCSI_S_FAILURE
         } else {
             VIRTIO_SCSI_S_OK
@@ -991,10 +1083,24 @@ impl ScsiCmdHandler {
 pub struct ScsiCompleteCb {
     pub mem_space: Arc<AddressSpace>,
     req: Arc<Mutex<ScsiRequest>>,
+    /// IO statistics of the scsi device.
+    stats: Arc<BlockStats>,
+    /// The scsi device has a volatile write cache.
+    write_cache: bool,
 }
 
 impl ScsiCompleteCb {
-    fn new(mem_space: Arc<AddressSpace>, req: Arc<Mutex<ScsiRequest>>) -> Self {
-        ScsiCompleteCb { mem_space, req }
+    fn new(
+        mem_space: Arc<AddressSpace>,
+        req: Arc<Mutex<ScsiRequest>>,
+        stats: Arc<BlockStats>,
+        write_cache: bool,
+    ) -> Self {
+        ScsiCompleteCb {
+            mem_space,
+            req,
+            stats,
+            write_cache,
+        }
     }
 }
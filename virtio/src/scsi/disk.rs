@@ -17,8 +17,21 @@ use std::sync::{Arc, Mutex, Weak};
 
 use anyhow::{bail, Context, Result};
 
-use crate::ScsiBus::ScsiBus;
+use crate::block::{register_io_limits, BlockDriverOps};
+use crate::block_overlay::{anonymous_overlay, BlockOverlay};
+use crate::ScsiBus::{
+    ScsiBus, ScsiSense, ALLOW_MEDIUM_REMOVAL, GESN_EC_MEDIAREMOVAL, GESN_EC_NEWMEDIA,
+    GESN_EC_NOCHG, GET_CONFIGURATION, GET_EVENT_STATUS_NOTIFICATION, INQUIRY, MODE_SENSE,
+    MODE_SENSE_10, REPORT_LUNS, REQUEST_SENSE, SCSI_SENSE_CAPACITY_CHANGED,
+    SCSI_SENSE_MEDIUM_CHANGED, SCSI_SENSE_NO_MEDIUM, SCSI_SENSE_UNIT_ATTENTION_NO_MEDIUM,
+    SCSI_SENSE_WRITE_PROTECTED, START_STOP, UNMAP, WRITE_10, WRITE_12, WRITE_16, WRITE_6,
+    WRITE_SAME_10, WRITE_SAME_16, WRITE_VERIFY_10, WRITE_VERIFY_12, WRITE_VERIFY_16,
+};
 use machine_manager::config::{DriveFile, ScsiDevConfig, VmConfig};
+use machine_manager::machine::{register_block_stats, BlockStats};
+use util::file::{get_file_alignment, open_file};
+use util::leak_bucket::{IoLimits, IoThrottle};
+use util::loop_context::EventLoopContext;
 
 /// SCSI DEVICE TYPES.
 pub const SCSI_TYPE_DISK: u32 = 0x00;
@@ -57,6 +70,33 @@ pub const SCSI_DISK_DEFAULT_BLOCK_SIZE: u32 = 1 << SCSI_DISK_DEFAULT_BLOCK_SIZE_
 pub const SCSI_CDROM_DEFAULT_BLOCK_SIZE_SHIFT: u32 = 11;
 pub const SCSI_CDROM_DEFAULT_BLOCK_SIZE: u32 = 1 << SCSI_CDROM_DEFAULT_BLOCK_SIZE_SHIFT;
 
+/// Commands which can be executed without medium.
+const NO_MEDIUM_COMMANDS: [u8; 9] = [
+    INQUIRY,
+    REPORT_LUNS,
+    REQUEST_SENSE,
+    MODE_SENSE,
+    MODE_SENSE_10,
+    GET_CONFIGURATION,
+    GET_EVENT_STATUS_NOTIFICATION,
+    START_STOP,
+    ALLOW_MEDIUM_REMOVAL,
+];
+
+/// Commands which modify the medium.
+const WRITE_COMMANDS: [u8; 10] = [
+    WRITE_6,
+    WRITE_10,
+    WRITE_12,
+    WRITE_16,
+    WRITE_VERIFY_10,
+    WRITE_VERIFY_12,
+    WRITE_VERIFY_16,
+    WRITE_SAME_10,
+    WRITE_SAME_16,
+    UNMAP,
+];
+
 #[derive(Clone, Default)]
 pub struct ScsiDevState {
     /// Features which the scsi device supports.
@@ -71,6 +111,8 @@ pub struct ScsiDevState {
     pub version: String,
     /// Scsi device serial number.
     pub serial: String,
+    /// World wide name of the scsi device.
+    pub wwn: Option<u64>,
 }
 
 impl ScsiDevState {
@@ -82,6 +124,7 @@ impl ScsiDevState {
             device_id: "".to_string(),
             version: "".to_string(),
             serial: "".to_string(),
+            wwn: None,
         }
     }
 }
@@ -94,6 +137,8 @@ pub struct ScsiDevice {
     pub state: ScsiDevState,
     /// Image file opened.
     pub disk_image: Option<Arc<File>>,
+    /// Anonymous overlay of snapshot mode, which serves the data requests if exists.
+    pub(crate) driver: Option<Arc<Mutex<dyn BlockDriverOps>>>,
     /// The align requirement of request(offset/len).
     pub req_align: u32,
     /// The align requirement of buffer(iova_base).
@@ -106,6 +151,20 @@ pub struct ScsiDevice {
     pub scsi_type: u32,
     /// Scsi Bus attached to.
     pub parent_bus: Weak<Mutex<ScsiBus>>,
+    /// Capacity of the device has changed, which should be reported to the guest
+    /// by unit attention condition.
+    pub capacity_changed: bool,
+    /// Medium of the cdrom has been changed or ejected, which should be reported to
+    /// the guest by unit attention condition.
+    pub media_changed: bool,
+    /// Media event code which is reported by the next GET EVENT STATUS NOTIFICATION.
+    pub media_event: u8,
+    /// IO statistics of the scsi device.
+    pub stats: Arc<BlockStats>,
+    /// IO limits of the device, which may be changed at runtime.
+    pub(crate) io_limits: Arc<Mutex<IoLimits>>,
+    /// Using the leak buckets to implement IO limits.
+    pub(crate) throttle: Option<Arc<Mutex<IoThrottle>>>,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
 }
@@ -120,12 +179,19 @@ impl ScsiDevice {
             config,
             state: ScsiDevState::new(),
             disk_image: None,
+            driver: None,
             req_align: 1,
             buf_align: 1,
             disk_sectors: 0,
             block_size: 0,
             scsi_type,
             parent_bus: Weak::new(),
+            capacity_changed: false,
+            media_changed: false,
+            media_event: GESN_EC_NOCHG,
+            stats: Arc::new(BlockStats::default()),
+            io_limits: Arc::new(Mutex::new(IoLimits::default())),
+            throttle: None,
             drive_files,
         }
     }
@@ -139,6 +205,7 @@ impl ScsiDevice {
             SCSI_TYPE_ROM => {
                 self.block_size = SCSI_CDROM_DEFAULT_BLOCK_SIZE;
                 self.state.product = "STRA CDROM".to_string();
+                self.config.read_only = true;
             }
             _ => {
                 bail!("Scsi type {} does not support now", self.scsi_type);
@@ -148,8 +215,19 @@ impl ScsiDevice {
         if let Some(serial) = &self.config.serial {
             self.state.serial = serial.clone();
         }
+        if let Some(vendor) = &self.config.vendor {
+            self.state.vendor = vendor.clone();
+        }
+        if let Some(product) = &self.config.product {
+            self.state.product = product.clone();
+        }
+        if let Some(product_rev) = &self.config.product_rev {
+            self.state.version = product_rev.clone();
+        }
+        self.state.wwn = self.config.wwn;
         let mut disk_size = DUMMY_IMG_SIZE;
 
+        self.driver = None;
         if !self.config.path_on_host.is_empty() {
             self.disk_image = None;
 
@@ -158,6 +236,15 @@ impl ScsiDevice {
             disk_size = file
                 .seek(SeekFrom::End(0))
                 .with_context(|| "Failed to seek the end for scsi device")?;
+            // Cdrom is read-only, so it never needs the overlay.
+            if self.config.snapshot && !self.config.read_only {
+                let base = file
+                    .try_clone()
+                    .with_context(|| "Failed to clone the image file for snapshot")?;
+                let block_overlay = BlockOverlay::new(base, anonymous_overlay()?)
+                    .with_context(|| "Failed to create overlay for snapshot")?;
+                self.driver = Some(Arc::new(Mutex::new(block_overlay)));
+            }
             self.disk_image = Some(Arc::new(file));
 
             let alignments = VmConfig::fetch_drive_align(&drive_files, &self.config.path_on_host)?;
@@ -171,6 +258,168 @@ impl ScsiDevice {
 
         self.disk_sectors = disk_size >> SECTOR_SHIFT;
 
+        // The wakeup event of the throttle is shared by the devices on the same bus.
+        *self.io_limits.lock().unwrap() = self.config.throttle;
+        let throttle = match self.parent_bus.upgrade() {
+            Some(bus) => {
+                let throttle_evt = bus.lock().unwrap().throttle_evt.clone();
+                IoThrottle::with_timer(self.config.throttle, throttle_evt)
+            }
+            None => IoThrottle::new(self.config.throttle)?,
+        };
+        self.throttle = Some(Arc::new(Mutex::new(throttle)));
+
+        self.stats.reset();
+        if !self.config.id.is_empty() {
+            register_io_limits(&self.config.id, self.io_limits.clone());
+            register_block_stats(&self.config.id, self.stats.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Return true if the request should be throttled by the io limits of the device,
+    /// the limits changed by QMP are picked up first.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - used for delay function call.
+    /// * `write` - whether the request is write or read.
+    /// * `bytes` - the data length of the request.
+    pub(crate) fn throttled(&self, ctx: &mut EventLoopContext, write: bool, bytes: u64) -> bool {
+        let throttle = match &self.throttle {
+            Some(throttle) => throttle,
+            None => return false,
+        };
+        let mut locked_throttle = throttle.lock().unwrap();
+        locked_throttle.set_limits(*self.io_limits.lock().unwrap());
+        locked_throttle.throttled(ctx, write, bytes)
+    }
+
+    /// Grow the capacity of the scsi disk to `size` bytes. The change is reported
+    /// to the guest by the next command sent to the disk.
+    pub fn resize(&mut self, size: u64) -> Result<()> {
+        if self.scsi_type != SCSI_TYPE_DISK {
+            bail!("Scsi device {} is not a disk", self.config.id);
+        }
+        let disk_image = self
+            .disk_image
+            .as_ref()
+            .with_context(|| format!("Scsi device {} has no image file", self.config.id))?;
+        if self.driver.is_some() {
+            bail!(
+                "Resizing scsi device {} in snapshot mode is not supported",
+                self.config.id
+            );
+        }
+        if size % self.block_size as u64 != 0 {
+            bail!(
+                "Size {} is not aligned to block size {}",
+                size,
+                self.block_size
+            );
+        }
+        let disk_sectors = size >> SECTOR_SHIFT;
+        if disk_sectors < self.disk_sectors {
+            bail!(
+                "Shrinking scsi device {} from {} to {} bytes is not supported",
+                self.config.id,
+                self.disk_sectors << SECTOR_SHIFT,
+                size
+            );
+        }
+        let mut file: &File = disk_image;
+        let file_size = file
+            .seek(SeekFrom::End(0))
+            .with_context(|| "Failed to seek the end for scsi device")?;
+        if file_size < size {
+            bail!(
+                "Image file of scsi device {} is {} bytes, it must be resized on host first",
+                self.config.id,
+                file_size
+            );
+        }
+
+        self.capacity_changed = disk_sectors != self.disk_sectors;
+        self.disk_sectors = disk_sectors;
+        Ok(())
+    }
+
+    /// Replace the medium of the cdrom with the image file `path`. The change is
+    /// reported to the guest by the next command sent to the cdrom.
+    pub fn change_medium(&mut self, path: &str) -> Result<()> {
+        if self.scsi_type != SCSI_TYPE_ROM {
+            bail!("Scsi device {} is not a cdrom", self.config.id);
+        }
+        let mut file = open_file(path, true, self.config.direct)?;
+        let disk_size = file
+            .seek(SeekFrom::End(0))
+            .with_context(|| format!("Failed to seek the end of {}", path))?;
+        let (req_align, buf_align) = get_file_alignment(&file, self.config.direct);
+
+        self.disk_image = Some(Arc::new(file));
+        self.req_align = req_align;
+        self.buf_align = buf_align;
+        self.disk_sectors = disk_size >> SECTOR_SHIFT;
+        self.config.path_on_host = path.to_string();
+        self.media_changed = true;
+        self.media_event = GESN_EC_NEWMEDIA;
+        Ok(())
+    }
+
+    /// Remove the medium of the cdrom.
+    pub fn eject(&mut self) -> Result<()> {
+        if self.scsi_type != SCSI_TYPE_ROM {
+            bail!("Scsi device {} is not a cdrom", self.config.id);
+        }
+        if self.disk_image.is_none() {
+            bail!("Scsi device {} has no medium", self.config.id);
+        }
+        self.disk_image = None;
+        self.disk_sectors = 0;
+        self.config.path_on_host.clear();
+        self.media_changed = true;
+        self.media_event = GESN_EC_MEDIAREMOVAL;
         Ok(())
     }
+
+    /// Get the sense of the condition which fails `command` before it's executed:
+    /// pending unit attention, no medium, or write to the cdrom.
+    pub fn check_condition(&mut self, command: u8) -> Option<ScsiSense> {
+        // Unit attention is reported to the first command which is not INQUIRY,
+        // REPORT LUNS or REQUEST SENSE.
+        if ![INQUIRY, REPORT_LUNS, REQUEST_SENSE].contains(&command) {
+            if self.media_changed {
+                self.media_changed = false;
+                self.capacity_changed = false;
+                return Some(match self.disk_image {
+                    Some(_) => SCSI_SENSE_MEDIUM_CHANGED,
+                    None => SCSI_SENSE_UNIT_ATTENTION_NO_MEDIUM,
+                });
+            }
+            if self.capacity_changed {
+                self.capacity_changed = false;
+                return Some(SCSI_SENSE_CAPACITY_CHANGED);
+            }
+        }
+        if self.scsi_type == SCSI_TYPE_ROM {
+            if self.disk_image.is_none() && !NO_MEDIUM_COMMANDS.contains(&command) {
+                return Some(SCSI_SENSE_NO_MEDIUM);
+            }
+            if WRITE_COMMANDS.contains(&command) {
+                return Some(SCSI_SENSE_WRITE_PROTECTED);
+            }
+        }
+        None
+    }
+
+    /// Whether UNMAP command deallocates the range of the image.
+    pub fn discard_enabled(&self) -> bool {
+        self.scsi_type == SCSI_TYPE_DISK && self.config.discard && !self.config.read_only
+    }
+
+    /// Whether WRITE SAME command with zeroed data zeroes the range of the image.
+    pub fn write_zeroes_enabled(&self) -> bool {
+        self.scsi_type == SCSI_TYPE_DISK && self.config.write_zeroes && !self.config.read_only
+    }
 }
@@ -21,6 +21,7 @@ use address_space::AddressSpace;
 use anyhow::{anyhow, bail, Context, Result};
 use machine_manager::config::NetworkInterfaceConfig;
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use migration::{MigrationError, MigrationHook, MigrationManager, StateTransfer};
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
 use util::num_ops::read_u32;
@@ -29,12 +30,16 @@ use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::ioctl::ioctl_with_ref;
 
 use super::super::super::{
-    net::{build_device_config_space, create_tap, CtrlInfo, VirtioNetState, MAC_ADDR_LEN},
+    net::{
+        build_device_config_space, create_tap, get_disabled_offload_features,
+        get_tap_offload_flags, CtrlInfo, VirtioNetState, MAC_ADDR_LEN,
+    },
     CtrlVirtio, NetCtrlHandler, Queue, VirtioDevice, VirtioInterrupt, VIRTIO_F_ACCESS_PLATFORM,
     VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
     VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM,
-    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO,
-    VIRTIO_NET_F_MQ, VIRTIO_TYPE_NET,
+    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO,
+    VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MQ,
+    VIRTIO_TYPE_NET,
 };
 use super::super::{VhostNotify, VhostOps};
 use super::{VhostBackend, VhostIoHandler, VhostVringFile, VHOST_NET_SET_BACKEND};
@@ -141,11 +146,15 @@ impl VirtioDevice for Net {
             | 1 << VIRTIO_NET_F_CSUM
             | 1 << VIRTIO_NET_F_GUEST_CSUM
             | 1 << VIRTIO_NET_F_GUEST_TSO4
+            | 1 << VIRTIO_NET_F_GUEST_TSO6
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
+            | 1 << VIRTIO_NET_F_HOST_TSO6
             | 1 << VIRTIO_NET_F_HOST_UFO;
+        device_features &= !get_disabled_offload_features(&self.net_cfg.offload);
 
         let mut locked_state = self.state.lock().unwrap();
+        locked_state.vhost = true;
         if self.net_cfg.mq
             && (VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN..=VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX)
                 .contains(&queue_pairs)
@@ -174,6 +183,10 @@ impl VirtioDevice for Net {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(
+            VirtioNetState::descriptor(),
+            &self.net_cfg.id,
+        );
         Ok(())
     }
 
@@ -278,6 +291,16 @@ impl VirtioDevice for Net {
             )?;
         }
 
+        // Keep the tap offload consistent with the features negotiated, as the
+        // in-VMM datapath does.
+        let flags = get_tap_offload_flags(driver_features);
+        if let Some(taps) = &self.taps {
+            for tap in taps.iter() {
+                tap.set_offload(flags)
+                    .with_context(|| "Failed to set tap offload for vhost net")?;
+            }
+        }
+
         let queue_pairs = queue_num / 2;
         for index in 0..queue_pairs {
             let mut host_notifies = Vec::new();
@@ -407,11 +430,49 @@ impl VirtioDevice for Net {
     }
 }
 
+impl StateTransfer for Net {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        // The rings and guest memory are accessed by vhost-net kernel module,
+        // whose dirty pages can't be tracked now.
+        bail!(
+            "vhost-net of net device {} doesn't support migration, use vhost=off",
+            self.net_cfg.id
+        );
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let mut net_state = VirtioNetState::default();
+        let s_len = std::mem::size_of::<VirtioNetState>();
+        if state.len() != s_len {
+            bail!("Invalid state length {}, expected {}", state.len(), s_len);
+        }
+        net_state.as_mut_bytes().copy_from_slice(state);
+        if !net_state.vhost {
+            return Err(anyhow!(MigrationError::MigrationConfigErr(
+                format!("vhost of net device {}", self.net_cfg.id),
+                "off".to_string(),
+                "on".to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        if let Some(alias) = MigrationManager::get_desc_alias(&VirtioNetState::descriptor().name) {
+            alias
+        } else {
+            !0
+        }
+    }
+}
+
+impl MigrationHook for Net {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use address_space::*;
-    use machine_manager::config::DEFAULT_VIRTQUEUE_SIZE;
+    use machine_manager::config::{NetOffloadConfig, DEFAULT_VIRTQUEUE_SIZE};
     use std::fs::File;
 
     const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
@@ -441,6 +502,24 @@ mod tests {
         sys_space
     }
 
+    #[test]
+    fn test_vhost_net_migration_state() {
+        let net_cfg = NetworkInterfaceConfig {
+            id: "net0".to_string(),
+            vhost_type: Some("vhost-kernel".to_string()),
+            ..Default::default()
+        };
+        let mut vhost_net = Net::new(&net_cfg, &vhost_address_space_init());
+        // Migration of vhost-net is refused in source.
+        assert!(vhost_net.get_state_vec().is_err());
+
+        // The state from in-VMM datapath mismatches.
+        let mut net_state = VirtioNetState::default();
+        assert!(vhost_net.set_state_mut(net_state.as_bytes()).is_err());
+        net_state.vhost = true;
+        assert!(vhost_net.set_state_mut(net_state.as_bytes()).is_ok());
+    }
+
     #[test]
     fn test_vhost_net_realize() {
         let net1 = NetworkInterfaceConfig {
@@ -455,6 +534,10 @@ mod tests {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            rx_max_packets: 0,
+            rx_usecs: 0,
+            offload: NetOffloadConfig::default(),
+            boot_index: None,
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -477,6 +560,10 @@ mod tests {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            rx_max_packets: 0,
+            rx_usecs: 0,
+            offload: NetOffloadConfig::default(),
+            boot_index: None,
         };
         let conf = vec![net1];
         let confs = Some(conf);
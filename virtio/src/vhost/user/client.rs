@@ -298,6 +298,10 @@ impl Listener for VhostUserMemInfo {
         self.enabled = false;
     }
 
+    fn pin_owner(&self) -> Option<String> {
+        Some("vhost-user".to_string())
+    }
+
     fn handle_request(
         &self,
         range: Option<&FlatRange>,
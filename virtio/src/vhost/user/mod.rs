@@ -24,3 +24,13 @@ pub use self::client::*;
 pub use self::fs::*;
 pub use self::message::*;
 pub use self::sock::*;
+
+use util::seccomp::BpfRule;
+
+/// Create a syscall bpf rule for vhost-user devices.
+pub fn vhost_user_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
+    syscall_allow_list.extend(vec![
+        BpfRule::new(libc::SYS_socket),
+        BpfRule::new(libc::SYS_connect),
+    ])
+}
@@ -26,7 +26,10 @@ use util::num_ops::read_u32;
 use vmm_sys_util::eventfd::EventFd;
 
 use super::super::super::{
-    net::{build_device_config_space, CtrlInfo, VirtioNetState, MAC_ADDR_LEN},
+    net::{
+        build_device_config_space, get_disabled_offload_features, CtrlInfo, VirtioNetState,
+        MAC_ADDR_LEN,
+    },
     CtrlVirtio, NetCtrlHandler, Queue, VirtioDevice, VirtioInterrupt, VIRTIO_F_RING_EVENT_IDX,
     VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
     VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM,
@@ -140,7 +143,8 @@ impl VirtioDevice for Net {
             | 1 << VIRTIO_NET_F_HOST_UFO
             | 1 << VIRTIO_NET_F_MRG_RXBUF
             | 1 << VIRTIO_F_RING_EVENT_IDX;
-        locked_state.device_features &= features;
+        locked_state.device_features &=
+            features & !get_disabled_offload_features(&self.net_cfg.offload);
 
         let queue_pairs = self.net_cfg.queues / 2;
         if self.net_cfg.mq
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp::min;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -273,10 +274,14 @@ impl VirtioMmioCommonConfig {
                         .lock()
                         .unwrap()
                         .set_driver_features(self.acked_features_select, value);
-                    if self.acked_features_select == 1
-                        && virtio_has_feature(u64::from(value) << 32, VIRTIO_F_RING_PACKED)
-                    {
-                        self.queue_type = QUEUE_TYPE_PACKED_VRING;
+                    if self.acked_features_select == 1 {
+                        let features =
+                            u64::from(device.lock().unwrap().get_driver_features(1)) << 32;
+                        if virtio_has_feature(features, VIRTIO_F_RING_PACKED) {
+                            self.queue_type = QUEUE_TYPE_PACKED_VRING;
+                        } else {
+                            self.queue_type = QUEUE_TYPE_SPLIT_VRING;
+                        }
                     }
                 } else {
                     return Err(anyhow!(VirtioError::DevStatErr(self.device_status)));
@@ -284,9 +289,10 @@ impl VirtioMmioCommonConfig {
             }
             DRIVER_FEATURES_SEL_REG => self.acked_features_select = value,
             QUEUE_SEL_REG => self.queue_select = value,
+            // The driver may only shrink the queue, clamp it to what the device offers.
             QUEUE_NUM_REG => self
                 .get_mut_queue_config()
-                .map(|config| config.size = value as u16)?,
+                .map(|config| config.size = min(value, u32::from(config.max_size)) as u16)?,
             QUEUE_READY_REG => self
                 .get_mut_queue_config()
                 .map(|config| config.ready = value == 1)?,
@@ -372,6 +378,9 @@ impl VirtioMmioDevice {
         region_size: u64,
         #[cfg(target_arch = "x86_64")] bs: &Arc<Mutex<BootSource>>,
     ) -> Result<Arc<Mutex<Self>>> {
+        if region_base >= sysbus.mmio_region.1 {
+            bail!("Mmio region space exhausted.");
+        }
         self.assign_interrupt_cb();
         self.device
             .lock()
@@ -379,9 +388,6 @@ impl VirtioMmioDevice {
             .realize()
             .with_context(|| "Failed to realize virtio.")?;
 
-        if region_base >= sysbus.mmio_region.1 {
-            bail!("Mmio region space exhausted.");
-        }
         self.set_sys_resource(sysbus, region_base, region_size)?;
         let dev = Arc::new(Mutex::new(self));
         sysbus.attach_device(&dev, region_base, region_size)?;
@@ -669,6 +675,22 @@ impl StateTransfer for VirtioMmioDevice {
         if state.len() != s_len {
             bail!("Invalid state length {}, expected {}", state.len(), s_len);
         }
+        let new_state = VirtioMmioState::from_bytes(state).ok_or_else(|| {
+            anyhow!(migration::error::MigrationError::FromBytesError(
+                "MMIO_DEVICE"
+            ))
+        })?;
+        let queue_size = self.device.lock().unwrap().queue_size();
+        if let Some(q) = new_state.config_space.queues_config[0..new_state.config_space.queue_num]
+            .iter()
+            .find(|q| q.max_size != queue_size)
+        {
+            bail!(
+                "Virtio queue size mismatch: source uses {}, destination uses {}",
+                q.max_size,
+                queue_size
+            );
+        }
         let mut locked_state = self.state.lock().unwrap();
         locked_state.as_mut_bytes().copy_from_slice(state);
         let cloned_mem_space = self.mem_space.clone();
@@ -708,6 +730,19 @@ impl StateTransfer for VirtioMmioDevice {
 }
 
 impl MigrationHook for VirtioMmioDevice {
+    fn post_restore(&self) -> migration::Result<()> {
+        if self.state.lock().unwrap().activated {
+            for (index, queue) in self.queues.iter().enumerate() {
+                let locked_queue = queue.lock().unwrap();
+                if locked_queue.is_enabled() && !locked_queue.is_valid(&self.mem_space) {
+                    bail!("Restored queue {} of virtio mmio device is invalid", index);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn resume(&mut self) -> migration::Result<()> {
         if self.state.lock().unwrap().activated {
             let mut queue_evts = Vec::<Arc<EventFd>>::new();
@@ -409,9 +409,10 @@ impl VirtioPciCommonConfig {
                     self.queue_select = value as u16;
                 }
             }
+            // The driver may only shrink the queue, clamp it to what the device offers.
             COMMON_Q_SIZE_REG => self
                 .get_mut_queue_config(true)
-                .map(|config| config.size = value as u16)?,
+                .map(|config| config.size = min(value, u32::from(config.max_size)) as u16)?,
             COMMON_Q_ENABLE_REG => {
                 if value != 1 {
                     error!("Driver set illegal value for queue_enable {}", value);
@@ -575,6 +576,8 @@ pub struct VirtioPciState {
     queues_config: [QueueConfig; 32],
     /// The number of queues.
     queue_num: usize,
+    /// The type of queues negotiated with driver.
+    queue_type: u16,
 }
 
 /// Virtio-PCI device structure
@@ -879,6 +882,7 @@ impl VirtioPciDevice {
         let common_region_ops = self.build_common_cfg_ops();
         let common_region =
             Region::init_io_region(u64::from(VIRTIO_PCI_CAP_COMMON_LENGTH), common_region_ops);
+        common_region.set_name("virtio-pci-common");
         modern_mem_region
             .add_subregion(common_region, u64::from(VIRTIO_PCI_CAP_COMMON_OFFSET))
             .with_context(|| "Failed to register pci-common-cap region.")?;
@@ -900,6 +904,7 @@ impl VirtioPciDevice {
         };
         let isr_region =
             Region::init_io_region(u64::from(VIRTIO_PCI_CAP_ISR_LENGTH), isr_region_ops);
+        isr_region.set_name("virtio-pci-isr");
         modern_mem_region
             .add_subregion(isr_region, u64::from(VIRTIO_PCI_CAP_ISR_OFFSET))
             .with_context(|| "Failed to register pci-isr-cap region.")?;
@@ -928,6 +933,7 @@ impl VirtioPciDevice {
         };
         let device_region =
             Region::init_io_region(u64::from(VIRTIO_PCI_CAP_DEVICE_LENGTH), device_region_ops);
+        device_region.set_name("virtio-pci-device");
         modern_mem_region
             .add_subregion(device_region, u64::from(VIRTIO_PCI_CAP_DEVICE_OFFSET))
             .with_context(|| "Failed to register pci-dev-cap region.")?;
@@ -942,6 +948,7 @@ impl VirtioPciDevice {
         let notify_region =
             Region::init_io_region(u64::from(VIRTIO_PCI_CAP_NOTIFY_LENGTH), notify_region_ops);
         notify_region.set_ioeventfds(&self.ioeventfds());
+        notify_region.set_name("virtio-pci-notify");
         modern_mem_region
             .add_subregion(notify_region, u64::from(VIRTIO_PCI_CAP_NOTIFY_OFFSET))
             .with_context(|| "Failed to register pci-notify-cap region.")?;
@@ -1185,22 +1192,19 @@ impl PciDevOps for VirtioPciDevice {
         let dev = Arc::new(Mutex::new(self));
         let pci_bus = dev.lock().unwrap().parent_bus.upgrade().unwrap();
         let mut locked_pci_bus = pci_bus.lock().unwrap();
-        let pci_device = locked_pci_bus.devices.get(&devfn);
-        if pci_device.is_none() {
-            locked_pci_bus.devices.insert(devfn, dev.clone());
-        } else {
-            bail!(
-                "Devfn {:?} has been used by {:?}",
-                &devfn,
-                pci_device.unwrap().lock().unwrap().name()
-            );
-        }
+        locked_pci_bus.attach_device(devfn, dev.clone())?;
         MigrationManager::register_transport_instance(VirtioPciState::descriptor(), dev, &name);
 
         Ok(())
     }
 
     fn unrealize(&mut self) -> PciResult<()> {
+        // Quiesce the queues before detaching, the guest may not have reset the device
+        // if it is removed without the guest's cooperation.
+        if !self.deactivate_device() {
+            bail!("Failed to deactivate virtio device {}", self.name);
+        }
+
         self.device
             .lock()
             .unwrap()
@@ -1249,6 +1253,10 @@ impl PciDevOps for VirtioPciDevice {
         self.name.clone()
     }
 
+    fn pci_config(&self) -> Option<&PciConfig> {
+        Some(&self.config)
+    }
+
     fn devfn(&self) -> Option<u8> {
         Some(self.devfn)
     }
@@ -1287,6 +1295,12 @@ impl PciDevOps for VirtioPciDevice {
                 let dev_path = self.populate_dev_path(parent_dev_path, self.devfn, "/scsi@");
                 Some(dev_path)
             }
+            VIRTIO_TYPE_NET => {
+                // Eg: OpenFirmware device path(virtio-net): /pci@i0cf8/ethernet@3[,1].
+                let parent_dev_path = self.get_parent_dev_path(parent_bus);
+                let dev_path = self.populate_dev_path(parent_dev_path, self.devfn, "/ethernet@");
+                Some(dev_path)
+            }
             _ => None,
         }
     }
@@ -1321,6 +1335,7 @@ impl StateTransfer for VirtioPciDevice {
             state.device_status = common_config.device_status;
             state.config_generation = common_config.config_generation;
             state.queue_select = common_config.queue_select;
+            state.queue_type = common_config.queue_type;
         }
 
         // Save virtio pci state.
@@ -1344,6 +1359,19 @@ impl StateTransfer for VirtioPciDevice {
             ),)
         })?;
 
+        let queue_size = self.device.lock().unwrap().queue_size();
+        if let Some(q) = pci_state.queues_config[0..pci_state.queue_num]
+            .iter()
+            .find(|q| q.max_size != queue_size)
+        {
+            bail!(
+                "Virtio queue size mismatch for {}: source uses {}, destination uses {}",
+                self.name,
+                q.max_size,
+                queue_size
+            );
+        }
+
         // Set virtio pci config state.
         let config_length = self.config.config.len();
         self.config.config = pci_state.config_space[..config_length].to_vec();
@@ -1363,6 +1391,11 @@ impl StateTransfer for VirtioPciDevice {
             common_config.device_status = pci_state.device_status;
             common_config.config_generation = pci_state.config_generation;
             common_config.queue_select = pci_state.queue_select;
+            // The state from older version doesn't carry queue type, which is split.
+            common_config.queue_type = match pci_state.queue_type {
+                0 => QUEUE_TYPE_SPLIT_VRING,
+                queue_type => queue_type,
+            };
         }
 
         // Set virtio pci state.
@@ -1402,6 +1435,19 @@ impl StateTransfer for VirtioPciDevice {
 }
 
 impl MigrationHook for VirtioPciDevice {
+    fn post_restore(&self) -> migration::Result<()> {
+        if self.device_activated.load(Ordering::Relaxed) {
+            for (index, queue) in self.queues.lock().unwrap().iter().enumerate() {
+                let locked_queue = queue.lock().unwrap();
+                if locked_queue.is_enabled() && !locked_queue.is_valid(&self.sys_mem) {
+                    bail!("Restored queue {} of virtio pci device is invalid", index);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn resume(&mut self) -> migration::Result<()> {
         if self.device_activated.load(Ordering::Relaxed) {
             // Reregister ioevents for notifies.
@@ -1657,6 +1703,16 @@ mod tests {
             .write_common_config(&virtio_pci, COMMON_Q_SIZE_REG, 128)
             .is_err());
 
+        // Queue size requested by driver is clamped to the maximum of device
+        cmn_cfg.device_status = CONFIG_STATUS_FEATURES_OK | CONFIG_STATUS_DRIVER;
+        cmn_cfg.queue_select = 0;
+        com_cfg_write_test!(cmn_cfg, virtio_pci, COMMON_Q_SIZE_REG, 128_u32);
+        assert_eq!(cmn_cfg.queues_config[0].size, 128);
+        cmn_cfg
+            .write_common_config(&virtio_pci, COMMON_Q_SIZE_REG, 0x10000)
+            .unwrap();
+        assert_eq!(cmn_cfg.queues_config[0].size, queue_size);
+
         // Test Queue ready register
         cmn_cfg.device_status = CONFIG_STATUS_FEATURES_OK | CONFIG_STATUS_DRIVER;
         cmn_cfg.queue_select = 0;
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+mod packed;
 mod split;
 
 use address_space::{AddressSpace, GuestAddress, RegionCache};
@@ -17,6 +18,7 @@ use anyhow::{bail, Result};
 use std::sync::Arc;
 use vmm_sys_util::eventfd::EventFd;
 
+pub use packed::*;
 pub use split::*;
 
 /// Split Virtqueue.
@@ -179,6 +181,7 @@ impl Queue {
     pub fn new(queue_config: QueueConfig, queue_type: u16) -> Result<Self> {
         let vring: Box<dyn VringOps + Send> = match queue_type {
             QUEUE_TYPE_SPLIT_VRING => Box::new(SplitVring::new(queue_config)),
+            QUEUE_TYPE_PACKED_VRING => Box::new(PackedVring::new(queue_config)),
             _ => {
                 bail!("Unsupported queue type {}", queue_type);
             }
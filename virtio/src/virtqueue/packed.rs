@@ -0,0 +1,806 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::num::Wrapping;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{fence, Ordering};
+use std::sync::Arc;
+
+use address_space::{AddressSpace, GuestAddress, RegionCache, RegionType};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, warn};
+use util::byte_code::ByteCode;
+
+use super::{
+    checked_offset_mem, ElemIovec, Element, QueueConfig, VringOps, VIRTQ_DESC_F_INDIRECT,
+    VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
+};
+use crate::{virtio_has_feature, VirtioError, VIRTIO_F_RING_EVENT_IDX};
+
+/// This marks a descriptor as available, compared with the avail wrap counter.
+const VRING_PACKED_DESC_F_AVAIL: u16 = 1 << 7;
+/// This marks a descriptor as used, compared with the used wrap counter.
+const VRING_PACKED_DESC_F_USED: u16 = 1 << 15;
+/// Enable events.
+const VRING_PACKED_EVENT_FLAG_ENABLE: u16 = 0x0;
+/// Disable events.
+const VRING_PACKED_EVENT_FLAG_DISABLE: u16 = 0x1;
+/// Only trigger the event at the descriptor specified by off_wrap.
+const VRING_PACKED_EVENT_FLAG_DESC: u16 = 0x2;
+/// The bit of wrap counter in the off_wrap field of event suppression structure.
+const VRING_PACKED_EVENT_F_WRAP_CTR: u16 = 15;
+/// The maximum size of packed virtqueue.
+const VRING_PACKED_MAX_SIZE: u16 = 1 << 15;
+
+/// Max total len of a descriptor chain.
+const DESC_CHAIN_MAX_TOTAL_LEN: u64 = 1u64 << 32;
+/// The length of packed descriptor.
+const PACKED_DESC_LEN: u64 = size_of::<PackedVringDesc>() as u64;
+/// The length of event suppression structure.
+const PACKED_EVENT_LEN: u64 = size_of::<PackedVringEvent>() as u64;
+/// The offset of len field in the packed descriptor.
+const PACKED_DESC_LEN_OFFSET: u64 = 8;
+/// The offset of id field in the packed descriptor.
+const PACKED_DESC_ID_OFFSET: u64 = 12;
+/// The offset of flags field in the packed descriptor.
+const PACKED_DESC_FLAGS_OFFSET: u64 = 14;
+
+/// Descriptor of packed vring.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub struct PackedVringDesc {
+    /// Address (guest-physical).
+    pub addr: GuestAddress,
+    /// Length.
+    pub len: u32,
+    /// Buffer id.
+    pub id: u16,
+    /// The flags as indicated above.
+    pub flags: u16,
+}
+
+impl ByteCode for PackedVringDesc {}
+
+impl PackedVringDesc {
+    /// Return true if the descriptor is made available by driver in current round.
+    fn is_avail(&self, wrap_counter: bool) -> bool {
+        let avail = self.flags & VRING_PACKED_DESC_F_AVAIL != 0;
+        let used = self.flags & VRING_PACKED_DESC_F_USED != 0;
+        avail == wrap_counter && used != wrap_counter
+    }
+
+    /// Return true if this descriptor has next descriptor.
+    fn has_next(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_NEXT != 0
+    }
+
+    /// Check whether this descriptor is write-only or read-only.
+    fn write_only(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_WRITE != 0
+    }
+
+    /// Return true if this descriptor is a indirect descriptor.
+    fn is_indirect_desc(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_INDIRECT != 0
+    }
+}
+
+/// Event suppression structure of driver area and device area.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct PackedVringEvent {
+    /// Descriptor ring offset in bits 0-14 and wrap counter in bit 15.
+    off_wrap: u16,
+    /// Event flags as indicated above.
+    flags: u16,
+}
+
+impl ByteCode for PackedVringEvent {}
+
+/// Packed vring.
+///
+/// The `next_avail` and `next_used` of `QueueConfig` hold the ring index in bits 0-14 and
+/// the inverted wrap counter in bit 15, so that the initial wrap counter of a reset
+/// configuration is 1 and the wrap counter migrates with the configuration.
+#[derive(Default, Clone)]
+pub struct PackedVring {
+    /// Region cache information.
+    cache: Option<RegionCache>,
+    /// The configuration of virtqueue.
+    queue_config: QueueConfig,
+    /// The number of ring descriptors of buffers being processed, indexed by buffer id.
+    in_flight: HashMap<u16, u16>,
+    /// The ring position and buffer id of the last popped buffer.
+    last_avail: Option<(Wrapping<u16>, u16)>,
+}
+
+impl Deref for PackedVring {
+    type Target = QueueConfig;
+    fn deref(&self) -> &Self::Target {
+        &self.queue_config
+    }
+}
+
+impl DerefMut for PackedVring {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.queue_config
+    }
+}
+
+impl PackedVring {
+    /// Create a packed vring.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue_config` - Configuration of the vring.
+    pub fn new(queue_config: QueueConfig) -> Self {
+        PackedVring {
+            cache: None,
+            queue_config,
+            in_flight: HashMap::new(),
+            last_avail: None,
+        }
+    }
+
+    /// The actual size of the queue.
+    fn actual_size(&self) -> u16 {
+        min(self.size, self.max_size)
+    }
+
+    /// Split a ring position into the ring index and the wrap counter.
+    fn position(pos: Wrapping<u16>) -> (u16, bool) {
+        (
+            pos.0 & !(1 << VRING_PACKED_EVENT_F_WRAP_CTR),
+            pos.0 & (1 << VRING_PACKED_EVENT_F_WRAP_CTR) == 0,
+        )
+    }
+
+    /// Move a ring position forward by `count` descriptors, flipping the wrap counter
+    /// when the end of the ring is passed.
+    fn advance(&self, pos: Wrapping<u16>, count: u16) -> Wrapping<u16> {
+        let size = self.actual_size();
+        let (mut idx, mut wrap) = Self::position(pos);
+        idx += count;
+        if idx >= size {
+            idx -= size;
+            wrap = !wrap;
+        }
+        let wrap_bit = if wrap {
+            0
+        } else {
+            1 << VRING_PACKED_EVENT_F_WRAP_CTR
+        };
+        Wrapping(idx | wrap_bit)
+    }
+
+    /// Get the descriptor at `index` of the descriptor ring.
+    fn get_desc(&self, sys_mem: &Arc<AddressSpace>, index: u16) -> Result<PackedVringDesc> {
+        let desc_addr = self.addr_cache.desc_table_host + u64::from(index) * PACKED_DESC_LEN;
+        sys_mem
+            .read_object_direct::<PackedVringDesc>(desc_addr)
+            .with_context(|| anyhow!(VirtioError::ReadObjectErr("a descriptor", desc_addr)))
+    }
+
+    /// Get the driver event suppression structure from guest memory.
+    fn get_driver_event(&self, sys_mem: &Arc<AddressSpace>) -> Result<PackedVringEvent> {
+        // Make sure the event read from sys_mem is new.
+        fence(Ordering::SeqCst);
+        sys_mem
+            .read_object_direct::<PackedVringEvent>(self.addr_cache.avail_ring_host)
+            .with_context(|| {
+                anyhow!(VirtioError::ReadObjectErr(
+                    "driver event",
+                    self.avail_ring.raw_value()
+                ))
+            })
+    }
+
+    /// Set the device event suppression structure to guest memory.
+    fn set_device_event(&self, sys_mem: &Arc<AddressSpace>, event: PackedVringEvent) -> Result<()> {
+        sys_mem
+            .write_object_direct::<PackedVringEvent>(&event, self.addr_cache.used_ring_host)
+            .with_context(|| {
+                format!(
+                    "Failed to set device event, device area: 0x{:X}",
+                    self.used_ring.raw_value()
+                )
+            })?;
+        // Make sure the data has been set.
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Return true if the next descriptor in the ring is available.
+    fn has_avail_desc(&self, sys_mem: &Arc<AddressSpace>) -> Result<bool> {
+        let (index, wrap_counter) = Self::position(self.next_avail);
+        // Make sure the descriptor read from sys_mem is new.
+        fence(Ordering::SeqCst);
+        Ok(self.get_desc(sys_mem, index)?.is_avail(wrap_counter))
+    }
+
+    /// Check the memory pointed by a descriptor, and cache the ram region it belongs to.
+    fn check_desc_memory(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        desc: &PackedVringDesc,
+    ) -> Result<()> {
+        if desc.len == 0 {
+            bail!("Zero sized buffers are not allowed");
+        }
+        if let Some(reg_cache) = &self.cache {
+            let end = desc
+                .addr
+                .0
+                .checked_add(u64::from(desc.len))
+                .ok_or_else(|| anyhow!("The memory of descriptor is invalid, range overflows"))?;
+            if desc.addr.0 > reg_cache.start && end < reg_cache.end {
+                return Ok(());
+            }
+        } else if let Some(cache) = sys_mem.get_region_cache(desc.addr) {
+            if cache.reg_type == RegionType::Ram {
+                self.cache = Some(cache);
+            }
+        }
+        checked_offset_mem(sys_mem, desc.addr, u64::from(desc.len))
+            .map(|_| ())
+            .with_context(|| anyhow!(VirtioError::QueueDescInvalid))
+    }
+
+    /// Put the buffer described by `desc` into the element.
+    fn push_iovec(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        desc: &PackedVringDesc,
+        elem: &mut Element,
+        total_len: &mut u64,
+    ) -> Result<()> {
+        self.check_desc_memory(sys_mem, desc)?;
+        let iovec = ElemIovec {
+            addr: desc.addr,
+            len: desc.len,
+        };
+        if desc.write_only() {
+            elem.in_iovec.push(iovec);
+        } else {
+            if !elem.in_iovec.is_empty() {
+                bail!("Invalid order of the descriptor elem");
+            }
+            elem.out_iovec.push(iovec);
+        }
+        elem.desc_num = elem
+            .desc_num
+            .checked_add(1)
+            .ok_or_else(|| anyhow!("The chained desc number overflows"))?;
+        *total_len += u64::from(desc.len);
+        Ok(())
+    }
+
+    /// Put all the buffers in the table of indirect descriptor into the element.
+    fn push_indirect_iovec(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        desc: &PackedVringDesc,
+        elem: &mut Element,
+        total_len: &mut u64,
+    ) -> Result<()> {
+        if desc.len == 0
+            || u64::from(desc.len) % PACKED_DESC_LEN != 0
+            || u64::from(desc.len) / PACKED_DESC_LEN > u64::from(u16::MAX)
+        {
+            bail!("The indirect descriptor is invalid, len: {}", desc.len);
+        }
+        if desc.has_next() {
+            bail!("INDIRECT and NEXT flag should not be used together");
+        }
+        checked_offset_mem(sys_mem, desc.addr, u64::from(desc.len))
+            .with_context(|| "The table of indirect descriptor is out of bounds")?;
+        let table_host = sys_mem
+            .get_host_address_from_cache(desc.addr, &self.cache)
+            .ok_or_else(|| anyhow!("Failed to get descriptor table entry host address"))?;
+
+        for i in 0..u64::from(desc.len) / PACKED_DESC_LEN {
+            let entry_addr = table_host + i * PACKED_DESC_LEN;
+            let entry = sys_mem
+                .read_object_direct::<PackedVringDesc>(entry_addr)
+                .with_context(|| anyhow!(VirtioError::ReadObjectErr("a descriptor", entry_addr)))?;
+            if entry.is_indirect_desc() {
+                bail!("Found two indirect descriptor elem in one request");
+            }
+            self.push_iovec(sys_mem, &entry, elem, total_len)?;
+        }
+        Ok(())
+    }
+
+    /// Assemble the element from the descriptors starting at `index` of the ring, and
+    /// return the number of ring descriptors the buffer occupies.
+    fn get_element(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        mut index: u16,
+        mut desc: PackedVringDesc,
+        elem: &mut Element,
+    ) -> Result<u16> {
+        let size = self.actual_size();
+        let mut desc_count: u16 = 0;
+        let mut total_len: u64 = 0;
+
+        loop {
+            desc_count += 1;
+            if desc_count > size {
+                bail!("The element desc number exceeds max allowed");
+            }
+
+            if desc.is_indirect_desc() {
+                self.push_indirect_iovec(sys_mem, &desc, elem, &mut total_len)?;
+            } else {
+                self.push_iovec(sys_mem, &desc, elem, &mut total_len)?;
+            }
+            // Buffer id is carried by the last descriptor of the chain.
+            elem.index = desc.id;
+
+            if !desc.has_next() {
+                break;
+            }
+            index += 1;
+            if index >= size {
+                index = 0;
+            }
+            desc = self.get_desc(sys_mem, index)?;
+        }
+
+        if total_len > DESC_CHAIN_MAX_TOTAL_LEN {
+            bail!("Find a descriptor chain longer than 4GB in total");
+        }
+
+        Ok(desc_count)
+    }
+
+    /// Return true if it's required to trigger interrupt for the used descriptor,
+    /// according to the off_wrap set by driver.
+    fn used_desc_need_event(&mut self, off_wrap: u16) -> bool {
+        let (new, wrap_counter) = Self::position(self.next_used);
+        let old = self.last_signal_used;
+        let valid = self.signal_used_valid;
+        self.signal_used_valid = true;
+        self.last_signal_used = Wrapping(new);
+        if !valid {
+            return true;
+        }
+
+        let mut event_idx = Wrapping(off_wrap & !(1 << VRING_PACKED_EVENT_F_WRAP_CTR));
+        if wrap_counter != (off_wrap >> VRING_PACKED_EVENT_F_WRAP_CTR == 1) {
+            event_idx -= Wrapping(self.actual_size());
+        }
+        (Wrapping(new) - event_idx - Wrapping(1)) < (Wrapping(new) - old)
+    }
+
+    fn is_invalid_memory(&self, sys_mem: &Arc<AddressSpace>, actual_size: u64) -> bool {
+        if let Err(ref e) =
+            checked_offset_mem(sys_mem, self.desc_table, PACKED_DESC_LEN * actual_size)
+        {
+            error!(
+                "descriptor ring is out of bounds: start:0x{:X} size:{} {:?}",
+                self.desc_table.raw_value(),
+                PACKED_DESC_LEN * actual_size,
+                e
+            );
+            return true;
+        }
+        if let Err(ref e) = checked_offset_mem(sys_mem, self.avail_ring, PACKED_EVENT_LEN) {
+            error!(
+                "driver area is out of bounds: start:0x{:X} {:?}",
+                self.avail_ring.raw_value(),
+                e
+            );
+            return true;
+        }
+        if let Err(ref e) = checked_offset_mem(sys_mem, self.used_ring, PACKED_EVENT_LEN) {
+            error!(
+                "device area is out of bounds: start:0x{:X} {:?}",
+                self.used_ring.raw_value(),
+                e
+            );
+            return true;
+        }
+
+        if self.desc_table.0 & 0xf != 0 {
+            error!(
+                "descriptor ring: 0x{:X} is not aligned",
+                self.desc_table.raw_value()
+            );
+            true
+        } else if self.avail_ring.0 & 0x3 != 0 {
+            error!(
+                "driver area: 0x{:X} is not aligned",
+                self.avail_ring.raw_value()
+            );
+            true
+        } else if self.used_ring.0 & 0x3 != 0 {
+            error!(
+                "device area: 0x{:X} is not aligned",
+                self.used_ring.raw_value()
+            );
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl VringOps for PackedVring {
+    fn is_enabled(&self) -> bool {
+        self.ready
+    }
+
+    fn is_valid(&self, sys_mem: &Arc<AddressSpace>) -> bool {
+        if !self.ready {
+            error!("The configuration of vring is not ready\n");
+            false
+        } else if self.size > self.max_size || self.size == 0 || self.size > VRING_PACKED_MAX_SIZE {
+            error!(
+                "vring with invalid size:{} max size:{}",
+                self.size, self.max_size
+            );
+            false
+        } else {
+            !self.is_invalid_memory(sys_mem, u64::from(self.actual_size()))
+        }
+    }
+
+    fn pop_avail(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> Result<Element> {
+        let mut element = Element::new(0);
+        if !self.has_avail_desc(sys_mem)? {
+            return Ok(element);
+        }
+
+        // Make sure descriptor read does not bypass the check of avail flag.
+        fence(Ordering::Acquire);
+
+        let (index, _) = Self::position(self.next_avail);
+        let desc = self.get_desc(sys_mem, index)?;
+        let desc_count = self
+            .get_element(sys_mem, index, desc, &mut element)
+            .with_context(|| {
+                format!(
+                    "Failed to get element from descriptor ring {}, table addr: 0x{:X}, size: {}",
+                    index,
+                    self.addr_cache.desc_table_host,
+                    self.actual_size(),
+                )
+            })?;
+
+        self.last_avail = Some((self.next_avail, element.index));
+        self.in_flight.insert(element.index, desc_count);
+        self.next_avail = self.advance(self.next_avail, desc_count);
+
+        // Suppress queue notification related to current processing buffer.
+        if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) {
+            self.suppress_queue_notify(sys_mem, features, false)
+                .with_context(|| "Failed to set device event for popping descriptor ring")?;
+        }
+
+        Ok(element)
+    }
+
+    fn push_back(&mut self) {
+        if let Some((next_avail, id)) = self.last_avail.take() {
+            self.next_avail = next_avail;
+            self.in_flight.remove(&id);
+        }
+    }
+
+    fn add_used(&mut self, sys_mem: &Arc<AddressSpace>, index: u16, len: u32) -> Result<()> {
+        if index >= self.actual_size() {
+            return Err(anyhow!(VirtioError::QueueIndex(index, self.size)));
+        }
+        let desc_count = self.in_flight.remove(&index).unwrap_or_else(|| {
+            warn!(
+                "Buffer id {} is not in flight, assume one descriptor",
+                index
+            );
+            1
+        });
+        if self.last_avail.map_or(false, |(_, id)| id == index) {
+            self.last_avail = None;
+        }
+
+        let (used_idx, wrap_counter) = Self::position(self.next_used);
+        let desc_addr = self.addr_cache.desc_table_host + u64::from(used_idx) * PACKED_DESC_LEN;
+        sys_mem
+            .write_object_direct::<u32>(&len, desc_addr + PACKED_DESC_LEN_OFFSET)
+            .with_context(|| "Failed to write len of used descriptor")?;
+        sys_mem
+            .write_object_direct::<u16>(&index, desc_addr + PACKED_DESC_ID_OFFSET)
+            .with_context(|| "Failed to write id of used descriptor")?;
+        // Make sure id and len are filled before exposing the used flags.
+        fence(Ordering::Release);
+
+        let flags = if wrap_counter {
+            VRING_PACKED_DESC_F_AVAIL | VRING_PACKED_DESC_F_USED
+        } else {
+            0
+        };
+        sys_mem
+            .write_object_direct::<u16>(&flags, desc_addr + PACKED_DESC_FLAGS_OFFSET)
+            .with_context(|| "Failed to write flags of used descriptor")?;
+        // Make sure used flags is exposed before notifying guest.
+        fence(Ordering::SeqCst);
+
+        self.next_used = self.advance(self.next_used, desc_count);
+        Ok(())
+    }
+
+    fn should_notify(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> bool {
+        let event = match self.get_driver_event(sys_mem) {
+            Ok(event) => event,
+            Err(ref e) => {
+                error!("Failed to get the status for notifying used vring {:?}", e);
+                return false;
+            }
+        };
+
+        match event.flags {
+            VRING_PACKED_EVENT_FLAG_DISABLE => false,
+            VRING_PACKED_EVENT_FLAG_DESC
+                if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) =>
+            {
+                self.used_desc_need_event(event.off_wrap)
+            }
+            _ => true,
+        }
+    }
+
+    fn suppress_queue_notify(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        features: u64,
+        suppress: bool,
+    ) -> Result<()> {
+        let mut event = PackedVringEvent::default();
+        if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) {
+            let (index, wrap_counter) = Self::position(self.next_avail);
+            event.flags = VRING_PACKED_EVENT_FLAG_DESC;
+            event.off_wrap = index | (u16::from(wrap_counter) << VRING_PACKED_EVENT_F_WRAP_CTR);
+        } else if suppress {
+            event.flags = VRING_PACKED_EVENT_FLAG_DISABLE;
+        } else {
+            event.flags = VRING_PACKED_EVENT_FLAG_ENABLE;
+        }
+        self.set_device_event(sys_mem, event)
+    }
+
+    fn actual_size(&self) -> u16 {
+        self.actual_size()
+    }
+
+    fn get_queue_config(&self) -> QueueConfig {
+        let mut config = self.queue_config;
+        config.signal_used_valid = false;
+        config
+    }
+
+    /// Packed vring doesn't expose the number of available buffers, so it returns 1 if
+    /// there is at least one available buffer.
+    fn avail_ring_len(&mut self, sys_mem: &Arc<AddressSpace>) -> Result<u16> {
+        Ok(u16::from(self.has_avail_desc(sys_mem)?))
+    }
+
+    /// Get the ring index with the wrap counter in bit 15 of the next available buffer.
+    fn get_avail_idx(&self, _sys_mem: &Arc<AddressSpace>) -> Result<u16> {
+        let (index, wrap_counter) = Self::position(self.next_avail);
+        Ok(index | (u16::from(wrap_counter) << VRING_PACKED_EVENT_F_WRAP_CTR))
+    }
+
+    fn get_cache(&self) -> &Option<RegionCache> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Queue, QUEUE_TYPE_PACKED_VRING};
+    use address_space::{HostMemMapping, Region};
+
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+    const QUEUE_SIZE: u16 = 4;
+    const DRIVER_AREA: u64 = 0x1000;
+    const DEVICE_AREA: u64 = 0x2000;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let sys_space = AddressSpace::new(root).unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                SYSTEM_SPACE_SIZE,
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn queue_config_init(sys_space: &Arc<AddressSpace>) -> QueueConfig {
+        let mut queue_config = QueueConfig::new(QUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(0);
+        queue_config.avail_ring = GuestAddress(DRIVER_AREA);
+        queue_config.used_ring = GuestAddress(DEVICE_AREA);
+        queue_config.addr_cache.desc_table_host =
+            sys_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.addr_cache.avail_ring_host =
+            sys_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.addr_cache.used_ring_host =
+            sys_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.ready = true;
+        queue_config.size = QUEUE_SIZE;
+        queue_config
+    }
+
+    fn set_desc(
+        sys_space: &Arc<AddressSpace>,
+        index: u16,
+        addr: u64,
+        len: u32,
+        id: u16,
+        flags: u16,
+    ) {
+        let desc = PackedVringDesc {
+            addr: GuestAddress(addr),
+            len,
+            id,
+            flags,
+        };
+        sys_space
+            .write_object(&desc, GuestAddress(u64::from(index) * PACKED_DESC_LEN))
+            .unwrap();
+    }
+
+    fn get_desc(sys_space: &Arc<AddressSpace>, index: u16) -> PackedVringDesc {
+        sys_space
+            .read_object::<PackedVringDesc>(GuestAddress(u64::from(index) * PACKED_DESC_LEN))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_packed_vring_valid() {
+        let sys_space = address_space_init();
+        let mut queue_config = queue_config_init(&sys_space);
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(queue.is_valid(&sys_space));
+
+        // The size of packed vring is not required to be power of 2.
+        queue_config.max_size = 8;
+        queue_config.size = 3;
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(queue.is_valid(&sys_space));
+
+        queue_config.size = 0;
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(!queue.is_valid(&sys_space));
+
+        // The device area is not aligned.
+        queue_config.size = QUEUE_SIZE;
+        queue_config.used_ring = GuestAddress(DEVICE_AREA + 2);
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(!queue.is_valid(&sys_space));
+    }
+
+    #[test]
+    fn test_packed_vring_pop_and_add_used() {
+        let sys_space = address_space_init();
+        let mut vring = PackedVring::new(queue_config_init(&sys_space));
+        let avail = VRING_PACKED_DESC_F_AVAIL;
+
+        // Nothing is available in the initial ring.
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 0);
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.desc_num, 0);
+
+        // A chain of two descriptors with buffer id 3.
+        set_desc(&sys_space, 0, 0x4000, 16, 0, avail | VIRTQ_DESC_F_NEXT);
+        set_desc(&sys_space, 1, 0x5000, 32, 3, avail | VIRTQ_DESC_F_WRITE);
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 1);
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 3);
+        assert_eq!(elem.desc_num, 2);
+        assert_eq!(elem.out_iovec[0].addr, GuestAddress(0x4000));
+        assert_eq!(elem.in_iovec[0].len, 32);
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 0);
+
+        // The used descriptor is written at the start of the buffer, and the next one
+        // skips the whole chain.
+        vring.add_used(&sys_space, 3, 100).unwrap();
+        let desc = get_desc(&sys_space, 0);
+        assert_eq!(desc.id, 3);
+        assert_eq!(desc.len, 100);
+        assert_eq!(desc.flags, avail | VRING_PACKED_DESC_F_USED);
+        assert_eq!(PackedVring::position(vring.next_used), (2, true));
+
+        // Pop two single descriptors, rolling back the first one once.
+        set_desc(&sys_space, 2, 0x4000, 16, 1, avail);
+        set_desc(&sys_space, 3, 0x5000, 16, 2, avail);
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 1);
+        vring.push_back();
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 1);
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 2);
+        assert_eq!(PackedVring::position(vring.next_avail), (0, false));
+
+        // Descriptor 0 is used by device, it isn't available in the next round.
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 0);
+        vring.add_used(&sys_space, 2, 0).unwrap();
+        vring.add_used(&sys_space, 1, 0).unwrap();
+        assert_eq!(get_desc(&sys_space, 3).id, 1);
+        assert_eq!(PackedVring::position(vring.next_used), (0, false));
+
+        // Driver makes the descriptor available with the flipped wrap counter.
+        set_desc(&sys_space, 0, 0x4000, 16, 3, VRING_PACKED_DESC_F_USED);
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 3);
+        vring.add_used(&sys_space, 3, 0).unwrap();
+        assert_eq!(get_desc(&sys_space, 0).flags, 0);
+    }
+
+    #[test]
+    fn test_packed_vring_notify() {
+        let sys_space = address_space_init();
+        let mut vring = PackedVring::new(queue_config_init(&sys_space));
+
+        let mut event = PackedVringEvent {
+            off_wrap: 0,
+            flags: VRING_PACKED_EVENT_FLAG_DISABLE,
+        };
+        sys_space
+            .write_object(&event, GuestAddress(DRIVER_AREA))
+            .unwrap();
+        assert!(!vring.should_notify(&sys_space, 0));
+
+        event.flags = VRING_PACKED_EVENT_FLAG_ENABLE;
+        sys_space
+            .write_object(&event, GuestAddress(DRIVER_AREA))
+            .unwrap();
+        assert!(vring.should_notify(&sys_space, 0));
+
+        vring.suppress_queue_notify(&sys_space, 0, true).unwrap();
+        let device_event = sys_space
+            .read_object::<PackedVringEvent>(GuestAddress(DEVICE_AREA))
+            .unwrap();
+        assert_eq!(device_event.flags, VRING_PACKED_EVENT_FLAG_DISABLE);
+
+        let features = 1_u64 << VIRTIO_F_RING_EVENT_IDX;
+        vring
+            .suppress_queue_notify(&sys_space, features, false)
+            .unwrap();
+        let device_event = sys_space
+            .read_object::<PackedVringEvent>(GuestAddress(DEVICE_AREA))
+            .unwrap();
+        assert_eq!(device_event.flags, VRING_PACKED_EVENT_FLAG_DESC);
+        assert_eq!(device_event.off_wrap, 1 << VRING_PACKED_EVENT_F_WRAP_CTR);
+    }
+}
@@ -80,13 +80,13 @@ pub struct QueueConfig {
     /// Interrupt vector index of the queue for msix
     pub vector: u16,
     /// The next index which can be popped in the available vring.
-    next_avail: Wrapping<u16>,
+    pub(super) next_avail: Wrapping<u16>,
     /// The next index which can be pushed in the used vring.
-    next_used: Wrapping<u16>,
+    pub(super) next_used: Wrapping<u16>,
     /// The index of last descriptor used which has triggered interrupt.
-    last_signal_used: Wrapping<u16>,
+    pub(super) last_signal_used: Wrapping<u16>,
     /// The last_signal_used is valid or not.
-    signal_used_valid: bool,
+    pub(super) signal_used_valid: bool,
 }
 
 impl QueueConfig {
@@ -1047,7 +1047,7 @@ mod tests {
         let queue = Queue::new(queue_config, 0);
         assert!(queue.is_err());
         let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING);
-        assert!(queue.is_err());
+        assert!(queue.is_ok());
 
         // it is valid
         queue_config.desc_table = GuestAddress(0);